@@ -8,12 +8,27 @@ mod error {
 mod payload {
     include!("../src/payload.rs");
 }
+mod bloom {
+    include!("../src/bloom.rs");
+}
 mod types {
     include!("../src/types.rs");
 }
+mod stats {
+    include!("../src/stats.rs");
+}
+mod systemd {
+    include!("../src/systemd.rs");
+}
 mod table {
     include!("../src/table.rs");
 }
+mod telemetry {
+    include!("../src/telemetry.rs");
+}
+mod api {
+    include!("../src/api.rs");
+}
 mod cloud {
     include!("../src/cloud.rs");
 }
@@ -26,24 +41,120 @@ mod device {
 mod net {
     include!("../src/net.rs");
 }
+mod netlink {
+    include!("../src/netlink.rs");
+}
+mod noise {
+    include!("../src/noise.rs");
+}
+mod cert_auth {
+    include!("../src/cert_auth.rs");
+}
+mod acl {
+    include!("../src/acl.rs");
+}
+mod arp_proxy {
+    include!("../src/arp_proxy.rs");
+}
+mod audit {
+    include!("../src/audit.rs");
+}
 mod beacon {
     include!("../src/beacon.rs");
 }
+mod capture {
+    include!("../src/capture.rs");
+}
+mod dns_beacon {
+    include!("../src/dns_beacon.rs");
+}
+#[cfg(feature = "ebpf")]
+mod ebpf {
+    include!("../src/ebpf.rs");
+}
+mod fragment {
+    include!("../src/fragment.rs");
+}
+mod gossip {
+    include!("../src/gossip.rs");
+}
+#[cfg(feature = "grpc")]
+#[allow(clippy::result_large_err)]
+mod grpc {
+    include!("../src/grpc.rs");
+}
+mod identity {
+    include!("../src/identity.rs");
+}
+mod igmp_snoop {
+    include!("../src/igmp_snoop.rs");
+}
+mod local_bus {
+    include!("../src/local_bus.rs");
+}
 mod messages {
     include!("../src/messages.rs");
 }
+mod metrics {
+    include!("../src/metrics.rs");
+}
+mod pacing {
+    include!("../src/pacing.rs");
+}
+mod peer_list {
+    include!("../src/peer_list.rs");
+}
+mod policy {
+    include!("../src/policy.rs");
+}
 mod port_forwarding {
     include!("../src/port_forwarding.rs");
 }
+mod receivers {
+    include!("../src/receivers.rs");
+}
+mod reorder {
+    include!("../src/reorder.rs");
+}
+mod route_sync {
+    include!("../src/route_sync.rs");
+}
+mod snmp {
+    include!("../src/snmp.rs");
+}
+mod srv_bootstrap {
+    include!("../src/srv_bootstrap.rs");
+}
+mod tofu {
+    include!("../src/tofu.rs");
+}
+mod topology {
+    include!("../src/topology.rs");
+}
 mod traffic {
     include!("../src/traffic.rs");
 }
+mod turn {
+    include!("../src/turn.rs");
+}
+mod vxlan {
+    include!("../src/vxlan.rs");
+}
+mod webhook {
+    include!("../src/webhook.rs");
+}
 mod poll {
     pub mod epoll{
         include!("../src/poll/epoll.rs");
     }
+    #[cfg(feature = "async")]
+    mod async_wait {
+        include!("../src/poll/async_wait.rs");
+    }
     #[cfg(any(target_os = "linux", target_os = "android"))]
     pub use self::epoll::EpollWait as WaitImpl;
+    #[cfg(feature = "async")]
+    pub use self::async_wait::AsyncWait;
 
     use std::io;
 
@@ -61,6 +172,9 @@ mod crypto {
     pub mod init {
         include!("../src/crypto/init.rs");
     }
+    pub mod kem {
+        include!("../src/crypto/kem.rs");
+    }
     pub mod rotate {
         include!("../src/crypto/rotate.rs");
     }
@@ -69,9 +183,13 @@ mod crypto {
     }
     pub use common::*;
     pub use self::core::{EXTRA_LEN, TAG_LEN};
+    pub use self::init::Fingerprint;
 }
 mod tests {
     pub mod common {
         include!("../src/tests/common.rs");
     }
+    pub mod transport {
+        include!("../src/tests/transport.rs");
+    }
 }
@@ -0,0 +1,269 @@
+// Benchmarks of the hot paths, CI compares them against the base branch of a pull request.
+//
+// A flamegraph of a benchmark can be created on Linux with:
+//   cargo bench --bench core -- --profile-time 10 message_roundtrip
+// It is written to target/criterion/<benchmark>/profile/flamegraph.svg
+
+#![allow(dead_code, unused_macros, unused_imports)]
+#[macro_use] extern crate serde;
+#[macro_use] extern crate log;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode, Throughput};
+#[cfg(target_os = "linux")]
+use pprof::criterion::{Output, PProfProfiler};
+
+use smallvec::smallvec;
+
+use std::str::FromStr;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+include!(".code.rs");
+
+pub use error::Error;
+use util::{MockTimeSource, MsgBuffer, MsgBufferPool};
+use types::{Address, AddressFamily, Range};
+use table::ClaimTable;
+use device::Type;
+use config::{Config, CryptoConfig};
+use crypto::{Crypto, PeerCrypto};
+use messages::{PeerInfo, PeerList, MESSAGE_TYPE_DATA};
+use beacon::BeaconSerializer;
+use net::Socket;
+use tests::common::TapSimulator;
+
+const PAYLOAD_SIZES: [usize; 3] = [64, 512, 1500];
+
+fn crypto_config(encrypted: bool) -> CryptoConfig {
+    // A single algorithm keeps the speed test of each node short
+    CryptoConfig {
+        password: Some("test123".to_string()),
+        algorithms: vec![if encrypted { "aes128" } else { "plain" }.to_string()],
+        argon2_memory_kib: Some(64),
+        argon2_iterations: Some(1),
+        ..CryptoConfig::default()
+    }
+}
+
+fn encryption_name(encrypted: bool) -> &'static str {
+    if encrypted { "encrypted" } else { "plain" }
+}
+
+fn connected_pair(encrypted: bool) -> (PeerCrypto<Vec<u8>>, PeerCrypto<Vec<u8>>) {
+    let config = crypto_config(encrypted);
+    let mut node1 = Crypto::new([1; 16], &config).unwrap().peer_instance(vec![], Instant::now);
+    let mut node2 = Crypto::new([2; 16], &config).unwrap().peer_instance(vec![], Instant::now);
+    let mut msg = MsgBuffer::new(64);
+    node1.initialize(&mut msg).unwrap();
+    let mut to_node2 = true;
+    while !msg.is_empty() {
+        if to_node2 {
+            node2.handle_message(&mut msg).unwrap();
+        } else {
+            node1.handle_message(&mut msg).unwrap();
+        }
+        to_node2 = !to_node2;
+    }
+    assert_eq!(node1.is_encrypted(), encrypted);
+    assert_eq!(node2.is_encrypted(), encrypted);
+    (node1, node2)
+}
+
+fn message_roundtrip(c: &mut Criterion) {
+    let mut g = c.benchmark_group("message_roundtrip");
+    g.sampling_mode(SamplingMode::Flat);
+    for &encrypted in &[false, true] {
+        let (mut sender, mut receiver) = connected_pair(encrypted);
+        for &size in &PAYLOAD_SIZES {
+            let mut buffer = MsgBuffer::new(64);
+            buffer.set_length(size);
+            g.throughput(Throughput::Bytes(size as u64));
+            g.bench_with_input(BenchmarkId::new(encryption_name(encrypted), size), &size, |b, _| {
+                b.iter(|| {
+                    sender.send_message(MESSAGE_TYPE_DATA, &mut buffer).unwrap();
+                    receiver.handle_message(&mut buffer).unwrap()
+                });
+            });
+        }
+    }
+    g.finish()
+}
+
+fn table_lookup(c: &mut Criterion) {
+    let mut g = c.benchmark_group("table_lookup");
+    g.sampling_mode(SamplingMode::Flat);
+    for &entries in &[10, 100, 1000] {
+        let mut table = ClaimTable::<MockTimeSource>::new(60, 60);
+        for i in 0..entries {
+            let peer = SocketAddr::from_str(&format!("192.168.{}.{}:3210", i / 256, i % 256)).unwrap();
+            let range = Range::from_str(&format!("10.{}.{}.0/24", i / 256, i % 256)).unwrap();
+            table.set_claims(peer, smallvec![range]);
+        }
+        let last = entries - 1;
+        let addr = Address::from_str(&format!("10.{}.{}.1", last / 256, last % 256)).unwrap();
+        g.bench_with_input(BenchmarkId::from_parameter(entries), &entries, |b, _| {
+            b.iter(|| {
+                table.clear_cache();
+                table.lookup(addr)
+            });
+        });
+    }
+    g.finish()
+}
+
+fn peer_list_contains_addr(c: &mut Criterion) {
+    let mut g = c.benchmark_group("peer_list_contains_addr");
+    g.sampling_mode(SamplingMode::Flat);
+    for &count in &[10, 100] {
+        let mut peers = PeerList::new();
+        for i in 0..count {
+            peers.push(PeerInfo {
+                node_id: Some([i as u8; 16]),
+                addrs: smallvec![
+                    SocketAddr::from_str(&format!("192.168.{}.{}:3210", i / 256, i % 256)).unwrap(),
+                    SocketAddr::from_str(&format!("[2001:db8::{:x}]:3210", i)).unwrap()
+                ]
+            })
+        }
+        // The worst case: the address of the last peer
+        let addr = SocketAddr::from_str(&format!("[2001:db8::{:x}]:3210", count - 1)).unwrap();
+        g.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| black_box(&peers).iter().any(|p| p.addrs.contains(&addr)));
+        });
+    }
+    g.finish()
+}
+
+fn broadcast_msg(c: &mut Criterion) {
+    let mut frame = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 2, 1, 1, 1, 1, 1, 0x08, 0x00];
+    frame.append(&mut vec![0; 1400]);
+    let mut g = c.benchmark_group("broadcast_msg");
+    g.sampling_mode(SamplingMode::Flat);
+    for &encrypted in &[false, true] {
+        for &peers in &[5, 20, 100] {
+            let config = Config { device_type: Type::Tap, crypto: crypto_config(encrypted), ..Config::default() };
+            let mut sim = TapSimulator::new();
+            log::set_max_level(log::LevelFilter::Error);
+            let node = sim.add_node(false, &config);
+            for _ in 0..peers {
+                let peer = sim.add_node(false, &config);
+                sim.connect(peer, node);
+            }
+            sim.simulate_all_messages();
+            assert_eq!(sim.get_node(node).peer_count(), peers);
+            let node = sim.get_node(node);
+            g.throughput(Throughput::Bytes((frame.len() * peers) as u64));
+            g.bench_with_input(BenchmarkId::new(encryption_name(encrypted), peers), &peers, |b, _| {
+                b.iter(|| {
+                    // The frame is sent to all peers as the destination is the broadcast address
+                    node.device().put_inbound(frame.clone());
+                    node.trigger_device_event();
+                    while node.socket().pop_outbound().is_some() {}
+                });
+            });
+        }
+    }
+    g.finish()
+}
+
+fn beacon_encode(c: &mut Criterion) {
+    let peers: Vec<SocketAddr> = (1..=10).map(|i| SocketAddr::from_str(&format!("10.0.0.{}:3210", i)).unwrap()).collect();
+    let mut g = c.benchmark_group("beacon_encode");
+    g.sampling_mode(SamplingMode::Flat);
+    for &encrypted in &[false, true] {
+        let mut beacon = BeaconSerializer::<MockTimeSource>::new(b"vpnc");
+        if encrypted {
+            beacon = beacon.with_encryption(b"secret", false);
+        }
+        g.bench_function(encryption_name(encrypted), |b| {
+            b.iter(|| beacon.encode(&peers));
+        });
+    }
+    g.finish()
+}
+
+fn receive_threads(c: &mut Criterion) {
+    const PACKETS: usize = 64;
+    let mut g = c.benchmark_group("receive_threads");
+    g.sampling_mode(SamplingMode::Flat);
+    g.throughput(Throughput::Elements(PACKETS as u64));
+    log::set_max_level(log::LevelFilter::Error);
+    for &threads in &[1, 2, 4, 8] {
+        let socket = UdpSocket::listen_shared("127.0.0.1:0", AddressFamily::DualStack).unwrap();
+        let addr = SocketAddr::from(([127, 0, 0, 1], socket.local_addr().unwrap().port()));
+        let receivers = socket.spawn_receivers(threads, &MsgBufferPool::new(256, 0)).unwrap();
+        // Packets from different ports are spread over the sockets
+        let senders: Vec<_> = (0..16).map(|_| UdpSocket::bind("127.0.0.1:0").unwrap()).collect();
+        let data = [0; 1400];
+        g.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, _| {
+            b.iter(|| {
+                for n in 0..PACKETS {
+                    senders[n % senders.len()].send_to(&data, addr).unwrap();
+                }
+                let mut received = 0;
+                let deadline = Instant::now() + Duration::from_secs(1);
+                while received < PACKETS && Instant::now() < deadline {
+                    received += receivers.receive().count();
+                }
+            });
+        });
+    }
+    g.finish()
+}
+
+fn local_shortcut(c: &mut Criterion) {
+    const PACKETS: usize = 64;
+    let mut g = c.benchmark_group("local_shortcut");
+    g.sampling_mode(SamplingMode::Flat);
+    g.throughput(Throughput::Elements(PACKETS as u64));
+    log::set_max_level(log::LevelFilter::Error);
+    let buffers = MsgBufferPool::new(256, 0);
+    let socket1 = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let socket2 = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr2 = socket2.local_addr().unwrap();
+    let receivers1 = socket1.spawn_receivers(1, &buffers).unwrap();
+    let receivers2 = socket2.spawn_receivers(1, &buffers).unwrap();
+    let node1 = local_bus::register([1; 16], receivers1.injector());
+    let _node2 = local_bus::register([2; 16], receivers2.injector());
+    let peer = node1.lookup(&[2; 16], addr2, &[]).unwrap();
+    let data = [0; 1400];
+    for &shortcut in &[false, true] {
+        let name = if shortcut { "shortcut" } else { "socket" };
+        g.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..PACKETS {
+                    if shortcut {
+                        peer.deliver(&buffers, &data);
+                    } else {
+                        socket1.send_to(&data, addr2).unwrap();
+                    }
+                }
+                let mut received = 0;
+                let deadline = Instant::now() + Duration::from_secs(1);
+                while received < PACKETS && Instant::now() < deadline {
+                    received += receivers2.receive().count();
+                }
+            });
+        });
+    }
+    g.finish()
+}
+
+#[cfg(target_os = "linux")]
+fn config() -> Criterion {
+    // The profiler only runs with --profile-time and writes a flamegraph for each benchmark
+    Criterion::default().sample_size(100).with_profiler(PProfProfiler::new(100, Output::Flamegraph(None)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn config() -> Criterion {
+    Criterion::default().sample_size(100)
+}
+
+criterion_group!{
+    name = benches;
+    config = config();
+    targets = message_roundtrip, table_lookup, peer_list_contains_addr, broadcast_msg, beacon_encode, receive_threads,
+        local_shortcut
+}
+criterion_main!(benches);
@@ -15,11 +15,11 @@ include!(".code.rs");
 pub use error::Error;
 use util::{MockTimeSource, MsgBuffer};
 use types::{Address, Range};
-use table::{ClaimTable};
+use table::{ClaimTable, MultiPathLookup};
 use device::Type;
 use config::Config;
 use payload::{Packet, Frame, Protocol};
-use crypto::core::{create_dummy_pair, EXTRA_LEN};
+use crypto::core::{create_dummy_pair, CpuFeatures, EXTRA_LEN};
 use tests::common::{TunSimulator, TapSimulator};
 
 fn udp_send(c: &mut Criterion) {
@@ -104,13 +104,31 @@ fn lookup_cold(c: &mut Criterion) {
     g.finish();
 }
 
+fn lookup_multipath(c: &mut Criterion) {
+    // At 100k packets per second, each packet may take 10µs in total
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 60);
+    let addr = Address::from_str("1.2.3.4").unwrap();
+    let peers: Vec<SocketAddr> = (1..=4).map(|i| SocketAddr::from_str(&format!("10.0.0.{}:3210", i)).unwrap()).collect();
+    for peer in &peers {
+        table.set_claims(*peer, smallvec![Range::from_str("0.0.0.0/0").unwrap()]);
+    }
+    let rtts: std::collections::HashMap<SocketAddr, u64> = peers.iter().enumerate().map(|(i, p)| (*p, 50 - i as u64)).collect();
+    let mut multipath = MultiPathLookup::default();
+    let mut g = c.benchmark_group("table");
+    g.throughput(Throughput::Bytes(1400));
+    g.bench_function("lookup_multipath", |b| {
+        b.iter(|| multipath.lookup(&mut table, addr, |p| rtts.get(p).copied()));
+    });
+    g.finish();
+}
+
 fn crypto_bench(c: &mut Criterion, algo: &'static aead::Algorithm) {
     let mut buffer = MsgBuffer::new(EXTRA_LEN);
     buffer.set_length(1400);
     let (mut sender, mut receiver) = create_dummy_pair(algo);
     let mut g = c.benchmark_group("crypto");
     g.throughput(Throughput::Bytes(2*1400));
-    g.bench_function(format!("{:?}", algo), |b| {
+    g.bench_function(format!("{:?} ({})", algo, CpuFeatures::detect().aes_backend()), |b| {
         b.iter(|| {
             sender.encrypt(&mut buffer);
             receiver.decrypt(&mut buffer).unwrap();
@@ -197,7 +215,7 @@ fn full_communication_tap_switch(c: &mut Criterion) {
 criterion_group!(benches, 
     udp_send, 
     decode_ipv4, decode_ipv6, decode_ethernet, decode_ethernet_with_vlan, 
-    lookup_cold, lookup_warm, 
+    lookup_cold, lookup_warm, lookup_multipath,
     crypto_chacha20, crypto_aes128, crypto_aes256,
     full_communication_tun_router, full_communication_tap_switch
 );
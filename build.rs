@@ -25,4 +25,22 @@ fn main() {
             println!("cargo:warning=The manpage will not be build. Do you have 'asciidoctor'?");
         }
     }
+
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+/// Generates the bindings of the gRPC management API
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto/vpncloud.proto");
+    if env::var_os("PROTOC").is_none() {
+        env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    }
+    // The connect helper of the generated client needs the prelude of edition 2021
+    tonic_build::configure()
+        .build_transport(false)
+        .type_attribute(".vpncloud.v1", "#[derive(serde::Deserialize)] #[serde(default)]")
+        .compile_protos(&["proto/vpncloud.proto"], &["proto"])
+        .unwrap();
 }
@@ -0,0 +1,45 @@
+#[macro_use]
+mod util {
+    include!("../src/util.rs");
+}
+mod error {
+    include!("../src/error.rs");
+}
+mod types {
+    include!("../src/types.rs");
+}
+mod beacon {
+    include!("../src/beacon.rs");
+}
+mod cert_auth {
+    include!("../src/cert_auth.rs");
+}
+mod identity {
+    include!("../src/identity.rs");
+}
+mod messages {
+    include!("../src/messages.rs");
+}
+mod noise {
+    include!("../src/noise.rs");
+}
+mod crypto {
+    pub mod core {
+        include!("../src/crypto/core.rs");
+    }
+    pub mod init {
+        include!("../src/crypto/init.rs");
+    }
+    pub mod kem {
+        include!("../src/crypto/kem.rs");
+    }
+    pub mod rotate {
+        include!("../src/crypto/rotate.rs");
+    }
+    pub mod common {
+        include!("../src/crypto/common.rs");
+    }
+    pub use common::*;
+    pub use self::core::{CpuFeatures, EXTRA_LEN, TAG_LEN};
+    pub use self::init::Fingerprint;
+}
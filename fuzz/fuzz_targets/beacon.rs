@@ -0,0 +1,26 @@
+#![no_main]
+#![allow(dead_code, unused_macros, unused_imports)]
+#[macro_use] extern crate serde;
+#[macro_use] extern crate log;
+
+use libfuzzer_sys::fuzz_target;
+
+include!("../.code.rs");
+
+use beacon::BeaconSerializer;
+use util::MockTimeSource;
+
+fuzz_target!(|data: &[u8]| {
+    let data = String::from_utf8_lossy(data);
+    let plain = BeaconSerializer::<MockTimeSource>::new(b"vpnc");
+    let sealed = BeaconSerializer::<MockTimeSource>::new(b"vpnc").with_encryption(b"secret", true);
+    let mut peers = plain.decode(&data, None);
+    sealed.decode_checked(&data, None).ok();
+    if !peers.is_empty() {
+        // The peers are grouped by address family when encoding
+        let mut decoded = plain.decode(&plain.encode(&peers), None);
+        peers.sort();
+        decoded.sort();
+        assert_eq!(decoded, peers);
+    }
+});
@@ -0,0 +1,47 @@
+#![no_main]
+#![allow(dead_code, unused_macros, unused_imports)]
+#[macro_use] extern crate serde;
+#[macro_use] extern crate log;
+
+use libfuzzer_sys::fuzz_target;
+
+include!("../.code.rs");
+
+use messages::{strip_padding, NodeInfo, PunchCoordinate, MESSAGE_TYPE_DATA_PADDED, MESSAGE_TYPE_NODE_INFO, MESSAGE_TYPE_PUNCH_COORDINATE};
+use util::MsgBuffer;
+
+/// Largest message that can arrive in a single UDP packet
+const MAX_MESSAGE: usize = 65507;
+
+// The first byte selects the message type like in the decrypted messages of a peer, the rest is the payload
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() || data.len() > MAX_MESSAGE {
+        return
+    }
+    let (type_, payload) = (data[0], &data[1..]);
+    let mut buffer = MsgBuffer::new(0);
+    match type_ {
+        MESSAGE_TYPE_NODE_INFO => {
+            if let Ok(info) = NodeInfo::decode(payload) {
+                // Unknown parts are skipped when decoding, so only the decoded message has to survive a round trip
+                info.encode(&mut buffer);
+                assert_eq!(NodeInfo::decode(buffer.message()).expect("Failed to decode encoded node info"), info);
+            }
+        }
+        MESSAGE_TYPE_PUNCH_COORDINATE => {
+            if let Ok(punch) = PunchCoordinate::decode(payload) {
+                punch.encode(&mut buffer);
+                assert_eq!(buffer.message(), &payload[..buffer.len()]);
+                assert_eq!(PunchCoordinate::decode(buffer.message()).expect("Failed to decode encoded punch"), punch);
+            }
+        }
+        MESSAGE_TYPE_DATA_PADDED => {
+            buffer.clone_from(payload);
+            if strip_padding(&mut buffer).is_ok() {
+                assert!(buffer.len() <= payload.len());
+                assert_eq!(buffer.message(), &payload[..buffer.len()]);
+            }
+        }
+        _ => ()
+    }
+});
@@ -0,0 +1,74 @@
+#![no_main]
+#![allow(dead_code, unused_macros, unused_imports)]
+#[macro_use] extern crate serde;
+#[macro_use] extern crate log;
+
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+include!("../.code.rs");
+
+use crypto::{Config, Crypto, INIT_MESSAGE_FIRST_BYTE};
+use messages::NodeInfo;
+use types::{NodeId, NODE_ID_BYTES};
+use util::MsgBuffer;
+
+/// Largest message that can arrive in a single UDP packet
+const MAX_MESSAGE: usize = 65507;
+
+/// Number of init messages in the handshake: ping, pong and peng
+const MESSAGES: u8 = 3;
+
+fn node_info(node_id: NodeId) -> NodeInfo {
+    NodeInfo {
+        node_id,
+        peers: Default::default(),
+        claims: Default::default(),
+        peer_timeout: None,
+        addrs: Default::default(),
+        tags: Default::default(),
+        mtu: None,
+        identity: None,
+        protocol_version: messages::PROTOCOL_VERSION,
+        extensions: Default::default()
+    }
+}
+
+// Deriving the key pair and measuring the algorithm speeds is too slow to be done for every input
+fn nodes() -> &'static (Crypto, Crypto) {
+    static NODES: OnceLock<(Crypto, Crypto)> = OnceLock::new();
+    NODES.get_or_init(|| {
+        let config = Config {
+            password: Some("fuzzing password".to_string()),
+            algorithms: vec!["AES128".to_string()],
+            argon2_memory_kib: Some(64),
+            argon2_iterations: Some(1),
+            ..Default::default()
+        };
+        (Crypto::new([1; NODE_ID_BYTES], &config).unwrap(), Crypto::new([2; NODE_ID_BYTES], &config).unwrap())
+    })
+}
+
+// The first byte selects the message of the handshake that is replaced by the rest of the input. The messages before
+// it are exchanged normally, so that every stage of the initialization is reached.
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() || data.len() > MAX_MESSAGE {
+        return
+    }
+    let (stage, payload) = (data[0] % MESSAGES, &data[1..]);
+    let (crypto1, crypto2) = nodes();
+    let mut node1 = crypto1.peer_instance(node_info([1; NODE_ID_BYTES]));
+    let mut node2 = crypto2.peer_instance(node_info([2; NODE_ID_BYTES]));
+    let mut msg = MsgBuffer::new(16);
+    node1.initialize(&mut msg).unwrap();
+    for i in 0..stage {
+        let receiver = if i % 2 == 0 { &mut node2 } else { &mut node1 };
+        receiver.handle_message(&mut msg).unwrap();
+    }
+    let receiver = if stage % 2 == 0 { &mut node2 } else { &mut node1 };
+    msg.clone_from(payload);
+    msg.prepend_byte(INIT_MESSAGE_FIRST_BYTE);
+    receiver.handle_message(&mut msg).ok();
+    // Retransmissions use the state left behind by the message
+    receiver.every_second(&mut msg).ok();
+});
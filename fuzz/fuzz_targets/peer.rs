@@ -0,0 +1,89 @@
+#![no_main]
+#![allow(dead_code, unused_macros, unused_imports)]
+#[macro_use] extern crate serde;
+#[macro_use] extern crate log;
+
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+include!("../.code.rs");
+
+use crypto::{Config, Crypto, MessageResult, PeerCrypto};
+use messages::{NodeInfo, MESSAGE_TYPE_NODE_INFO};
+use types::{NodeId, NODE_ID_BYTES};
+use util::MsgBuffer;
+
+/// Largest message that can arrive in a single UDP packet, minus the space for the crypto overhead
+const MAX_MESSAGE: usize = 65507 - 64;
+
+/// Reserved for key rotation messages, `send_message` refuses to send it
+const MESSAGE_TYPE_ROTATION: u8 = 0x10;
+
+fn node_info(node_id: NodeId) -> NodeInfo {
+    NodeInfo {
+        node_id,
+        peers: Default::default(),
+        claims: Default::default(),
+        peer_timeout: None,
+        addrs: Default::default(),
+        tags: Default::default(),
+        mtu: None,
+        identity: None,
+        protocol_version: messages::PROTOCOL_VERSION,
+        extensions: Default::default()
+    }
+}
+
+// Deriving the key pair and measuring the algorithm speeds is too slow to be done for every input
+fn nodes() -> &'static (Crypto, Crypto) {
+    static NODES: OnceLock<(Crypto, Crypto)> = OnceLock::new();
+    NODES.get_or_init(|| {
+        let config = Config {
+            password: Some("fuzzing password".to_string()),
+            algorithms: vec!["AES128".to_string()],
+            argon2_memory_kib: Some(64),
+            argon2_iterations: Some(1),
+            ..Default::default()
+        };
+        (Crypto::new([1; NODE_ID_BYTES], &config).unwrap(), Crypto::new([2; NODE_ID_BYTES], &config).unwrap())
+    })
+}
+
+/// Runs the whole handshake between two new peer instances
+fn connect() -> (PeerCrypto<NodeInfo>, PeerCrypto<NodeInfo>) {
+    let (crypto1, crypto2) = nodes();
+    let mut node1 = crypto1.peer_instance(node_info([1; NODE_ID_BYTES]));
+    let mut node2 = crypto2.peer_instance(node_info([2; NODE_ID_BYTES]));
+    let mut msg = MsgBuffer::new(16);
+    node1.initialize(&mut msg).unwrap();
+    node2.handle_message(&mut msg).unwrap();
+    node1.handle_message(&mut msg).unwrap();
+    node2.handle_message(&mut msg).unwrap();
+    node1.handle_message(&mut msg).unwrap();
+    assert!(node1.is_ready() && node2.is_ready());
+    (node1, node2)
+}
+
+// The first byte is the message type and the rest is the content, like in the inputs of the decode target
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() || data.len() > MAX_MESSAGE {
+        return
+    }
+    let (mut node1, mut node2) = connect();
+    let mut buffer = MsgBuffer::new(64);
+    // A message with this content has to arrive unchanged
+    let (type_, payload) = (data[0], &data[1..]);
+    if type_ != MESSAGE_TYPE_ROTATION {
+        buffer.clone_from(payload);
+        node1.send_message(type_, &mut buffer).expect("Failed to encrypt message");
+        assert_eq!(node2.handle_message(&mut buffer).expect("Failed to decrypt message"), MessageResult::Message(type_));
+        assert_eq!(buffer.message(), payload);
+        if type_ == MESSAGE_TYPE_NODE_INFO {
+            NodeInfo::decode(buffer.message()).ok();
+        }
+    }
+    // The input itself as received from the peer
+    buffer.clone_from(data);
+    node2.handle_message(&mut buffer).ok();
+    node2.every_second(&mut buffer).ok();
+});
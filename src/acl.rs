@@ -0,0 +1,114 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::str::FromStr;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    config::AclRuleConfig,
+    error::Error,
+    types::{Address, Range},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+impl FromStr for AclAction {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match &text.to_lowercase() as &str {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            _ => Err("Unknown ACL action"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AclRule {
+    pub action: AclAction,
+    pub src: Option<Range>,
+    pub dst: Option<Range>,
+    pub proto: Option<u8>,
+}
+
+/// The IP protocol of the packet, only IP packets with IPv4 or IPv6 addresses have one
+#[inline]
+fn ip_protocol(dst: Address, data: &[u8]) -> Option<u8> {
+    match dst.len {
+        4 => data.get(9).copied(),
+        // Extension headers are not skipped
+        16 => data.get(6).copied(),
+        _ => None,
+    }
+}
+
+impl AclRule {
+    #[inline]
+    fn matches(&self, src: Address, dst: Address, data: &[u8]) -> bool {
+        self.src.map(|r| r.matches(src)).unwrap_or(true)
+            && self.dst.map(|r| r.matches(dst)).unwrap_or(true)
+            && self.proto.map(|p| ip_protocol(dst, data) == Some(p)).unwrap_or(true)
+    }
+}
+
+/// Ordered list of rules that decide whether packets are forwarded, packets matching no rule are allowed
+#[derive(Default)]
+pub struct Acl {
+    rules: Vec<AclRule>,
+}
+
+impl Acl {
+    pub fn from_config(rules: &[AclRuleConfig]) -> Result<Self, Error> {
+        let parse_range = |s: &Option<String>| s.as_ref().map(|s| Range::from_str(s)).transpose();
+        let mut acl_rules = Vec::with_capacity(rules.len());
+        for rule in rules {
+            acl_rules.push(AclRule {
+                action: rule.action,
+                src: parse_range(&rule.from)?,
+                dst: parse_range(&rule.to)?,
+                proto: rule.proto,
+            })
+        }
+        Ok(Self { rules: acl_rules })
+    }
+
+    /// Returns whether the packet with the given addresses may be forwarded
+    #[inline]
+    pub fn allows(&self, src: Address, dst: Address, data: &[u8]) -> bool {
+        // HOT PATH
+        for rule in &self.rules {
+            if rule.matches(src, dst, data) {
+                return rule.action == AclAction::Allow
+            }
+        }
+        true
+    }
+}
+
+#[test]
+fn acl_rules() {
+    let acl = Acl::from_config(&[
+        "allow from 10.1.0.0/16 to 10.2.0.1/32".parse().unwrap(),
+        "deny to 10.2.0.0/16 proto 6".parse().unwrap(),
+        "deny from 10.3.0.0/16".parse().unwrap(),
+    ])
+    .unwrap();
+    let addr = |s: &str| Address::from_str(s).unwrap();
+    let tcp = [0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0, 10, 1, 0, 1, 10, 2, 0, 1];
+    let udp = [0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0, 10, 1, 0, 1, 10, 2, 0, 1];
+    assert!(acl.allows(addr("10.1.0.1"), addr("10.2.0.1"), &tcp));
+    assert!(!acl.allows(addr("10.4.0.1"), addr("10.2.0.1"), &tcp));
+    assert!(acl.allows(addr("10.4.0.1"), addr("10.2.0.1"), &udp));
+    assert!(!acl.allows(addr("10.3.0.1"), addr("10.5.0.1"), &udp));
+    assert!(acl.allows(addr("10.4.0.1"), addr("10.5.0.1"), &udp));
+    assert!(Acl::default().allows(addr("10.3.0.1"), addr("10.5.0.1"), &udp));
+}
@@ -0,0 +1,569 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// HTTP management API
+//
+// The server runs in its own thread and hands all requests to the main loop via a channel, so the state of the node
+// is only ever touched by the main loop. Requests are answered during the housekeeping, i.e. within a second.
+// Events are published by the main loop to a broadcast channel that the WebSocket clients subscribe to.
+
+use serde::Serialize;
+
+use crate::{audit::DisconnectReason, stats::StatsSnapshot};
+
+/// Number of events a WebSocket client may fall behind before it is disconnected
+pub const MAX_EVENT_LAG: usize = 1000;
+
+/// Commands of the management API that are executed by the main loop
+#[cfg_attr(not(feature = "rest-api"), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiCommand {
+    Peers,
+    Stats,
+    Table,
+    TableDump,
+    TableQuery(String),
+    Topology,
+    TopologyDot,
+    Connect(String),
+    Disconnect(String),
+    ReconnectPeers,
+    AddReconnectPeer(String),
+    RemoveReconnectPeer { addr: String, disconnect: bool },
+    TofuList,
+    TofuRemove(String),
+    TofuTrust(String),
+    Reload,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: u16,
+    pub error: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, error: impl Into<String>) -> Self {
+        Self { status, error: error.into() }
+    }
+}
+
+pub type ApiResult = Result<serde_json::Value, ApiError>;
+
+pub fn api_value<T: Serialize>(value: &T) -> ApiResult {
+    serde_json::to_value(value).map_err(|e| ApiError::new(500, e.to_string()))
+}
+
+/// Events that are pushed to the clients of the event stream
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event")]
+pub enum ApiEvent {
+    PeerConnected { peer: String, node_id: String },
+    PeerDisconnected { peer: String, node_id: String, reason: DisconnectReason },
+    PeerTimeout { peer: String, node_id: String },
+    KeyRotation { peer: String },
+    StatsSnapshot(Box<StatsSnapshot>),
+}
+
+/// Reasons why a client of the event stream did not get the next event
+#[cfg_attr(not(feature = "rest-api"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamError {
+    /// Events have been dropped as the client fell behind
+    Lagged,
+    /// No more events will be published
+    Closed,
+}
+
+#[cfg(feature = "rest-api")]
+impl From<tokio::sync::broadcast::error::RecvError> for StreamError {
+    fn from(err: tokio::sync::broadcast::error::RecvError) -> Self {
+        match err {
+            tokio::sync::broadcast::error::RecvError::Lagged(_) => StreamError::Lagged,
+            tokio::sync::broadcast::error::RecvError::Closed => StreamError::Closed,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "rest-api"), allow(dead_code))]
+#[derive(Debug, PartialEq)]
+pub enum StreamAction<T> {
+    Send(T),
+    /// The client fell behind by more than `MAX_EVENT_LAG` events
+    Disconnect,
+    Stop,
+}
+
+/// Decides what to do with the next event of a client, `pending` is the number of events still queued for the client
+#[cfg_attr(not(feature = "rest-api"), allow(dead_code))]
+pub fn stream_action<T>(event: Result<T, StreamError>, pending: usize) -> StreamAction<T> {
+    match event {
+        Ok(event) if pending <= MAX_EVENT_LAG => StreamAction::Send(event),
+        Ok(_) | Err(StreamError::Lagged) => StreamAction::Disconnect,
+        Err(StreamError::Closed) => StreamAction::Stop,
+    }
+}
+
+#[cfg_attr(not(feature = "rest-api"), allow(dead_code))]
+pub struct ApiRequest {
+    pub command: ApiCommand,
+    reply: Box<dyn FnOnce(ApiResult) + Send>,
+}
+
+impl ApiRequest {
+    pub fn reply(self, result: ApiResult) {
+        (self.reply)(result)
+    }
+}
+
+#[cfg(feature = "rest-api")]
+mod internal {
+    use std::{
+        fs::File,
+        io::BufReader,
+        net::{SocketAddr, TcpListener as StdTcpListener},
+        sync::Arc,
+        thread,
+    };
+
+    use axum::{
+        extract::{
+            ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+            Path, Request, State,
+        },
+        http::{header, StatusCode},
+        middleware::{self, Next},
+        response::{IntoResponse, Response},
+        routing::{delete, get, post},
+        Json, Router,
+    };
+    use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+    use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+    use ring::constant_time::verify_slices_are_equal;
+    use serde::Deserialize;
+    use tokio::{
+        net::TcpListener,
+        sync::{broadcast, oneshot},
+    };
+    use tokio_rustls::{
+        rustls::{self, crypto::ring::default_provider},
+        TlsAcceptor,
+    };
+
+    use super::{stream_action, ApiCommand, ApiError, ApiEvent, ApiRequest, ApiResult, StreamAction, MAX_EVENT_LAG};
+    use crate::{config::Config, error::Error};
+
+    #[cfg(feature = "grpc")]
+    use crate::grpc::start_grpc;
+
+    #[cfg(not(feature = "grpc"))]
+    fn start_grpc(
+        _addr: &str, _token: Option<String>, _requests: Sender<ApiRequest>, _events: broadcast::Sender<Arc<str>>,
+    ) -> Result<(), Error> {
+        Err(Error::InvalidConfig("The gRPC API is not supported by this build"))
+    }
+
+    /// Number of requests that may wait for the main loop
+    const QUEUE_SIZE: usize = 16;
+
+    #[derive(Clone)]
+    struct ApiState {
+        token: Option<Arc<String>>,
+        requests: Sender<ApiRequest>,
+        events: broadcast::Sender<Arc<str>>,
+    }
+
+    /// Hands the command to the main loop and waits for the result
+    pub async fn execute(requests: &Sender<ApiRequest>, command: ApiCommand) -> ApiResult {
+        let (sender, receiver) = oneshot::channel();
+        let request = ApiRequest {
+            command,
+            reply: Box::new(move |result| {
+                sender.send(result).ok();
+            }),
+        };
+        match requests.try_send(request) {
+            Ok(()) => receiver.await.unwrap_or_else(|_| Err(ApiError::new(503, "Request was dropped"))),
+            Err(TrySendError::Full(_)) => Err(ApiError::new(503, "Too many pending requests")),
+            Err(TrySendError::Disconnected(_)) => Err(ApiError::new(503, "Shutting down")),
+        }
+    }
+
+    impl ApiState {
+        async fn call(&self, command: ApiCommand) -> Response {
+            match execute(&self.requests, command).await {
+                Ok(value) => Json(value).into_response(),
+                Err(err) => (StatusCode::from_u16(err.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR), Json(err))
+                    .into_response(),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct ConnectRequest {
+        addr: String,
+    }
+
+    #[derive(Deserialize)]
+    struct RemoveRequest {
+        addr: String,
+        #[serde(default)]
+        disconnect: bool,
+    }
+
+    async fn authenticate(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+        if let Some(ref token) = state.token {
+            let given = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .unwrap_or("");
+            if verify_slices_are_equal(given.as_bytes(), token.as_bytes()).is_err() {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Bearer")],
+                    Json(ApiError::new(401, "Invalid token")),
+                )
+                    .into_response()
+            }
+        }
+        next.run(request).await
+    }
+
+    async fn get_peers(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::Peers).await
+    }
+
+    async fn get_stats(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::Stats).await
+    }
+
+    async fn get_table(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::Table).await
+    }
+
+    async fn get_table_dump(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::TableDump).await
+    }
+
+    async fn query_table(State(state): State<ApiState>, Path(addr): Path<String>) -> Response {
+        state.call(ApiCommand::TableQuery(addr)).await
+    }
+
+    async fn get_topology(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::Topology).await
+    }
+
+    async fn get_topology_dot(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::TopologyDot).await
+    }
+
+    async fn connect_peer(State(state): State<ApiState>, Json(request): Json<ConnectRequest>) -> Response {
+        state.call(ApiCommand::Connect(request.addr)).await
+    }
+
+    async fn disconnect_peer(State(state): State<ApiState>, Path(addr): Path<String>) -> Response {
+        state.call(ApiCommand::Disconnect(addr)).await
+    }
+
+    async fn get_reconnect_peers(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::ReconnectPeers).await
+    }
+
+    async fn add_reconnect_peer(State(state): State<ApiState>, Json(request): Json<ConnectRequest>) -> Response {
+        state.call(ApiCommand::AddReconnectPeer(request.addr)).await
+    }
+
+    async fn remove_reconnect_peer(State(state): State<ApiState>, Json(request): Json<RemoveRequest>) -> Response {
+        state.call(ApiCommand::RemoveReconnectPeer { addr: request.addr, disconnect: request.disconnect }).await
+    }
+
+    async fn get_tofu(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::TofuList).await
+    }
+
+    async fn remove_tofu(State(state): State<ApiState>, Path(node_id): Path<String>) -> Response {
+        state.call(ApiCommand::TofuRemove(node_id)).await
+    }
+
+    async fn trust_tofu(State(state): State<ApiState>, Path(node_id): Path<String>) -> Response {
+        state.call(ApiCommand::TofuTrust(node_id)).await
+    }
+
+    async fn reload(State(state): State<ApiState>) -> Response {
+        state.call(ApiCommand::Reload).await
+    }
+
+    async fn event_stream(State(state): State<ApiState>, ws: WebSocketUpgrade) -> Response {
+        let events = state.events.subscribe();
+        ws.on_upgrade(move |socket| stream_events(socket, events))
+    }
+
+    async fn stream_events(mut socket: WebSocket, mut events: broadcast::Receiver<Arc<str>>) {
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match stream_action(event.map_err(Into::into), events.len()) {
+                        StreamAction::Send(event) => event,
+                        StreamAction::Disconnect => {
+                            debug!("Disconnecting slow event stream client");
+                            let frame = CloseFrame { code: close_code::POLICY, reason: "Too many pending events".into() };
+                            socket.send(Message::Close(Some(frame))).await.ok();
+                            return
+                        }
+                        StreamAction::Stop => return,
+                    };
+                    if socket.send(Message::Text(event.to_string())).await.is_err() {
+                        return
+                    }
+                }
+                msg = socket.recv() => {
+                    // Only pings and the closing handshake are expected from the client
+                    if let None | Some(Err(_)) | Some(Ok(Message::Close(_))) = msg {
+                        return
+                    }
+                }
+            }
+        }
+    }
+
+    fn load_tls_config(cert_path: &str, key_path: &str) -> Result<Arc<rustls::ServerConfig>, Error> {
+        let mut reader = BufReader::new(
+            File::open(cert_path)
+                .map_err(|e| Error::FileIo { message: "Failed to open API certificate", source: e })?,
+        );
+        let certs = rustls_pemfile::certs(&mut reader)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::FileIo { message: "Failed to read API certificate", source: e })?;
+        let mut reader = BufReader::new(
+            File::open(key_path).map_err(|e| Error::FileIo { message: "Failed to open API key", source: e })?,
+        );
+        let key = rustls_pemfile::private_key(&mut reader)
+            .map_err(|e| Error::FileIo { message: "Failed to read API key", source: e })?
+            .ok_or(Error::InvalidConfig("No private key in API key file"))?;
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|_| Error::InvalidConfig("Invalid API certificate or key"))?;
+        Ok(Arc::new(config))
+    }
+
+    async fn serve_tls(listener: TcpListener, app: Router, acceptor: TlsAcceptor) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!("Failed to accept API connection: {}", err);
+                    continue
+                }
+            };
+            let acceptor = acceptor.clone();
+            let service = TowerToHyperService::new(app.clone());
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        if let Err(err) = hyper::server::conn::http1::Builder::new()
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await
+                        {
+                            debug!("API connection failed: {}", err)
+                        }
+                    }
+                    Err(err) => debug!("API TLS handshake failed: {}", err),
+                }
+            });
+        }
+    }
+
+    pub struct ApiServer {
+        requests: Option<Receiver<ApiRequest>>,
+        events: Option<broadcast::Sender<Arc<str>>>,
+    }
+
+    impl ApiServer {
+        pub fn start(config: &Config) -> Result<Self, Error> {
+            if config.api_addr.is_none() && config.grpc_addr.is_none() {
+                return Ok(Self { requests: None, events: None })
+            }
+            let (sender, receiver) = bounded(QUEUE_SIZE);
+            // The lag of each client is checked separately, the channel only has to hold enough events for that
+            let (events, _) = broadcast::channel(MAX_EVENT_LAG + 1);
+            if let Some(ref addr) = config.api_addr {
+                Self::start_http(config, addr, sender.clone(), events.clone())?;
+            }
+            if let Some(ref addr) = config.grpc_addr {
+                start_grpc(addr, config.api_token.clone(), sender, events.clone())?;
+            }
+            Ok(Self { requests: Some(receiver), events: Some(events) })
+        }
+
+        fn start_http(
+            config: &Config, addr: &str, requests: Sender<ApiRequest>, events: broadcast::Sender<Arc<str>>,
+        ) -> Result<(), Error> {
+            let addr: SocketAddr = addr.parse().map_err(|_| Error::InvalidConfig("Invalid API address"))?;
+            let tls = match (&config.api_tls_cert, &config.api_tls_key) {
+                (Some(cert), Some(key)) => Some(TlsAcceptor::from(load_tls_config(cert, key)?)),
+                (None, None) => None,
+                _ => return Err(Error::InvalidConfig("API TLS needs both a certificate and a key")),
+            };
+            if config.api_token.is_none() {
+                warn!("The management API on {} does not require authentication", addr);
+            }
+            let listener = StdTcpListener::bind(addr)
+                .map_err(|e| Error::SocketIo { message: "Failed to bind API socket", source: e })?;
+            listener
+                .set_nonblocking(true)
+                .map_err(|e| Error::SocketIo { message: "Failed to bind API socket", source: e })?;
+            let state = ApiState { token: config.api_token.clone().map(Arc::new), requests, events };
+            let app = Router::new()
+                .route("/api/peers", get(get_peers))
+                .route("/api/peers/connect", post(connect_peer))
+                .route("/api/peers/:addr", delete(disconnect_peer))
+                .route("/api/reconnect-peers", get(get_reconnect_peers).post(add_reconnect_peer))
+                .route("/api/reconnect-peers/remove", post(remove_reconnect_peer))
+                .route("/api/tofu", get(get_tofu))
+                .route("/api/tofu/:node_id", delete(remove_tofu))
+                .route("/api/tofu/:node_id/trust", post(trust_tofu))
+                .route("/api/stats", get(get_stats))
+                .route("/api/table", get(get_table))
+                .route("/api/table/dump", get(get_table_dump))
+                .route("/api/table/query/:addr", get(query_table))
+                .route("/api/topology", get(get_topology))
+                .route("/api/topology/dot", get(get_topology_dot))
+                .route("/api/reload", post(reload))
+                .route("/api/events", get(event_stream))
+                .route_layer(middleware::from_fn_with_state(state.clone(), authenticate))
+                .with_state(state);
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .build()
+                .map_err(|e| Error::SocketIo { message: "Failed to start API runtime", source: e })?;
+            thread::Builder::new()
+                .name("api".to_string())
+                .spawn(move || {
+                    runtime.block_on(async move {
+                        let listener = match TcpListener::from_std(listener) {
+                            Ok(listener) => listener,
+                            Err(err) => return error!("Failed to start API server: {}", err),
+                        };
+                        match tls {
+                            Some(acceptor) => serve_tls(listener, app, acceptor).await,
+                            None => {
+                                if let Err(err) = axum::serve(listener, app).await {
+                                    error!("API server failed: {}", err)
+                                }
+                            }
+                        }
+                    })
+                })
+                .map_err(|e| Error::SocketIo { message: "Failed to start API thread", source: e })?;
+            info!("Serving management API on {}", addr);
+            Ok(())
+        }
+
+        #[inline]
+        pub fn try_recv(&self) -> Option<ApiRequest> {
+            self.requests.as_ref().and_then(|r| r.try_recv().ok())
+        }
+
+        /// Sends an event to all clients of the event stream, the event is only created if there are any
+        pub fn publish<F: FnOnce() -> ApiEvent>(&self, event: F) {
+            if let Some(ref events) = self.events {
+                if events.receiver_count() > 0 {
+                    match serde_json::to_string(&event()) {
+                        Ok(json) => {
+                            events.send(json.into()).ok();
+                        }
+                        Err(err) => error!("Failed to serialize event: {}", err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "rest-api"))]
+mod internal {
+    use super::{ApiEvent, ApiRequest};
+    use crate::{config::Config, error::Error};
+
+    pub struct ApiServer;
+
+    impl ApiServer {
+        pub fn start(config: &Config) -> Result<Self, Error> {
+            match (&config.api_addr, &config.grpc_addr) {
+                (None, None) => Ok(ApiServer),
+                _ => Err(Error::InvalidConfig("The management API is not supported by this build")),
+            }
+        }
+
+        #[inline]
+        pub fn try_recv(&self) -> Option<ApiRequest> {
+            None
+        }
+
+        #[inline]
+        pub fn publish<F: FnOnce() -> ApiEvent>(&self, _event: F) {}
+    }
+}
+
+pub use internal::*;
+
+#[test]
+fn disconnect_lagging_clients() {
+    assert_eq!(stream_action(Ok(1), 0), StreamAction::Send(1));
+    assert_eq!(stream_action(Ok(1), MAX_EVENT_LAG), StreamAction::Send(1));
+    // Clients are disconnected before the channel has to drop events for them
+    assert_eq!(stream_action(Ok(1), MAX_EVENT_LAG + 1), StreamAction::Disconnect);
+    assert_eq!(stream_action::<u32>(Err(StreamError::Lagged), 0), StreamAction::Disconnect);
+    assert_eq!(stream_action::<u32>(Err(StreamError::Closed), 0), StreamAction::Stop);
+}
+
+#[cfg(feature = "rest-api")]
+#[test]
+fn api_requests() {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let addr = format!("127.0.0.1:{}", port);
+    let config = crate::config::Config {
+        api_addr: Some(addr.clone()),
+        api_token: Some("secret".to_string()),
+        ..Default::default()
+    };
+    let server = ApiServer::start(&config).unwrap();
+    let request = |req: String| {
+        let addr = addr.clone();
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(req.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+    };
+    let unauthorized = request("GET /api/peers HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n".to_string());
+    assert!(unauthorized.join().unwrap().starts_with("HTTP/1.1 401"));
+    let peers = request(
+        "DELETE /api/peers/1.2.3.4:3210 HTTP/1.1\r\nHost: x\r\nAuthorization: Bearer secret\r\nConnection: close\r\n\r\n"
+            .to_string(),
+    );
+    let api_request = loop {
+        if let Some(request) = server.try_recv() {
+            break request
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(api_request.command, ApiCommand::Disconnect("1.2.3.4:3210".to_string()));
+    api_request.reply(Err(ApiError::new(404, "Not a peer")));
+    let response = peers.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"));
+    assert!(response.ends_with("{\"error\":\"Not a peer\"}"));
+}
@@ -0,0 +1,240 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// ARP proxy for Ethernet frames
+//
+// ARP requests are broadcast frames, so without a proxy every request is flooded to all peers. To avoid this, ARP
+// replies that are received from peers are inspected and the IPv4 to MAC mappings are cached. Requests from the local
+// network for a cached address are answered locally with a synthetic reply and are not sent to any peer.
+//
+// Only replies from peers are learned, so that hosts on the local network always answer for themselves. Requests for
+// unknown addresses are still flooded, the reply then fills the cache.
+
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    net::{Ipv4Addr, SocketAddr},
+};
+
+use crate::{
+    cloud::Hash,
+    util::{Duration, Time, TimeSource},
+};
+
+const ETHERTYPE_VLAN: [u8; 2] = [0x81, 0x00];
+const ETHERTYPE_ARP: [u8; 2] = [0x08, 0x06];
+
+/// Ethernet, IPv4, hardware address length 6, protocol address length 4
+const ARP_ETHERNET_IPV4: [u8; 6] = [0x00, 0x01, 0x08, 0x00, 6, 4];
+const ARP_REQUEST: u8 = 1;
+const ARP_REPLY: u8 = 2;
+const ARP_LEN: usize = 28;
+
+#[derive(Debug, PartialEq)]
+struct ArpPacket {
+    vlan: Option<[u8; 2]>,
+    oper: u8,
+    sender_mac: [u8; 6],
+    sender_ip: Ipv4Addr,
+    target_ip: Ipv4Addr,
+}
+
+fn parse_arp(frame: &[u8]) -> Option<ArpPacket> {
+    if frame.len() < 14 {
+        return None
+    }
+    let (vlan, data) = if frame[12..14] == ETHERTYPE_VLAN {
+        if frame.len() < 18 {
+            return None
+        }
+        (Some([frame[14] & 0x0f, frame[15]]), &frame[16..])
+    } else {
+        (None, &frame[12..])
+    };
+    if data[0..2] != ETHERTYPE_ARP {
+        return None
+    }
+    let arp = &data[2..];
+    if arp.len() < ARP_LEN || arp[0..6] != ARP_ETHERNET_IPV4 || arp[6] != 0 {
+        return None
+    }
+    let mut sender_mac = [0; 6];
+    sender_mac.copy_from_slice(&arp[8..14]);
+    Some(ArpPacket {
+        vlan,
+        oper: arp[7],
+        sender_mac,
+        sender_ip: Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]),
+        target_ip: Ipv4Addr::new(arp[24], arp[25], arp[26], arp[27]),
+    })
+}
+
+/// Builds an ARP reply to the request, with `mac` as the address of the requested IP
+fn build_reply(request: &ArpPacket, mac: [u8; 6]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(18 + ARP_LEN);
+    frame.extend_from_slice(&request.sender_mac);
+    frame.extend_from_slice(&mac);
+    if let Some(vlan) = request.vlan {
+        frame.extend_from_slice(&ETHERTYPE_VLAN);
+        frame.extend_from_slice(&vlan);
+    }
+    frame.extend_from_slice(&ETHERTYPE_ARP);
+    frame.extend_from_slice(&ARP_ETHERNET_IPV4);
+    frame.extend_from_slice(&[0, ARP_REPLY]);
+    frame.extend_from_slice(&mac);
+    frame.extend_from_slice(&request.target_ip.octets());
+    frame.extend_from_slice(&request.sender_mac);
+    frame.extend_from_slice(&request.sender_ip.octets());
+    frame
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ArpAnswer {
+    /// The frame is no ARP request
+    NoRequest,
+    /// The requested address is cached, the frame contains the reply
+    Reply(Vec<u8>),
+    /// The requested address is unknown
+    Miss,
+}
+
+struct ArpEntry {
+    mac: [u8; 6],
+    peer: SocketAddr,
+    timeout: Time,
+}
+
+/// IPv4 to MAC mappings of hosts behind peers
+pub struct ArpProxy<TS: TimeSource> {
+    cache: HashMap<(Option<[u8; 2]>, Ipv4Addr), ArpEntry, Hash>,
+    timeout: Duration,
+    _dummy: PhantomData<TS>,
+}
+
+impl<TS: TimeSource> ArpProxy<TS> {
+    pub fn new(timeout: Duration) -> Self {
+        Self { cache: HashMap::default(), timeout, _dummy: PhantomData }
+    }
+
+    /// Learns the address mapping from an ARP reply received from the peer
+    pub fn learn(&mut self, peer: SocketAddr, frame: &[u8]) {
+        let reply = match parse_arp(frame) {
+            Some(packet) if packet.oper == ARP_REPLY => packet,
+            _ => return,
+        };
+        debug!("Learned ARP entry {} => {:02x?} from peer {}", reply.sender_ip, reply.sender_mac, peer);
+        let timeout = TS::now() + Time::from(self.timeout);
+        self.cache.insert((reply.vlan, reply.sender_ip), ArpEntry { mac: reply.sender_mac, peer, timeout });
+    }
+
+    /// Returns the reply to an ARP request from the local network if the requested address is cached
+    pub fn answer(&self, frame: &[u8]) -> ArpAnswer {
+        let request = match parse_arp(frame) {
+            // Gratuitous requests announce the address of the sender, they need no reply
+            Some(packet) if packet.oper == ARP_REQUEST && packet.sender_ip != packet.target_ip => packet,
+            _ => return ArpAnswer::NoRequest,
+        };
+        match self.cache.get(&(request.vlan, request.target_ip)) {
+            Some(entry) if entry.timeout >= TS::now() => ArpAnswer::Reply(build_reply(&request, entry.mac)),
+            _ => ArpAnswer::Miss,
+        }
+    }
+
+    pub fn housekeep(&mut self) {
+        let now = TS::now();
+        self.cache.retain(|_, entry| entry.timeout >= now);
+    }
+
+    /// Forgets the entries learned from all peers that do not match the predicate
+    pub fn retain<F: FnMut(&SocketAddr) -> bool>(&mut self, mut f: F) {
+        self.cache.retain(|_, entry| f(&entry.peer));
+    }
+}
+
+#[cfg(test)]
+fn arp_frame(vlan: Option<[u8; 2]>, oper: u8, sender: ([u8; 6], [u8; 4]), target: ([u8; 6], [u8; 4])) -> Vec<u8> {
+    let mut frame = if oper == ARP_REQUEST { vec![0xff; 6] } else { target.0.to_vec() };
+    frame.extend_from_slice(&sender.0);
+    if let Some(vlan) = vlan {
+        frame.extend_from_slice(&ETHERTYPE_VLAN);
+        frame.extend_from_slice(&vlan);
+    }
+    frame.extend_from_slice(&ETHERTYPE_ARP);
+    frame.extend_from_slice(&ARP_ETHERNET_IPV4);
+    frame.extend_from_slice(&[0, oper]);
+    frame.extend_from_slice(&sender.0);
+    frame.extend_from_slice(&sender.1);
+    frame.extend_from_slice(&target.0);
+    frame.extend_from_slice(&target.1);
+    frame
+}
+
+#[test]
+fn parse_arp_packets() {
+    let request = arp_frame(None, ARP_REQUEST, ([1; 6], [10, 0, 0, 1]), ([0; 6], [10, 0, 0, 2]));
+    assert_eq!(
+        parse_arp(&request),
+        Some(ArpPacket {
+            vlan: None,
+            oper: ARP_REQUEST,
+            sender_mac: [1; 6],
+            sender_ip: Ipv4Addr::new(10, 0, 0, 1),
+            target_ip: Ipv4Addr::new(10, 0, 0, 2)
+        })
+    );
+    let reply = arp_frame(Some([0, 0x67]), ARP_REPLY, ([2; 6], [10, 0, 0, 2]), ([1; 6], [10, 0, 0, 1]));
+    let packet = parse_arp(&reply).unwrap();
+    assert_eq!(packet.vlan, Some([0, 0x67]));
+    assert_eq!(packet.oper, ARP_REPLY);
+    // Truncated packets and other protocols are ignored
+    assert_eq!(parse_arp(&request[..request.len() - 1]), None);
+    assert_eq!(parse_arp(&[2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 0x08, 0x00, 0x45, 0]), None);
+    assert_eq!(parse_arp(&[2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 0x81, 0x00]), None);
+}
+
+#[test]
+fn answer_cached_requests() {
+    use crate::util::MockTimeSource;
+    let peer = "1.2.3.4:3210".parse().unwrap();
+    let mut proxy = ArpProxy::<MockTimeSource>::new(300);
+    let request = arp_frame(None, ARP_REQUEST, ([1; 6], [10, 0, 0, 1]), ([0; 6], [10, 0, 0, 2]));
+    assert_eq!(proxy.answer(&request), ArpAnswer::Miss);
+    // Requests are not learned, only replies from peers
+    proxy.learn(peer, &arp_frame(None, ARP_REQUEST, ([2; 6], [10, 0, 0, 2]), ([0; 6], [10, 0, 0, 3])));
+    assert_eq!(proxy.answer(&request), ArpAnswer::Miss);
+    let reply = arp_frame(None, ARP_REPLY, ([2; 6], [10, 0, 0, 2]), ([1; 6], [10, 0, 0, 1]));
+    proxy.learn(peer, &reply);
+    assert_eq!(proxy.answer(&request), ArpAnswer::Reply(reply));
+    // Other frames and gratuitous requests are not answered
+    assert_eq!(proxy.answer(&[2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2, 3, 4, 5]), ArpAnswer::NoRequest);
+    let gratuitous = arp_frame(None, ARP_REQUEST, ([3; 6], [10, 0, 0, 2]), ([0; 6], [10, 0, 0, 2]));
+    assert_eq!(proxy.answer(&gratuitous), ArpAnswer::NoRequest);
+    // Addresses are cached per VLAN
+    let request = arp_frame(Some([0, 0x67]), ARP_REQUEST, ([1; 6], [10, 0, 0, 1]), ([0; 6], [10, 0, 0, 2]));
+    assert_eq!(proxy.answer(&request), ArpAnswer::Miss);
+    let reply = arp_frame(Some([0, 0x67]), ARP_REPLY, ([4; 6], [10, 0, 0, 2]), ([1; 6], [10, 0, 0, 1]));
+    proxy.learn(peer, &reply);
+    assert_eq!(proxy.answer(&request), ArpAnswer::Reply(reply));
+}
+
+#[test]
+fn forget_entries() {
+    use crate::util::MockTimeSource;
+    let peer1 = "1.2.3.4:3210".parse().unwrap();
+    let peer2 = "1.2.3.5:3210".parse().unwrap();
+    MockTimeSource::set_time(0);
+    let mut proxy = ArpProxy::<MockTimeSource>::new(300);
+    proxy.learn(peer1, &arp_frame(None, ARP_REPLY, ([2; 6], [10, 0, 0, 2]), ([1; 6], [10, 0, 0, 1])));
+    MockTimeSource::set_time(200);
+    proxy.learn(peer2, &arp_frame(None, ARP_REPLY, ([3; 6], [10, 0, 0, 3]), ([1; 6], [10, 0, 0, 1])));
+    let request2 = arp_frame(None, ARP_REQUEST, ([1; 6], [10, 0, 0, 1]), ([0; 6], [10, 0, 0, 2]));
+    let request3 = arp_frame(None, ARP_REQUEST, ([1; 6], [10, 0, 0, 1]), ([0; 6], [10, 0, 0, 3]));
+    MockTimeSource::set_time(400);
+    assert_eq!(proxy.answer(&request2), ArpAnswer::Miss);
+    proxy.housekeep();
+    assert_eq!(proxy.cache.len(), 1);
+    assert!(matches!(proxy.answer(&request3), ArpAnswer::Reply(_)));
+    proxy.retain(|addr| *addr != peer2);
+    assert_eq!(proxy.answer(&request3), ArpAnswer::Miss);
+}
@@ -0,0 +1,149 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    fs::{self, File, OpenOptions, Permissions},
+    io::{self, BufWriter, Write},
+    marker::PhantomData,
+    net::SocketAddr,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
+
+use serde::Serialize;
+
+use super::{
+    error::Error,
+    types::NodeId,
+    util::{addr_nice, bytes_to_hex, Time, TimeSource},
+};
+
+/// Why a peer has been disconnected
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// No message has been received from the peer within the peer timeout
+    Timeout,
+    /// The peer sent a close message
+    Close,
+    /// The peer did not answer the probes of the dead peer detection
+    DeadPeer,
+    /// The key exchange with the peer failed
+    CryptoFailure,
+    /// The peer was removed via the management API
+    Removed,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum AuditEvent {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    timestamp: Time,
+    event: AuditEvent,
+    peer: String,
+    node_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<DisconnectReason>,
+}
+
+/// Log of all peer connections, written as one JSON object per line
+///
+/// The file is renamed to `<path>.1` when it grows larger than the configured size.
+pub struct AuditLog<TS: TimeSource> {
+    path: String,
+    max_bytes: u64,
+    writer: BufWriter<File>,
+    size: u64,
+    _dummy: PhantomData<TS>,
+}
+
+impl<TS: TimeSource> AuditLog<TS> {
+    pub fn open(path: &str, max_bytes: u64) -> Result<Self, Error> {
+        let file =
+            Self::open_file(path).map_err(|e| Error::FileIo { message: "Failed to open audit log", source: e })?;
+        let size = file.metadata().map_err(|e| Error::FileIo { message: "Failed to open audit log", source: e })?.len();
+        Ok(Self { path: path.to_string(), max_bytes, writer: BufWriter::new(file), size, _dummy: PhantomData })
+    }
+
+    fn open_file(path: &str) -> Result<File, io::Error> {
+        let file = OpenOptions::new().create(true).append(true).mode(0o600).open(path)?;
+        // The mode is only applied to new files
+        fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+
+    pub fn connected(&mut self, peer: SocketAddr, node_id: &NodeId) {
+        self.write(AuditEvent::Connected, peer, node_id, None)
+    }
+
+    pub fn disconnected(&mut self, peer: SocketAddr, node_id: &NodeId, reason: DisconnectReason) {
+        self.write(AuditEvent::Disconnected, peer, node_id, Some(reason))
+    }
+
+    fn write(&mut self, event: AuditEvent, peer: SocketAddr, node_id: &NodeId, reason: Option<DisconnectReason>) {
+        let entry = AuditEntry {
+            timestamp: TS::now(),
+            event,
+            peer: addr_nice(peer).to_string(),
+            node_id: bytes_to_hex(node_id),
+            reason,
+        };
+        if let Err(err) = self.write_entry(&entry) {
+            error!("Failed to write audit log: {}", err);
+        }
+        if self.size > self.max_bytes {
+            if let Err(err) = self.rotate() {
+                error!("Failed to rotate audit log: {}", err);
+            }
+        }
+    }
+
+    fn write_entry(&mut self, entry: &AuditEntry) -> Result<(), io::Error> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.writer.flush()?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        info!("Rotating audit log {}", self.path);
+        fs::rename(&self.path, format!("{}.1", self.path))?;
+        self.writer = BufWriter::new(Self::open_file(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[test]
+fn audit_log_rotation() {
+    use crate::util::MockTimeSource;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.log");
+    let path = path.to_str().unwrap();
+    let peer = "1.2.3.4:3210".parse().unwrap();
+    MockTimeSource::set_time(1000);
+    let mut log = AuditLog::<MockTimeSource>::open(path, 200).unwrap();
+    log.connected(peer, &[1; 16]);
+    let content = fs::read_to_string(path).unwrap();
+    let entry: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+    assert_eq!(entry["timestamp"], 1000);
+    assert_eq!(entry["event"], "connected");
+    assert_eq!(entry["peer"], "1.2.3.4:3210");
+    assert_eq!(entry["node_id"], "01010101010101010101010101010101");
+    assert!(entry.get("reason").is_none());
+    assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+    // The second entry exceeds the size limit, so the file is rotated
+    log.disconnected(peer, &[1; 16], DisconnectReason::DeadPeer);
+    let rotated = fs::read_to_string(format!("{}.1", path)).unwrap();
+    assert_eq!(rotated.lines().count(), 2);
+    assert!(rotated.contains("\"reason\":\"dead_peer\""));
+    log.disconnected(peer, &[1; 16], DisconnectReason::Timeout);
+    assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 1);
+}
@@ -2,7 +2,11 @@
 // Copyright (C) 2015-2021  Dennis Schwerdel
 // This software is licensed under GPL-3 or newer (see LICENSE.md)
 
-use ring::digest;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    digest, hkdf,
+    rand::{SecureRandom, SystemRandom},
+};
 
 use std::{
     fs::{self, File, Permissions},
@@ -20,7 +24,10 @@ use std::{
     thread,
 };
 
-use super::util::{from_base62, to_base62, Encoder, TimeSource};
+use super::{
+    error::Error,
+    util::{from_base62, to_base62, Encoder, TimeSource},
+};
 use smallvec::SmallVec;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
@@ -28,6 +35,12 @@ const TYPE_BEGIN: u8 = 0;
 const TYPE_END: u8 = 1;
 const TYPE_DATA: u8 = 2;
 const TYPE_SEED: u8 = 3;
+const TYPE_BEGIN_SEALED: u8 = 4;
+const TYPE_END_SEALED: u8 = 5;
+
+/// Prefix of encrypted peer lists, it also keeps leading zeros of the nonce from getting lost in the base62 encoding
+const SEALED_VERSION: u8 = 1;
+const SEALING_SALT: &[u8; 16] = b"vpncloudBeacons!";
 
 fn base_62_sanitize(data: &str) -> String {
     data.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
@@ -45,6 +58,8 @@ struct FutureResult<T> {
 #[derive(Clone)]
 pub struct BeaconSerializer<TS> {
     shared_key: Vec<u8>,
+    sealing_key: Option<Arc<LessSafeKey>>,
+    allow_plaintext: bool,
     future_peers: Arc<FutureResult<Vec<SocketAddr>>>,
     _dummy_ts: PhantomData<TS>,
 }
@@ -53,11 +68,24 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
     pub fn new(shared_key: &[u8]) -> Self {
         Self {
             shared_key: shared_key.to_owned(),
+            sealing_key: None,
+            allow_plaintext: false,
             future_peers: Arc::new(FutureResult { has_result: AtomicBool::new(false), result: Mutex::new(Vec::new()) }),
             _dummy_ts: PhantomData,
         }
     }
 
+    /// Encrypts the beacons with a key derived from the secret, only beacons encrypted with the same secret are read
+    ///
+    /// With `allow_plaintext`, beacons that are not encrypted are read as well.
+    pub fn with_encryption(mut self, secret: &[u8], allow_plaintext: bool) -> Self {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, SEALING_SALT).extract(secret);
+        let key = prk.expand(&[b"beacon"], &AES_256_GCM).expect("Invalid key length");
+        self.sealing_key = Some(Arc::new(LessSafeKey::new(UnboundKey::from(key))));
+        self.allow_plaintext = allow_plaintext;
+        self
+    }
+
     fn now_hour_16() -> u16 {
         ((TS::now() / 3600) & 0xffff) as u16
     }
@@ -85,12 +113,25 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
         }
     }
 
+    fn marker(&self, type_: u8) -> String {
+        to_base62(&self.get_keystream(type_, 0, 0))[0..5].to_string()
+    }
+
     fn begin(&self) -> String {
-        to_base62(&self.get_keystream(TYPE_BEGIN, 0, 0))[0..5].to_string()
+        self.marker(TYPE_BEGIN)
     }
 
     fn end(&self) -> String {
-        to_base62(&self.get_keystream(TYPE_END, 0, 0))[0..5].to_string()
+        self.marker(TYPE_END)
+    }
+
+    /// Returns the prefix and suffix of the beacons written by this serializer
+    fn markers(&self) -> (String, String) {
+        if self.sealing_key.is_some() {
+            (self.marker(TYPE_BEGIN_SEALED), self.marker(TYPE_END_SEALED))
+        } else {
+            (self.begin(), self.end())
+        }
     }
 
     fn encrypt_data(&self, data: &mut Vec<u8>) {
@@ -112,6 +153,27 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
     }
 
     fn peerlist_encode(&self, peers: &[SocketAddr]) -> String {
+        let mut data = Self::peerlist_bytes(peers);
+        match self.sealing_key {
+            Some(ref key) => {
+                let mut nonce = [0; NONCE_LEN];
+                SystemRandom::new().fill(&mut nonce).expect("Failed to create nonce");
+                key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+                    .expect("Failed to encrypt beacon");
+                let mut sealed = Vec::with_capacity(1 + NONCE_LEN + data.len());
+                sealed.push(SEALED_VERSION);
+                sealed.extend_from_slice(&nonce);
+                sealed.extend_from_slice(&data);
+                to_base62(&sealed)
+            }
+            None => {
+                self.encrypt_data(&mut data);
+                to_base62(&data)
+            }
+        }
+    }
+
+    fn peerlist_bytes(peers: &[SocketAddr]) -> Vec<u8> {
         let mut data = Vec::new();
         // Add timestamp
         data.extend_from_slice(&Self::now_hour_16().to_be_bytes());
@@ -148,20 +210,39 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
             Encoder::write_u16(addr.port(), &mut dat[16..]);
             data.extend_from_slice(&dat);
         }
-        self.encrypt_data(&mut data);
-        to_base62(&data)
+        data
     }
 
     fn peerlist_decode(&self, data: &str, ttl_hours: Option<u16>) -> Vec<SocketAddr> {
         let mut data = from_base62(data).expect("Invalid input");
-        let mut peers = Vec::new();
-        let mut pos = 0;
         if data.len() < 4 {
-            return peers;
+            return Vec::new();
         }
         if !self.decrypt_data(&mut data) {
-            return peers;
+            return Vec::new();
+        }
+        Self::peerlist_parse(&data, ttl_hours)
+    }
+
+    fn peerlist_open(key: &LessSafeKey, data: &str, ttl_hours: Option<u16>) -> Result<Vec<SocketAddr>, Error> {
+        let data = from_base62(data).expect("Invalid input");
+        if data.len() <= NONCE_LEN || data[0] != SEALED_VERSION {
+            return Err(Error::Beacon("Unsupported beacon format".to_string()))
         }
+        let nonce = Nonce::try_assume_unique_for_key(&data[1..=NONCE_LEN]).expect("Invalid nonce length");
+        let mut sealed = data[1 + NONCE_LEN..].to_vec();
+        let data = key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .map_err(|_| Error::Beacon("Failed to authenticate beacon".to_string()))?;
+        if data.len() < 3 {
+            return Ok(Vec::new())
+        }
+        Ok(Self::peerlist_parse(data, ttl_hours))
+    }
+
+    fn peerlist_parse(data: &[u8], ttl_hours: Option<u16>) -> Vec<SocketAddr> {
+        let mut peers = Vec::new();
+        let mut pos = 0;
         let then = Wrapping(Encoder::read_u16(&data[pos..=pos + 1]));
         if let Some(ttl) = ttl_hours {
             let now = Wrapping(Self::now_hour_16());
@@ -205,7 +286,8 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
     }
 
     pub fn encode(&self, peers: &[SocketAddr]) -> String {
-        format!("{}{}{}", self.begin(), self.peerlist_encode(peers), self.end())
+        let (begin, end) = self.markers();
+        format!("{}{}{}", begin, self.peerlist_encode(peers), end)
     }
 
     pub fn write_to_file<P: AsRef<Path>>(&self, peers: &[SocketAddr], path: P) -> Result<(), io::Error> {
@@ -222,9 +304,8 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
     }
 
     pub fn write_to_cmd(&self, peers: &[SocketAddr], cmd: &str) -> Result<(), io::Error> {
-        let begin = self.begin();
+        let (begin, end) = self.markers();
         let data = self.peerlist_encode(peers);
-        let end = self.end();
         let beacon = format!("{}{}{}", begin, data, end);
         debug!("Calling beacon command: {}", cmd);
         let process = Command::new("sh")
@@ -247,38 +328,64 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
         Ok(())
     }
 
-    pub fn decode(&self, data: &str, ttl_hours: Option<u16>) -> Vec<SocketAddr> {
+    fn for_each_beacon<F: FnMut(&str)>(data: &str, begin: &str, end: &str, mut f: F) {
         let data = base_62_sanitize(data);
-        let mut peers = Vec::new();
-        let begin = self.begin();
-        let end = self.end();
         let mut pos = 0;
-        while let Some(found) = data[pos..].find(&begin) {
+        while let Some(found) = data[pos..].find(begin) {
             pos += found;
             let start_pos = pos + begin.len();
-            if let Some(found) = data[pos..].find(&end) {
+            if let Some(found) = data[pos..].find(end) {
                 let end_pos = pos + found;
-                peers.append(&mut self.peerlist_decode(&data[start_pos..end_pos], ttl_hours));
+                f(&data[start_pos..end_pos]);
                 pos = start_pos
             } else {
                 break;
             }
         }
+    }
+
+    pub fn decode(&self, data: &str, ttl_hours: Option<u16>) -> Vec<SocketAddr> {
+        let mut peers = Vec::new();
+        Self::for_each_beacon(data, &self.begin(), &self.end(), |beacon| {
+            peers.append(&mut self.peerlist_decode(beacon, ttl_hours))
+        });
         peers
     }
 
-    pub fn read_from_file<P: AsRef<Path>>(
-        &self, path: P, ttl_hours: Option<u16>,
-    ) -> Result<Vec<SocketAddr>, io::Error> {
-        let mut f = File::open(&path)?;
+    /// Decodes the beacons like `decode` but only reads encrypted beacons if the serializer has a key
+    ///
+    /// Fails if no peers were found but an encrypted beacon could not be authenticated.
+    pub fn decode_checked(&self, data: &str, ttl_hours: Option<u16>) -> Result<Vec<SocketAddr>, Error> {
+        let key = match self.sealing_key {
+            Some(ref key) => key,
+            None => return Ok(self.decode(data, ttl_hours)),
+        };
+        let mut peers = Vec::new();
+        let mut error = None;
+        let (begin, end) = self.markers();
+        Self::for_each_beacon(data, &begin, &end, |beacon| match Self::peerlist_open(key, beacon, ttl_hours) {
+            Ok(mut found) => peers.append(&mut found),
+            Err(err) => error = Some(err),
+        });
+        if self.allow_plaintext {
+            peers.append(&mut self.decode(data, ttl_hours));
+        }
+        match error {
+            Some(err) if peers.is_empty() => Err(err),
+            _ => Ok(peers),
+        }
+    }
+
+    pub fn read_from_file<P: AsRef<Path>>(&self, path: P, ttl_hours: Option<u16>) -> Result<Vec<SocketAddr>, Error> {
         let mut contents = String::new();
-        f.read_to_string(&mut contents)?;
-        Ok(self.decode(&contents, ttl_hours))
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| Error::BeaconIo { message: "Failed to read beacon from file", source: e })?;
+        self.decode_checked(&contents, ttl_hours)
     }
 
     pub fn read_from_cmd(&self, cmd: &str, ttl_hours: Option<u16>) -> Result<(), io::Error> {
-        let begin = self.begin();
-        let end = self.end();
+        let (begin, end) = self.markers();
         debug!("Calling beacon command: {}", cmd);
         let process = Command::new("sh")
             .args(&["-c", cmd])
@@ -292,10 +399,14 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
             let output = process.wait_with_output().expect("Failed to wait on child");
             if output.status.success() {
                 let data = String::from_utf8_lossy(&output.stdout);
-                let mut peers = this.decode(&data, ttl_hours);
-                debug!("Beacon command succeeded with {} peers", peers.len());
-                mem::swap(&mut peers, &mut this.future_peers.result.lock().expect("Lock poisoned"));
-                this.future_peers.has_result.store(true, Ordering::Relaxed);
+                match this.decode_checked(&data, ttl_hours) {
+                    Ok(mut peers) => {
+                        debug!("Beacon command succeeded with {} peers", peers.len());
+                        mem::swap(&mut peers, &mut this.future_peers.result.lock().expect("Lock poisoned"));
+                        this.future_peers.has_result.store(true, Ordering::Relaxed);
+                    }
+                    Err(err) => error!("{}", err),
+                }
             } else {
                 error!("Beacon command failed: {}", String::from_utf8_lossy(&output.stderr));
             }
@@ -315,6 +426,210 @@ impl<TS: TimeSource> BeaconSerializer<TS> {
     }
 }
 
+/// Beacons older than this are ignored when loading
+pub const BEACON_TTL_HOURS: u16 = 50;
+
+/// A location to store beacons in and load them from
+pub trait BeaconStore: Send {
+    /// Stores a beacon with the given peers
+    fn write(&mut self, peers: &[SocketAddr]) -> Result<(), Error>;
+
+    /// Loads the peers from all beacons, at most `max` of them
+    ///
+    /// Stores that load in the background return no peers here, the peers are returned by `poll` later.
+    fn read(&mut self, max: Option<usize>) -> Result<Vec<SocketAddr>, Error>;
+
+    /// Returns the peers of a background load that completed since the last call
+    fn poll(&mut self) -> Option<Vec<SocketAddr>> {
+        None
+    }
+
+    /// Removes the beacon of this node, called on shutdown
+    fn remove(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub fn limit_peers(mut peers: Vec<SocketAddr>, max: Option<usize>) -> Vec<SocketAddr> {
+    if let Some(max) = max {
+        peers.truncate(max)
+    }
+    peers
+}
+
+/// Opens the store for the location given in the config
+///
+/// Locations starting with a pipe character are commands, locations starting with `etcd://` are keys in etcd and all
+/// other locations are files.
+pub fn open_beacon_store<TS: TimeSource>(
+    location: &str, serializer: BeaconSerializer<TS>,
+) -> Result<Box<dyn BeaconStore>, Error> {
+    Ok(if let Some(cmd) = location.strip_prefix('|') {
+        Box::new(CmdBeaconStore { serializer, cmd: cmd.to_string(), max: None })
+    } else if location.starts_with("etcd://") {
+        Box::new(EtcdBeaconStore { serializer, client: EtcdClient::connect(location)? })
+    } else {
+        Box::new(FileBeaconStore { serializer, path: location.to_string() })
+    })
+}
+
+/// Stores the beacon in a file and loads beacons from a file
+pub struct FileBeaconStore<TS> {
+    serializer: BeaconSerializer<TS>,
+    path: String,
+}
+
+impl<TS: TimeSource> BeaconStore for FileBeaconStore<TS> {
+    fn write(&mut self, peers: &[SocketAddr]) -> Result<(), Error> {
+        self.serializer
+            .write_to_file(peers, &self.path)
+            .map_err(|e| Error::BeaconIo { message: "Failed to write beacon to file", source: e })
+    }
+
+    fn read(&mut self, max: Option<usize>) -> Result<Vec<SocketAddr>, Error> {
+        let peers = self.serializer.read_from_file(&self.path, Some(BEACON_TTL_HOURS))?;
+        Ok(limit_peers(peers, max))
+    }
+
+    fn remove(&mut self) -> Result<(), Error> {
+        let path = Path::new(&self.path);
+        if path.exists() {
+            info!("Removing beacon file");
+            fs::remove_file(path)
+                .map_err(|e| Error::BeaconIo { message: "Failed to remove beacon file", source: e })?;
+        }
+        Ok(())
+    }
+}
+
+/// Passes the beacon to a command and loads beacons from the output of a command
+///
+/// The commands run in the background, so loaded peers are returned by `poll`.
+pub struct CmdBeaconStore<TS> {
+    serializer: BeaconSerializer<TS>,
+    cmd: String,
+    max: Option<usize>,
+}
+
+impl<TS: TimeSource> BeaconStore for CmdBeaconStore<TS> {
+    fn write(&mut self, peers: &[SocketAddr]) -> Result<(), Error> {
+        self.serializer
+            .write_to_cmd(peers, &self.cmd)
+            .map_err(|e| Error::BeaconIo { message: "Failed to call beacon command", source: e })
+    }
+
+    fn read(&mut self, max: Option<usize>) -> Result<Vec<SocketAddr>, Error> {
+        self.max = max;
+        self.serializer
+            .read_from_cmd(&self.cmd, Some(BEACON_TTL_HOURS))
+            .map_err(|e| Error::BeaconIo { message: "Failed to call beacon command", source: e })?;
+        Ok(vec![])
+    }
+
+    fn poll(&mut self) -> Option<Vec<SocketAddr>> {
+        self.serializer.get_cmd_results().map(|peers| limit_peers(peers, self.max))
+    }
+}
+
+#[cfg(feature = "etcd")]
+mod internal {
+    use std::time::Duration;
+
+    use etcd_client::{Client, ConnectOptions};
+    use tokio::runtime::{Builder, Runtime};
+
+    use crate::error::Error;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub struct EtcdClient {
+        runtime: Runtime,
+        client: Client,
+        key: String,
+    }
+
+    impl EtcdClient {
+        /// Connects to the endpoints of a location like `etcd://host1:2379,host2:2379/key`
+        pub fn connect(location: &str) -> Result<Self, Error> {
+            let location = location.strip_prefix("etcd://").ok_or(Error::InvalidConfig("Invalid etcd location"))?;
+            let (endpoints, key) = location.split_once('/').ok_or(Error::InvalidConfig("No etcd key given"))?;
+            if endpoints.is_empty() || key.is_empty() {
+                return Err(Error::InvalidConfig("Invalid etcd location"))
+            }
+            let endpoints: Vec<_> = endpoints.split(',').map(|e| format!("http://{}", e)).collect();
+            let runtime = Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|_| Error::InvalidConfig("Failed to create etcd runtime"))?;
+            let options = ConnectOptions::new().with_timeout(TIMEOUT).with_connect_timeout(TIMEOUT);
+            let client = runtime
+                .block_on(Client::connect(endpoints, Some(options)))
+                .map_err(|e| Error::Beacon(e.to_string()))?;
+            Ok(Self { runtime, client, key: key.to_string() })
+        }
+
+        pub fn put(&mut self, value: String) -> Result<(), Error> {
+            self.runtime
+                .block_on(self.client.put(self.key.as_str(), value, None))
+                .map_err(|e| Error::Beacon(e.to_string()))?;
+            Ok(())
+        }
+
+        pub fn get(&mut self) -> Result<Option<String>, Error> {
+            let res = self
+                .runtime
+                .block_on(self.client.get(self.key.as_str(), None))
+                .map_err(|e| Error::Beacon(e.to_string()))?;
+            Ok(res.kvs().first().and_then(|kv| kv.value_str().ok()).map(str::to_string))
+        }
+    }
+}
+
+#[cfg(not(feature = "etcd"))]
+mod internal {
+    use crate::error::Error;
+
+    pub struct EtcdClient;
+
+    impl EtcdClient {
+        pub fn connect(_location: &str) -> Result<Self, Error> {
+            Err(Error::InvalidConfig("etcd is not supported by this build"))
+        }
+
+        pub fn put(&mut self, _value: String) -> Result<(), Error> {
+            unreachable!("etcd is not supported by this build")
+        }
+
+        pub fn get(&mut self) -> Result<Option<String>, Error> {
+            unreachable!("etcd is not supported by this build")
+        }
+    }
+}
+
+use internal::EtcdClient;
+
+/// Stores the beacon under a key in etcd and loads beacons from that key
+///
+/// All nodes share the key, so the last node to store its beacon wins. As every node stores its beacon periodically,
+/// every node is found eventually. The key is not removed on shutdown as it might hold the beacon of another node.
+pub struct EtcdBeaconStore<TS> {
+    serializer: BeaconSerializer<TS>,
+    client: EtcdClient,
+}
+
+impl<TS: TimeSource> BeaconStore for EtcdBeaconStore<TS> {
+    fn write(&mut self, peers: &[SocketAddr]) -> Result<(), Error> {
+        self.client.put(self.serializer.encode(peers))
+    }
+
+    fn read(&mut self, max: Option<usize>) -> Result<Vec<SocketAddr>, Error> {
+        Ok(match self.client.get()? {
+            Some(data) => limit_peers(self.serializer.decode_checked(&data, Some(BEACON_TTL_HOURS))?, max),
+            None => vec![],
+        })
+    }
+}
+
 #[cfg(test)]
 use crate::util::MockTimeSource;
 #[cfg(test)]
@@ -443,6 +758,25 @@ fn encode_decode_file() {
     assert_eq!(format!("{:?}", peers), format!("{:?}", peers2.unwrap()));
 }
 
+#[test]
+fn encrypted_beacon_file() {
+    MockTimeSource::set_time(2000 * 3600);
+    let ser = BeaconSerializer::<MockTimeSource>::new(b"").with_encryption(b"mysecretkey", false);
+    let peers = vec![SocketAddr::from_str("1.2.3.4:5678").unwrap(), SocketAddr::from_str("[::1]:5678").unwrap()];
+    let file = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+    ser.write_to_file(&peers, file.path()).unwrap();
+    assert_eq!(ser.read_from_file(file.path(), None).unwrap(), peers);
+    // The beacon can not be read without the key
+    let plain = BeaconSerializer::<MockTimeSource>::new(b"");
+    assert!(plain.read_from_file(file.path(), None).unwrap().is_empty());
+    let wrong = BeaconSerializer::<MockTimeSource>::new(b"").with_encryption(b"otherkey", true);
+    assert!(matches!(wrong.read_from_file(file.path(), None), Err(Error::Beacon(_))));
+    // Beacons that are not encrypted are only read if allowed
+    plain.write_to_file(&peers, file.path()).unwrap();
+    assert!(ser.read_from_file(file.path(), None).unwrap().is_empty());
+    assert_eq!(wrong.read_from_file(file.path(), None).unwrap(), peers);
+}
+
 #[test]
 fn encode_decode_cmd() {
     MockTimeSource::set_time(2000 * 3600);
@@ -458,3 +792,20 @@ fn encode_decode_cmd() {
     assert!(peers2.is_some());
     assert_eq!(format!("{:?}", peers), format!("{:?}", peers2.unwrap()));
 }
+
+#[test]
+fn file_beacon_store() {
+    MockTimeSource::set_time(2000 * 3600);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("beacon");
+    let ser = BeaconSerializer::<MockTimeSource>::new(b"mysecretkey");
+    let mut store = open_beacon_store(path.to_str().unwrap(), ser).unwrap();
+    let peers = vec![SocketAddr::from_str("1.2.3.4:5678").unwrap(), SocketAddr::from_str("[::1]:5678").unwrap()];
+    store.write(&peers).unwrap();
+    assert_eq!(store.read(None).unwrap(), peers);
+    assert_eq!(store.read(Some(1)).unwrap(), &peers[..1]);
+    assert!(store.poll().is_none());
+    store.remove().unwrap();
+    assert!(!path.exists());
+    assert!(store.read(None).is_err());
+}
@@ -0,0 +1,23 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+//! CNI plugin binary, the container runtime calls it by the name `vpncloud-cni`
+//!
+//! The plugin is implemented by the `vpncloud cni` command, this binary only executes it. The `vpncloud` binary is
+//! taken from the same directory or from the `PATH`.
+
+use std::{env, os::unix::process::CommandExt, path::PathBuf, process};
+
+fn main() {
+    let binary = env::current_exe()
+        .map(|exe| exe.with_file_name("vpncloud"))
+        .ok()
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from("vpncloud"));
+    // Only returns on failure, stdin and environment are passed on to the command
+    let err = process::Command::new(&binary).arg("cni").exec();
+    let msg = format!("Failed to execute {}: {}", binary.display(), err);
+    println!("{}", serde_json::json!({ "cniVersion": "0.4.0", "code": 100, "msg": msg }));
+    process::exit(1)
+}
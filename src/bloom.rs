@@ -0,0 +1,104 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Probabilistic set membership
+//
+// A Bloom filter answers whether an item might be in a set: an item that has been inserted is always found, an item
+// that has not been inserted is found with a small probability (false positive). Items can not be removed, so the
+// filter has to be rebuilt from the set after removals.
+//
+// The positions of an item are derived from two FNV hashes (double hashing), which is as good as independent hash
+// functions for this purpose.
+
+use fnv::FnvHasher;
+use std::hash::{Hash, Hasher};
+
+/// Offset of the second hash, any value works as long as it differs from the first
+const SECOND_HASH_KEY: u64 = 0x9e37_79b9_7f4a_7c15;
+
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hashes: u32,
+    capacity: usize,
+    items: usize,
+}
+
+impl BloomFilter {
+    /// Creates a filter that has the given false positive rate when it contains `capacity` items
+    pub fn with_rate(capacity: usize, fp_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        let ln2 = std::f64::consts::LN_2;
+        let words = (-(capacity as f64) * fp_rate.ln() / (ln2 * ln2) / 64.0) as usize + 1;
+        let bit_count = words as u64 * 64;
+        let hashes = ((bit_count as f64 / capacity as f64 * ln2).round() as u32).max(1);
+        Self { bits: vec![0; words], bit_count, hashes, capacity, items: 0 }
+    }
+
+    #[inline]
+    fn hashes<T: Hash>(item: &T) -> (u64, u64) {
+        let mut hasher = FnvHasher::default();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+        let mut hasher = FnvHasher::with_key(SECOND_HASH_KEY);
+        item.hash(&mut hasher);
+        // An odd step visits different positions for all hashes
+        (h1, hasher.finish() | 1)
+    }
+
+    pub fn insert<T: Hash>(&mut self, item: &T) {
+        let (h1, h2) = Self::hashes(item);
+        for i in 0..self.hashes as u64 {
+            let pos = h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count;
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+        self.items += 1;
+    }
+
+    /// Returns false if the item has definitely not been inserted
+    #[inline]
+    pub fn contains<T: Hash>(&self, item: &T) -> bool {
+        let (h1, h2) = Self::hashes(item);
+        (0..self.hashes as u64).all(|i| {
+            let pos = h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count;
+            self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0
+        })
+    }
+
+    pub fn clear(&mut self) {
+        for word in &mut self.bits {
+            *word = 0
+        }
+        self.items = 0;
+    }
+
+    /// Number of insertions since the filter was cleared
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// Number of items for which the filter has been sized
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[test]
+fn false_positive_rate() {
+    let mut filter = BloomFilter::with_rate(1000, 0.01);
+    for i in 0..1000u32 {
+        filter.insert(&i);
+    }
+    assert_eq!(filter.len(), 1000);
+    assert!((0..1000u32).all(|i| filter.contains(&i)));
+    let false_positives = (1000..101_000u32).filter(|i| filter.contains(i)).count();
+    assert!(false_positives < 2000, "{}", false_positives);
+    filter.clear();
+    assert!(filter.is_empty());
+    assert!(!filter.contains(&1u32));
+}
@@ -0,0 +1,389 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Packet capture in the pcap-ng format
+//
+// The packets are copied into a queue that is written to the file by a background thread, so that disk I/O does not
+// stall the main loop. Network packets are captured with synthesized IP and UDP headers, as the socket only provides
+// the payload. The capture stops once the file reaches its maximum size.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use schemars::JsonSchema;
+
+use crate::{device::Type, error::Error, net::mapped_addr};
+
+/// Number of packets that can be queued before new packets are dropped
+const QUEUE_SIZE: usize = 1024;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 1;
+const BLOCK_ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const OPTION_END: u16 = 0;
+const OPTION_EPB_FLAGS: u16 = 2;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+
+/// Interface of the network packets in the capture
+const INTERFACE_NETWORK: u32 = 0;
+/// Interface of the device packets in the capture
+const INTERFACE_DEVICE: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+pub enum CaptureLayer {
+    /// The UDP packets exchanged with the peers
+    #[serde(rename = "network")]
+    Network,
+    /// The packets read from and written to the virtual device
+    #[serde(rename = "device")]
+    Device,
+    #[serde(rename = "both")]
+    Both,
+}
+
+impl fmt::Display for CaptureLayer {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            CaptureLayer::Network => write!(formatter, "network"),
+            CaptureLayer::Device => write!(formatter, "device"),
+            CaptureLayer::Both => write!(formatter, "both"),
+        }
+    }
+}
+
+impl FromStr for CaptureLayer {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match &text.to_lowercase() as &str {
+            "network" => Self::Network,
+            "device" => Self::Device,
+            "both" => Self::Both,
+            _ => return Err("Unknown capture layer"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+struct Packet {
+    interface: u32,
+    direction: Direction,
+    time: SystemTime,
+    peer: Option<SocketAddr>,
+    data: Vec<u8>,
+}
+
+fn write_block<W: Write>(out: &mut W, type_: u32, body: &[u8]) -> Result<usize, io::Error> {
+    let padding = (4 - body.len() % 4) % 4;
+    let len = (12 + body.len() + padding) as u32;
+    out.write_all(&type_.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&[0; 3][..padding])?;
+    out.write_all(&len.to_le_bytes())?;
+    Ok(len as usize)
+}
+
+fn write_header<W: Write>(out: &mut W, device_type: Type) -> Result<usize, io::Error> {
+    let mut body = Vec::with_capacity(16);
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // The length of the section is not known
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    let mut written = write_block(out, BLOCK_SECTION_HEADER, &body)?;
+    let device_link = match device_type {
+        Type::Tap => LINKTYPE_ETHERNET,
+        Type::Tun => LINKTYPE_RAW,
+    };
+    for link in &[LINKTYPE_RAW, device_link] {
+        let mut body = Vec::with_capacity(8);
+        body.extend_from_slice(&link.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // No limit on the captured length
+        body.extend_from_slice(&0u32.to_le_bytes());
+        written += write_block(out, BLOCK_INTERFACE_DESCRIPTION, &body)?;
+    }
+    Ok(written)
+}
+
+fn unmapped(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), v6.port()),
+            None => addr,
+        },
+        _ => addr,
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header.chunks(2).map(|c| u32::from(u16::from_be_bytes([c[0], c[1]]))).sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Prepends IP and UDP headers to the payload of a network packet
+fn encapsulate(src: SocketAddr, dst: SocketAddr, payload: &[u8], out: &mut Vec<u8>) {
+    let udp_len = (8 + payload.len()) as u16;
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let mut header = [0; 20];
+            header[0] = 0x45;
+            header[2..4].copy_from_slice(&(20 + udp_len).to_be_bytes());
+            // Don't fragment
+            header[6] = 0x40;
+            header[8] = 64;
+            header[9] = 17;
+            header[12..16].copy_from_slice(&src_ip.octets());
+            header[16..20].copy_from_slice(&dst_ip.octets());
+            let checksum = ipv4_checksum(&header);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            out.extend_from_slice(&header);
+        }
+        (src_ip, dst_ip) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            out.extend_from_slice(&[0x60, 0, 0, 0]);
+            out.extend_from_slice(&udp_len.to_be_bytes());
+            out.extend_from_slice(&[17, 64]);
+            out.extend_from_slice(&to_v6(src_ip).octets());
+            out.extend_from_slice(&to_v6(dst_ip).octets());
+        }
+    }
+    out.extend_from_slice(&src.port().to_be_bytes());
+    out.extend_from_slice(&dst.port().to_be_bytes());
+    out.extend_from_slice(&udp_len.to_be_bytes());
+    // The checksum is not calculated
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(payload);
+}
+
+fn write_packet<W: Write>(out: &mut W, local: SocketAddr, packet: &Packet) -> Result<usize, io::Error> {
+    let micros = packet.time.duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0);
+    let mut data = Vec::with_capacity(packet.data.len() + 48);
+    match packet.peer {
+        Some(peer) => {
+            let peer = unmapped(peer);
+            let local = match (local.ip(), peer.ip()) {
+                (IpAddr::V4(_), IpAddr::V6(_)) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), local.port()),
+                (IpAddr::V6(ip), IpAddr::V4(_)) => match ip.to_ipv4_mapped() {
+                    Some(ip) => SocketAddr::new(IpAddr::V4(ip), local.port()),
+                    None => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), local.port()),
+                },
+                _ => local,
+            };
+            match packet.direction {
+                Direction::In => encapsulate(peer, local, &packet.data, &mut data),
+                Direction::Out => encapsulate(local, peer, &packet.data, &mut data),
+            }
+        }
+        None => data.extend_from_slice(&packet.data),
+    }
+    let padding = (4 - data.len() % 4) % 4;
+    let mut body = Vec::with_capacity(20 + data.len() + padding + 12);
+    body.extend_from_slice(&packet.interface.to_le_bytes());
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&data);
+    body.extend_from_slice(&[0; 3][..padding]);
+    let flags: u32 = match packet.direction {
+        Direction::In => 1,
+        Direction::Out => 2,
+    };
+    body.extend_from_slice(&OPTION_EPB_FLAGS.to_le_bytes());
+    body.extend_from_slice(&4u16.to_le_bytes());
+    body.extend_from_slice(&flags.to_le_bytes());
+    body.extend_from_slice(&OPTION_END.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    write_block(out, BLOCK_ENHANCED_PACKET, &body)
+}
+
+/// Captures the packets of the node to a pcap-ng file
+pub struct PacketCapture {
+    packets: SyncSender<Packet>,
+    active: Arc<AtomicBool>,
+    network: bool,
+    device: bool,
+    filter_peer: Option<SocketAddr>,
+}
+
+impl PacketCapture {
+    pub fn start(
+        path: &str, layer: CaptureLayer, max_bytes: u64, filter_peer: Option<SocketAddr>, device_type: Type,
+        local: SocketAddr,
+    ) -> Result<Self, Error> {
+        let mut file = BufWriter::new(
+            File::create(path).map_err(|e| Error::FileIo { message: "Failed to create capture file", source: e })?,
+        );
+        let written = write_header(&mut file, device_type)
+            .and_then(|written| file.flush().map(|_| written))
+            .map_err(|e| Error::FileIo { message: "Failed to write capture file", source: e })?;
+        let (packets, receiver) = sync_channel(QUEUE_SIZE);
+        let active = Arc::new(AtomicBool::new(true));
+        let thread_active = active.clone();
+        let path = path.to_string();
+        thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || {
+                if let Err(err) = Self::run(file, written as u64, max_bytes, local, receiver, &thread_active) {
+                    error!("Failed to write capture file {}: {}", path, err)
+                }
+                thread_active.store(false, Ordering::Relaxed);
+            })
+            .map_err(|e| Error::FileIo { message: "Failed to start capture thread", source: e })?;
+        Ok(Self {
+            packets,
+            active,
+            network: layer != CaptureLayer::Device,
+            device: layer != CaptureLayer::Network,
+            filter_peer: filter_peer.map(mapped_addr),
+        })
+    }
+
+    fn run(
+        mut file: BufWriter<File>, mut written: u64, max_bytes: u64, local: SocketAddr, packets: Receiver<Packet>,
+        active: &AtomicBool,
+    ) -> Result<(), io::Error> {
+        let mut data = Vec::with_capacity(2048);
+        while let Ok(packet) = packets.recv() {
+            for packet in Some(packet).into_iter().chain(packets.try_iter()) {
+                data.clear();
+                write_packet(&mut data, local, &packet)?;
+                if written + data.len() as u64 > max_bytes {
+                    info!("Capture file reached its maximum size, stopping the capture");
+                    active.store(false, Ordering::Relaxed);
+                    return file.flush()
+                }
+                file.write_all(&data)?;
+                written += data.len() as u64;
+            }
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn send(&self, packet: Packet) {
+        if self.packets.try_send(packet).is_err() {
+            debug!("Dropping captured packet, the capture queue is full")
+        }
+    }
+
+    /// Captures a packet received from or sent to the peer
+    #[inline]
+    pub fn network(&self, direction: Direction, peer: SocketAddr, data: &[u8]) {
+        if !self.network || !self.active.load(Ordering::Relaxed) {
+            return
+        }
+        if let Some(filter) = self.filter_peer {
+            if filter != mapped_addr(peer) {
+                return
+            }
+        }
+        self.send(Packet {
+            interface: INTERFACE_NETWORK,
+            direction,
+            time: SystemTime::now(),
+            peer: Some(peer),
+            data: data.to_vec(),
+        })
+    }
+
+    /// Captures a packet read from or written to the device
+    ///
+    /// Packets read from the device are captured as outbound, packets written to it as inbound.
+    #[inline]
+    pub fn device(&self, direction: Direction, data: &[u8]) {
+        if !self.device || !self.active.load(Ordering::Relaxed) {
+            return
+        }
+        self.send(Packet {
+            interface: INTERFACE_DEVICE,
+            direction,
+            time: SystemTime::now(),
+            peer: None,
+            data: data.to_vec(),
+        })
+    }
+}
+
+#[test]
+fn capture_file_format() {
+    use std::time::Duration;
+    let mut out = vec![];
+    assert_eq!(write_header(&mut out, Type::Tap).unwrap(), 68);
+    assert_eq!(&out[0..4], &[0x0a, 0x0d, 0x0d, 0x0a]);
+    assert_eq!(&out[8..12], &BYTE_ORDER_MAGIC.to_le_bytes());
+    assert_eq!(&out[28..32], &BLOCK_INTERFACE_DESCRIPTION.to_le_bytes());
+    assert_eq!(&out[36..38], &LINKTYPE_RAW.to_le_bytes());
+    assert_eq!(&out[56..58], &LINKTYPE_ETHERNET.to_le_bytes());
+    let local = "192.168.1.1:3210".parse().unwrap();
+    let packet = Packet {
+        interface: INTERFACE_NETWORK,
+        direction: Direction::In,
+        time: UNIX_EPOCH + Duration::from_micros(0x1_0000_0002),
+        peer: Some(mapped_addr("1.2.3.4:5678".parse().unwrap())),
+        data: vec![1, 2, 3],
+    };
+    let mut out = vec![];
+    // 32 bytes of headers, 20 bytes IPv4, 8 bytes UDP, 3 bytes data padded to 4, 12 bytes options
+    assert_eq!(write_packet(&mut out, local, &packet).unwrap(), 76);
+    assert_eq!(out.len(), 76);
+    assert_eq!(&out[0..4], &BLOCK_ENHANCED_PACKET.to_le_bytes());
+    assert_eq!(&out[4..8], &76u32.to_le_bytes());
+    assert_eq!(&out[12..16], &1u32.to_le_bytes());
+    assert_eq!(&out[16..20], &2u32.to_le_bytes());
+    assert_eq!(&out[20..24], &31u32.to_le_bytes());
+    let ip = &out[28..48];
+    assert_eq!(ip[0], 0x45);
+    assert_eq!(ipv4_checksum(ip), 0);
+    assert_eq!(&ip[12..16], &[1, 2, 3, 4]);
+    assert_eq!(&ip[16..20], &[192, 168, 1, 1]);
+    assert_eq!(&out[48..56], &[0x16, 0x2e, 0x0c, 0x8a, 0, 11, 0, 0]);
+    assert_eq!(&out[56..59], &[1, 2, 3]);
+    assert_eq!(&out[72..76], &76u32.to_le_bytes());
+}
+
+#[test]
+fn capture_max_bytes() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let path = file.path().to_str().unwrap();
+    let capture =
+        PacketCapture::start(path, CaptureLayer::Both, 240, None, Type::Tun, "0.0.0.0:3210".parse().unwrap()).unwrap();
+    for _ in 0..10 {
+        capture.device(Direction::Out, &[0x45; 40]);
+    }
+    while capture.active.load(Ordering::Relaxed) {
+        thread::yield_now();
+    }
+    // The header and two packets of 84 bytes fit
+    assert_eq!(std::fs::metadata(path).unwrap().len(), 68 + 2 * 84);
+    capture.device(Direction::Out, &[0x45; 40]);
+}
@@ -4,41 +4,89 @@
 
 use std::{
     cmp::{max, min},
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fmt,
-    fs::{self, File},
+    fs::File,
     hash::BuildHasherDefault,
     io::{self, Cursor, Seek, SeekFrom, Write},
     marker::PhantomData,
     net::{SocketAddr, ToSocketAddrs},
+    os::unix::io::{AsRawFd, RawFd},
     path::Path,
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Instant,
 };
 
 use fnv::FnvHasher;
-use rand::{random, seq::SliceRandom, thread_rng};
+use rand::{random, seq::SliceRandom, thread_rng, Rng};
+use ring::digest;
 use smallvec::{smallvec, SmallVec};
 
 use crate::{
-    beacon::BeaconSerializer,
+    acl::Acl,
+    api::{api_value, ApiCommand, ApiError, ApiEvent, ApiResult, ApiServer},
+    arp_proxy::{ArpAnswer, ArpProxy},
+    audit::{AuditLog, DisconnectReason},
+    beacon::{open_beacon_store, BeaconSerializer, BeaconStore},
+    capture::{Direction, PacketCapture},
     config::{Config, DEFAULT_PEER_TIMEOUT, DEFAULT_PORT},
-    crypto::{is_init_message, Crypto, MessageResult, PeerCrypto},
-    device::{Device, Type},
+    crypto::{is_init_message, Crypto, MessageResult, Payload, PeerCrypto, EXTRA_LEN, TAG_LEN},
+    device::{mac_from_node_id, parse_mac, Device, Type, DEFAULT_MTU},
+    dns_beacon::DnsBeaconStore,
     error::Error,
+    fragment::{self, FragmentBuffer},
+    gossip::{Change, ChangeLog},
+    identity::Identity,
+    igmp_snoop::GroupTable,
+    local_bus::{self, LocalPeer},
     messages::{
-        AddrList, NodeInfo, PeerInfo, MESSAGE_TYPE_CLOSE, MESSAGE_TYPE_DATA, MESSAGE_TYPE_KEEPALIVE,
-        MESSAGE_TYPE_NODE_INFO,
+        add_padding, strip_padding, AddrList, Feature, Features, NodeInfo, PeerInfo, PeerUpdate, PunchCoordinate,
+        MESSAGE_TYPE_CLOSE, MESSAGE_TYPE_DATA, MESSAGE_TYPE_DATA_FRAGMENT, MESSAGE_TYPE_DATA_PADDED,
+        MESSAGE_TYPE_DATA_TRACED, MESSAGE_TYPE_KEEPALIVE, MESSAGE_TYPE_MTU_PROBE, MESSAGE_TYPE_MTU_PROBE_REPLY,
+        MESSAGE_TYPE_NODE_INFO, MESSAGE_TYPE_PEER_UPDATE, MESSAGE_TYPE_PING, MESSAGE_TYPE_PONG, MESSAGE_TYPE_PROBE,
+        MESSAGE_TYPE_PROBE_REPLY, MESSAGE_TYPE_PUNCH_COORDINATE, PADDING_TRAILER_LEN, PROTOCOL_VERSION,
     },
-    net::{mapped_addr, parse_listen, Socket},
+    metrics::{self, InfluxLine},
+    net::{mapped_addr, parse_listen, PathMtuTable, Socket},
+    netlink::RtnetlinkWatcher,
+    noise::{self, NoiseHandshake},
+    pacing::{Pacer, PACING_POLL_TIMEOUT},
     payload::Protocol,
-    poll::{WaitImpl, WaitResult},
+    peer_list::{PeerList, SavedPeer},
+    policy::PolicyTable,
+    poll::WaitResult,
     port_forwarding::PortForwarding,
-    table::ClaimTable,
-    traffic::TrafficStats,
+    receivers::ReceiveThreads,
+    reorder::ReorderBuffer,
+    route_sync::RouteSync,
+    snmp::{SnmpAgent, SnmpStats},
+    srv_bootstrap,
+    stats::{
+        CryptoSnapshot, MtuSnapshot, PeerInfo as PeerInfoSnapshot, PeerPath, PeerSnapshot, ReconnectPeerSnapshot,
+        StatsFormat, StatsSnapshot, TableClaimSnapshot, STATS_SCHEMA_VERSION,
+    },
+    systemd::SystemdNotifier,
+    table::{MultiPathLookup, PersistentTable},
+    telemetry::{Telemetry, TraceContext, TraceSpan},
+    tofu::TofuStore,
+    topology::Topology,
+    traffic::{PacketLoss, TrafficStats},
+    turn::{TurnRelay, TurnResult},
     types::{Address, Mode, NodeId, Range, RangeList},
-    util::{addr_nice, bytes_to_hex, resolve, CtrlC, Duration, MsgBuffer, StatsdMsg, Time, TimeSource},
+    util::{addr_nice, bytes_to_hex, resolve, CtrlC, Duration, MsgBuffer, MsgBufferPool, StatsdMsg, Time, TimeSource},
+    vxlan::{Encap, VxlanTransport, VXLAN_PORT},
+    webhook::WebhookNotifier,
 };
 
+#[cfg(feature = "async")]
+use crate::poll::AsyncWait;
+#[cfg(not(feature = "async"))]
+use crate::poll::WaitImpl;
+
 pub type Hash = BuildHasherDefault<FnvHasher>;
 
 const MAX_RECONNECT_INTERVAL: u16 = 3600;
@@ -46,6 +94,25 @@ const RESOLVE_INTERVAL: Time = 300;
 pub const STATS_INTERVAL: Time = 60;
 const OWN_ADDRESS_RESET_INTERVAL: Time = 300;
 const SPACE_BEFORE: usize = 100;
+const DPD_RETRY_INTERVAL: Time = 5;
+const MTU_PROBE_SIZE: u16 = 1400;
+const HOLE_PUNCH_TIMEOUT: Time = 60;
+const PING_INTERVAL: Time = 10;
+// UDP payload of a 1500 byte IPv4 packet, used when the path MTU is unknown
+const DEFAULT_MAX_PAYLOAD: usize = 1472;
+// Ethernet header with a VLAN tag
+const MAX_FRAME_HEADER: usize = 18;
+
+/// Logs the size of a socket buffer and warns if the system limited it to less than 80% of the requested size
+fn check_buffer_size(name: &str, requested: usize, actual: usize, sysctl: &str) {
+    debug!("Socket {} buffer size is {} bytes", name, actual);
+    if actual < requested / 5 * 4 {
+        warn!(
+            "Socket {} buffer is limited to {} bytes instead of {} bytes, raise the limit with `sysctl -w {}={}`",
+            name, actual, requested, sysctl, requested
+        );
+    }
+}
 
 struct PeerData {
     addrs: AddrList,
@@ -54,9 +121,41 @@ struct PeerData {
     timeout: Time,
     peer_timeout: u16,
     node_id: NodeId,
+    /// The protocol extensions that both sides support
+    features: Features,
+    tags: HashMap<String, String>,
+    path: PeerPath,
+    encap: Encap,
+    loss: PacketLoss,
+    advertised_peers: SmallVec<[NodeId; 16]>,
+    /// The smaller one of the MTUs of both sides, if the peer advertised its MTU
+    mtu: Option<u16>,
+    /// Data longer than this is sent in fragments, if the peer reassembles fragments
+    max_data_len: Option<usize>,
+    /// Version of the change log at the last peer list or update sent to the peer
+    gossip_version: u64,
+    next_full_gossip: Time,
+    /// Smoothed round trip time in milliseconds, only measured with latency based routing
+    rtt: Option<u64>,
+    /// Data messages held back to deliver them in order, with their message type
+    reorder: Option<ReorderBuffer<(u8, Vec<u8>)>>,
+    /// The peer runs in the same process and receives the messages directly
+    local: Option<LocalPeer>,
     crypto: PeerCrypto<NodeInfo>,
 }
 
+struct DpdState {
+    packets: usize,
+    probes: usize,
+    next_probe: Time,
+}
+
+struct NatKeepaliveState {
+    /// Number of packets sent to the peer at the last check
+    packets: usize,
+    last_sent: Time,
+}
+
 #[derive(Clone)]
 pub struct ReconnectEntry {
     address: Option<(String, Time)>,
@@ -64,43 +163,226 @@ pub struct ReconnectEntry {
     tries: u16,
     timeout: u16,
     next: Time,
+    last_attempt: Option<Time>,
     final_timeout: Option<Time>,
 }
 
+impl ReconnectEntry {
+    pub fn snapshot(&self) -> ReconnectPeerSnapshot {
+        ReconnectPeerSnapshot {
+            address: self.address.as_ref().map(|(address, _)| address.clone()),
+            resolved: self.resolved.iter().map(|addr| addr_nice(*addr).to_string()).collect(),
+            tries: self.tries,
+        }
+    }
+
+    #[inline]
+    fn has_address(&self, address: &str) -> bool {
+        self.address.as_ref().map(|(a, _)| a == address).unwrap_or(false)
+    }
+
+    /// Schedules the next connection attempt with exponential back-off
+    ///
+    /// With jitter, the attempt happens at a random time within the interval, so that nodes that lost the same peer
+    /// do not all reconnect at once.
+    fn schedule_next(&mut self, now: Time, jitter: bool) {
+        // Every 10 tries, the interval doubles
+        self.tries += 1;
+        if self.tries > 10 {
+            self.tries = 0;
+            self.timeout *= 2;
+        }
+        // Maximum interval is one hour
+        if self.timeout > MAX_RECONNECT_INTERVAL {
+            self.timeout = MAX_RECONNECT_INTERVAL;
+        }
+        let delay = if jitter { thread_rng().gen_range(0..self.timeout) } else { self.timeout };
+        self.next = now + Time::from(delay);
+    }
+}
+
 pub struct GenericCloud<D: Device, P: Protocol, S: Socket, TS: TimeSource> {
     node_id: NodeId,
     config: Config,
     learning: bool,
     broadcast: bool,
+    /// Only accessed by the thread running the cloud, so lookups never wait for a lock
     peers: HashMap<SocketAddr, PeerData, Hash>,
+    dpd_state: HashMap<SocketAddr, DpdState, Hash>,
+    nat_keepalive_state: HashMap<SocketAddr, NatKeepaliveState, Hash>,
+    hole_punches: HashMap<NodeId, Time, Hash>,
     reconnect_peers: SmallVec<[ReconnectEntry; 3]>,
     own_addresses: AddrList,
     pending_inits: HashMap<SocketAddr, PeerCrypto<NodeInfo>, Hash>,
-    table: ClaimTable<TS>,
+    table: PersistentTable<TS>,
+    multipath: Option<MultiPathLookup>,
+    policy: PolicyTable,
+    acl: Acl,
+    groups: Option<GroupTable<TS>>,
+    arp_proxy: Option<ArpProxy<TS>>,
+    fragment_buffer: FragmentBuffer,
+    peer_changes: ChangeLog,
     socket: S,
+    path_mtus: PathMtuTable,
+    device_mtu: u16,
+    packet_tos: Option<u8>,
+    pacer: Option<Pacer>,
+    turn: TurnRelay,
+    buffers: MsgBufferPool,
     device: D,
     claims: RangeList,
+    excluded_routes: RangeList,
     crypto: Crypto,
     next_peers: Time,
+    next_keepalive: Time,
+    next_ping: Time,
+    /// Reference point of the timestamps in pings
+    clock: Instant,
     peer_timeout_publish: u16,
     update_freq: u16,
     stats_file: Option<File>,
+    audit_log: Option<AuditLog<TS>>,
+    webhook: Option<WebhookNotifier<TS>>,
+    snmp: Option<SnmpAgent>,
+    capture: Option<PacketCapture>,
+    tofu: Option<TofuStore<TS>>,
+    identity: Option<Identity>,
+    noise: Option<NoiseHandshake>,
+    vxlan: Option<VxlanTransport>,
+    /// Addresses without a session that sent VXLAN encapsulated messages
+    pending_vxlan: HashSet<SocketAddr, Hash>,
+    api: ApiServer,
     statsd_server: Option<String>,
     next_housekeep: Time,
+    next_init_retransmit: Instant,
     next_stats_out: Time,
     next_beacon: Time,
     next_own_address_reset: Time,
+    address_watcher: Option<RtnetlinkWatcher>,
     port_forwarding: Option<PortForwarding>,
     traffic: TrafficStats,
-    beacon_serializer: BeaconSerializer<TS>,
+    beacon_store: Option<Box<dyn BeaconStore>>,
+    beacon_load: Option<Box<dyn BeaconStore>>,
+    /// Peers found in the SRV records of the bootstrap domains
+    bootstrap: Option<mpsc::Receiver<Vec<String>>>,
+    receivers: Option<ReceiveThreads>,
+    /// Registration on the bus of the nodes in this process, if the local shortcut is enabled
+    local_bus: Option<local_bus::Registration>,
+    telemetry: Telemetry,
+    notifier: SystemdNotifier,
+    stop_flag: Arc<AtomicBool>,
+    _dummy_p: PhantomData<P>,
+    _dummy_ts: PhantomData<TS>,
+}
+
+/// Stops a running node, also from other threads
+#[derive(Clone)]
+pub struct StopHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    /// Stops the main loop within a second, the node closes the connections to its peers before `run` returns
+    pub fn stop(&self) {
+        self.flag.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+}
+
+/// Collects the parts of a node before it is created
+///
+/// The config, the socket and the device are required, port forwarding and the stats file are optional.
+#[must_use]
+pub struct CloudBuilder<D: Device, P: Protocol, S: Socket, TS: TimeSource> {
+    config: Option<Config>,
+    socket: Option<S>,
+    device: Option<D>,
+    port_forwarding: Option<PortForwarding>,
+    stats_file: Option<File>,
     _dummy_p: PhantomData<P>,
     _dummy_ts: PhantomData<TS>,
 }
 
+impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> CloudBuilder<D, P, S, TS> {
+    pub fn new() -> Self {
+        Self {
+            config: None,
+            socket: None,
+            device: None,
+            port_forwarding: None,
+            stats_file: None,
+            _dummy_p: PhantomData,
+            _dummy_ts: PhantomData,
+        }
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn socket(mut self, socket: S) -> Self {
+        self.socket = Some(socket);
+        self
+    }
+
+    pub fn device(mut self, device: D) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn port_forwarding(mut self, port_forwarding: Option<PortForwarding>) -> Self {
+        self.port_forwarding = port_forwarding;
+        self
+    }
+
+    pub fn stats_file(mut self, stats_file: Option<File>) -> Self {
+        self.stats_file = stats_file;
+        self
+    }
+
+    /// Creates the node
+    ///
+    /// # Errors
+    /// Returns an error if the config, the socket or the device is missing. Invalid settings in the config still
+    /// terminate the process like before.
+    pub fn build(self) -> Result<GenericCloud<D, P, S, TS>, Error> {
+        let config = self.config.ok_or(Error::InvalidConfig("The node needs a config"))?;
+        let socket = self.socket.ok_or(Error::InvalidConfig("The node needs a socket"))?;
+        let device = self.device.ok_or(Error::InvalidConfig("The node needs a device"))?;
+        Ok(GenericCloud::create(&config, socket, device, self.port_forwarding, self.stats_file))
+    }
+}
+
+impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> Default for CloudBuilder<D, P, S, TS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS> {
-    #[allow(clippy::too_many_arguments)]
+    /// Creates a node, see `CloudBuilder` for a more readable way
+    #[allow(dead_code)]
     pub fn new(
         config: &Config, socket: S, device: D, port_forwarding: Option<PortForwarding>, stats_file: Option<File>,
+    ) -> Self {
+        try_fail!(
+            CloudBuilder::new()
+                .config(config.clone())
+                .socket(socket)
+                .device(device)
+                .port_forwarding(port_forwarding)
+                .stats_file(stats_file)
+                .build(),
+            "Failed to create node: {}"
+        )
+    }
+
+    fn create(
+        config: &Config, mut socket: S, device: D, port_forwarding: Option<PortForwarding>, stats_file: Option<File>,
     ) -> Self {
         let (learning, broadcast) = match config.mode {
             Mode::Normal => match config.device_type {
@@ -122,7 +404,7 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
                     info!("Auto-claiming {} due to interface address", range);
                     claims.push(range);
                 }
-                Err(Error::DeviceIo(_, e)) if e.kind() == io::ErrorKind::AddrNotAvailable => {
+                Err(Error::DeviceIo { source: e, .. }) if e.kind() == io::ErrorKind::AddrNotAvailable => {
                     info!("No address set on interface.")
                 }
                 Err(e) => error!("{}", e),
@@ -130,35 +412,312 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         }
         let now = TS::now();
         let update_freq = config.get_keepalive() as u16;
-        let node_id = random();
+        let node_id = match config.node_id {
+            Some(ref node_id) => try_fail!(Crypto::parse_node_id(node_id), "Invalid node id: {}"),
+            None => random(),
+        };
         let crypto = Crypto::new(node_id, &config.crypto).unwrap();
         let beacon_key = config.beacon_password.as_ref().map(|s| s.as_bytes()).unwrap_or(&[]);
+        let mut beacon_serializer = BeaconSerializer::<TS>::new(beacon_key);
+        let beacons_enabled =
+            config.beacon_store.is_some() || config.beacon_load.is_some() || config.dns_beacon_domain.is_some();
+        if config.beacon_encrypt {
+            // The beacons can only be encrypted with a secret that all nodes share
+            match config.beacon_password.as_ref().or(config.crypto.password.as_ref()) {
+                Some(secret) => {
+                    beacon_serializer =
+                        beacon_serializer.with_encryption(secret.as_bytes(), config.beacon_allow_plaintext)
+                }
+                None if beacons_enabled => warn!("Beacons are not encrypted as no beacon password or password is set"),
+                None => (),
+            }
+        }
+        // Beacons are only stored in DNS with a key to sign the updates
+        let beacon_store: Option<Box<dyn BeaconStore>> =
+            match (&config.beacon_store, &config.dns_beacon_domain, &config.dns_key_file) {
+                (Some(location), _, _) => Some(try_fail!(
+                    open_beacon_store(location, beacon_serializer.clone()),
+                    "Failed to open beacon store: {}"
+                )),
+                (None, Some(domain), Some(key_file)) => Some(Box::new(try_fail!(
+                    DnsBeaconStore::open(domain, Some(key_file), beacon_serializer.clone()),
+                    "Failed to open DNS beacon store: {}"
+                ))),
+                _ => None,
+            };
+        let beacon_load: Option<Box<dyn BeaconStore>> = match (&config.beacon_load, &config.dns_beacon_domain) {
+            (Some(location), _) => Some(try_fail!(
+                open_beacon_store(location, beacon_serializer.clone()),
+                "Failed to open beacon store: {}"
+            )),
+            (None, Some(domain)) => Some(Box::new(try_fail!(
+                DnsBeaconStore::open(domain, None, beacon_serializer.clone()),
+                "Failed to open DNS beacon store: {}"
+            ))),
+            (None, None) => None,
+        };
+        let bootstrap = if config.bootstrap_dns.is_empty() {
+            None
+        } else {
+            Some(try_fail!(srv_bootstrap::start(&config.bootstrap_dns), "Failed to start DNS bootstrapping: {}"))
+        };
+        let mut table = try_fail!(
+            PersistentTable::new(
+                config.switch_timeout as Duration,
+                config.peer_timeout as Duration,
+                config.table_persistence_path.as_deref()
+            ),
+            "Failed to load routing table: {}"
+        );
+        table.set_flap_dampening(config.flap_window_secs, config.max_flaps);
+        table.set_bloom_capacity(config.max_table_entries);
+        if config.sync_routes {
+            RouteSync::new(device.ifname(), config.local_tunnel_ip, config.sync_routes_dry_run)
+                .start(table.subscribe());
+        }
+        if config.pmtu_discovery {
+            try_fail!(socket.set_dont_fragment(true), "Failed to enable path MTU discovery: {}");
+        }
+        if let Some(dscp) = config.dscp {
+            if dscp > 63 {
+                fail!("Invalid DSCP value: {}", dscp);
+            }
+            try_fail!(socket.set_tos(dscp << 2), "Failed to set DSCP value: {}");
+            if config.dscp_inherit {
+                warn!("The static DSCP value is used instead of inheriting it from the tunneled packets");
+            }
+        }
+        let mut traffic = TrafficStats::new(config.traffic_stats_addresses);
+        if let Some(size) = config.socket_recv_buffer_bytes {
+            let actual = try_fail!(socket.set_recv_buffer_size(size), "Failed to set socket receive buffer size: {}");
+            check_buffer_size("receive", size, actual, "net.core.rmem_max");
+            traffic.socket_recv_buffer_bytes = Some(actual);
+        }
+        if let Some(size) = config.socket_send_buffer_bytes {
+            let actual = try_fail!(socket.set_send_buffer_size(size), "Failed to set socket send buffer size: {}");
+            check_buffer_size("send", size, actual, "net.core.wmem_max");
+            traffic.socket_send_buffer_bytes = Some(actual);
+        }
+        #[cfg(feature = "ebpf")]
+        {
+            if crate::ebpf::applies(&config) {
+                match socket.attach_filter() {
+                    Ok(()) => info!("Dropping invalid packets in the kernel"),
+                    Err(err) => warn!("Failed to attach packet filter: {}", err),
+                }
+            } else {
+                info!("Not filtering packets in the kernel, as unencrypted messages, Noise, VXLAN or TURN are enabled")
+            }
+        }
+        let mut excluded_routes = SmallVec::with_capacity(config.excluded_routes.len());
+        for s in &config.excluded_routes {
+            excluded_routes.push(try_fail!(Range::from_str(s), "Invalid subnet format: {} ({})", s));
+        }
+        if !excluded_routes.is_empty() {
+            warn!("Traffic to excluded routes is dropped, OS routes for them have to be installed separately");
+        }
+        let policy = try_fail!(
+            PolicyTable::from_config(&config.policy_rules, &config.policy_tables),
+            "Invalid policy routing config: {}"
+        );
+        let acl = try_fail!(Acl::from_config(&config.acl), "Invalid ACL config: {}");
+        // Multicast groups are only snooped when switching Ethernet frames
+        let groups = if learning && config.device_type == Type::Tap {
+            Some(GroupTable::new(config.igmp_timeout as Duration))
+        } else {
+            None
+        };
+        let arp_proxy = if config.arp_proxy && learning && config.device_type == Type::Tap {
+            Some(ArpProxy::new(config.arp_proxy_ttl_secs))
+        } else {
+            None
+        };
+        let audit_log = config
+            .audit_log
+            .as_ref()
+            .map(|path| try_fail!(AuditLog::open(path, config.audit_log_max_bytes), "Failed to open audit log: {}"));
+        let webhook = config.peer_event_webhook.as_ref().map(|url| {
+            try_fail!(WebhookNotifier::start(url, config.webhook_secret.clone()), "Failed to setup webhook: {}")
+        });
+        let capture = config.capture_file.as_ref().map(|path| {
+            let local = socket.address().unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+            try_fail!(
+                PacketCapture::start(
+                    path,
+                    config.capture_layer,
+                    config.capture_max_bytes,
+                    config.capture_filter_peer,
+                    config.device_type,
+                    local
+                ),
+                "Failed to start packet capture: {}"
+            )
+        });
+        let snmp = if config.snmp_addr.is_some() || config.snmp_trap_receiver.is_some() {
+            Some(try_fail!(
+                SnmpAgent::start(config.snmp_addr, config.snmp_community.clone(), config.snmp_trap_receiver),
+                "Failed to setup SNMP agent: {}"
+            ))
+        } else {
+            None
+        };
+        let tofu = config
+            .tofu_store
+            .as_ref()
+            .map(|path| try_fail!(TofuStore::open(path, config.tofu_mode), "Failed to open TOFU store: {}"));
+        let identity = config
+            .identity_key
+            .as_ref()
+            .map(|path| try_fail!(Identity::load_or_create(path), "Failed to load identity key: {}"));
+        if !config.plaintext_peers.is_empty() && config.require_encryption {
+            fail!("Plaintext peers are refused as encryption is required (see require-encryption)");
+        }
+        for peer in &config.plaintext_peers {
+            warn!("[WARNING] plaintext peer configured: {}, its messages are not encrypted", peer);
+        }
+        let vxlan = config.vxlan_vni.map(|vni| {
+            let port = socket.address().map(|addr| addr.port()).unwrap_or(0);
+            let vxlan = try_fail!(VxlanTransport::new(vni, port), "Invalid VXLAN config: {}");
+            if !vxlan.is_listening() {
+                warn!("VXLAN peers are only detected when listening on port {}", VXLAN_PORT);
+            }
+            vxlan
+        });
+        let noise = if config.noise_protocol {
+            // The pre-shared key is the SHA-256 hash of the password, so that WireGuard peers can be configured with it
+            let psk = match config.crypto.password {
+                Some(ref password) => {
+                    let mut psk = [0; 32];
+                    psk.copy_from_slice(digest::digest(&digest::SHA256, password.as_bytes()).as_ref());
+                    psk
+                }
+                None => fail!("Noise peers can not be authenticated without a password"),
+            };
+            let path = match config.noise_key {
+                Some(ref path) => path,
+                None => fail!("The noise protocol requires a key file (see noise-key)"),
+            };
+            let noise = try_fail!(NoiseHandshake::load_or_create(path, psk), "Failed to load noise key: {}");
+            info!("Noise public key is {}", bytes_to_hex(noise.public_key()));
+            Some(noise)
+        } else {
+            None
+        };
+        let device_mtu = match device.get_mtu() {
+            Ok(mtu) => mtu,
+            Err(err) => {
+                warn!("Failed to detect MTU of device, assuming {}: {}", DEFAULT_MTU, err);
+                DEFAULT_MTU
+            }
+        };
+        if device.get_type() == Type::Tap {
+            let mac = match config.tap_mac {
+                Some(ref mac) => Some(try_fail!(parse_mac(mac), "Invalid MAC address {}: {}", mac)),
+                None if config.tap_mac_from_node_id => Some(mac_from_node_id(&node_id)),
+                None => None,
+            };
+            if let Some(mac) = mac {
+                if let Err(err) = device.set_mac(mac) {
+                    warn!("Failed to set MAC address of device: {}", err);
+                }
+            }
+            if let Ok(mac) = device.get_mac() {
+                let mut data = [0; 16];
+                data[..6].copy_from_slice(&mac);
+                info!("Device MAC address is {}", Address { data, len: 6 });
+            }
+        } else if config.tap_mac.is_some() || config.tap_mac_from_node_id {
+            warn!("MAC addresses can only be set on TAP devices");
+        }
+        let mut config = config.clone();
+        config.mtu = Some(match config.mtu {
+            Some(mtu) if mtu > device_mtu => {
+                warn!("MTU {} is larger than the device MTU {}, using the device MTU", mtu, device_mtu);
+                device_mtu
+            }
+            Some(mtu) => mtu,
+            None => device_mtu,
+        });
+        let telemetry = try_fail!(Telemetry::new(config.otel_endpoint.as_deref()), "Failed to setup OpenTelemetry: {}");
+        let api = try_fail!(ApiServer::start(&config), "Failed to start management API: {}");
+        let address_watcher = match socket.watch_addresses() {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                debug!("Not watching for address changes: {}", err);
+                None
+            }
+        };
         let mut res = GenericCloud {
             node_id,
             peers: HashMap::default(),
+            dpd_state: HashMap::default(),
+            nat_keepalive_state: HashMap::default(),
+            hole_punches: HashMap::default(),
             claims,
+            excluded_routes,
             learning,
             broadcast,
             pending_inits: HashMap::default(),
             reconnect_peers: SmallVec::new(),
             own_addresses: SmallVec::new(),
             peer_timeout_publish: config.peer_timeout as u16,
-            table: ClaimTable::new(config.switch_timeout as Duration, config.peer_timeout as Duration),
+            table,
+            multipath: if config.latency_routing { Some(MultiPathLookup::default()) } else { None },
+            policy,
+            acl,
+            groups,
+            arp_proxy,
+            fragment_buffer: FragmentBuffer::new(
+                std::time::Duration::from_millis(u64::from(config.fragment_timeout_ms)),
+                config.fragment_max_buffers,
+            ),
+            peer_changes: ChangeLog::new(),
             socket,
+            path_mtus: PathMtuTable::default(),
+            device_mtu,
+            packet_tos: None,
+            pacer: config
+                .send_pacing_kbps
+                .filter(|rate| *rate > 0)
+                .map(|rate| Pacer::new(rate, config.send_queue_max_bytes)),
+            turn: TurnRelay::new(&config.turn_servers),
+            buffers: MsgBufferPool::new(config.buffer_pool_size, SPACE_BEFORE),
             device,
             next_peers: now,
+            next_keepalive: now,
+            next_ping: now,
+            clock: TS::instant(),
             update_freq,
             stats_file,
+            audit_log,
+            webhook,
+            snmp,
+            capture,
+            tofu,
+            identity,
+            noise,
+            vxlan,
+            pending_vxlan: HashSet::default(),
+            api,
             statsd_server: config.statsd_server.clone(),
             next_housekeep: now,
+            next_init_retransmit: TS::instant(),
             next_stats_out: now + STATS_INTERVAL,
             next_beacon: now,
             next_own_address_reset: now + OWN_ADDRESS_RESET_INTERVAL,
+            address_watcher,
             port_forwarding,
-            traffic: TrafficStats::default(),
-            beacon_serializer: BeaconSerializer::new(beacon_key),
+            traffic,
+            beacon_store,
+            beacon_load,
+            bootstrap,
+            receivers: None,
+            local_bus: None,
+            telemetry,
+            notifier: SystemdNotifier::from_env(),
+            stop_flag: Arc::new(AtomicBool::new(false)),
             crypto,
-            config: config.clone(),
+            config,
             _dummy_p: PhantomData,
             _dummy_ts: PhantomData,
         };
@@ -171,6 +730,24 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         self.device.ifname()
     }
 
+    /// Sends the message to the peer, either directly or via the TURN server if the peer is relayed
+    #[inline]
+    fn send_raw(
+        socket: &mut S, path_mtus: &mut PathMtuTable, turn: &TurnRelay, addr: SocketAddr, msg: &mut MsgBuffer,
+        tos: Option<u8>,
+    ) -> Result<(), Error> {
+        // HOT PATH
+        let dst = match turn.server() {
+            Some(server) if turn.is_relayed(&addr) && turn.wrap(&addr, msg) => server,
+            _ => addr,
+        };
+        match path_mtus.send(socket, msg.message(), dst, tos) {
+            Ok(written) if written == msg.len() => Ok(()),
+            Ok(_) => Err(Error::Socket("Sent out truncated packet")),
+            Err(e) => Err(Error::SocketIo { message: "IOError when sending", source: e }),
+        }
+    }
+
     /// Sends the message to all peers
     ///
     /// # Errors
@@ -180,18 +757,56 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
     #[inline]
     fn broadcast_msg(&mut self, type_: u8, msg: &mut MsgBuffer) -> Result<(), Error> {
         debug!("Broadcasting message type {}, {:?} bytes to {} peers", type_, msg.len(), self.peers.len());
-        let mut msg_data = MsgBuffer::new(100);
+        let mut msg_data = self.buffers.acquire();
+        let mut fragmented: SmallVec<[(SocketAddr, usize); 4]> = SmallVec::new();
         for (addr, peer) in &mut self.peers {
+            if let Some(max_len) =
+                peer.max_data_len.filter(|max_len| type_ == MESSAGE_TYPE_DATA && msg.len() > *max_len)
+            {
+                // COLD PATH
+                fragmented.push((*addr, max_len));
+                continue
+            }
             msg_data.set_start(msg.get_start());
             msg_data.set_length(msg.len());
             msg_data.message_mut().clone_from_slice(msg.message());
             peer.crypto.send_message(type_, &mut msg_data)?;
+            if let (Encap::Vxlan, Some(vxlan)) = (peer.encap, &self.vxlan) {
+                vxlan.encode(&mut msg_data)
+            }
             self.traffic.count_out_traffic(*addr, msg_data.len());
-            match self.socket.send(msg_data.message(), *addr) {
-                Ok(written) if written == msg_data.len() => Ok(()),
-                Ok(_) => Err(Error::Socket("Sent out truncated packet")),
-                Err(e) => Err(Error::SocketIo("IOError when sending", e)),
-            }?
+            if let Some(ref mut pacer) = self.pacer {
+                if pacer.defer(*addr, msg_data.message(), peer.rtt, TS::instant()) {
+                    continue
+                }
+            }
+            if let Some(ref capture) = self.capture {
+                capture.network(Direction::Out, *addr, msg_data.message())
+            }
+            Self::send_raw(&mut self.socket, &mut self.path_mtus, &self.turn, *addr, &mut msg_data, self.packet_tos)?
+        }
+        for (addr, max_len) in fragmented {
+            self.send_fragments(addr, msg.message(), max_len)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the data message to the given peers
+    fn multicast_msg(&mut self, peers: &[SocketAddr], msg: &MsgBuffer) -> Result<(), Error> {
+        let mut msg_data = self.buffers.acquire();
+        for addr in peers {
+            let max_data_len = match self.peers.get(addr) {
+                Some(peer) => peer.max_data_len,
+                None => continue,
+            };
+            if let Some(max_len) = max_data_len.filter(|max_len| msg.len() > *max_len) {
+                self.send_fragments(*addr, msg.message(), max_len)?;
+                continue
+            }
+            msg_data.set_start(msg.get_start());
+            msg_data.set_length(msg.len());
+            msg_data.message_mut().clone_from_slice(msg.message());
+            self.send_msg(*addr, MESSAGE_TYPE_DATA, &mut msg_data)?;
         }
         Ok(())
     }
@@ -200,12 +815,74 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
     fn send_to(&mut self, addr: SocketAddr, msg: &mut MsgBuffer) -> Result<(), Error> {
         // HOT PATH
         debug!("Sending msg with {} bytes to {}", msg.len(), addr);
+        if let Some(ref vxlan) = self.vxlan {
+            // COLD PATH
+            let encap = match self.peers.get(&addr) {
+                Some(peer) => peer.encap,
+                None if self.pending_vxlan.contains(&addr) => Encap::Vxlan,
+                None => Encap::None,
+            };
+            if encap == Encap::Vxlan {
+                vxlan.encode(msg)
+            }
+        }
         self.traffic.count_out_traffic(addr, msg.len());
-        match self.socket.send(msg.message(), addr) {
-            Ok(written) if written == msg.len() => Ok(()),
-            Ok(_) => Err(Error::Socket("Sent out truncated packet")),
-            Err(e) => Err(Error::SocketIo("IOError when sending", e)),
+        if let Some(ref mut pacer) = self.pacer {
+            let rtt = self.peers.get(&addr).and_then(|peer| peer.rtt);
+            if pacer.defer(addr, msg.message(), rtt, TS::instant()) {
+                return Ok(())
+            }
+        }
+        if let Some(ref capture) = self.capture {
+            capture.network(Direction::Out, addr, msg.message())
+        }
+        if self.local_bus.is_some() {
+            if let Some(peer) = self.peers.get_mut(&addr) {
+                if let Some(ref local) = peer.local {
+                    if local.deliver(&self.buffers, msg.message()) {
+                        return Ok(())
+                    }
+                    // COLD PATH
+                    info!("Peer {} stopped receiving directly", addr_nice(addr));
+                    peer.local = None
+                }
+            }
+        }
+        Self::send_raw(&mut self.socket, &mut self.path_mtus, &self.turn, addr, msg, self.packet_tos)
+    }
+
+    /// Sends the messages deferred by the pacing that are due now
+    fn send_deferred(&mut self, buffer: &mut MsgBuffer) {
+        let ready = match self.pacer {
+            Some(ref mut pacer) if !pacer.is_empty() => {
+                let peers = &self.peers;
+                pacer.pop_ready(TS::instant(), |addr| peers.get(addr).and_then(|peer| peer.rtt))
+            }
+            _ => return,
+        };
+        for (addr, data) in ready {
+            buffer.clear();
+            buffer.clone_from(&data);
+            if let Some(ref capture) = self.capture {
+                capture.network(Direction::Out, addr, buffer.message())
+            }
+            if let Err(err) =
+                Self::send_raw(&mut self.socket, &mut self.path_mtus, &self.turn, addr, buffer, self.packet_tos)
+            {
+                error!("Failed to send deferred message: {}", err)
+            }
+        }
+    }
+
+    /// Sends a message to the TURN server
+    fn send_to_turn_server(&mut self, msg: &[u8]) -> Result<(), Error> {
+        if let Some(server) = self.turn.server() {
+            self.traffic.count_out_traffic(server, msg.len());
+            self.socket
+                .send(msg, server)
+                .map_err(|e| Error::SocketIo { message: "IOError when sending", source: e })?;
         }
+        Ok(())
     }
 
     #[inline]
@@ -220,6 +897,68 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         self.send_to(addr, msg)
     }
 
+    /// Pads the data to the configured size plus a random amount that fits into the path MTU
+    ///
+    /// Returns `false` if the data is too large to be padded to the configured size.
+    fn pad_data(&mut self, addr: SocketAddr, data: &mut MsgBuffer) -> bool {
+        let data_len = data.len();
+        let min_len = data_len + PADDING_TRAILER_LEN;
+        let max_len =
+            self.path_mtus.max_payload(&addr).unwrap_or(DEFAULT_MAX_PAYLOAD).saturating_sub(1 + EXTRA_LEN + TAG_LEN);
+        let mut len = match self.config.pad_to {
+            Some(pad_to) if pad_to as usize >= min_len => max(min(pad_to as usize, max_len), min_len),
+            Some(_) => {
+                // COLD PATH
+                self.traffic.count_unpadded();
+                return false
+            }
+            None => min_len,
+        };
+        if self.config.pad_amount > 0 {
+            len = max(len, min(len + thread_rng().gen_range(1..=self.config.pad_amount as usize), max_len));
+        }
+        add_padding(data, len);
+        self.traffic.count_padding(len - data_len);
+        true
+    }
+
+    fn send_data(&mut self, addr: SocketAddr, data: &mut MsgBuffer, trace: Option<TraceContext>) -> Result<(), Error> {
+        // HOT PATH
+        if let Some(max_len) = self.peers.get(&addr).and_then(|p| p.max_data_len) {
+            if data.len() > max_len {
+                // COLD PATH
+                return self.send_fragments(addr, data.message(), max_len)
+            }
+        }
+        match trace {
+            Some(trace) if self.peers.get(&addr).map(|p| p.features.has(Feature::Tracing)).unwrap_or(false) => {
+                // Only peers that announced tracing support understand the trace header
+                trace.write_to(data);
+                self.send_msg(addr, MESSAGE_TYPE_DATA_TRACED, data)
+            }
+            _ if (self.config.pad_to.is_some() || self.config.pad_amount > 0)
+                && self.peers.get(&addr).map(|p| p.features.has(Feature::Padding)).unwrap_or(false)
+                && self.pad_data(addr, data) =>
+            {
+                // Only peers that announced padding support are able to strip the padding
+                self.send_msg(addr, MESSAGE_TYPE_DATA_PADDED, data)
+            }
+            _ => self.send_msg(addr, MESSAGE_TYPE_DATA, data),
+        }
+    }
+
+    /// Sends the data split into fragments of at most `max_len` bytes
+    fn send_fragments(&mut self, addr: SocketAddr, data: &[u8], max_len: usize) -> Result<(), Error> {
+        debug!("Sending {} bytes to {} in fragments", data.len(), addr_nice(addr));
+        let id = self.fragment_buffer.next_id();
+        let mut msg = self.buffers.acquire();
+        for fragment in fragment::split(id, data, max_len) {
+            fragment.write_to(&mut msg);
+            self.send_msg(addr, MESSAGE_TYPE_DATA_FRAGMENT, &mut msg)?;
+        }
+        Ok(())
+    }
+
     pub fn reset_own_addresses(&mut self) -> io::Result<()> {
         self.own_addresses.clear();
         let socket_addr = self.socket.address().map(mapped_addr)?;
@@ -250,6 +989,10 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
     /// This method adds a peer to the list of nodes to reconnect to. A periodic task will try to
     /// connect to the peer if it is not already connected.
     pub fn add_reconnect_peer(&mut self, add: String) {
+        if self.reconnect_peers.len() >= self.config.max_reconnect_peers {
+            warn!("Not reconnecting to {}, maximum number of reconnect peers reached", add);
+            return;
+        }
         let now = TS::now();
         let resolved = match resolve(&add as &str) {
             Ok(addrs) => addrs,
@@ -264,10 +1007,28 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             timeout: 1,
             resolved,
             next: now,
+            last_attempt: None,
             final_timeout: None,
         })
     }
 
+    /// Removes all reconnect entries with the given address and returns whether there were any
+    ///
+    /// Peers that have been connected via those entries stay connected but will not be reconnected.
+    pub fn remove_reconnect_peer(&mut self, address: &str) -> bool {
+        !self.take_reconnect_peers(address).is_empty()
+    }
+
+    fn take_reconnect_peers(&mut self, address: &str) -> SmallVec<[ReconnectEntry; 3]> {
+        let (removed, kept) = self.reconnect_peers.drain(..).partition(|entry| entry.has_address(address));
+        self.reconnect_peers = kept;
+        removed
+    }
+
+    pub fn reconnect_peers_list(&self) -> Vec<ReconnectEntry> {
+        self.reconnect_peers.to_vec()
+    }
+
     /// Connects to a node given by its address
     ///
     /// This method connects to node by sending a `Message::Init` to it. If `addr` is a name that
@@ -277,7 +1038,12 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
     /// # Errors
     /// This method returns `Error::NameError` if the address is a name that fails to resolve.
     pub fn connect<Addr: ToSocketAddrs + fmt::Debug + Clone>(&mut self, addr: Addr) -> Result<(), Error> {
-        let addrs = resolve(&addr)?.into_iter().map(mapped_addr).collect::<SmallVec<[SocketAddr; 3]>>();
+        let family = self.config.address_family;
+        let addrs = resolve(&addr)?
+            .into_iter()
+            .map(mapped_addr)
+            .filter(|addr| family.matches(addr))
+            .collect::<SmallVec<[SocketAddr; 3]>>();
         for addr in &addrs {
             if self.own_addresses.contains(addr)
                 || self.peers.contains_key(addr)
@@ -306,10 +1072,13 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         for peer in self.peers.values() {
             peers.push(PeerInfo { node_id: Some(peer.node_id), addrs: peer.addrs.clone() })
         }
-        if peers.len() > 20 {
+        let fanout = self.config.gossip_fanout;
+        if peers.len() > fanout {
             let mut rng = rand::thread_rng();
-            peers.partial_shuffle(&mut rng, 20);
-            peers.truncate(20);
+            let len = peers.len();
+            peers.partial_shuffle(&mut rng, fanout);
+            // The randomly chosen peers are moved to the end
+            peers.drain(..len - fanout);
         }
         NodeInfo {
             node_id: self.node_id,
@@ -317,28 +1086,145 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             claims: self.claims.clone(),
             peer_timeout: Some(self.peer_timeout_publish),
             addrs: self.own_addresses.clone(),
+            tags: self.config.local_tags.clone(),
+            mtu: self.config.mtu,
+            // Identity proofs are bound to a handshake and only added to its payload
+            identity: None,
+            protocol_version: PROTOCOL_VERSION,
+            extensions: self.own_features(),
+        }
+    }
+
+    /// The protocol extensions that this node supports
+    fn own_features(&self) -> Features {
+        let mut features = Features::default();
+        features.set(Feature::Tracing, self.telemetry.enabled());
+        features.set(Feature::Dpd, true);
+        features.set(Feature::MtuProbe, true);
+        features.set(Feature::HolePunch, self.config.hole_punch);
+        features.set(Feature::Padding, true);
+        features.set(Feature::Ping, true);
+        features.set(Feature::Fragments, true);
+        features.set(Feature::PeerUpdates, true);
+        features
+    }
+
+    /// Sends the peer list to all peers, or only the changes to peers that got a full peer list recently
+    fn send_peer_lists(&mut self, now: Time) -> Result<(), Error> {
+        let version = self.peer_changes.version();
+        let full_interval = Time::from(self.config.gossip_full_interval_secs);
+        let mut full: SmallVec<[SocketAddr; 16]> = SmallVec::new();
+        let mut unchanged: SmallVec<[SocketAddr; 16]> = SmallVec::new();
+        let mut updates = vec![];
+        for (addr, peer) in &mut self.peers {
+            match self.peer_changes.since(peer.gossip_version) {
+                Some(update) if peer.features.has(Feature::PeerUpdates) && peer.next_full_gossip > now => {
+                    if update.added.is_empty() && update.removed.is_empty() {
+                        unchanged.push(*addr)
+                    } else {
+                        updates.push((*addr, update))
+                    }
+                }
+                _ => {
+                    full.push(*addr);
+                    peer.next_full_gossip = now + full_interval;
+                }
+            }
+            peer.gossip_version = version;
+        }
+        let mut buffer = self.buffers.acquire();
+        if !full.is_empty() {
+            let info = self.create_node_info();
+            for addr in full {
+                buffer.clear();
+                info.encode(&mut buffer);
+                self.traffic.gossip_messages_sent_total += 1;
+                self.send_msg(addr, MESSAGE_TYPE_NODE_INFO, &mut buffer)?;
+            }
+        }
+        for (addr, update) in updates {
+            debug!("Sending {} peer changes to {}", update.added.len() + update.removed.len(), addr_nice(addr));
+            buffer.clear();
+            update.encode(&mut buffer);
+            self.traffic.gossip_updates_sent_total += 1;
+            self.send_msg(addr, MESSAGE_TYPE_PEER_UPDATE, &mut buffer)?;
+        }
+        // The peer lists also keep the connections alive
+        for addr in unchanged {
+            buffer.clear();
+            self.send_msg(addr, MESSAGE_TYPE_KEEPALIVE, &mut buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Connects to the peers that the peer added and forgets the removed ones as relays for hole punching
+    ///
+    /// The node stays connected to removed peers, the peer might only have lost its own connection to them.
+    fn apply_peer_update(&mut self, addr: SocketAddr, update: PeerUpdate) -> Result<(), Error> {
+        let peer = match self.peers.get_mut(&addr) {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        debug!("Received peer update from {}: {:?}", addr_nice(addr), update);
+        peer.advertised_peers.retain(|node_id| !update.removed.iter().any(|p| p.node_id == Some(*node_id)));
+        for node_id in update.added.iter().filter_map(|p| p.node_id) {
+            if !peer.advertised_peers.contains(&node_id) {
+                peer.advertised_peers.push(node_id)
+            }
+        }
+        self.connect_to_peers(&update.added)
+    }
+
+    fn peer_limit_reached(&self) -> bool {
+        match self.config.max_peers {
+            Some(max) => self.peers.len() + self.pending_inits.len() >= max,
+            None => false,
+        }
+    }
+
+    /// Creates the crypto instance for a new connection, plaintext peers leave their messages unencrypted
+    fn peer_crypto(&self, addr: SocketAddr) -> PeerCrypto<NodeInfo> {
+        let payload = self.create_node_info();
+        let mut crypto = if self.config.plaintext_peers.iter().any(|peer| mapped_addr(*peer) == addr) {
+            self.crypto.plaintext_peer_instance(payload, TS::instant)
+        } else {
+            self.crypto.peer_instance(payload, TS::instant)
+        };
+        if let (Some(identity), Some(session_key)) = (&self.identity, crypto.session_key()) {
+            let proof = identity.prove(&self.node_id, session_key, TS::now());
+            if let Some(payload) = crypto.init_payload_mut() {
+                payload.identity = Some(proof)
+            }
         }
+        crypto
     }
 
     fn connect_sock(&mut self, addr: SocketAddr) -> Result<(), Error> {
         let addr = mapped_addr(addr);
+        if !self.config.address_family.matches(&addr) {
+            debug!("Not connecting to {}, address family is not used", addr_nice(addr));
+            return Ok(());
+        }
         if self.peers.contains_key(&addr)
             || self.own_addresses.contains(&addr)
             || self.pending_inits.contains_key(&addr)
         {
             return Ok(());
         }
+        if self.peer_limit_reached() {
+            debug!("Not connecting to {}, maximum number of peers reached", addr_nice(addr));
+            return Ok(());
+        }
         debug!("Connecting to {:?}", addr);
-        let payload = self.create_node_info();
-        let mut peer_crypto = self.crypto.peer_instance(payload);
-        let mut msg = MsgBuffer::new(SPACE_BEFORE);
+        let mut peer_crypto = self.peer_crypto(addr);
+        let mut msg = self.buffers.acquire();
         peer_crypto.initialize(&mut msg)?;
         self.pending_inits.insert(addr, peer_crypto);
         self.send_to(addr, &mut msg)
     }
 
     fn crypto_housekeep(&mut self) -> Result<(), Error> {
-        let mut msg = MsgBuffer::new(SPACE_BEFORE);
+        let mut msg = self.buffers.acquire();
         let mut del: SmallVec<[SocketAddr; 4]> = smallvec![];
         for addr in self.pending_inits.keys().copied().collect::<SmallVec<[SocketAddr; 4]>>() {
             msg.clear();
@@ -351,16 +1237,31 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         }
         for addr in self.peers.keys().copied().collect::<SmallVec<[SocketAddr; 16]>>() {
             msg.clear();
-            match self.peers.get_mut(&addr).unwrap().crypto.every_second(&mut msg) {
+            let crypto = &mut self.peers.get_mut(&addr).unwrap().crypto;
+            let result = crypto.every_second(&mut msg);
+            if crypto.take_key_rotated() {
+                self.api.publish(|| ApiEvent::KeyRotation { peer: addr_nice(addr).to_string() });
+            }
+            match result {
                 Err(_) => del.push(addr),
                 Ok(MessageResult::None) => (),
                 Ok(MessageResult::Reply) => self.send_to(addr, &mut msg)?,
                 Ok(_) => unreachable!(),
             }
         }
+        let now = TS::now();
         for addr in del {
-            self.pending_inits.remove(&addr);
-            if self.peers.remove(&addr).is_some() {
+            if self.pending_inits.remove(&addr).is_some() && !self.peers.contains_key(&addr) {
+                if self.turn.is_relayed(&addr) {
+                    self.turn.unbind(&addr);
+                } else if let Some(request) = self.turn.bind(addr, now) {
+                    // The peer could not be reached directly, try again via the TURN server
+                    self.send_to_turn_server(&request)?;
+                    self.connect_sock(addr)?;
+                }
+            }
+            if self.peers.contains_key(&addr) {
+                self.remove_peer(addr, DisconnectReason::CryptoFailure);
                 self.connect_sock(addr)?;
             }
         }
@@ -403,26 +1304,27 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             if entry.next > now {
                 continue;
             }
-            // Exponential back-off: every 10 tries, the interval doubles
-            entry.tries += 1;
-            if entry.tries > 10 {
-                entry.tries = 0;
-                entry.timeout *= 2;
+            if let Some(last_attempt) = entry.last_attempt {
+                debug!("Tried to reconnect to {:?}, previous attempt {}s ago", entry.resolved, now - last_attempt);
             }
-            // Maximum interval is one hour
-            if entry.timeout > MAX_RECONNECT_INTERVAL {
-                entry.timeout = MAX_RECONNECT_INTERVAL;
-            }
-            // Schedule next connection attempt
-            entry.next = now + Time::from(entry.timeout);
+            entry.last_attempt = Some(now);
+            entry.schedule_next(now, self.config.reconnect_jitter);
         }
         self.reconnect_peers.retain(|e| e.final_timeout.unwrap_or(now) >= now);
         Ok(())
     }
 
     fn housekeep(&mut self) -> Result<(), Error> {
+        self.notifier.housekeep(self.peers.len());
+        let mut span = self.telemetry.span("housekeep", None);
+        let result = self.run_housekeeping();
+        span.set_result(&result);
+        result
+    }
+
+    fn run_housekeeping(&mut self) -> Result<(), Error> {
         let now = TS::now();
-        let mut buffer = MsgBuffer::new(SPACE_BEFORE);
+        let mut buffer = self.buffers.acquire();
         let mut del: SmallVec<[SocketAddr; 3]> = SmallVec::new();
         for (&addr, ref data) in &self.peers {
             if data.timeout < now {
@@ -431,42 +1333,110 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         }
         for addr in del {
             info!("Forgot peer {} due to timeout", addr_nice(addr));
-            self.peers.remove(&addr);
-            self.table.remove_claims(addr);
+            self.remove_peer(addr, DisconnectReason::Timeout);
             self.connect_sock(addr)?; // Try to reconnect
         }
+        self.dead_peer_detection()?;
+        self.nat_keepalive()?;
+        self.send_pings()?;
+        let peers = &self.peers;
+        self.path_mtus.retain(&mut self.socket, |addr| peers.contains_key(addr));
+        self.hole_punches.retain(|_, timeout| *timeout > now);
+        if let Some(ref mut groups) = self.groups {
+            groups.housekeep();
+            groups.retain(|addr| peers.contains_key(addr));
+        }
+        if let Some(ref mut arp_proxy) = self.arp_proxy {
+            arp_proxy.housekeep();
+            arp_proxy.retain(|addr| peers.contains_key(addr));
+        }
+        self.fragment_buffer.housekeep(TS::instant());
+        self.fragment_buffer.retain(|addr| peers.contains_key(addr));
+        if let Some(ref mut pacer) = self.pacer {
+            pacer.housekeep(TS::instant());
+        }
+        let pending_inits = &self.pending_inits;
+        self.turn.retain(|addr| peers.contains_key(addr) || pending_inits.contains_key(addr));
+        self.pending_vxlan.retain(|addr| pending_inits.contains_key(addr));
+        for msg in self.turn.housekeep(now) {
+            self.send_to_turn_server(&msg)?;
+        }
+        if let Some(ref bus) = self.local_bus {
+            for (addr, peer) in self.peers.iter_mut().filter(|(_, peer)| peer.local.is_none()) {
+                peer.local = bus.lookup(&peer.node_id, *addr, &self.own_addresses);
+                if peer.local.is_some() {
+                    info!("Passing messages to peer {} directly", addr_nice(*addr))
+                }
+            }
+        }
         self.table.housekeep();
+        if self.table.claim_len() > self.config.max_table_entries {
+            for addr in self.peers.keys() {
+                self.table.aggregate_claims(*addr)
+            }
+            debug!("Aggregated claims, {} claims left", self.table.claim_len());
+        }
         self.crypto_housekeep()?;
         // Periodically extend the port-forwarding
         if let Some(ref mut pfw) = self.port_forwarding {
             pfw.check_extend();
         }
         let now = TS::now();
-        // Periodically send peer list to peers
+        let min_peer_timeout = self.peers.iter().map(|p| p.1.peer_timeout).min().unwrap_or(DEFAULT_PEER_TIMEOUT);
+        let keepalive_interval = Time::from(min(self.update_freq as u16, max(min_peer_timeout / 2 - 60, 1)));
+        // Peers need to learn changed addresses right away
+        if self.address_watcher.as_mut().map(RtnetlinkWatcher::changed).unwrap_or(false) {
+            info!("Network addresses changed");
+            self.reset_own_addresses()
+                .map_err(|err| Error::SocketIo { message: "Failed to get own addresses", source: err })?;
+            self.next_own_address_reset = now + OWN_ADDRESS_RESET_INTERVAL;
+            self.next_peers = now;
+            for peer in self.peers.values_mut() {
+                peer.next_full_gossip = now;
+            }
+        }
+        // Periodically send peer list to peers, this also keeps the connections alive
         if self.next_peers <= now {
             debug!("Send peer list to all peers");
-            let info = self.create_node_info();
-            info.encode(&mut buffer);
-            self.broadcast_msg(MESSAGE_TYPE_NODE_INFO, &mut buffer)?;
+            self.send_peer_lists(now)?;
             // Reschedule for next update
-            let min_peer_timeout = self.peers.iter().map(|p| p.1.peer_timeout).min().unwrap_or(DEFAULT_PEER_TIMEOUT);
-            let interval = min(self.update_freq as u16, max(min_peer_timeout / 2 - 60, 1));
-            self.next_peers = now + Time::from(interval);
+            self.next_peers = now + self.config.gossip_interval.map(Time::from).unwrap_or(keepalive_interval);
+            self.next_keepalive = now + keepalive_interval;
+        } else if self.next_keepalive <= now {
+            debug!("Send keepalive to all peers");
+            buffer.clear();
+            self.broadcast_msg(MESSAGE_TYPE_KEEPALIVE, &mut buffer)?;
+            self.next_keepalive = now + keepalive_interval;
         }
         self.reconnect_to_peers()?;
+        self.update_snmp();
         if self.next_stats_out < now {
+            for peer in self.peers.values_mut() {
+                peer.loss.period();
+            }
             // Write out the statistics
-            self.write_out_stats().map_err(|err| Error::FileIo("Failed to write stats file", err))?;
+            self.write_out_stats()
+                .map_err(|err| Error::FileIo { message: "Failed to write stats file", source: err })?;
             self.send_stats_to_statsd()?;
+            self.send_stats_to_influx();
+            self.api.publish(|| ApiEvent::StatsSnapshot(Box::new(self.stats_snapshot())));
             self.next_stats_out = now + STATS_INTERVAL;
             self.traffic.period(Some(5));
         }
-        if let Some(peers) = self.beacon_serializer.get_cmd_results() {
+        if let Some(peers) = self.beacon_load.as_mut().and_then(|store| store.poll()) {
             debug!("Loaded beacon with peers: {:?}", peers);
             for peer in peers {
                 self.connect_sock(peer)?;
             }
         }
+        while let Some(targets) = self.bootstrap.as_ref().and_then(|bootstrap| bootstrap.try_recv().ok()) {
+            for target in targets {
+                if !self.reconnect_peers.iter().any(|entry| entry.has_address(&target)) {
+                    info!("Adding peer {} from DNS SRV records", target);
+                    self.add_reconnect_peer(target);
+                }
+            }
+        }
         if self.next_beacon < now {
             self.store_beacon()?;
             self.load_beacon()?;
@@ -474,47 +1444,153 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         }
         // Periodically reset own peers
         if self.next_own_address_reset <= now {
-            self.reset_own_addresses().map_err(|err| Error::SocketIo("Failed to get own addresses", err))?;
+            self.reset_own_addresses()
+                .map_err(|err| Error::SocketIo { message: "Failed to get own addresses", source: err })?;
             self.next_own_address_reset = now + OWN_ADDRESS_RESET_INTERVAL;
         }
         Ok(())
     }
 
-    /// Stores the beacon
-    fn store_beacon(&mut self) -> Result<(), Error> {
-        if let Some(ref path) = self.config.beacon_store {
-            let peers: SmallVec<[SocketAddr; 3]> =
-                self.own_addresses.choose_multiple(&mut thread_rng(), 3).cloned().collect();
-            if let Some(path) = path.strip_prefix('|') {
-                self.beacon_serializer
-                    .write_to_cmd(&peers, path)
-                    .map_err(|e| Error::BeaconIo("Failed to call beacon command", e))?;
+    fn dead_peer_detection(&mut self) -> Result<(), Error> {
+        let interval = Time::from(self.config.dpd_probe_interval);
+        if interval == 0 {
+            return Ok(())
+        }
+        let now = TS::now();
+        let peers = &self.peers;
+        self.dpd_state.retain(|addr, _| peers.contains_key(addr));
+        let mut probe: SmallVec<[SocketAddr; 4]> = smallvec![];
+        let mut dead: SmallVec<[SocketAddr; 4]> = smallvec![];
+        for (&addr, peer) in &self.peers {
+            if !peer.features.has(Feature::Dpd) {
+                // Older peers do not answer probes, they are only removed by timeout
+                continue
+            }
+            let packets = self.traffic.peer_in_packets(&addr);
+            let state =
+                self.dpd_state.entry(addr).or_insert(DpdState { packets, probes: 0, next_probe: now + interval });
+            if state.packets != packets {
+                // Any received packet proves that the peer is alive
+                *state = DpdState { packets, probes: 0, next_probe: now + interval };
+                continue
+            }
+            if state.next_probe > now {
+                continue
+            }
+            if state.probes >= self.config.dpd_retries {
+                dead.push(addr);
             } else {
-                self.beacon_serializer
-                    .write_to_file(&peers, &path)
-                    .map_err(|e| Error::BeaconIo("Failed to write beacon to file", e))?;
+                state.probes += 1;
+                state.next_probe = now + DPD_RETRY_INTERVAL;
+                probe.push(addr);
+            }
+        }
+        let mut msg = self.buffers.acquire();
+        for addr in probe {
+            debug!("Probing silent peer {}", addr_nice(addr));
+            msg.clear();
+            self.send_msg(addr, MESSAGE_TYPE_PROBE, &mut msg)?;
+        }
+        for addr in dead {
+            info!("Removing dead peer {}, {} probes were not answered", addr_nice(addr), self.config.dpd_retries);
+            self.dpd_state.remove(&addr);
+            self.traffic.dpd_evictions_total += 1;
+            self.remove_peer(addr, DisconnectReason::DeadPeer);
+        }
+        Ok(())
+    }
+
+    /// Sends keepalives to idle hole punched peers, so that the mappings in the NATs do not expire
+    fn nat_keepalive(&mut self) -> Result<(), Error> {
+        let interval = Time::from(self.config.nat_keepalive);
+        if interval == 0 {
+            return Ok(())
+        }
+        let now = TS::now();
+        let peers = &self.peers;
+        self.nat_keepalive_state
+            .retain(|addr, _| peers.get(addr).map(|p| p.path == PeerPath::HolePunched).unwrap_or(false));
+        let mut idle: SmallVec<[SocketAddr; 4]> = smallvec![];
+        for (&addr, peer) in &self.peers {
+            if peer.path != PeerPath::HolePunched {
+                continue
+            }
+            let packets = self.traffic.peer_out_packets(&addr);
+            let state = self.nat_keepalive_state.entry(addr).or_insert(NatKeepaliveState { packets, last_sent: now });
+            if state.packets != packets {
+                *state = NatKeepaliveState { packets, last_sent: now };
+            } else if state.last_sent + interval <= now {
+                idle.push(addr);
             }
         }
+        let mut msg = self.buffers.acquire();
+        for addr in idle {
+            debug!("Sending NAT keepalive to {}", addr_nice(addr));
+            msg.clear();
+            self.send_msg(addr, MESSAGE_TYPE_KEEPALIVE, &mut msg)?;
+            self.traffic.keepalive_packets_sent += 1;
+            let packets = self.traffic.peer_out_packets(&addr);
+            self.nat_keepalive_state.insert(addr, NatKeepaliveState { packets, last_sent: now });
+        }
+        Ok(())
+    }
+
+    /// Sends pings to all peers to measure the round trip times for latency based routing
+    fn send_pings(&mut self) -> Result<(), Error> {
+        let now = TS::now();
+        if self.multipath.is_none() || self.next_ping > now {
+            return Ok(())
+        }
+        self.next_ping = now + PING_INTERVAL;
+        let peers: SmallVec<[SocketAddr; 4]> =
+            self.peers.iter().filter(|(_, peer)| peer.features.has(Feature::Ping)).map(|(addr, _)| *addr).collect();
+        let timestamp = (TS::instant() - self.clock).as_millis() as u64;
+        let mut msg = self.buffers.acquire();
+        for addr in peers {
+            msg.clear();
+            msg.clone_from(&timestamp.to_be_bytes());
+            self.send_msg(addr, MESSAGE_TYPE_PING, &mut msg)?;
+        }
+        Ok(())
+    }
+
+    fn handle_pong(&mut self, src: SocketAddr, data: &[u8]) -> Result<(), Error> {
+        if data.len() != 8 {
+            self.traffic.count_invalid_protocol(data.len());
+            return Err(Error::Message("Invalid pong"))
+        }
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(data);
+        let rtt = ((TS::instant() - self.clock).as_millis() as u64).saturating_sub(u64::from_be_bytes(timestamp));
+        self.update_peer_info(src, None)?;
+        if let Some(peer) = self.peers.get_mut(&src) {
+            // Smoothed like the round trip time of TCP (RFC 6298)
+            peer.rtt = Some(match peer.rtt {
+                Some(srtt) => (7 * srtt + rtt) / 8,
+                None => rtt,
+            });
+        }
+        Ok(())
+    }
+
+    /// Stores the beacon
+    fn store_beacon(&mut self) -> Result<(), Error> {
+        if let Some(ref mut store) = self.beacon_store {
+            let peers: SmallVec<[SocketAddr; 3]> =
+                self.own_addresses.choose_multiple(&mut thread_rng(), 3).cloned().collect();
+            store.write(&peers)?;
+        }
         Ok(())
     }
 
     /// Loads the beacon
     fn load_beacon(&mut self) -> Result<(), Error> {
-        let peers;
-        if let Some(ref path) = self.config.beacon_load {
-            if let Some(path) = path.strip_prefix('|') {
-                self.beacon_serializer
-                    .read_from_cmd(path, Some(50))
-                    .map_err(|e| Error::BeaconIo("Failed to call beacon command", e))?;
-                return Ok(());
-            } else {
-                peers = self
-                    .beacon_serializer
-                    .read_from_file(&path, Some(50))
-                    .map_err(|e| Error::BeaconIo("Failed to read beacon from file", e))?;
-            }
-        } else {
-            return Ok(());
+        let peers = match self.beacon_load {
+            Some(ref mut store) => store.read(None)?,
+            None => return Ok(()),
+        };
+        if peers.is_empty() {
+            return Ok(())
         }
         debug!("Loaded beacon with peers: {:?}", peers);
         for peer in peers {
@@ -523,28 +1599,137 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         Ok(())
     }
 
+    /// Returns a structured snapshot of the current statistics
+    pub fn stats_snapshot(&self) -> StatsSnapshot {
+        let now = TS::now();
+        let mut peers: Vec<_> = self
+            .peers
+            .iter()
+            .map(|(addr, data)| PeerSnapshot {
+                addr: addr_nice(*addr).to_string(),
+                ttl_secs: data.timeout - now,
+                crypto: data.crypto.algorithm_name().to_string(),
+                encrypted: data.crypto.is_encrypted(),
+                path_mtu: self.path_mtus.get(addr),
+                mtu: data.mtu,
+                path: data.path,
+                tags: data.tags.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                lost_packets: data.loss.last_period_lost,
+                lost_packets_total: data.loss.lost_packets_total,
+                loss_percent: data.loss.loss_percent,
+            })
+            .collect();
+        peers.sort_unstable_by(|a, b| a.addr.cmp(&b.addr));
+        let cpu_features = self.crypto.cpu_features();
+        StatsSnapshot {
+            schema_version: STATS_SCHEMA_VERSION,
+            crypto: CryptoSnapshot {
+                backend: cpu_features.aes_backend().to_string(),
+                cpu_features: cpu_features.names().into_iter().map(String::from).collect(),
+            },
+            mtu: MtuSnapshot { device: self.device_mtu, effective: self.config.mtu.unwrap_or(self.device_mtu) },
+            peers,
+            excluded_routes: self.excluded_routes.iter().map(Range::to_string).collect(),
+            table: self.table.snapshot(),
+            traffic: self.traffic.snapshot(),
+            buffer_pool_exhausted_total: self.buffers.exhausted(),
+        }
+    }
+
+    /// Returns a copy of the connected peers, sorted by address
+    pub fn peers_snapshot(&self) -> Vec<PeerInfoSnapshot> {
+        let now = TS::now();
+        let mut peers: Vec<_> = self
+            .peers
+            .iter()
+            .map(|(addr, data)| {
+                let (tx_bytes, rx_bytes) = self.traffic.peer_bytes(addr);
+                PeerInfoSnapshot {
+                    addr: addr_nice(*addr).to_string(),
+                    node_id: bytes_to_hex(&data.node_id),
+                    ttl_secs: data.timeout - now,
+                    alt_addrs: data.addrs.iter().filter(|a| *a != addr).map(|a| addr_nice(*a).to_string()).collect(),
+                    rtt_ms: data.rtt,
+                    tx_bytes,
+                    rx_bytes,
+                }
+            })
+            .collect();
+        peers.sort_unstable_by(|a, b| a.addr.cmp(&b.addr));
+        peers
+    }
+
+    /// Returns a copy of the claims in the routing table
+    pub fn table_snapshot(&self) -> Vec<TableClaimSnapshot> {
+        self.table.dump()
+    }
+
     /// Writes out the statistics to a file
     fn write_out_stats(&mut self) -> Result<(), io::Error> {
+        let snapshot = match self.config.stats_format {
+            StatsFormat::Json if self.stats_file.is_some() => Some(self.stats_snapshot()),
+            _ => None,
+        };
         if let Some(ref mut f) = self.stats_file {
             debug!("Writing out stats");
             f.seek(SeekFrom::Start(0))?;
             f.set_len(0)?;
+            if let Some(snapshot) = snapshot {
+                serde_json::to_writer_pretty(&mut *f, &snapshot)?;
+                writeln!(f)?;
+                return Ok(());
+            }
+            if self.config.stats_format == StatsFormat::Prometheus {
+                return self.traffic.write_prometheus(f);
+            }
+            let cpu_features = self.crypto.cpu_features();
+            writeln!(f, "crypto:")?;
+            writeln!(f, "  backend: {}", cpu_features.aes_backend())?;
+            writeln!(f, "  cpu_features: {:?}", cpu_features.names())?;
+            writeln!(f)?;
+            writeln!(f, "mtu:")?;
+            writeln!(f, "  device: {}", self.device_mtu)?;
+            writeln!(f, "  effective: {}", self.config.mtu.unwrap_or(self.device_mtu))?;
+            writeln!(f)?;
             writeln!(f, "peers:")?;
             let now = TS::now();
             for (addr, data) in &self.peers {
-                writeln!(
+                write!(
                     f,
-                    "  - \"{}\": {{ ttl_secs: {}, crypto: {} }}",
+                    "  - \"{}\": {{ ttl_secs: {}, crypto: {}, encrypted: {}, path: {}",
                     addr_nice(*addr),
                     data.timeout - now,
-                    data.crypto.algorithm_name()
+                    data.crypto.algorithm_name(),
+                    data.crypto.is_encrypted(),
+                    data.path
                 )?;
+                if let Some(mtu) = self.path_mtus.get(addr) {
+                    write!(f, ", path_mtu: {}", mtu)?;
+                }
+                if let Some(mtu) = data.mtu {
+                    write!(f, ", mtu: {}", mtu)?;
+                }
+                write!(
+                    f,
+                    ", lost_packets: {}, loss_percent: {:.2}, lost_packets_total: {}",
+                    data.loss.last_period_lost, data.loss.loss_percent, data.loss.lost_packets_total
+                )?;
+                if !data.tags.is_empty() {
+                    let tags: BTreeMap<_, _> = data.tags.iter().collect();
+                    write!(f, ", tags: {:?}", tags)?;
+                }
+                writeln!(f, " }}")?;
             }
             writeln!(f)?;
+            if !self.excluded_routes.is_empty() {
+                writeln!(f, "excluded_routes: {:?}", self.excluded_routes)?;
+                writeln!(f)?;
+            }
             self.table.write_out(f)?;
             writeln!(f)?;
             self.traffic.write_out(f)?;
             writeln!(f)?;
+            writeln!(f, "buffer_pool_exhausted_total: {}", self.buffers.exhausted())?;
         }
         Ok(())
     }
@@ -559,8 +1744,14 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             let msg = StatsdMsg::new()
                 .with_ns(prefix, |msg| {
                     msg.add("peer_count", self.peers.len(), "g");
+                    if let Some(max_peers) = self.config.max_peers {
+                        msg.add("max_peers", max_peers, "g");
+                    }
                     msg.add("table_cache_entries", self.table.cache_len(), "g");
                     msg.add("table_claims", self.table.claim_len(), "g");
+                    msg.add("buffer_pool_exhausted_total", self.buffers.exhausted(), "g");
+                    msg.add("padded_bytes", self.traffic.padded_bytes, "g");
+                    msg.add("unpadded_packets", self.traffic.unpadded_packets, "g");
                     msg.with_ns("traffic", |msg| {
                         msg.with_ns("protocol", |msg| {
                             msg.with_ns("inbound", |msg| {
@@ -599,7 +1790,7 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
                 match self.socket.send(msg_data, *addr) {
                     Ok(written) if written == msg_data.len() => Ok(()),
                     Ok(_) => Err(Error::Socket("Sent out truncated packet")),
-                    Err(e) => Err(Error::SocketIo("IOError when sending", e)),
+                    Err(e) => Err(Error::SocketIo { message: "IOError when sending", source: e }),
                 }?
             } else {
                 error!("Failed to resolve statsd server {}", endpoint);
@@ -608,17 +1799,143 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         Ok(())
     }
 
+    /// Sends the statistics to an InfluxDB UDP listener
+    fn send_stats_to_influx(&mut self) {
+        if let Some(addr) = self.config.influx_addr {
+            let peer_traffic = self.traffic.total_peer_traffic();
+            let payload_traffic = self.traffic.total_payload_traffic();
+            let dropped = &self.traffic.dropped;
+            let port = self.socket.address().map(|addr| addr.port()).unwrap_or(0);
+            let line = InfluxLine::new(&self.config.influx_measurement)
+                .tag("host", metrics::hostname())
+                .tag("port", port)
+                .field("bytes_in", peer_traffic.in_bytes_sum())
+                .field("bytes_out", peer_traffic.out_bytes_sum())
+                .field("packets_in", peer_traffic.in_packets_sum() as u64)
+                .field("packets_out", peer_traffic.out_packets_sum() as u64)
+                .field("payload_bytes_in", payload_traffic.in_bytes_sum())
+                .field("payload_bytes_out", payload_traffic.out_bytes_sum())
+                .field("dropped_packets", dropped.out_packets_sum() as u64)
+                .field("invalid_protocol", dropped.in_packets_sum() as u64)
+                .field("peers", self.peers.len() as u64)
+                .field("table_claims", self.table.claim_len() as u64)
+                .field("influx_write_errors_total", self.traffic.influx_write_errors_total)
+                .field("dpd_evictions_total", self.traffic.dpd_evictions_total)
+                .field("keepalive_packets_sent", self.traffic.keepalive_packets_sent)
+                .field("gossip_messages_sent_total", self.traffic.gossip_messages_sent_total)
+                .field("gossip_updates_sent_total", self.traffic.gossip_updates_sent_total)
+                .build(TS::now() * 1_000_000_000);
+            match self.socket.send(line.as_bytes(), addr) {
+                Ok(written) if written == line.len() => (),
+                Ok(_) => {
+                    self.traffic.influx_write_errors_total += 1;
+                    warn!("Sent out truncated stats to InfluxDB {}", addr)
+                }
+                Err(err) => {
+                    self.traffic.influx_write_errors_total += 1;
+                    warn!("Failed to send stats to InfluxDB {}: {}", addr, err)
+                }
+            }
+        }
+    }
+
+    /// Updates the values that the SNMP agent serves
+    fn update_snmp(&self) {
+        if let Some(ref snmp) = self.snmp {
+            let peer_traffic = self.traffic.total_peer_traffic();
+            let active_tunnels = self
+                .traffic
+                .get_peer_traffic()
+                .filter(|(addr, entry)| entry.in_packets > 0 && self.peers.contains_key(addr))
+                .count();
+            snmp.update(SnmpStats {
+                peers: self.peers.len() as u32,
+                bytes_in: peer_traffic.in_bytes_sum(),
+                bytes_out: peer_traffic.out_bytes_sum(),
+                packets_in: peer_traffic.in_packets_sum() as u64,
+                packets_out: peer_traffic.out_packets_sum() as u64,
+                lost_packets: self.peers.values().map(|peer| peer.loss.lost_packets_total).sum(),
+                active_tunnels: active_tunnels as u32,
+            })
+        }
+    }
+
     pub fn handle_interface_data(&mut self, data: &mut MsgBuffer) -> Result<(), Error> {
+        // HOT PATH
+        let mut span = self.telemetry.span("handle_interface_data", None);
+        span.set_message_type(MESSAGE_TYPE_DATA);
+        span.set_size(data.len());
+        // A static DSCP value is already set on the socket and takes precedence
+        if self.config.dscp_inherit && self.config.dscp.is_none() {
+            self.packet_tos = P::dscp_tos(data.message());
+        }
+        let result = self.forward_interface_data(data, &mut span);
+        self.packet_tos = None;
+        span.set_result(&result);
+        result
+    }
+
+    #[inline]
+    fn lookup_peer(&mut self, addr: Address) -> Option<SocketAddr> {
+        // HOT PATH
+        match self.multipath {
+            Some(ref mut multipath) => {
+                let peers = &self.peers;
+                self.table.lookup_multipath(multipath, addr, |peer| peers.get(peer).and_then(|p| p.rtt))
+            }
+            None => self.table.lookup(addr),
+        }
+    }
+
+    fn forward_interface_data(&mut self, data: &mut MsgBuffer, span: &mut TraceSpan) -> Result<(), Error> {
         // HOT PATH
         let (src, dst) = P::parse(data.message())?;
         debug!("Read data from interface: src: {}, dst: {}, {} bytes", src, dst, data.len());
+        if self.excluded_routes.iter().any(|r| r.matches(dst)) {
+            // COLD PATH
+            debug!("Destination {} is excluded from the VPN, dropping", dst);
+            self.traffic.count_dropped_payload(data.len());
+            return Ok(())
+        }
+        if !self.acl.allows(src, dst, data.message()) {
+            // COLD PATH
+            debug!("Packet from {} to {} denied by ACL, dropping", src, dst);
+            self.traffic.count_dropped_payload(data.len());
+            return Ok(())
+        }
+        if let Some(ref arp_proxy) = self.arp_proxy {
+            match arp_proxy.answer(data.message()) {
+                ArpAnswer::NoRequest => (),
+                ArpAnswer::Reply(reply) => {
+                    // COLD PATH
+                    debug!("Answering ARP request from {} locally", src);
+                    self.traffic.arp_proxy_hits_total += 1;
+                    data.clone_from(&reply);
+                    if let Some(ref capture) = self.capture {
+                        capture.device(Direction::In, data.message())
+                    }
+                    return self.device.write(data)
+                }
+                ArpAnswer::Miss => self.traffic.arp_proxy_misses_total += 1,
+            }
+        }
         self.traffic.count_out_payload(dst, src, data.len());
-        match self.table.lookup(dst) {
+        // Packets selecting a policy table are routed via its gateway
+        let target = self.policy.lookup(src, dst);
+        let peer = self.lookup_peer(target);
+        if peer.is_none() && target != dst {
+            // COLD PATH
+            debug!("Policy gateway {} for {} not reachable, dropping", target, dst);
+            self.traffic.count_dropped_payload(data.len());
+            return Ok(())
+        }
+        match peer {
             Some(addr) => {
                 // HOT PATH
                 // Peer found for destination
                 debug!("Found destination for {} => {}", dst, addr);
-                self.send_msg(addr, MESSAGE_TYPE_DATA, data)?;
+                span.set_peer(addr);
+                self.send_data(addr, data, span.context())?;
                 if !self.peers.contains_key(&addr) {
                     // COLD PATH
                     // If the peer is not actually connected, remove the entry in the table and try
@@ -631,8 +1948,13 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             None => {
                 // COLD PATH
                 if self.broadcast {
-                    debug!("No destination for {} found, broadcasting", dst);
-                    self.broadcast_msg(MESSAGE_TYPE_DATA, data)?;
+                    if let Some(peers) = self.groups.as_ref().and_then(|g| g.lookup(&dst, data.message())) {
+                        debug!("Sending data for multicast group {} to {} peers", dst, peers.len());
+                        self.multicast_msg(&peers, data)?;
+                    } else {
+                        debug!("No destination for {} found, broadcasting", dst);
+                        self.broadcast_msg(MESSAGE_TYPE_DATA, data)?;
+                    }
                 } else {
                     debug!("No destination for {} found, dropping", dst);
                     self.traffic.count_dropped_payload(data.len());
@@ -642,8 +1964,44 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         Ok(())
     }
 
+    fn reject_peer(&mut self, addr: SocketAddr, err: Error) -> Result<(), Error> {
+        warn!("Rejecting peer {}: {}", addr_nice(addr), err);
+        if let Some(mut init) = self.pending_inits.remove(&addr) {
+            let mut msg = self.buffers.acquire();
+            msg.clear();
+            if init.send_message(MESSAGE_TYPE_CLOSE, &mut msg).is_ok() {
+                self.send_to(addr, &mut msg)?;
+            }
+        }
+        Err(err)
+    }
+
     fn add_new_peer(&mut self, addr: SocketAddr, info: NodeInfo) -> Result<(), Error> {
-        info!("Added peer {}", addr_nice(addr));
+        if let Some(identity) = &info.identity {
+            let session_key = self.pending_inits.get(&addr).and_then(PeerCrypto::peer_session_key);
+            let res = match session_key {
+                Some(session_key) => identity.verify(&info.node_id, session_key, TS::now()),
+                None => Err(Error::AuthFailed("Identity proof without a handshake")),
+            };
+            if let Err(err) = res {
+                return self.reject_peer(addr, err)
+            }
+        }
+        let fingerprint = self.pending_inits.get(&addr).and_then(|init| init.peer_fingerprint().copied());
+        if let (Some(tofu), Some(fingerprint)) = (self.tofu.as_mut(), fingerprint) {
+            // Node ids are random for each start, so identity keys are preferred to identify the peer
+            let peer_id = info.identity.as_ref().map(|i| &i.public_key[..]).unwrap_or(&info.node_id);
+            if let Err(err) = tofu.check(peer_id, &fingerprint) {
+                return self.reject_peer(addr, err)
+            }
+        }
+        if info.tags.is_empty() {
+            info!("Added peer {}", addr_nice(addr));
+        } else {
+            let mut tags: Vec<_> = info.tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            tags.sort_unstable();
+            info!("Added peer {} with tags {}", addr_nice(addr), tags.join(", "));
+        }
         self.config.call_hook(
             "peer_connected",
             vec![
@@ -655,6 +2013,33 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             true,
         );
         if let Some(init) = self.pending_inits.remove(&addr) {
+            if let Some(ref mut audit_log) = self.audit_log {
+                audit_log.connected(addr, &info.node_id);
+            }
+            if let Some(ref webhook) = self.webhook {
+                webhook.connected(addr, &info.node_id);
+            }
+            if let Some(ref mut snmp) = self.snmp {
+                snmp.peer_connected(addr, &info.node_id);
+            }
+            self.api.publish(|| ApiEvent::PeerConnected {
+                peer: addr_nice(addr).to_string(),
+                node_id: bytes_to_hex(&info.node_id),
+            });
+            debug!(
+                "Peer {} speaks protocol version {} with extensions {:#x}",
+                addr_nice(addr),
+                info.protocol_version,
+                info.extensions.0
+            );
+            let features = self.own_features().intersect(info.extensions);
+            let path = if self.turn.is_relayed(&addr) {
+                PeerPath::Relayed
+            } else if self.hole_punches.remove(&info.node_id).is_some() {
+                PeerPath::HolePunched
+            } else {
+                PeerPath::Direct
+            };
             self.peers.insert(
                 addr,
                 PeerData {
@@ -664,18 +2049,74 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
                     peer_timeout: info.peer_timeout.unwrap_or(DEFAULT_PEER_TIMEOUT),
                     last_seen: TS::now(),
                     timeout: TS::now() + self.config.peer_timeout as Time,
+                    features,
+                    tags: info.tags.clone(),
+                    path,
+                    encap: if self.pending_vxlan.remove(&addr) { Encap::Vxlan } else { Encap::None },
+                    loss: PacketLoss::default(),
+                    advertised_peers: SmallVec::new(),
+                    mtu: None,
+                    max_data_len: None,
+                    gossip_version: self.peer_changes.version(),
+                    // New peers get a full peer list with the next gossip
+                    next_full_gossip: TS::now(),
+                    rtt: None,
+                    reorder: if self.config.reorder_buffer {
+                        Some(ReorderBuffer::new(
+                            std::time::Duration::from_millis(u64::from(self.config.reorder_timeout_ms)),
+                            self.config.reorder_buffer_max_packets,
+                        ))
+                    } else {
+                        None
+                    },
+                    local: None,
                 },
             );
             self.update_peer_info(addr, Some(info))?;
+            let peer = &self.peers[&addr];
+            self.peer_changes.record(Change::Added, peer.node_id, peer.addrs.clone());
+            if self.config.pmtu_discovery && features.has(Feature::MtuProbe) {
+                self.send_mtu_probe(addr, MESSAGE_TYPE_MTU_PROBE, MTU_PROBE_SIZE)?;
+            }
         } else {
             error!("No init for new peer {}", addr_nice(addr));
         }
         Ok(())
     }
 
-    fn remove_peer(&mut self, addr: SocketAddr) {
+    /// Sends a message padded to the given size, so that the path MTU is discovered if it is smaller
+    fn send_mtu_probe(&mut self, addr: SocketAddr, type_: u8, size: u16) -> Result<(), Error> {
+        let mut msg = self.buffers.acquire();
+        msg.set_length(max(size as usize, 2));
+        let data = msg.message_mut();
+        data[..2].copy_from_slice(&size.to_be_bytes());
+        for b in &mut data[2..] {
+            *b = 0;
+        }
+        self.send_msg(addr, type_, &mut msg)
+    }
+
+    /// Removes the peer and reports it to the peer list changes, the audit log, webhooks, SNMP, the API and hooks
+    fn remove_peer(&mut self, addr: SocketAddr, reason: DisconnectReason) {
         if let Some(peer) = self.peers.remove(&addr) {
             info!("Closing connection to {}", addr_nice(addr));
+            self.peer_changes.record(Change::Removed, peer.node_id, peer.addrs.clone());
+            if let Some(ref mut audit_log) = self.audit_log {
+                audit_log.disconnected(addr, &peer.node_id, reason);
+            }
+            if let Some(ref webhook) = self.webhook {
+                webhook.disconnected(addr, &peer.node_id, reason);
+            }
+            if let Some(ref mut snmp) = self.snmp {
+                snmp.peer_disconnected(addr, &peer.node_id);
+            }
+            self.api.publish(|| {
+                let (peer, node_id) = (addr_nice(addr).to_string(), bytes_to_hex(&peer.node_id));
+                match reason {
+                    DisconnectReason::Timeout => ApiEvent::PeerTimeout { peer, node_id },
+                    _ => ApiEvent::PeerDisconnected { peer, node_id, reason },
+                }
+            });
             self.table.remove_claims(addr);
             self.config.call_hook(
                 "peer_disconnected",
@@ -713,15 +2154,96 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
                 }
             }
             self.connect(&peer.addrs as &[SocketAddr])?;
+            if let Some(node_id) = peer.node_id {
+                // The direct connection fails if the node is behind a NAT
+                self.request_hole_punch(node_id)?;
+            }
         }
         Ok(())
     }
 
+    /// Asks a peer that advertised the target node to coordinate a hole punch to that node
+    pub fn request_hole_punch(&mut self, target: NodeId) -> Result<(), Error> {
+        if !self.config.hole_punch || self.hole_punches.contains_key(&target) {
+            return Ok(())
+        }
+        let relay = self
+            .peers
+            .iter()
+            .find(|(_, peer)| peer.features.has(Feature::HolePunch) && peer.advertised_peers.contains(&target))
+            .map(|(addr, _)| *addr);
+        let relay = match relay {
+            Some(relay) => relay,
+            None => return Ok(()),
+        };
+        debug!("Requesting hole punch to {} via {}", bytes_to_hex(&target), addr_nice(relay));
+        self.hole_punches.insert(target, TS::now() + HOLE_PUNCH_TIMEOUT);
+        // The relay fills in the address it sees for this node
+        let initiator_addr = self.own_addresses.first().copied().unwrap_or_else(|| SocketAddr::from(([0; 4], 0)));
+        let mut msg = self.buffers.acquire();
+        PunchCoordinate { target_node_id: target, initiator_addr }.encode(&mut msg);
+        self.send_msg(relay, MESSAGE_TYPE_PUNCH_COORDINATE, &mut msg)
+    }
+
+    fn handle_punch_coordinate(&mut self, src: SocketAddr, msg: PunchCoordinate) -> Result<(), Error> {
+        if !self.config.hole_punch {
+            return Ok(())
+        }
+        if msg.target_node_id == self.node_id {
+            info!("Punching hole to {} as requested by {}", addr_nice(msg.initiator_addr), addr_nice(src));
+            return self.connect_sock(msg.initiator_addr)
+        }
+        // Relay the request to both sides with the address of the other side
+        let initiator_id = match self.peers.get(&src) {
+            Some(peer) => peer.node_id,
+            None => return Ok(()),
+        };
+        let target = self
+            .peers
+            .iter()
+            .find(|(_, peer)| peer.features.has(Feature::HolePunch) && peer.node_id == msg.target_node_id)
+            .map(|(addr, _)| *addr);
+        let target = match target {
+            Some(target) if target != src => target,
+            _ => {
+                debug!("Can not coordinate hole punch to unknown node {}", bytes_to_hex(&msg.target_node_id));
+                return Ok(())
+            }
+        };
+        debug!("Coordinating hole punch between {} and {}", addr_nice(src), addr_nice(target));
+        let mut buffer = self.buffers.acquire();
+        PunchCoordinate { target_node_id: msg.target_node_id, initiator_addr: src }.encode(&mut buffer);
+        self.send_msg(target, MESSAGE_TYPE_PUNCH_COORDINATE, &mut buffer)?;
+        buffer.clear();
+        PunchCoordinate { target_node_id: initiator_id, initiator_addr: target }.encode(&mut buffer);
+        self.send_msg(src, MESSAGE_TYPE_PUNCH_COORDINATE, &mut buffer)
+    }
+
     fn update_peer_info(&mut self, addr: SocketAddr, info: Option<NodeInfo>) -> Result<(), Error> {
+        // The MTU does not cover the Ethernet header of frames
+        let frame_header = if self.config.device_type == Type::Tap { MAX_FRAME_HEADER } else { 0 };
+        let own_features = self.own_features();
         if let Some(peer) = self.peers.get_mut(&addr) {
             peer.last_seen = TS::now();
             peer.timeout = TS::now() + self.config.peer_timeout as Time;
             if let Some(info) = &info {
+                peer.features = own_features.intersect(info.extensions);
+                peer.tags = info.tags.clone();
+                peer.mtu = match (info.mtu, self.config.mtu) {
+                    (Some(theirs), Some(ours)) if theirs < ours => {
+                        if peer.mtu != Some(theirs) {
+                            info!("Peer {} has a smaller MTU of {}", addr_nice(addr), theirs);
+                        }
+                        Some(theirs)
+                    }
+                    (Some(_), ours) => ours,
+                    (None, _) => None,
+                };
+                peer.max_data_len = match peer.mtu {
+                    Some(mtu) if peer.features.has(Feature::Fragments) => Some(mtu as usize + frame_header),
+                    _ => None,
+                };
+                peer.advertised_peers = info.peers.iter().filter_map(|p| p.node_id).collect();
                 // Update peer addresses, always add seen address
                 peer.addrs.clear();
                 peer.addrs.push(addr);
@@ -740,6 +2262,9 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             self.table.set_claims(addr, info.claims);
             debug!("Received {} peers from {}: {:?}", info.peers.len(), addr_nice(addr), info.peers);
             self.connect_to_peers(&info.peers)?;
+        } else {
+            // Peers that got a full peer list recently only send changes or keepalives, their claims stay valid
+            self.table.refresh_claims(addr);
         }
         Ok(())
     }
@@ -748,8 +2273,17 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         // HOT PATH
         let (src, dst) = P::parse(data.message())?;
         let len = data.len();
+        if !self.acl.allows(src, dst, data.message()) {
+            // COLD PATH
+            debug!("Packet from {} to {} denied by ACL, dropping", src, dst);
+            self.traffic.count_dropped_payload(len);
+            return Ok(())
+        }
         debug!("Writing data to device: {} bytes", len);
         self.traffic.count_in_payload(src, dst, len);
+        if let Some(ref capture) = self.capture {
+            capture.device(Direction::In, data.message())
+        }
         if let Err(e) = self.device.write(data) {
             error!("Failed to send via device: {}", e);
             return Err(e);
@@ -757,22 +2291,71 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         if self.learning {
             // Learn single address
             self.table.cache(src, peer);
+            if dst.is_multicast() {
+                // COLD PATH
+                if let Some(ref mut groups) = self.groups {
+                    groups.snoop(peer, data.message());
+                }
+            }
+            if let Some(ref mut arp_proxy) = self.arp_proxy {
+                arp_proxy.learn(peer, data.message());
+            }
         }
         Ok(())
     }
 
     fn handle_message(
-        &mut self, src: SocketAddr, msg_result: MessageResult<NodeInfo>, data: &mut MsgBuffer,
+        &mut self, src: SocketAddr, msg_result: MessageResult<NodeInfo>, data: &mut MsgBuffer, span: &mut TraceSpan,
     ) -> Result<(), Error> {
         // HOT PATH
         match msg_result {
             MessageResult::Message(type_) => {
                 // HOT PATH
+                span.set_message_type(type_);
                 match type_ {
                     MESSAGE_TYPE_DATA => {
                         // HOT PATH
                         self.handle_payload_from(src, data)?
                     }
+                    MESSAGE_TYPE_DATA_PADDED => {
+                        // HOT PATH
+                        if let Err(err) = strip_padding(data) {
+                            self.traffic.count_invalid_protocol(data.len());
+                            return Err(err)
+                        }
+                        self.handle_payload_from(src, data)?
+                    }
+                    MESSAGE_TYPE_DATA_FRAGMENT => {
+                        // COLD PATH
+                        let frame = match self.fragment_buffer.reassemble(src, data.message(), TS::instant()) {
+                            Ok(Some(frame)) if frame.len() <= data.buffer().len() => frame,
+                            Ok(Some(_)) => return Err(Error::Message("Reassembled frame too large")),
+                            Ok(None) => return Ok(()),
+                            Err(err) => {
+                                self.traffic.count_invalid_protocol(data.len());
+                                return Err(err)
+                            }
+                        };
+                        data.clone_from(&frame);
+                        self.handle_payload_from(src, data)?
+                    }
+                    MESSAGE_TYPE_DATA_TRACED => {
+                        // HOT PATH
+                        let trace = match TraceContext::read_from(data) {
+                            Ok(val) => val,
+                            Err(err) => {
+                                self.traffic.count_invalid_protocol(data.len());
+                                return Err(err);
+                            }
+                        };
+                        // Continue the trace of the sending node
+                        let mut payload_span = self.telemetry.span("handle_payload", Some(trace));
+                        payload_span.set_peer(src);
+                        payload_span.set_size(data.len());
+                        let result = self.handle_payload_from(src, data);
+                        payload_span.set_result(&result);
+                        result?
+                    }
                     MESSAGE_TYPE_NODE_INFO => {
                         // COLD PATH
                         let info = match NodeInfo::decode(Cursor::new(data.message())) {
@@ -788,9 +2371,66 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
                         // COLD PATH
                         self.update_peer_info(src, None)?
                     }
+                    MESSAGE_TYPE_PROBE => {
+                        // COLD PATH
+                        self.update_peer_info(src, None)?;
+                        data.clear();
+                        self.send_msg(src, MESSAGE_TYPE_PROBE_REPLY, data)?
+                    }
+                    MESSAGE_TYPE_PROBE_REPLY => {
+                        // COLD PATH
+                        self.update_peer_info(src, None)?
+                    }
+                    MESSAGE_TYPE_PING => {
+                        // COLD PATH
+                        self.update_peer_info(src, None)?;
+                        self.send_msg(src, MESSAGE_TYPE_PONG, data)?
+                    }
+                    MESSAGE_TYPE_PONG => {
+                        // COLD PATH
+                        self.handle_pong(src, data.message())?
+                    }
+                    MESSAGE_TYPE_MTU_PROBE => {
+                        // COLD PATH
+                        if data.len() < 2 {
+                            self.traffic.count_invalid_protocol(data.len());
+                            return Err(Error::Message("MTU probe too short"))
+                        }
+                        let size = u16::from_be_bytes([data.message()[0], data.message()[1]]);
+                        self.update_peer_info(src, None)?;
+                        // Reply with the same size to probe the path in the other direction
+                        self.send_mtu_probe(src, MESSAGE_TYPE_MTU_PROBE_REPLY, size)?
+                    }
+                    MESSAGE_TYPE_MTU_PROBE_REPLY => {
+                        // COLD PATH
+                        self.update_peer_info(src, None)?
+                    }
+                    MESSAGE_TYPE_PEER_UPDATE => {
+                        // COLD PATH
+                        let update = match PeerUpdate::decode(Cursor::new(data.message())) {
+                            Ok(val) => val,
+                            Err(err) => {
+                                self.traffic.count_invalid_protocol(data.len());
+                                return Err(err);
+                            }
+                        };
+                        self.update_peer_info(src, None)?;
+                        self.apply_peer_update(src, update)?
+                    }
+                    MESSAGE_TYPE_PUNCH_COORDINATE => {
+                        // COLD PATH
+                        let msg = match PunchCoordinate::decode(Cursor::new(data.message())) {
+                            Ok(val) => val,
+                            Err(err) => {
+                                self.traffic.count_invalid_protocol(data.len());
+                                return Err(err);
+                            }
+                        };
+                        self.handle_punch_coordinate(src, msg)?
+                    }
                     MESSAGE_TYPE_CLOSE => {
                         // COLD PATH
-                        self.remove_peer(src)
+                        self.remove_peer(src, DisconnectReason::Close)
                     }
                     _ => {
                         // COLD PATH
@@ -805,8 +2445,9 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             }
             MessageResult::InitializedWithReply(info) => {
                 // COLD PATH
-                self.add_new_peer(src, info)?;
-                self.send_to(src, data)?
+                // Reply first, messages sent when adding the peer can only be read after the peer got the reply
+                self.send_to(src, data)?;
+                self.add_new_peer(src, info)?
             }
             MessageResult::Reply => {
                 // COLD PATH
@@ -819,11 +2460,100 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         Ok(())
     }
 
+    fn handle_noise_initiation(
+        &mut self, src: SocketAddr, data: &mut MsgBuffer,
+    ) -> Result<MessageResult<NodeInfo>, Error> {
+        if !self.peers.contains_key(&src) && !self.pending_inits.contains_key(&src) && self.peer_limit_reached() {
+            warn!("Refusing noise connection from {}, maximum number of peers reached", addr_nice(src));
+            data.clear();
+            return Ok(MessageResult::None);
+        }
+        let msg = data.message().to_vec();
+        let session_key = match noise::session_key(&msg) {
+            Some(key) => key,
+            None => return Err(Error::CryptoInit("Invalid noise handshake")),
+        };
+        data.clear();
+        let mut own_info = self.create_node_info();
+        own_info.identity =
+            self.identity.as_ref().map(|identity| identity.prove(&self.node_id, session_key, TS::now()));
+        own_info.write_to(data);
+        let (transport, payload, reply) = self.noise.as_ref().unwrap().respond(&msg, data.message())?;
+        let info =
+            NodeInfo::read_from(&payload[..]).map_err(|_| Error::CryptoInit("Invalid noise handshake payload"))?;
+        data.clone_from(&reply);
+        debug!("Answered noise handshake from {}", addr_nice(src));
+        self.pending_inits.insert(src, PeerCrypto::from_noise(self.node_id, transport, info, session_key));
+        Ok(MessageResult::Reply)
+    }
+
     pub fn handle_net_message(&mut self, src: SocketAddr, data: &mut MsgBuffer) -> Result<(), Error> {
         // HOT PATH
-        let src = mapped_addr(src);
+        let mut src = mapped_addr(src);
+        let relayed = Some(src) == self.turn.server();
+        if relayed {
+            // COLD PATH
+            match self.turn.handle_message(data, TS::now()) {
+                Ok(TurnResult::Data(peer)) => src = peer,
+                Ok(TurnResult::Reply) => return self.send_to_turn_server(data.message()),
+                Ok(TurnResult::None) => return Ok(()),
+                Err(err) => {
+                    self.traffic.count_invalid_protocol(data.len());
+                    return Err(err);
+                }
+            }
+        }
+        let mut encap = Encap::None;
+        if let Some(ref vxlan) = self.vxlan {
+            // COLD PATH
+            if vxlan.decode(data) {
+                encap = Encap::Vxlan
+            }
+            if !self.peers.contains_key(&src) {
+                if encap == Encap::Vxlan {
+                    self.pending_vxlan.insert(src);
+                } else {
+                    self.pending_vxlan.remove(&src);
+                }
+            }
+        }
+        let mut span = self.telemetry.span("handle_net_message", None);
+        span.set_peer(src);
+        span.set_size(data.len());
+        let result = self.process_net_message(src, data, &mut span);
+        span.set_result(&result);
+        if self.vxlan.is_some() && result.is_ok() {
+            // COLD PATH
+            if let Some(peer) = self.peers.get_mut(&src) {
+                // Peers switch their encapsulation with authenticated messages only
+                peer.encap = encap;
+            }
+        }
+        if !relayed && result.is_ok() && self.turn.is_relayed(&src) {
+            // COLD PATH
+            info!("Peer {} is reachable directly, no longer relaying via TURN server", addr_nice(src));
+            self.turn.unbind(&src);
+            if let Some(peer) = self.peers.get_mut(&src) {
+                peer.path = PeerPath::Direct;
+            }
+        }
+        result
+    }
+
+    fn process_net_message(
+        &mut self, src: SocketAddr, data: &mut MsgBuffer, span: &mut TraceSpan,
+    ) -> Result<(), Error> {
+        // HOT PATH
         debug!("Received {} bytes from {}", data.len(), src);
-        let msg_result = if let Some(init) = self.pending_inits.get_mut(&src) {
+        let mut rx_seq = None;
+        let msg_result = if self.noise.is_some()
+            && noise::is_initiation(data.message())
+            && self.pending_inits.get(&src).map(PeerCrypto::is_noise).unwrap_or(true)
+            && self.peers.get(&src).map(|peer| peer.crypto.is_noise()).unwrap_or(true)
+        {
+            // COLD PATH
+            self.handle_noise_initiation(src, data)
+        } else if let Some(init) = self.pending_inits.get_mut(&src) {
             // COLD PATH
             init.handle_message(data)
         } else if is_init_message(data.message()) {
@@ -836,8 +2566,14 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             }
             if let Some(result) = result {
                 result
+            } else if self.peer_limit_reached() {
+                // COLD PATH
+                // No session exists yet, so the peer can not be sent a close message. Ignoring the init makes it
+                // give up after some retries.
+                warn!("Refusing connection from {}, maximum number of peers reached", addr_nice(src));
+                return Ok(());
             } else {
-                let mut init = self.crypto.peer_instance(self.create_node_info());
+                let mut init = self.peer_crypto(src);
                 let msg_result = init.handle_message(data);
                 match msg_result {
                     Ok(res) => {
@@ -860,7 +2596,12 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
             }
         } else if let Some(peer) = self.peers.get_mut(&src) {
             // HOT PATH
-            peer.crypto.handle_message(data)
+            let result = peer.crypto.handle_message(data);
+            if let Some(seq) = peer.crypto.take_rx_seq() {
+                peer.loss.count(seq);
+                rx_seq = Some(seq)
+            }
+            result
         } else {
             // COLD PATH
             info!("Ignoring non-init message from unknown peer {}", addr_nice(src));
@@ -869,9 +2610,16 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         };
         // HOT PATH
         match msg_result {
+            Ok(MessageResult::Message(type_)) if rx_seq.is_some() && self.config.reorder_buffer => {
+                // COLD PATH
+                if !self.reorder(src, rx_seq.unwrap(), type_, data) {
+                    self.handle_message(src, MessageResult::Message(type_), data, span)?;
+                }
+                self.release_reordered(src, data, span)
+            }
             Ok(val) => {
                 // HOT PATH
-                self.handle_message(src, val, data)
+                self.handle_message(src, val, data, span)
             }
             Err(err) => {
                 // COLD PATH
@@ -881,16 +2629,166 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
         }
     }
 
+    /// Holds back data messages that arrive ahead of their sequence number, returns whether the message was held
+    ///
+    /// Other messages are handled right away, their sequence numbers are only recorded to not appear as gaps.
+    fn reorder(&mut self, src: SocketAddr, seq: u64, type_: u8, data: &MsgBuffer) -> bool {
+        let reorder = match self.peers.get_mut(&src).and_then(|peer| peer.reorder.as_mut()) {
+            Some(reorder) => reorder,
+            None => return false,
+        };
+        let now = TS::instant();
+        if reorder.expire(now) {
+            self.traffic.reorder_buffer_flushes_total += 1;
+        }
+        if reorder.accept(seq) {
+            return false
+        }
+        let is_data = matches!(type_, MESSAGE_TYPE_DATA | MESSAGE_TYPE_DATA_PADDED | MESSAGE_TYPE_DATA_TRACED);
+        let item = if is_data { Some((type_, data.message().to_vec())) } else { None };
+        if reorder.hold(seq, item, now) {
+            self.traffic.reorder_buffer_overflows_total += 1;
+        }
+        is_data
+    }
+
+    /// Handles the held back messages of the peer that are in order now
+    fn release_reordered(&mut self, src: SocketAddr, data: &mut MsgBuffer, span: &mut TraceSpan) -> Result<(), Error> {
+        while let Some((type_, msg)) = self.peers.get_mut(&src).and_then(|peer| peer.reorder.as_mut()?.pop()) {
+            data.clear();
+            data.clone_from(&msg);
+            self.handle_message(src, MessageResult::Message(type_), data, span)?
+        }
+        Ok(())
+    }
+
+    /// Releases the held back messages whose predecessors did not arrive in time
+    fn flush_reorder_buffers(&mut self, buffer: &mut MsgBuffer) {
+        let now = TS::instant();
+        let mut expired: SmallVec<[SocketAddr; 4]> = SmallVec::new();
+        for (addr, peer) in &mut self.peers {
+            if let Some(ref mut reorder) = peer.reorder {
+                if reorder.expire(now) {
+                    expired.push(*addr)
+                }
+            }
+        }
+        for addr in expired {
+            self.traffic.reorder_buffer_flushes_total += 1;
+            let mut span = self.telemetry.span("handle_net_message", None);
+            span.set_peer(addr);
+            let result = self.release_reordered(addr, buffer, &mut span);
+            span.set_result(&result);
+            if let Err(err) = result {
+                error!("{}", err)
+            }
+        }
+    }
+
+    /// Repeats the init messages that the peers did not reply to within the init timeout
+    fn retransmit_inits(&mut self) {
+        let now = TS::instant();
+        if now < self.next_init_retransmit {
+            return
+        }
+        let timeout = std::time::Duration::from_millis(u64::from(self.config.init_timeout_ms));
+        self.next_init_retransmit = now + timeout / 2;
+        let retries = self.config.init_retries;
+        let mut msg = self.buffers.acquire();
+        for addr in self.pending_inits.keys().copied().collect::<SmallVec<[SocketAddr; 4]>>() {
+            if self.pending_inits.get_mut(&addr).unwrap().retransmit_init(now, timeout, retries, &mut msg) {
+                if let Err(err) = self.send_to(addr, &mut msg) {
+                    error!("{}", err)
+                }
+            }
+        }
+        let addrs: SmallVec<[SocketAddr; 4]> =
+            self.peers.iter().filter(|(_, peer)| peer.crypto.has_init()).map(|(addr, _)| *addr).collect();
+        for addr in addrs {
+            if self.peers.get_mut(&addr).unwrap().crypto.retransmit_init(now, timeout, retries, &mut msg) {
+                if let Err(err) = self.send_to(addr, &mut msg) {
+                    error!("{}", err)
+                }
+            }
+        }
+    }
+
     fn initialize(&mut self) {
         if let Err(err) = self.reset_own_addresses() {
             error!("Failed to obtain local addresses: {}", err)
         }
+        if let Some(ref path) = self.config.peer_state_file {
+            if Path::new(path).exists() {
+                match PeerList::load(path) {
+                    Ok(list) => self.add_saved_peers(list),
+                    Err(err) => warn!("Failed to load saved peers: {}", err),
+                }
+            }
+        }
+    }
+
+    /// Reconnects to the peers of the last run
+    ///
+    /// The peers need a new handshake, so they are only tried until the peer timeout has passed.
+    fn add_saved_peers(&mut self, list: PeerList) {
+        let now = TS::now();
+        for peer in list.peers {
+            if peer.addrs.iter().any(|addr| self.reconnect_peers.iter().any(|e| e.resolved.contains(addr))) {
+                continue
+            }
+            if self.reconnect_peers.len() >= self.config.max_reconnect_peers {
+                warn!("Not reconnecting to saved peers, maximum number of reconnect peers reached");
+                break
+            }
+            info!("Reconnecting to saved peer {:?}", peer.addrs);
+            self.reconnect_peers.push(ReconnectEntry {
+                address: None,
+                resolved: peer.addrs.into_iter().collect(),
+                tries: 0,
+                timeout: 1,
+                next: now,
+                last_attempt: None,
+                final_timeout: Some(now + Time::from(self.config.peer_timeout)),
+            })
+        }
+    }
+
+    fn save_peers(&self, path: &str) {
+        let peers = self
+            .peers
+            .iter()
+            .map(|(addr, data)| {
+                let mut addrs = vec![*addr];
+                addrs.extend(data.addrs.iter().filter(|a| *a != addr));
+                SavedPeer { addrs }
+            })
+            .collect();
+        info!("Saving peers to {}", path);
+        if let Err(err) = (PeerList { peers }).save(path) {
+            error!("Failed to save peers: {}", err)
+        }
     }
 
     fn handle_socket_event(&mut self, buffer: &mut MsgBuffer) {
         // HOT PATH
+        if let Some(receivers) = self.receivers.take() {
+            for (src, mut data) in receivers.receive() {
+                self.handle_received(src, &mut data)
+            }
+            receivers.finish_batch();
+            self.receivers = Some(receivers);
+            return
+        }
         let src = try_fail!(self.socket.receive(buffer), "Failed to read from network socket: {}");
+        self.handle_received(src, buffer)
+    }
+
+    fn handle_received(&mut self, src: SocketAddr, buffer: &mut MsgBuffer) {
+        // HOT PATH
         self.traffic.count_in_traffic(src, buffer.len());
+        if let Some(ref capture) = self.capture {
+            capture.network(Direction::In, src, buffer.message())
+        }
         match self.handle_net_message(src, buffer) {
             Err(e @ Error::CryptoInitFatal(_)) => {
                 // COLD PATH
@@ -908,6 +2806,10 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
                 debug!("Recoverable init error from {}: {}", src, e);
                 info!("Ignoring invalid init message from peer {}", addr_nice(src));
             }
+            Err(e) if e.is_transient() => {
+                // COLD PATH
+                warn!("{}", e);
+            }
             Err(e) => {
                 // COLD PATH
                 error!("{}", e);
@@ -918,12 +2820,169 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
 
     fn handle_device_event(&mut self, buffer: &mut MsgBuffer) {
         // HOT PATH
+        // The packet is read, encrypted and sent in place, see MsgBuffer
         try_fail!(self.device.read(buffer), "Failed to read from device: {}");
+        if let Some(ref capture) = self.capture {
+            capture.device(Direction::Out, buffer.message())
+        }
         if let Err(e) = self.handle_interface_data(buffer) {
             error!("{}", e);
         }
     }
 
+    /// Builds the mesh graph from the direct peers and the peer lists they advertised
+    fn topology(&self) -> Topology {
+        let own_addr = self.own_addresses.first().map(|addr| addr_nice(*addr).to_string());
+        let mut topology = Topology::new(&self.node_id, own_addr);
+        for (addr, peer) in &self.peers {
+            let addr = addr_nice(*addr).to_string();
+            topology.add_peer(&self.node_id, &peer.node_id, addr, peer.rtt, &peer.advertised_peers);
+        }
+        topology
+    }
+
+    /// Answers the pending requests of the management API
+    fn handle_api_requests(&mut self) {
+        while let Some(request) = self.api.try_recv() {
+            let result = self.handle_api_command(&request.command);
+            request.reply(result)
+        }
+    }
+
+    fn get_tofu(&mut self) -> Result<&mut TofuStore<TS>, ApiError> {
+        self.tofu.as_mut().ok_or_else(|| ApiError::new(404, "No TOFU store configured"))
+    }
+
+    fn handle_api_command(&mut self, command: &ApiCommand) -> ApiResult {
+        match command {
+            ApiCommand::Peers => api_value(&self.stats_snapshot().peers),
+            ApiCommand::Stats => api_value(&self.stats_snapshot()),
+            ApiCommand::Table => api_value(&self.table.snapshot()),
+            ApiCommand::TableDump => {
+                self.traffic.table_dump_calls_total += 1;
+                api_value(&self.table_snapshot())
+            }
+            ApiCommand::TableQuery(addr) => {
+                let addr = Address::from_str(addr).map_err(|_| ApiError::new(400, "Invalid address"))?;
+                match self.table.query(addr) {
+                    Some(claim) => api_value(&claim),
+                    None => Err(ApiError::new(404, "No matching claim")),
+                }
+            }
+            ApiCommand::Topology => api_value(&self.topology()),
+            ApiCommand::TopologyDot => api_value(&self.topology().to_dot()),
+            ApiCommand::Connect(addr) => {
+                info!("Connecting to {} as requested via API", addr);
+                self.connect(addr as &str).map_err(|e| ApiError::new(400, e.to_string()))?;
+                self.add_reconnect_peer(addr.clone());
+                api_value(&addr)
+            }
+            ApiCommand::Disconnect(addr) => {
+                let addr = match addr.parse::<SocketAddr>() {
+                    Ok(addr) => mapped_addr(addr),
+                    Err(_) => return Err(ApiError::new(400, "Invalid address")),
+                };
+                if !self.peers.contains_key(&addr) {
+                    return Err(ApiError::new(404, "Not a peer"))
+                }
+                info!("Removing peer {} as requested via API", addr_nice(addr));
+                self.reconnect_peers.retain(|entry| !entry.resolved.contains(&addr));
+                let mut buffer = self.buffers.acquire();
+                buffer.clear();
+                self.send_msg(addr, MESSAGE_TYPE_CLOSE, &mut buffer).ok();
+                self.remove_peer(addr, DisconnectReason::Removed);
+                api_value(&addr_nice(addr).to_string())
+            }
+            ApiCommand::ReconnectPeers => {
+                api_value(&self.reconnect_peers_list().iter().map(ReconnectEntry::snapshot).collect::<Vec<_>>())
+            }
+            ApiCommand::AddReconnectPeer(addr) => {
+                if self.reconnect_peers.iter().any(|entry| entry.has_address(addr)) {
+                    return Err(ApiError::new(409, "Already a reconnect peer"))
+                }
+                if self.reconnect_peers.len() >= self.config.max_reconnect_peers {
+                    return Err(ApiError::new(400, "Maximum number of reconnect peers reached"))
+                }
+                info!("Adding reconnect peer {} as requested via API", addr);
+                self.add_reconnect_peer(addr.clone());
+                api_value(&addr)
+            }
+            ApiCommand::RemoveReconnectPeer { addr, disconnect } => {
+                let entries = self.take_reconnect_peers(addr);
+                if entries.is_empty() {
+                    return Err(ApiError::new(404, "Not a reconnect peer"))
+                }
+                info!("Removed reconnect peer {} as requested via API", addr);
+                if *disconnect {
+                    for peer in entries.iter().flat_map(|entry| entry.resolved.iter()).map(|a| mapped_addr(*a)) {
+                        if self.peers.contains_key(&peer) {
+                            let mut buffer = self.buffers.acquire();
+                            buffer.clear();
+                            self.send_msg(peer, MESSAGE_TYPE_CLOSE, &mut buffer).ok();
+                            self.remove_peer(peer, DisconnectReason::Removed);
+                        }
+                    }
+                }
+                api_value(&addr)
+            }
+            ApiCommand::TofuList => api_value(self.get_tofu()?.entries()),
+            ApiCommand::TofuRemove(node_id) => {
+                if !self.get_tofu()?.remove(node_id).map_err(|e| ApiError::new(500, e.to_string()))? {
+                    return Err(ApiError::new(404, "Node id is not pinned"))
+                }
+                info!("Removed pinned key of node {} as requested via API", node_id);
+                api_value(&node_id)
+            }
+            ApiCommand::TofuTrust(node_id) => {
+                if !self.get_tofu()?.trust(node_id).map_err(|e| ApiError::new(500, e.to_string()))? {
+                    return Err(ApiError::new(404, "No rejected connection from this node id"))
+                }
+                api_value(&node_id)
+            }
+            ApiCommand::Reload => Err(ApiError::new(501, "Reloading the configuration is not supported")),
+        }
+    }
+
+    /// Returns a handle that stops the main loop, so the node can be stopped without a signal
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle { flag: self.stop_flag.clone() }
+    }
+
+    /// Timeout of the run loop in milliseconds, shorter with pacing, reordering and init retries to act in time
+    fn poll_timeout(&self) -> u32 {
+        let mut timeout = 1000;
+        if self.pacer.is_some() {
+            timeout = PACING_POLL_TIMEOUT
+        }
+        if self.config.reorder_buffer {
+            timeout = min(timeout, max(self.config.reorder_timeout_ms / 2, 1))
+        }
+        if self.config.init_retries > 0 {
+            timeout = min(timeout, max(self.config.init_timeout_ms / 2, 1))
+        }
+        timeout
+    }
+
+    /// Starts the receive threads if configured and returns the file descriptor to wait for instead of the socket
+    fn start_receivers(&mut self) -> RawFd {
+        // Peers in the same process pass their messages into the channel of the receive threads
+        if self.config.receive_threads > 1 || self.config.local_shortcut {
+            match self.socket.spawn_receivers(self.config.receive_threads.max(1), &self.buffers) {
+                Ok(receivers) => self.receivers = Some(receivers),
+                Err(err) => warn!("Failed to start receive threads, receiving in the main thread: {}", err),
+            }
+        }
+        if self.config.local_shortcut {
+            if let Some(ref receivers) = self.receivers {
+                self.local_bus = Some(local_bus::register(self.node_id, receivers.injector()))
+            }
+        }
+        match self.receivers {
+            Some(ref receivers) => receivers.as_raw_fd(),
+            None => self.socket.as_raw_fd(),
+        }
+    }
+
     /// The main method of the node
     ///
     /// This method will use epoll to wait in the sockets and the device at the same time.
@@ -931,53 +2990,112 @@ impl<D: Device, P: Protocol, S: Socket, TS: TimeSource> GenericCloud<D, P, S, TS
     /// `handle_net_message` method. It will also read from the device and call
     /// `handle_interface_data` for each packet read.
     /// Also, this method will call `housekeep` every second.
+    /// It returns after a signal or a call to `StopHandle::stop`.
+    #[cfg(not(feature = "async"))]
     pub fn run(&mut self) {
         let ctrlc = CtrlC::new();
         let waiter = try_fail!(
-            WaitImpl::new(self.socket.as_raw_fd(), self.device.as_raw_fd(), 1000),
+            WaitImpl::new(self.start_receivers(), self.device.as_raw_fd(), self.poll_timeout()),
             "Failed to setup poll: {}"
         );
-        let mut buffer = MsgBuffer::new(SPACE_BEFORE);
+        let mut buffer = self.buffers.acquire();
         let mut poll_error = false;
         self.config.call_hook("vpn_started", vec![("IFNAME", self.device.ifname())], true);
+        self.notifier.ready();
         for evt in waiter {
-            // HOT PATH
-            match evt {
-                WaitResult::Error(err) => {
-                    // COLD PATH
-                    if poll_error {
-                        fail!("Poll wait failed again: {}", err);
-                    }
-                    debug!("Poll wait failed: {}, retrying...", err);
-                    poll_error = true;
-                }
-                WaitResult::Timeout => {}
-                WaitResult::Socket => self.handle_socket_event(&mut buffer),
-                WaitResult::Device => self.handle_device_event(&mut buffer),
+            if !self.handle_wait_result(evt, &mut buffer, &mut poll_error, &ctrlc) {
+                break;
             }
-            if self.next_housekeep < TS::now() {
+        }
+        self.shutdown(&mut buffer);
+    }
+
+    /// Runs the node on a single threaded tokio runtime
+    #[cfg(feature = "async")]
+    pub fn run(&mut self) {
+        let runtime = try_fail!(
+            tokio::runtime::Builder::new_current_thread().enable_all().build(),
+            "Failed to start runtime: {}"
+        );
+        try_fail!(runtime.block_on(self.run_async()), "{}")
+    }
+
+    /// Runs the node until it is stopped on the tokio runtime of the calling task
+    ///
+    /// The event handlers do not yield, so other tasks on the same thread only run between two events.
+    #[cfg(feature = "async")]
+    pub async fn run_async(&mut self) -> Result<(), Error> {
+        let ctrlc = CtrlC::new();
+        let mut waiter = AsyncWait::new(self.start_receivers(), self.device.as_raw_fd(), self.poll_timeout())
+            .map_err(|e| Error::SocketIo { message: "Failed to setup poll", source: e })?;
+        let mut buffer = self.buffers.acquire();
+        let mut poll_error = false;
+        self.config.call_hook("vpn_started", vec![("IFNAME", self.device.ifname())], true);
+        self.notifier.ready();
+        loop {
+            let evt = waiter.wait().await;
+            if !self.handle_wait_result(evt, &mut buffer, &mut poll_error, &ctrlc) {
+                break;
+            }
+        }
+        self.shutdown(&mut buffer);
+        Ok(())
+    }
+
+    /// Handles one event of the run loop, returns false when the node should stop
+    #[inline]
+    fn handle_wait_result(
+        &mut self, evt: WaitResult, buffer: &mut MsgBuffer, poll_error: &mut bool, ctrlc: &CtrlC,
+    ) -> bool {
+        // HOT PATH
+        match evt {
+            WaitResult::Error(err) => {
                 // COLD PATH
-                poll_error = false;
-                if ctrlc.was_pressed() {
-                    break;
+                if *poll_error {
+                    fail!("Poll wait failed again: {}", err);
                 }
-                if let Err(e) = self.housekeep() {
-                    error!("{}", e)
-                }
-                self.next_housekeep = TS::now() + 1
+                debug!("Poll wait failed: {}, retrying...", err);
+                *poll_error = true;
             }
+            WaitResult::Timeout => {}
+            WaitResult::Socket => self.handle_socket_event(buffer),
+            WaitResult::Device => self.handle_device_event(buffer),
+        }
+        self.send_deferred(buffer);
+        if self.config.reorder_buffer {
+            self.flush_reorder_buffers(buffer);
+        }
+        if self.config.init_retries > 0 {
+            self.retransmit_inits();
         }
+        if self.next_housekeep < TS::now() {
+            // COLD PATH
+            *poll_error = false;
+            if ctrlc.was_pressed() || self.stop_flag.load(Ordering::Relaxed) {
+                return false
+            }
+            if let Err(e) = self.housekeep() {
+                error!("{}", e)
+            }
+            self.handle_api_requests();
+            self.next_housekeep = TS::now() + 1
+        }
+        true
+    }
+
+    fn shutdown(&mut self, buffer: &mut MsgBuffer) {
         info!("Shutting down...");
         self.config.call_hook("vpn_shutdown", vec![("IFNAME", self.device.ifname())], true);
+        if let Some(ref path) = self.config.peer_state_file {
+            self.save_peers(path);
+        }
+        // The peers should learn about the shutdown even if the messages to them are paced
+        self.pacer = None;
         buffer.clear();
-        self.broadcast_msg(MESSAGE_TYPE_CLOSE, &mut buffer).ok();
-        if let Some(ref path) = self.config.beacon_store {
-            let path = Path::new(path);
-            if path.exists() {
-                info!("Removing beacon file");
-                if let Err(e) = fs::remove_file(path) {
-                    error!("Failed to remove beacon file: {}", e)
-                }
+        self.broadcast_msg(MESSAGE_TYPE_CLOSE, buffer).ok();
+        if let Some(ref mut store) = self.beacon_store {
+            if let Err(e) = store.remove() {
+                error!("{}", e)
             }
         }
     }
@@ -988,6 +3106,8 @@ use super::device::MockDevice;
 #[cfg(test)]
 use super::net::MockSocket;
 #[cfg(test)]
+use super::tests::transport::{LoopbackTransport, SimulationDevice};
+#[cfg(test)]
 use super::util::MockTimeSource;
 
 #[cfg(test)]
@@ -999,17 +3119,69 @@ impl<P: Protocol> GenericCloud<MockDevice, P, MockSocket, MockTimeSource> {
     pub fn device(&mut self) -> &mut MockDevice {
         &mut self.device
     }
+}
+
+#[cfg(test)]
+impl<P: Protocol> GenericCloud<SimulationDevice, P, LoopbackTransport, MockTimeSource> {
+    pub fn socket(&mut self) -> &mut LoopbackTransport {
+        &mut self.socket
+    }
+
+    pub fn device(&mut self) -> &mut SimulationDevice {
+        &mut self.device
+    }
+
+    /// A single iteration of the run loop, returns whether an event was pending
+    ///
+    /// Messages on the transport are handled before packets on the device. Housekeeping runs when it is due according
+    /// to the mock time, just like in the real run loop. Once the node is stopped, it shuts down and returns false.
+    pub fn run_step(&mut self) -> bool {
+        let mut buffer = self.buffers.acquire();
+        let pending = if self.socket.has_pending() {
+            self.handle_socket_event(&mut buffer);
+            true
+        } else if self.device.has_pending() {
+            self.handle_device_event(&mut buffer);
+            true
+        } else {
+            false
+        };
+        self.send_deferred(&mut buffer);
+        if self.config.reorder_buffer {
+            self.flush_reorder_buffers(&mut buffer);
+        }
+        if self.config.init_retries > 0 {
+            self.retransmit_inits();
+        }
+        if self.next_housekeep < MockTimeSource::now() {
+            self.next_housekeep = MockTimeSource::now() + 1;
+            if self.stop_flag.load(Ordering::Relaxed) {
+                self.shutdown(&mut buffer);
+                return false
+            }
+            assert!(self.housekeep().is_ok());
+        }
+        pending
+    }
+}
 
+#[cfg(test)]
+impl<D: Device, P: Protocol, S: Socket> GenericCloud<D, P, S, MockTimeSource> {
     pub fn trigger_socket_event(&mut self) {
-        let mut buffer = MsgBuffer::new(SPACE_BEFORE);
+        let mut buffer = self.buffers.acquire();
         self.handle_socket_event(&mut buffer);
     }
 
     pub fn trigger_device_event(&mut self) {
-        let mut buffer = MsgBuffer::new(SPACE_BEFORE);
+        let mut buffer = self.buffers.acquire();
         self.handle_device_event(&mut buffer);
     }
 
+    pub fn trigger_shutdown(&mut self) {
+        let mut buffer = self.buffers.acquire();
+        self.shutdown(&mut buffer)
+    }
+
     pub fn trigger_housekeep(&mut self) {
         assert!(self.housekeep().is_ok())
     }
@@ -1018,6 +3190,18 @@ impl<P: Protocol> GenericCloud<MockDevice, P, MockSocket, MockTimeSource> {
         self.peers.contains_key(addr)
     }
 
+    pub fn traffic(&self) -> &TrafficStats {
+        &self.traffic
+    }
+
+    pub fn peer_rtt(&self, addr: &SocketAddr) -> Option<u64> {
+        self.peers.get(addr).and_then(|peer| peer.rtt)
+    }
+
+    pub fn peer_features(&self, addr: &SocketAddr) -> Option<Features> {
+        self.peers.get(addr).map(|peer| peer.features)
+    }
+
     pub fn own_addresses(&self) -> &[SocketAddr] {
         &self.own_addresses
     }
@@ -1026,3 +3210,69 @@ impl<P: Protocol> GenericCloud<MockDevice, P, MockSocket, MockTimeSource> {
         self.socket.address().unwrap().port() as usize
     }
 }
+
+#[test]
+fn builder_requires_socket_and_device() {
+    type TestBuilder = CloudBuilder<MockDevice, crate::payload::Frame, MockSocket, MockTimeSource>;
+    let builder = TestBuilder::new().config(Config::default());
+    assert!(matches!(builder.build(), Err(Error::InvalidConfig("The node needs a socket"))));
+    let builder = TestBuilder::new().config(Config::default()).socket(MockSocket::new("[::]:1".parse().unwrap()));
+    assert!(matches!(builder.build(), Err(Error::InvalidConfig("The node needs a device"))));
+    let builder = TestBuilder::new().device(MockDevice::new());
+    assert!(matches!(builder.build(), Err(Error::InvalidConfig("The node needs a config"))));
+}
+
+#[test]
+fn address_change_updates_own_addresses() {
+    use crate::netlink::{netlink_message, RTM_NEWADDR, RTM_NEWLINK};
+    use std::os::unix::{
+        io::{FromRawFd, IntoRawFd},
+        net::UnixDatagram,
+    };
+    MockTimeSource::set_time(0);
+    let mut config = Config::default();
+    config.crypto.password = Some("test123".to_string());
+    config.crypto.argon2_memory_kib = Some(64);
+    config.crypto.argon2_iterations = Some(1);
+    let mut cloud = CloudBuilder::<MockDevice, crate::payload::Frame, MockSocket, MockTimeSource>::new()
+        .config(config)
+        .socket(MockSocket::new("1.2.3.4:3210".parse().unwrap()))
+        .device(MockDevice::new())
+        .build()
+        .unwrap();
+    // Mock sockets do not open a netlink socket
+    assert!(cloud.address_watcher.is_none());
+    let (sender, receiver) = UnixDatagram::pair().unwrap();
+    receiver.set_nonblocking(true).unwrap();
+    cloud.address_watcher = Some(RtnetlinkWatcher::from_socket(unsafe { File::from_raw_fd(receiver.into_raw_fd()) }));
+    let new_addr = mapped_addr("5.6.7.8:3210".parse().unwrap());
+    cloud.socket().set_address(new_addr);
+    // Other netlink messages do not refresh the addresses
+    sender.send(&netlink_message(RTM_NEWLINK, 8)).unwrap();
+    cloud.trigger_housekeep();
+    assert!(!cloud.own_addresses().contains(&new_addr));
+    sender.send(&netlink_message(RTM_NEWADDR, 8)).unwrap();
+    cloud.trigger_housekeep();
+    assert!(cloud.own_addresses().contains(&new_addr));
+}
+
+#[test]
+fn reconnect_jitter() {
+    let mut entry = ReconnectEntry {
+        address: None,
+        resolved: smallvec![],
+        tries: 0,
+        timeout: 1,
+        next: 0,
+        last_attempt: None,
+        final_timeout: None,
+    };
+    for now in 0..100 {
+        entry.schedule_next(now, true);
+        assert!(entry.next >= now && entry.next <= now + Time::from(entry.timeout));
+    }
+    // The back-off still follows the doubling schedule
+    assert_eq!(entry.timeout, 512);
+    entry.schedule_next(1000, false);
+    assert_eq!(entry.next, 1512);
+}
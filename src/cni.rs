@@ -0,0 +1,613 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, UdpSocket},
+    os::unix::io::AsRawFd,
+    path::Path,
+    process, thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    device::{get_device_hwaddr, is_device_up, Type},
+    error::Error,
+    manager::TapCloud,
+    net::open_socket,
+    payload::Frame,
+};
+
+/// The CNI version of the results of this plugin
+pub const CNI_VERSION: &str = "0.4.0";
+pub const SUPPORTED_VERSIONS: &[&str] = &["0.3.0", "0.3.1", "0.4.0"];
+
+/// Directory of the IPAM files of networks that do not configure one
+pub const DEFAULT_IPAM_DIR: &str = "/var/lib/vpncloud/cni";
+
+const ERR_INCOMPATIBLE_VERSION: u32 = 1;
+const ERR_INVALID_ENV: u32 = 4;
+const ERR_DECODE: u32 = 6;
+const ERR_PLUGIN: u32 = 100;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CniError {
+    cni_version: &'static str,
+    code: u32,
+    msg: String,
+}
+
+impl CniError {
+    fn new(code: u32, msg: String) -> Self {
+        Self { cni_version: CNI_VERSION, code, msg }
+    }
+}
+
+impl From<Error> for CniError {
+    fn from(err: Error) -> Self {
+        Self::new(ERR_PLUGIN, err.to_string())
+    }
+}
+
+/// Parameters that the container runtime passes via environment variables
+#[derive(Debug, Clone, PartialEq)]
+pub struct CniArgs {
+    pub command: String,
+    pub container_id: String,
+    pub netns: String,
+    pub ifname: String,
+}
+
+impl CniArgs {
+    fn from_env() -> Result<Self, CniError> {
+        let var = |name: &str| {
+            env::var(name).map_err(|_| CniError::new(ERR_INVALID_ENV, format!("Missing environment variable {}", name)))
+        };
+        let command = var("CNI_COMMAND")?;
+        if command == "VERSION" {
+            return Ok(Self { command, container_id: String::new(), netns: String::new(), ifname: String::new() })
+        }
+        Ok(Self {
+            command,
+            container_id: var("CNI_CONTAINERID")?,
+            // The network namespace might already be gone when an interface is deleted
+            netns: env::var("CNI_NETNS").unwrap_or_default(),
+            ifname: var("CNI_IFNAME")?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CniRoute {
+    pub dst: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gw: Option<Ipv4Addr>,
+}
+
+/// Address pool of the host-local IPAM model
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IpamConf {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub subnet: String,
+    pub range_start: Option<Ipv4Addr>,
+    pub range_end: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub routes: Vec<CniRoute>,
+}
+
+impl IpamConf {
+    fn subnet(&self) -> Result<(Ipv4Addr, u8), Error> {
+        let (ip, netmask) =
+            crate::parse_ip_netmask(&self.subnet).map_err(|_| Error::InvalidConfig("Invalid IPAM subnet"))?;
+        Ok((Ipv4Addr::from(u32::from(ip) & u32::from(netmask)), u32::from(netmask).count_ones() as u8))
+    }
+
+    /// Returns the first and the last address that can be allocated
+    fn range(&self) -> Result<(u32, u32), Error> {
+        let (network, prefix_len) = self.subnet()?;
+        let network = u32::from(network);
+        let broadcast = network | (u32::MAX >> prefix_len);
+        let start = self.range_start.map(u32::from).unwrap_or(network + 1);
+        let end = self.range_end.map(u32::from).unwrap_or(broadcast - 1);
+        if start <= network || end >= broadcast || start > end {
+            return Err(Error::InvalidConfig("Invalid IPAM range"))
+        }
+        Ok((start, end))
+    }
+}
+
+/// Network configuration that the container runtime passes via stdin
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NetConf {
+    pub cni_version: String,
+    pub name: String,
+    /// Path of the VpnCloud config file of the network
+    pub config: String,
+    pub ipam: IpamConf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CniInterface {
+    pub name: String,
+    pub mac: String,
+    pub sandbox: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CniIp {
+    pub version: String,
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<Ipv4Addr>,
+    pub interface: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CniResult {
+    pub cni_version: String,
+    pub interfaces: Vec<CniInterface>,
+    pub ips: Vec<CniIp>,
+    pub routes: Vec<CniRoute>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Allocation {
+    pub container_id: String,
+    pub ifname: String,
+}
+
+impl Allocation {
+    fn owned_by(&self, container_id: &str, ifname: &str) -> bool {
+        self.container_id == container_id && self.ifname == ifname
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct IpamState {
+    allocations: BTreeMap<Ipv4Addr, Allocation>,
+}
+
+/// Address allocations of the host-local IPAM model, stored as JSON
+///
+/// The file is locked while this struct exists, so concurrent plugin calls do not allocate the same address.
+pub struct HostLocalIpam {
+    path: String,
+    _lock: File,
+}
+
+impl HostLocalIpam {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::FileIo { message: "Failed to create IPAM directory", source: e })?;
+        }
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(format!("{}.lock", path))
+            .map_err(|e| Error::FileIo { message: "Failed to open IPAM lock", source: e })?;
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(Error::FileIo { message: "Failed to lock IPAM file", source: io::Error::last_os_error() })
+        }
+        Ok(Self { path: path.to_string(), _lock: lock })
+    }
+
+    fn read(&self) -> Result<IpamState, Error> {
+        if !Path::new(&self.path).exists() {
+            return Ok(IpamState::default())
+        }
+        let data =
+            fs::read(&self.path).map_err(|e| Error::FileIo { message: "Failed to read IPAM file", source: e })?;
+        serde_json::from_slice(&data).map_err(|_| Error::Parse("Failed to parse IPAM file"))
+    }
+
+    fn write(&self, state: &IpamState) -> Result<(), Error> {
+        let tmp_path = format!("{}.tmp", self.path);
+        let data = serde_json::to_vec_pretty(state).expect("Failed to serialize IPAM state");
+        File::create(&tmp_path)
+            .and_then(|mut file| file.write_all(&data).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .map_err(|e| Error::FileIo { message: "Failed to write IPAM file", source: e })
+    }
+
+    /// Returns the address of an interface, allocating a free one if it has none
+    pub fn allocate(&self, conf: &IpamConf, container_id: &str, ifname: &str) -> Result<Ipv4Addr, Error> {
+        let mut state = self.read()?;
+        if let Some((ip, _)) =
+            state.allocations.iter().find(|(_, allocation)| allocation.owned_by(container_id, ifname))
+        {
+            return Ok(*ip)
+        }
+        let (start, end) = conf.range()?;
+        for ip in (start..=end).map(Ipv4Addr::from) {
+            if conf.gateway == Some(ip) || state.allocations.contains_key(&ip) {
+                continue
+            }
+            info!("Allocating {} for {}/{}", ip, container_id, ifname);
+            state
+                .allocations
+                .insert(ip, Allocation { container_id: container_id.to_string(), ifname: ifname.to_string() });
+            self.write(&state)?;
+            return Ok(ip)
+        }
+        Err(Error::Cni("No free address left in IPAM range"))
+    }
+
+    pub fn get(&self, container_id: &str, ifname: &str) -> Result<Option<Ipv4Addr>, Error> {
+        Ok(self
+            .read()?
+            .allocations
+            .into_iter()
+            .find(|(_, allocation)| allocation.owned_by(container_id, ifname))
+            .map(|(ip, _)| ip))
+    }
+
+    /// Releases the address of an interface, returns the address if there was one
+    pub fn release(&self, container_id: &str, ifname: &str) -> Result<Option<Ipv4Addr>, Error> {
+        let mut state = self.read()?;
+        let ip = match state.allocations.iter().find(|(_, allocation)| allocation.owned_by(container_id, ifname)) {
+            Some((ip, _)) => *ip,
+            None => return Ok(None),
+        };
+        info!("Releasing {} of {}/{}", ip, container_id, ifname);
+        state.allocations.remove(&ip);
+        self.write(&state)?;
+        Ok(Some(ip))
+    }
+}
+
+/// Moves the current thread into a network namespace until it is dropped
+pub struct NetNs {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    original: File,
+}
+
+impl NetNs {
+    #[cfg(target_os = "linux")]
+    pub fn enter(path: &str) -> Result<Self, Error> {
+        let original = File::open("/proc/thread-self/ns/net")
+            .map_err(|e| Error::FileIo { message: "Failed to open network namespace", source: e })?;
+        let target =
+            File::open(path).map_err(|e| Error::FileIo { message: "Failed to open network namespace", source: e })?;
+        if unsafe { libc::setns(target.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            return Err(Error::FileIo {
+                message: "Failed to enter network namespace",
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(Self { original })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn enter(_path: &str) -> Result<Self, Error> {
+        Err(Error::InvalidConfig("Network namespaces only exist on Linux"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for NetNs {
+    fn drop(&mut self) {
+        if unsafe { libc::setns(self.original.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            fail!("Failed to leave network namespace: {}", io::Error::last_os_error());
+        }
+    }
+}
+
+fn format_mac(mac: [u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+fn process_running(pid: libc::pid_t) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+/// CNI plugin that connects containers to a VpnCloud network via a TAP device
+///
+/// Every interface is served by its own VpnCloud daemon that is started by `ADD` and stopped by `DEL`.
+pub struct CniPlugin {
+    args: CniArgs,
+    netconf: NetConf,
+    config: Config,
+}
+
+impl CniPlugin {
+    pub fn new(args: CniArgs, netconf: NetConf) -> Result<Self, Error> {
+        if netconf.ipam.type_ != "host-local" {
+            return Err(Error::InvalidConfig("Only host-local IPAM is supported"))
+        }
+        let mut config = Config::default();
+        config.merge_file(crate::read_config_file(&netconf.config));
+        config.device_type = Type::Tap;
+        config.device_name = args.ifname.clone();
+        if !crate::validate_config(&config) {
+            return Err(Error::InvalidConfig("Network config is invalid"))
+        }
+        Ok(Self { args, netconf, config })
+    }
+
+    fn ipam_file(&self) -> String {
+        match &self.config.cni_ipam_file {
+            Some(path) => path.clone(),
+            None => format!("{}/{}.json", DEFAULT_IPAM_DIR, self.netconf.name),
+        }
+    }
+
+    fn ipam(&self) -> Result<HostLocalIpam, Error> {
+        HostLocalIpam::open(&self.ipam_file())
+    }
+
+    /// The PID file of the daemon of the interface, next to the IPAM file
+    fn pid_file(&self) -> String {
+        let name = format!("{}-{}.pid", self.args.container_id, self.args.ifname);
+        Path::new(&self.ipam_file()).with_file_name(name).to_string_lossy().to_string()
+    }
+
+    fn daemon_pid(&self) -> Option<libc::pid_t> {
+        fs::read_to_string(self.pid_file()).ok().and_then(|pid| pid.trim().parse().ok())
+    }
+
+    /// Creates the interface in the container, the returned cloud needs to be run to connect it
+    pub fn add(&mut self) -> Result<(CniResult, TapCloud), Error> {
+        let (_, prefix_len) = self.netconf.ipam.subnet()?;
+        let ip = self.ipam()?.allocate(&self.netconf.ipam, &self.args.container_id, &self.args.ifname)?;
+        self.config.ip = Some(format!("{}/{}", ip, prefix_len));
+        self.config.pid_file = Some(self.pid_file());
+        match self.create_interface() {
+            Ok((cloud, mac)) => Ok((self.result(ip, prefix_len, mac), cloud)),
+            Err(err) => {
+                self.ipam()?.release(&self.args.container_id, &self.args.ifname)?;
+                Err(err)
+            }
+        }
+    }
+
+    fn create_interface(&self) -> Result<(TapCloud, [u8; 6]), Error> {
+        // The socket stays in the host namespace to reach the peers
+        let socket =
+            open_socket::<UdpSocket>(&self.config.listen, self.config.address_family, self.config.receive_threads)
+                .map_err(|e| Error::SocketIo { message: "Failed to open socket", source: e })?;
+        let netns = NetNs::enter(&self.args.netns)?;
+        let device = crate::setup_device(&self.config);
+        let mac = get_device_hwaddr(&self.args.ifname)
+            .map_err(|e| Error::DeviceIo { message: "Failed to read MAC address", source: e })?;
+        for route in &self.netconf.ipam.routes {
+            self.add_route(route)?;
+        }
+        drop(netns);
+        Ok((crate::create_cloud_with_device::<Frame, _>(&self.config, socket, device), mac))
+    }
+
+    fn add_route(&self, route: &CniRoute) -> Result<(), Error> {
+        let mut cmd = process::Command::new("ip");
+        cmd.arg("route").arg("add").arg(&route.dst);
+        if let Some(gw) = route.gw.or(self.netconf.ipam.gateway) {
+            cmd.arg("via").arg(gw.to_string());
+        }
+        cmd.arg("dev").arg(&self.args.ifname);
+        debug!("Adding route: {:?}", cmd);
+        match cmd.status() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(_) => Err(Error::Device("Failed to add route")),
+            Err(e) => Err(Error::DeviceIo { message: "Failed to add route", source: e }),
+        }
+    }
+
+    fn result(&self, ip: Ipv4Addr, prefix_len: u8, mac: [u8; 6]) -> CniResult {
+        CniResult {
+            cni_version: self.netconf.cni_version.clone(),
+            interfaces: vec![CniInterface {
+                name: self.args.ifname.clone(),
+                mac: format_mac(mac),
+                sandbox: self.args.netns.clone(),
+            }],
+            ips: vec![CniIp {
+                version: "4".to_string(),
+                address: format!("{}/{}", ip, prefix_len),
+                gateway: self.netconf.ipam.gateway,
+                interface: 0,
+            }],
+            routes: self.netconf.ipam.routes.clone(),
+        }
+    }
+
+    /// Checks that the interface has an address, is up and that its daemon is running
+    pub fn check(&self) -> Result<(), Error> {
+        if self.ipam()?.get(&self.args.container_id, &self.args.ifname)?.is_none() {
+            return Err(Error::Cni("Interface has no address allocated"))
+        }
+        match self.daemon_pid() {
+            Some(pid) if process_running(pid) => (),
+            _ => return Err(Error::Cni("Daemon of the interface is not running")),
+        }
+        let _netns = NetNs::enter(&self.args.netns)?;
+        match is_device_up(&self.args.ifname) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::Device("Interface is down")),
+            Err(e) => Err(Error::DeviceIo { message: "Failed to read interface state", source: e }),
+        }
+    }
+
+    /// Stops the daemon of the interface, which removes the interface, and releases its address
+    pub fn del(&self) -> Result<(), Error> {
+        if let Some(pid) = self.daemon_pid() {
+            info!("Stopping daemon {} of {}/{}", pid, self.args.container_id, self.args.ifname);
+            unsafe { libc::kill(pid, libc::SIGTERM) };
+            for _ in 0..50 {
+                if !process_running(pid) {
+                    break
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            if process_running(pid) {
+                return Err(Error::Cni("Daemon of the interface did not stop"))
+            }
+            fs::remove_file(self.pid_file())
+                .map_err(|e| Error::FileIo { message: "Failed to remove PID file", source: e })?;
+        }
+        self.ipam()?.release(&self.args.container_id, &self.args.ifname)?;
+        Ok(())
+    }
+}
+
+fn execute(args: CniArgs, input: &str) -> Result<(), CniError> {
+    if args.command == "VERSION" {
+        let version = serde_json::json!({ "cniVersion": CNI_VERSION, "supportedVersions": SUPPORTED_VERSIONS });
+        println!("{}", version);
+        return Ok(())
+    }
+    let netconf: NetConf = serde_json::from_str(input)
+        .map_err(|e| CniError::new(ERR_DECODE, format!("Failed to parse network configuration: {}", e)))?;
+    if !SUPPORTED_VERSIONS.contains(&(&netconf.cni_version as &str)) {
+        return Err(CniError::new(ERR_INCOMPATIBLE_VERSION, format!("Unsupported CNI version {}", netconf.cni_version)))
+    }
+    let mut plugin = CniPlugin::new(args, netconf)?;
+    match &plugin.args.command as &str {
+        "ADD" => {
+            let (result, cloud) = plugin.add()?;
+            println!("{}", serde_json::to_string(&result).expect("Failed to serialize result"));
+            io::stdout().flush().expect("Failed to write result");
+            plugin.config.daemonize = true;
+            crate::drop_privileges(&plugin.config);
+            crate::run_cloud(cloud, &plugin.config);
+            Ok(())
+        }
+        "CHECK" => Ok(plugin.check()?),
+        "DEL" => Ok(plugin.del()?),
+        command => Err(CniError::new(ERR_INVALID_ENV, format!("Unknown command {}", command))),
+    }
+}
+
+/// Runs the CNI command given by the environment, errors are reported on stdout as the specification requires
+pub fn run() {
+    let mut input = String::new();
+    let res = CniArgs::from_env().and_then(|args| {
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|e| CniError::new(ERR_DECODE, format!("Failed to read network configuration: {}", e)))?;
+        execute(args, &input)
+    });
+    if let Err(err) = res {
+        println!("{}", serde_json::to_string(&err).expect("Failed to serialize error"));
+        process::exit(1)
+    }
+}
+
+#[test]
+fn cni_host_local_ipam() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("ipam.json");
+    let path = path.to_str().unwrap();
+    let conf: IpamConf =
+        serde_json::from_str(r#"{"type": "host-local", "subnet": "10.0.0.0/30", "gateway": "10.0.0.1"}"#).unwrap();
+    let ipam = HostLocalIpam::open(path).unwrap();
+    assert_eq!(ipam.allocate(&conf, "c1", "eth0").unwrap(), Ipv4Addr::new(10, 0, 0, 2));
+    assert_eq!(ipam.allocate(&conf, "c1", "eth0").unwrap(), Ipv4Addr::new(10, 0, 0, 2));
+    // The range is exhausted, the gateway and the broadcast address are never allocated
+    assert!(ipam.allocate(&conf, "c2", "eth0").is_err());
+    assert_eq!(ipam.release("c2", "eth0").unwrap(), None);
+    drop(ipam);
+    let ipam = HostLocalIpam::open(path).unwrap();
+    assert_eq!(ipam.get("c1", "eth0").unwrap(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+    assert_eq!(ipam.release("c1", "eth0").unwrap(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+    assert_eq!(ipam.allocate(&conf, "c2", "eth0").unwrap(), Ipv4Addr::new(10, 0, 0, 2));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn cni_add_del() {
+    use crate::device::TunTapDevice;
+    use std::sync::mpsc;
+    // The container is simulated by a thread in its own network namespace
+    let (ns_tx, ns_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let container = thread::spawn(move || {
+        if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 || TunTapDevice::new("cnitest", Type::Tap, None).is_err() {
+            ns_tx.send(None).unwrap();
+            return
+        }
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+        ns_tx.send(Some(format!("/proc/{}/task/{}/ns/net", process::id(), tid))).unwrap();
+        done_rx.recv().ok();
+    });
+    let netns = match ns_rx.recv().unwrap() {
+        Some(netns) => netns,
+        None => {
+            warn!("Skipping test as network namespaces or TAP devices can not be created");
+            return
+        }
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    let config = format!("{}/vpn.net", dir);
+    fs::write(
+        &config,
+        format!(
+            "listen: 127.0.0.1:0\nport-forwarding: false\ncrypto:\n  password: test123\n  argon2-memory-kib: 64\n  \
+             argon2-iterations: 1\ncni-ipam-file: {}/ipam.json\n",
+            dir
+        ),
+    )
+    .unwrap();
+    let netconf: NetConf = serde_json::from_value(serde_json::json!({
+        "cniVersion": "0.4.0",
+        "name": "vpn",
+        "type": "vpncloud-cni",
+        "config": config,
+        "ipam": {
+            "type": "host-local",
+            "subnet": "10.99.0.0/24",
+            "gateway": "10.99.0.1",
+            "routes": [{"dst": "10.98.0.0/16"}]
+        }
+    }))
+    .unwrap();
+    let args = CniArgs {
+        command: "ADD".to_string(),
+        container_id: "c1".to_string(),
+        netns: netns.clone(),
+        ifname: "eth0".to_string(),
+    };
+    let mut plugin = CniPlugin::new(args, netconf).unwrap();
+    let (result, cloud) = plugin.add().unwrap();
+    let netns_guard = NetNs::enter(&netns).unwrap();
+    assert!(is_device_up("eth0").unwrap());
+    let routes = process::Command::new("ip").arg("route").arg("show").arg("10.98.0.0/16").output().unwrap().stdout;
+    assert!(String::from_utf8_lossy(&routes).contains("via 10.99.0.1 dev eth0"));
+    drop(netns_guard);
+    assert_eq!(result.interfaces[0].name, "eth0");
+    assert_eq!(result.interfaces[0].sandbox, netns);
+    assert_eq!(result.interfaces[0].mac.len(), 17);
+    assert_eq!(result.ips[0].address, "10.99.0.2/24");
+    assert_eq!(result.ips[0].gateway, Some(Ipv4Addr::new(10, 99, 0, 1)));
+    // The daemon is simulated by a process that is stopped by DEL
+    assert!(plugin.check().is_err());
+    let mut daemon = process::Command::new("sleep").arg("60").spawn().unwrap();
+    fs::write(plugin.pid_file(), daemon.id().to_string()).unwrap();
+    let daemon = thread::spawn(move || daemon.wait().unwrap());
+    plugin.check().unwrap();
+    plugin.del().unwrap();
+    assert!(!daemon.join().unwrap().success());
+    assert!(plugin.check().is_err());
+    assert_eq!(plugin.ipam().unwrap().get("c1", "eth0").unwrap(), None);
+    // The interface is removed with the daemon
+    drop(cloud);
+    let netns_guard = NetNs::enter(&netns).unwrap();
+    assert!(is_device_up("eth0").is_err());
+    drop(netns_guard);
+    done_tx.send(()).unwrap();
+    container.join().unwrap();
+}
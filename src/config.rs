@@ -2,11 +2,41 @@
 // Copyright (C) 2015-2021  Dennis Schwerdel
 // This software is licensed under GPL-3 or newer (see LICENSE.md)
 
-use super::{device::Type, types::Mode, util::run_cmd, util::Duration};
+use super::{
+    acl::AclAction,
+    capture::CaptureLayer,
+    crypto::{Crypto, PeerKeyEntry, EXTRA_LEN, TAG_LEN},
+    device::{parse_mac, Type},
+    stats::StatsFormat,
+    tofu::TofuMode,
+    turn::DEFAULT_TURN_PORT,
+    types::{AddressFamily, Mode, Range},
+    util::run_cmd,
+    util::Duration,
+};
 pub use crate::crypto::Config as CryptoConfig;
 
-use std::{cmp::max, collections::HashMap, ffi::OsStr, process, thread};
-use structopt::{clap::Shell, StructOpt};
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{InstanceType, Schema, SchemaObject},
+    JsonSchema,
+};
+use std::{
+    cmp::max,
+    collections::HashMap,
+    ffi::OsStr,
+    fs::File,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    thread,
+};
+use structopt::{
+    clap::{AppSettings, Shell},
+    StructOpt,
+};
+use thiserror::Error;
 
 pub const DEFAULT_PEER_TIMEOUT: u16 = 300;
 pub const DEFAULT_PORT: u16 = 3210;
@@ -17,36 +47,124 @@ pub struct Config {
     pub device_name: String,
     pub device_path: Option<String>,
     pub fix_rp_filter: bool,
+    pub mtu: Option<u16>,
+    pub device_persistent: bool,
+    pub tap_mac: Option<String>,
+    pub tap_mac_from_node_id: bool,
 
     pub ip: Option<String>,
     pub advertise_addresses: Vec<String>,
     pub ifup: Option<String>,
     pub ifdown: Option<String>,
+    pub sync_routes: bool,
+    pub local_tunnel_ip: Option<IpAddr>,
+    pub sync_routes_dry_run: bool,
+    pub node_id: Option<String>,
 
     pub crypto: CryptoConfig,
 
     pub listen: String,
     pub peers: Vec<String>,
+    pub bootstrap_dns: Vec<String>,
     pub peer_timeout: Duration,
     pub keepalive: Option<Duration>,
+    pub max_peers: Option<usize>,
+    pub max_reconnect_peers: usize,
+    pub reconnect_jitter: bool,
+    pub dpd_probe_interval: Duration,
+    pub dpd_retries: usize,
+    pub nat_keepalive: Duration,
+    pub gossip_fanout: usize,
+    pub gossip_interval: Option<Duration>,
+    pub gossip_full_interval_secs: Duration,
+    pub latency_routing: bool,
     pub beacon_store: Option<String>,
     pub beacon_load: Option<String>,
     pub beacon_interval: Duration,
     pub beacon_password: Option<String>,
+    pub beacon_encrypt: bool,
+    pub beacon_allow_plaintext: bool,
+    pub dns_beacon_domain: Option<String>,
+    pub dns_key_file: Option<String>,
     pub mode: Mode,
+    pub address_family: AddressFamily,
     pub switch_timeout: Duration,
+    pub igmp_timeout: Duration,
+    pub arp_proxy: bool,
+    pub arp_proxy_ttl_secs: Duration,
+    pub flap_window_secs: Duration,
+    pub max_flaps: usize,
+    pub max_table_entries: usize,
+    pub buffer_pool_size: usize,
+    pub receive_threads: usize,
+    pub socket_recv_buffer_bytes: Option<usize>,
+    pub socket_send_buffer_bytes: Option<usize>,
+    pub local_shortcut: bool,
+    pub pad_to: Option<u16>,
+    pub pad_amount: u16,
+    pub dscp: Option<u8>,
+    pub dscp_inherit: bool,
     pub claims: Vec<String>,
+    pub excluded_routes: Vec<String>,
+    pub policy_rules: Vec<PolicyRuleConfig>,
+    pub policy_tables: Vec<PolicyTableConfig>,
+    pub acl: Vec<AclRuleConfig>,
     pub auto_claim: bool,
     pub port_forwarding: bool,
+    pub pmtu_discovery: bool,
+    pub send_pacing_kbps: Option<u32>,
+    pub send_queue_max_bytes: usize,
+    pub reorder_buffer: bool,
+    pub reorder_timeout_ms: u32,
+    pub reorder_buffer_max_packets: usize,
+    pub fragment_timeout_ms: u32,
+    pub fragment_max_buffers: usize,
+    pub init_timeout_ms: u32,
+    pub init_retries: usize,
+    pub hole_punch: bool,
+    pub turn_servers: Vec<TurnServer>,
     pub daemonize: bool,
     pub pid_file: Option<String>,
+    pub table_persistence_path: Option<String>,
     pub stats_file: Option<String>,
+    pub audit_log: Option<String>,
+    pub audit_log_max_bytes: u64,
+    pub peer_event_webhook: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub tofu_store: Option<String>,
+    pub tofu_mode: TofuMode,
+    pub peer_state_file: Option<String>,
+    pub identity_key: Option<String>,
+    pub noise_protocol: bool,
+    pub noise_key: Option<String>,
+    pub vxlan_vni: Option<u32>,
+    pub require_encryption: bool,
+    pub plaintext_peers: Vec<SocketAddr>,
+    pub cni_ipam_file: Option<String>,
+    pub stats_format: StatsFormat,
+    pub traffic_stats_addresses: usize,
     pub statsd_server: Option<String>,
     pub statsd_prefix: Option<String>,
+    pub influx_addr: Option<SocketAddr>,
+    pub influx_measurement: String,
+    pub snmp_addr: Option<SocketAddr>,
+    pub snmp_community: String,
+    pub snmp_trap_receiver: Option<SocketAddr>,
+    pub capture_file: Option<String>,
+    pub capture_layer: CaptureLayer,
+    pub capture_max_bytes: u64,
+    pub capture_filter_peer: Option<SocketAddr>,
+    pub otel_endpoint: Option<String>,
+    pub api_addr: Option<String>,
+    pub api_token: Option<String>,
+    pub api_tls_cert: Option<String>,
+    pub api_tls_key: Option<String>,
+    pub grpc_addr: Option<String>,
     pub user: Option<String>,
     pub group: Option<String>,
     pub hook: Option<String>,
     pub hooks: HashMap<String, String>,
+    pub local_tags: HashMap<String, String>,
 }
 
 impl Default for Config {
@@ -56,33 +174,121 @@ impl Default for Config {
             device_name: "vpncloud%d".to_string(),
             device_path: None,
             fix_rp_filter: false,
+            mtu: None,
+            device_persistent: false,
+            tap_mac: None,
+            tap_mac_from_node_id: false,
             ip: None,
             advertise_addresses: vec![],
             ifup: None,
             ifdown: None,
+            sync_routes: false,
+            local_tunnel_ip: None,
+            sync_routes_dry_run: false,
+            node_id: None,
             crypto: CryptoConfig::default(),
             listen: "3210".to_string(),
             peers: vec![],
+            bootstrap_dns: vec![],
             peer_timeout: DEFAULT_PEER_TIMEOUT as Duration,
             keepalive: None,
+            max_peers: None,
+            max_reconnect_peers: 64,
+            reconnect_jitter: true,
+            dpd_probe_interval: 30,
+            dpd_retries: 3,
+            nat_keepalive: 25,
+            gossip_fanout: 20,
+            gossip_interval: None,
+            gossip_full_interval_secs: 600,
+            latency_routing: false,
             beacon_store: None,
             beacon_load: None,
             beacon_interval: 3600,
             beacon_password: None,
+            beacon_encrypt: true,
+            beacon_allow_plaintext: false,
+            dns_beacon_domain: None,
+            dns_key_file: None,
             mode: Mode::Normal,
+            address_family: AddressFamily::DualStack,
             switch_timeout: 300,
+            igmp_timeout: 260,
+            arp_proxy: false,
+            arp_proxy_ttl_secs: 300,
+            flap_window_secs: 60,
+            max_flaps: 5,
+            max_table_entries: 1000,
+            buffer_pool_size: 256,
+            receive_threads: 1,
+            socket_recv_buffer_bytes: None,
+            socket_send_buffer_bytes: None,
+            local_shortcut: false,
+            pad_to: None,
+            pad_amount: 0,
+            dscp: None,
+            dscp_inherit: false,
             claims: vec![],
+            excluded_routes: vec![],
+            policy_rules: vec![],
+            policy_tables: vec![],
+            acl: vec![],
             auto_claim: true,
             port_forwarding: true,
+            pmtu_discovery: false,
+            send_pacing_kbps: None,
+            send_queue_max_bytes: 1024 * 1024,
+            reorder_buffer: false,
+            reorder_timeout_ms: 50,
+            reorder_buffer_max_packets: 32,
+            fragment_timeout_ms: 500,
+            fragment_max_buffers: 32,
+            init_timeout_ms: 500,
+            init_retries: 3,
+            hole_punch: true,
+            turn_servers: vec![],
             daemonize: false,
             pid_file: None,
+            table_persistence_path: None,
             stats_file: None,
+            audit_log: None,
+            audit_log_max_bytes: 10 * 1024 * 1024,
+            peer_event_webhook: None,
+            webhook_secret: None,
+            tofu_store: None,
+            tofu_mode: TofuMode::AllowFirst,
+            peer_state_file: None,
+            identity_key: None,
+            noise_protocol: false,
+            noise_key: None,
+            vxlan_vni: None,
+            require_encryption: true,
+            plaintext_peers: vec![],
+            cni_ipam_file: None,
+            stats_format: StatsFormat::Text,
+            traffic_stats_addresses: 1000,
             statsd_server: None,
             statsd_prefix: None,
+            influx_addr: None,
+            influx_measurement: "vpncloud".to_string(),
+            snmp_addr: None,
+            snmp_community: "public".to_string(),
+            snmp_trap_receiver: None,
+            capture_file: None,
+            capture_layer: CaptureLayer::Network,
+            capture_max_bytes: 100 * 1024 * 1024,
+            capture_filter_peer: None,
+            otel_endpoint: None,
+            api_addr: None,
+            api_token: None,
+            api_tls_cert: None,
+            api_tls_key: None,
+            grpc_addr: None,
             user: None,
             group: None,
             hook: None,
             hooks: HashMap::new(),
+            local_tags: HashMap::new(),
         }
     }
 }
@@ -103,6 +309,18 @@ impl Config {
             if let Some(val) = device.fix_rp_filter {
                 self.fix_rp_filter = val;
             }
+            if let Some(val) = device.mtu {
+                self.mtu = Some(val);
+            }
+            if let Some(val) = device.persistent {
+                self.device_persistent = val;
+            }
+            if let Some(val) = device.mac {
+                self.tap_mac = Some(val);
+            }
+            if let Some(val) = device.mac_from_node_id {
+                self.tap_mac_from_node_id = val;
+            }
         }
         if let Some(val) = file.ip {
             self.ip = Some(val);
@@ -116,18 +334,63 @@ impl Config {
         if let Some(val) = file.ifdown {
             self.ifdown = Some(val);
         }
+        if let Some(val) = file.sync_routes {
+            self.sync_routes = val;
+        }
+        if let Some(val) = file.local_tunnel_ip {
+            self.local_tunnel_ip = Some(val);
+        }
+        if let Some(val) = file.sync_routes_dry_run {
+            self.sync_routes_dry_run = val;
+        }
+        if let Some(val) = file.node_id {
+            self.node_id = Some(val);
+        }
         if let Some(val) = file.listen {
             self.listen = val;
         }
         if let Some(mut val) = file.peers {
             self.peers.append(&mut val);
         }
+        if let Some(mut val) = file.bootstrap_dns {
+            self.bootstrap_dns.append(&mut val);
+        }
         if let Some(val) = file.peer_timeout {
             self.peer_timeout = val;
         }
         if let Some(val) = file.keepalive {
             self.keepalive = Some(val);
         }
+        if let Some(val) = file.max_peers {
+            self.max_peers = Some(val);
+        }
+        if let Some(val) = file.max_reconnect_peers {
+            self.max_reconnect_peers = val;
+        }
+        if let Some(val) = file.reconnect_jitter {
+            self.reconnect_jitter = val;
+        }
+        if let Some(val) = file.dpd_probe_interval {
+            self.dpd_probe_interval = val;
+        }
+        if let Some(val) = file.dpd_retries {
+            self.dpd_retries = val;
+        }
+        if let Some(val) = file.nat_keepalive {
+            self.nat_keepalive = val;
+        }
+        if let Some(val) = file.gossip_fanout {
+            self.gossip_fanout = val;
+        }
+        if let Some(val) = file.gossip_interval {
+            self.gossip_interval = Some(val);
+        }
+        if let Some(val) = file.gossip_full_interval_secs {
+            self.gossip_full_interval_secs = val;
+        }
+        if let Some(val) = file.latency_routing {
+            self.latency_routing = val;
+        }
         if let Some(beacon) = file.beacon {
             if let Some(val) = beacon.store {
                 self.beacon_store = Some(val);
@@ -141,28 +404,187 @@ impl Config {
             if let Some(val) = beacon.password {
                 self.beacon_password = Some(val);
             }
+            if let Some(val) = beacon.encrypt {
+                self.beacon_encrypt = val;
+            }
+            if let Some(val) = beacon.allow_plaintext {
+                self.beacon_allow_plaintext = val;
+            }
+            if let Some(val) = beacon.dns_domain {
+                self.dns_beacon_domain = Some(val);
+            }
+            if let Some(val) = beacon.dns_key_file {
+                self.dns_key_file = Some(val);
+            }
         }
         if let Some(val) = file.mode {
             self.mode = val;
         }
+        if let Some(val) = file.address_family {
+            self.address_family = val;
+        }
         if let Some(val) = file.switch_timeout {
             self.switch_timeout = val;
         }
+        if let Some(val) = file.igmp_timeout {
+            self.igmp_timeout = val;
+        }
+        if let Some(val) = file.arp_proxy {
+            self.arp_proxy = val;
+        }
+        if let Some(val) = file.arp_proxy_ttl_secs {
+            self.arp_proxy_ttl_secs = val;
+        }
+        if let Some(val) = file.flap_window_secs {
+            self.flap_window_secs = val;
+        }
+        if let Some(val) = file.max_flaps {
+            self.max_flaps = val;
+        }
+        if let Some(val) = file.max_table_entries {
+            self.max_table_entries = val;
+        }
+        if let Some(val) = file.buffer_pool_size {
+            self.buffer_pool_size = val;
+        }
+        if let Some(val) = file.receive_threads {
+            self.receive_threads = val;
+        }
+        if let Some(val) = file.socket_recv_buffer_bytes {
+            self.socket_recv_buffer_bytes = Some(val);
+        }
+        if let Some(val) = file.socket_send_buffer_bytes {
+            self.socket_send_buffer_bytes = Some(val);
+        }
+        if let Some(val) = file.local_shortcut {
+            self.local_shortcut = val;
+        }
+        if let Some(val) = file.pad_to {
+            self.pad_to = Some(val);
+        }
+        if let Some(val) = file.pad_amount {
+            self.pad_amount = val;
+        }
+        if let Some(val) = file.dscp {
+            self.dscp = Some(val);
+        }
+        if let Some(val) = file.dscp_inherit {
+            self.dscp_inherit = val;
+        }
         if let Some(mut val) = file.claims {
             self.claims.append(&mut val);
         }
+        if let Some(mut val) = file.excluded_routes {
+            self.excluded_routes.append(&mut val);
+        }
+        if let Some(mut val) = file.policy_rules {
+            self.policy_rules.append(&mut val);
+        }
+        if let Some(mut val) = file.policy_tables {
+            self.policy_tables.append(&mut val);
+        }
+        if let Some(mut val) = file.acl {
+            self.acl.append(&mut val);
+        }
         if let Some(val) = file.auto_claim {
             self.auto_claim = val;
         }
         if let Some(val) = file.port_forwarding {
             self.port_forwarding = val;
         }
+        if let Some(val) = file.pmtu_discovery {
+            self.pmtu_discovery = val;
+        }
+        if let Some(val) = file.send_pacing_kbps {
+            self.send_pacing_kbps = Some(val);
+        }
+        if let Some(val) = file.send_queue_max_bytes {
+            self.send_queue_max_bytes = val;
+        }
+        if let Some(val) = file.reorder_buffer {
+            self.reorder_buffer = val;
+        }
+        if let Some(val) = file.reorder_timeout_ms {
+            self.reorder_timeout_ms = val;
+        }
+        if let Some(val) = file.reorder_buffer_max_packets {
+            self.reorder_buffer_max_packets = val;
+        }
+        if let Some(val) = file.fragment_timeout_ms {
+            self.fragment_timeout_ms = val;
+        }
+        if let Some(val) = file.fragment_max_buffers {
+            self.fragment_max_buffers = val;
+        }
+        if let Some(val) = file.init_timeout_ms {
+            self.init_timeout_ms = val;
+        }
+        if let Some(val) = file.init_retries {
+            self.init_retries = val;
+        }
+        if let Some(val) = file.hole_punch {
+            self.hole_punch = val;
+        }
+        if let Some(mut val) = file.turn_servers {
+            self.turn_servers.append(&mut val);
+        }
         if let Some(val) = file.pid_file {
             self.pid_file = Some(val);
         }
+        if let Some(val) = file.table_persistence_path {
+            self.table_persistence_path = Some(val);
+        }
         if let Some(val) = file.stats_file {
             self.stats_file = Some(val);
         }
+        if let Some(val) = file.audit_log {
+            self.audit_log = Some(val);
+        }
+        if let Some(val) = file.audit_log_max_bytes {
+            self.audit_log_max_bytes = val;
+        }
+        if let Some(val) = file.peer_event_webhook {
+            self.peer_event_webhook = Some(val);
+        }
+        if let Some(val) = file.webhook_secret {
+            self.webhook_secret = Some(val);
+        }
+        if let Some(val) = file.tofu_store {
+            self.tofu_store = Some(val);
+        }
+        if let Some(val) = file.tofu_mode {
+            self.tofu_mode = val;
+        }
+        if let Some(val) = file.peer_state_file {
+            self.peer_state_file = Some(val);
+        }
+        if let Some(val) = file.identity_key {
+            self.identity_key = Some(val);
+        }
+        if let Some(val) = file.noise_protocol {
+            self.noise_protocol = val;
+        }
+        if let Some(val) = file.noise_key {
+            self.noise_key = Some(val);
+        }
+        if let Some(val) = file.vxlan_vni {
+            self.vxlan_vni = Some(val);
+        }
+        if let Some(val) = file.require_encryption {
+            self.require_encryption = val;
+        }
+        if let Some(mut val) = file.plaintext_peers {
+            self.plaintext_peers.append(&mut val);
+        }
+        if let Some(val) = file.cni_ipam_file {
+            self.cni_ipam_file = Some(val);
+        }
+        if let Some(val) = file.stats_format {
+            self.stats_format = val;
+        }
+        if let Some(val) = file.traffic_stats_addresses {
+            self.traffic_stats_addresses = val;
+        }
         if let Some(statsd) = file.statsd {
             if let Some(val) = statsd.server {
                 self.statsd_server = Some(val);
@@ -171,6 +593,59 @@ impl Config {
                 self.statsd_prefix = Some(val);
             }
         }
+        if let Some(influx) = file.influx {
+            if let Some(val) = influx.addr {
+                self.influx_addr = Some(val);
+            }
+            if let Some(val) = influx.measurement {
+                self.influx_measurement = val;
+            }
+        }
+        if let Some(snmp) = file.snmp {
+            if let Some(val) = snmp.addr {
+                self.snmp_addr = Some(val);
+            }
+            if let Some(val) = snmp.community {
+                self.snmp_community = val;
+            }
+            if let Some(val) = snmp.trap_receiver {
+                self.snmp_trap_receiver = Some(val);
+            }
+        }
+        if let Some(capture) = file.capture {
+            if let Some(val) = capture.file {
+                self.capture_file = Some(val);
+            }
+            if let Some(val) = capture.layer {
+                self.capture_layer = val;
+            }
+            if let Some(val) = capture.max_bytes {
+                self.capture_max_bytes = val;
+            }
+            if let Some(val) = capture.filter_peer {
+                self.capture_filter_peer = Some(val);
+            }
+        }
+        if let Some(val) = file.otel_endpoint {
+            self.otel_endpoint = Some(val);
+        }
+        if let Some(api) = file.api {
+            if let Some(val) = api.addr {
+                self.api_addr = Some(val);
+            }
+            if let Some(val) = api.token {
+                self.api_token = Some(val);
+            }
+            if let Some(val) = api.tls_cert {
+                self.api_tls_cert = Some(val);
+            }
+            if let Some(val) = api.tls_key {
+                self.api_tls_key = Some(val);
+            }
+            if let Some(val) = api.grpc_addr {
+                self.grpc_addr = Some(val);
+            }
+        }
         if let Some(val) = file.user {
             self.user = Some(val);
         }
@@ -187,15 +662,37 @@ impl Config {
             self.crypto.private_key = Some(val)
         }
         self.crypto.trusted_keys.append(&mut file.crypto.trusted_keys);
+        self.crypto.peer_keys.append(&mut file.crypto.peer_keys);
         if !file.crypto.algorithms.is_empty() {
             self.crypto.algorithms = file.crypto.algorithms.clone();
         }
+        if file.crypto.pq_kem {
+            self.crypto.pq_kem = true;
+        }
+        if let Some(val) = file.crypto.argon2_memory_kib {
+            self.crypto.argon2_memory_kib = Some(val)
+        }
+        if let Some(val) = file.crypto.argon2_iterations {
+            self.crypto.argon2_iterations = Some(val)
+        }
+        if let Some(val) = file.crypto.ca_cert {
+            self.crypto.ca_cert = Some(val)
+        }
+        if let Some(val) = file.crypto.node_cert {
+            self.crypto.node_cert = Some(val)
+        }
+        if let Some(val) = file.crypto.node_key {
+            self.crypto.node_key = Some(val)
+        }
         if let Some(val) = file.hook {
             self.hook = Some(val)
         }
         for (k, v) in file.hooks {
             self.hooks.insert(k, v);
         }
+        for (k, v) in file.local_tags {
+            self.local_tags.insert(k, v);
+        }
     }
 
     pub fn merge_args(&mut self, mut args: Args) {
@@ -211,6 +708,18 @@ impl Config {
         if args.fix_rp_filter {
             self.fix_rp_filter = true;
         }
+        if let Some(val) = args.mtu {
+            self.mtu = Some(val);
+        }
+        if args.device_persistent {
+            self.device_persistent = true;
+        }
+        if let Some(val) = args.tap_mac {
+            self.tap_mac = Some(val);
+        }
+        if args.tap_mac_from_node_id {
+            self.tap_mac_from_node_id = true;
+        }
         if let Some(val) = args.ip {
             self.ip = Some(val);
         }
@@ -221,16 +730,59 @@ impl Config {
         if let Some(val) = args.ifdown {
             self.ifdown = Some(val);
         }
+        if args.sync_routes {
+            self.sync_routes = true;
+        }
+        if let Some(val) = args.local_tunnel_ip {
+            self.local_tunnel_ip = Some(val);
+        }
+        if args.sync_routes_dry_run {
+            self.sync_routes_dry_run = true;
+        }
+        if let Some(val) = args.node_id {
+            self.node_id = Some(val);
+        }
         if let Some(val) = args.listen {
             self.listen = val;
         }
         self.peers.append(&mut args.peers);
+        self.bootstrap_dns.append(&mut args.bootstrap_dns);
         if let Some(val) = args.peer_timeout {
             self.peer_timeout = val;
         }
         if let Some(val) = args.keepalive {
             self.keepalive = Some(val);
         }
+        if let Some(val) = args.max_peers {
+            self.max_peers = Some(val);
+        }
+        if let Some(val) = args.max_reconnect_peers {
+            self.max_reconnect_peers = val;
+        }
+        if args.no_reconnect_jitter {
+            self.reconnect_jitter = false;
+        }
+        if let Some(val) = args.dpd_probe_interval {
+            self.dpd_probe_interval = val;
+        }
+        if let Some(val) = args.dpd_retries {
+            self.dpd_retries = val;
+        }
+        if let Some(val) = args.nat_keepalive {
+            self.nat_keepalive = val;
+        }
+        if let Some(val) = args.gossip_fanout {
+            self.gossip_fanout = val;
+        }
+        if let Some(val) = args.gossip_interval {
+            self.gossip_interval = Some(val);
+        }
+        if let Some(val) = args.gossip_full_interval_secs {
+            self.gossip_full_interval_secs = val;
+        }
+        if args.latency_routing {
+            self.latency_routing = true;
+        }
         if let Some(val) = args.beacon_store {
             self.beacon_store = Some(val);
         }
@@ -243,34 +795,226 @@ impl Config {
         if let Some(val) = args.beacon_password {
             self.beacon_password = Some(val);
         }
+        if args.no_beacon_encrypt {
+            self.beacon_encrypt = false;
+        }
+        if args.beacon_allow_plaintext {
+            self.beacon_allow_plaintext = true;
+        }
+        if let Some(val) = args.dns_beacon_domain {
+            self.dns_beacon_domain = Some(val);
+        }
+        if let Some(val) = args.dns_key_file {
+            self.dns_key_file = Some(val);
+        }
         if let Some(val) = args.mode {
             self.mode = val;
         }
+        if let Some(val) = args.address_family {
+            self.address_family = val;
+        }
         if let Some(val) = args.switch_timeout {
             self.switch_timeout = val;
         }
+        if let Some(val) = args.igmp_timeout {
+            self.igmp_timeout = val;
+        }
+        if args.arp_proxy {
+            self.arp_proxy = true;
+        }
+        if let Some(val) = args.arp_proxy_ttl_secs {
+            self.arp_proxy_ttl_secs = val;
+        }
+        if let Some(val) = args.flap_window_secs {
+            self.flap_window_secs = val;
+        }
+        if let Some(val) = args.max_flaps {
+            self.max_flaps = val;
+        }
+        if let Some(val) = args.max_table_entries {
+            self.max_table_entries = val;
+        }
+        if let Some(val) = args.buffer_pool_size {
+            self.buffer_pool_size = val;
+        }
+        if let Some(val) = args.receive_threads {
+            self.receive_threads = val;
+        }
+        if let Some(val) = args.socket_recv_buffer_bytes {
+            self.socket_recv_buffer_bytes = Some(val);
+        }
+        if let Some(val) = args.socket_send_buffer_bytes {
+            self.socket_send_buffer_bytes = Some(val);
+        }
+        if args.local_shortcut {
+            self.local_shortcut = true;
+        }
+        if let Some(val) = args.pad_to {
+            self.pad_to = Some(val);
+        }
+        if let Some(val) = args.pad_amount {
+            self.pad_amount = val;
+        }
+        if let Some(val) = args.dscp {
+            self.dscp = Some(val);
+        }
+        if args.dscp_inherit {
+            self.dscp_inherit = true;
+        }
         self.claims.append(&mut args.claims);
+        self.excluded_routes.append(&mut args.excluded_routes);
+        self.policy_rules.append(&mut args.policy_rules);
+        self.policy_tables.append(&mut args.policy_tables);
+        self.acl.append(&mut args.acl);
         if args.no_auto_claim {
             self.auto_claim = false;
         }
         if args.no_port_forwarding {
             self.port_forwarding = false;
         }
+        if args.pmtu_discovery {
+            self.pmtu_discovery = true;
+        }
+        if let Some(val) = args.send_pacing_kbps {
+            self.send_pacing_kbps = Some(val);
+        }
+        if let Some(val) = args.send_queue_max_bytes {
+            self.send_queue_max_bytes = val;
+        }
+        if args.reorder_buffer {
+            self.reorder_buffer = true;
+        }
+        if let Some(val) = args.reorder_timeout_ms {
+            self.reorder_timeout_ms = val;
+        }
+        if let Some(val) = args.reorder_buffer_max_packets {
+            self.reorder_buffer_max_packets = val;
+        }
+        if let Some(val) = args.fragment_timeout_ms {
+            self.fragment_timeout_ms = val;
+        }
+        if let Some(val) = args.fragment_max_buffers {
+            self.fragment_max_buffers = val;
+        }
+        if let Some(val) = args.init_timeout_ms {
+            self.init_timeout_ms = val;
+        }
+        if let Some(val) = args.init_retries {
+            self.init_retries = val;
+        }
+        if args.no_hole_punch {
+            self.hole_punch = false;
+        }
+        self.turn_servers.append(&mut args.turn_servers);
         if args.daemon {
             self.daemonize = true;
         }
         if let Some(val) = args.pid_file {
             self.pid_file = Some(val);
         }
+        if let Some(val) = args.table_persistence_path {
+            self.table_persistence_path = Some(val);
+        }
         if let Some(val) = args.stats_file {
             self.stats_file = Some(val);
         }
+        if let Some(val) = args.audit_log {
+            self.audit_log = Some(val);
+        }
+        if let Some(val) = args.audit_log_max_bytes {
+            self.audit_log_max_bytes = val;
+        }
+        if let Some(val) = args.peer_event_webhook {
+            self.peer_event_webhook = Some(val);
+        }
+        if let Some(val) = args.webhook_secret {
+            self.webhook_secret = Some(val);
+        }
+        if let Some(val) = args.tofu_store {
+            self.tofu_store = Some(val);
+        }
+        if let Some(val) = args.tofu_mode {
+            self.tofu_mode = val;
+        }
+        if let Some(val) = args.peer_state_file {
+            self.peer_state_file = Some(val);
+        }
+        if let Some(val) = args.identity_key {
+            self.identity_key = Some(val);
+        }
+        if args.noise_protocol {
+            self.noise_protocol = true;
+        }
+        if let Some(val) = args.noise_key {
+            self.noise_key = Some(val);
+        }
+        if let Some(val) = args.vxlan_vni {
+            self.vxlan_vni = Some(val);
+        }
+        if args.no_require_encryption {
+            self.require_encryption = false;
+        }
+        self.plaintext_peers.append(&mut args.plaintext_peers);
+        if let Some(val) = args.cni_ipam_file {
+            self.cni_ipam_file = Some(val);
+        }
+        if let Some(val) = args.stats_format {
+            self.stats_format = val;
+        }
+        if let Some(val) = args.traffic_stats_addresses {
+            self.traffic_stats_addresses = val;
+        }
         if let Some(val) = args.statsd_server {
             self.statsd_server = Some(val);
         }
         if let Some(val) = args.statsd_prefix {
             self.statsd_prefix = Some(val);
         }
+        if let Some(val) = args.influx_addr {
+            self.influx_addr = Some(val);
+        }
+        if let Some(val) = args.influx_measurement {
+            self.influx_measurement = val;
+        }
+        if let Some(val) = args.snmp_addr {
+            self.snmp_addr = Some(val);
+        }
+        if let Some(val) = args.snmp_community {
+            self.snmp_community = val;
+        }
+        if let Some(val) = args.snmp_trap_receiver {
+            self.snmp_trap_receiver = Some(val);
+        }
+        if let Some(val) = args.capture_file {
+            self.capture_file = Some(val);
+        }
+        if let Some(val) = args.capture_layer {
+            self.capture_layer = val;
+        }
+        if let Some(val) = args.capture_max_bytes {
+            self.capture_max_bytes = val;
+        }
+        if let Some(val) = args.capture_filter_peer {
+            self.capture_filter_peer = Some(val);
+        }
+        if let Some(val) = args.otel_endpoint {
+            self.otel_endpoint = Some(val);
+        }
+        if let Some(val) = args.api_addr {
+            self.api_addr = Some(val);
+        }
+        if let Some(val) = args.api_token {
+            self.api_token = Some(val);
+        }
+        if let Some(val) = args.api_tls_cert {
+            self.api_tls_cert = Some(val);
+        }
+        if let Some(val) = args.api_tls_key {
+            self.api_tls_key = Some(val);
+        }
+        if let Some(val) = args.grpc_addr {
+            self.grpc_addr = Some(val);
+        }
         if let Some(val) = args.user {
             self.user = Some(val);
         }
@@ -287,9 +1031,28 @@ impl Config {
             self.crypto.private_key = Some(val)
         }
         self.crypto.trusted_keys.append(&mut args.trusted_keys);
+        self.crypto.peer_keys.append(&mut args.peer_keys);
         if !args.algorithms.is_empty() {
             self.crypto.algorithms = args.algorithms.clone();
         }
+        if args.pq_kem {
+            self.crypto.pq_kem = true;
+        }
+        if let Some(val) = args.argon2_memory_kib {
+            self.crypto.argon2_memory_kib = Some(val)
+        }
+        if let Some(val) = args.argon2_iterations {
+            self.crypto.argon2_iterations = Some(val)
+        }
+        if let Some(val) = args.ca_cert {
+            self.crypto.ca_cert = Some(val)
+        }
+        if let Some(val) = args.node_cert {
+            self.crypto.node_cert = Some(val)
+        }
+        if let Some(val) = args.node_key {
+            self.crypto.node_key = Some(val)
+        }
         for s in args.hook {
             if s.contains(':') {
                 let pos = s.find(':').unwrap();
@@ -300,43 +1063,143 @@ impl Config {
                 self.hook = Some(s);
             }
         }
+        for s in args.tags {
+            let (key, val) = match s.find('=') {
+                Some(pos) => (&s[..pos], &s[pos + 1..]),
+                None => (&s as &str, ""),
+            };
+            self.local_tags.insert(key.to_string(), val.to_string());
+        }
     }
 
     pub fn into_config_file(self) -> ConfigFile {
         ConfigFile {
             auto_claim: Some(self.auto_claim),
             claims: Some(self.claims),
+            excluded_routes: Some(self.excluded_routes),
+            policy_rules: Some(self.policy_rules),
+            policy_tables: Some(self.policy_tables),
+            acl: Some(self.acl),
             beacon: Some(ConfigFileBeacon {
                 store: self.beacon_store,
                 load: self.beacon_load,
                 interval: Some(self.beacon_interval),
                 password: self.beacon_password,
+                encrypt: Some(self.beacon_encrypt),
+                allow_plaintext: Some(self.beacon_allow_plaintext),
+                dns_domain: self.dns_beacon_domain,
+                dns_key_file: self.dns_key_file,
             }),
             device: Some(ConfigFileDevice {
                 name: Some(self.device_name),
                 path: self.device_path,
                 type_: Some(self.device_type),
                 fix_rp_filter: Some(self.fix_rp_filter),
+                mtu: self.mtu,
+                persistent: Some(self.device_persistent),
+                mac: self.tap_mac,
+                mac_from_node_id: Some(self.tap_mac_from_node_id),
             }),
             crypto: self.crypto,
             group: self.group,
             user: self.user,
             ifup: self.ifup,
             ifdown: self.ifdown,
+            sync_routes: Some(self.sync_routes),
+            local_tunnel_ip: self.local_tunnel_ip,
+            sync_routes_dry_run: Some(self.sync_routes_dry_run),
+            node_id: self.node_id,
             ip: self.ip,
             advertise_addresses: Some(self.advertise_addresses),
             keepalive: self.keepalive,
+            max_peers: self.max_peers,
+            max_reconnect_peers: Some(self.max_reconnect_peers),
+            reconnect_jitter: Some(self.reconnect_jitter),
+            dpd_probe_interval: Some(self.dpd_probe_interval),
+            dpd_retries: Some(self.dpd_retries),
+            nat_keepalive: Some(self.nat_keepalive),
+            gossip_fanout: Some(self.gossip_fanout),
+            gossip_interval: self.gossip_interval,
+            gossip_full_interval_secs: Some(self.gossip_full_interval_secs),
+            latency_routing: Some(self.latency_routing),
             listen: Some(self.listen),
             mode: Some(self.mode),
+            address_family: Some(self.address_family),
             peer_timeout: Some(self.peer_timeout),
             peers: Some(self.peers),
+            bootstrap_dns: Some(self.bootstrap_dns),
             pid_file: self.pid_file,
+            table_persistence_path: self.table_persistence_path,
             port_forwarding: Some(self.port_forwarding),
+            pmtu_discovery: Some(self.pmtu_discovery),
+            send_pacing_kbps: self.send_pacing_kbps,
+            send_queue_max_bytes: Some(self.send_queue_max_bytes),
+            reorder_buffer: Some(self.reorder_buffer),
+            reorder_timeout_ms: Some(self.reorder_timeout_ms),
+            reorder_buffer_max_packets: Some(self.reorder_buffer_max_packets),
+            fragment_timeout_ms: Some(self.fragment_timeout_ms),
+            fragment_max_buffers: Some(self.fragment_max_buffers),
+            init_timeout_ms: Some(self.init_timeout_ms),
+            init_retries: Some(self.init_retries),
+            hole_punch: Some(self.hole_punch),
+            turn_servers: Some(self.turn_servers),
             stats_file: self.stats_file,
+            audit_log: self.audit_log,
+            audit_log_max_bytes: Some(self.audit_log_max_bytes),
+            peer_event_webhook: self.peer_event_webhook,
+            webhook_secret: self.webhook_secret,
+            tofu_store: self.tofu_store,
+            tofu_mode: Some(self.tofu_mode),
+            peer_state_file: self.peer_state_file,
+            identity_key: self.identity_key,
+            noise_protocol: Some(self.noise_protocol),
+            noise_key: self.noise_key,
+            vxlan_vni: self.vxlan_vni,
+            require_encryption: Some(self.require_encryption),
+            plaintext_peers: Some(self.plaintext_peers),
+            cni_ipam_file: self.cni_ipam_file,
+            stats_format: Some(self.stats_format),
+            traffic_stats_addresses: Some(self.traffic_stats_addresses),
             statsd: Some(ConfigFileStatsd { server: self.statsd_server, prefix: self.statsd_prefix }),
+            influx: Some(ConfigFileInflux { addr: self.influx_addr, measurement: Some(self.influx_measurement) }),
+            snmp: Some(ConfigFileSnmp {
+                addr: self.snmp_addr,
+                community: Some(self.snmp_community),
+                trap_receiver: self.snmp_trap_receiver,
+            }),
+            capture: Some(ConfigFileCapture {
+                file: self.capture_file,
+                layer: Some(self.capture_layer),
+                max_bytes: Some(self.capture_max_bytes),
+                filter_peer: self.capture_filter_peer,
+            }),
+            otel_endpoint: self.otel_endpoint,
+            api: Some(ConfigFileApi {
+                addr: self.api_addr,
+                token: self.api_token,
+                tls_cert: self.api_tls_cert,
+                tls_key: self.api_tls_key,
+                grpc_addr: self.grpc_addr,
+            }),
             switch_timeout: Some(self.switch_timeout),
+            igmp_timeout: Some(self.igmp_timeout),
+            arp_proxy: Some(self.arp_proxy),
+            arp_proxy_ttl_secs: Some(self.arp_proxy_ttl_secs),
+            flap_window_secs: Some(self.flap_window_secs),
+            max_flaps: Some(self.max_flaps),
+            max_table_entries: Some(self.max_table_entries),
+            buffer_pool_size: Some(self.buffer_pool_size),
+            receive_threads: Some(self.receive_threads),
+            socket_recv_buffer_bytes: self.socket_recv_buffer_bytes,
+            socket_send_buffer_bytes: self.socket_send_buffer_bytes,
+            local_shortcut: Some(self.local_shortcut),
+            pad_to: self.pad_to,
+            pad_amount: Some(self.pad_amount),
+            dscp: self.dscp,
+            dscp_inherit: Some(self.dscp_inherit),
             hook: self.hook,
             hooks: self.hooks,
+            local_tags: self.local_tags,
         }
     }
 
@@ -370,13 +1233,232 @@ impl Config {
             run_cmd(cmd)
         }
     }
+
+    /// Checks the config for invalid values and conflicting options
+    ///
+    /// All problems are returned, not only the first one, so that they can be fixed at once.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = vec![];
+        if self.crypto.password.is_none() && self.crypto.private_key.is_none() && self.crypto.node_key.is_none() {
+            errors.push(ConfigError::MissingCredentials);
+        }
+        if !self.listen.starts_with("ws://") {
+            if let Err(err) = validate_listen(&self.listen) {
+                errors.push(err);
+            }
+        }
+        for peer in &self.peers {
+            if let Err(err) = validate_peer(peer) {
+                errors.push(err);
+            }
+        }
+        for peer in &self.plaintext_peers {
+            if peer.port() == 0 {
+                errors.push(ConfigError::PortOutOfRange(peer.to_string()));
+            }
+        }
+        if let Some(ip) = &self.ip {
+            if !is_valid_ip_netmask(ip) {
+                errors.push(ConfigError::InvalidAddress(ip.clone()));
+            }
+        }
+        for range in self.claims.iter().chain(&self.excluded_routes) {
+            if Range::from_str(range).is_err() {
+                errors.push(ConfigError::InvalidRange(range.clone()));
+            }
+        }
+        if let Some(node_id) = &self.node_id {
+            if Crypto::parse_node_id(node_id).is_err() {
+                errors.push(ConfigError::InvalidNodeId(node_id.clone()));
+            }
+        }
+        if let Some(mac) = &self.tap_mac {
+            if parse_mac(mac).is_err() {
+                errors.push(ConfigError::InvalidMac(mac.clone()));
+            }
+            if self.tap_mac_from_node_id {
+                errors.push(ConfigError::ConflictingOptions("tap-mac", "tap-mac-from-node-id"));
+            }
+        }
+        if (self.tap_mac.is_some() || self.tap_mac_from_node_id) && self.device_type == Type::Tun {
+            errors.push(ConfigError::ConflictingOptions("tap-mac", "device type tun"));
+        }
+        if let Some(mtu) = self.mtu {
+            if mtu < MIN_MTU {
+                errors.push(ConfigError::MtuTooSmall(mtu));
+            }
+        }
+        if let Some(pad_to) = self.pad_to {
+            if pad_to > MAX_PAD_TO {
+                errors.push(ConfigError::PadToTooLarge(pad_to));
+            }
+        }
+        if let Some(dscp) = self.dscp {
+            if dscp > 63 {
+                errors.push(ConfigError::InvalidDscp(dscp));
+            }
+        }
+        if self.gossip_fanout < MIN_GOSSIP_FANOUT {
+            errors.push(ConfigError::GossipFanoutTooSmall(self.gossip_fanout));
+        }
+        let too_short = match self.keepalive {
+            Some(keepalive) => keepalive >= self.peer_timeout,
+            // The default keepalive is derived from the peer timeout
+            None => self.peer_timeout < 122,
+        };
+        if too_short {
+            errors.push(ConfigError::PeerTimeoutTooShort(self.peer_timeout));
+        }
+        if let Some(vni) = self.vxlan_vni {
+            if vni > 0xff_ffff {
+                errors.push(ConfigError::InvalidVni(vni));
+            }
+        }
+        if !self.plaintext_peers.is_empty() && self.require_encryption {
+            errors.push(ConfigError::ConflictingOptions("plaintext-peers", "require-encryption"));
+        }
+        if self.api_tls_cert.is_some() != self.api_tls_key.is_some() {
+            errors.push(ConfigError::ConflictingOptions("api-tls-cert", "api-tls-key"));
+        }
+        for path in self.api_tls_cert.iter().chain(&self.api_tls_key) {
+            if File::open(path).is_err() {
+                errors.push(ConfigError::UnreadableKeyFile(PathBuf::from(path)));
+            }
+        }
+        for path in self.identity_key.iter().chain(&self.noise_key) {
+            // Missing identity and noise keys are created on startup
+            if Path::new(path).exists() && File::open(path).is_err() {
+                errors.push(ConfigError::UnreadableKeyFile(PathBuf::from(path)));
+            }
+        }
+        if self.noise_protocol {
+            // Without a password, the pre-shared key of the handshake is public and anyone could connect
+            if self.crypto.password.is_none() {
+                errors.push(ConfigError::MissingOption("noise-protocol", "password"));
+            }
+            if self.noise_key.is_none() {
+                errors.push(ConfigError::MissingOption("noise-protocol", "noise-key"));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
-#[derive(StructOpt, Debug, Default)]
+/// Smallest MTU that every IPv4 host must accept
+const MIN_MTU: u16 = 576;
+/// Largest padded data message that fits into the UDP payload of a 1500 byte IPv4 packet once encrypted
+const MAX_PAD_TO: u16 = (1472 - 1 - EXTRA_LEN - TAG_LEN) as u16;
+/// Peer lists with fewer peers do not spread through the network
+const MIN_GOSSIP_FANOUT: usize = 2;
+
+/// A problem found by [`Config::validate`]
+#[derive(Error, Debug, PartialEq)]
+pub enum ConfigError {
+    #[error("Either password, private key or node key must be set")]
+    MissingCredentials,
+
+    #[error("Port out of range: {0}")]
+    PortOutOfRange(String),
+
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+
+    #[error("Invalid subnet: {0}")]
+    InvalidRange(String),
+
+    #[error("Invalid node id: {0}")]
+    InvalidNodeId(String),
+
+    #[error("Invalid MAC address: {0}")]
+    InvalidMac(String),
+
+    #[error("MTU {0} is smaller than {}", MIN_MTU)]
+    MtuTooSmall(u16),
+
+    #[error("Padding size {0} is larger than {}", MAX_PAD_TO)]
+    PadToTooLarge(u16),
+
+    #[error("Invalid DSCP value: {0}")]
+    InvalidDscp(u8),
+
+    #[error("Gossip fan-out {0} is smaller than {}", MIN_GOSSIP_FANOUT)]
+    GossipFanoutTooSmall(usize),
+
+    #[error("Peer timeout of {0}s is too short for the keepalive interval")]
+    PeerTimeoutTooShort(Duration),
+
+    #[error("VXLAN network identifier {0} has more than 24 bits")]
+    InvalidVni(u32),
+
+    #[error("Options {0} and {1} can not be combined")]
+    ConflictingOptions(&'static str, &'static str),
+
+    #[error("Option {0} requires {1} to be set")]
+    MissingOption(&'static str, &'static str),
+
+    #[error("Key file can not be read: {0:?}")]
+    UnreadableKeyFile(PathBuf),
+}
+
+fn parse_port(text: &str, addr: &str) -> Result<u16, ConfigError> {
+    match u32::from_str(text) {
+        Ok(port) if port <= u16::MAX as u32 => Ok(port as u16),
+        Ok(_) => Err(ConfigError::PortOutOfRange(addr.to_string())),
+        Err(_) => Err(ConfigError::InvalidAddress(addr.to_string())),
+    }
+}
+
+/// Checks the formats accepted by `parse_listen`: `*:PORT`, `IP:PORT`, `PORT` and `IP`
+fn validate_listen(addr: &str) -> Result<(), ConfigError> {
+    if let Some(port) = addr.strip_prefix("*:") {
+        parse_port(port, addr)?;
+    } else if let Some(pos) = addr.rfind(':') {
+        if addr.parse::<SocketAddr>().is_err() {
+            parse_port(&addr[pos + 1..], addr)?;
+            return Err(ConfigError::InvalidAddress(addr.to_string()));
+        }
+    } else if !addr.is_empty() && addr.bytes().all(|b| b.is_ascii_digit()) {
+        parse_port(addr, addr)?;
+    } else if addr.parse::<IpAddr>().is_err() {
+        return Err(ConfigError::InvalidAddress(addr.to_string()));
+    }
+    Ok(())
+}
+
+/// Peers can be given as names that are resolved later, so only the port is checked
+fn validate_peer(addr: &str) -> Result<(), ConfigError> {
+    if addr.is_empty() {
+        return Err(ConfigError::InvalidAddress(addr.to_string()));
+    }
+    match addr.rfind(':') {
+        // A colon inside of an IPv6 address does not start the port
+        Some(pos) if pos > addr.rfind(']').unwrap_or(0) && addr.parse::<IpAddr>().is_err() => {
+            match parse_port(&addr[pos + 1..], addr)? {
+                0 => Err(ConfigError::PortOutOfRange(addr.to_string())),
+                _ => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_valid_ip_netmask(addr: &str) -> bool {
+    let (ip, prefix_len) = match addr.find('/') {
+        Some(pos) => (&addr[..pos], &addr[pos + 1..]),
+        None => (addr, "24"),
+    };
+    Ipv4Addr::from_str(ip).is_ok() && u8::from_str(prefix_len).map(|len| len <= 32).unwrap_or(false)
+}
+
+#[derive(StructOpt, Debug, Default, Clone)]
 pub struct Args {
-    /// Read configuration options from the specified file.
+    /// Read configuration options from the specified file, multiple files run multiple networks.
     #[structopt(long)]
-    pub config: Option<String>,
+    pub config: Vec<String>,
 
     /// Set the type of network
     #[structopt(name = "type", short, long, possible_values=&["tun", "tap"])]
@@ -390,10 +1472,30 @@ pub struct Args {
     #[structopt(long)]
     pub fix_rp_filter: bool,
 
+    /// Set the MTU of the device, by default it is derived from the default device
+    #[structopt(long)]
+    pub mtu: Option<u16>,
+
+    /// Keep the device when VpnCloud exits, this requires a fixed device name
+    #[structopt(long)]
+    pub device_persistent: bool,
+
+    /// Set the MAC address of a TAP device
+    #[structopt(long)]
+    pub tap_mac: Option<String>,
+
+    /// Derive the MAC address of a TAP device from the node id
+    #[structopt(long)]
+    pub tap_mac_from_node_id: bool,
+
     /// The mode of the VPN
     #[structopt(short, long, possible_values=&["normal", "router", "switch", "hub"])]
     pub mode: Option<Mode>,
 
+    /// The address family to use for peers [default: dual-stack]
+    #[structopt(long, possible_values=&["dual-stack", "ipv4", "ipv6"])]
+    pub address_family: Option<AddressFamily>,
+
     /// The shared password to encrypt all traffic
     #[structopt(short, long, env)]
     pub password: Option<String>,
@@ -410,14 +1512,58 @@ pub struct Args {
     #[structopt(long = "trusted-key", alias = "trust", use_delimiter = true)]
     pub trusted_keys: Vec<String>,
 
+    /// Keys only shared with specific peers, given as NODE_ID:KEY
+    #[structopt(long = "peer-key")]
+    pub peer_keys: Vec<PeerKeyEntry>,
+
     /// Algorithms to allow
     #[structopt(long = "algorithm", alias = "algo", use_delimiter=true, case_insensitive = true, possible_values=&["plain", "aes128", "aes256", "chacha20"])]
     pub algorithms: Vec<String>,
 
+    /// Use a post-quantum key exchange in addition to ECDH
+    #[structopt(long)]
+    pub pq_kem: bool,
+
+    /// Memory in KiB used to derive keys from passwords [default: 65536]
+    #[structopt(long)]
+    pub argon2_memory_kib: Option<u32>,
+
+    /// Iterations used to derive keys from passwords [default: 3]
+    #[structopt(long)]
+    pub argon2_iterations: Option<u32>,
+
+    /// The CA certificate (PEM file) to verify peer certificates with
+    #[structopt(long, requires = "node-cert")]
+    pub ca_cert: Option<String>,
+
+    /// The certificate (PEM file) of this node
+    #[structopt(long, requires = "ca-cert")]
+    pub node_cert: Option<String>,
+
+    /// The Ed25519 private key (PEM file) matching the node certificate
+    #[structopt(long, conflicts_with = "password")]
+    pub node_key: Option<String>,
+
     /// The local subnets to claim (IP or IP/prefix)
     #[structopt(long = "claim", use_delimiter = true)]
     pub claims: Vec<String>,
 
+    /// Subnets that are not sent into the VPN (IP/prefix)
+    #[structopt(long = "exclude-route", use_delimiter = true)]
+    pub excluded_routes: Vec<String>,
+
+    /// A rule selecting the routing table by source and destination (from <range> [to <range>] table <id>)
+    #[structopt(long = "policy-rule")]
+    pub policy_rules: Vec<PolicyRuleConfig>,
+
+    /// A routing table that sends its packets via a gateway address (<id> via <address>)
+    #[structopt(long = "policy-table")]
+    pub policy_tables: Vec<PolicyTableConfig>,
+
+    /// A rule allowing or denying packets (<allow|deny> [from <range>] [to <range>] [proto <num>])
+    #[structopt(long = "acl")]
+    pub acl: Vec<AclRuleConfig>,
+
     /// Do not automatically claim the device ip
     #[structopt(long)]
     pub no_auto_claim: bool,
@@ -434,6 +1580,10 @@ pub struct Args {
     #[structopt(short = "c", long = "peer", alias = "connect")]
     pub peers: Vec<String>,
 
+    /// Domain whose _vpncloud._udp SRV records name peers to connect to
+    #[structopt(long)]
+    pub bootstrap_dns: Vec<String>,
+
     /// Peer timeout in seconds
     #[structopt(long)]
     pub peer_timeout: Option<Duration>,
@@ -442,10 +1592,110 @@ pub struct Args {
     #[structopt(long)]
     pub keepalive: Option<Duration>,
 
+    /// Refuse connections when this many peers are connected
+    #[structopt(long)]
+    pub max_peers: Option<usize>,
+
+    /// Maximum number of peers to keep reconnecting to
+    #[structopt(long)]
+    pub max_reconnect_peers: Option<usize>,
+
+    /// Reconnect at fixed intervals instead of random times within the back-off interval
+    #[structopt(long)]
+    pub no_reconnect_jitter: bool,
+
+    /// Probe peers that have been silent for this many seconds (0 to disable)
+    #[structopt(long)]
+    pub dpd_probe_interval: Option<Duration>,
+
+    /// Remove peers after this many unanswered probes
+    #[structopt(long)]
+    pub dpd_retries: Option<usize>,
+
+    /// Send keepalives to hole punched peers that have been idle for this many seconds (0 to disable)
+    #[structopt(long)]
+    pub nat_keepalive: Option<Duration>,
+
+    /// Maximum number of peers to include in each peer list [default: 20]
+    #[structopt(long)]
+    pub gossip_fanout: Option<usize>,
+
+    /// Interval in seconds of the peer lists [default: keepalive interval]
+    #[structopt(long)]
+    pub gossip_interval: Option<Duration>,
+
+    /// Interval in seconds of full peer lists, only changes are sent in between [default: 600]
+    #[structopt(long)]
+    pub gossip_full_interval_secs: Option<Duration>,
+
+    /// Route to the peer with the lowest round trip time if several peers claim the destination
+    #[structopt(long)]
+    pub latency_routing: bool,
+
     /// Switch table entry timeout in seconds
     #[structopt(long)]
     pub switch_timeout: Option<Duration>,
 
+    /// Multicast group membership timeout in seconds [default: 260]
+    #[structopt(long)]
+    pub igmp_timeout: Option<Duration>,
+
+    /// Answer ARP requests for addresses behind peers locally (switch mode only)
+    #[structopt(long)]
+    pub arp_proxy: bool,
+
+    /// Time in seconds after which addresses learned by the ARP proxy expire [default: 300]
+    #[structopt(long)]
+    pub arp_proxy_ttl_secs: Option<Duration>,
+
+    /// Time window in seconds in which route changes are counted as flaps [default: 60]
+    #[structopt(long)]
+    pub flap_window_secs: Option<Duration>,
+
+    /// Suppress routes that changed more often than this in the flap window, 0 to disable [default: 5]
+    #[structopt(long)]
+    pub max_flaps: Option<usize>,
+
+    /// Aggregate claims when the table has more than this many claims
+    #[structopt(long)]
+    pub max_table_entries: Option<usize>,
+
+    /// Number of message buffers to keep for reuse [default: 256]
+    #[structopt(long)]
+    pub buffer_pool_size: Option<usize>,
+
+    /// Number of threads that receive packets on separate sockets sharing the port [default: 1]
+    #[structopt(long)]
+    pub receive_threads: Option<usize>,
+
+    /// Size of the receive buffer of the socket in bytes, limited by net.core.rmem_max
+    #[structopt(long)]
+    pub socket_recv_buffer_bytes: Option<usize>,
+
+    /// Size of the send buffer of the socket in bytes, limited by net.core.wmem_max
+    #[structopt(long)]
+    pub socket_send_buffer_bytes: Option<usize>,
+
+    /// Pass messages to peers in the same process directly instead of sending them through the system
+    #[structopt(long)]
+    pub local_shortcut: bool,
+
+    /// Pad data messages to this size in bytes
+    #[structopt(long)]
+    pub pad_to: Option<u16>,
+
+    /// Add a random amount of padding up to this many bytes to data messages
+    #[structopt(long)]
+    pub pad_amount: Option<u16>,
+
+    /// Mark outgoing packets with this DSCP value (0-63)
+    #[structopt(long)]
+    pub dscp: Option<u8>,
+
+    /// Copy the DSCP value of tunneled IP packets to the outgoing packets
+    #[structopt(long)]
+    pub dscp_inherit: bool,
+
     /// The file path or |command to store the beacon
     #[structopt(long)]
     pub beacon_store: Option<String>,
@@ -462,6 +1712,22 @@ pub struct Args {
     #[structopt(long)]
     pub beacon_password: Option<String>,
 
+    /// Do not encrypt the beacons
+    #[structopt(long)]
+    pub no_beacon_encrypt: bool,
+
+    /// Also read beacons that are not encrypted
+    #[structopt(long)]
+    pub beacon_allow_plaintext: bool,
+
+    /// Domain to store and load beacons as DNS TXT records
+    #[structopt(long)]
+    pub dns_beacon_domain: Option<String>,
+
+    /// TSIG key file to authenticate the DNS updates with
+    #[structopt(long)]
+    pub dns_key_file: Option<String>,
+
     /// Print debug information
     #[structopt(short, long, conflicts_with = "quiet")]
     pub verbose: bool,
@@ -486,14 +1752,86 @@ pub struct Args {
     #[structopt(long)]
     pub ifdown: Option<String>,
 
+    /// Add routes for the claims of peers to the OS routing table
+    #[structopt(long)]
+    pub sync_routes: bool,
+
+    /// Gateway address for synced routes, routes only name the device if not set
+    #[structopt(long)]
+    pub local_tunnel_ip: Option<IpAddr>,
+
+    /// Only log the route commands instead of running them
+    #[structopt(long)]
+    pub sync_routes_dry_run: bool,
+
+    /// Fixed node id of this node as 32 hex characters, needed for peer keys
+    #[structopt(long)]
+    pub node_id: Option<String>,
+
     /// Print the version and exit
     #[structopt(long)]
     pub version: bool,
 
+    /// Check the config for errors and exit
+    #[structopt(long)]
+    pub check_config: bool,
+
+    /// Print the JSON Schema of the config file and exit
+    #[structopt(long)]
+    pub print_config_schema: bool,
+
     /// Disable automatic port forwarding
     #[structopt(long)]
     pub no_port_forwarding: bool,
 
+    /// Discover the path MTU to peers and avoid fragmentation
+    #[structopt(long)]
+    pub pmtu_discovery: bool,
+
+    /// Pace the messages to every peer to this rate in kbit/s
+    #[structopt(long)]
+    pub send_pacing_kbps: Option<u32>,
+
+    /// Maximum number of bytes of all messages deferred by the pacing
+    #[structopt(long)]
+    pub send_queue_max_bytes: Option<usize>,
+
+    /// Deliver the payload of every peer in the order it was sent
+    #[structopt(long)]
+    pub reorder_buffer: bool,
+
+    /// Maximum time in milliseconds to hold back a packet for reordering
+    #[structopt(long)]
+    pub reorder_timeout_ms: Option<u32>,
+
+    /// Maximum number of packets to hold back per peer for reordering
+    #[structopt(long)]
+    pub reorder_buffer_max_packets: Option<usize>,
+
+    /// Maximum time in milliseconds to wait for the missing fragments of a frame [default: 500]
+    #[structopt(long)]
+    pub fragment_timeout_ms: Option<u32>,
+
+    /// Maximum number of incomplete fragmented frames per peer [default: 32]
+    #[structopt(long)]
+    pub fragment_max_buffers: Option<usize>,
+
+    /// Time in milliseconds to wait for the reply to an init message before repeating it [default: 500]
+    #[structopt(long)]
+    pub init_timeout_ms: Option<u32>,
+
+    /// Number of times an init message is repeated after the init timeout [default: 3]
+    #[structopt(long)]
+    pub init_retries: Option<usize>,
+
+    /// Disable hole punching via other peers
+    #[structopt(long)]
+    pub no_hole_punch: bool,
+
+    /// A TURN server to relay messages via (user:password@host:port)
+    #[structopt(long = "turn-server")]
+    pub turn_servers: Vec<TurnServer>,
+
     /// Run the process in the background
     #[structopt(long)]
     pub daemon: bool,
@@ -502,10 +1840,78 @@ pub struct Args {
     #[structopt(long)]
     pub pid_file: Option<String>,
 
+    /// Persist the learned routing table in this directory
+    #[structopt(long)]
+    pub table_persistence_path: Option<String>,
+
     /// Print statistics to this file
     #[structopt(long)]
     pub stats_file: Option<String>,
 
+    /// Append peer connection events to this file
+    #[structopt(long)]
+    pub audit_log: Option<String>,
+
+    /// Rotate the audit log when it grows larger than this [default: 10485760]
+    #[structopt(long)]
+    pub audit_log_max_bytes: Option<u64>,
+
+    /// Send peer connect and disconnect events to this URL via HTTP POST
+    #[structopt(long)]
+    pub peer_event_webhook: Option<String>,
+
+    /// Sign the webhook requests with HMAC-SHA256 using this secret
+    #[structopt(long, requires = "peer-event-webhook")]
+    pub webhook_secret: Option<String>,
+
+    /// Pin the keys of peers to their node ids in this file (trust on first use)
+    #[structopt(long)]
+    pub tofu_store: Option<String>,
+
+    /// Whether peers with unknown node ids are pinned or rejected
+    #[structopt(long, possible_values=&["allow_first", "deny_unknown"])]
+    pub tofu_mode: Option<TofuMode>,
+
+    /// Save the peers to this file on shutdown and reconnect to them on start
+    #[structopt(long)]
+    pub peer_state_file: Option<String>,
+
+    /// Long-lived identity key of this node, generated if the file does not exist
+    #[structopt(long)]
+    pub identity_key: Option<String>,
+
+    /// Accept connections from peers using the Noise IK handshake of WireGuard
+    #[structopt(long)]
+    pub noise_protocol: bool,
+
+    /// Static key of the Noise handshake, generated if the file does not exist
+    #[structopt(long)]
+    pub noise_key: Option<String>,
+
+    /// Encapsulate the messages of VXLAN peers with this network identifier (VNI)
+    #[structopt(long)]
+    pub vxlan_vni: Option<u32>,
+
+    /// Allow plaintext peers, only for benchmarks and trusted networks
+    #[structopt(long)]
+    pub no_require_encryption: bool,
+
+    /// Do not encrypt the messages of this peer if it also disables encryption (IP:PORT)
+    #[structopt(long = "plaintext-peer")]
+    pub plaintext_peers: Vec<SocketAddr>,
+
+    /// File that stores the IP allocations of the CNI plugin
+    #[structopt(long)]
+    pub cni_ipam_file: Option<String>,
+
+    /// The format of the statistics file
+    #[structopt(long, possible_values=&["text", "json", "prometheus"])]
+    pub stats_format: Option<StatsFormat>,
+
+    /// Maximum number of addresses to keep payload traffic statistics for
+    #[structopt(long)]
+    pub traffic_stats_addresses: Option<usize>,
+
     /// Send statistics to this statsd server
     #[structopt(long)]
     pub statsd_server: Option<String>,
@@ -514,6 +1920,66 @@ pub struct Args {
     #[structopt(long, requires = "statsd-server")]
     pub statsd_prefix: Option<String>,
 
+    /// Send statistics to this InfluxDB UDP listener (IP:PORT)
+    #[structopt(long)]
+    pub influx_addr: Option<SocketAddr>,
+
+    /// Measurement name of the InfluxDB points [default: vpncloud]
+    #[structopt(long, requires = "influx-addr")]
+    pub influx_measurement: Option<String>,
+
+    /// Serve statistics via SNMP on this UDP address (IP:PORT)
+    #[structopt(long)]
+    pub snmp_addr: Option<SocketAddr>,
+
+    /// Community string that SNMP requests and traps use [default: public]
+    #[structopt(long)]
+    pub snmp_community: Option<String>,
+
+    /// Send SNMP traps when peers connect or disconnect to this address (IP:PORT)
+    #[structopt(long)]
+    pub snmp_trap_receiver: Option<SocketAddr>,
+
+    /// Capture packets to this pcap-ng file
+    #[structopt(long)]
+    pub capture_file: Option<String>,
+
+    /// Which packets to capture [default: network]
+    #[structopt(long, possible_values=&["network", "device", "both"], requires = "capture-file")]
+    pub capture_layer: Option<CaptureLayer>,
+
+    /// Stop capturing when the capture file reaches this size in bytes [default: 104857600]
+    #[structopt(long, requires = "capture-file")]
+    pub capture_max_bytes: Option<u64>,
+
+    /// Only capture the network packets from and to this peer (IP:PORT)
+    #[structopt(long, requires = "capture-file")]
+    pub capture_filter_peer: Option<SocketAddr>,
+
+    /// Export traces of the packet flow to this OpenTelemetry (OTLP/HTTP) endpoint
+    #[structopt(long)]
+    pub otel_endpoint: Option<String>,
+
+    /// Serve the HTTP management API on this address
+    #[structopt(long)]
+    pub api_addr: Option<String>,
+
+    /// Require this bearer token for the management API
+    #[structopt(long, requires = "api-addr")]
+    pub api_token: Option<String>,
+
+    /// Serve the management API via TLS with this certificate file
+    #[structopt(long, requires_all = &["api-addr", "api-tls-key"])]
+    pub api_tls_cert: Option<String>,
+
+    /// The private key file for the management API certificate
+    #[structopt(long, requires = "api-tls-cert")]
+    pub api_tls_key: Option<String>,
+
+    /// Serve the gRPC management API on this address
+    #[structopt(long)]
+    pub grpc_addr: Option<String>,
+
     /// Run as other user
     #[structopt(long)]
     pub user: Option<String>,
@@ -530,11 +1996,15 @@ pub struct Args {
     #[structopt(long)]
     pub hook: Vec<String>,
 
+    /// A tag to advertise to peers (key=value)
+    #[structopt(long = "tag")]
+    pub tags: Vec<String>,
+
     #[structopt(subcommand)]
     pub cmd: Option<Command>,
 }
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 pub enum Command {
     /// Generate and print a key-pair and exit
     #[structopt(name = "genkey", alias = "gen-key")]
@@ -561,6 +2031,10 @@ pub enum Command {
         config_file: String,
     },
 
+    /// Run as CNI plugin, this is called by the vpncloud-cni binary
+    #[structopt(setting = AppSettings::Hidden)]
+    Cni,
+
     /// Generate shell completions
     Completion {
         /// Shell to create completions for
@@ -585,61 +2059,462 @@ pub enum Command {
     },
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
 pub struct ConfigFileDevice {
+    /// Type of the device, tun for IP packets or tap for Ethernet frames
     #[serde(rename = "type")]
     pub type_: Option<Type>,
+    /// Name of the virtual device, %d is replaced by a number
     pub name: Option<String>,
+    /// Path of the tun/tap device file
     pub path: Option<String>,
+    /// Set the rp_filter setting of the device to 1
     pub fix_rp_filter: Option<bool>,
+    /// MTU of the device
+    #[schemars(range(min = 576))]
+    pub mtu: Option<u16>,
+    /// Keep the device when VpnCloud exits
+    pub persistent: Option<bool>,
+    /// MAC address of the tap device
+    pub mac: Option<String>,
+    /// Derive the MAC address of the tap device from the node id
+    pub mac_from_node_id: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TurnServer {
+    /// URL of the server
+    pub url: String,
+    /// User name
+    pub username: String,
+    /// Password
+    pub password: String,
+}
+
+impl TurnServer {
+    /// Host and port of the server
+    pub fn address(&self) -> String {
+        let addr = self.url.strip_prefix("turn:").unwrap_or(&self.url);
+        let has_port = if addr.starts_with('[') { addr.contains("]:") } else { addr.contains(':') };
+        if has_port {
+            addr.to_string()
+        } else {
+            format!("{}:{}", addr, DEFAULT_TURN_PORT)
+        }
+    }
+}
+
+impl FromStr for TurnServer {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        const FORMAT: &str = "TURN servers must be given as user:password@host:port";
+        let at = text.rfind('@').ok_or(FORMAT)?;
+        let colon = text[..at].find(':').ok_or(FORMAT)?;
+        Ok(Self {
+            url: text[at + 1..].to_string(),
+            username: text[..colon].to_string(),
+            password: text[colon + 1..at].to_string(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PolicyRuleConfig {
+    /// Source subnet
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Destination subnet
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Id of the policy table
+    pub table: u8,
+}
+
+impl FromStr for PolicyRuleConfig {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        const FORMAT: &str = "Policy rules must be given as from <range> [to <range>] table <id>";
+        let mut rule = Self { from: None, to: None, table: 0 };
+        let mut table = None;
+        let mut words = text.split_whitespace();
+        while let Some(key) = words.next() {
+            let val = words.next().ok_or(FORMAT)?;
+            match key {
+                "from" => rule.from = Some(val.to_string()),
+                "to" => rule.to = Some(val.to_string()),
+                "table" => table = Some(val.parse().map_err(|_| FORMAT)?),
+                _ => return Err(FORMAT),
+            }
+        }
+        rule.table = table.ok_or(FORMAT)?;
+        Ok(rule)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PolicyTableConfig {
+    /// Id of the table
+    pub id: u8,
+    /// Gateway of the table
+    pub via: String,
+}
+
+impl FromStr for PolicyTableConfig {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        const FORMAT: &str = "Policy tables must be given as <id> via <address>";
+        let words: Vec<_> = text.split_whitespace().collect();
+        match words[..] {
+            [id, "via", via] => Ok(Self { id: id.parse().map_err(|_| FORMAT)?, via: via.to_string() }),
+            _ => Err(FORMAT),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AclRuleConfig {
+    /// Whether matching traffic is allowed
+    pub action: AclAction,
+    /// Source subnet
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Destination subnet
+    #[serde(default)]
+    pub to: Option<String>,
+    /// IP protocol number
+    #[serde(default)]
+    pub proto: Option<u8>,
+}
+
+impl FromStr for AclRuleConfig {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        const FORMAT: &str = "ACL rules must be given as <allow|deny> [from <range>] [to <range>] [proto <num>]";
+        let mut words = text.split_whitespace();
+        let action = words.next().ok_or(FORMAT)?.parse().map_err(|_| FORMAT)?;
+        let mut rule = Self { action, from: None, to: None, proto: None };
+        while let Some(key) = words.next() {
+            let val = words.next().ok_or(FORMAT)?;
+            match key {
+                "from" => rule.from = Some(val.to_string()),
+                "to" => rule.to = Some(val.to_string()),
+                "proto" => rule.proto = Some(val.parse().map_err(|_| FORMAT)?),
+                _ => return Err(FORMAT),
+            }
+        }
+        Ok(rule)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
 pub struct ConfigFileBeacon {
+    /// File or command to store beacons in
     pub store: Option<String>,
+    /// File or command to load beacons from
     pub load: Option<String>,
+    /// Interval in seconds in which beacons are stored and loaded
     pub interval: Option<Duration>,
+    /// Password that encrypts the beacons
     pub password: Option<String>,
+    /// Encrypt the beacons
+    pub encrypt: Option<bool>,
+    /// Also read beacons that are not encrypted
+    pub allow_plaintext: Option<bool>,
+    /// Domain to store and load beacons as DNS TXT records
+    pub dns_domain: Option<String>,
+    /// TSIG key file to authenticate the DNS updates with
+    pub dns_key_file: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
 pub struct ConfigFileStatsd {
+    /// Statsd server to send statistics to (host:port)
     pub server: Option<String>,
+    /// Prefix of the statsd metrics
     pub prefix: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct ConfigFileInflux {
+    /// InfluxDB UDP listener to send statistics to (IP:PORT)
+    pub addr: Option<SocketAddr>,
+    /// Measurement name of the points
+    pub measurement: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct ConfigFileSnmp {
+    /// UDP address to answer SNMP requests on (IP:PORT)
+    pub addr: Option<SocketAddr>,
+    /// Community string of requests and traps
+    pub community: Option<String>,
+    /// Receiver of the traps on peer events (IP:PORT)
+    pub trap_receiver: Option<SocketAddr>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct ConfigFileCapture {
+    /// File to capture packets to in the pcap-ng format
+    pub file: Option<String>,
+    /// Which packets to capture
+    pub layer: Option<CaptureLayer>,
+    /// Maximum size of the capture file in bytes
+    pub max_bytes: Option<u64>,
+    /// Only capture the network packets of this peer (IP:PORT)
+    pub filter_peer: Option<SocketAddr>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct ConfigFileApi {
+    /// Address of the management API
+    pub addr: Option<String>,
+    /// Bearer token that the management API requires
+    pub token: Option<String>,
+    /// TLS certificate of the management API
+    pub tls_cert: Option<String>,
+    /// TLS key of the management API
+    pub tls_key: Option<String>,
+    /// Address of the gRPC management API
+    pub grpc_addr: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, JsonSchema)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
 pub struct ConfigFile {
+    /// Settings of the virtual device
     pub device: Option<ConfigFileDevice>,
 
+    /// IPv4 address and netmask of the device, e.g. 10.0.0.1/24
     pub ip: Option<String>,
+    /// Public addresses to advertise to peers
     pub advertise_addresses: Option<Vec<String>>,
+    /// Command to run after the device has been set up
     pub ifup: Option<String>,
+    /// Command to run before the device is taken down
     pub ifdown: Option<String>,
-
+    /// Mirror the claims of the peers into the routing table
+    pub sync_routes: Option<bool>,
+    /// Gateway of the synced routes
+    pub local_tunnel_ip: Option<IpAddr>,
+    /// Only log the route changes instead of applying them
+    pub sync_routes_dry_run: Option<bool>,
+    /// Fixed node id as 32 hex characters
+    pub node_id: Option<String>,
+
+    /// Credentials and crypto settings
     pub crypto: CryptoConfig,
+    /// Address to listen on, a port, IP, IP:PORT or ws:// URL
+    #[schemars(schema_with = "listen_schema")]
     pub listen: Option<String>,
+    /// Peers to connect to (host:port)
     pub peers: Option<Vec<String>>,
+    /// Domains whose _vpncloud._udp SRV records name peers to connect to
+    pub bootstrap_dns: Option<Vec<String>>,
+    /// Time in seconds after which silent peers are removed
     pub peer_timeout: Option<Duration>,
+    /// Interval in seconds of keepalive messages
     pub keepalive: Option<Duration>,
-
+    /// Maximum number of peers
+    pub max_peers: Option<usize>,
+    /// Maximum number of peers to reconnect to
+    pub max_reconnect_peers: Option<usize>,
+    /// Randomize the reconnect times within the back-off interval
+    pub reconnect_jitter: Option<bool>,
+    /// Interval in seconds of dead peer detection probes
+    pub dpd_probe_interval: Option<Duration>,
+    /// Unanswered probes after which a peer is considered dead
+    pub dpd_retries: Option<usize>,
+    /// Interval in seconds of keepalives to hole punched peers
+    pub nat_keepalive: Option<Duration>,
+    /// Maximum number of peers in each peer list
+    #[schemars(range(min = 2))]
+    pub gossip_fanout: Option<usize>,
+    /// Interval in seconds of the peer lists
+    pub gossip_interval: Option<Duration>,
+    /// Interval in seconds of full peer lists, only changes are sent in between
+    pub gossip_full_interval_secs: Option<Duration>,
+    /// Route to the peer with the lowest round trip time if several peers claim the destination
+    pub latency_routing: Option<bool>,
+
+    /// Settings of the beacons
     pub beacon: Option<ConfigFileBeacon>,
+    /// Mode of the VPN, i.e. how messages are forwarded
     pub mode: Option<Mode>,
+    /// IP versions of the socket
+    pub address_family: Option<AddressFamily>,
+    /// Time in seconds after which learnt addresses expire
     pub switch_timeout: Option<Duration>,
+    /// Time in seconds after which multicast group memberships expire
+    pub igmp_timeout: Option<Duration>,
+    /// Whether to answer ARP requests for addresses behind peers locally
+    pub arp_proxy: Option<bool>,
+    /// Time in seconds after which addresses learned by the ARP proxy expire
+    pub arp_proxy_ttl_secs: Option<Duration>,
+    /// Window in seconds in which address flaps are counted
+    pub flap_window_secs: Option<Duration>,
+    /// Address flaps within the window after which an address is locked
+    pub max_flaps: Option<usize>,
+    /// Maximum number of entries in the switch table
+    pub max_table_entries: Option<usize>,
+    /// Number of preallocated message buffers
+    pub buffer_pool_size: Option<usize>,
+    /// Number of threads receiving packets
+    pub receive_threads: Option<usize>,
+    /// Size of the receive buffer of the socket
+    pub socket_recv_buffer_bytes: Option<usize>,
+    /// Size of the send buffer of the socket
+    pub socket_send_buffer_bytes: Option<usize>,
+    /// Pass messages to peers in the same process directly
+    pub local_shortcut: Option<bool>,
+    /// Pad messages to a multiple of this size
+    pub pad_to: Option<u16>,
+    /// Maximum random padding in bytes
+    pub pad_amount: Option<u16>,
+    /// DSCP value of the sent packets
+    #[schemars(range(max = 63))]
+    pub dscp: Option<u8>,
+    /// Copy the DSCP value of the inner packets
+    pub dscp_inherit: Option<bool>,
+    /// Subnets that this node claims
     pub claims: Option<Vec<String>>,
+    /// Subnets that are not routed through the VPN
+    pub excluded_routes: Option<Vec<String>>,
+    /// Rules that route traffic via a policy table
+    pub policy_rules: Option<Vec<PolicyRuleConfig>>,
+    /// Policy tables with their gateways
+    pub policy_tables: Option<Vec<PolicyTableConfig>>,
+    /// Rules that allow or deny traffic
+    pub acl: Option<Vec<AclRuleConfig>>,
+    /// Claim the subnet of the device
     pub auto_claim: Option<bool>,
+    /// Forward the port on the router via UPnP
     pub port_forwarding: Option<bool>,
+    /// Discover the path MTU to peers
+    pub pmtu_discovery: Option<bool>,
+    /// Rate in kbit/s to pace the messages to every peer to
+    pub send_pacing_kbps: Option<u32>,
+    /// Maximum size of the messages deferred by the pacing
+    pub send_queue_max_bytes: Option<usize>,
+    /// Whether to deliver the payload of every peer in order
+    pub reorder_buffer: Option<bool>,
+    /// Maximum time to hold back a packet for reordering
+    pub reorder_timeout_ms: Option<u32>,
+    /// Maximum number of packets to hold back per peer
+    pub reorder_buffer_max_packets: Option<usize>,
+    /// Maximum time to wait for the missing fragments of a frame
+    pub fragment_timeout_ms: Option<u32>,
+    /// Maximum number of incomplete fragmented frames per peer
+    pub fragment_max_buffers: Option<usize>,
+    /// Time to wait for the reply to an init message before repeating it
+    pub init_timeout_ms: Option<u32>,
+    /// Number of times an init message is repeated after the init timeout
+    pub init_retries: Option<usize>,
+    /// Punch holes via other peers
+    pub hole_punch: Option<bool>,
+    /// TURN servers to relay messages via
+    pub turn_servers: Option<Vec<TurnServer>>,
+    /// File to write the process id to
     pub pid_file: Option<String>,
+    /// File to persist the switch table in
+    pub table_persistence_path: Option<String>,
+    /// File to write statistics to
     pub stats_file: Option<String>,
+    /// File to log peer events to
+    pub audit_log: Option<String>,
+    /// Size of the audit log after which it is rotated
+    pub audit_log_max_bytes: Option<u64>,
+    /// URL to send peer connect and disconnect events to
+    pub peer_event_webhook: Option<String>,
+    /// Secret to sign the webhook requests with
+    pub webhook_secret: Option<String>,
+    /// File with the keys of known peers
+    pub tofu_store: Option<String>,
+    /// How unknown peers are treated
+    pub tofu_mode: Option<TofuMode>,
+    /// File to save the peers in across restarts
+    pub peer_state_file: Option<String>,
+    /// File with the identity key of this node
+    pub identity_key: Option<String>,
+    /// Accept peers using the Noise IK handshake
+    pub noise_protocol: Option<bool>,
+    /// File with the static key of the Noise handshake
+    pub noise_key: Option<String>,
+    /// VXLAN network identifier of VXLAN peers
+    #[schemars(range(max = 0xff_ffff))]
+    pub vxlan_vni: Option<u32>,
+    /// Refuse plaintext peers
+    pub require_encryption: Option<bool>,
+    /// Peers whose messages are not encrypted
+    pub plaintext_peers: Option<Vec<SocketAddr>>,
+    /// File with the address allocations of the CNI plugin
+    pub cni_ipam_file: Option<String>,
+    /// Format of the stats file
+    pub stats_format: Option<StatsFormat>,
+    /// Maximum number of addresses with payload traffic statistics
+    pub traffic_stats_addresses: Option<usize>,
+    /// Settings of the statsd reporting
     pub statsd: Option<ConfigFileStatsd>,
+    /// Settings of the InfluxDB reporting
+    pub influx: Option<ConfigFileInflux>,
+    /// Settings of the SNMP agent
+    pub snmp: Option<ConfigFileSnmp>,
+    /// Settings of the packet capture
+    pub capture: Option<ConfigFileCapture>,
+    /// OpenTelemetry endpoint to export traces to
+    pub otel_endpoint: Option<String>,
+    /// Settings of the management API
+    pub api: Option<ConfigFileApi>,
+    /// User to run the process as
     pub user: Option<String>,
+    /// Group to run the process as
     pub group: Option<String>,
+    /// Command to run on all events
     pub hook: Option<String>,
+    /// Commands to run on specific events
     pub hooks: HashMap<String, String>,
+    /// Tags of this node that are shared with the peers
+    pub local_tags: HashMap<String, String>,
+}
+
+/// The listen address can also be given as a bare port number
+fn listen_schema(_: &mut SchemaGenerator) -> Schema {
+    let types = vec![InstanceType::String, InstanceType::Integer, InstanceType::Null];
+    SchemaObject { instance_type: Some(types.into()), ..Default::default() }.into()
+}
+
+impl ConfigFile {
+    /// JSON Schema (draft 7) of the config file with the defaults of all options
+    pub fn json_schema() -> serde_json::Value {
+        let schema = SchemaSettings::draft07().into_generator().into_root_schema_for::<ConfigFile>();
+        let mut schema = serde_json::to_value(schema).expect("Failed to serialize schema");
+        schema["title"] = "VpnCloud config file".into();
+        // The fields are optional in the file, their defaults come from the config
+        let defaults = serde_json::to_value(Config::default().into_config_file()).expect("Failed to serialize config");
+        if let (Some(properties), Some(defaults)) = (schema["properties"].as_object_mut(), defaults.as_object()) {
+            for (key, property) in properties {
+                match defaults.get(key) {
+                    Some(default) if !default.is_null() => property["default"] = default.clone(),
+                    _ => (),
+                }
+            }
+        }
+        schema
+    }
 }
 
 #[test]
@@ -649,34 +2524,134 @@ device:
   type: tun
   name: vpncloud%d
   path: /dev/net/tun
+  mtu: 1380
+  persistent: true
+  mac: 02:00:00:00:00:01
 ip: 10.0.1.1/16
 advertise-addresses:
   - 192.168.0.1
   - 192.168.1.1
 ifup: ifconfig $IFNAME 10.0.1.1/16 mtu 1400 up
 ifdown: 'true'
+sync-routes: true
+local-tunnel-ip: 10.0.1.1
+node-id: 0123456789abcdef0123456789abcdef
 peers:
   - remote.machine.foo:3210
   - remote.machine.bar:3210
+bootstrap-dns:
+  - vpn.example.com
 peer-timeout: 600
 keepalive: 840
+max-peers: 100
+max-reconnect-peers: 32
+reconnect-jitter: false
+dpd-probe-interval: 20
+dpd-retries: 5
+nat-keepalive: 20
+gossip-fanout: 10
+gossip-interval: 60
+gossip-full-interval-secs: 900
+latency-routing: true
 switch-timeout: 300
+igmp-timeout: 200
+arp-proxy: true
+arp-proxy-ttl-secs: 120
+flap-window-secs: 120
+max-table-entries: 500
+buffer-pool-size: 128
+receive-threads: 2
+socket-recv-buffer-bytes: 4194304
+socket-send-buffer-bytes: 1048576
+local-shortcut: true
+pad-to: 1000
+pad-amount: 32
+dscp: 46
+dscp-inherit: true
 beacon:
   store: /run/vpncloud.beacon.out
   load: /run/vpncloud.beacon.in
   interval: 3600
   password: test123
+  encrypt: false
+  allow-plaintext: true
+  dns-domain: vpn.example.com
+  dns-key-file: /etc/vpncloud/dns.key
 mode: normal
+address-family: ipv6
 claims:
   - 10.0.1.0/24
+excluded-routes:
+  - 192.168.0.0/16
+policy-rules:
+  - from: 10.0.2.0/24
+    table: 1
+policy-tables:
+  - id: 1
+    via: 10.0.1.2
+acl:
+  - action: deny
+    to: 10.0.3.0/24
+    proto: 17
 port-forwarding: true
+pmtu-discovery: true
+send-pacing-kbps: 10000
+send-queue-max-bytes: 65536
+reorder-buffer: true
+reorder-timeout-ms: 20
+reorder-buffer-max-packets: 16
+fragment-timeout-ms: 800
+fragment-max-buffers: 8
+init-timeout-ms: 300
+init-retries: 5
+hole-punch: false
+turn-servers:
+  - url: turn.example.com
+    username: user
+    password: pass
 user: nobody
 group: nogroup
 pid-file: /run/vpncloud.run
+table-persistence-path: /var/lib/vpncloud/table
 stats-file: /var/log/vpncloud.stats
+audit-log: /var/log/vpncloud.audit
+audit-log-max-bytes: 1000000
+peer-event-webhook: http://localhost:8500/v1/event
+webhook-secret: secret
+tofu-store: /var/lib/vpncloud/tofu.json
+tofu-mode: deny_unknown
+peer-state-file: /var/lib/vpncloud/peers.state
+identity-key: /var/lib/vpncloud/identity.key
+noise-protocol: true
+noise-key: /var/lib/vpncloud/noise.key
+vxlan-vni: 4242
+require-encryption: false
+plaintext-peers:
+  - 192.168.1.2:3210
+cni-ipam-file: /var/lib/vpncloud/cni-ipam.json
+stats-format: json
+traffic-stats-addresses: 500
 statsd:
   server: example.com:1234
   prefix: prefix
+influx:
+  addr: 192.168.1.10:8089
+  measurement: vpn
+snmp:
+  addr: 127.0.0.1:161
+  community: private
+  trap-receiver: 192.168.1.10:162
+capture:
+  file: /tmp/vpncloud.pcapng
+  layer: both
+  max-bytes: 1000000
+otel-endpoint: http://localhost:4318/v1/traces
+api:
+  addr: 127.0.0.1:8080
+  token: secret
+  grpc-addr: 127.0.0.1:50051
+local-tags:
+  dc: eu-west-1
     ";
     assert_eq!(
         serde_yaml::from_str::<ConfigFile>(config_file).unwrap(),
@@ -685,38 +2660,143 @@ statsd:
                 type_: Some(Type::Tun),
                 name: Some("vpncloud%d".to_string()),
                 path: Some("/dev/net/tun".to_string()),
-                fix_rp_filter: None
+                fix_rp_filter: None,
+                mtu: Some(1380),
+                persistent: Some(true),
+                mac: Some("02:00:00:00:00:01".to_string()),
+                mac_from_node_id: None
             }),
             ip: Some("10.0.1.1/16".to_string()),
             advertise_addresses: Some(vec!["192.168.0.1".to_string(), "192.168.1.1".to_string()]),
             ifup: Some("ifconfig $IFNAME 10.0.1.1/16 mtu 1400 up".to_string()),
             ifdown: Some("true".to_string()),
+            sync_routes: Some(true),
+            local_tunnel_ip: Some(IpAddr::from([10, 0, 1, 1])),
+            sync_routes_dry_run: None,
+            node_id: Some("0123456789abcdef0123456789abcdef".to_string()),
             crypto: CryptoConfig::default(),
             listen: None,
             peers: Some(vec!["remote.machine.foo:3210".to_string(), "remote.machine.bar:3210".to_string()]),
+            bootstrap_dns: Some(vec!["vpn.example.com".to_string()]),
             peer_timeout: Some(600),
             keepalive: Some(840),
+            max_peers: Some(100),
+            max_reconnect_peers: Some(32),
+            reconnect_jitter: Some(false),
+            dpd_probe_interval: Some(20),
+            dpd_retries: Some(5),
+            nat_keepalive: Some(20),
+            gossip_fanout: Some(10),
+            gossip_interval: Some(60),
+            gossip_full_interval_secs: Some(900),
+            latency_routing: Some(true),
             beacon: Some(ConfigFileBeacon {
                 store: Some("/run/vpncloud.beacon.out".to_string()),
                 load: Some("/run/vpncloud.beacon.in".to_string()),
                 interval: Some(3600),
-                password: Some("test123".to_string())
+                password: Some("test123".to_string()),
+                encrypt: Some(false),
+                allow_plaintext: Some(true),
+                dns_domain: Some("vpn.example.com".to_string()),
+                dns_key_file: Some("/etc/vpncloud/dns.key".to_string())
             }),
             mode: Some(Mode::Normal),
+            address_family: Some(AddressFamily::Ipv6Only),
             switch_timeout: Some(300),
+            igmp_timeout: Some(200),
+            arp_proxy: Some(true),
+            arp_proxy_ttl_secs: Some(120),
+            flap_window_secs: Some(120),
+            max_flaps: None,
+            max_table_entries: Some(500),
+            buffer_pool_size: Some(128),
+            receive_threads: Some(2),
+            socket_recv_buffer_bytes: Some(4194304),
+            socket_send_buffer_bytes: Some(1048576),
+            local_shortcut: Some(true),
+            pad_to: Some(1000),
+            pad_amount: Some(32),
+            dscp: Some(46),
+            dscp_inherit: Some(true),
             claims: Some(vec!["10.0.1.0/24".to_string()]),
+            excluded_routes: Some(vec!["192.168.0.0/16".to_string()]),
+            policy_rules: Some(vec![PolicyRuleConfig { from: Some("10.0.2.0/24".to_string()), to: None, table: 1 }]),
+            policy_tables: Some(vec![PolicyTableConfig { id: 1, via: "10.0.1.2".to_string() }]),
+            acl: Some(vec![AclRuleConfig {
+                action: AclAction::Deny,
+                from: None,
+                to: Some("10.0.3.0/24".to_string()),
+                proto: Some(17)
+            }]),
             auto_claim: None,
             port_forwarding: Some(true),
+            pmtu_discovery: Some(true),
+            send_pacing_kbps: Some(10000),
+            send_queue_max_bytes: Some(65536),
+            reorder_buffer: Some(true),
+            reorder_timeout_ms: Some(20),
+            reorder_buffer_max_packets: Some(16),
+            fragment_timeout_ms: Some(800),
+            fragment_max_buffers: Some(8),
+            init_timeout_ms: Some(300),
+            init_retries: Some(5),
+            hole_punch: Some(false),
+            turn_servers: Some(vec![TurnServer {
+                url: "turn.example.com".to_string(),
+                username: "user".to_string(),
+                password: "pass".to_string()
+            }]),
             user: Some("nobody".to_string()),
             group: Some("nogroup".to_string()),
             pid_file: Some("/run/vpncloud.run".to_string()),
+            table_persistence_path: Some("/var/lib/vpncloud/table".to_string()),
             stats_file: Some("/var/log/vpncloud.stats".to_string()),
+            audit_log: Some("/var/log/vpncloud.audit".to_string()),
+            audit_log_max_bytes: Some(1000000),
+            peer_event_webhook: Some("http://localhost:8500/v1/event".to_string()),
+            webhook_secret: Some("secret".to_string()),
+            tofu_store: Some("/var/lib/vpncloud/tofu.json".to_string()),
+            tofu_mode: Some(TofuMode::DenyUnknown),
+            peer_state_file: Some("/var/lib/vpncloud/peers.state".to_string()),
+            identity_key: Some("/var/lib/vpncloud/identity.key".to_string()),
+            noise_protocol: Some(true),
+            noise_key: Some("/var/lib/vpncloud/noise.key".to_string()),
+            vxlan_vni: Some(4242),
+            require_encryption: Some(false),
+            plaintext_peers: Some(vec![SocketAddr::from(([192, 168, 1, 2], 3210))]),
+            cni_ipam_file: Some("/var/lib/vpncloud/cni-ipam.json".to_string()),
+            stats_format: Some(StatsFormat::Json),
+            traffic_stats_addresses: Some(500),
             statsd: Some(ConfigFileStatsd {
                 server: Some("example.com:1234".to_string()),
                 prefix: Some("prefix".to_string())
             }),
+            influx: Some(ConfigFileInflux {
+                addr: Some(SocketAddr::from(([192, 168, 1, 10], 8089))),
+                measurement: Some("vpn".to_string())
+            }),
+            snmp: Some(ConfigFileSnmp {
+                addr: Some(SocketAddr::from(([127, 0, 0, 1], 161))),
+                community: Some("private".to_string()),
+                trap_receiver: Some(SocketAddr::from(([192, 168, 1, 10], 162)))
+            }),
+            capture: Some(ConfigFileCapture {
+                file: Some("/tmp/vpncloud.pcapng".to_string()),
+                layer: Some(CaptureLayer::Both),
+                max_bytes: Some(1_000_000),
+                filter_peer: None
+            }),
+            otel_endpoint: Some("http://localhost:4318/v1/traces".to_string()),
+            api: Some(ConfigFileApi {
+                addr: Some("127.0.0.1:8080".to_string()),
+                token: Some("secret".to_string()),
+                tls_cert: None,
+                tls_key: None,
+                grpc_addr: Some("127.0.0.1:50051".to_string())
+            }),
             hook: None,
-            hooks: HashMap::new()
+            hooks: HashMap::new(),
+            local_tags: vec![("dc".to_string(), "eu-west-1".to_string())].into_iter().collect()
         }
     )
 }
@@ -735,37 +2815,118 @@ fn config_merge() {
             name: Some("vpncloud%d".to_string()),
             path: None,
             fix_rp_filter: None,
+            mtu: None,
+            persistent: None,
+            mac: None,
+            mac_from_node_id: None,
         }),
         ip: None,
         advertise_addresses: Some(vec![]),
         ifup: Some("ifconfig $IFNAME 10.0.1.1/16 mtu 1400 up".to_string()),
         ifdown: Some("true".to_string()),
+        sync_routes: None,
+        local_tunnel_ip: None,
+        sync_routes_dry_run: None,
+        node_id: None,
         crypto: CryptoConfig::default(),
         listen: None,
         peers: Some(vec!["remote.machine.foo:3210".to_string(), "remote.machine.bar:3210".to_string()]),
+        bootstrap_dns: None,
         peer_timeout: Some(600),
         keepalive: Some(840),
+        max_peers: None,
+        max_reconnect_peers: None,
+        reconnect_jitter: None,
+        dpd_probe_interval: None,
+        dpd_retries: None,
+        nat_keepalive: None,
+        gossip_fanout: None,
+        gossip_interval: None,
+        gossip_full_interval_secs: None,
+        latency_routing: None,
         beacon: Some(ConfigFileBeacon {
             store: Some("/run/vpncloud.beacon.out".to_string()),
             load: Some("/run/vpncloud.beacon.in".to_string()),
             interval: Some(7200),
             password: Some("test123".to_string()),
+            encrypt: Some(false),
+            allow_plaintext: Some(true),
+            dns_domain: Some("vpn.example.com".to_string()),
+            dns_key_file: Some("/etc/vpncloud/dns.key".to_string()),
         }),
         mode: Some(Mode::Normal),
+        address_family: None,
         switch_timeout: Some(300),
+        igmp_timeout: None,
+        arp_proxy: None,
+        arp_proxy_ttl_secs: None,
+        flap_window_secs: None,
+        max_flaps: None,
+        max_table_entries: None,
+        buffer_pool_size: None,
+        receive_threads: None,
+        socket_recv_buffer_bytes: None,
+        socket_send_buffer_bytes: None,
+        local_shortcut: None,
+        pad_to: None,
+        pad_amount: None,
+        dscp: None,
+        dscp_inherit: None,
         claims: Some(vec!["10.0.1.0/24".to_string()]),
+        excluded_routes: Some(vec!["192.168.0.0/16".to_string()]),
+        policy_rules: None,
+        policy_tables: None,
+        acl: None,
         auto_claim: Some(true),
         port_forwarding: Some(true),
+        pmtu_discovery: None,
+        send_pacing_kbps: None,
+        send_queue_max_bytes: None,
+        reorder_buffer: None,
+        reorder_timeout_ms: None,
+        reorder_buffer_max_packets: None,
+        fragment_timeout_ms: None,
+        fragment_max_buffers: None,
+        init_timeout_ms: None,
+        init_retries: None,
+        hole_punch: None,
+        turn_servers: None,
         user: Some("nobody".to_string()),
         group: Some("nogroup".to_string()),
         pid_file: Some("/run/vpncloud.run".to_string()),
+        table_persistence_path: None,
         stats_file: Some("/var/log/vpncloud.stats".to_string()),
+        audit_log: None,
+        audit_log_max_bytes: None,
+        peer_event_webhook: None,
+        webhook_secret: None,
+        tofu_store: None,
+        tofu_mode: None,
+        peer_state_file: None,
+        identity_key: None,
+        noise_protocol: None,
+        noise_key: None,
+        vxlan_vni: None,
+        require_encryption: None,
+        plaintext_peers: None,
+        cni_ipam_file: None,
+        stats_format: None,
+        traffic_stats_addresses: None,
         statsd: Some(ConfigFileStatsd {
             server: Some("example.com:1234".to_string()),
             prefix: Some("prefix".to_string()),
         }),
+        influx: Some(ConfigFileInflux {
+            addr: Some(SocketAddr::from(([192, 168, 1, 10], 8089))),
+            measurement: Some("vpn".to_string()),
+        }),
+        snmp: None,
+        capture: None,
+        otel_endpoint: None,
+        api: None,
         hook: None,
         hooks: HashMap::new(),
+        local_tags: HashMap::new(),
     });
     assert_eq!(
         config,
@@ -786,44 +2947,133 @@ fn config_merge() {
             beacon_load: Some("/run/vpncloud.beacon.in".to_string()),
             beacon_interval: 7200,
             beacon_password: Some("test123".to_string()),
+            beacon_encrypt: false,
+            beacon_allow_plaintext: true,
+            dns_beacon_domain: Some("vpn.example.com".to_string()),
+            dns_key_file: Some("/etc/vpncloud/dns.key".to_string()),
             mode: Mode::Normal,
             port_forwarding: true,
             claims: vec!["10.0.1.0/24".to_string()],
+            excluded_routes: vec!["192.168.0.0/16".to_string()],
             user: Some("nobody".to_string()),
             group: Some("nogroup".to_string()),
             pid_file: Some("/run/vpncloud.run".to_string()),
             stats_file: Some("/var/log/vpncloud.stats".to_string()),
             statsd_server: Some("example.com:1234".to_string()),
             statsd_prefix: Some("prefix".to_string()),
+            influx_addr: Some(SocketAddr::from(([192, 168, 1, 10], 8089))),
+            influx_measurement: "vpn".to_string(),
             ..Default::default()
         }
     );
     config.merge_args(Args {
         type_: Some(Type::Tap),
+        mtu: Some(1360),
+        device_persistent: true,
+        tap_mac_from_node_id: true,
         device: Some("vpncloud0".to_string()),
         device_path: Some("/dev/null".to_string()),
         ifup: Some("ifconfig $IFNAME 10.0.1.2/16 mtu 1400 up".to_string()),
         ifdown: Some("ifconfig $IFNAME down".to_string()),
+        sync_routes: true,
+        node_id: Some("00112233445566778899aabbccddeeff".to_string()),
         password: Some("anothersecret".to_string()),
         listen: Some("[::]:3211".to_string()),
         peer_timeout: Some(1801),
         keepalive: Some(850),
+        max_peers: Some(200),
+        max_reconnect_peers: Some(16),
+        no_reconnect_jitter: true,
+        dpd_probe_interval: Some(10),
+        dpd_retries: Some(2),
+        nat_keepalive: Some(15),
+        gossip_fanout: Some(8),
+        gossip_interval: Some(30),
+        gossip_full_interval_secs: Some(1200),
+        latency_routing: true,
         switch_timeout: Some(301),
+        igmp_timeout: Some(100),
+        arp_proxy: true,
+        arp_proxy_ttl_secs: Some(600),
+        max_table_entries: Some(2000),
+        buffer_pool_size: Some(128),
+        receive_threads: Some(4),
+        socket_recv_buffer_bytes: Some(8388608),
+        socket_send_buffer_bytes: None,
+        local_shortcut: true,
+        pad_to: Some(1200),
+        pad_amount: Some(64),
+        dscp: Some(34),
+        dscp_inherit: true,
         beacon_store: Some("/run/vpncloud.beacon.out2".to_string()),
         beacon_load: Some("/run/vpncloud.beacon.in2".to_string()),
         beacon_interval: Some(3600),
         beacon_password: Some("test1234".to_string()),
+        no_beacon_encrypt: true,
+        beacon_allow_plaintext: true,
+        dns_beacon_domain: Some("vpn2.example.com".to_string()),
+        dns_key_file: Some("/etc/vpncloud/dns2.key".to_string()),
         mode: Some(Mode::Switch),
+        address_family: Some(AddressFamily::Ipv4Only),
         claims: vec![],
+        excluded_routes: vec!["172.16.0.0/12".to_string()],
+        policy_tables: vec!["1 via 10.0.0.1".parse().unwrap()],
+        acl: vec!["allow from 10.0.4.0/24".parse().unwrap()],
+        tags: vec!["role=gateway".to_string()],
         peers: vec!["another:3210".to_string()],
+        bootstrap_dns: vec!["vpn2.example.com".to_string()],
         no_port_forwarding: true,
+        pmtu_discovery: true,
+        send_pacing_kbps: Some(20000),
+        send_queue_max_bytes: Some(131072),
+        reorder_buffer: true,
+        reorder_timeout_ms: Some(30),
+        reorder_buffer_max_packets: Some(64),
+        fragment_timeout_ms: Some(250),
+        fragment_max_buffers: Some(16),
+        init_timeout_ms: Some(400),
+        init_retries: Some(6),
+        no_hole_punch: true,
+        turn_servers: vec!["user:pass@turn.example.com:3478".parse().unwrap()],
         daemon: true,
         pid_file: Some("/run/vpncloud-mynet.run".to_string()),
+        table_persistence_path: Some("/var/lib/vpncloud/mynet".to_string()),
         stats_file: Some("/var/log/vpncloud-mynet.stats".to_string()),
+        audit_log: Some("/var/log/vpncloud-mynet.audit".to_string()),
+        audit_log_max_bytes: Some(2000000),
+        peer_event_webhook: Some("http://localhost:8080/events".to_string()),
+        tofu_store: Some("/var/lib/vpncloud/mynet.tofu".to_string()),
+        tofu_mode: Some(TofuMode::DenyUnknown),
+        peer_state_file: Some("/var/lib/vpncloud/mynet.peers".to_string()),
+        identity_key: Some("/var/lib/vpncloud/mynet.identity".to_string()),
+        noise_protocol: true,
+        noise_key: Some("/var/lib/vpncloud/mynet.noise".to_string()),
+        vxlan_vni: Some(1234),
+        no_require_encryption: true,
+        plaintext_peers: vec![SocketAddr::from(([192, 168, 1, 3], 3210))],
+        cni_ipam_file: Some("/var/lib/vpncloud/mynet-ipam.json".to_string()),
+        stats_format: Some(StatsFormat::Json),
+        traffic_stats_addresses: Some(2000),
         statsd_server: Some("example.com:2345".to_string()),
         statsd_prefix: Some("prefix2".to_string()),
+        influx_addr: Some(SocketAddr::from(([192, 168, 1, 11], 8089))),
+        influx_measurement: Some("vpn2".to_string()),
+        snmp_addr: Some(SocketAddr::from(([127, 0, 0, 1], 1161))),
+        snmp_community: Some("secret".to_string()),
+        snmp_trap_receiver: Some(SocketAddr::from(([192, 168, 1, 11], 162))),
+        capture_file: Some("/tmp/capture.pcapng".to_string()),
+        capture_layer: Some(CaptureLayer::Device),
+        capture_max_bytes: Some(1000),
+        capture_filter_peer: Some(SocketAddr::from(([192, 168, 1, 12], 3210))),
+        otel_endpoint: Some("http://localhost:4318/v1/traces".to_string()),
+        api_addr: Some("127.0.0.1:8081".to_string()),
+        api_token: Some("secret2".to_string()),
+        api_tls_cert: Some("/etc/vpncloud/api.crt".to_string()),
+        api_tls_key: Some("/etc/vpncloud/api.key".to_string()),
+        grpc_addr: Some("127.0.0.1:50052".to_string()),
         user: Some("root".to_string()),
         group: Some("root".to_string()),
+        max_flaps: Some(3),
         ..Default::default()
     });
     assert_eq!(
@@ -833,11 +3083,19 @@ fn config_merge() {
             device_name: "vpncloud0".to_string(),
             device_path: Some("/dev/null".to_string()),
             fix_rp_filter: false,
+            mtu: Some(1360),
+            device_persistent: true,
+            tap_mac: None,
+            tap_mac_from_node_id: true,
             ip: None,
             advertise_addresses: vec![],
 
             ifup: Some("ifconfig $IFNAME 10.0.1.2/16 mtu 1400 up".to_string()),
             ifdown: Some("ifconfig $IFNAME down".to_string()),
+            sync_routes: true,
+            local_tunnel_ip: None,
+            sync_routes_dry_run: false,
+            node_id: Some("00112233445566778899aabbccddeeff".to_string()),
             crypto: CryptoConfig { password: Some("anothersecret".to_string()), ..CryptoConfig::default() },
             listen: "[::]:3211".to_string(),
             peers: vec![
@@ -845,26 +3103,260 @@ fn config_merge() {
                 "remote.machine.bar:3210".to_string(),
                 "another:3210".to_string()
             ],
+            bootstrap_dns: vec!["vpn2.example.com".to_string()],
             peer_timeout: 1801,
             keepalive: Some(850),
+            max_peers: Some(200),
+            max_reconnect_peers: 16,
+            reconnect_jitter: false,
+            dpd_probe_interval: 10,
+            dpd_retries: 2,
+            nat_keepalive: 15,
+            gossip_fanout: 8,
+            gossip_interval: Some(30),
+            gossip_full_interval_secs: 1200,
+            latency_routing: true,
             switch_timeout: 301,
+            igmp_timeout: 100,
+            arp_proxy: true,
+            arp_proxy_ttl_secs: 600,
+            flap_window_secs: 60,
+            max_flaps: 3,
+            max_table_entries: 2000,
+            buffer_pool_size: 128,
+            receive_threads: 4,
+            socket_recv_buffer_bytes: Some(8388608),
+            socket_send_buffer_bytes: None,
+            local_shortcut: true,
+            pad_to: Some(1200),
+            pad_amount: 64,
+            dscp: Some(34),
+            dscp_inherit: true,
             beacon_store: Some("/run/vpncloud.beacon.out2".to_string()),
             beacon_load: Some("/run/vpncloud.beacon.in2".to_string()),
             beacon_interval: 3600,
             beacon_password: Some("test1234".to_string()),
+            beacon_encrypt: false,
+            beacon_allow_plaintext: true,
+            dns_beacon_domain: Some("vpn2.example.com".to_string()),
+            dns_key_file: Some("/etc/vpncloud/dns2.key".to_string()),
             mode: Mode::Switch,
+            address_family: AddressFamily::Ipv4Only,
             port_forwarding: false,
+            pmtu_discovery: true,
+            send_pacing_kbps: Some(20000),
+            send_queue_max_bytes: 131072,
+            reorder_buffer: true,
+            reorder_timeout_ms: 30,
+            reorder_buffer_max_packets: 64,
+            fragment_timeout_ms: 250,
+            fragment_max_buffers: 16,
+            init_timeout_ms: 400,
+            init_retries: 6,
+            hole_punch: false,
+            turn_servers: vec![TurnServer {
+                url: "turn.example.com:3478".to_string(),
+                username: "user".to_string(),
+                password: "pass".to_string()
+            }],
             claims: vec!["10.0.1.0/24".to_string()],
+            excluded_routes: vec!["192.168.0.0/16".to_string(), "172.16.0.0/12".to_string()],
+            policy_rules: vec![],
+            policy_tables: vec![PolicyTableConfig { id: 1, via: "10.0.0.1".to_string() }],
+            acl: vec![AclRuleConfig {
+                action: AclAction::Allow,
+                from: Some("10.0.4.0/24".to_string()),
+                to: None,
+                proto: None
+            }],
             auto_claim: true,
             user: Some("root".to_string()),
             group: Some("root".to_string()),
             pid_file: Some("/run/vpncloud-mynet.run".to_string()),
+            table_persistence_path: Some("/var/lib/vpncloud/mynet".to_string()),
             stats_file: Some("/var/log/vpncloud-mynet.stats".to_string()),
+            audit_log: Some("/var/log/vpncloud-mynet.audit".to_string()),
+            audit_log_max_bytes: 2000000,
+            peer_event_webhook: Some("http://localhost:8080/events".to_string()),
+            webhook_secret: None,
+            tofu_store: Some("/var/lib/vpncloud/mynet.tofu".to_string()),
+            tofu_mode: TofuMode::DenyUnknown,
+            peer_state_file: Some("/var/lib/vpncloud/mynet.peers".to_string()),
+            identity_key: Some("/var/lib/vpncloud/mynet.identity".to_string()),
+            noise_protocol: true,
+            noise_key: Some("/var/lib/vpncloud/mynet.noise".to_string()),
+            vxlan_vni: Some(1234),
+            require_encryption: false,
+            plaintext_peers: vec![SocketAddr::from(([192, 168, 1, 3], 3210))],
+            cni_ipam_file: Some("/var/lib/vpncloud/mynet-ipam.json".to_string()),
+            stats_format: StatsFormat::Json,
+            traffic_stats_addresses: 2000,
             statsd_server: Some("example.com:2345".to_string()),
             statsd_prefix: Some("prefix2".to_string()),
+            influx_addr: Some(SocketAddr::from(([192, 168, 1, 11], 8089))),
+            influx_measurement: "vpn2".to_string(),
+            snmp_addr: Some(SocketAddr::from(([127, 0, 0, 1], 1161))),
+            snmp_community: "secret".to_string(),
+            snmp_trap_receiver: Some(SocketAddr::from(([192, 168, 1, 11], 162))),
+            capture_file: Some("/tmp/capture.pcapng".to_string()),
+            capture_layer: CaptureLayer::Device,
+            capture_max_bytes: 1000,
+            capture_filter_peer: Some(SocketAddr::from(([192, 168, 1, 12], 3210))),
+            otel_endpoint: Some("http://localhost:4318/v1/traces".to_string()),
+            api_addr: Some("127.0.0.1:8081".to_string()),
+            api_token: Some("secret2".to_string()),
+            api_tls_cert: Some("/etc/vpncloud/api.crt".to_string()),
+            api_tls_key: Some("/etc/vpncloud/api.key".to_string()),
+            grpc_addr: Some("127.0.0.1:50052".to_string()),
             daemonize: true,
             hook: None,
-            hooks: HashMap::new()
+            hooks: HashMap::new(),
+            local_tags: vec![("role".to_string(), "gateway".to_string())].into_iter().collect()
         }
     );
 }
+
+#[test]
+fn turn_server_address() {
+    let server: TurnServer = "user:pass:word@turn:example.com".parse().unwrap();
+    assert_eq!(server.username, "user");
+    assert_eq!(server.password, "pass:word");
+    assert_eq!(server.address(), "example.com:3478");
+    let server: TurnServer = "user:pass@[::1]:5000".parse().unwrap();
+    assert_eq!(server.address(), "[::1]:5000");
+    assert!("example.com:3478".parse::<TurnServer>().is_err());
+}
+
+#[test]
+fn policy_rule_parse() {
+    let rule: PolicyRuleConfig = "from 10.1.0.0/16 table 1".parse().unwrap();
+    assert_eq!(rule, PolicyRuleConfig { from: Some("10.1.0.0/16".to_string()), to: None, table: 1 });
+    let rule: PolicyRuleConfig = "to 10.2.0.0/16 from 10.1.0.0/16 table 2".parse().unwrap();
+    assert_eq!(rule.to, Some("10.2.0.0/16".to_string()));
+    assert!("from 10.1.0.0/16".parse::<PolicyRuleConfig>().is_err());
+    assert!("from 10.1.0.0/16 table".parse::<PolicyRuleConfig>().is_err());
+    let table: PolicyTableConfig = "1 via 10.0.0.1".parse().unwrap();
+    assert_eq!(table, PolicyTableConfig { id: 1, via: "10.0.0.1".to_string() });
+    assert!("1 10.0.0.1".parse::<PolicyTableConfig>().is_err());
+}
+
+#[test]
+fn acl_rule_parse() {
+    let rule: AclRuleConfig = "deny from 10.1.0.0/16 proto 6".parse().unwrap();
+    assert_eq!(
+        rule,
+        AclRuleConfig { action: AclAction::Deny, from: Some("10.1.0.0/16".to_string()), to: None, proto: Some(6) }
+    );
+    let rule: AclRuleConfig = "Allow".parse().unwrap();
+    assert_eq!(rule.action, AclAction::Allow);
+    assert!("drop from 10.1.0.0/16".parse::<AclRuleConfig>().is_err());
+    assert!("deny proto tcp".parse::<AclRuleConfig>().is_err());
+}
+
+#[test]
+fn config_validate() {
+    let valid = Config {
+        crypto: CryptoConfig { password: Some("test".to_string()), ..Default::default() },
+        ..Default::default()
+    };
+    assert_eq!(valid.validate(), Ok(()));
+    let invalid = |f: &dyn Fn(&mut Config)| {
+        let mut config = valid.clone();
+        f(&mut config);
+        config.validate().unwrap_err()
+    };
+    use ConfigError::*;
+    assert_eq!(invalid(&|c| c.crypto.password = None), vec![MissingCredentials]);
+    assert_eq!(invalid(&|c| c.listen = "70000".to_string()), vec![PortOutOfRange("70000".to_string())]);
+    assert_eq!(invalid(&|c| c.listen = "*:99999".to_string()), vec![PortOutOfRange("*:99999".to_string())]);
+    assert_eq!(invalid(&|c| c.listen = "1.2.3:3210".to_string()), vec![InvalidAddress("1.2.3:3210".to_string())]);
+    assert_eq!(
+        invalid(&|c| c.peers = vec!["node1:65536".to_string()]),
+        vec![PortOutOfRange("node1:65536".to_string())]
+    );
+    assert_eq!(invalid(&|c| c.peers = vec!["node1:port".to_string()]), vec![InvalidAddress("node1:port".to_string())]);
+    assert_eq!(invalid(&|c| c.ip = Some("10.0.0.1/33".to_string())), vec![InvalidAddress("10.0.0.1/33".to_string())]);
+    assert_eq!(invalid(&|c| c.claims = vec!["10.0.0.0".to_string()]), vec![InvalidRange("10.0.0.0".to_string())]);
+    assert_eq!(invalid(&|c| c.node_id = Some("abc".to_string())), vec![InvalidNodeId("abc".to_string())]);
+    assert_eq!(
+        invalid(&|c| {
+            c.device_type = Type::Tap;
+            c.tap_mac = Some("01:00:00:00:00:01".to_string());
+            c.tap_mac_from_node_id = true
+        }),
+        vec![InvalidMac("01:00:00:00:00:01".to_string()), ConflictingOptions("tap-mac", "tap-mac-from-node-id")]
+    );
+    assert_eq!(invalid(&|c| c.tap_mac_from_node_id = true), vec![ConflictingOptions("tap-mac", "device type tun")]);
+    assert_eq!(invalid(&|c| c.mtu = Some(100)), vec![MtuTooSmall(100)]);
+    assert_eq!(invalid(&|c| c.pad_to = Some(60000)), vec![PadToTooLarge(60000)]);
+    assert_eq!(invalid(&|c| c.dscp = Some(64)), vec![InvalidDscp(64)]);
+    assert_eq!(invalid(&|c| c.gossip_fanout = 1), vec![GossipFanoutTooSmall(1)]);
+    assert_eq!(invalid(&|c| c.peer_timeout = 60), vec![PeerTimeoutTooShort(60)]);
+    assert_eq!(invalid(&|c| c.keepalive = Some(300)), vec![PeerTimeoutTooShort(300)]);
+    assert_eq!(invalid(&|c| c.vxlan_vni = Some(1 << 24)), vec![InvalidVni(1 << 24)]);
+    assert_eq!(invalid(&|c| c.noise_protocol = true), vec![MissingOption("noise-protocol", "noise-key")]);
+    assert_eq!(
+        invalid(&|c| {
+            c.noise_protocol = true;
+            c.noise_key = Some("/var/lib/vpncloud/noise.key".to_string());
+            c.crypto.password = None;
+            c.crypto.private_key = Some("key".to_string())
+        }),
+        vec![MissingOption("noise-protocol", "password")]
+    );
+    assert_eq!(
+        invalid(&|c| c.plaintext_peers = vec![SocketAddr::from_str("1.2.3.4:3210").unwrap()]),
+        vec![ConflictingOptions("plaintext-peers", "require-encryption")]
+    );
+    assert_eq!(
+        invalid(&|c| c.api_tls_key = Some("/nonexistent/vpncloud.key".to_string())),
+        vec![
+            ConflictingOptions("api-tls-cert", "api-tls-key"),
+            UnreadableKeyFile(PathBuf::from("/nonexistent/vpncloud.key"))
+        ]
+    );
+    // All errors are reported at once
+    assert_eq!(
+        invalid(&|c| {
+            c.crypto.password = None;
+            c.listen = "65536".to_string();
+            c.dscp = Some(100)
+        }),
+        vec![MissingCredentials, PortOutOfRange("65536".to_string()), InvalidDscp(100)]
+    );
+    // Valid addresses
+    let mut config = valid.clone();
+    config.listen = "[::1]:3210".to_string();
+    config.peers = vec!["[::1]:3210".to_string(), "::1".to_string(), "node1".to_string(), "1.2.3.4:3210".to_string()];
+    config.ip = Some("10.0.0.1".to_string());
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn config_schema() {
+    let schema = ConfigFile::json_schema();
+    assert_eq!(schema["$schema"], "http://json-schema.org/draft-07/schema#");
+    let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+    let parse = |yaml: &str| serde_yaml::from_str::<serde_json::Value>(yaml).unwrap();
+    let is_valid = |yaml: &str| {
+        let config = parse(yaml);
+        if let Err(errors) = schema.validate(&config) {
+            for err in errors {
+                println!("{}: {}", err.instance_path, err);
+            }
+            return false
+        }
+        true
+    };
+    // Minimal config
+    assert!(is_valid("crypto:\n  password: test\n"));
+    // Maximal configs
+    assert!(is_valid(include_str!("../assets/example.net.disabled")));
+    assert!(schema.is_valid(&serde_json::to_value(Config::default().into_config_file()).unwrap()));
+    // Invalid configs
+    assert!(!is_valid("unknown-option: 1"));
+    assert!(!is_valid("mode: bridge"));
+    assert!(!is_valid("dscp: 64"));
+    assert!(!is_valid("device:\n  mtu: 100\n"));
+    assert!(!is_valid("peer-timeout: -1"));
+}
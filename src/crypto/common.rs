@@ -1,31 +1,46 @@
 use super::{
-    core::{test_speed, CryptoCore},
-    init::{self, InitResult, InitState, CLOSING},
+    core::{test_speed, CpuFeatures, CryptoCore},
+    init::{self, Fingerprint, InitResult, InitState, CLOSING},
+    kem,
     rotate::RotationState,
 };
 use crate::{
+    cert_auth::{self, CertAuth},
     error::Error,
+    noise::NoiseTransport,
     types::NodeId,
-    util::{from_base62, to_base62, MsgBuffer},
+    util::{from_base62, hex_to_bytes, to_base62, MsgBuffer},
 };
+use argon2::{Argon2, Params, Version};
 use ring::{
     aead::{self, Algorithm, LessSafeKey, UnboundKey},
     agreement::{EphemeralPrivateKey, UnparsedPublicKey},
-    pbkdf2,
     rand::{SecureRandom, SystemRandom},
     signature::{Ed25519KeyPair, KeyPair, ED25519_PUBLIC_KEY_LEN},
 };
+use schemars::JsonSchema;
 use smallvec::{smallvec, SmallVec};
-use std::{fmt::Debug, io::Read, num::NonZeroU32, sync::Arc, time::Duration};
+use std::{
+    fmt::Debug,
+    io::Read,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-const SALT: &[u8; 32] = b"vpncloudVPNCLOUDvpncl0udVpnCloud";
-const INIT_MESSAGE_FIRST_BYTE: u8 = 0xff;
+const SALT: &[u8; 16] = b"vpncloudArgon2id";
+const PEER_KEY_SALT: &[u8; 16] = b"vpncloudPeerKeys";
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 65536;
+pub const DEFAULT_ARGON2_ITERATIONS: u32 = 3;
+const MIN_PASSWORD_LEN: usize = 12;
+pub const INIT_MESSAGE_FIRST_BYTE: u8 = 0xff;
 const MESSAGE_TYPE_ROTATION: u8 = 0x10;
 
 pub type Ed25519PublicKey = [u8; ED25519_PUBLIC_KEY_LEN];
 pub type EcdhPublicKey = UnparsedPublicKey<SmallVec<[u8; 96]>>;
 pub type EcdhPrivateKey = EphemeralPrivateKey;
 pub type Key = SmallVec<[u8; 32]>;
+pub type PeerKey = [u8; 32];
 
 const DEFAULT_ALGORITHMS: [&str; 3] = ["AES128", "AES256", "CHACHA20"];
 
@@ -35,6 +50,8 @@ const SPEED_TEST_TIME: f32 = 0.02;
 const SPEED_TEST_TIME: f32 = 0.1;
 
 const ROTATE_INTERVAL: usize = 120;
+/// Seconds in which a Noise peer has to send its first message to confirm the handshake
+const NOISE_CONFIRM_TIMEOUT: usize = 10;
 
 pub trait Payload: Debug + PartialEq + Sized {
     fn write_to(&self, buffer: &mut MsgBuffer);
@@ -47,21 +64,67 @@ pub struct Algorithms {
     pub allow_unencrypted: bool,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+#[schemars(rename = "CryptoConfig")]
 pub struct Config {
+    /// Shared password of the network
     pub password: Option<String>,
+    /// Private key of this node
     pub private_key: Option<String>,
+    /// Public key of this node
     pub public_key: Option<String>,
+    /// Public keys of the trusted peers
     pub trusted_keys: Vec<String>,
+    /// Allowed encryption algorithms
     pub algorithms: Vec<String>,
+    /// Combine the key exchange with a post-quantum KEM
+    pub pq_kem: bool,
+    /// CA certificate that signs the node certificates
+    pub ca_cert: Option<String>,
+    /// Certificate of this node
+    pub node_cert: Option<String>,
+    /// Key of the node certificate
+    pub node_key: Option<String>,
+    /// Keys that are only shared with specific peers
+    pub peer_keys: Vec<PeerKeyEntry>,
+    /// Memory in KiB used to derive the key from the password
+    pub argon2_memory_kib: Option<u32>,
+    /// Iterations used to derive the key from the password
+    pub argon2_iterations: Option<u32>,
+}
+
+/// A key that is only shared with the peer with the given node id
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PeerKeyEntry {
+    /// Node id of the peer
+    pub node_id: String,
+    /// Key shared with the peer
+    pub key: String,
+}
+
+impl FromStr for PeerKeyEntry {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = text.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(node_id), Some(key)) => Ok(Self { node_id: node_id.to_string(), key: key.to_string() }),
+            _ => Err("Peer key must be given as NODE_ID:KEY"),
+        }
+    }
 }
 
 pub struct Crypto {
     node_id: NodeId,
     key_pair: Arc<Ed25519KeyPair>,
     trusted_keys: Arc<[Ed25519PublicKey]>,
+    cert_auth: Option<Arc<CertAuth>>,
+    peer_keys: Arc<[(NodeId, PeerKey)]>,
     algorithms: Algorithms,
+    cpu_features: CpuFeatures,
+    pq_kem: bool,
 }
 
 impl Crypto {
@@ -87,17 +150,33 @@ impl Crypto {
     }
 
     pub fn new(node_id: NodeId, config: &Config) -> Result<Self, Error> {
-        let key_pair = if let Some(priv_key) = &config.private_key {
+        let key_pair = if let Some(node_key) = &config.node_key {
+            cert_auth::load_node_key(node_key)?
+        } else if let Some(priv_key) = &config.private_key {
             if let Some(pub_key) = &config.public_key {
                 Self::parse_keypair(priv_key, pub_key)?
             } else {
                 Self::parse_private_key(priv_key)?
             }
         } else if let Some(password) = &config.password {
-            Self::keypair_from_password(password)
+            if password.len() < MIN_PASSWORD_LEN {
+                warn!("The password is shorter than {} characters, it can easily be guessed", MIN_PASSWORD_LEN)
+            }
+            info!("Deriving the key pair from the password with Argon2id, nodes still using PBKDF2 can not connect");
+            Self::keypair_from_password(password, config)?
         } else {
             return Err(Error::InvalidConfig("Either private_key or password must be set"));
         };
+        let cert_auth = match (&config.ca_cert, &config.node_cert) {
+            (Some(ca_cert), Some(node_cert)) => {
+                if config.node_key.is_none() {
+                    return Err(Error::InvalidConfig("Certificate authentication requires node_key to be set"));
+                }
+                Some(Arc::new(CertAuth::load(ca_cert, node_cert)?))
+            }
+            (None, None) => None,
+            _ => return Err(Error::InvalidConfig("ca_cert and node_cert must be set together")),
+        };
         let mut trusted_keys = vec![];
         for tn in &config.trusted_keys {
             trusted_keys.push(Self::parse_public_key(tn)?);
@@ -108,10 +187,20 @@ impl Crypto {
             key.clone_from_slice(key_pair.public_key().as_ref());
             trusted_keys.push(key);
         }
+        let mut peer_keys = vec![];
+        for entry in &config.peer_keys {
+            let key = Self::derive_key(&entry.key, PEER_KEY_SALT, config)?;
+            peer_keys.push((Self::parse_node_id(&entry.node_id)?, key));
+        }
+        if config.pq_kem && !kem::SUPPORTED {
+            return Err(Error::InvalidConfig("Post-quantum key exchange is not supported by this build"));
+        }
         let (unencrypted, allowed_algos) = Self::parse_algorithms(&config.algorithms)?;
         if unencrypted {
             warn!("Crypto settings allow unencrypted connections")
         }
+        let cpu_features = CpuFeatures::detect();
+        info!("Using {} AES implementation (CPU features: {:?})", cpu_features.aes_backend(), cpu_features.names());
         let mut algos = Algorithms { algorithm_speeds: smallvec![], allow_unencrypted: unencrypted };
         let duration = Duration::from_secs_f32(SPEED_TEST_TIME);
         let mut speeds = Vec::new();
@@ -130,10 +219,20 @@ impl Crypto {
             node_id,
             key_pair: Arc::new(key_pair),
             trusted_keys: trusted_keys.into_boxed_slice().into(),
+            cert_auth,
+            peer_keys: peer_keys.into_boxed_slice().into(),
             algorithms: algos,
+            cpu_features,
+            pq_kem: config.pq_kem,
         })
     }
 
+    /// The CPU features that have been detected at startup
+    pub fn cpu_features(&self) -> CpuFeatures {
+        self.cpu_features
+    }
+
+    /// Generates a random key pair or derives it from the password with the default Argon2 parameters
     pub fn generate_keypair(password: Option<&str>) -> (String, String) {
         let mut bytes = [0; 32];
         match password {
@@ -141,15 +240,7 @@ impl Crypto {
                 let rng = SystemRandom::new();
                 rng.fill(&mut bytes).unwrap();
             }
-            Some(password) => {
-                pbkdf2::derive(
-                    pbkdf2::PBKDF2_HMAC_SHA256,
-                    NonZeroU32::new(4096).unwrap(),
-                    SALT,
-                    password.as_bytes(),
-                    &mut bytes,
-                );
-            }
+            Some(password) => bytes = Self::derive_key(password, SALT, &Config::default()).unwrap(),
         }
         let keypair = Ed25519KeyPair::from_seed_unchecked(&bytes).unwrap();
         let privkey = to_base62(&bytes);
@@ -157,10 +248,37 @@ impl Crypto {
         (privkey, pubkey)
     }
 
-    fn keypair_from_password(password: &str) -> Ed25519KeyPair {
+    fn keypair_from_password(password: &str, config: &Config) -> Result<Ed25519KeyPair, Error> {
+        let key = Self::derive_key(password, SALT, config)?;
+        Ok(Ed25519KeyPair::from_seed_unchecked(&key).unwrap())
+    }
+
+    /// Derives a 32 byte key from the password with Argon2id
+    ///
+    /// The salt is fixed, so that all nodes derive the same key from the same password.
+    fn derive_key(password: &str, salt: &[u8], config: &Config) -> Result<[u8; 32], Error> {
+        let params = Params::new(
+            config.argon2_memory_kib.unwrap_or(DEFAULT_ARGON2_MEMORY_KIB),
+            config.argon2_iterations.unwrap_or(DEFAULT_ARGON2_ITERATIONS),
+            1,
+            Some(32),
+        )
+        .map_err(|_| Error::InvalidConfig("Invalid Argon2 parameters"))?;
         let mut key = [0; 32];
-        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, NonZeroU32::new(4096).unwrap(), SALT, password.as_bytes(), &mut key);
-        Ed25519KeyPair::from_seed_unchecked(&key).unwrap()
+        Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut key)
+            .map_err(|_| Error::InvalidConfig("Failed to derive key from password"))?;
+        Ok(key)
+    }
+
+    pub fn parse_node_id(node_id: &str) -> Result<NodeId, Error> {
+        let bytes = hex_to_bytes(node_id).ok_or(Error::InvalidConfig("Failed to parse node id"))?;
+        let mut result = NodeId::default();
+        if bytes.len() != result.len() {
+            return Err(Error::InvalidConfig("Failed to parse node id"))
+        }
+        result.clone_from_slice(&bytes);
+        Ok(result)
     }
 
     fn parse_keypair(privkey: &str, pubkey: &str) -> Result<Ed25519KeyPair, Error> {
@@ -193,13 +311,35 @@ impl Crypto {
         Ok(to_base62(keypair.public_key().as_ref()))
     }
 
-    pub fn peer_instance<P: Payload>(&self, payload: P) -> PeerCrypto<P> {
+    /// Creates the crypto instance of a new connection, the clock is used to time the retransmits of the handshake
+    pub fn peer_instance<P: Payload>(&self, payload: P, clock: fn() -> Instant) -> PeerCrypto<P> {
         PeerCrypto::new(
             self.node_id,
             payload,
             self.key_pair.clone(),
             self.trusted_keys.clone(),
+            self.cert_auth.clone(),
+            self.peer_keys.clone(),
             self.algorithms.clone(),
+            self.pq_kem,
+            clock,
+        )
+    }
+
+    /// Creates an instance that prefers to leave the messages unencrypted if the peer allows it as well
+    pub fn plaintext_peer_instance<P: Payload>(&self, payload: P, clock: fn() -> Instant) -> PeerCrypto<P> {
+        let mut algorithms = self.algorithms.clone();
+        algorithms.allow_unencrypted = true;
+        PeerCrypto::new(
+            self.node_id,
+            payload,
+            self.key_pair.clone(),
+            self.trusted_keys.clone(),
+            self.cert_auth.clone(),
+            self.peer_keys.clone(),
+            algorithms,
+            self.pq_kem,
+            clock,
         )
     }
 }
@@ -221,20 +361,61 @@ pub struct PeerCrypto<P: Payload> {
     unencrypted: bool,
     core: Option<CryptoCore>,
     rotate_counter: usize,
+    peer_fingerprint: Option<Fingerprint>,
+    peer_session_key: Option<Vec<u8>>,
+    noise: Option<NoiseTransport>,
+    /// Payload of a Noise peer until its first message confirms the handshake
+    noise_payload: Option<P>,
 }
 
 impl<P: Payload> PeerCrypto<P> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         node_id: NodeId, init_payload: P, key_pair: Arc<Ed25519KeyPair>, trusted_keys: Arc<[Ed25519PublicKey]>,
-        algorithms: Algorithms,
+        cert_auth: Option<Arc<CertAuth>>, peer_keys: Arc<[(NodeId, PeerKey)]>, algorithms: Algorithms, pq_kem: bool,
+        clock: fn() -> Instant,
     ) -> Self {
         Self {
             node_id,
-            init: Some(InitState::new(node_id, init_payload, key_pair, trusted_keys, algorithms)),
+            init: Some(InitState::new(
+                node_id,
+                init_payload,
+                key_pair,
+                trusted_keys,
+                cert_auth,
+                peer_keys,
+                algorithms,
+                pq_kem,
+                clock,
+            )),
+            rotation: None,
+            unencrypted: false,
+            core: None,
+            rotate_counter: 0,
+            peer_fingerprint: None,
+            peer_session_key: None,
+            noise: None,
+            noise_payload: None,
+        }
+    }
+
+    /// Creates an instance for a peer that connected via the Noise handshake
+    ///
+    /// As the responder can not verify the pre-shared key, the peer is only initialized when its first message can be
+    /// decrypted. That message is used as confirmation and not processed otherwise. The session key is the ephemeral
+    /// key of the initiator.
+    pub fn from_noise(node_id: NodeId, noise: NoiseTransport, peer_payload: P, session_key: &[u8]) -> Self {
+        Self {
+            node_id,
+            init: None,
             rotation: None,
             unencrypted: false,
             core: None,
             rotate_counter: 0,
+            peer_fingerprint: Some(*noise.fingerprint()),
+            peer_session_key: Some(session_key.to_vec()),
+            noise: Some(noise),
+            noise_payload: Some(peer_payload),
         }
     }
 
@@ -277,12 +458,69 @@ impl<P: Payload> PeerCrypto<P> {
         self.init.is_some()
     }
 
+    /// Repeats the last init message if the peer did not reply to it within the timeout
+    pub fn retransmit_init(
+        &mut self, now: Instant, timeout: Duration, max_retries: usize, out: &mut MsgBuffer,
+    ) -> bool {
+        out.clear();
+        if let Some(ref mut init) = self.init {
+            if init.retransmit(now, timeout, max_retries, out) {
+                out.prepend_byte(INIT_MESSAGE_FIRST_BYTE);
+                return true
+            }
+        }
+        false
+    }
+
     pub fn is_ready(&self) -> bool {
-        self.core.is_some()
+        self.core.is_some() || self.noise.is_some()
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        !self.unencrypted
+    }
+
+    pub fn is_noise(&self) -> bool {
+        self.noise.is_some()
+    }
+
+    /// Sequence number of the last decrypted message, if any
+    ///
+    /// There is none for unencrypted messages.
+    #[inline]
+    pub fn take_rx_seq(&mut self) -> Option<u64> {
+        self.core.as_mut().and_then(CryptoCore::take_last_seq)
+    }
+
+    /// Returns whether a new key has been used for sending since the last call
+    /// The SHA-256 hash of the public key or certificate the peer authenticated with
+    pub fn peer_fingerprint(&self) -> Option<&Fingerprint> {
+        self.peer_fingerprint.as_ref()
+    }
+
+    /// The key exchange key of the handshake that the payload of the peer has been bound to
+    pub fn peer_session_key(&self) -> Option<&[u8]> {
+        self.peer_session_key.as_deref()
+    }
+
+    /// The key exchange key of the handshake that the own payload can be bound to, while the handshake is running
+    pub fn session_key(&self) -> Option<&[u8]> {
+        self.init.as_ref().map(InitState::session_key)
+    }
+
+    /// The own payload of a running handshake
+    pub fn init_payload_mut(&mut self) -> Option<&mut P> {
+        self.init.as_mut().map(InitState::payload_mut)
+    }
+
+    pub fn take_key_rotated(&mut self) -> bool {
+        self.core.as_mut().map(CryptoCore::take_key_rotated).unwrap_or(false)
     }
 
     pub fn algorithm_name(&self) -> &'static str {
-        if let Some(ref core) = self.core {
+        if self.noise.is_some() {
+            "NOISE"
+        } else if let Some(ref core) = self.core {
             let algo = core.algorithm();
             if algo == &aead::CHACHA20_POLY1305 {
                 "CHACHA20"
@@ -307,6 +545,8 @@ impl<P: Payload> PeerCrypto<P> {
             InitResult::Continue => Ok(MessageResult::Reply),
             InitResult::Success { peer_payload, is_initiator } => {
                 self.core = self.get_init()?.take_core();
+                self.peer_fingerprint = self.get_init()?.peer_fingerprint();
+                self.peer_session_key = self.get_init()?.peer_session_key().map(<[u8]>::to_vec);
                 if self.core.is_none() {
                     self.unencrypted = true;
                 }
@@ -346,6 +586,9 @@ impl<P: Payload> PeerCrypto<P> {
         if self.unencrypted {
             return Ok(());
         }
+        if let Some(ref mut noise) = self.noise {
+            return noise.encrypt(buffer);
+        }
         self.get_core()?.encrypt(buffer);
         Ok(())
     }
@@ -355,6 +598,9 @@ impl<P: Payload> PeerCrypto<P> {
         if self.unencrypted {
             return Ok(());
         }
+        if let Some(ref mut noise) = self.noise {
+            return noise.decrypt(buffer);
+        }
         self.get_core()?.decrypt(buffer)
     }
 
@@ -372,6 +618,15 @@ impl<P: Payload> PeerCrypto<P> {
             // HOT PATH
             debug!("Received encrypted message");
             self.decrypt_message(buffer)?;
+            if let Some(ref mut init) = self.init {
+                // The peer completed the initialization, the last init message does not need to be repeated
+                init.confirm()
+            }
+            if let Some(payload) = self.noise_payload.take() {
+                // COLD PATH
+                buffer.clear();
+                return Ok(MessageResult::Initialized(payload));
+            }
             let msg_type = buffer.take_prefix();
             if msg_type == MESSAGE_TYPE_ROTATION {
                 // COLD PATH
@@ -394,6 +649,12 @@ impl<P: Payload> PeerCrypto<P> {
 
     pub fn every_second(&mut self, out: &mut MsgBuffer) -> Result<MessageResult<P>, Error> {
         out.clear();
+        if self.noise_payload.is_some() {
+            self.rotate_counter += 1;
+            if self.rotate_counter >= NOISE_CONFIRM_TIMEOUT {
+                return Err(Error::CryptoInitFatal("Noise handshake was not confirmed"));
+            }
+        }
         if let Some(ref mut core) = self.core {
             core.every_second()
         }
@@ -444,12 +705,17 @@ mod tests {
         let mut node_id = [0; NODE_ID_BYTES];
         rng.fill(&mut node_id).unwrap();
         let crypto = Crypto::new(node_id, config).unwrap();
-        crypto.peer_instance(vec![])
+        crypto.peer_instance(vec![], Instant::now)
     }
 
     #[test]
     fn normal() {
-        let config = Config { password: Some("test".to_string()), ..Default::default() };
+        let config = Config {
+            password: Some("test".to_string()),
+            argon2_memory_kib: Some(64),
+            argon2_iterations: Some(1),
+            ..Default::default()
+        };
         let mut node1 = create_node(&config);
         let mut node2 = create_node(&config);
         let mut msg = MsgBuffer::new(16);
@@ -504,4 +770,16 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn argon2_key_derivation() {
+        let config = Config { argon2_memory_kib: Some(64), argon2_iterations: Some(1), ..Default::default() };
+        let key = Crypto::derive_key("password", SALT, &config).unwrap();
+        assert_eq!(key, Crypto::derive_key("password", SALT, &config).unwrap());
+        assert_ne!(key, Crypto::derive_key("password", PEER_KEY_SALT, &config).unwrap());
+        let config2 = Config { argon2_iterations: Some(2), ..config.clone() };
+        assert_ne!(key, Crypto::derive_key("password", SALT, &config2).unwrap());
+        let invalid = Config { argon2_memory_kib: Some(1), ..config };
+        assert!(Crypto::derive_key("password", SALT, &invalid).is_err());
+    }
 }
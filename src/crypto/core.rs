@@ -88,6 +88,11 @@ impl Nonce {
         &self.0
     }
 
+    /// The transmitted part of the nonce as a number
+    fn sequence(&self) -> u64 {
+        self.0[5..].iter().fold(0, |seq, b| seq << 8 | u64::from(*b))
+    }
+
     fn increment(&mut self) {
         for i in (0..NONCE_LEN).rev() {
             let mut num = self.0[i];
@@ -133,6 +138,8 @@ pub struct CryptoCore {
     keys: [CryptoKey; 4],
     current_key: usize,
     nonce_half: bool,
+    last_seq: Option<u64>,
+    key_rotated: bool,
 }
 
 impl CryptoCore {
@@ -151,6 +158,8 @@ impl CryptoCore {
             ],
             current_key: 0,
             nonce_half,
+            last_seq: None,
+            key_rotated: false,
             rand,
         }
     }
@@ -192,7 +201,9 @@ impl CryptoCore {
     }
 
     pub fn decrypt(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error> {
-        assert!(buffer.len() >= EXTRA_LEN + TAG_LEN);
+        if buffer.len() < EXTRA_LEN + TAG_LEN {
+            return Err(Error::Crypto("Input data too short"))
+        }
         let (extra, data_and_tag) = buffer.message_mut().split_at_mut(EXTRA_LEN);
         let key_id;
         let mut nonce;
@@ -203,22 +214,37 @@ impl CryptoCore {
             extra.read_exact(&mut nonce.0[5..]).map_err(|_| Error::Crypto("Input data too short"))?;
             nonce.set_msb(if self.nonce_half { 0x00 } else { 0x80 });
         }
+        let seq = u64::from(key_id) << 56 | nonce.sequence();
         let key = &mut self.keys[key_id as usize];
         let result = Self::decrypt_with_key(key, nonce, data_and_tag);
+        if result.is_ok() {
+            self.last_seq = Some(seq);
+        }
         buffer.set_start(buffer.get_start() + EXTRA_LEN);
         buffer.set_length(buffer.len() - TAG_LEN);
         result
     }
 
+    /// Takes the sequence number of the last decrypted message, the key id is stored in the most significant byte
+    pub fn take_last_seq(&mut self) -> Option<u64> {
+        self.last_seq.take()
+    }
+
     pub fn rotate_key(&mut self, key: LessSafeKey, id: u64, use_for_sending: bool) {
         debug!("Rotated key {} (use for sending: {})", id, use_for_sending);
         let id = (id % 4) as usize;
         self.keys[id] = CryptoKey::new(&self.rand, key, self.nonce_half);
         if use_for_sending {
-            self.current_key = id
+            self.current_key = id;
+            self.key_rotated = true;
         }
     }
 
+    /// Returns whether a new key has been used for sending since the last call
+    pub fn take_key_rotated(&mut self) -> bool {
+        mem::replace(&mut self.key_rotated, false)
+    }
+
     pub fn algorithm(&self) -> &'static aead::Algorithm {
         self.keys[self.current_key].key.algorithm()
     }
@@ -231,6 +257,56 @@ impl CryptoCore {
     }
 }
 
+/// CPU features that accelerate the crypto algorithms
+///
+/// Ring detects these features once and dispatches to the accelerated implementations itself, so there is no own
+/// dispatch. This only reports which implementation ring uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuFeatures {
+    pub aes: bool,
+    pub clmul: bool,
+    pub avx2: bool,
+}
+
+impl CpuFeatures {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub fn detect() -> Self {
+        Self {
+            aes: is_x86_feature_detected!("aes"),
+            clmul: is_x86_feature_detected!("pclmulqdq"),
+            avx2: is_x86_feature_detected!("avx2"),
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    pub fn detect() -> Self {
+        Self::default()
+    }
+
+    /// Name of the AES-GCM implementation that ring selects, it needs both AES-NI and CLMUL for the accelerated one
+    pub fn aes_backend(&self) -> &'static str {
+        if self.aes && self.clmul {
+            "aes-ni"
+        } else {
+            "software"
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = vec![];
+        if self.aes {
+            names.push("aes")
+        }
+        if self.clmul {
+            names.push("clmul")
+        }
+        if self.avx2 {
+            names.push("avx2")
+        }
+        names
+    }
+}
+
 pub fn create_dummy_pair(algo: &'static aead::Algorithm) -> (CryptoCore, CryptoCore) {
     let key_data = random_data(algo.key_len());
     let sender = CryptoCore::new(LessSafeKey::new(UnboundKey::new(algo, &key_data).unwrap()), true);
@@ -269,6 +345,23 @@ mod tests {
         assert_eq!(nonce.as_bytes(), &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
         nonce.increment();
         assert_eq!(nonce.as_bytes(), &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(nonce.sequence(), 2);
+    }
+
+    #[test]
+    fn test_last_seq() {
+        let (mut sender, mut receiver) = create_dummy_pair(&aead::AES_128_GCM);
+        let mut buffer = MsgBuffer::new(EXTRA_LEN);
+        let mut seqs = vec![];
+        for _ in 0..3 {
+            buffer.clone_from(&[1, 2, 3]);
+            sender.encrypt(&mut buffer);
+            receiver.decrypt(&mut buffer).unwrap();
+            seqs.push(receiver.take_last_seq().unwrap());
+        }
+        assert_eq!(receiver.take_last_seq(), None);
+        assert_eq!(seqs[1], seqs[0] + 1);
+        assert_eq!(seqs[2], seqs[0] + 2);
     }
 
     fn test_encrypt_decrypt(algo: &'static aead::Algorithm) {
@@ -318,6 +411,10 @@ mod tests {
         d = buffer.clone();
         d.message_mut()[EXTRA_LEN] ^= 1;
         assert!(receiver.decrypt(&mut d).is_err());
+        // Truncate the message
+        d = buffer.clone();
+        d.set_length(EXTRA_LEN + TAG_LEN - 1);
+        assert!(receiver.decrypt(&mut d).is_err());
         // Check everything still works
         d = buffer;
         assert!(receiver.decrypt(&mut d).is_ok());
@@ -390,11 +487,14 @@ mod tests {
 
         let new_key = random_data(algo.key_len());
         receiver.rotate_key(LessSafeKey::new(UnboundKey::new(algo, &new_key).unwrap()), 1, false);
+        assert!(!receiver.take_key_rotated());
         receiver.encrypt(&mut buffer);
         assert!(sender.decrypt(&mut buffer).is_ok());
         sender.encrypt(&mut buffer);
         assert!(receiver.decrypt(&mut buffer).is_ok());
         sender.rotate_key(LessSafeKey::new(UnboundKey::new(algo, &new_key).unwrap()), 1, true);
+        assert!(sender.take_key_rotated());
+        assert!(!sender.take_key_rotated());
         receiver.encrypt(&mut buffer);
         assert!(sender.decrypt(&mut buffer).is_ok());
         sender.encrypt(&mut buffer);
@@ -430,7 +530,7 @@ mod tests {
 
     #[test]
     fn test_core_size() {
-        assert_eq!(2384, mem::size_of::<CryptoCore>());
+        assert_eq!(2400, mem::size_of::<CryptoCore>());
     }
 
     #[test]
@@ -53,17 +53,39 @@
 //
 // Once every second, both nodes check whether they have already finished the initialization. If not, they repeat their
 // last message. After 5 seconds, the initialization is aborted as failed.
+//
+// Optionally, the nodes can use a post-quantum key encapsulation mechanism (Kyber-768) in addition to the ECDH key
+// exchange. In this case, A creates an ephemeral KEM key pair and includes the KEM public key in the ping message. B
+// encapsulates a random secret with this public key and includes the ciphertext in the pong message. After
+// decapsulating the ciphertext, both nodes share the KEM secret and mix it into the ECDH key material via HKDF. That
+// way the resulting key is secure as long as either of the two key exchanges is secure. If one node requires the
+// post-quantum key exchange and the other node does not provide it, the initialization fails.
+//
+// If certificate authentication is configured, every message also contains the certificate of the sender. Messages
+// signed by a key that is not trusted are accepted if the certificate is signed by the configured CA and the
+// signature can be verified with the public key contained in the certificate.
+//
+// The fingerprint of the peer identity, i.e. the SHA-256 hash of its public key or its certificate, is kept so that
+// the application can pin it.
+//
+// Nodes can also have pre-shared keys for specific peers. When a node receives a ping or pong message, it checks the
+// salted node id hash of the sender against the node ids of its peer keys. If one matches, the peer key is mixed into
+// the key material via HKDF so that only nodes knowing the peer key can decrypt the payload and the later messages.
+// B selects the key when receiving the ping message and A when receiving the pong message. If only one of the nodes
+// has a peer key for the other one, they derive different keys and the connection fails, so that nodes that only know
+// the global key can not impersonate a peer that has its own key.
 
 use super::{
     core::{CryptoCore, EXTRA_LEN},
-    Algorithms, EcdhPrivateKey, EcdhPublicKey, Ed25519PublicKey, Payload,
+    kem::{self, KemPrivateKey},
+    Algorithms, EcdhPrivateKey, EcdhPublicKey, Ed25519PublicKey, Payload, PeerKey,
 };
-use crate::{error::Error, types::NodeId, util::MsgBuffer};
+use crate::{cert_auth::CertAuth, error::Error, types::NodeId, util::MsgBuffer};
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use ring::{
     aead::{Algorithm, LessSafeKey, UnboundKey, AES_128_GCM, AES_256_GCM, CHACHA20_POLY1305},
     agreement::{agree_ephemeral, X25519},
-    digest,
+    digest, hkdf,
     rand::{SecureRandom, SystemRandom},
     signature::{self, Ed25519KeyPair, KeyPair, ED25519, ED25519_PUBLIC_KEY_LEN},
 };
@@ -73,6 +95,7 @@ use std::{
     fmt::Debug,
     io::{self, Cursor, Read, Write},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 pub const STAGE_PING: u8 = 1;
@@ -84,7 +107,11 @@ pub const CLOSING: u8 = 5;
 pub const MAX_FAILED_RETRIES: usize = 120;
 
 pub const SALTED_NODE_ID_HASH_LEN: usize = 20;
+
+const HYBRID_KEY_INFO: &[u8] = b"vpncloud hybrid key";
+const PEER_KEY_INFO: &[u8] = b"vpncloud peer key";
 pub type SaltedNodeIdHash = [u8; SALTED_NODE_ID_HASH_LEN];
+pub type Fingerprint = [u8; 32];
 
 #[allow(clippy::large_enum_variant)]
 pub enum InitMsg {
@@ -92,12 +119,14 @@ pub enum InitMsg {
         salted_node_id_hash: SaltedNodeIdHash,
         ecdh_public_key: EcdhPublicKey,
         algorithms: Algorithms,
+        kem_public_key: Option<Vec<u8>>,
     },
     Pong {
         salted_node_id_hash: SaltedNodeIdHash,
         ecdh_public_key: EcdhPublicKey,
         algorithms: Algorithms,
         encrypted_payload: MsgBuffer,
+        kem_ciphertext: Option<Vec<u8>>,
     },
     Peng {
         salted_node_id_hash: SaltedNodeIdHash,
@@ -107,8 +136,11 @@ pub enum InitMsg {
 
 impl InitMsg {
     const PART_ALGORITHMS: u8 = 4;
+    const PART_CERTIFICATE: u8 = 8;
     const PART_ECDH_PUBLIC_KEY: u8 = 3;
     const PART_END: u8 = 0;
+    const PART_KEM_CIPHERTEXT: u8 = 7;
+    const PART_KEM_PUBLIC_KEY: u8 = 6;
     const PART_PAYLOAD: u8 = 5;
     const PART_SALTED_NODE_ID_HASH: u8 = 2;
     const PART_STAGE: u8 = 1;
@@ -139,7 +171,9 @@ impl InitMsg {
         short_hash
     }
 
-    fn read_from(buffer: &[u8], trusted_keys: &[Ed25519PublicKey]) -> Result<(Self, Ed25519PublicKey), Error> {
+    fn read_from(
+        buffer: &[u8], trusted_keys: &[Ed25519PublicKey], cert_auth: Option<&CertAuth>,
+    ) -> Result<(Self, Fingerprint), Error> {
         let mut r = Cursor::new(buffer);
 
         let mut public_key_salt = [0; 4];
@@ -155,7 +189,7 @@ impl InitMsg {
                 break;
             }
         }
-        if !found_key {
+        if !found_key && cert_auth.is_none() {
             return Err(Error::Crypto("untrusted peer"));
         }
 
@@ -164,6 +198,9 @@ impl InitMsg {
         let mut ecdh_public_key = None;
         let mut encrypted_payload = None;
         let mut algorithms = None;
+        let mut kem_public_key = None;
+        let mut kem_ciphertext = None;
+        let mut certificate = None;
 
         loop {
             let field = r.read_u8().map_err(|_| Error::Parse("Init message too short"))?;
@@ -220,6 +257,21 @@ impl InitMsg {
                     }
                     algorithms = Some(Algorithms { algorithm_speeds: algos, allow_unencrypted });
                 }
+                Self::PART_KEM_PUBLIC_KEY => {
+                    let mut data = vec![0; field_len];
+                    r.read_exact(&mut data).map_err(|_| Error::Parse("Init message too short"))?;
+                    kem_public_key = Some(data);
+                }
+                Self::PART_KEM_CIPHERTEXT => {
+                    let mut data = vec![0; field_len];
+                    r.read_exact(&mut data).map_err(|_| Error::Parse("Init message too short"))?;
+                    kem_ciphertext = Some(data);
+                }
+                Self::PART_CERTIFICATE => {
+                    let mut data = vec![0; field_len];
+                    r.read_exact(&mut data).map_err(|_| Error::Parse("Init message too short"))?;
+                    certificate = Some(data);
+                }
                 _ => {
                     let mut data = vec![0; field_len];
                     r.read_exact(&mut data).map_err(|_| Error::Parse("Init message too short"))?;
@@ -234,10 +286,18 @@ impl InitMsg {
         r.read_exact(&mut signature).map_err(|_| Error::Parse("Init message too short"))?;
 
         let signed_data = &r.into_inner()[0..pos];
-        let public_key = signature::UnparsedPublicKey::new(&ED25519, &public_key_data);
-        if public_key.verify(&signed_data, &signature).is_err() {
-            return Err(Error::Crypto("invalid signature"));
-        }
+        let identity = if found_key {
+            let public_key = signature::UnparsedPublicKey::new(&ED25519, &public_key_data);
+            if public_key.verify(&signed_data, &signature).is_err() {
+                return Err(Error::Crypto("invalid signature"));
+            }
+            digest::digest(&digest::SHA256, &public_key_data)
+        } else if let (Some(cert_auth), Some(certificate)) = (cert_auth, certificate) {
+            cert_auth.verify(&certificate, signed_data, &signature)?;
+            digest::digest(&digest::SHA256, &certificate)
+        } else {
+            return Err(Error::AuthFailed("untrusted peer without certificate"));
+        };
 
         let stage = match stage {
             Some(val) => val,
@@ -258,7 +318,7 @@ impl InitMsg {
                     Some(val) => val,
                     None => return Err(Error::CryptoInit("Init message without algorithms")),
                 };
-                Self::Ping { salted_node_id_hash, ecdh_public_key, algorithms }
+                Self::Ping { salted_node_id_hash, ecdh_public_key, algorithms, kem_public_key }
             }
             STAGE_PONG => {
                 let ecdh_public_key = match ecdh_public_key {
@@ -273,7 +333,7 @@ impl InitMsg {
                     Some(val) => val,
                     None => return Err(Error::CryptoInit("Init message without payload")),
                 };
-                Self::Pong { salted_node_id_hash, ecdh_public_key, algorithms, encrypted_payload }
+                Self::Pong { salted_node_id_hash, ecdh_public_key, algorithms, encrypted_payload, kem_ciphertext }
             }
             STAGE_PENG => {
                 let encrypted_payload = match encrypted_payload {
@@ -285,10 +345,14 @@ impl InitMsg {
             _ => return Err(Error::CryptoInit("Invalid stage")),
         };
 
-        Ok((msg, public_key_data))
+        let mut fingerprint = [0; 32];
+        fingerprint.clone_from_slice(identity.as_ref());
+        Ok((msg, fingerprint))
     }
 
-    fn write_to(&self, buffer: &mut [u8], key: &Ed25519KeyPair) -> Result<usize, io::Error> {
+    fn write_to(
+        &self, buffer: &mut [u8], key: &Ed25519KeyPair, certificate: Option<&[u8]>,
+    ) -> Result<usize, io::Error> {
         let mut w = Cursor::new(buffer);
 
         let rand = SystemRandom::new();
@@ -352,6 +416,26 @@ impl InitMsg {
             _ => (),
         }
 
+        if let Some(certificate) = certificate {
+            w.write_u8(Self::PART_CERTIFICATE)?;
+            w.write_u16::<NetworkEndian>(certificate.len() as u16)?;
+            w.write_all(certificate)?;
+        }
+
+        match &self {
+            Self::Ping { kem_public_key: Some(data), .. } => {
+                w.write_u8(Self::PART_KEM_PUBLIC_KEY)?;
+                w.write_u16::<NetworkEndian>(data.len() as u16)?;
+                w.write_all(data)?;
+            }
+            Self::Pong { kem_ciphertext: Some(data), .. } => {
+                w.write_u8(Self::PART_KEM_CIPHERTEXT)?;
+                w.write_u16::<NetworkEndian>(data.len() as u16)?;
+                w.write_all(data)?;
+            }
+            _ => (),
+        }
+
         match &self {
             Self::Pong { encrypted_payload, .. } | Self::Peng { encrypted_payload, .. } => {
                 w.write_u8(Self::PART_PAYLOAD)?;
@@ -384,7 +468,15 @@ pub struct InitState<P: Payload> {
     payload: P,
     key_pair: Arc<Ed25519KeyPair>,
     trusted_keys: Arc<[Ed25519PublicKey]>,
+    cert_auth: Option<Arc<CertAuth>>,
+    peer_keys: Arc<[(NodeId, PeerKey)]>,
+    peer_fingerprint: Option<Fingerprint>,
     ecdh_private_key: Option<EcdhPrivateKey>,
+    /// The key exchange key is created upfront, so that the payload can be bound to it
+    ecdh_public_key: EcdhPublicKey,
+    peer_ecdh_public_key: Option<EcdhPublicKey>,
+    pq_kem: bool,
+    kem_private_key: Option<KemPrivateKey>,
     next_stage: u8,
     close_time: usize,
     last_message: Option<Vec<u8>>,
@@ -393,12 +485,19 @@ pub struct InitState<P: Payload> {
     #[allow(dead_code)] // Used in tests
     selected_algorithm: Option<&'static Algorithm>,
     failed_retries: usize,
+    /// When the last message has been sent, `None` once the peer replied
+    last_sent: Option<Instant>,
+    /// Reads the time when a message is sent, so that tests can use a mock clock
+    clock: fn() -> Instant,
+    fast_retries: usize,
 }
 
 impl<P: Payload> InitState<P> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         node_id: NodeId, payload: P, key_pair: Arc<Ed25519KeyPair>, trusted_keys: Arc<[Ed25519PublicKey]>,
-        algorithms: Algorithms,
+        cert_auth: Option<Arc<CertAuth>>, peer_keys: Arc<[(NodeId, PeerKey)]>, algorithms: Algorithms, pq_kem: bool,
+        clock: fn() -> Instant,
     ) -> Self {
         let mut hash = [0; SALTED_NODE_ID_HASH_LEN];
         let rng = SystemRandom::new();
@@ -406,30 +505,48 @@ impl<P: Payload> InitState<P> {
         hash[4..].clone_from_slice(&node_id);
         let d = digest::digest(&digest::SHA256, &hash);
         hash[4..].clone_from_slice(&d.as_ref()[..16]);
+        let (ecdh_private_key, ecdh_public_key) = Self::create_ecdh_keypair();
         Self {
             node_id,
             salted_node_id_hash: hash,
             payload,
             key_pair,
             trusted_keys,
+            cert_auth,
+            peer_keys,
+            peer_fingerprint: None,
             next_stage: STAGE_PING,
             last_message: None,
             crypto: None,
-            ecdh_private_key: None,
+            ecdh_private_key: Some(ecdh_private_key),
+            ecdh_public_key,
+            peer_ecdh_public_key: None,
+            pq_kem,
+            kem_private_key: None,
             selected_algorithm: None,
             algorithms,
             failed_retries: 0,
+            last_sent: None,
+            clock,
+            fast_retries: 0,
             close_time: 60,
         }
     }
 
     pub fn send_ping(&mut self, out: &mut MsgBuffer) {
-        // create ecdh ephemeral key
-        let (ecdh_private_key, ecdh_public_key) = self.create_ecdh_keypair();
-        self.ecdh_private_key = Some(ecdh_private_key);
+        let ecdh_public_key = self.ecdh_public_key.clone();
+
+        // create kem ephemeral key
+        let kem_public_key = if self.pq_kem {
+            let (kem_private_key, kem_public_key) = kem::create_keypair();
+            self.kem_private_key = Some(kem_private_key);
+            Some(kem_public_key)
+        } else {
+            None
+        };
 
         // create stage 1 msg
-        self.send_message(STAGE_PING, Some(ecdh_public_key), out);
+        self.send_message(STAGE_PING, Some(ecdh_public_key), kem_public_key, out);
 
         self.next_stage = STAGE_PONG;
     }
@@ -458,14 +575,65 @@ impl<P: Payload> InitState<P> {
         }
     }
 
-    fn derive_master_key(&self, algo: &'static Algorithm, privk: EcdhPrivateKey, pubk: &EcdhPublicKey) -> LessSafeKey {
+    /// Repeats the last message if the peer did not reply to it within the timeout
+    ///
+    /// This is done at most `max_retries` times per message, after that the message is only repeated every second.
+    pub fn retransmit(&mut self, now: Instant, timeout: Duration, max_retries: usize, out: &mut MsgBuffer) -> bool {
+        if !matches!(self.next_stage, STAGE_PONG | STAGE_PENG | WAITING_TO_CLOSE) || self.fast_retries >= max_retries {
+            return false
+        }
+        match self.last_sent {
+            Some(sent) if now.saturating_duration_since(sent) >= timeout => {
+                self.fast_retries += 1;
+                self.last_sent = Some(now);
+                self.repeat_last_message(out);
+                !out.is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    /// Stops repeating the last message as the peer replied with an encrypted message
+    pub fn confirm(&mut self) {
+        self.last_sent = None
+    }
+
+    /// Derives the master key, including the peer key if one is given
+    fn derive_master_key(
+        &self, algo: &'static Algorithm, privk: EcdhPrivateKey, pubk: &EcdhPublicKey, kem_secret: Option<&[u8]>,
+        peer_key: Option<&PeerKey>,
+    ) -> LessSafeKey {
         agree_ephemeral(privk, pubk, (), |k| {
-            UnboundKey::new(algo, &k[..algo.key_len()]).map(LessSafeKey::new).map_err(|_| ())
+            match peer_key {
+                Some(peer_key) => {
+                    let mut secret = k.to_vec();
+                    secret.extend_from_slice(kem_secret.unwrap_or(&[]));
+                    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, peer_key).extract(&secret);
+                    // The fingerprint binds the derived key to the peer key, the ephemeral ECDH secret still
+                    // prevents that a leaked peer key can be used to decrypt recorded sessions
+                    let fingerprint = digest::digest(&digest::SHA256, peer_key);
+                    let info = [PEER_KEY_INFO, &fingerprint.as_ref()[..8]];
+                    let okm = prk.expand(&info, algo).map_err(|_| ())?;
+                    Ok(LessSafeKey::new(UnboundKey::from(okm)))
+                }
+                None => match kem_secret {
+                    Some(kem_secret) => {
+                        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, kem_secret).extract(k);
+                        let okm = prk.expand(&[HYBRID_KEY_INFO], algo).map_err(|_| ())?;
+                        Ok(LessSafeKey::new(UnboundKey::from(okm)))
+                    }
+                    None => UnboundKey::new(algo, &k[..algo.key_len()]).map(LessSafeKey::new).map_err(|_| ()),
+                },
+            }
         })
         .unwrap()
     }
 
-    fn create_ecdh_keypair(&self) -> (EcdhPrivateKey, EcdhPublicKey) {
+    fn find_peer_key(&self, hash: &SaltedNodeIdHash) -> Option<&PeerKey> {
+        self.peer_keys.iter().find(|(node_id, _)| self.check_salted_node_id_hash(hash, *node_id)).map(|(_, key)| key)
+    }
+
+    fn create_ecdh_keypair() -> (EcdhPrivateKey, EcdhPublicKey) {
         let rand = SystemRandom::new();
         let ecdh_private_key = EcdhPrivateKey::generate(&X25519, &rand).unwrap();
         let public_key = ecdh_private_key.compute_public_key().unwrap();
@@ -496,10 +664,12 @@ impl<P: Payload> InitState<P> {
         h2[0..4].clone_from_slice(&hash[0..4]);
         h2[4..].clone_from_slice(&node_id);
         let d = digest::digest(&digest::SHA256, &h2);
-        hash == d.as_ref()
+        hash[4..] == d.as_ref()[..16]
     }
 
-    fn send_message(&mut self, stage: u8, ecdh_public_key: Option<EcdhPublicKey>, out: &mut MsgBuffer) {
+    fn send_message(
+        &mut self, stage: u8, ecdh_public_key: Option<EcdhPublicKey>, kem_data: Option<Vec<u8>>, out: &mut MsgBuffer,
+    ) {
         debug!("Sending init with stage={}", stage);
         assert!(out.is_empty());
         let mut public_key = [0; ED25519_PUBLIC_KEY_LEN];
@@ -509,12 +679,14 @@ impl<P: Payload> InitState<P> {
                 salted_node_id_hash: self.salted_node_id_hash,
                 ecdh_public_key: ecdh_public_key.unwrap(),
                 algorithms: self.algorithms.clone(),
+                kem_public_key: kem_data,
             },
             STAGE_PONG => InitMsg::Pong {
                 salted_node_id_hash: self.salted_node_id_hash,
                 ecdh_public_key: ecdh_public_key.unwrap(),
                 algorithms: self.algorithms.clone(),
                 encrypted_payload: self.encrypt_payload(),
+                kem_ciphertext: kem_data,
             },
             STAGE_PENG => InitMsg::Peng {
                 salted_node_id_hash: self.salted_node_id_hash,
@@ -523,8 +695,11 @@ impl<P: Payload> InitState<P> {
             _ => unreachable!(),
         };
         let mut bytes = out.buffer();
-        let len = msg.write_to(&mut bytes, &self.key_pair).expect("Buffer too small");
+        let certificate = self.cert_auth.as_ref().map(|c| c.node_cert());
+        let len = msg.write_to(&mut bytes, &self.key_pair, certificate).expect("Buffer too small");
         self.last_message = Some(bytes[0..len].to_vec());
+        self.last_sent = Some((self.clock)());
+        self.fast_retries = 0;
         out.set_length(len);
     }
 
@@ -565,7 +740,7 @@ impl<P: Payload> InitState<P> {
     }
 
     pub fn handle_init(&mut self, out: &mut MsgBuffer) -> Result<InitResult<P>, Error> {
-        let (msg, _peer_key) = InitMsg::read_from(out.buffer(), &self.trusted_keys)?;
+        let (msg, peer_fingerprint) = InitMsg::read_from(out.buffer(), &self.trusted_keys, self.cert_auth.as_deref())?;
         out.clear();
         let stage = msg.stage();
         let salted_node_id_hash = *msg.salted_node_id_hash();
@@ -583,7 +758,8 @@ impl<P: Payload> InitState<P> {
                     // reset to initial state
                     self.next_stage = STAGE_PING;
                     self.last_message = None;
-                    self.ecdh_private_key = None;
+                    self.last_sent = None;
+                    self.kem_private_key = None;
                 } else {
                     return Ok(InitResult::Continue);
                 }
@@ -597,34 +773,78 @@ impl<P: Payload> InitState<P> {
             }
         }
         self.failed_retries = 0;
+        self.peer_fingerprint = Some(peer_fingerprint);
         match msg {
-            InitMsg::Ping { ecdh_public_key, algorithms, .. } => {
-                // create ecdh ephemeral key
-                let (my_ecdh_private_key, my_ecdh_public_key) = self.create_ecdh_keypair();
+            InitMsg::Ping { ecdh_public_key, algorithms, kem_public_key, .. } => {
+                let my_ecdh_private_key = match self.ecdh_private_key.take() {
+                    Some(key) => key,
+                    None => return Err(Error::CryptoInitFatal("Key exchange key has already been used")),
+                };
+                let my_ecdh_public_key = self.ecdh_public_key.clone();
+
+                // encapsulate kem secret
+                let (kem_secret, kem_ciphertext) = match kem_public_key {
+                    Some(kem_public_key) if self.pq_kem => {
+                        let (secret, ciphertext) = kem::encapsulate(&kem_public_key)?;
+                        (Some(secret), Some(ciphertext))
+                    }
+                    None if self.pq_kem => {
+                        return Err(Error::CryptoInitFatal("Peer does not support post-quantum key exchange"))
+                    }
+                    _ => (None, None),
+                };
 
                 // do ecdh agreement and derive master key
                 let algorithm = self.select_algorithm(&algorithms)?;
                 self.selected_algorithm = algorithm.map(|a| a.0);
                 if let Some((algorithm, _speed)) = algorithm {
-                    let master_key = self.derive_master_key(algorithm, my_ecdh_private_key, &ecdh_public_key);
+                    let peer_key = self.find_peer_key(&salted_node_id_hash);
+                    let master_key = self.derive_master_key(
+                        algorithm,
+                        my_ecdh_private_key,
+                        &ecdh_public_key,
+                        kem_secret.as_deref(),
+                        peer_key,
+                    );
                     self.crypto = Some(CryptoCore::new(master_key, self.salted_node_id_hash > salted_node_id_hash));
                 }
+                self.peer_ecdh_public_key = Some(ecdh_public_key);
 
                 // create and send stage 2 reply
-                self.send_message(STAGE_PONG, Some(my_ecdh_public_key), out);
+                self.send_message(STAGE_PONG, Some(my_ecdh_public_key), kem_ciphertext, out);
 
                 self.next_stage = STAGE_PENG;
                 Ok(InitResult::Continue)
             }
-            InitMsg::Pong { ecdh_public_key, algorithms, mut encrypted_payload, .. } => {
+            InitMsg::Pong { ecdh_public_key, algorithms, mut encrypted_payload, kem_ciphertext, .. } => {
+                // decapsulate kem secret
+                let kem_secret = if self.pq_kem {
+                    let kem_ciphertext = match kem_ciphertext {
+                        Some(val) => val,
+                        None => return Err(Error::CryptoInitFatal("Peer does not support post-quantum key exchange")),
+                    };
+                    let kem_private_key = self.kem_private_key.take().unwrap();
+                    Some(kem::decapsulate(&kem_private_key, &kem_ciphertext)?)
+                } else {
+                    None
+                };
+
                 // do ecdh agreement and derive master key
                 let ecdh_private_key = self.ecdh_private_key.take().unwrap();
                 let algorithm = self.select_algorithm(&algorithms)?;
                 self.selected_algorithm = algorithm.map(|a| a.0);
                 if let Some((algorithm, _speed)) = algorithm {
-                    let master_key = self.derive_master_key(algorithm, ecdh_private_key, &ecdh_public_key);
+                    let peer_key = self.find_peer_key(&salted_node_id_hash);
+                    let master_key = self.derive_master_key(
+                        algorithm,
+                        ecdh_private_key,
+                        &ecdh_public_key,
+                        kem_secret.as_deref(),
+                        peer_key,
+                    );
                     self.crypto = Some(CryptoCore::new(master_key, self.salted_node_id_hash > salted_node_id_hash));
                 }
+                self.peer_ecdh_public_key = Some(ecdh_public_key);
 
                 // decrypt the payload
                 let peer_payload = self
@@ -632,7 +852,7 @@ impl<P: Payload> InitState<P> {
                     .map_err(|_| Error::CryptoInitFatal("Failed to decrypt payload"))?;
 
                 // create and send stage 3 reply
-                self.send_message(STAGE_PENG, None, out);
+                self.send_message(STAGE_PENG, None, None, out);
 
                 self.next_stage = WAITING_TO_CLOSE;
                 self.close_time = 60;
@@ -653,6 +873,25 @@ impl<P: Payload> InitState<P> {
     pub fn take_core(&mut self) -> Option<CryptoCore> {
         self.crypto.take()
     }
+
+    pub fn peer_fingerprint(&self) -> Option<Fingerprint> {
+        self.peer_fingerprint
+    }
+
+    /// The public key exchange key of this handshake, a new one is used for every handshake
+    pub fn session_key(&self) -> &[u8] {
+        self.ecdh_public_key.bytes()
+    }
+
+    /// The public key exchange key of the peer, once it has been received
+    pub fn peer_session_key(&self) -> Option<&[u8]> {
+        self.peer_ecdh_public_key.as_ref().map(|key| &key.bytes()[..])
+    }
+
+    /// The payload can be changed until it has been sent
+    pub fn payload_mut(&mut self) -> &mut P {
+        &mut self.payload
+    }
 }
 
 #[cfg(test)]
@@ -674,6 +913,10 @@ mod tests {
     }
 
     fn create_pair() -> (InitState<Vec<u8>>, InitState<Vec<u8>>) {
+        create_pair_with_kem(false)
+    }
+
+    fn create_pair_with_kem(pq_kem: bool) -> (InitState<Vec<u8>>, InitState<Vec<u8>>) {
         let rng = SystemRandom::new();
         let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
         let key_pair = Arc::new(Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref()).unwrap());
@@ -688,8 +931,20 @@ mod tests {
             algorithm_speeds: smallvec![(&AES_128_GCM, 600.0), (&AES_256_GCM, 500.0), (&CHACHA20_POLY1305, 400.0)],
             allow_unencrypted: false,
         };
-        let sender = InitState::new(node1, vec![1], key_pair.clone(), trusted_nodes.clone(), algorithms.clone());
-        let receiver = InitState::new(node2, vec![2], key_pair, trusted_nodes, algorithms);
+        let peer_keys: Arc<[(NodeId, PeerKey)]> = Arc::new([]);
+        let sender = InitState::new(
+            node1,
+            vec![1],
+            key_pair.clone(),
+            trusted_nodes.clone(),
+            None,
+            peer_keys.clone(),
+            algorithms.clone(),
+            pq_kem,
+            Instant::now,
+        );
+        let receiver =
+            InitState::new(node2, vec![2], key_pair, trusted_nodes, None, peer_keys, algorithms, pq_kem, Instant::now);
         (sender, receiver)
     }
 
@@ -715,6 +970,13 @@ mod tests {
         }
     }
 
+    #[test]
+    fn salted_node_id_hash() {
+        let (sender, receiver) = create_pair();
+        assert!(receiver.check_salted_node_id_hash(&sender.salted_node_id_hash, sender.node_id));
+        assert!(!receiver.check_salted_node_id_hash(&sender.salted_node_id_hash, receiver.node_id));
+    }
+
     #[test]
     fn lost_init_sender_recovers() {
         let (mut sender, mut receiver) = create_pair();
@@ -795,6 +1057,50 @@ mod tests {
         assert_eq!(sender.stage(), CLOSING);
     }
 
+    #[test]
+    fn fast_retransmit() {
+        let (mut sender, mut receiver) = create_pair();
+        let timeout = Duration::from_millis(500);
+        let mut out = MsgBuffer::new(8);
+        sender.send_ping(&mut out);
+        let ping = out.message().to_vec();
+        let now = Instant::now();
+        out.clear();
+        // The ping is repeated after the timeout, but only up to the retry limit
+        assert!(!sender.retransmit(now, timeout, 2, &mut out));
+        for n in 1..3 {
+            out.clear();
+            assert!(sender.retransmit(now + timeout * n, timeout, 2, &mut out));
+            assert_eq!(out.message(), &ping[..]);
+        }
+        out.clear();
+        assert!(!sender.retransmit(now + timeout * 3, timeout, 2, &mut out));
+        // A reply resets the retries
+        out.clear();
+        out.set_length(ping.len());
+        out.message_mut().copy_from_slice(&ping);
+        receiver.handle_init(&mut out).unwrap();
+        sender.handle_init(&mut out).unwrap();
+        assert_eq!(sender.stage(), WAITING_TO_CLOSE);
+        let peng = out.message().to_vec();
+        let later = Instant::now() + timeout;
+        out.clear();
+        assert!(sender.retransmit(later, timeout, 2, &mut out));
+        assert_eq!(out.message(), &peng[..]);
+        // Once the peer confirmed the peng, it is not repeated anymore
+        sender.confirm();
+        out.clear();
+        assert!(!sender.retransmit(later + timeout, timeout, 2, &mut out));
+        // The receiver has nothing to repeat once the initialization is complete
+        out.clear();
+        out.set_length(peng.len());
+        out.message_mut().copy_from_slice(&peng);
+        receiver.handle_init(&mut out).unwrap();
+        assert_eq!(receiver.stage(), CLOSING);
+        out.clear();
+        assert!(!receiver.retransmit(later + timeout, timeout, 2, &mut out));
+    }
+
     #[test]
     fn untrusted_peer() {
         let (mut sender, _) = create_pair();
@@ -824,6 +1130,106 @@ mod tests {
         assert!(sender.handle_init(&mut out).is_err());
     }
 
+    #[test]
+    #[cfg(feature = "pq_kem")]
+    fn pq_kem_init() {
+        let (mut sender, mut receiver) = create_pair_with_kem(true);
+        let mut out = MsgBuffer::new(8);
+        sender.send_ping(&mut out);
+        assert_eq!(receiver.handle_init(&mut out).unwrap(), InitResult::Continue);
+        match sender.handle_init(&mut out).unwrap() {
+            InitResult::Success { .. } => (),
+            InitResult::Continue => unreachable!(),
+        }
+        match receiver.handle_init(&mut out).unwrap() {
+            InitResult::Success { .. } => (),
+            InitResult::Continue => unreachable!(),
+        }
+        let mut sender_core = sender.take_core().unwrap();
+        let mut receiver_core = receiver.take_core().unwrap();
+        let mut data = MsgBuffer::new(EXTRA_LEN);
+        data.set_length(4);
+        data.message_mut().copy_from_slice(&[1, 2, 3, 4]);
+        sender_core.encrypt(&mut data);
+        receiver_core.decrypt(&mut data).unwrap();
+        assert_eq!(data.message(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[cfg(feature = "pq_kem")]
+    fn pq_kem_required() {
+        let (mut sender, mut receiver) = create_pair_with_kem(true);
+        sender.pq_kem = false;
+        let mut out = MsgBuffer::new(8);
+        sender.send_ping(&mut out);
+        assert!(receiver.handle_init(&mut out).is_err());
+        let (mut sender, mut receiver) = create_pair_with_kem(true);
+        receiver.pq_kem = false;
+        out.clear();
+        sender.send_ping(&mut out);
+        assert_eq!(receiver.handle_init(&mut out).unwrap(), InitResult::Continue);
+        assert!(sender.handle_init(&mut out).is_err());
+    }
+
+    fn run_init(sender: &mut InitState<Vec<u8>>, receiver: &mut InitState<Vec<u8>>) -> Result<(), Error> {
+        let mut out = MsgBuffer::new(8);
+        sender.send_ping(&mut out);
+        receiver.handle_init(&mut out)?;
+        sender.handle_init(&mut out)?;
+        receiver.handle_init(&mut out)?;
+        assert_eq!(receiver.stage(), CLOSING);
+        Ok(())
+    }
+
+    #[test]
+    fn peer_key_init() {
+        let (mut sender, mut receiver) = create_pair();
+        sender.peer_keys = Arc::new([(receiver.node_id, [1; 32])]);
+        receiver.peer_keys = Arc::new([(sender.node_id, [1; 32])]);
+        run_init(&mut sender, &mut receiver).unwrap();
+        // Different peer keys
+        let (mut sender, mut receiver) = create_pair();
+        sender.peer_keys = Arc::new([(receiver.node_id, [1; 32])]);
+        receiver.peer_keys = Arc::new([(sender.node_id, [2; 32])]);
+        assert!(run_init(&mut sender, &mut receiver).is_err());
+        // Neither side falls back to the global key
+        let (mut sender, mut receiver) = create_pair();
+        sender.peer_keys = Arc::new([(receiver.node_id, [1; 32])]);
+        assert!(run_init(&mut sender, &mut receiver).is_err());
+        let (mut sender, mut receiver) = create_pair();
+        receiver.peer_keys = Arc::new([(sender.node_id, [1; 32])]);
+        assert!(run_init(&mut sender, &mut receiver).is_err());
+    }
+
+    #[test]
+    fn ephemeral_session_keys() {
+        let (mut sender, mut receiver) = create_pair();
+        sender.peer_keys = Arc::new([(receiver.node_id, [1; 32])]);
+        receiver.peer_keys = Arc::new([(sender.node_id, [1; 32])]);
+        // Same long-term keys for the second handshake
+        let (mut sender2, mut receiver2) = create_pair();
+        for node in [&mut sender2, &mut receiver2].iter_mut() {
+            node.key_pair = sender.key_pair.clone();
+            node.trusted_keys = sender.trusted_keys.clone();
+        }
+        sender2.peer_keys = Arc::new([(receiver2.node_id, [1; 32])]);
+        receiver2.peer_keys = Arc::new([(sender2.node_id, [1; 32])]);
+        run_init(&mut sender, &mut receiver).unwrap();
+        run_init(&mut sender2, &mut receiver2).unwrap();
+        let (mut core, mut peer_core) = (sender.take_core().unwrap(), receiver.take_core().unwrap());
+        let mut core2 = sender2.take_core().unwrap();
+        // Both sides derive the same session key
+        let mut msg = MsgBuffer::new(EXTRA_LEN);
+        msg.clone_from(&[1, 2, 3]);
+        core.encrypt(&mut msg);
+        peer_core.decrypt(&mut msg).unwrap();
+        assert_eq!(msg.message(), &[1, 2, 3]);
+        // Another handshake with the same keys results in another session key
+        msg.clone_from(&[1, 2, 3]);
+        core2.encrypt(&mut msg);
+        assert!(peer_core.decrypt(&mut msg).is_err());
+    }
+
     fn test_algorithm_negotiation(
         algos1: Algorithms, algos2: Algorithms, success: bool, selected: Option<&'static Algorithm>,
     ) {
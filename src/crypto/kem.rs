@@ -0,0 +1,58 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Post-quantum key encapsulation (Kyber-768) that is used in addition to the ECDH key exchange in the handshake.
+
+#[cfg(feature = "pq_kem")]
+mod internal {
+    use crate::error::Error;
+    use pqcrypto_kyber::kyber768;
+    use pqcrypto_traits::kem::{Ciphertext, PublicKey, SharedSecret};
+
+    pub const SUPPORTED: bool = true;
+
+    pub struct KemPrivateKey(kyber768::SecretKey);
+
+    pub fn create_keypair() -> (KemPrivateKey, Vec<u8>) {
+        let (public_key, private_key) = kyber768::keypair();
+        (KemPrivateKey(private_key), public_key.as_bytes().to_vec())
+    }
+
+    pub fn encapsulate(public_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let public_key =
+            kyber768::PublicKey::from_bytes(public_key).map_err(|_| Error::CryptoInit("Invalid KEM public key"))?;
+        let (secret, ciphertext) = kyber768::encapsulate(&public_key);
+        Ok((secret.as_bytes().to_vec(), ciphertext.as_bytes().to_vec()))
+    }
+
+    pub fn decapsulate(private_key: &KemPrivateKey, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let ciphertext =
+            kyber768::Ciphertext::from_bytes(ciphertext).map_err(|_| Error::CryptoInit("Invalid KEM ciphertext"))?;
+        let secret = kyber768::decapsulate(&ciphertext, &private_key.0);
+        Ok(secret.as_bytes().to_vec())
+    }
+}
+
+#[cfg(not(feature = "pq_kem"))]
+mod internal {
+    use crate::error::Error;
+
+    pub const SUPPORTED: bool = false;
+
+    pub struct KemPrivateKey;
+
+    pub fn create_keypair() -> (KemPrivateKey, Vec<u8>) {
+        unreachable!("Post-quantum key exchange is not supported by this build")
+    }
+
+    pub fn encapsulate(_public_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        Err(Error::CryptoInitFatal("Post-quantum key exchange is not supported by this build"))
+    }
+
+    pub fn decapsulate(_private_key: &KemPrivateKey, _ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(Error::CryptoInitFatal("Post-quantum key exchange is not supported by this build"))
+    }
+}
+
+pub use internal::*;
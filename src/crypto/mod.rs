@@ -5,7 +5,9 @@
 mod common;
 mod core;
 mod init;
+mod kem;
 mod rotate;
 
-pub use self::core::{EXTRA_LEN, TAG_LEN};
+pub use self::core::{CpuFeatures, EXTRA_LEN, TAG_LEN};
+pub use self::init::Fingerprint;
 pub use common::*;
@@ -7,23 +7,68 @@ use std::{
     collections::VecDeque,
     convert::TryInto,
     fmt,
-    fs::{self, File},
-    io::{self, BufRead, BufReader, Cursor, Error as IoError, Read, Write},
+    fs::File,
+    io::{self, BufRead, BufReader, Error as IoError, Read, Write},
+    mem,
     net::{Ipv4Addr, UdpSocket},
     os::unix::io::{AsRawFd, RawFd},
     str,
     str::FromStr,
 };
 
-use crate::{crypto, error::Error, util::MsgBuffer};
+use schemars::JsonSchema;
 
+use crate::{crypto, error::Error, types::NodeId, util::MsgBuffer};
+
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(target_os = "macos")]
+mod macos;
+
+#[cfg(target_os = "freebsd")]
+use self::freebsd::{SIOCGIFFLAGS, SIOCGIFMTU, SIOCGIFNETMASK, SIOCSIFADDR, SIOCSIFFLAGS, SIOCSIFMTU, SIOCSIFNETMASK};
+#[cfg(not(target_os = "freebsd"))]
+use libc::{SIOCGIFFLAGS, SIOCGIFMTU, SIOCGIFNETMASK, SIOCSIFADDR, SIOCSIFFLAGS, SIOCSIFMTU, SIOCSIFNETMASK};
+
+#[cfg(not(any(target_os = "freebsd", target_os = "macos")))]
 static TUNSETIFF: libc::c_ulong = 1074025674;
+static TUNSETPERSIST: libc::c_ulong = 1074025675;
+
+/// MTU that is assumed when the MTU of a device can not be determined
+pub const DEFAULT_MTU: u16 = 1400;
+
+/// IPv4 socket address as in `struct sockaddr_in`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IfAddr {
+    #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+    len: u8,
+    #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+    family: u8,
+    #[cfg(not(any(target_os = "freebsd", target_os = "macos")))]
+    family: libc::c_short,
+    port: u16,
+    ip: Ipv4Addr,
+}
+
+impl IfAddr {
+    fn new(ip: Ipv4Addr) -> Self {
+        Self {
+            #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+            len: mem::size_of::<libc::sockaddr_in>() as u8,
+            family: libc::AF_INET as _,
+            port: 0,
+            ip,
+        }
+    }
+}
 
 #[repr(C)]
 union IfReqData {
     flags: libc::c_short,
     value: libc::c_int,
-    addr: (libc::c_short, Ipv4Addr),
+    addr: IfAddr,
+    hwaddr: libc::sockaddr,
     _dummy: [u8; 24],
 }
 
@@ -43,7 +88,7 @@ impl IfReq {
 }
 
 /// The type of a tun/tap device
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
 pub enum Type {
     /// Tun interface: This interface transports IP packets.
     #[serde(rename = "tun")]
@@ -106,6 +151,15 @@ pub trait Device: AsRawFd {
     fn write(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error>;
 
     fn get_ip(&self) -> Result<Ipv4Addr, Error>;
+
+    /// Returns the MTU of this device
+    fn get_mtu(&self) -> io::Result<u16>;
+
+    /// Sets the MAC address of this device
+    fn set_mac(&self, mac: [u8; 6]) -> io::Result<()>;
+
+    /// Returns the MAC address of this device
+    fn get_mac(&self) -> io::Result<[u8; 6]>;
 }
 
 /// Represents a tun/tap device
@@ -115,6 +169,16 @@ pub struct TunTapDevice {
     type_: Type,
 }
 
+/// The header of TUN packets on macOS and FreeBSD, the address family in network byte order
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+fn address_family_header(ip_version: u8) -> [u8; 4] {
+    match ip_version {
+        4 => (libc::AF_INET as u32).to_be_bytes(),
+        6 => (libc::AF_INET6 as u32).to_be_bytes(),
+        _ => unreachable!(),
+    }
+}
+
 impl TunTapDevice {
     /// Creates a new tun/tap device
     ///
@@ -125,18 +189,22 @@ impl TunTapDevice {
     /// interface name will be free. In this case, the `ifname()` method can be used to obtain the
     /// final interface name.
     ///
+    /// If a persistent device with the name `ifname` exists, this method attaches to it.
+    ///
     /// # Errors
     /// This method will return an error when the underlying system call fails. Common cases are:
     /// - The special device file `/dev/net/tun` does not exist or is not accessible by the current user.
-    /// - The interface name is invalid or already in use.
+    /// - The interface name is invalid or already in use by another device or a device of another type.
     /// - The current user does not have enough permissions to create tun/tap devices (this requires root permissions).
     ///
     /// # Panics
     /// This method panics if the interface name is longer than 31 bytes.
+    #[cfg(not(any(target_os = "freebsd", target_os = "macos")))]
     #[allow(clippy::useless_conversion)]
     pub fn new(ifname: &str, type_: Type, path: Option<&str>) -> io::Result<Self> {
+        let existing = !ifname.contains('%') && is_device_up(ifname).is_ok();
         let path = path.unwrap_or_else(|| Self::default_path(type_));
-        let fd = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let fd = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
         let flags = match type_ {
             Type::Tun => libc::IFF_TUN | libc::IFF_NO_PI,
             Type::Tap => libc::IFF_TAP | libc::IFF_NO_PI,
@@ -147,12 +215,29 @@ impl TunTapDevice {
         match res {
             0 => {
                 let mut ifname = String::with_capacity(32);
-                let mut cursor = Cursor::new(ifreq.ifr_name);
+                let mut cursor = io::Cursor::new(ifreq.ifr_name);
                 cursor.read_to_string(&mut ifname)?;
                 ifname = ifname.trim_end_matches('\0').to_owned();
+                if existing {
+                    info!("Attached to existing device {}", ifname);
+                }
                 Ok(Self { fd, ifname, type_ })
             }
-            _ => Err(IoError::last_os_error()),
+            _ => {
+                let err = IoError::last_os_error();
+                match err.raw_os_error() {
+                    // The kernel refuses names of devices that are no tun/tap devices of the requested type
+                    Some(libc::EINVAL) if existing => Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("Device {} already exists and is no {} device", ifname, type_),
+                    )),
+                    Some(libc::EBUSY) => Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("Device {} is already in use", ifname),
+                    )),
+                    _ => Err(err),
+                }
+            }
         }
     }
 
@@ -164,6 +249,17 @@ impl TunTapDevice {
         }
     }
 
+    /// Sets whether the device survives when it is closed
+    #[allow(clippy::useless_conversion)]
+    pub fn set_persistent(&self, persistent: bool) -> io::Result<()> {
+        let res =
+            unsafe { libc::ioctl(self.fd.as_raw_fd(), TUNSETPERSIST.try_into().unwrap(), persistent as libc::c_int) };
+        match res {
+            0 => Ok(()),
+            _ => Err(IoError::last_os_error()),
+        }
+    }
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     #[inline]
     fn correct_data_after_read(&mut self, _buffer: &mut MsgBuffer) {}
@@ -171,9 +267,7 @@ impl TunTapDevice {
     #[cfg(any(
         target_os = "bitrig",
         target_os = "dragonfly",
-        target_os = "freebsd",
         target_os = "ios",
-        target_os = "macos",
         target_os = "netbsd",
         target_os = "openbsd"
     ))]
@@ -193,9 +287,7 @@ impl TunTapDevice {
     #[cfg(any(
         target_os = "bitrig",
         target_os = "dragonfly",
-        target_os = "freebsd",
         target_os = "ios",
-        target_os = "macos",
         target_os = "netbsd",
         target_os = "openbsd"
     ))]
@@ -224,13 +316,16 @@ impl TunTapDevice {
         }
     }
 
+    /// The MTU of the default device minus the overhead of the VPN
+    pub fn optimal_mtu(&self) -> io::Result<usize> {
+        let default_device = get_default_device()?;
+        Ok(get_device_mtu(&default_device)? - self.get_overhead())
+    }
+
     pub fn set_mtu(&self, value: Option<usize>) -> io::Result<()> {
         let value = match value {
             Some(value) => value,
-            None => {
-                let default_device = get_default_device()?;
-                get_device_mtu(&default_device)? - self.get_overhead()
-            }
+            None => self.optimal_mtu()?,
         };
         info!("Setting MTU {} on device {}", value, self.ifname);
         set_device_mtu(&self.ifname, value)
@@ -270,7 +365,7 @@ impl Device for TunTapDevice {
 
     fn read(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error> {
         buffer.clear();
-        let read = self.fd.read(buffer.buffer()).map_err(|e| Error::DeviceIo("Read error", e))?;
+        let read = self.fd.read(buffer.buffer()).map_err(|e| Error::DeviceIo { message: "Read error", source: e })?;
         buffer.set_length(read);
         self.correct_data_after_read(buffer);
         Ok(())
@@ -279,13 +374,25 @@ impl Device for TunTapDevice {
     fn write(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error> {
         self.correct_data_before_write(buffer);
         match self.fd.write_all(buffer.message()) {
-            Ok(_) => self.fd.flush().map_err(|e| Error::DeviceIo("Flush error", e)),
-            Err(e) => Err(Error::DeviceIo("Write error", e)),
+            Ok(_) => self.fd.flush().map_err(|e| Error::DeviceIo { message: "Flush error", source: e }),
+            Err(e) => Err(Error::DeviceIo { message: "Write error", source: e }),
         }
     }
 
     fn get_ip(&self) -> Result<Ipv4Addr, Error> {
-        get_device_addr(&self.ifname).map_err(|e| Error::DeviceIo("Error getting IP address", e))
+        get_device_addr(&self.ifname).map_err(|e| Error::DeviceIo { message: "Error getting IP address", source: e })
+    }
+
+    fn get_mtu(&self) -> io::Result<u16> {
+        get_device_mtu(&self.ifname).map(|mtu| cmp::min(mtu, u16::MAX as usize) as u16)
+    }
+
+    fn set_mac(&self, mac: [u8; 6]) -> io::Result<()> {
+        set_device_hwaddr(&self.ifname, mac)
+    }
+
+    fn get_mac(&self) -> io::Result<[u8; 6]> {
+        get_device_hwaddr(&self.ifname)
     }
 }
 
@@ -347,6 +454,18 @@ impl Device for MockDevice {
     fn get_ip(&self) -> Result<Ipv4Addr, Error> {
         Err(Error::Device("Dummy devices have no IP address"))
     }
+
+    fn get_mtu(&self) -> io::Result<u16> {
+        Err(io::Error::other("Dummy devices have no MTU"))
+    }
+
+    fn set_mac(&self, _mac: [u8; 6]) -> io::Result<()> {
+        Err(io::Error::other("Dummy devices have no MAC address"))
+    }
+
+    fn get_mac(&self) -> io::Result<[u8; 6]> {
+        Err(io::Error::other("Dummy devices have no MAC address"))
+    }
 }
 
 impl Default for MockDevice {
@@ -367,7 +486,7 @@ fn set_device_mtu(ifname: &str, mtu: usize) -> io::Result<()> {
     let sock = UdpSocket::bind("0.0.0.0:0")?;
     let mut ifreq = IfReq::new(ifname);
     ifreq.data.value = mtu as libc::c_int;
-    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFMTU.try_into().unwrap(), &mut ifreq) };
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCSIFMTU.try_into().unwrap(), &mut ifreq) };
     match res {
         0 => Ok(()),
         _ => Err(IoError::last_os_error()),
@@ -378,7 +497,7 @@ fn set_device_mtu(ifname: &str, mtu: usize) -> io::Result<()> {
 fn get_device_mtu(ifname: &str) -> io::Result<usize> {
     let sock = UdpSocket::bind("0.0.0.0:0")?;
     let mut ifreq = IfReq::new(ifname);
-    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFMTU.try_into().unwrap(), &mut ifreq) };
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCGIFMTU.try_into().unwrap(), &mut ifreq) };
     match res {
         0 => Ok(unsafe { ifreq.data.value as usize }),
         _ => Err(IoError::last_os_error()),
@@ -392,11 +511,11 @@ fn get_device_addr(ifname: &str) -> io::Result<Ipv4Addr> {
     let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFADDR.try_into().unwrap(), &mut ifreq) };
     match res {
         0 => {
-            let af = unsafe { ifreq.data.addr.0 };
+            let af = unsafe { ifreq.data.addr.family };
             if af as libc::c_int != libc::AF_INET {
-                return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "Invalid address family".to_owned()));
+                return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "Invalid address family".to_owned()))
             }
-            let ip = unsafe { ifreq.data.addr.1 };
+            let ip = unsafe { ifreq.data.addr.ip };
             Ok(ip)
         }
         _ => Err(IoError::last_os_error()),
@@ -407,8 +526,8 @@ fn get_device_addr(ifname: &str) -> io::Result<Ipv4Addr> {
 fn set_device_addr(ifname: &str, addr: Ipv4Addr) -> io::Result<()> {
     let sock = UdpSocket::bind("0.0.0.0:0")?;
     let mut ifreq = IfReq::new(ifname);
-    ifreq.data.addr = (libc::AF_INET as libc::c_short, addr);
-    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFADDR.try_into().unwrap(), &mut ifreq) };
+    ifreq.data.addr = IfAddr::new(addr);
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCSIFADDR.try_into().unwrap(), &mut ifreq) };
     match res {
         0 => Ok(()),
         _ => Err(IoError::last_os_error()),
@@ -420,14 +539,14 @@ fn set_device_addr(ifname: &str, addr: Ipv4Addr) -> io::Result<()> {
 fn get_device_netmask(ifname: &str) -> io::Result<Ipv4Addr> {
     let sock = UdpSocket::bind("0.0.0.0:0")?;
     let mut ifreq = IfReq::new(ifname);
-    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFNETMASK.try_into().unwrap(), &mut ifreq) };
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCGIFNETMASK.try_into().unwrap(), &mut ifreq) };
     match res {
         0 => {
-            let af = unsafe { ifreq.data.addr.0 };
+            let af = unsafe { ifreq.data.addr.family };
             if af as libc::c_int != libc::AF_INET {
-                return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "Invalid address family".to_owned()));
+                return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "Invalid address family".to_owned()))
             }
-            let ip = unsafe { ifreq.data.addr.1 };
+            let ip = unsafe { ifreq.data.addr.ip };
             Ok(ip)
         }
         _ => Err(IoError::last_os_error()),
@@ -438,8 +557,8 @@ fn get_device_netmask(ifname: &str) -> io::Result<Ipv4Addr> {
 fn set_device_netmask(ifname: &str, addr: Ipv4Addr) -> io::Result<()> {
     let sock = UdpSocket::bind("0.0.0.0:0")?;
     let mut ifreq = IfReq::new(ifname);
-    ifreq.data.addr = (libc::AF_INET as libc::c_short, addr);
-    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFNETMASK.try_into().unwrap(), &mut ifreq) };
+    ifreq.data.addr = IfAddr::new(addr);
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCSIFNETMASK.try_into().unwrap(), &mut ifreq) };
     match res {
         0 => Ok(()),
         _ => Err(IoError::last_os_error()),
@@ -450,21 +569,105 @@ fn set_device_netmask(ifname: &str, addr: Ipv4Addr) -> io::Result<()> {
 fn set_device_enabled(ifname: &str, up: bool) -> io::Result<()> {
     let sock = UdpSocket::bind("0.0.0.0:0")?;
     let mut ifreq = IfReq::new(ifname);
-    if unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFFLAGS.try_into().unwrap(), &mut ifreq) } != 0 {
-        return Err(IoError::last_os_error());
+    if unsafe { libc::ioctl(sock.as_raw_fd(), SIOCGIFFLAGS.try_into().unwrap(), &mut ifreq) } != 0 {
+        return Err(IoError::last_os_error())
     }
     if up {
         unsafe { ifreq.data.value |= libc::IFF_UP | libc::IFF_RUNNING }
     } else {
         unsafe { ifreq.data.value &= !libc::IFF_UP }
     }
-    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFFLAGS.try_into().unwrap(), &mut ifreq) };
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCSIFFLAGS.try_into().unwrap(), &mut ifreq) };
     match res {
         0 => Ok(()),
         _ => Err(IoError::last_os_error()),
     }
 }
 
+/// Returns the MAC address of a device
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[allow(clippy::useless_conversion)]
+pub fn get_device_hwaddr(ifname: &str) -> io::Result<[u8; 6]> {
+    let sock = UdpSocket::bind("0.0.0.0:0")?;
+    let mut ifreq = IfReq::new(ifname);
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFHWADDR.try_into().unwrap(), &mut ifreq) };
+    match res {
+        0 => {
+            let data = unsafe { ifreq.data.hwaddr.sa_data };
+            let mut mac = [0; 6];
+            for (m, d) in mac.iter_mut().zip(data.iter()) {
+                *m = *d as u8
+            }
+            Ok(mac)
+        }
+        _ => Err(IoError::last_os_error()),
+    }
+}
+
+/// Returns the MAC address of a device
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn get_device_hwaddr(_ifname: &str) -> io::Result<[u8; 6]> {
+    Err(io::Error::other("MAC addresses can only be read on Linux"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[allow(clippy::useless_conversion)]
+fn set_device_hwaddr(ifname: &str, mac: [u8; 6]) -> io::Result<()> {
+    let sock = UdpSocket::bind("0.0.0.0:0")?;
+    let mut ifreq = IfReq::new(ifname);
+    let mut hwaddr: libc::sockaddr = unsafe { mem::zeroed() };
+    hwaddr.sa_family = libc::ARPHRD_ETHER;
+    for (d, m) in hwaddr.sa_data.iter_mut().zip(mac.iter()) {
+        *d = *m as libc::c_char
+    }
+    ifreq.data.hwaddr = hwaddr;
+    let res = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFHWADDR.try_into().unwrap(), &mut ifreq) };
+    match res {
+        0 => Ok(()),
+        _ => Err(IoError::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_device_hwaddr(_ifname: &str, _mac: [u8; 6]) -> io::Result<()> {
+    Err(io::Error::other("MAC addresses can only be set on Linux"))
+}
+
+/// Parses a MAC address in the form `aa:bb:cc:dd:ee:ff`
+pub fn parse_mac(text: &str) -> Result<[u8; 6], Error> {
+    let parts: Vec<_> = text.split(':').collect();
+    if parts.len() != 6 {
+        return Err(Error::Parse("Invalid MAC address"))
+    }
+    let mut mac = [0; 6];
+    for (m, p) in mac.iter_mut().zip(parts) {
+        *m = u8::from_str_radix(p, 16).map_err(|_| Error::Parse("Invalid MAC address"))?;
+    }
+    if mac[0] & 0x01 != 0 {
+        return Err(Error::Parse("MAC address must not be a multicast address"))
+    }
+    Ok(mac)
+}
+
+/// Derives a locally administered unicast MAC address from a node id
+pub fn mac_from_node_id(node_id: &NodeId) -> [u8; 6] {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&node_id[..6]);
+    mac[0] = (mac[0] | 0x02) & 0xfe;
+    mac
+}
+
+/// Returns whether a device exists and is up
+#[allow(clippy::useless_conversion)]
+pub fn is_device_up(ifname: &str) -> io::Result<bool> {
+    let sock = UdpSocket::bind("0.0.0.0:0")?;
+    let mut ifreq = IfReq::new(ifname);
+    if unsafe { libc::ioctl(sock.as_raw_fd(), SIOCGIFFLAGS.try_into().unwrap(), &mut ifreq) } != 0 {
+        return Err(IoError::last_os_error())
+    }
+    Ok(unsafe { ifreq.data.flags } & libc::IFF_UP as libc::c_short != 0)
+}
+
 fn get_default_device() -> io::Result<String> {
     let fd = BufReader::new(File::open("/proc/net/route")?);
     let mut best = None;
@@ -473,7 +676,7 @@ fn get_default_device() -> io::Result<String> {
         let parts = line.split('\t').collect::<Vec<_>>();
         if parts[1] == "00000000" {
             best = Some(parts[0].to_string());
-            break;
+            break
         }
         if parts[2] != "00000000" {
             best = Some(parts[0].to_string())
@@ -497,3 +700,42 @@ fn set_rp_filter(device: &str, val: u8) -> io::Result<()> {
     let mut fd = File::create(format!("/proc/sys/net/ipv4/conf/{}/rp_filter", device))?;
     writeln!(fd, "{}", val)
 }
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn persistent_device() {
+    let device = match TunTapDevice::new("vpnpersist0", Type::Tun, None) {
+        Ok(device) => device,
+        Err(_) => {
+            warn!("Skipping test as TUN devices can not be created");
+            return
+        }
+    };
+    device.set_persistent(true).unwrap();
+    drop(device);
+    assert!(is_device_up("vpnpersist0").is_ok());
+    assert!(TunTapDevice::new("vpnpersist0", Type::Tap, None).is_err());
+    let device = TunTapDevice::new("vpnpersist0", Type::Tun, None).unwrap();
+    device.set_persistent(false).unwrap();
+    drop(device);
+    assert!(is_device_up("vpnpersist0").is_err());
+    assert!(TunTapDevice::new("lo", Type::Tun, None).is_err());
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn tap_mac_address() {
+    assert_eq!(parse_mac("02:00:5e:10:00:ff").unwrap(), [2, 0, 0x5e, 0x10, 0, 0xff]);
+    assert!(parse_mac("01:00:5e:10:00:ff").is_err());
+    assert!(parse_mac("02:00:5e:10:00").is_err());
+    assert_eq!(mac_from_node_id(&[0xff; 16]), [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    let device = match TunTapDevice::new("vpnmac%d", Type::Tap, None) {
+        Ok(device) => device,
+        Err(_) => {
+            warn!("Skipping test as TAP devices can not be created");
+            return
+        }
+    };
+    device.set_mac([2, 0, 0x5e, 0x10, 0, 0xff]).unwrap();
+    assert_eq!(device.get_mac().unwrap(), [2, 0, 0x5e, 0x10, 0, 0xff]);
+}
@@ -0,0 +1,127 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+//! tun and tap devices of FreeBSD
+//!
+//! Opening `/dev/tun` or `/dev/tap` creates the next free device, opening e.g. `/dev/tun3` creates or attaches to
+//! that device.
+
+use std::{
+    ffi::CStr,
+    fs::{self, File},
+    io::{self, Error as IoError},
+    os::unix::{fs::MetadataExt, io::AsRawFd},
+};
+
+use super::{address_family_header, TunTapDevice, Type};
+use crate::util::MsgBuffer;
+
+static TUNSIFMODE: libc::c_ulong = 0x8004745e;
+static TUNSIFHEAD: libc::c_ulong = 0x80047460;
+
+// The interface ioctls that the libc crate does not define for FreeBSD
+pub(super) const SIOCSIFADDR: libc::c_ulong = 0x8020690c;
+pub(super) const SIOCSIFFLAGS: libc::c_ulong = 0x80206910;
+pub(super) const SIOCGIFFLAGS: libc::c_ulong = 0xc0206911;
+pub(super) const SIOCSIFNETMASK: libc::c_ulong = 0x80206916;
+pub(super) const SIOCGIFNETMASK: libc::c_ulong = 0xc0206925;
+pub(super) const SIOCGIFMTU: libc::c_ulong = 0xc0206933;
+pub(super) const SIOCSIFMTU: libc::c_ulong = 0x80206934;
+
+/// Returns the path of the device node to open for the given name
+fn device_path(ifname: &str, type_: Type) -> io::Result<String> {
+    if ifname.contains('%') {
+        return Ok(format!("/dev/{}", type_))
+    }
+    let valid = ifname
+        .strip_prefix(&type_.to_string() as &str)
+        .map(|num| !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()))
+        .unwrap_or(false);
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid device name {}, {} devices on FreeBSD are named {} followed by a number",
+                ifname, type_, type_
+            ),
+        ))
+    }
+    Ok(format!("/dev/{}", ifname))
+}
+
+/// Returns the name of the device behind the file
+fn device_name(fd: &File) -> io::Result<String> {
+    let rdev = fd.metadata()?.rdev();
+    let mut name = [0 as libc::c_char; 32];
+    let res =
+        unsafe { libc::devname_r(rdev as libc::dev_t, libc::S_IFCHR, name.as_mut_ptr(), name.len() as libc::c_int) };
+    if res.is_null() {
+        return Err(IoError::last_os_error())
+    }
+    Ok(unsafe { CStr::from_ptr(res) }.to_string_lossy().into_owned())
+}
+
+fn set_int_option(fd: &File, request: libc::c_ulong, value: libc::c_int) -> io::Result<()> {
+    match unsafe { libc::ioctl(fd.as_raw_fd(), request, &value) } {
+        0 => Ok(()),
+        _ => Err(IoError::last_os_error()),
+    }
+}
+
+impl TunTapDevice {
+    /// Creates a new tun/tap device
+    ///
+    /// The `ifname` must be `tun` or `tap` followed by a number or contain `%d` to use the next free device. The
+    /// final name can be obtained with the `ifname()` method. The `path` overrides the device node to open.
+    ///
+    /// # Errors
+    /// This method will return an error when the name is invalid or when the underlying system call fails, e.g. when
+    /// the device is in use or the user is not root.
+    pub fn new(ifname: &str, type_: Type, path: Option<&str>) -> io::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => device_path(ifname, type_)?,
+        };
+        let fd = fs::OpenOptions::new().read(true).write(true).open(&path).map_err(|err| match err.raw_os_error() {
+            Some(libc::EBUSY) => {
+                io::Error::new(io::ErrorKind::AlreadyExists, format!("Device {} is already in use", path))
+            }
+            _ => err,
+        })?;
+        if type_ == Type::Tun {
+            set_int_option(&fd, TUNSIFMODE, libc::IFF_POINTOPOINT | libc::IFF_MULTICAST)?;
+            // Prefix each packet with its address family so that IPv6 packets can be written
+            set_int_option(&fd, TUNSIFHEAD, 1)?;
+        }
+        let ifname = device_name(&fd)?;
+        Ok(Self { fd, ifname, type_ })
+    }
+
+    #[inline]
+    pub(super) fn correct_data_after_read(&mut self, buffer: &mut MsgBuffer) {
+        if self.type_ == Type::Tun {
+            buffer.set_start(buffer.get_start() + 4);
+        }
+    }
+
+    #[inline]
+    pub(super) fn correct_data_before_write(&mut self, buffer: &mut MsgBuffer) {
+        if self.type_ == Type::Tun {
+            buffer.set_start(buffer.get_start() - 4);
+            let header = address_family_header(buffer.message()[4] >> 4);
+            buffer.message_mut()[0..4].copy_from_slice(&header);
+        }
+    }
+}
+
+#[test]
+fn device_paths() {
+    assert_eq!(device_path("vpncloud%d", Type::Tun).unwrap(), "/dev/tun");
+    assert_eq!(device_path("vpncloud%d", Type::Tap).unwrap(), "/dev/tap");
+    assert_eq!(device_path("tun3", Type::Tun).unwrap(), "/dev/tun3");
+    assert_eq!(device_path("tap0", Type::Tap).unwrap(), "/dev/tap0");
+    assert!(device_path("tap0", Type::Tun).is_err());
+    assert!(device_path("tun", Type::Tun).is_err());
+    assert!(device_path("tun1a", Type::Tun).is_err());
+}
@@ -0,0 +1,145 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+//! utun devices of macOS
+//!
+//! These devices are part of the system and need no kernel extension, but they only support IP packets (TUN mode).
+
+use std::{
+    fs::File,
+    io::{self, Error as IoError},
+    mem,
+    os::unix::io::{AsRawFd, FromRawFd},
+};
+
+use super::{address_family_header, TunTapDevice, Type};
+use crate::util::MsgBuffer;
+
+const UTUN_CONTROL_NAME: &[u8] = b"com.apple.net.utun_control";
+const MAX_KCTL_NAME: usize = 96;
+const IFNAMSIZ: usize = 16;
+static CTLIOCGINFO: libc::c_ulong = 0xc0644e03;
+
+/// Kernel control info as in `struct ctl_info`
+#[repr(C)]
+struct CtlInfo {
+    ctl_id: u32,
+    ctl_name: [u8; MAX_KCTL_NAME],
+}
+
+/// Returns the unit of the device, 0 lets the kernel pick a free one
+///
+/// The device `utunN` is unit `N+1`, names with a placeholder get the next free device.
+fn parse_unit(ifname: &str) -> io::Result<u32> {
+    if ifname.contains('%') {
+        return Ok(0)
+    }
+    match ifname.strip_prefix("utun").and_then(|num| num.parse::<u32>().ok()) {
+        Some(num) => Ok(num + 1),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid device name {}, devices on macOS are named utun followed by a number", ifname),
+        )),
+    }
+}
+
+/// Creates a utun device and returns it together with its name
+fn create_utun(ifname: &str) -> io::Result<(File, String)> {
+    let unit = parse_unit(ifname)?;
+    let fd = unsafe { libc::socket(libc::PF_SYSTEM, libc::SOCK_DGRAM, libc::SYSPROTO_CONTROL) };
+    if fd < 0 {
+        return Err(IoError::last_os_error())
+    }
+    // The file closes the socket if anything fails
+    let fd = unsafe { File::from_raw_fd(fd) };
+    let mut info = CtlInfo { ctl_id: 0, ctl_name: [0; MAX_KCTL_NAME] };
+    info.ctl_name[..UTUN_CONTROL_NAME.len()].copy_from_slice(UTUN_CONTROL_NAME);
+    if unsafe { libc::ioctl(fd.as_raw_fd(), CTLIOCGINFO, &mut info) } < 0 {
+        return Err(IoError::last_os_error())
+    }
+    let addr = libc::sockaddr_ctl {
+        sc_len: mem::size_of::<libc::sockaddr_ctl>() as libc::c_uchar,
+        sc_family: libc::AF_SYSTEM as libc::c_uchar,
+        ss_sysaddr: libc::AF_SYS_CONTROL as u16,
+        sc_id: info.ctl_id,
+        sc_unit: unit,
+        sc_reserved: [0; 5],
+    };
+    let res = unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_ctl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ctl>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        let err = IoError::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::EBUSY) => {
+                Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("Device {} is already in use", ifname)))
+            }
+            _ => Err(err),
+        }
+    }
+    let mut name = [0u8; IFNAMSIZ];
+    let mut len = name.len() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SYSPROTO_CONTROL,
+            libc::UTUN_OPT_IFNAME,
+            name.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(IoError::last_os_error())
+    }
+    let name = String::from_utf8_lossy(&name[..len as usize]).trim_end_matches('\0').to_owned();
+    Ok((fd, name))
+}
+
+impl TunTapDevice {
+    /// Creates a new utun device
+    ///
+    /// The `ifname` must be `utun` followed by a number or contain `%d` to use the next free device. The final name
+    /// can be obtained with the `ifname()` method.
+    ///
+    /// # Errors
+    /// This method will return an error when a TAP device is requested, when the name is invalid or when the
+    /// underlying system call fails, e.g. when the device is in use or the user is not root.
+    pub fn new(ifname: &str, type_: Type, _path: Option<&str>) -> io::Result<Self> {
+        if type_ == Type::Tap {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TAP devices are not supported on macOS, only TUN devices can be used",
+            ))
+        }
+        let (fd, ifname) = create_utun(ifname)?;
+        Ok(Self { fd, ifname, type_ })
+    }
+
+    #[inline]
+    pub(super) fn correct_data_after_read(&mut self, buffer: &mut MsgBuffer) {
+        // utun devices prepend the address family of the packet
+        buffer.set_start(buffer.get_start() + 4);
+    }
+
+    #[inline]
+    pub(super) fn correct_data_before_write(&mut self, buffer: &mut MsgBuffer) {
+        buffer.set_start(buffer.get_start() - 4);
+        let header = address_family_header(buffer.message()[4] >> 4);
+        buffer.message_mut()[0..4].copy_from_slice(&header);
+    }
+}
+
+#[test]
+fn utun_units() {
+    assert_eq!(parse_unit("vpncloud%d").unwrap(), 0);
+    assert_eq!(parse_unit("utun0").unwrap(), 1);
+    assert_eq!(parse_unit("utun12").unwrap(), 13);
+    assert!(parse_unit("tun0").is_err());
+    assert!(parse_unit("utun").is_err());
+    assert!(parse_unit("utunx").is_err());
+}
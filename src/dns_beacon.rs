@@ -0,0 +1,306 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{fs, net::SocketAddr};
+
+use crate::{
+    beacon::{limit_peers, BeaconSerializer, BeaconStore, BEACON_TTL_HOURS},
+    error::Error,
+    util::TimeSource,
+};
+
+/// Label below the beacon domain that holds the TXT records
+pub const DNS_BEACON_LABEL: &str = "_vpncloud";
+/// The strings in a TXT record can not be longer than this
+const MAX_TXT_LEN: usize = 255;
+
+/// A TSIG key in the format written by `tsig-keygen`
+#[derive(Debug, PartialEq)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: String,
+    /// The base64 encoded secret
+    pub secret: String,
+}
+
+impl TsigKey {
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let data: String = data
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#') && !line.trim_start().starts_with("//"))
+            .map(|line| line.replace(['{', '}', ';'], " "))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut tokens = data.split_whitespace().map(|t| t.trim_matches('"'));
+        let (mut name, mut algorithm, mut secret) = (None, None, None);
+        while let Some(token) = tokens.next() {
+            match token {
+                "key" => name = tokens.next(),
+                "algorithm" => algorithm = tokens.next(),
+                "secret" => secret = tokens.next(),
+                _ => (),
+            }
+        }
+        match (name, algorithm, secret) {
+            (Some(name), Some(algorithm), Some(secret)) => {
+                Ok(Self { name: name.to_string(), algorithm: algorithm.to_string(), secret: secret.to_string() })
+            }
+            _ => Err(Error::Parse("Invalid TSIG key file")),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::FileIo { message: "Failed to read TSIG key file", source: e })?;
+        Self::parse(&data)
+    }
+}
+
+#[cfg(feature = "dns")]
+mod internal {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use data_encoding::BASE64;
+    use trust_dns_proto::{
+        op::{Message, MessageType, OpCode, Query, ResponseCode, UpdateMessage},
+        rr::{
+            dnssec::{rdata::tsig::TsigAlgorithm, tsig::TSigner},
+            rdata::TXT,
+            DNSClass, Name, RData, Record, RecordType,
+        },
+    };
+    use trust_dns_resolver::{error::ResolveErrorKind, Resolver};
+
+    use super::{TsigKey, DNS_BEACON_LABEL};
+    use crate::error::Error;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    /// Short, so that new beacons are seen soon by the other nodes
+    const RECORD_TTL: u32 = 60;
+    /// Maximum clock difference to the name server that the signatures allow
+    const TSIG_FUDGE: u16 = 300;
+
+    fn dns_error<E: ToString>(err: E) -> Error {
+        Error::Beacon(err.to_string())
+    }
+
+    pub struct DnsClient {
+        resolver: Resolver,
+        zone: Name,
+        name: Name,
+        signer: Option<TSigner>,
+    }
+
+    impl DnsClient {
+        pub fn new(domain: &str, key: Option<TsigKey>) -> Result<Self, Error> {
+            let mut zone = Name::from_ascii(domain).map_err(|_| Error::InvalidConfig("Invalid DNS beacon domain"))?;
+            zone.set_fqdn(true);
+            let name = Name::from_ascii(DNS_BEACON_LABEL)
+                .and_then(|label| label.append_domain(&zone))
+                .map_err(|_| Error::InvalidConfig("Invalid DNS beacon domain"))?;
+            let signer = match key {
+                Some(key) => {
+                    let secret = BASE64
+                        .decode(key.secret.as_bytes())
+                        .map_err(|_| Error::InvalidConfig("Invalid TSIG secret"))?;
+                    let algorithm = Name::from_ascii(&key.algorithm)
+                        .map(TsigAlgorithm::from_name)
+                        .map_err(|_| Error::InvalidConfig("Invalid TSIG algorithm"))?;
+                    let key_name =
+                        Name::from_ascii(&key.name).map_err(|_| Error::InvalidConfig("Invalid TSIG key name"))?;
+                    Some(
+                        TSigner::new(secret, algorithm, key_name, TSIG_FUDGE)
+                            .map_err(|_| Error::InvalidConfig("Unsupported TSIG algorithm"))?,
+                    )
+                }
+                None => None,
+            };
+            let resolver = Resolver::from_system_conf()
+                .map_err(|e| Error::BeaconIo { message: "Failed to create resolver", source: e })?;
+            Ok(Self { resolver, zone, name, signer })
+        }
+
+        /// Returns the contents of all TXT records
+        pub fn lookup(&self) -> Result<Vec<String>, Error> {
+            let records = match self.resolver.txt_lookup(self.name.clone()) {
+                Ok(records) => records,
+                Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => return Ok(vec![]),
+                Err(err) => return Err(dns_error(err)),
+            };
+            Ok(records.iter().map(|txt| txt.txt_data().iter().map(|s| String::from_utf8_lossy(s)).collect()).collect())
+        }
+
+        /// Finds the primary name server of the zone, updates must be sent there
+        fn primary_server(&self) -> Result<SocketAddr, Error> {
+            let soa = self.resolver.soa_lookup(self.zone.clone()).map_err(dns_error)?;
+            let mname =
+                soa.iter().next().ok_or_else(|| Error::Beacon("No SOA record found".to_string()))?.mname().clone();
+            let ips = self.resolver.lookup_ip(mname).map_err(dns_error)?;
+            let ip = ips.iter().next().ok_or_else(|| Error::Beacon("Primary name server not found".to_string()))?;
+            Ok(SocketAddr::new(ip, 53))
+        }
+
+        /// Replaces the TXT record `old` with `new` with a dynamic update (RFC 2136)
+        pub fn update(&self, old: Option<&str>, new: Option<&str>) -> Result<(), Error> {
+            let record = |data: &str| {
+                Record::from_rdata(self.name.clone(), RECORD_TTL, RData::TXT(TXT::new(vec![data.to_string()])))
+            };
+            let mut message = Message::new();
+            message
+                .set_id(rand::random())
+                .set_message_type(MessageType::Query)
+                .set_op_code(OpCode::Update)
+                .set_recursion_desired(false);
+            message.add_zone(Query::query(self.zone.clone(), RecordType::SOA));
+            if let Some(old) = old {
+                let mut delete = record(old);
+                delete.set_dns_class(DNSClass::NONE).set_ttl(0);
+                message.add_update(delete);
+            }
+            if let Some(new) = new {
+                message.add_update(record(new));
+            }
+            let verifier = match self.signer {
+                Some(ref signer) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time before epoch").as_secs();
+                    message.finalize(signer, now as u32).map_err(dns_error)?
+                }
+                None => None,
+            };
+            let request = message.to_vec().map_err(dns_error)?;
+            let server = self.primary_server()?;
+            let socket = UdpSocket::bind(if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })
+                .and_then(|socket| socket.connect(server).map(|_| socket))
+                .map_err(|e| Error::BeaconIo { message: "Failed to open DNS socket", source: e })?;
+            socket
+                .set_read_timeout(Some(TIMEOUT))
+                .map_err(|e| Error::BeaconIo { message: "Failed to open DNS socket", source: e })?;
+            socket.send(&request).map_err(|e| Error::BeaconIo { message: "Failed to send DNS update", source: e })?;
+            let mut buffer = [0; 4096];
+            let size = socket
+                .recv(&mut buffer)
+                .map_err(|e| Error::BeaconIo { message: "Failed to receive DNS response", source: e })?;
+            let response = match verifier {
+                Some(mut verify) => verify(&buffer[..size]).map_err(dns_error)?.into_message(),
+                None => Message::from_vec(&buffer[..size]).map_err(dns_error)?,
+            };
+            if response.id() != message.id() {
+                return Err(Error::Beacon("DNS response does not match the update".to_string()))
+            }
+            if response.response_code() != ResponseCode::NoError {
+                return Err(Error::Beacon(format!("DNS update failed: {}", response.response_code())))
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "dns"))]
+mod internal {
+    use super::TsigKey;
+    use crate::error::Error;
+
+    pub struct DnsClient;
+
+    impl DnsClient {
+        pub fn new(_domain: &str, _key: Option<TsigKey>) -> Result<Self, Error> {
+            Err(Error::InvalidConfig("DNS beacons are not supported by this build"))
+        }
+
+        pub fn lookup(&self) -> Result<Vec<String>, Error> {
+            unreachable!("DNS beacons are not supported by this build")
+        }
+
+        pub fn update(&self, _old: Option<&str>, _new: Option<&str>) -> Result<(), Error> {
+            unreachable!("DNS beacons are not supported by this build")
+        }
+    }
+}
+
+use internal::DnsClient;
+
+/// Stores the beacon as a TXT record of `_vpncloud.<domain>` and loads the beacons from all TXT records there
+///
+/// The record is published with dynamic DNS updates signed with a TSIG key to the primary name server of the domain.
+/// Every node only replaces its own record, so the beacons of all nodes are found.
+pub struct DnsBeaconStore<TS> {
+    serializer: BeaconSerializer<TS>,
+    client: DnsClient,
+    /// The beacon that this node published last
+    published: Option<String>,
+}
+
+impl<TS: TimeSource> DnsBeaconStore<TS> {
+    /// Opens the store, without a key file it can only load beacons
+    pub fn open(domain: &str, key_file: Option<&str>, serializer: BeaconSerializer<TS>) -> Result<Self, Error> {
+        let key = key_file.map(TsigKey::load).transpose()?;
+        Ok(Self { serializer, client: DnsClient::new(domain, key)?, published: None })
+    }
+}
+
+impl<TS: TimeSource> BeaconStore for DnsBeaconStore<TS> {
+    fn write(&mut self, peers: &[SocketAddr]) -> Result<(), Error> {
+        let beacon = self.serializer.encode(peers);
+        if beacon.len() > MAX_TXT_LEN {
+            return Err(Error::Beacon("Beacon is too long for a TXT record".to_string()))
+        }
+        if self.published.as_ref() == Some(&beacon) {
+            return Ok(())
+        }
+        self.client.update(self.published.as_deref(), Some(&beacon))?;
+        self.published = Some(beacon);
+        Ok(())
+    }
+
+    fn read(&mut self, max: Option<usize>) -> Result<Vec<SocketAddr>, Error> {
+        let data = self.client.lookup()?.join(" ");
+        Ok(limit_peers(self.serializer.decode_checked(&data, Some(BEACON_TTL_HOURS))?, max))
+    }
+
+    fn remove(&mut self) -> Result<(), Error> {
+        if let Some(beacon) = self.published.take() {
+            self.client.update(Some(&beacon), None)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use crate::util::MockTimeSource;
+
+#[test]
+fn tsig_key_file() {
+    let key = TsigKey::parse(
+        "# Generated by tsig-keygen\nkey \"vpncloud\" {\n\talgorithm hmac-sha256;\n\tsecret \"c2VjcmV0\";\n};\n",
+    )
+    .unwrap();
+    assert_eq!(
+        key,
+        TsigKey { name: "vpncloud".to_string(), algorithm: "hmac-sha256".to_string(), secret: "c2VjcmV0".to_string() }
+    );
+    assert!(TsigKey::parse("key \"vpncloud\" { algorithm hmac-sha256; };").is_err());
+}
+
+#[test]
+fn beacon_fits_txt_record() {
+    MockTimeSource::set_time(2000 * 3600);
+    let ser = BeaconSerializer::<MockTimeSource>::new(b"mysecretkey");
+    // The node stores at most 3 of its addresses in a beacon
+    let peers: Vec<SocketAddr> = vec![
+        "[2001:db8::1]:65535".parse().unwrap(),
+        "[2001:db8::2]:65535".parse().unwrap(),
+        "[2001:db8::3]:65535".parse().unwrap(),
+    ];
+    let beacon = ser.encode(&peers);
+    assert!(beacon.len() <= MAX_TXT_LEN);
+    assert!(beacon.chars().all(|c| c.is_ascii_alphanumeric()));
+    assert_eq!(ser.decode(&beacon, Some(BEACON_TTL_HOURS)), peers);
+    let ser = ser.with_encryption(b"mysecretkey", false);
+    let beacon = ser.encode(&peers);
+    assert!(beacon.len() <= MAX_TXT_LEN);
+    assert_eq!(ser.decode_checked(&beacon, Some(BEACON_TTL_HOURS)).unwrap(), peers);
+}
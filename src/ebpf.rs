@@ -0,0 +1,144 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Early classification of received packets in the kernel
+//
+// A BPF program attached to the socket drops packets that can not be VpnCloud messages before they are queued to the
+// socket, so floods of invalid packets do not wake up the node. The program is written in classic BPF, which the kernel
+// translates to eBPF and compiles, so it needs neither a BPF toolchain at build time nor privileges at runtime.
+//
+// The protocol has no magic bytes, but every message starts with either the init marker or the id of the key that
+// encrypted it and encrypted messages contain at least the nonce, the message type and the tag. This only holds when
+// all messages are handled by the crypto core, so the filter is not used with unencrypted messages, plaintext peers,
+// the Noise protocol, VXLAN or TURN.
+
+use std::{io, mem, os::unix::io::RawFd};
+
+use crate::{
+    config::Config,
+    crypto::{Crypto, EXTRA_LEN, INIT_MESSAGE_FIRST_BYTE, TAG_LEN},
+};
+
+const BPF_LD_LEN: u16 = 0x80;
+const BPF_LDB_ABS: u16 = 0x30;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+/// The filter sees the packets including the UDP header
+const UDP_HEADER_LEN: u32 = 8;
+/// Number of keys that a peer can encrypt with, their id is the first byte of encrypted messages
+const KEY_COUNT: u32 = 4;
+/// Key id and nonce, message type and tag
+const MIN_ENCRYPTED_LEN: u32 = (EXTRA_LEN + 1 + TAG_LEN) as u32;
+
+const fn op(code: u16, jt: u8, jf: u8, k: u32) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+/// The jump offsets count the instructions skipped after the next one
+const PROGRAM: [libc::sock_filter; 9] = [
+    op(BPF_LD_LEN, 0, 0, 0),
+    op(BPF_JGE_K, 0, 6, UDP_HEADER_LEN + 1),
+    op(BPF_LDB_ABS, 0, 0, UDP_HEADER_LEN),
+    op(BPF_JEQ_K, 3, 0, INIT_MESSAGE_FIRST_BYTE as u32),
+    op(BPF_JGE_K, 3, 0, KEY_COUNT),
+    op(BPF_LD_LEN, 0, 0, 0),
+    op(BPF_JGE_K, 0, 1, UDP_HEADER_LEN + MIN_ENCRYPTED_LEN),
+    // Accept the whole packet
+    op(BPF_RET_K, 0, 0, u32::MAX),
+    // Drop the packet
+    op(BPF_RET_K, 0, 0, 0),
+];
+
+/// Whether all messages that the node accepts with this config pass the filter
+pub fn applies(config: &Config) -> bool {
+    let unencrypted = Crypto::parse_algorithms(&config.crypto.algorithms).map(|(plain, _)| plain).unwrap_or(true);
+    !unencrypted
+        && config.plaintext_peers.is_empty()
+        && !config.noise_protocol
+        && config.vxlan_vni.is_none()
+        && config.turn_servers.is_empty()
+}
+
+fn set_filter(fd: RawFd, program: &mut [libc::sock_filter]) -> Result<(), io::Error> {
+    let fprog = libc::sock_fprog { len: program.len() as libc::c_ushort, filter: program.as_mut_ptr() };
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_FILTER,
+            &fprog as *const libc::sock_fprog as *const libc::c_void,
+            mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Attaches the filter to the socket
+pub fn attach(fd: RawFd) -> Result<(), io::Error> {
+    set_filter(fd, &mut PROGRAM.clone())
+}
+
+/// Attaches the filter of one socket to another socket, if the first socket has one
+pub fn copy(from: RawFd, to: RawFd) -> Result<(), io::Error> {
+    // The length is given in instructions, a length of 0 only returns the length of the program
+    let mut len: libc::socklen_t = 0;
+    let res = unsafe { libc::getsockopt(from, libc::SOL_SOCKET, libc::SO_GET_FILTER, std::ptr::null_mut(), &mut len) };
+    if res != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    if len == 0 {
+        return Ok(())
+    }
+    let mut program = vec![op(0, 0, 0, 0); len as usize];
+    let res = unsafe {
+        libc::getsockopt(
+            from,
+            libc::SOL_SOCKET,
+            libc::SO_GET_FILTER,
+            program.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    program.truncate(len as usize);
+    set_filter(to, &mut program)
+}
+
+#[test]
+fn drop_invalid_packets() {
+    use std::{net::UdpSocket, os::unix::io::AsRawFd, time::Duration};
+    let filtered = UdpSocket::bind("127.0.0.1:0").unwrap();
+    attach(filtered.as_raw_fd()).unwrap();
+    let copied = UdpSocket::bind("127.0.0.1:0").unwrap();
+    copy(filtered.as_raw_fd(), copied.as_raw_fd()).unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let messages: [&[u8]; 6] = [b"", b"GET / HTTP/1.0", &[0; 20], &[0xff, 1, 2], &[4; 40], &[3; 25]];
+    for socket in &[&filtered, &copied] {
+        socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        for msg in &messages {
+            sender.send_to(msg, socket.local_addr().unwrap()).unwrap();
+        }
+        let mut buffer = [0; 64];
+        let mut received = vec![];
+        while let Ok(len) = socket.recv(&mut buffer) {
+            received.push(buffer[..len].to_vec())
+        }
+        assert_eq!(received, vec![vec![0xff, 1, 2], vec![3; 25]]);
+    }
+}
+
+#[test]
+fn applies_to_encrypted_config() {
+    assert!(applies(&Config::default()));
+    // Messages of plaintext peers start with the message type
+    assert!(!applies(&Config { plaintext_peers: vec!["1.2.3.4:3210".parse().unwrap()], ..Config::default() }));
+    assert!(!applies(&Config { vxlan_vni: Some(1234), ..Config::default() }));
+}
@@ -7,6 +7,7 @@ use thiserror::Error;
 use std::io;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Crypto init error, this is recoverable
     #[error("Crypto initialization error: {0}")]
@@ -20,36 +21,117 @@ pub enum Error {
     #[error("Crypto error: {0}")]
     Crypto(&'static str),
 
+    /// Peer authentication failed, the peer must not be added
+    #[error("Authentication failed: {0}")]
+    AuthFailed(&'static str),
+
     #[error("Invalid crypto state: {0}")]
     InvalidCryptoState(&'static str),
 
     #[error("Invalid config: {0}")]
     InvalidConfig(&'static str),
 
-    #[error("Socker error: {0}")]
+    #[error("Socket error: {0}")]
     Socket(&'static str),
 
-    #[error("Socker error: {0} ({1})")]
-    SocketIo(&'static str, #[source] io::Error),
+    #[error("Socket error: {message} ({source})")]
+    SocketIo {
+        message: &'static str,
+        #[source]
+        source: io::Error,
+    },
 
     #[error("Device error: {0}")]
     Device(&'static str),
 
-    #[error("Device error: {0} ({1})")]
-    DeviceIo(&'static str, #[source] io::Error),
+    #[error("Device error: {message} ({source})")]
+    DeviceIo {
+        message: &'static str,
+        #[source]
+        source: io::Error,
+    },
 
-    #[error("File error: {0}")]
-    FileIo(&'static str, #[source] io::Error),
+    #[error("File error: {message}")]
+    FileIo {
+        message: &'static str,
+        #[source]
+        source: io::Error,
+    },
 
     #[error("Message error: {0}")]
     Message(&'static str),
 
-    #[error("Beacon error: {0} ({1})")]
-    BeaconIo(&'static str, #[source] io::Error),
+    #[error("Beacon error: {message} ({source})")]
+    BeaconIo {
+        message: &'static str,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Beacon error: {0}")]
+    Beacon(String),
 
     #[error("Parse error: {0}")]
     Parse(&'static str),
 
+    #[error("CNI error: {0}")]
+    Cni(&'static str),
+
+    #[error("HTTP error: {0}")]
+    Http(String),
+
     #[error("Name can not be resolved: {0}")]
     NameUnresolvable(String),
 }
+
+impl Error {
+    /// Returns whether the error only affects a single message or attempt, so the operation can be retried
+    ///
+    /// Configuration errors and failed authentications are permanent, repeating the operation fails again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::CryptoInit(_)
+                | Error::Crypto(_)
+                | Error::Socket(_)
+                | Error::SocketIo { .. }
+                | Error::Message(_)
+                | Error::Beacon(_)
+                | Error::BeaconIo { .. }
+                | Error::Http(_)
+                | Error::NameUnresolvable(_)
+        )
+    }
+}
+
+#[test]
+fn display() {
+    let io_error = || io::Error::other("broken");
+    let cases = vec![
+        (Error::CryptoInit("init"), "Crypto initialization error: init"),
+        (Error::CryptoInitFatal("init"), "Fatal crypto initialization error: init"),
+        (Error::Crypto("decrypt"), "Crypto error: decrypt"),
+        (Error::AuthFailed("key"), "Authentication failed: key"),
+        (Error::InvalidCryptoState("state"), "Invalid crypto state: state"),
+        (Error::InvalidConfig("option"), "Invalid config: option"),
+        (Error::Socket("send"), "Socket error: send"),
+        (Error::SocketIo { message: "send", source: io_error() }, "Socket error: send (broken)"),
+        (Error::Device("read"), "Device error: read"),
+        (Error::DeviceIo { message: "read", source: io_error() }, "Device error: read (broken)"),
+        (Error::FileIo { message: "open", source: io_error() }, "File error: open"),
+        (Error::Message("type"), "Message error: type"),
+        (Error::BeaconIo { message: "read", source: io_error() }, "Beacon error: read (broken)"),
+        (Error::Beacon("invalid".to_string()), "Beacon error: invalid"),
+        (Error::Parse("number"), "Parse error: number"),
+        (Error::Cni("netns"), "CNI error: netns"),
+        (Error::Http("timeout".to_string()), "HTTP error: timeout"),
+        (Error::NameUnresolvable("example.com".to_string()), "Name can not be resolved: example.com"),
+    ];
+    for (error, text) in cases {
+        assert_eq!(error.to_string(), text);
+    }
+    let error = Error::FileIo { message: "open", source: io_error() };
+    assert_eq!(std::error::Error::source(&error).unwrap().to_string(), "broken");
+    assert!(Error::SocketIo { message: "send", source: io_error() }.is_transient());
+    assert!(!Error::InvalidConfig("option").is_transient());
+}
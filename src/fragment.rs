@@ -0,0 +1,248 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Fragmentation of payload that exceeds the MTU of a peer
+//
+// Frames larger than the MTU that the peer negotiated are split into `MESSAGE_TYPE_DATA_FRAGMENT` messages. Each
+// fragment carries the id of the frame, the offset of its data and the total length of the frame, so fragments can
+// arrive in any order. The receiver collects the fragments per peer and delivers the frame once all data arrived.
+//
+// Incomplete frames are discarded after a timeout. The number of incomplete frames per peer is limited, the oldest
+// one is discarded when a new one would exceed the limit, so floods of fragments can not exhaust the memory.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use byteorder::{ByteOrder, NetworkEndian};
+use smallvec::SmallVec;
+
+use crate::{cloud::Hash, error::Error, util::MsgBuffer};
+
+/// Length of the header of a fragment: id, offset and total length
+pub const FRAGMENT_HEADER_LEN: usize = 12;
+/// Maximum length of a fragmented frame, the size of a message buffer
+const MAX_TOTAL_LEN: usize = 65535;
+
+#[derive(Debug, PartialEq)]
+pub struct Fragment<'a> {
+    pub id: u32,
+    pub offset: u32,
+    pub total: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> Fragment<'a> {
+    pub fn read_from(data: &'a [u8]) -> Result<Self, Error> {
+        if data.len() < FRAGMENT_HEADER_LEN {
+            return Err(Error::Message("Fragment too short"))
+        }
+        let fragment = Fragment {
+            id: NetworkEndian::read_u32(&data[0..4]),
+            offset: NetworkEndian::read_u32(&data[4..8]),
+            total: NetworkEndian::read_u32(&data[8..12]),
+            data: &data[FRAGMENT_HEADER_LEN..],
+        };
+        if fragment.total as usize > MAX_TOTAL_LEN
+            || fragment.data.is_empty()
+            || fragment.offset as usize + fragment.data.len() > fragment.total as usize
+        {
+            return Err(Error::Message("Invalid fragment"))
+        }
+        Ok(fragment)
+    }
+
+    /// Replaces the contents of the buffer with the fragment
+    pub fn write_to(&self, buffer: &mut MsgBuffer) {
+        buffer.clear();
+        buffer.set_length(FRAGMENT_HEADER_LEN + self.data.len());
+        let msg = buffer.message_mut();
+        NetworkEndian::write_u32(&mut msg[0..4], self.id);
+        NetworkEndian::write_u32(&mut msg[4..8], self.offset);
+        NetworkEndian::write_u32(&mut msg[8..12], self.total);
+        msg[FRAGMENT_HEADER_LEN..].copy_from_slice(self.data);
+    }
+}
+
+/// Splits the frame into fragments with the given id that are at most `max_len` bytes long including the header
+pub fn split(id: u32, data: &[u8], max_len: usize) -> impl Iterator<Item = Fragment<'_>> {
+    let total = data.len() as u32;
+    let chunk_len = max_len.saturating_sub(FRAGMENT_HEADER_LEN).max(1);
+    data.chunks(chunk_len).enumerate().map(move |(i, chunk)| Fragment {
+        id,
+        offset: (i * chunk_len) as u32,
+        total,
+        data: chunk,
+    })
+}
+
+struct PartialFrame {
+    data: Vec<u8>,
+    /// Start and end of the fragments that have been received, to ignore duplicates and reject overlaps
+    ranges: SmallVec<[(usize, usize); 8]>,
+    received: usize,
+    started: Instant,
+}
+
+/// Frames of peers that have not been received completely
+pub struct FragmentBuffer {
+    next_id: u32,
+    frames: HashMap<SocketAddr, HashMap<u32, PartialFrame, Hash>, Hash>,
+    timeout: Duration,
+    max_buffers: usize,
+}
+
+impl FragmentBuffer {
+    pub fn new(timeout: Duration, max_buffers: usize) -> Self {
+        Self { next_id: 0, frames: HashMap::default(), timeout, max_buffers }
+    }
+
+    /// Returns the id for the next frame that is split into fragments
+    pub fn next_id(&mut self) -> u32 {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.next_id
+    }
+
+    /// Adds the fragment received from the peer, returns the frame once all of its fragments have been received
+    pub fn reassemble(&mut self, peer: SocketAddr, data: &[u8], now: Instant) -> Result<Option<Vec<u8>>, Error> {
+        let fragment = Fragment::read_from(data)?;
+        let timeout = self.timeout;
+        let frames = self.frames.entry(peer).or_default();
+        frames.retain(|_, frame| now.duration_since(frame.started) < timeout);
+        if !frames.contains_key(&fragment.id) && frames.len() >= self.max_buffers {
+            if let Some(oldest) = frames.iter().min_by_key(|(_, frame)| frame.started).map(|(id, _)| *id) {
+                debug!("Too many incomplete frames from {}, discarding frame {}", peer, oldest);
+                frames.remove(&oldest);
+            }
+        }
+        let frame = frames.entry(fragment.id).or_insert_with(|| PartialFrame {
+            data: vec![0; fragment.total as usize],
+            ranges: SmallVec::new(),
+            received: 0,
+            started: now,
+        });
+        if frame.data.len() != fragment.total as usize {
+            return Err(Error::Message("Fragment does not match the frame"))
+        }
+        let (start, end) = (fragment.offset as usize, fragment.offset as usize + fragment.data.len());
+        if frame.ranges.contains(&(start, end)) {
+            return Ok(None)
+        }
+        // Overlapping fragments would be counted twice and leave gaps in the frame
+        if frame.ranges.iter().any(|&(s, e)| start < e && s < end) {
+            return Err(Error::Message("Fragment overlaps other fragments"))
+        }
+        frame.data[start..end].copy_from_slice(fragment.data);
+        frame.ranges.push((start, end));
+        frame.received += fragment.data.len();
+        if frame.received < frame.data.len() {
+            return Ok(None)
+        }
+        Ok(frames.remove(&fragment.id).map(|frame| frame.data))
+    }
+
+    /// Discards the frames that have not been completed within the timeout
+    pub fn housekeep(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        for frames in self.frames.values_mut() {
+            frames.retain(|_, frame| now.duration_since(frame.started) < timeout);
+        }
+        self.frames.retain(|_, frames| !frames.is_empty());
+    }
+
+    /// Forgets the frames of all peers that do not match the predicate
+    pub fn retain<F: FnMut(&SocketAddr) -> bool>(&mut self, mut f: F) {
+        self.frames.retain(|addr, _| f(addr))
+    }
+}
+
+#[cfg(test)]
+fn fragment_messages(id: u32, data: &[u8], max_len: usize) -> Vec<Vec<u8>> {
+    let mut buffer = MsgBuffer::new(0);
+    split(id, data, max_len)
+        .map(|fragment| {
+            fragment.write_to(&mut buffer);
+            buffer.message().to_vec()
+        })
+        .collect()
+}
+
+#[test]
+fn split_frames() {
+    let data: Vec<u8> = (0..100).collect();
+    let fragments: Vec<_> = split(7, &data, 52).collect();
+    assert_eq!(fragments.len(), 3);
+    assert_eq!(fragments[0], Fragment { id: 7, offset: 0, total: 100, data: &data[0..40] });
+    assert_eq!(fragments[2], Fragment { id: 7, offset: 80, total: 100, data: &data[80..] });
+    let messages = fragment_messages(7, &data, 52);
+    assert!(messages.iter().all(|msg| msg.len() <= 52));
+    assert_eq!(Fragment::read_from(&messages[1]).unwrap(), fragments[1]);
+    // Fragments that do not fit into the frame are rejected
+    assert!(Fragment::read_from(&messages[1][..FRAGMENT_HEADER_LEN]).is_err());
+    let mut invalid = messages[2].clone();
+    invalid[11] = 99;
+    assert!(Fragment::read_from(&invalid).is_err());
+}
+
+#[test]
+fn reassemble_frames() {
+    let peer = "1.2.3.4:3210".parse().unwrap();
+    let now = Instant::now();
+    let mut buffer = FragmentBuffer::new(Duration::from_millis(500), 32);
+    let data: Vec<u8> = (0..100).collect();
+    let messages = fragment_messages(1, &data, 52);
+    // Fragments arrive in any order, duplicates are ignored
+    assert_eq!(buffer.reassemble(peer, &messages[2], now).unwrap(), None);
+    assert_eq!(buffer.reassemble(peer, &messages[0], now).unwrap(), None);
+    assert_eq!(buffer.reassemble(peer, &messages[0], now).unwrap(), None);
+    assert_eq!(buffer.reassemble(peer, &messages[1], now).unwrap(), Some(data.clone()));
+    // Incomplete frames time out
+    assert_eq!(buffer.reassemble(peer, &fragment_messages(2, &data, 52)[0], now).unwrap(), None);
+    let later = now + Duration::from_millis(600);
+    let messages = fragment_messages(2, &data, 52);
+    assert_eq!(buffer.reassemble(peer, &messages[1], later).unwrap(), None);
+    assert_eq!(buffer.reassemble(peer, &messages[2], later).unwrap(), None);
+    buffer.housekeep(later + Duration::from_millis(600));
+    assert!(buffer.frames.is_empty());
+}
+
+#[test]
+fn reject_overlapping_fragments() {
+    let peer = "1.2.3.4:3210".parse().unwrap();
+    let now = Instant::now();
+    let mut buffer = FragmentBuffer::new(Duration::from_millis(500), 32);
+    let data: Vec<u8> = (0..100).collect();
+    let messages = fragment_messages(1, &data, 62);
+    let mut overlapping = MsgBuffer::new(0);
+    Fragment { id: 1, offset: 25, total: 100, data: &data[25..75] }.write_to(&mut overlapping);
+    assert_eq!(buffer.reassemble(peer, &messages[0], now).unwrap(), None);
+    // Together with the first fragment, this would add up to the total length with a gap at the end
+    assert!(buffer.reassemble(peer, overlapping.message(), now).is_err());
+    assert_eq!(buffer.reassemble(peer, &messages[1], now).unwrap(), Some(data));
+}
+
+#[test]
+fn limit_incomplete_frames() {
+    let peer1 = "1.2.3.4:3210".parse().unwrap();
+    let peer2 = "1.2.3.5:3210".parse().unwrap();
+    let now = Instant::now();
+    let mut buffer = FragmentBuffer::new(Duration::from_millis(500), 2);
+    let data: Vec<u8> = (0..100).collect();
+    let messages1 = fragment_messages(1, &data, 62);
+    assert_eq!(buffer.reassemble(peer1, &messages1[0], now).unwrap(), None);
+    for id in 2..4 {
+        let later = now + Duration::from_millis(u64::from(id));
+        assert_eq!(buffer.reassemble(peer1, &fragment_messages(id, &data, 62)[0], later).unwrap(), None);
+    }
+    // The oldest frame has been discarded
+    assert_eq!(buffer.frames[&peer1].len(), 2);
+    assert_eq!(buffer.reassemble(peer1, &messages1[1], now).unwrap(), None);
+    // The limit applies per peer
+    assert_eq!(buffer.reassemble(peer2, &messages1[0], now).unwrap(), None);
+    assert_eq!(buffer.reassemble(peer2, &messages1[1], now).unwrap(), Some(data));
+    buffer.retain(|addr| *addr != peer1);
+    assert!(!buffer.frames.contains_key(&peer1));
+}
@@ -0,0 +1,117 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Incremental synchronisation of peer lists
+//
+// Every node logs the peers that it added and removed, numbered with increasing versions. For each peer the node
+// remembers the version of the log at the last peer list or update that it sent, so the next update only has to
+// contain the changes since then. When these changes are no longer in the log, a full peer list is sent instead.
+
+use std::collections::VecDeque;
+
+use smallvec::smallvec;
+
+use crate::{
+    messages::{AddrList, PeerInfo, PeerUpdate},
+    types::NodeId,
+};
+
+/// Number of changes kept in the log, peers that missed more changes get a full peer list
+pub const MAX_CHANGES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Added,
+    Removed,
+}
+
+struct LogEntry {
+    version: u64,
+    change: Change,
+    node_id: NodeId,
+    addrs: AddrList,
+}
+
+#[derive(Default)]
+pub struct ChangeLog {
+    version: u64,
+    changes: VecDeque<LogEntry>,
+}
+
+impl ChangeLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The version of the latest change
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn record(&mut self, change: Change, node_id: NodeId, addrs: AddrList) {
+        self.version += 1;
+        if self.changes.len() >= MAX_CHANGES {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(LogEntry { version: self.version, change, node_id, addrs });
+    }
+
+    /// Returns the changes after the given version or `None` if they are no longer all in the log
+    ///
+    /// Only the last change of each node is included, so a node that has been added and removed again is only
+    /// contained in the removed peers.
+    pub fn since(&self, version: u64) -> Option<PeerUpdate> {
+        if self.version.saturating_sub(version) > self.changes.len() as u64 {
+            return None
+        }
+        let mut update = PeerUpdate { added: smallvec![], removed: smallvec![] };
+        for entry in self.changes.iter().filter(|entry| entry.version > version) {
+            update.added.retain(|peer| peer.node_id != Some(entry.node_id));
+            update.removed.retain(|peer| peer.node_id != Some(entry.node_id));
+            let peer = PeerInfo { node_id: Some(entry.node_id), addrs: entry.addrs.clone() };
+            match entry.change {
+                Change::Added => update.added.push(peer),
+                Change::Removed => update.removed.push(peer),
+            }
+        }
+        Some(update)
+    }
+}
+
+#[cfg(test)]
+fn node_id(n: u8) -> NodeId {
+    [n; 16]
+}
+
+#[test]
+fn changes_since_version() {
+    let mut log = ChangeLog::new();
+    assert_eq!(log.since(0).unwrap(), PeerUpdate { added: smallvec![], removed: smallvec![] });
+    let addr1 = "1.2.3.4:3210".parse().unwrap();
+    let addr2 = "1.2.3.5:3210".parse().unwrap();
+    log.record(Change::Added, node_id(1), smallvec![addr1]);
+    log.record(Change::Added, node_id(2), smallvec![addr2]);
+    log.record(Change::Removed, node_id(1), smallvec![addr1]);
+    assert_eq!(log.version(), 3);
+    let update = log.since(0).unwrap();
+    assert_eq!(&update.added[..], &[PeerInfo { node_id: Some(node_id(2)), addrs: smallvec![addr2] }]);
+    assert_eq!(&update.removed[..], &[PeerInfo { node_id: Some(node_id(1)), addrs: smallvec![addr1] }]);
+    let update = log.since(2).unwrap();
+    assert!(update.added.is_empty());
+    assert_eq!(update.removed.len(), 1);
+    assert_eq!(log.since(3).unwrap(), PeerUpdate { added: smallvec![], removed: smallvec![] });
+}
+
+#[test]
+fn forget_old_changes() {
+    let mut log = ChangeLog::new();
+    let addr = "1.2.3.4:3210".parse().unwrap();
+    for n in 0..MAX_CHANGES + 10 {
+        log.record(Change::Added, node_id(n as u8), smallvec![addr]);
+    }
+    assert!(log.since(0).is_none());
+    assert!(log.since(9).is_none());
+    assert_eq!(log.since(10).unwrap().added.len(), MAX_CHANGES);
+    assert_eq!(log.since(log.version() - 1).unwrap().added.len(), 1);
+}
@@ -0,0 +1,244 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// gRPC management API
+//
+// The server offers the commands of the HTTP management API and hands them to the main loop via the same channel.
+// The event stream is fed by the same broadcast channel as the WebSocket clients of the HTTP API.
+
+use std::{net::TcpListener as StdTcpListener, pin::Pin, sync::Arc, thread};
+
+use crossbeam_channel::Sender;
+use ring::constant_time::verify_slices_are_equal;
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc},
+};
+use tokio_stream::{
+    wrappers::{ReceiverStream, TcpListenerStream},
+    Stream,
+};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{
+    api::{execute, stream_action, ApiCommand, ApiError, ApiRequest, StreamAction},
+    error::Error,
+};
+
+pub mod proto {
+    tonic::include_proto!("vpncloud.v1");
+}
+
+use proto::{
+    management_server::{Management, ManagementServer},
+    ConnectRequest, ConnectResponse, DisconnectRequest, DisconnectResponse, Event, GetPeersRequest, GetPeersResponse,
+    GetStatsRequest, GetStatsResponse, GetTableRequest, GetTableResponse, WatchEventsRequest,
+};
+
+/// Number of events that are buffered for each client in addition to the broadcast channel
+const STREAM_BUFFER: usize = 16;
+
+impl From<ApiError> for Status {
+    fn from(err: ApiError) -> Self {
+        match err.status {
+            400 => Status::invalid_argument(err.error),
+            404 => Status::not_found(err.error),
+            409 => Status::already_exists(err.error),
+            501 => Status::unimplemented(err.error),
+            503 => Status::unavailable(err.error),
+            _ => Status::internal(err.error),
+        }
+    }
+}
+
+struct ManagementService {
+    requests: Sender<ApiRequest>,
+    events: broadcast::Sender<Arc<str>>,
+}
+
+impl ManagementService {
+    /// Executes the command and converts the JSON result into the message
+    async fn call<T: serde::de::DeserializeOwned>(&self, command: ApiCommand) -> Result<T, Status> {
+        let value = execute(&self.requests, command).await?;
+        serde_json::from_value(value).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+#[tonic::async_trait]
+impl Management for ManagementService {
+    type WatchEventsStream = Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send>>;
+
+    async fn get_peers(&self, _request: Request<GetPeersRequest>) -> Result<Response<GetPeersResponse>, Status> {
+        Ok(Response::new(GetPeersResponse { peers: self.call(ApiCommand::Peers).await? }))
+    }
+
+    async fn get_stats(&self, _request: Request<GetStatsRequest>) -> Result<Response<GetStatsResponse>, Status> {
+        let stats = execute(&self.requests, ApiCommand::Stats).await?;
+        Ok(Response::new(GetStatsResponse { json: stats.to_string() }))
+    }
+
+    async fn connect(&self, request: Request<ConnectRequest>) -> Result<Response<ConnectResponse>, Status> {
+        let addr = self.call(ApiCommand::Connect(request.into_inner().addr)).await?;
+        Ok(Response::new(ConnectResponse { addr }))
+    }
+
+    async fn disconnect(&self, request: Request<DisconnectRequest>) -> Result<Response<DisconnectResponse>, Status> {
+        let addr = self.call(ApiCommand::Disconnect(request.into_inner().addr)).await?;
+        Ok(Response::new(DisconnectResponse { addr }))
+    }
+
+    async fn get_table(&self, _request: Request<GetTableRequest>) -> Result<Response<GetTableResponse>, Status> {
+        Ok(Response::new(self.call(ApiCommand::Table).await?))
+    }
+
+    async fn watch_events(
+        &self, _request: Request<WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let mut events = self.events.subscribe();
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let event = events.recv().await.map_err(Into::into);
+                let event = match stream_action(event, events.len()) {
+                    StreamAction::Send(event) => Ok(Event { json: event.to_string() }),
+                    StreamAction::Disconnect => {
+                        debug!("Disconnecting slow gRPC event stream client");
+                        Err(Status::resource_exhausted("Too many pending events"))
+                    }
+                    StreamAction::Stop => return,
+                };
+                let lagged = event.is_err();
+                if sender.send(event).await.is_err() || lagged {
+                    return
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+fn authenticate(token: &Option<String>, request: &Request<()>) -> Result<(), Status> {
+    if let Some(ref token) = token {
+        let authorized = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|value| verify_slices_are_equal(value.as_bytes(), token.as_bytes()).is_ok())
+            .unwrap_or(false);
+        if !authorized {
+            return Err(Status::unauthenticated("Invalid token"))
+        }
+    }
+    Ok(())
+}
+
+/// Serves the gRPC API in its own thread
+pub fn start_grpc(
+    addr: &str, token: Option<String>, requests: Sender<ApiRequest>, events: broadcast::Sender<Arc<str>>,
+) -> Result<(), Error> {
+    let listener =
+        StdTcpListener::bind(addr).map_err(|e| Error::SocketIo { message: "Failed to bind gRPC socket", source: e })?;
+    listener.set_nonblocking(true).map_err(|e| Error::SocketIo { message: "Failed to bind gRPC socket", source: e })?;
+    if token.is_none() {
+        warn!("The gRPC API on {} does not require authentication", addr);
+    }
+    let service = ManagementServer::with_interceptor(ManagementService { requests, events }, move |request| {
+        authenticate(&token, &request)?;
+        Ok(request)
+    });
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| Error::SocketIo { message: "Failed to start gRPC runtime", source: e })?;
+    thread::Builder::new()
+        .name("grpc".to_string())
+        .spawn(move || {
+            runtime.block_on(async move {
+                let listener = match TcpListener::from_std(listener) {
+                    Ok(listener) => listener,
+                    Err(err) => return error!("Failed to start gRPC server: {}", err),
+                };
+                if let Err(err) =
+                    Server::builder().add_service(service).serve_with_incoming(TcpListenerStream::new(listener)).await
+                {
+                    error!("gRPC server failed: {}", err)
+                }
+            })
+        })
+        .map_err(|e| Error::SocketIo { message: "Failed to start gRPC thread", source: e })?;
+    info!("Serving gRPC API on {}", addr);
+    Ok(())
+}
+
+#[test]
+fn grpc_requests() {
+    use crate::api::{ApiEvent, ApiServer};
+    use proto::management_client::ManagementClient;
+    use std::time::Duration;
+    use tonic::metadata::MetadataValue;
+
+    let port = StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let addr = format!("127.0.0.1:{}", port);
+    let config = crate::config::Config {
+        grpc_addr: Some(addr.clone()),
+        api_token: Some("secret".to_string()),
+        ..Default::default()
+    };
+    let server = ApiServer::start(&config).unwrap();
+    let client = thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let channel =
+                tonic::transport::Endpoint::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+            let mut unauthorized = ManagementClient::new(channel.clone());
+            let status = unauthorized.get_peers(GetPeersRequest {}).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            let token: MetadataValue<_> = "Bearer secret".parse().unwrap();
+            let mut client = ManagementClient::with_interceptor(channel, move |mut request: Request<()>| {
+                request.metadata_mut().insert("authorization", token.clone());
+                Ok(request)
+            });
+            let peers = client.get_peers(GetPeersRequest {}).await.unwrap().into_inner().peers;
+            assert_eq!(peers.len(), 1);
+            assert_eq!(peers[0].addr, "1.2.3.4:3210");
+            assert_eq!(peers[0].path_mtu, None);
+            assert_eq!(peers[0].tags.get("dc").map(String::as_str), Some("eu"));
+            let status = client.disconnect(DisconnectRequest { addr: "1.2.3.5:3210".to_string() }).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::NotFound);
+            let mut events = client.watch_events(WatchEventsRequest {}).await.unwrap().into_inner();
+            let event = events.message().await.unwrap().unwrap();
+            assert_eq!(event.json, r#"{"event":"KeyRotation","peer":"1.2.3.4:3210"}"#);
+        })
+    });
+    // Act as the main loop until the client is done
+    while !client.is_finished() {
+        if let Some(request) = server.try_recv() {
+            let result = match request.command {
+                ApiCommand::Peers => Ok(serde_json::json!([{
+                    "addr": "1.2.3.4:3210",
+                    "ttl_secs": 300,
+                    "crypto": "aes256",
+                    "encrypted": true,
+                    "path_mtu": null,
+                    "mtu": 1400,
+                    "path": "direct",
+                    "tags": {"dc": "eu"},
+                    "lost_packets": 0,
+                    "lost_packets_total": 2,
+                    "loss_percent": 0.0
+                }])),
+                ApiCommand::Disconnect(ref addr) => {
+                    assert_eq!(addr, "1.2.3.5:3210");
+                    Err(ApiError::new(404, "Not a peer"))
+                }
+                ref command => panic!("Unexpected command {:?}", command),
+            };
+            request.reply(result)
+        }
+        server.publish(|| ApiEvent::KeyRotation { peer: "1.2.3.4:3210".to_string() });
+        thread::sleep(Duration::from_millis(10));
+    }
+    client.join().unwrap();
+}
@@ -0,0 +1,142 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+};
+
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::{self, Ed25519KeyPair, KeyPair},
+};
+
+use super::{
+    error::Error,
+    types::NodeId,
+    util::{from_base62, to_base62, Time},
+};
+
+pub type IdentityKey = [u8; 32];
+
+/// Maximal difference between the timestamp of an identity proof and the local clock
+pub const MAX_CLOCK_SKEW: Time = 3600;
+
+const IDENTITY_CONTEXT: &[u8] = b"vpncloud-identity";
+
+fn signed_data(node_id: &NodeId, session_key: &[u8], timestamp: Time) -> Vec<u8> {
+    let mut data = Vec::with_capacity(IDENTITY_CONTEXT.len() + node_id.len() + session_key.len() + 8);
+    data.extend_from_slice(IDENTITY_CONTEXT);
+    data.extend_from_slice(node_id);
+    data.extend_from_slice(session_key);
+    data.extend_from_slice(&timestamp.to_be_bytes());
+    data
+}
+
+/// Signature of the identity key over the node id and the key exchange of a session
+///
+/// The session key is the ephemeral key exchange key of the handshake that carries the proof. As only the node that
+/// created it can complete that handshake, a recorded proof can not be replayed in another one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdentityProof {
+    pub public_key: IdentityKey,
+    pub timestamp: Time,
+    pub signature: [u8; 64],
+}
+
+impl IdentityProof {
+    pub fn verify(&self, node_id: &NodeId, session_key: &[u8], now: Time) -> Result<(), Error> {
+        if self.timestamp.abs_diff(now) > MAX_CLOCK_SKEW as u64 {
+            return Err(Error::AuthFailed("Identity proof is outdated"))
+        }
+        signature::UnparsedPublicKey::new(&signature::ED25519, &self.public_key)
+            .verify(&signed_data(node_id, session_key, self.timestamp), &self.signature)
+            .map_err(|_| Error::AuthFailed("Invalid identity signature"))
+    }
+}
+
+/// Long-lived Ed25519 key that identifies a node across restarts
+///
+/// The key file contains the base62 encoded private key, like `vpncloud genkey` prints it.
+pub struct Identity {
+    keypair: Ed25519KeyPair,
+}
+
+/// Loads a 32 byte secret from the key file, generating a random one if the file does not exist
+///
+/// The file contains the base62 encoded secret. Key files that are accessible by other users than the owner are
+/// rejected.
+pub fn load_or_create_secret(path: &str) -> Result<[u8; 32], Error> {
+    if !Path::new(path).exists() {
+        info!("Generating key file {}", path);
+        let mut secret = [0; 32];
+        SystemRandom::new().fill(&mut secret).map_err(|_| Error::InvalidConfig("Failed to generate key"))?;
+        write_key(path, &to_base62(&secret))
+            .map_err(|e| Error::FileIo { message: "Failed to write key file", source: e })?;
+    }
+    let meta = fs::metadata(path).map_err(|e| Error::FileIo { message: "Failed to read key file", source: e })?;
+    if meta.permissions().mode() & 0o077 != 0 {
+        return Err(Error::InvalidConfig("Key file must only be accessible by its owner (mode 0600)"))
+    }
+    let data =
+        fs::read_to_string(path).map_err(|e| Error::FileIo { message: "Failed to read key file", source: e })?;
+    let data = from_base62(data.trim()).map_err(|_| Error::InvalidConfig("Failed to parse key file"))?;
+    if data.len() > 32 {
+        return Err(Error::InvalidConfig("Failed to parse key file"))
+    }
+    // Leading zeros are not encoded in base62
+    let mut secret = [0; 32];
+    secret[32 - data.len()..].clone_from_slice(&data);
+    Ok(secret)
+}
+
+fn write_key(path: &str, key: &str) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    writeln!(file, "{}", key)?;
+    file.sync_all()
+}
+
+impl Identity {
+    /// Loads the key from the file, generating it if the file does not exist
+    ///
+    /// Key files that are accessible by other users than the owner are rejected.
+    pub fn load_or_create(path: &str) -> Result<Self, Error> {
+        let seed = load_or_create_secret(path)?;
+        let keypair = Ed25519KeyPair::from_seed_unchecked(&seed)
+            .map_err(|_| Error::InvalidConfig("Failed to parse identity key"))?;
+        Ok(Self { keypair })
+    }
+
+    pub fn public_key(&self) -> IdentityKey {
+        let mut key = [0; 32];
+        key.clone_from_slice(self.keypair.public_key().as_ref());
+        key
+    }
+
+    pub fn prove(&self, node_id: &NodeId, session_key: &[u8], timestamp: Time) -> IdentityProof {
+        let mut signature = [0; 64];
+        signature.clone_from_slice(self.keypair.sign(&signed_data(node_id, session_key, timestamp)).as_ref());
+        IdentityProof { public_key: self.public_key(), timestamp, signature }
+    }
+}
+
+#[test]
+fn identity_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.key");
+    let path = path.to_str().unwrap();
+    let identity = Identity::load_or_create(path).unwrap();
+    assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(Identity::load_or_create(path).unwrap().public_key(), identity.public_key());
+    let proof = identity.prove(&[1; 16], &[3; 32], 1000);
+    proof.verify(&[1; 16], &[3; 32], 1000 + MAX_CLOCK_SKEW).unwrap();
+    proof.verify(&[1; 16], &[3; 32], 1000 - MAX_CLOCK_SKEW).unwrap();
+    assert!(proof.verify(&[2; 16], &[3; 32], 1000).is_err());
+    assert!(proof.verify(&[1; 16], &[4; 32], 1000).is_err());
+    assert!(proof.verify(&[1; 16], &[3; 32], 1001 + MAX_CLOCK_SKEW).is_err());
+    fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(Identity::load_or_create(path).is_err());
+}
@@ -0,0 +1,298 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Multicast group snooping for Ethernet frames
+//
+// Without snooping, multicast frames are flooded to all peers like broadcast frames. To avoid this, IGMP (IPv4) and
+// MLD (IPv6) membership reports that are received from peers are inspected and the joined groups are recorded for each
+// peer. Frames to a group that peers have joined are only sent to those peers.
+//
+// Frames to groups that no peer has joined are still flooded, so that nothing is lost if reports are missed. Also
+// link-local groups (e.g. the IGMP queries, ARP-like IPv6 neighbor discovery) are never restricted and membership
+// reports are flooded, so that all nodes can learn from them.
+
+use std::{collections::HashMap, marker::PhantomData, net::SocketAddr};
+
+use smallvec::SmallVec;
+
+use crate::{
+    cloud::Hash,
+    types::Address,
+    util::{Duration, Time, TimeSource},
+};
+
+const ETHERTYPE_VLAN: [u8; 2] = [0x81, 0x00];
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+const ETHERTYPE_IPV6: [u8; 2] = [0x86, 0xdd];
+
+const IP_PROTO_IGMP: u8 = 2;
+const IP_PROTO_ICMPV6: u8 = 58;
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_DEST_OPTS: u8 = 60;
+
+const IGMP_V1_REPORT: u8 = 0x12;
+const IGMP_V2_REPORT: u8 = 0x16;
+const IGMP_V2_LEAVE: u8 = 0x17;
+const IGMP_V3_REPORT: u8 = 0x22;
+const MLD_V1_REPORT: u8 = 131;
+const MLD_V1_DONE: u8 = 132;
+const MLD_V2_REPORT: u8 = 143;
+
+const RECORD_MODE_IS_INCLUDE: u8 = 1;
+const RECORD_MODE_IS_EXCLUDE: u8 = 2;
+const RECORD_CHANGE_TO_INCLUDE: u8 = 3;
+const RECORD_CHANGE_TO_EXCLUDE: u8 = 4;
+const RECORD_ALLOW_NEW_SOURCES: u8 = 5;
+
+#[derive(Debug, PartialEq)]
+enum Membership {
+    Join(Address),
+    Leave(Address),
+}
+
+type Memberships = SmallVec<[Membership; 4]>;
+
+/// Builds the address of a multicast MAC, prefixed with the VLAN tag like `Frame::parse` does
+fn group_address(vlan: Option<[u8; 2]>, mac: [u8; 6]) -> Address {
+    let mut data = [0; 16];
+    match vlan {
+        Some(vlan) => {
+            data[..2].copy_from_slice(&vlan);
+            data[2..8].copy_from_slice(&mac);
+            Address { data, len: 8 }
+        }
+        None => {
+            data[..6].copy_from_slice(&mac);
+            Address { data, len: 6 }
+        }
+    }
+}
+
+fn ipv4_group(vlan: Option<[u8; 2]>, group: &[u8]) -> Option<Address> {
+    if group[0] & 0xf0 != 0xe0 || group[..3] == [224, 0, 0] {
+        // Not a multicast group or a link-local group
+        return None
+    }
+    Some(group_address(vlan, [0x01, 0x00, 0x5e, group[1] & 0x7f, group[2], group[3]]))
+}
+
+fn ipv6_group(vlan: Option<[u8; 2]>, group: &[u8]) -> Option<Address> {
+    if group[0] != 0xff || group[1] & 0x0f <= 2 {
+        // Not a multicast group or a group with link-local scope
+        return None
+    }
+    Some(group_address(vlan, [0x33, 0x33, group[12], group[13], group[14], group[15]]))
+}
+
+/// Parses the group records of IGMPv3 and MLDv2 reports
+fn parse_records(
+    data: &[u8], addr_len: usize, group: impl Fn(&[u8]) -> Option<Address>, memberships: &mut Memberships,
+) {
+    if data.len() < 8 {
+        return
+    }
+    let count = u16::from_be_bytes([data[6], data[7]]) as usize;
+    let mut pos = 8;
+    for _ in 0..count {
+        if data.len() < pos + 4 + addr_len {
+            return
+        }
+        let type_ = data[pos];
+        let aux_len = data[pos + 1] as usize * 4;
+        let sources = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if let Some(group) = group(&data[pos + 4..pos + 4 + addr_len]) {
+            match type_ {
+                RECORD_MODE_IS_EXCLUDE | RECORD_CHANGE_TO_EXCLUDE => memberships.push(Membership::Join(group)),
+                RECORD_MODE_IS_INCLUDE | RECORD_CHANGE_TO_INCLUDE | RECORD_ALLOW_NEW_SOURCES if sources > 0 => {
+                    memberships.push(Membership::Join(group))
+                }
+                // Including no sources means leaving the group
+                RECORD_MODE_IS_INCLUDE | RECORD_CHANGE_TO_INCLUDE => memberships.push(Membership::Leave(group)),
+                _ => (),
+            }
+        }
+        pos += 4 + addr_len + sources * addr_len + aux_len;
+    }
+}
+
+fn parse_igmp(vlan: Option<[u8; 2]>, packet: &[u8], memberships: &mut Memberships) {
+    if packet.len() < 20 || packet[0] >> 4 != 4 || packet[9] != IP_PROTO_IGMP {
+        return
+    }
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    let igmp = match packet.get(header_len..) {
+        Some(igmp) if igmp.len() >= 8 => igmp,
+        _ => return,
+    };
+    match igmp[0] {
+        IGMP_V1_REPORT | IGMP_V2_REPORT => {
+            if let Some(group) = ipv4_group(vlan, &igmp[4..8]) {
+                memberships.push(Membership::Join(group))
+            }
+        }
+        IGMP_V2_LEAVE => {
+            if let Some(group) = ipv4_group(vlan, &igmp[4..8]) {
+                memberships.push(Membership::Leave(group))
+            }
+        }
+        IGMP_V3_REPORT => parse_records(igmp, 4, |g| ipv4_group(vlan, g), memberships),
+        _ => (),
+    }
+}
+
+fn parse_mld(vlan: Option<[u8; 2]>, packet: &[u8], memberships: &mut Memberships) {
+    if packet.len() < 40 || packet[0] >> 4 != 6 {
+        return
+    }
+    let mut next_header = packet[6];
+    let mut pos = 40;
+    // MLD messages always carry a hop-by-hop header with the router alert option
+    while next_header == IPV6_HOP_BY_HOP || next_header == IPV6_DEST_OPTS {
+        if packet.len() < pos + 2 {
+            return
+        }
+        next_header = packet[pos];
+        pos += (packet[pos + 1] as usize + 1) * 8;
+    }
+    if next_header != IP_PROTO_ICMPV6 {
+        return
+    }
+    let icmp = match packet.get(pos..) {
+        Some(icmp) if icmp.len() >= 8 => icmp,
+        _ => return,
+    };
+    match icmp[0] {
+        MLD_V1_REPORT | MLD_V1_DONE if icmp.len() >= 24 => {
+            if let Some(group) = ipv6_group(vlan, &icmp[8..24]) {
+                memberships.push(if icmp[0] == MLD_V1_REPORT {
+                    Membership::Join(group)
+                } else {
+                    Membership::Leave(group)
+                })
+            }
+        }
+        MLD_V2_REPORT => parse_records(icmp, 16, |g| ipv6_group(vlan, g), memberships),
+        _ => (),
+    }
+}
+
+/// Extracts the group memberships from IGMP and MLD reports in an Ethernet frame
+fn parse_memberships(frame: &[u8]) -> Memberships {
+    let mut memberships = SmallVec::new();
+    if frame.len() < 14 || frame[0] & 0x01 == 0 {
+        // Reports are always sent to multicast addresses
+        return memberships
+    }
+    let (vlan, ethertype, packet) = if frame[12..14] == ETHERTYPE_VLAN {
+        if frame.len() < 18 {
+            return memberships
+        }
+        let vlan = [frame[14] & 0x0f, frame[15]];
+        let vlan = if vlan == [0, 0] { None } else { Some(vlan) };
+        (vlan, [frame[16], frame[17]], &frame[18..])
+    } else {
+        (None, [frame[12], frame[13]], &frame[14..])
+    };
+    match ethertype {
+        ETHERTYPE_IPV4 => parse_igmp(vlan, packet, &mut memberships),
+        ETHERTYPE_IPV6 => parse_mld(vlan, packet, &mut memberships),
+        _ => (),
+    }
+    memberships
+}
+
+/// Multicast groups that peers have joined
+pub struct GroupTable<TS: TimeSource> {
+    groups: HashMap<Address, HashMap<SocketAddr, Time, Hash>, Hash>,
+    timeout: Duration,
+    _dummy: PhantomData<TS>,
+}
+
+impl<TS: TimeSource> GroupTable<TS> {
+    pub fn new(timeout: Duration) -> Self {
+        Self { groups: HashMap::default(), timeout, _dummy: PhantomData }
+    }
+
+    /// Learns the group memberships of the peer from a frame received from it
+    pub fn snoop(&mut self, peer: SocketAddr, frame: &[u8]) {
+        for membership in parse_memberships(frame) {
+            match membership {
+                Membership::Join(group) => {
+                    debug!("Peer {} joined multicast group {}", peer, group);
+                    let timeout = TS::now() + Time::from(self.timeout);
+                    self.groups.entry(group).or_default().insert(peer, timeout);
+                }
+                Membership::Leave(group) => {
+                    debug!("Peer {} left multicast group {}", peer, group);
+                    if let Some(peers) = self.groups.get_mut(&group) {
+                        peers.remove(&peer);
+                        if peers.is_empty() {
+                            self.groups.remove(&group);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the peers that joined the group the frame is sent to or `None` if the frame should be flooded
+    pub fn lookup(&self, dst: &Address, frame: &[u8]) -> Option<SmallVec<[SocketAddr; 4]>> {
+        if self.groups.is_empty() {
+            return None
+        }
+        let peers = self.groups.get(dst)?;
+        if !parse_memberships(frame).is_empty() {
+            // Reports are flooded so that all nodes can learn from them
+            return None
+        }
+        Some(peers.keys().copied().collect())
+    }
+
+    pub fn housekeep(&mut self) {
+        let now = TS::now();
+        for peers in self.groups.values_mut() {
+            peers.retain(|_, timeout| *timeout >= now);
+        }
+        self.groups.retain(|_, peers| !peers.is_empty());
+    }
+
+    /// Forgets the memberships of all peers that do not match the predicate
+    pub fn retain<F: FnMut(&SocketAddr) -> bool>(&mut self, mut f: F) {
+        for peers in self.groups.values_mut() {
+            peers.retain(|peer, _| f(peer));
+        }
+        self.groups.retain(|_, peers| !peers.is_empty());
+    }
+}
+
+#[test]
+fn parse_igmp_reports() {
+    use std::str::FromStr;
+    let group = Address::from_str("01:00:5e:01:02:03").unwrap();
+    // IGMPv2 report for 239.1.2.3
+    let mut frame = vec![1, 0, 0x5e, 1, 2, 3, 2, 2, 2, 2, 2, 2, 0x08, 0x00];
+    frame.extend_from_slice(&[0x46, 0, 0, 32, 0, 0, 0, 0, 1, 2, 0, 0, 10, 0, 0, 1, 239, 1, 2, 3, 0x94, 4, 0, 0]);
+    frame.extend_from_slice(&[0x16, 0, 0, 0, 239, 1, 2, 3]);
+    assert_eq!(parse_memberships(&frame).as_slice(), &[Membership::Join(group)]);
+    // IGMPv3 report leaving 239.1.2.3 and joining the link-local 224.0.0.251
+    let mut frame = vec![1, 0, 0x5e, 0, 0, 0x16, 2, 2, 2, 2, 2, 2, 0x08, 0x00];
+    frame.extend_from_slice(&[0x46, 0, 0, 48, 0, 0, 0, 0, 1, 2, 0, 0, 10, 0, 0, 1, 224, 0, 0, 22, 0x94, 4, 0, 0]);
+    frame.extend_from_slice(&[0x22, 0, 0, 0, 0, 0, 0, 2]);
+    frame.extend_from_slice(&[RECORD_CHANGE_TO_INCLUDE, 0, 0, 0, 239, 1, 2, 3]);
+    frame.extend_from_slice(&[RECORD_CHANGE_TO_EXCLUDE, 0, 0, 0, 224, 0, 0, 251]);
+    assert_eq!(parse_memberships(&frame).as_slice(), &[Membership::Leave(group)]);
+    // MLDv1 report for ff05::1:3
+    let mut frame = vec![0x33, 0x33, 0, 1, 0, 3, 2, 2, 2, 2, 2, 2, 0x86, 0xdd];
+    frame.extend_from_slice(&[0x60, 0, 0, 0, 0, 32, IPV6_HOP_BY_HOP, 1]);
+    frame.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    frame.extend_from_slice(&[0xff, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 3]);
+    frame.extend_from_slice(&[IP_PROTO_ICMPV6, 0, 5, 2, 0, 0, 1, 0]);
+    frame.extend_from_slice(&[MLD_V1_REPORT, 0, 0, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(&[0xff, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 3]);
+    let group = Address::from_str("33:33:00:01:00:03").unwrap();
+    assert_eq!(parse_memberships(&frame).as_slice(), &[Membership::Join(group)]);
+    // Unicast frames are ignored
+    frame[0] = 0x32;
+    assert!(parse_memberships(&frame).is_empty());
+}
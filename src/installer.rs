@@ -22,42 +22,46 @@ fn systemctl_daemon_reload() {
 pub fn install() -> Result<(), Error> {
     env::current_exe()
         .and_then(|p| fs::copy(p, "/usr/bin/vpncloud"))
-        .map_err(|e| Error::FileIo("Failed to copy binary", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to copy binary", source: e })?;
     fs::set_permissions("/usr/bin/vpncloud", fs::Permissions::from_mode(0o755))
-        .map_err(|e| Error::FileIo("Failed to set permissions for binary", e))?;
-    fs::create_dir_all("/etc/vpncloud").map_err(|e| Error::FileIo("Failed to create config folder", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to set permissions for binary", source: e })?;
+    fs::create_dir_all("/etc/vpncloud")
+        .map_err(|e| Error::FileIo { message: "Failed to create config folder", source: e })?;
     fs::set_permissions("/etc/vpncloud", fs::Permissions::from_mode(0o700))
-        .map_err(|e| Error::FileIo("Failed to set permissions for config folder", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to set permissions for config folder", source: e })?;
     File::create("/etc/vpncloud/example.net.disabled")
         .and_then(|mut f| f.write_all(EXAMPLE_CONFIG))
-        .map_err(|e| Error::FileIo("Failed to create example config", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to create example config", source: e })?;
     File::create("/usr/share/man/man1/vpncloud.1.gz")
         .and_then(|mut f| f.write_all(MANPAGE))
-        .map_err(|e| Error::FileIo("Failed to create manpage", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to create manpage", source: e })?;
     File::create("/lib/systemd/system/vpncloud@.service")
         .and_then(|mut f| f.write_all(SERVICE_FILE))
-        .map_err(|e| Error::FileIo("Failed to create service file", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to create service file", source: e })?;
     File::create("/lib/systemd/system/vpncloud.target")
         .and_then(|mut f| f.write_all(TARGET_FILE))
-        .map_err(|e| Error::FileIo("Failed to create service target file", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to create service target file", source: e })?;
     File::create("/lib/systemd/system/vpncloud-wsproxy.service")
         .and_then(|mut f| f.write_all(WS_PROXY_SERVICE_FILE))
-        .map_err(|e| Error::FileIo("Failed to create wsporxy service file", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to create wsporxy service file", source: e })?;
     systemctl_daemon_reload();
     info!("Install successful");
     Ok(())
 }
 
 pub fn uninstall() -> Result<(), Error> {
-    fs::remove_file("/etc/vpncloud/example.net.disabled").map_err(|e| Error::FileIo("Failed to remove binary", e))?;
-    fs::remove_file("/usr/share/man/man1/vpncloud.1.gz").map_err(|e| Error::FileIo("Failed to remove manpage", e))?;
+    fs::remove_file("/etc/vpncloud/example.net.disabled")
+        .map_err(|e| Error::FileIo { message: "Failed to remove binary", source: e })?;
+    fs::remove_file("/usr/share/man/man1/vpncloud.1.gz")
+        .map_err(|e| Error::FileIo { message: "Failed to remove manpage", source: e })?;
     fs::remove_file("/lib/systemd/system/vpncloud@.service")
-        .map_err(|e| Error::FileIo("Failed to remove service file", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to remove service file", source: e })?;
     fs::remove_file("/lib/systemd/system/vpncloud.target")
-        .map_err(|e| Error::FileIo("Failed to remove service target file", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to remove service target file", source: e })?;
     fs::remove_file("/lib/systemd/system/vpncloud-wsproxy.service")
-        .map_err(|e| Error::FileIo("Failed to remove wsproxy service file", e))?;
-    fs::remove_file("/usr/bin/vpncloud").map_err(|e| Error::FileIo("Failed to remove binary", e))?;
+        .map_err(|e| Error::FileIo { message: "Failed to remove wsproxy service file", source: e })?;
+    fs::remove_file("/usr/bin/vpncloud")
+        .map_err(|e| Error::FileIo { message: "Failed to remove binary", source: e })?;
     systemctl_daemon_reload();
     info!("Uninstall successful");
     Ok(())
@@ -0,0 +1,118 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Short-circuit of messages between nodes in the same process
+//
+// When several networks run in one process, nodes that are peers of each other can pass their messages to each other
+// directly instead of sending them through the loopback interface. Nodes register the channel of their receive threads
+// on a global bus under their node id. The messages are still encrypted and the receiving node handles them like
+// messages from its socket, only the round-trip through the system is skipped.
+//
+// The receiving node knows the sender by the source address of its packets. For destinations on the same host, the
+// system uses the destination address as source address, so the messages are passed on with that address and the
+// port of the sender.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+};
+
+use crate::{net::mapped_addr, receivers::Injector, types::NodeId, util::MsgBufferPool};
+
+static BUS: Mutex<Option<HashMap<NodeId, Injector>>> = Mutex::new(None);
+
+/// Registration of a node on the bus, the node is removed from the bus when this is dropped
+pub struct Registration {
+    node_id: NodeId,
+    port: u16,
+}
+
+/// Registers the node with the injector of its receive threads
+pub fn register(node_id: NodeId, injector: Injector) -> Registration {
+    let port = injector.addr().port();
+    BUS.lock().unwrap().get_or_insert_with(HashMap::new).insert(node_id, injector);
+    Registration { node_id, port }
+}
+
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip6) => ip6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(ip6)),
+        ip => ip,
+    }
+}
+
+impl Registration {
+    /// Returns the peer with the node id if it is registered on the bus and reachable at the address on this host
+    pub fn lookup(&self, node_id: &NodeId, addr: SocketAddr, own_addresses: &[SocketAddr]) -> Option<LocalPeer> {
+        let ip = canonical_ip(addr.ip());
+        if !ip.is_loopback() && !own_addresses.iter().any(|own| canonical_ip(own.ip()) == ip) {
+            return None
+        }
+        let bus = BUS.lock().unwrap();
+        let injector = bus.as_ref()?.get(node_id)?;
+        if injector.addr().port() != addr.port() {
+            // The peer is reached through a forwarded port
+            return None
+        }
+        let src = SocketAddr::new(ip, self.port);
+        // The source address has the form that the socket of the peer reports
+        let src = if injector.addr().is_ipv6() { mapped_addr(src) } else { src };
+        Some(LocalPeer { injector: injector.clone(), src })
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(bus) = BUS.lock().unwrap().as_mut() {
+            bus.remove(&self.node_id);
+        }
+    }
+}
+
+/// A peer in the same process
+#[derive(Clone)]
+pub struct LocalPeer {
+    injector: Injector,
+    /// The address that the peer knows this node by
+    src: SocketAddr,
+}
+
+impl LocalPeer {
+    /// Passes a copy of the message to the peer, returns false if the peer does not receive anymore
+    #[inline]
+    pub fn deliver(&self, buffers: &MsgBufferPool, data: &[u8]) -> bool {
+        // HOT PATH
+        let mut buffer = buffers.acquire();
+        buffer.clone_from(data);
+        self.injector.inject(self.src, buffer)
+    }
+}
+
+#[test]
+fn deliver_to_registered_nodes() {
+    use crate::net::Socket;
+    use std::{net::UdpSocket, thread, time::Duration};
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let buffers = MsgBufferPool::new(16, 0);
+    let receivers = socket.spawn_receivers(1, &buffers).unwrap();
+    let receiver = register([1; 16], receivers.injector());
+    let sender = Registration { node_id: [2; 16], port: 3210 };
+    let peer_addr = SocketAddr::from(([127, 0, 0, 1], addr.port()));
+    // Only registered nodes that are reached directly on this host are found
+    assert!(sender.lookup(&[3; 16], peer_addr, &[]).is_none());
+    assert!(sender.lookup(&[1; 16], SocketAddr::from(([10, 1, 2, 3], addr.port())), &[]).is_none());
+    assert!(sender.lookup(&[1; 16], SocketAddr::from(([127, 0, 0, 1], 1)), &[]).is_none());
+    let peer = sender.lookup(&[1; 16], peer_addr, &[]).unwrap();
+    assert!(peer.deliver(&buffers, &[1, 2, 3]));
+    thread::sleep(Duration::from_millis(10));
+    let received: Vec<_> = receivers.receive().map(|(src, data)| (src, data.message().to_vec())).collect();
+    assert_eq!(received, vec![("127.0.0.1:3210".parse().unwrap(), vec![1, 2, 3])]);
+    drop(receiver);
+    assert!(sender.lookup(&[1; 16], peer_addr, &[]).is_none());
+    // Peers that stopped receiving are detected
+    drop(receivers);
+    assert!(!peer.deliver(&buffers, &[1, 2, 3]));
+}
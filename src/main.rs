@@ -15,23 +15,63 @@ pub mod util;
 #[cfg(test)]
 #[macro_use]
 mod tests;
+pub mod acl;
+pub mod api;
+pub mod arp_proxy;
+pub mod audit;
 pub mod beacon;
+pub mod bloom;
+pub mod capture;
+pub mod cert_auth;
 pub mod cloud;
+pub mod cni;
 pub mod config;
 pub mod crypto;
 pub mod device;
+pub mod dns_beacon;
+#[cfg(feature = "ebpf")]
+pub mod ebpf;
 pub mod error;
+pub mod fragment;
+pub mod gossip;
+#[cfg(feature = "grpc")]
+// tonic::Status is large but it is the error type of all the generated interfaces
+#[allow(clippy::result_large_err)]
+pub mod grpc;
+pub mod identity;
+pub mod igmp_snoop;
 #[cfg(feature = "installer")]
 pub mod installer;
+pub mod local_bus;
+pub mod manager;
 pub mod messages;
+pub mod metrics;
 pub mod net;
+pub mod netlink;
+pub mod noise;
 pub mod oldconfig;
+pub mod pacing;
 pub mod payload;
+pub mod peer_list;
+pub mod policy;
 pub mod poll;
 pub mod port_forwarding;
+pub mod receivers;
+pub mod reorder;
+pub mod route_sync;
+pub mod snmp;
+pub mod srv_bootstrap;
+pub mod stats;
+pub mod systemd;
 pub mod table;
+pub mod telemetry;
+pub mod tofu;
+pub mod topology;
 pub mod traffic;
+pub mod turn;
 pub mod types;
+pub mod vxlan;
+pub mod webhook;
 #[cfg(feature = "wizard")]
 pub mod wizard;
 #[cfg(feature = "websocket")]
@@ -52,11 +92,12 @@ use std::{
 };
 
 use crate::{
-    cloud::GenericCloud,
-    config::{Args, Command, Config, DEFAULT_PORT},
+    cloud::{CloudBuilder, GenericCloud},
+    config::{Args, Command, Config, ConfigFile, DEFAULT_PORT},
     crypto::Crypto,
     device::{Device, TunTapDevice, Type},
-    net::Socket,
+    manager::CloudManager,
+    net::{open_socket, Socket},
     oldconfig::OldConfigFile,
     payload::Protocol,
     util::SystemTimeSource,
@@ -67,19 +108,20 @@ use crate::wsproxy::ProxyConnection;
 
 struct DualLogger {
     file: Option<Mutex<File>>,
+    stderr: bool,
 }
 
 impl DualLogger {
-    pub fn new<P: AsRef<Path>>(path: Option<P>) -> Result<Self, io::Error> {
+    pub fn new<P: AsRef<Path>>(path: Option<P>, stderr: bool) -> Result<Self, io::Error> {
         if let Some(path) = path {
             let path = path.as_ref();
             if path.exists() {
                 fs::remove_file(path)?
             }
             let file = File::create(path)?;
-            Ok(DualLogger { file: Some(Mutex::new(file)) })
+            Ok(DualLogger { file: Some(Mutex::new(file)), stderr })
         } else {
-            Ok(DualLogger { file: None })
+            Ok(DualLogger { file: None, stderr })
         }
     }
 }
@@ -93,7 +135,11 @@ impl log::Log for DualLogger {
     #[inline]
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
-            println!("{} - {}", record.level(), record.args());
+            if self.stderr {
+                eprintln!("{} - {}", record.level(), record.args());
+            } else {
+                println!("{} - {}", record.level(), record.args());
+            }
             if let Some(ref file) = self.file {
                 let mut file = file.lock().expect("Lock poisoned");
                 let time = chrono::Local::now().format("%F %H:%M:%S");
@@ -148,9 +194,19 @@ fn setup_device(config: &Config) -> TunTapDevice {
         config.device_name
     );
     info!("Opened device {}", device.ifname());
+    if config.device_persistent && config.device_name.contains('%') {
+        fail!("Persistent devices need a fixed name, {} contains a placeholder", config.device_name);
+    }
+    // Persistent devices from earlier runs are removed on exit unless they should be persistent
+    try_fail!(device.set_persistent(config.device_persistent), "Failed to set persistence of device: {}");
     config.call_hook("device_setup", vec![("IFNAME", device.ifname())], true);
-    if let Err(err) = device.set_mtu(None) {
-        error!("Error setting optimal MTU on {}: {}", device.ifname(), err);
+    if let (Some(mtu), Ok(optimal)) = (config.mtu, device.optimal_mtu()) {
+        if mtu as usize > optimal {
+            warn!("MTU {} is larger than the MTU of the default device minus the VPN overhead ({})", mtu, optimal);
+        }
+    }
+    if let Err(err) = device.set_mtu(config.mtu.map(usize::from)) {
+        error!("Error setting MTU on {}: {}", device.ifname(), err);
     }
     if let Some(ip) = &config.ip {
         let (ip, netmask) = try_fail!(parse_ip_netmask(ip), "Invalid ip address given: {}");
@@ -172,9 +228,16 @@ fn setup_device(config: &Config) -> TunTapDevice {
     device
 }
 
-#[allow(clippy::cognitive_complexity)]
-fn run<P: Protocol, S: Socket>(config: Config, socket: S) {
-    let device = setup_device(&config);
+fn create_cloud<P: Protocol, S: Socket>(
+    config: &Config, socket: S,
+) -> GenericCloud<TunTapDevice, P, S, SystemTimeSource> {
+    let device = setup_device(config);
+    create_cloud_with_device(config, socket, device)
+}
+
+fn create_cloud_with_device<P: Protocol, S: Socket>(
+    config: &Config, socket: S, device: TunTapDevice,
+) -> GenericCloud<TunTapDevice, P, S, SystemTimeSource> {
     let port_forwarding = if config.port_forwarding { socket.create_port_forwarding() } else { None };
     let stats_file = match config.stats_file {
         None => None,
@@ -191,9 +254,18 @@ fn run<P: Protocol, S: Socket>(config: Config, socket: S) {
             Some(file)
         }
     };
-    let mut cloud =
-        GenericCloud::<TunTapDevice, P, S, SystemTimeSource>::new(&config, socket, device, port_forwarding, stats_file);
-    for mut addr in config.peers {
+    let mut cloud = try_fail!(
+        CloudBuilder::<TunTapDevice, P, S, SystemTimeSource>::new()
+            .config(config.clone())
+            .socket(socket)
+            .device(device)
+            .port_forwarding(port_forwarding)
+            .stats_file(stats_file)
+            .build(),
+        "Failed to create node: {}"
+    );
+    for addr in &config.peers {
+        let mut addr = addr.clone();
         if addr.find(':').unwrap_or(0) <= addr.find(']').unwrap_or(0) {
             // : not present or only in IPv6 address
             addr = format!("{}:{}", addr, DEFAULT_PORT)
@@ -201,16 +273,20 @@ fn run<P: Protocol, S: Socket>(config: Config, socket: S) {
         try_fail!(cloud.connect(&addr as &str), "Failed to send message to {}: {}", &addr);
         cloud.add_reconnect_peer(addr);
     }
+    cloud
+}
+
+fn drop_privileges(config: &Config) {
     if config.daemonize {
         info!("Running process as daemon");
         let mut daemonize = daemonize::Daemonize::new();
-        if let Some(user) = config.user {
-            daemonize = daemonize.user(&user as &str);
+        if let Some(user) = &config.user {
+            daemonize = daemonize.user(user as &str);
         }
-        if let Some(group) = config.group {
-            daemonize = daemonize.group(&group as &str);
+        if let Some(group) = &config.group {
+            daemonize = daemonize.group(group as &str);
         }
-        if let Some(pid_file) = config.pid_file {
+        if let Some(pid_file) = &config.pid_file {
             daemonize = daemonize.pid_file(pid_file).chown_pid_file(true);
             // Give child process some time to write PID file
             daemonize = daemonize.exit_action(|| thread::sleep(std::time::Duration::from_millis(10)));
@@ -219,17 +295,57 @@ fn run<P: Protocol, S: Socket>(config: Config, socket: S) {
     } else if config.user.is_some() || config.group.is_some() {
         info!("Dropping privileges");
         let mut pd = privdrop::PrivDrop::default();
-        if let Some(user) = config.user {
+        if let Some(user) = &config.user {
             pd = pd.user(user);
         }
-        if let Some(group) = config.group {
+        if let Some(group) = &config.group {
             pd = pd.group(group);
         }
         try_fail!(pd.apply(), "Failed to drop privileges: {}");
     }
+}
+
+fn run_cloud<P: Protocol, S: Socket>(mut cloud: GenericCloud<TunTapDevice, P, S, SystemTimeSource>, config: &Config) {
     cloud.run();
-    if let Some(script) = config.ifdown {
-        run_script(&script, cloud.ifname());
+    if let Some(script) = &config.ifdown {
+        run_script(script, cloud.ifname());
+    }
+}
+
+fn run<P: Protocol, S: Socket>(config: Config, socket: S) {
+    let cloud = create_cloud::<P, S>(&config, socket);
+    drop_privileges(&config);
+    run_cloud(cloud, &config);
+}
+
+fn read_config_file(file: &str) -> ConfigFile {
+    info!("Reading config file '{}'", file);
+    let f = try_fail!(File::open(file), "Failed to open config file: {:?}");
+    match serde_yaml::from_reader(f) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Failed to read config file: {}", err);
+            info!("Trying to convert from old config format");
+            let f = try_fail!(File::open(file), "Failed to open config file: {:?}");
+            let config_file_old: OldConfigFile =
+                try_fail!(serde_yaml::from_reader(f), "Config file is neither version 2 nor version 1: {:?}");
+            let new_config = config_file_old.convert();
+            info!("Successfully converted from old format, please migrate your config using migrate-config");
+            new_config
+        }
+    }
+}
+
+fn validate_config(config: &Config) -> bool {
+    match config.validate() {
+        Ok(()) => true,
+        Err(errors) => {
+            for err in errors {
+                error!("Invalid config: {}", err);
+            }
+            log::logger().flush();
+            false
+        }
     }
 }
 
@@ -239,7 +355,13 @@ fn main() {
         println!("VpnCloud v{}", env!("CARGO_PKG_VERSION"));
         return;
     }
-    let logger = try_fail!(DualLogger::new(args.log_file.as_ref()), "Failed to open logfile: {}");
+    if args.print_config_schema {
+        println!("{}", serde_json::to_string_pretty(&ConfigFile::json_schema()).unwrap());
+        return;
+    }
+    // The CNI specification reserves stdout for results
+    let stderr = matches!(args.cmd, Some(Command::Cni));
+    let logger = try_fail!(DualLogger::new(args.log_file.as_ref(), stderr), "Failed to open logfile: {}");
     log::set_boxed_logger(Box::new(logger)).unwrap();
     assert!(!args.verbose || !args.quiet);
     log::set_max_level(if args.verbose {
@@ -278,6 +400,7 @@ fn main() {
                 );
                 try_fail!(serde_yaml::to_writer(f, &new_config), "Failed to write converted config: {:?}");
             }
+            Command::Cni => cni::run(),
             Command::Completion { shell } => {
                 Args::clap().gen_completions_to(env!("CARGO_PKG_NAME"), shell, &mut io::stdout());
             }
@@ -300,41 +423,60 @@ fn main() {
         }
         return;
     }
-    let mut config = Config::default();
-    if let Some(ref file) = args.config {
-        info!("Reading config file '{}'", file);
-        let f = try_fail!(File::open(file), "Failed to open config file: {:?}");
-        let config_file = match serde_yaml::from_reader(f) {
-            Ok(config) => config,
-            Err(err) => {
-                error!("Failed to read config file: {}", err);
-                info!("Trying to convert from old config format");
-                let f = try_fail!(File::open(file), "Failed to open config file: {:?}");
-                let config_file_old: OldConfigFile =
-                    try_fail!(serde_yaml::from_reader(f), "Config file is neither version 2 nor version 1: {:?}");
-                let new_config = config_file_old.convert();
-                info!("Successfully converted from old format, please migrate your config using migrate-config");
-                new_config
+    if args.config.len() > 1 {
+        let mut configs = vec![];
+        for file in &args.config {
+            let mut config = Config::default();
+            config.merge_file(read_config_file(file));
+            config.merge_args(args.clone());
+            debug!("Config: {:?}", config);
+            if !validate_config(&config) {
+                process::exit(1);
             }
-        };
-        config.merge_file(config_file)
+            configs.push(config);
+        }
+        if args.check_config {
+            info!("Config is valid");
+            return;
+        }
+        let first = configs[0].clone();
+        let manager = try_fail!(CloudManager::new(configs), "Failed to setup networks: {}");
+        drop_privileges(&first);
+        manager.run();
+        return;
     }
+    let mut config = Config::default();
+    if let Some(file) = args.config.first() {
+        config.merge_file(read_config_file(file))
+    }
+    let check_config = args.check_config;
     config.merge_args(args);
     debug!("Config: {:?}", config);
-    if config.crypto.password.is_none() && config.crypto.private_key.is_none() {
-        error!("Either password or private key must be set in config or given as parameter");
+    if !validate_config(&config) {
+        process::exit(1);
+    }
+    if check_config {
+        info!("Config is valid");
         return;
     }
     #[cfg(feature = "websocket")]
     if config.listen.starts_with("ws://") {
-        let socket = try_fail!(ProxyConnection::listen(&config.listen), "Failed to open socket {}: {}", config.listen);
+        let socket = try_fail!(
+            ProxyConnection::listen(&config.listen, config.address_family),
+            "Failed to open socket {}: {}",
+            config.listen
+        );
         match config.device_type {
             Type::Tap => run::<payload::Frame, _>(config, socket),
             Type::Tun => run::<payload::Packet, _>(config, socket),
         }
         return;
     }
-    let socket = try_fail!(UdpSocket::listen(&config.listen), "Failed to open socket {}: {}", config.listen);
+    let socket = try_fail!(
+        open_socket::<UdpSocket>(&config.listen, config.address_family, config.receive_threads),
+        "Failed to open socket {}: {}",
+        config.listen
+    );
     match config.device_type {
         Type::Tap => run::<payload::Frame, _>(config, socket),
         Type::Tun => run::<payload::Packet, _>(config, socket),
@@ -0,0 +1,190 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{collections::HashSet, net::UdpSocket, panic, thread, time::Duration};
+
+use crate::{
+    cloud::{GenericCloud, StopHandle},
+    config::{Config, DEFAULT_PORT},
+    device::{TunTapDevice, Type},
+    error::Error,
+    net::{open_socket, parse_listen},
+    payload::{Frame, Packet},
+    util::{CtrlC, SystemTimeSource},
+};
+
+pub type TunCloud = GenericCloud<TunTapDevice, Packet, UdpSocket, SystemTimeSource>;
+pub type TapCloud = GenericCloud<TunTapDevice, Frame, UdpSocket, SystemTimeSource>;
+
+pub enum ManagedCloud {
+    Tun(Box<TunCloud>),
+    Tap(Box<TapCloud>),
+}
+
+impl ManagedCloud {
+    fn stop_handle(&self) -> StopHandle {
+        match self {
+            ManagedCloud::Tun(cloud) => cloud.stop_handle(),
+            ManagedCloud::Tap(cloud) => cloud.stop_handle(),
+        }
+    }
+
+    fn run(self, config: &Config) {
+        match self {
+            ManagedCloud::Tun(cloud) => crate::run_cloud(*cloud, config),
+            ManagedCloud::Tap(cloud) => crate::run_cloud(*cloud, config),
+        }
+    }
+}
+
+/// Stops all networks when dropped, so that all networks stop when one of them stops or panics
+struct StopAll(Vec<StopHandle>);
+
+impl Drop for StopAll {
+    fn drop(&mut self) {
+        for handle in &self.0 {
+            handle.stop()
+        }
+    }
+}
+
+/// Runs multiple networks in one process, each in its own thread
+pub struct CloudManager {
+    clouds: Vec<(Config, ManagedCloud)>,
+}
+
+impl CloudManager {
+    fn validate(configs: &[Config]) -> Result<(), Error> {
+        let mut ports = HashSet::new();
+        let mut devices = HashSet::new();
+        let mut api_addrs = HashSet::new();
+        for config in configs {
+            if config.listen.starts_with("ws://") {
+                return Err(Error::InvalidConfig("Multiple networks can only listen on UDP sockets"))
+            }
+            if !ports.insert(parse_listen(&config.listen, DEFAULT_PORT).port()) {
+                return Err(Error::InvalidConfig("Multiple networks listen on the same port"))
+            }
+            // Names with placeholders are numbered by the kernel
+            if !config.device_name.contains('%') && !devices.insert(&config.device_name) {
+                return Err(Error::InvalidConfig("Multiple networks use the same device name"))
+            }
+            if let Some(addr) = &config.api_addr {
+                if !api_addrs.insert(addr) {
+                    return Err(Error::InvalidConfig("Multiple networks use the same management API address"))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the sockets and devices of all networks and connects to their peers
+    pub fn new(configs: Vec<Config>) -> Result<Self, Error> {
+        Self::validate(&configs)?;
+        let mut clouds = Vec::with_capacity(configs.len());
+        for config in configs {
+            let socket = open_socket::<UdpSocket>(&config.listen, config.address_family, config.receive_threads)
+                .map_err(|e| Error::SocketIo { message: "Failed to open socket", source: e })?;
+            let cloud = match config.device_type {
+                Type::Tun => ManagedCloud::Tun(Box::new(crate::create_cloud(&config, socket))),
+                Type::Tap => ManagedCloud::Tap(Box::new(crate::create_cloud(&config, socket))),
+            };
+            clouds.push((config, cloud));
+        }
+        Ok(Self { clouds })
+    }
+
+    /// Handles of the networks, stopping one of them stops all networks
+    pub fn stop_handles(&self) -> Vec<StopHandle> {
+        self.clouds.iter().map(|(_, cloud)| cloud.stop_handle()).collect()
+    }
+
+    /// Runs all networks until they are stopped, the first panic of a network is propagated
+    pub fn run(self) {
+        // Signals are blocked in this thread and all threads started by it
+        let ctrlc = CtrlC::new();
+        let stop_handles = self.stop_handles();
+        let mut threads = Vec::with_capacity(self.clouds.len());
+        for (config, cloud) in self.clouds {
+            let stop_all = StopAll(stop_handles.clone());
+            threads.push(thread::spawn(move || {
+                let _stop_all = stop_all;
+                cloud.run(&config)
+            }));
+        }
+        let stop_all = StopAll(stop_handles);
+        while !stop_all.0.iter().any(StopHandle::is_stopped) && !ctrlc.was_pressed() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        drop(stop_all);
+        let mut panic = None;
+        for thread in threads {
+            if let Err(err) = thread.join() {
+                panic.get_or_insert(err);
+            }
+        }
+        if let Some(err) = panic {
+            panic::resume_unwind(err)
+        }
+    }
+}
+
+#[test]
+fn manager_validate() {
+    let config = |listen: &str, device: &str| Config {
+        listen: listen.to_string(),
+        device_name: device.to_string(),
+        ..Config::default()
+    };
+    CloudManager::validate(&[config("3210", "vpncloud%d"), config("3211", "vpncloud%d")]).unwrap();
+    assert!(CloudManager::validate(&[config("3210", "vpn0"), config("[::]:3210", "vpn1")]).is_err());
+    assert!(CloudManager::validate(&[config("3210", "vpn0"), config("3211", "vpn0")]).is_err());
+    assert!(CloudManager::validate(&[config("3210", "vpn0"), config("ws://localhost:3211", "vpn1")]).is_err());
+    let api = |config: Config| Config { api_addr: Some("127.0.0.1:8080".to_string()), ..config };
+    assert!(CloudManager::validate(&[api(config("3210", "vpn0")), api(config("3211", "vpn1"))]).is_err());
+}
+
+#[test]
+fn manager_runs_networks() {
+    use crate::config::CryptoConfig;
+    use std::{collections::HashMap, path::Path, time::Instant};
+    if TunTapDevice::new("vpnmgr%d", Type::Tun, None).is_err() {
+        warn!("Skipping test as TUN devices can not be created");
+        return
+    }
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path().to_str().unwrap().to_string();
+    let ports: Vec<_> = (0..2).map(|_| UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()).collect();
+    let config = |port: u16, peers: Vec<String>| Config {
+        listen: format!("127.0.0.1:{}", port),
+        peers,
+        device_type: Type::Tun,
+        device_name: "vpnmgr%d".to_string(),
+        port_forwarding: false,
+        crypto: CryptoConfig {
+            password: Some("test123".to_string()),
+            argon2_memory_kib: Some(64),
+            argon2_iterations: Some(1),
+            ..CryptoConfig::default()
+        },
+        hooks: vec![("peer_connected".to_string(), format!("touch {}/{}", dir, port))]
+            .into_iter()
+            .collect::<HashMap<_, _>>(),
+        ..Config::default()
+    };
+    let manager =
+        CloudManager::new(vec![config(ports[0], vec![]), config(ports[1], vec![format!("127.0.0.1:{}", ports[0])])])
+            .unwrap();
+    let stop_handles = manager.stop_handles();
+    let thread = thread::spawn(move || manager.run());
+    let start = Instant::now();
+    while !ports.iter().all(|port| Path::new(&format!("{}/{}", dir, port)).exists()) {
+        assert!(start.elapsed() < Duration::from_secs(10), "Networks did not connect");
+        thread::sleep(Duration::from_millis(50));
+    }
+    // Stopping one network stops all of them
+    stop_handles[0].stop();
+    thread.join().unwrap();
+    assert!(stop_handles.iter().all(StopHandle::is_stopped));
+}
@@ -5,12 +5,15 @@
 use crate::{
     crypto::Payload,
     error::Error,
+    identity::IdentityProof,
     types::{NodeId, Range, RangeList, NODE_ID_BYTES},
     util::MsgBuffer,
 };
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use smallvec::{smallvec, SmallVec};
 use std::{
+    cmp::min,
+    collections::HashMap,
     io::{self, Cursor, Read, Seek, SeekFrom, Take, Write},
     net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
 };
@@ -18,8 +21,22 @@ use std::{
 pub const MESSAGE_TYPE_DATA: u8 = 0;
 pub const MESSAGE_TYPE_NODE_INFO: u8 = 1;
 pub const MESSAGE_TYPE_KEEPALIVE: u8 = 2;
+pub const MESSAGE_TYPE_DATA_TRACED: u8 = 3;
+pub const MESSAGE_TYPE_PROBE: u8 = 4;
+pub const MESSAGE_TYPE_PROBE_REPLY: u8 = 5;
+pub const MESSAGE_TYPE_MTU_PROBE: u8 = 6;
+pub const MESSAGE_TYPE_MTU_PROBE_REPLY: u8 = 7;
+pub const MESSAGE_TYPE_PUNCH_COORDINATE: u8 = 8;
+pub const MESSAGE_TYPE_DATA_PADDED: u8 = 9;
+pub const MESSAGE_TYPE_PING: u8 = 10;
+pub const MESSAGE_TYPE_PONG: u8 = 11;
+pub const MESSAGE_TYPE_DATA_FRAGMENT: u8 = 12;
+pub const MESSAGE_TYPE_PEER_UPDATE: u8 = 13;
 pub const MESSAGE_TYPE_CLOSE: u8 = 0xff;
 
+/// Version of the protocol of this node, nodes that do not send their version speak version 1
+pub const PROTOCOL_VERSION: u16 = 2;
+
 pub type AddrList = SmallVec<[SocketAddr; 4]>;
 pub type PeerList = SmallVec<[PeerInfo; 16]>;
 
@@ -29,6 +46,50 @@ pub struct PeerInfo {
     pub addrs: AddrList,
 }
 
+/// Protocol extensions that a node can support, the value is the bit in the extensions of the node info
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Traced data messages
+    Tracing = 0,
+    /// Dead peer detection probes
+    Dpd = 1,
+    /// Path MTU discovery probes
+    MtuProbe = 2,
+    /// Coordination of hole punching
+    HolePunch = 3,
+    /// Padded data messages
+    Padding = 4,
+    /// Answers to pings
+    Ping = 5,
+    /// Reassembly of fragmented data
+    Fragments = 6,
+    /// Peer list updates
+    PeerUpdates = 7,
+}
+
+/// Set of protocol extensions as a bitmask
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features(pub u64);
+
+impl Features {
+    pub fn has(self, feature: Feature) -> bool {
+        self.0 & (1 << feature as u64) != 0
+    }
+
+    pub fn set(&mut self, feature: Feature, enabled: bool) {
+        if enabled {
+            self.0 |= 1 << feature as u64
+        } else {
+            self.0 &= !(1 << feature as u64)
+        }
+    }
+
+    /// The extensions that both sides support
+    pub fn intersect(self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct NodeInfo {
     pub node_id: NodeId,
@@ -36,6 +97,13 @@ pub struct NodeInfo {
     pub claims: RangeList,
     pub peer_timeout: Option<u16>,
     pub addrs: AddrList,
+    pub tags: HashMap<String, String>,
+    pub mtu: Option<u16>,
+    pub identity: Option<IdentityProof>,
+    /// Version of the protocol, 1 if the node did not send it
+    pub protocol_version: u16,
+    /// Protocol extensions that the node supports
+    pub extensions: Features,
 }
 
 impl NodeInfo {
@@ -45,6 +113,11 @@ impl NodeInfo {
     const PART_PEERS: u8 = 1;
     const PART_PEER_TIMEOUT: u8 = 3;
     const PART_ADDRS: u8 = 5;
+    const PART_TAGS: u8 = 6;
+    const PART_IDENTITY: u8 = 7;
+    const PART_MTU: u8 = 8;
+    const PART_PROTOCOL_VERSION: u8 = 9;
+    const PART_EXTENSIONS: u8 = 10;
 
     fn read_addr_list<R: Read>(r: &mut Take<R>) -> Result<AddrList, io::Error> {
         let flags = r.read_u8()?;
@@ -97,12 +170,42 @@ impl NodeInfo {
         Ok(claims)
     }
 
+    fn decode_tags_part<R: Read>(r: &mut Take<R>) -> Result<HashMap<String, String>, io::Error> {
+        let mut tags = HashMap::new();
+        let read_string = |r: &mut Take<R>| -> Result<String, io::Error> {
+            let len = r.read_u8()? as usize;
+            let mut data = vec![0; len];
+            r.read_exact(&mut data)?;
+            String::from_utf8(data).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+        };
+        while r.limit() > 0 {
+            let key = read_string(r)?;
+            let val = read_string(r)?;
+            tags.insert(key, val);
+        }
+        Ok(tags)
+    }
+
+    fn decode_identity_part<R: Read>(r: &mut Take<R>) -> Result<IdentityProof, io::Error> {
+        let mut public_key = [0; 32];
+        r.read_exact(&mut public_key)?;
+        let timestamp = r.read_i64::<NetworkEndian>()?;
+        let mut signature = [0; 64];
+        r.read_exact(&mut signature)?;
+        Ok(IdentityProof { public_key, timestamp, signature })
+    }
+
     fn decode_internal<R: Read>(mut r: R) -> Result<Self, Error> {
         let mut peers = smallvec![];
         let mut claims = smallvec![];
         let mut peer_timeout = None;
         let mut node_id = None;
         let mut addrs = smallvec![];
+        let mut tags = HashMap::new();
+        let mut mtu = None;
+        let mut identity = None;
+        let mut protocol_version = 1;
+        let mut extensions = Features::default();
         loop {
             let part = r.read_u8().map_err(|_| Error::Message("Truncated message"))?;
             if part == Self::PART_END {
@@ -127,6 +230,23 @@ impl NodeInfo {
                 Self::PART_ADDRS => {
                     addrs = Self::read_addr_list(&mut rp).map_err(|_| Error::Message("Truncated message"))?;
                 }
+                Self::PART_PROTOCOL_VERSION => {
+                    protocol_version =
+                        rp.read_u16::<NetworkEndian>().map_err(|_| Error::Message("Truncated message"))?
+                }
+                Self::PART_EXTENSIONS => {
+                    extensions.0 |= rp.read_u64::<NetworkEndian>().map_err(|_| Error::Message("Truncated message"))?
+                }
+                Self::PART_TAGS => {
+                    tags = Self::decode_tags_part(&mut rp).map_err(|_| Error::Message("Invalid tags"))?
+                }
+                Self::PART_MTU => {
+                    mtu = Some(rp.read_u16::<NetworkEndian>().map_err(|_| Error::Message("Truncated message"))?)
+                }
+                Self::PART_IDENTITY => {
+                    identity =
+                        Some(Self::decode_identity_part(&mut rp).map_err(|_| Error::Message("Truncated message"))?)
+                }
                 _ => {
                     let mut data = vec![0; part_len];
                     rp.read_exact(&mut data).map_err(|_| Error::Message("Truncated message"))?;
@@ -138,15 +258,15 @@ impl NodeInfo {
             Some(node_id) => node_id,
             None => return Err(Error::Message("Payload without node_id")),
         };
-        Ok(Self { node_id, peers, claims, peer_timeout, addrs })
+        Ok(Self { node_id, peers, claims, peer_timeout, addrs, tags, mtu, identity, protocol_version, extensions })
     }
 
     pub fn decode<R: Read>(r: R) -> Result<Self, Error> {
         Self::decode_internal(r).map_err(|_| Error::Message("Input data too short"))
     }
 
-    fn encode_peer_list_part<W: Write>(&self, mut out: W) -> Result<(), io::Error> {
-        for p in &self.peers {
+    fn encode_peer_list_part<W: Write>(peers: &[PeerInfo], mut out: W) -> Result<(), io::Error> {
+        for p in peers {
             let mut addr_ipv4: SmallVec<[SocketAddrV4; 16]> = smallvec![];
             let mut addr_ipv6: SmallVec<[SocketAddrV6; 16]> = smallvec![];
             for a in &p.addrs {
@@ -209,6 +329,23 @@ impl NodeInfo {
         Ok(())
     }
 
+    fn encode_tags_part<W: Write>(&self, mut out: W) -> Result<(), io::Error> {
+        let mut write_string = |s: &str| -> Result<(), io::Error> {
+            // Longer strings are truncated at a character boundary
+            let mut len = min(s.len(), 255);
+            while !s.is_char_boundary(len) {
+                len -= 1
+            }
+            out.write_u8(len as u8)?;
+            out.write_all(&s.as_bytes()[..len])
+        };
+        for (key, val) in &self.tags {
+            write_string(key)?;
+            write_string(val)?;
+        }
+        Ok(())
+    }
+
     fn encode_part<F: FnOnce(&mut Cursor<&mut [u8]>) -> Result<(), io::Error>>(
         cursor: &mut Cursor<&mut [u8]>, part: u8, f: F,
     ) -> Result<(), io::Error> {
@@ -229,7 +366,9 @@ impl NodeInfo {
         {
             let mut cursor = Cursor::new(buffer.buffer());
             Self::encode_part(&mut cursor, Self::PART_NODEID, |cursor| cursor.write_all(&self.node_id))?;
-            Self::encode_part(&mut cursor, Self::PART_PEERS, |cursor| self.encode_peer_list_part(cursor))?;
+            Self::encode_part(&mut cursor, Self::PART_PEERS, |cursor| {
+                Self::encode_peer_list_part(&self.peers, cursor)
+            })?;
             Self::encode_part(&mut cursor, Self::PART_CLAIMS, |mut cursor| {
                 for c in &self.claims {
                     c.write_to(&mut cursor);
@@ -242,6 +381,25 @@ impl NodeInfo {
                 })?
             }
             Self::encode_part(&mut cursor, Self::PART_ADDRS, |cursor| self.encode_addrs_part(cursor))?;
+            if !self.tags.is_empty() {
+                Self::encode_part(&mut cursor, Self::PART_TAGS, |cursor| self.encode_tags_part(cursor))?;
+            }
+            if let Some(mtu) = self.mtu {
+                Self::encode_part(&mut cursor, Self::PART_MTU, |cursor| cursor.write_u16::<NetworkEndian>(mtu))?;
+            }
+            if let Some(identity) = &self.identity {
+                Self::encode_part(&mut cursor, Self::PART_IDENTITY, |cursor| {
+                    cursor.write_all(&identity.public_key)?;
+                    cursor.write_i64::<NetworkEndian>(identity.timestamp)?;
+                    cursor.write_all(&identity.signature)
+                })?;
+            }
+            Self::encode_part(&mut cursor, Self::PART_PROTOCOL_VERSION, |cursor| {
+                cursor.write_u16::<NetworkEndian>(self.protocol_version)
+            })?;
+            Self::encode_part(&mut cursor, Self::PART_EXTENSIONS, |cursor| {
+                cursor.write_u64::<NetworkEndian>(self.extensions.0)
+            })?;
             cursor.write_u8(Self::PART_END)?;
             len = cursor.position() as usize;
         }
@@ -263,3 +421,209 @@ impl Payload for NodeInfo {
         Self::decode(r)
     }
 }
+
+/// Length of the trailer of padded data messages that holds the padding length
+pub const PADDING_TRAILER_LEN: usize = 2;
+
+/// Pads the data of a `MESSAGE_TYPE_DATA_PADDED` message to the given length
+///
+/// The padding consists of zeros followed by a trailer with the total number of bytes that have been appended.
+/// The length must be at least `PADDING_TRAILER_LEN` bytes more than the data.
+#[inline]
+pub fn add_padding(buffer: &mut MsgBuffer, len: usize) {
+    let data_len = buffer.len();
+    let pad_len = len - data_len;
+    buffer.set_length(len);
+    let msg = buffer.message_mut();
+    for b in &mut msg[data_len..len - PADDING_TRAILER_LEN] {
+        *b = 0
+    }
+    msg[len - PADDING_TRAILER_LEN..].copy_from_slice(&(pad_len as u16).to_be_bytes())
+}
+
+/// Removes the padding from the data of a `MESSAGE_TYPE_DATA_PADDED` message
+#[inline]
+pub fn strip_padding(buffer: &mut MsgBuffer) -> Result<(), Error> {
+    let len = buffer.len();
+    if len < PADDING_TRAILER_LEN {
+        return Err(Error::Message("Padded message too short"))
+    }
+    let msg = buffer.message();
+    let pad_len = u16::from_be_bytes([msg[len - 2], msg[len - 1]]) as usize;
+    if pad_len < PADDING_TRAILER_LEN || pad_len > len {
+        return Err(Error::Message("Invalid padding length"))
+    }
+    buffer.set_length(len - pad_len);
+    Ok(())
+}
+
+/// Asks a peer to connect to the node with the given id at the given address
+///
+/// A node that can not reach another node sends this message to a peer that advertised the target node. That peer
+/// relays the message to both sides with the external address of the other side, so that both sides send init
+/// messages to each other at the same time and punch holes into their NATs.
+#[derive(Debug, PartialEq)]
+pub struct PunchCoordinate {
+    pub target_node_id: NodeId,
+    pub initiator_addr: SocketAddr,
+}
+
+impl PunchCoordinate {
+    fn decode_internal<R: Read>(mut r: R) -> Result<Self, io::Error> {
+        let mut target_node_id = [0; NODE_ID_BYTES];
+        r.read_exact(&mut target_node_id)?;
+        let initiator_addr = match r.read_u8()? {
+            4 => {
+                let mut ip = [0u8; 4];
+                r.read_exact(&mut ip)?;
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(ip), r.read_u16::<NetworkEndian>()?))
+            }
+            6 => {
+                let mut ip = [0u8; 16];
+                r.read_exact(&mut ip)?;
+                SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(ip), r.read_u16::<NetworkEndian>()?, 0, 0))
+            }
+            _ => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+        };
+        Ok(Self { target_node_id, initiator_addr })
+    }
+
+    pub fn decode<R: Read>(r: R) -> Result<Self, Error> {
+        Self::decode_internal(r).map_err(|_| Error::Message("Invalid punch coordinate message"))
+    }
+
+    fn encode_internal(&self, buffer: &mut MsgBuffer) -> Result<(), io::Error> {
+        let len;
+        {
+            let mut cursor = Cursor::new(buffer.buffer());
+            cursor.write_all(&self.target_node_id)?;
+            match self.initiator_addr {
+                SocketAddr::V4(addr) => {
+                    cursor.write_u8(4)?;
+                    cursor.write_all(&addr.ip().octets())?;
+                    cursor.write_u16::<NetworkEndian>(addr.port())?;
+                }
+                SocketAddr::V6(addr) => {
+                    cursor.write_u8(6)?;
+                    cursor.write_all(&addr.ip().octets())?;
+                    cursor.write_u16::<NetworkEndian>(addr.port())?;
+                }
+            }
+            len = cursor.position() as usize;
+        }
+        buffer.set_length(len);
+        Ok(())
+    }
+
+    pub fn encode(&self, buffer: &mut MsgBuffer) {
+        self.encode_internal(buffer).expect("Buffer too small")
+    }
+}
+
+/// Peers that have been added or removed since the last peer list sent to a peer
+///
+/// Full peer lists are only sent to new peers and periodically, in between peers that announced support for updates
+/// only receive the changes. The message consists of parts like the node info, the peers are encoded like its peer
+/// list.
+#[derive(Debug, PartialEq)]
+pub struct PeerUpdate {
+    pub added: PeerList,
+    pub removed: PeerList,
+}
+
+impl PeerUpdate {
+    const PART_END: u8 = 0;
+    const PART_ADDED: u8 = 1;
+    const PART_REMOVED: u8 = 2;
+
+    fn decode_internal<R: Read>(mut r: R) -> Result<Self, io::Error> {
+        let mut added = smallvec![];
+        let mut removed = smallvec![];
+        loop {
+            let part = r.read_u8()?;
+            if part == Self::PART_END {
+                break
+            }
+            let part_len = r.read_u16::<NetworkEndian>()?;
+            let mut rp = r.take(u64::from(part_len));
+            match part {
+                Self::PART_ADDED => added = NodeInfo::decode_peer_list_part(&mut rp)?,
+                Self::PART_REMOVED => removed = NodeInfo::decode_peer_list_part(&mut rp)?,
+                _ => io::copy(&mut rp, &mut io::sink()).map(|_| ())?,
+            }
+            r = rp.into_inner();
+        }
+        Ok(Self { added, removed })
+    }
+
+    pub fn decode<R: Read>(r: R) -> Result<Self, Error> {
+        Self::decode_internal(r).map_err(|_| Error::Message("Invalid peer update message"))
+    }
+
+    fn encode_internal(&self, buffer: &mut MsgBuffer) -> Result<(), io::Error> {
+        let len;
+        {
+            let mut cursor = Cursor::new(buffer.buffer());
+            NodeInfo::encode_part(&mut cursor, Self::PART_ADDED, |cursor| {
+                NodeInfo::encode_peer_list_part(&self.added, cursor)
+            })?;
+            NodeInfo::encode_part(&mut cursor, Self::PART_REMOVED, |cursor| {
+                NodeInfo::encode_peer_list_part(&self.removed, cursor)
+            })?;
+            cursor.write_u8(Self::PART_END)?;
+            len = cursor.position() as usize;
+        }
+        buffer.set_length(len);
+        Ok(())
+    }
+
+    pub fn encode(&self, buffer: &mut MsgBuffer) {
+        self.encode_internal(buffer).expect("Buffer too small")
+    }
+}
+
+#[cfg(test)]
+fn node_info(extensions: Features) -> NodeInfo {
+    NodeInfo {
+        node_id: [1; NODE_ID_BYTES],
+        peers: smallvec![],
+        claims: smallvec![],
+        peer_timeout: None,
+        addrs: smallvec![],
+        tags: HashMap::new(),
+        mtu: None,
+        identity: None,
+        protocol_version: PROTOCOL_VERSION,
+        extensions,
+    }
+}
+
+#[test]
+fn encode_extensions() {
+    let mut extensions = Features::default();
+    extensions.set(Feature::Ping, true);
+    extensions.set(Feature::Fragments, true);
+    // Unknown extensions of newer nodes are kept
+    extensions.0 |= 1 << 40;
+    let info = node_info(extensions);
+    let mut buffer = MsgBuffer::new(0);
+    info.encode(&mut buffer);
+    assert_eq!(NodeInfo::decode(buffer.message()).unwrap(), info);
+    let mut other = Features::default();
+    other.set(Feature::Ping, true);
+    other.set(Feature::PeerUpdates, true);
+    let both = extensions.intersect(other);
+    assert!(both.has(Feature::Ping));
+    assert!(!both.has(Feature::Fragments));
+    assert!(!both.has(Feature::PeerUpdates));
+}
+
+#[test]
+fn decode_without_extensions() {
+    let mut data = vec![NodeInfo::PART_NODEID, 0, NODE_ID_BYTES as u8];
+    data.extend_from_slice(&[1; NODE_ID_BYTES]);
+    data.push(NodeInfo::PART_END);
+    let info = NodeInfo::decode(&data[..]).unwrap();
+    assert_eq!(info.protocol_version, 1);
+    assert_eq!(info.extensions, Features::default());
+}
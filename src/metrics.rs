@@ -0,0 +1,110 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::fmt::{self, Write};
+
+/// A point in the InfluxDB line protocol
+///
+/// All tags have to be added before the first field.
+pub struct InfluxLine {
+    line: String,
+    fields: usize,
+}
+
+impl InfluxLine {
+    pub fn new(measurement: &str) -> Self {
+        Self { line: escape(measurement, &[',', ' ']), fields: 0 }
+    }
+
+    pub fn tag<T: fmt::Display>(&mut self, key: &str, value: T) -> &mut Self {
+        debug_assert_eq!(self.fields, 0, "Tags must be added before the fields");
+        let value = value.to_string();
+        write!(self.line, ",{}={}", escape(key, &[',', '=', ' ']), escape(&value, &[',', '=', ' '])).unwrap();
+        self
+    }
+
+    /// Adds an integer field
+    pub fn field(&mut self, key: &str, value: u64) -> &mut Self {
+        let sep = if self.fields == 0 { ' ' } else { ',' };
+        write!(self.line, "{}{}={}i", sep, escape(key, &[',', '=', ' ']), value).unwrap();
+        self.fields += 1;
+        self
+    }
+
+    pub fn build(&self, timestamp_ns: i64) -> String {
+        format!("{} {}", self.line, timestamp_ns)
+    }
+}
+
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The name of this host as reported by the OS
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return "localhost".to_string()
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[test]
+fn influx_line_protocol() {
+    use crate::{net::Socket, types::AddressFamily};
+    use std::{net::UdpSocket, time::Duration};
+
+    /// Splits the line at the spaces that are not escaped
+    fn split(line: &str) -> Vec<String> {
+        let mut parts = vec![String::new()];
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    parts.last_mut().unwrap().push(c);
+                    parts.last_mut().unwrap().extend(chars.next());
+                }
+                ' ' => parts.push(String::new()),
+                _ => parts.last_mut().unwrap().push(c),
+            }
+        }
+        parts
+    }
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    // The stats are sent via the socket of the cloud
+    let mut socket = UdpSocket::listen("127.0.0.1:0", AddressFamily::Ipv4Only).unwrap();
+    let line = InfluxLine::new("vpn cloud")
+        .tag("host", "my host")
+        .tag("port", 3210)
+        .field("bytes_in", 1000)
+        .field("peers", 2)
+        .build(1_600_000_000_000_000_000);
+    Socket::send(&mut socket, line.as_bytes(), server.local_addr().unwrap()).unwrap();
+    let mut data = [0; 1500];
+    let size = server.recv(&mut data).unwrap();
+    let line = std::str::from_utf8(&data[..size]).unwrap();
+    assert_eq!(line, "vpn\\ cloud,host=my\\ host,port=3210 bytes_in=1000i,peers=2i 1600000000000000000");
+    // Measurement with tags, fields and timestamp
+    let parts = split(line);
+    assert_eq!(parts.len(), 3);
+    let tags: Vec<_> = parts[0].split(',').collect();
+    assert_eq!(tags, ["vpn\\ cloud", "host=my\\ host", "port=3210"]);
+    for field in parts[1].split(',') {
+        let (key, value) = field.split_at(field.find('=').unwrap());
+        assert!(!key.is_empty());
+        assert!(value[1..].strip_suffix('i').unwrap().parse::<u64>().is_ok());
+    }
+    assert!(parts[2].parse::<i64>().is_ok());
+    assert!(!hostname().is_empty());
+}
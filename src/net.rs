@@ -5,13 +5,18 @@
 use std::{
     collections::{HashMap, VecDeque},
     io::{self, ErrorKind},
-    net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket},
-    os::unix::io::{AsRawFd, RawFd},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
     sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
 
-use super::util::{MockTimeSource, MsgBuffer, Time, TimeSource};
-use crate::{config::DEFAULT_PORT, port_forwarding::PortForwarding};
+use super::util::{addr_nice, MockTimeSource, MsgBuffer, MsgBufferPool, Time, TimeSource};
+use crate::{
+    cloud::Hash, config::DEFAULT_PORT, netlink::RtnetlinkWatcher, port_forwarding::PortForwarding,
+    receivers::ReceiveThreads, types::AddressFamily,
+};
 
 pub fn mapped_addr(addr: SocketAddr) -> SocketAddr {
     // HOT PATH
@@ -21,18 +26,286 @@ pub fn mapped_addr(addr: SocketAddr) -> SocketAddr {
     }
 }
 
+/// Returns the IPv4 address for IPv4-mapped IPv6 addresses
+pub fn unmapped_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(addr6) => match addr6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), addr6.port()),
+            None => addr,
+        },
+        _ => addr,
+    }
+}
+
+/// Returns the destination address in the form that the socket accepts
+///
+/// IPv4 sockets only accept IPv4 addresses. Linux also accepts them on dual-stack IPv6 sockets, so IPv4-mapped
+/// addresses are passed as IPv4 addresses there without looking up the family of the socket for every packet. Other
+/// systems only accept IPv6 addresses on IPv6 sockets, so IPv4-only sockets are IPv6 sockets there, see `listen_udp`.
+#[inline]
+fn send_addr(addr: SocketAddr) -> SocketAddr {
+    // HOT PATH
+    if cfg!(target_os = "linux") {
+        unmapped_addr(addr)
+    } else {
+        addr
+    }
+}
+
 pub fn get_ip() -> IpAddr {
     let s = UdpSocket::bind("[::]:0").unwrap();
-    s.connect("8.8.8.8:0").unwrap();
+    if s.connect("8.8.8.8:0").is_err() {
+        // Hosts without IPv4 connectivity
+        s.connect("[2001:4860:4860::8888]:0").unwrap();
+    }
     s.local_addr().unwrap().ip()
 }
 
 pub trait Socket: AsRawFd + Sized {
-    fn listen(addr: &str) -> Result<Self, io::Error>;
+    fn listen(addr: &str, family: AddressFamily) -> Result<Self, io::Error>;
     fn receive(&mut self, buffer: &mut MsgBuffer) -> Result<SocketAddr, io::Error>;
     fn send(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize, io::Error>;
     fn address(&self) -> Result<SocketAddr, io::Error>;
     fn create_port_forwarding(&self) -> Option<PortForwarding>;
+    /// Sets the don't fragment flag on all outgoing packets
+    ///
+    /// When disabled, the flag is still set on packets that fit the path MTU as known to the system and larger packets
+    /// are fragmented by the system.
+    fn set_dont_fragment(&mut self, enabled: bool) -> Result<(), io::Error>;
+    /// The MTU of the path to the address as known to the system
+    fn path_mtu(&self, addr: SocketAddr) -> Result<usize, io::Error>;
+    /// Sets the type of service byte on all outgoing packets
+    fn set_tos(&mut self, tos: u8) -> Result<(), io::Error>;
+    /// Sends a packet with the given type of service byte instead of the one set on the socket
+    fn send_with_tos(&mut self, data: &[u8], addr: SocketAddr, tos: u8) -> Result<usize, io::Error>;
+    /// Sets the size of the receive buffer, returns the size that the system actually uses
+    fn set_recv_buffer_size(&mut self, size: usize) -> Result<usize, io::Error>;
+    /// Sets the size of the send buffer, returns the size that the system actually uses
+    fn set_send_buffer_size(&mut self, size: usize) -> Result<usize, io::Error>;
+    /// Like `listen`, but allows `spawn_receivers` to open more sockets on the same port
+    fn listen_shared(addr: &str, family: AddressFamily) -> Result<Self, io::Error> {
+        Self::listen(addr, family)
+    }
+    /// Receives the packets of this socket and of `threads - 1` more sockets on the same port in background threads
+    fn spawn_receivers(&self, _threads: usize, _buffers: &MsgBufferPool) -> Result<ReceiveThreads, io::Error> {
+        Err(io::Error::new(ErrorKind::Unsupported, "Receive threads are not supported by this socket"))
+    }
+    /// Lets the system drop received packets that can not be messages of the protocol
+    fn attach_filter(&mut self) -> Result<(), io::Error> {
+        Err(io::Error::new(ErrorKind::Unsupported, "Packet filters are not supported by this socket"))
+    }
+    /// Watches the addresses of the network interfaces, so that changes of the own addresses are noticed right away
+    fn watch_addresses(&self) -> Result<RtnetlinkWatcher, io::Error> {
+        Err(io::Error::new(ErrorKind::Unsupported, "Address changes can not be watched with this socket"))
+    }
+}
+
+/// Size of the IP and UDP headers in front of each packet
+fn header_len(addr: SocketAddr) -> usize {
+    match addr {
+        SocketAddr::V4(_) => 28,
+        SocketAddr::V6(addr6) if addr6.ip().to_ipv4().is_some() => 28,
+        SocketAddr::V6(_) => 48,
+    }
+}
+
+/// Path MTUs to peers that have been discovered due to `EMSGSIZE` errors
+///
+/// Once a path MTU is known, the don't fragment flag of the socket is disabled, so the system fragments larger packets
+/// at the IP layer instead of dropping them. The system keeps setting the flag on all packets that fit the path MTU it
+/// knows, but it does not report the path MTU of further peers anymore, so the flag is enabled again when no path MTU
+/// is known anymore.
+#[derive(Default)]
+pub struct PathMtuTable {
+    mtus: HashMap<SocketAddr, usize, Hash>,
+    fragmenting: bool,
+}
+
+impl PathMtuTable {
+    #[inline]
+    fn send_once<S: Socket>(
+        socket: &mut S, data: &[u8], addr: SocketAddr, tos: Option<u8>,
+    ) -> Result<usize, io::Error> {
+        match tos {
+            Some(tos) => socket.send_with_tos(data, addr, tos),
+            None => socket.send(data, addr),
+        }
+    }
+
+    #[inline]
+    pub fn send<S: Socket>(
+        &mut self, socket: &mut S, data: &[u8], addr: SocketAddr, tos: Option<u8>,
+    ) -> Result<usize, io::Error> {
+        // HOT PATH
+        match Self::send_once(socket, data, addr, tos) {
+            Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => {
+                // COLD PATH
+                let mtu = socket.path_mtu(addr)?;
+                info!("Discovered path MTU of {} bytes to {}", mtu, addr_nice(addr));
+                self.mtus.insert(addr, mtu);
+                if !self.fragmenting {
+                    socket.set_dont_fragment(false)?;
+                    self.fragmenting = true;
+                }
+                Self::send_once(socket, data, addr, tos)
+            }
+            res => res,
+        }
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<usize> {
+        self.mtus.get(addr).copied()
+    }
+
+    /// The largest UDP payload that can be sent to the address without fragmentation, if known
+    #[inline]
+    pub fn max_payload(&self, addr: &SocketAddr) -> Option<usize> {
+        self.get(addr).map(|mtu| mtu.saturating_sub(header_len(*addr)))
+    }
+
+    pub fn retain<S: Socket, F: FnMut(&SocketAddr) -> bool>(&mut self, socket: &mut S, mut f: F) {
+        self.mtus.retain(|addr, _| f(addr));
+        if self.fragmenting && self.mtus.is_empty() {
+            match socket.set_dont_fragment(true) {
+                Ok(()) => self.fragmenting = false,
+                Err(err) => warn!("Failed to enable path MTU discovery again: {}", err),
+            }
+        }
+    }
+}
+
+fn set_sockopt(fd: RawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> Result<(), io::Error> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn get_sockopt(fd: RawFd, level: libc::c_int, name: libc::c_int) -> Result<libc::c_int, io::Error> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let res =
+        unsafe { libc::getsockopt(fd, level, name, &mut value as *mut libc::c_int as *mut libc::c_void, &mut len) };
+    match res {
+        0 => Ok(value),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Sets the size of a socket buffer and returns the size that the system actually uses, which may be capped
+fn set_buffer_size(fd: RawFd, name: libc::c_int, size: usize) -> Result<usize, io::Error> {
+    set_sockopt(fd, libc::SOL_SOCKET, name, size.min(libc::c_int::MAX as usize) as libc::c_int)?;
+    // The system reports twice the size to account for its bookkeeping overhead
+    Ok(get_sockopt(fd, libc::SOL_SOCKET, name)? as usize / 2)
+}
+
+/// Converts the address to the socket address structure of its family
+fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut sockaddr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = unsafe { &mut *(&mut sockaddr as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_port = addr.port().to_be();
+            sockaddr.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sockaddr = unsafe { &mut *(&mut sockaddr as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_port = addr.port().to_be();
+            sockaddr.sin6_flowinfo = addr.flowinfo();
+            sockaddr.sin6_addr.s6_addr = addr.ip().octets();
+            sockaddr.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (sockaddr, len as libc::socklen_t)
+}
+
+/// Sends a packet with a single integer control message
+fn send_with_cmsg(
+    fd: RawFd, data: &[u8], addr: SocketAddr, level: libc::c_int, name: libc::c_int, value: libc::c_int,
+) -> Result<usize, io::Error> {
+    let (mut sockaddr, sockaddr_len) = to_sockaddr(send_addr(addr));
+    let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() };
+    // Aligned space for the control message header and an integer
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut sockaddr as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = sockaddr_len;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    let res = unsafe {
+        msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = name;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::c_int>() as u32) as _;
+        (libc::CMSG_DATA(cmsg) as *mut libc::c_int).write_unaligned(value);
+        libc::sendmsg(fd, &msg, 0)
+    };
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_pmtu_discover(socket: &UdpSocket, mode: libc::c_int) -> Result<(), io::Error> {
+    // IPv4 packets of dual-stack sockets take the IPv4 option
+    set_sockopt(socket.as_raw_fd(), libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, mode)?;
+    if socket.local_addr()?.is_ipv6() {
+        set_sockopt(socket.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, mode)?;
+    }
+    Ok(())
+}
+
+/// Binds a socket with options that have to be set before binding
+///
+/// With `v6only`, an IPv6 socket only handles IPv6, so that no IPv4 support is needed on the host. With `reuse_port`,
+/// more sockets can be bound to the same port and the system spreads the received packets over them.
+fn bind_socket(addr: SocketAddr, v6only: bool, reuse_port: bool) -> Result<UdpSocket, io::Error> {
+    let domain = if addr.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+    #[cfg(not(target_os = "macos"))]
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    // macOS has no SOCK_CLOEXEC, the flag is set on the created socket instead
+    #[cfg(target_os = "macos")]
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error())
+    }
+    // The socket takes ownership of the file descriptor and closes it on errors
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    #[cfg(target_os = "macos")]
+    {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error())
+        }
+    }
+    if v6only {
+        set_sockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, 1)?;
+    }
+    if reuse_port {
+        set_sockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+    }
+    let (sockaddr, len) = to_sockaddr(addr);
+    let res = unsafe { libc::bind(fd, &sockaddr as *const libc::sockaddr_storage as *const libc::sockaddr, len) };
+    match res {
+        0 => Ok(socket),
+        _ => Err(io::Error::last_os_error()),
+    }
 }
 
 pub fn parse_listen(addr: &str, default_port: u16) -> SocketAddr {
@@ -49,11 +322,69 @@ pub fn parse_listen(addr: &str, default_port: u16) -> SocketAddr {
     }
 }
 
-impl Socket for UdpSocket {
-    fn listen(addr: &str) -> Result<Self, io::Error> {
-        let addr = mapped_addr(parse_listen(addr, DEFAULT_PORT));
+/// Opens the socket of a node, shared with more sockets if the node has several receive threads
+pub fn open_socket<S: Socket>(addr: &str, family: AddressFamily, receive_threads: usize) -> Result<S, io::Error> {
+    if receive_threads > 1 {
+        S::listen_shared(addr, family)
+    } else {
+        S::listen(addr, family)
+    }
+}
+
+fn listen_udp(addr: &str, family: AddressFamily, reuse_port: bool) -> Result<UdpSocket, io::Error> {
+    let mut addr = parse_listen(addr, DEFAULT_PORT);
+    let addr = match family {
+        AddressFamily::DualStack => mapped_addr(addr),
+        AddressFamily::Ipv4Only => {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+            }
+            // Binding to the IPv4-mapped address also only handles IPv4, but needs IPv6 support on the host
+            if cfg!(target_os = "linux") {
+                unmapped_addr(addr)
+            } else {
+                mapped_addr(addr)
+            }
+        }
+        AddressFamily::Ipv6Only if addr.is_ipv4() => {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "IPv6-only operation needs an IPv6 listen address"))
+        }
+        AddressFamily::Ipv6Only => return bind_socket(addr, true, reuse_port),
+    };
+    if reuse_port {
+        bind_socket(addr, false, true)
+    } else {
         UdpSocket::bind(addr)
     }
+}
+
+impl Socket for UdpSocket {
+    fn listen(addr: &str, family: AddressFamily) -> Result<Self, io::Error> {
+        listen_udp(addr, family, false)
+    }
+
+    fn listen_shared(addr: &str, family: AddressFamily) -> Result<Self, io::Error> {
+        listen_udp(addr, family, true)
+    }
+
+    fn spawn_receivers(&self, threads: usize, buffers: &MsgBufferPool) -> Result<ReceiveThreads, io::Error> {
+        let addr = self.local_addr()?;
+        let v6only = addr.is_ipv6() && get_sockopt(self.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_V6ONLY)? != 0;
+        let recv_buffer = get_sockopt(self.as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVBUF)? as usize / 2;
+        let mut sockets = vec![self.try_clone()?];
+        for _ in 1..threads {
+            let socket = bind_socket(addr, v6only, true)?;
+            set_buffer_size(socket.as_raw_fd(), libc::SO_RCVBUF, recv_buffer)?;
+            #[cfg(feature = "ebpf")]
+            crate::ebpf::copy(self.as_raw_fd(), socket.as_raw_fd())?;
+            sockets.push(socket);
+        }
+        for socket in &sockets {
+            // The threads check regularly whether they should stop
+            socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+        }
+        ReceiveThreads::start(addr, sockets, buffers)
+    }
 
     fn receive(&mut self, buffer: &mut MsgBuffer) -> Result<SocketAddr, io::Error> {
         buffer.clear();
@@ -63,7 +394,7 @@ impl Socket for UdpSocket {
     }
 
     fn send(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize, io::Error> {
-        self.send_to(data, addr)
+        self.send_to(data, send_addr(addr))
     }
 
     fn address(&self) -> Result<SocketAddr, io::Error> {
@@ -75,6 +406,71 @@ impl Socket for UdpSocket {
     fn create_port_forwarding(&self) -> Option<PortForwarding> {
         PortForwarding::new(self.address().unwrap().port())
     }
+
+    #[cfg(target_os = "linux")]
+    fn set_dont_fragment(&mut self, enabled: bool) -> Result<(), io::Error> {
+        // Without the option, Linux sets the flag but fragments packets larger than a known path MTU locally
+        set_pmtu_discover(self, if enabled { libc::IP_PMTUDISC_DO } else { libc::IP_PMTUDISC_WANT })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_dont_fragment(&mut self, _enabled: bool) -> Result<(), io::Error> {
+        // The path MTU can only be read on Linux, so other systems leave the fragmentation to the kernel
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn path_mtu(&self, addr: SocketAddr) -> Result<usize, io::Error> {
+        // The system only reports the path MTU on connected sockets
+        let (sock, level, name) = match addr {
+            SocketAddr::V6(addr6) if addr6.ip().to_ipv4().is_none() => {
+                (UdpSocket::bind("[::]:0")?, libc::IPPROTO_IPV6, libc::IPV6_MTU)
+            }
+            _ => (UdpSocket::bind("0.0.0.0:0")?, libc::IPPROTO_IP, libc::IP_MTU),
+        };
+        sock.connect(addr_nice(addr))?;
+        Ok(get_sockopt(sock.as_raw_fd(), level, name)? as usize)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn path_mtu(&self, _addr: SocketAddr) -> Result<usize, io::Error> {
+        Err(io::Error::other("The path MTU can only be read on Linux"))
+    }
+
+    fn set_tos(&mut self, tos: u8) -> Result<(), io::Error> {
+        set_sockopt(self.as_raw_fd(), libc::IPPROTO_IP, libc::IP_TOS, tos as libc::c_int)?;
+        if self.local_addr()?.is_ipv6() {
+            set_sockopt(self.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos as libc::c_int)?;
+        }
+        Ok(())
+    }
+
+    fn send_with_tos(&mut self, data: &[u8], addr: SocketAddr, tos: u8) -> Result<usize, io::Error> {
+        // HOT PATH
+        // IPv4 packets from dual-stack sockets take the IPv4 option
+        let (level, name) = match addr {
+            SocketAddr::V6(addr6) if addr6.ip().to_ipv4().is_none() => (libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+            _ => (libc::IPPROTO_IP, libc::IP_TOS),
+        };
+        send_with_cmsg(self.as_raw_fd(), data, addr, level, name, tos as libc::c_int)
+    }
+
+    fn set_recv_buffer_size(&mut self, size: usize) -> Result<usize, io::Error> {
+        set_buffer_size(self.as_raw_fd(), libc::SO_RCVBUF, size)
+    }
+
+    fn set_send_buffer_size(&mut self, size: usize) -> Result<usize, io::Error> {
+        set_buffer_size(self.as_raw_fd(), libc::SO_SNDBUF, size)
+    }
+
+    #[cfg(feature = "ebpf")]
+    fn attach_filter(&mut self) -> Result<(), io::Error> {
+        crate::ebpf::attach(self.as_raw_fd())
+    }
+
+    fn watch_addresses(&self) -> Result<RtnetlinkWatcher, io::Error> {
+        RtnetlinkWatcher::new()
+    }
 }
 
 thread_local! {
@@ -87,6 +483,10 @@ pub struct MockSocket {
     address: SocketAddr,
     outbound: VecDeque<(SocketAddr, Vec<u8>)>,
     inbound: VecDeque<(SocketAddr, Vec<u8>)>,
+    dont_fragment: bool,
+    path_mtu: Option<usize>,
+    tos: u8,
+    last_tos: u8,
 }
 
 impl MockSocket {
@@ -97,9 +497,28 @@ impl MockSocket {
             address,
             outbound: VecDeque::with_capacity(10),
             inbound: VecDeque::with_capacity(10),
+            dont_fragment: false,
+            path_mtu: None,
+            tos: 0,
+            last_tos: 0,
         }
     }
 
+    /// Simulates a changed address of the node
+    pub fn set_address(&mut self, address: SocketAddr) {
+        self.address = address
+    }
+
+    /// Simulates a path MTU for all destinations
+    pub fn set_path_mtu(&mut self, mtu: Option<usize>) {
+        self.path_mtu = mtu
+    }
+
+    /// The type of service byte of the last sent packet
+    pub fn last_tos(&self) -> u8 {
+        self.last_tos
+    }
+
     pub fn set_nat(nat: bool) {
         MOCK_SOCKET_NAT.with(|t| t.store(nat, Ordering::SeqCst))
     }
@@ -135,7 +554,7 @@ impl AsRawFd for MockSocket {
 }
 
 impl Socket for MockSocket {
-    fn listen(addr: &str) -> Result<Self, io::Error> {
+    fn listen(addr: &str, _family: AddressFamily) -> Result<Self, io::Error> {
         Ok(Self::new(mapped_addr(parse_listen(addr, DEFAULT_PORT))))
     }
 
@@ -151,6 +570,12 @@ impl Socket for MockSocket {
     }
 
     fn send(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize, io::Error> {
+        if let Some(mtu) = self.path_mtu {
+            if self.dont_fragment && data.len() + header_len(addr) > mtu {
+                return Err(io::Error::from_raw_os_error(libc::EMSGSIZE))
+            }
+        }
+        self.last_tos = self.tos;
         self.outbound.push_back((addr, data.into()));
         if self.nat {
             self.nat_peers.insert(addr, MockTimeSource::now() + 300);
@@ -165,6 +590,104 @@ impl Socket for MockSocket {
     fn create_port_forwarding(&self) -> Option<PortForwarding> {
         None
     }
+
+    fn set_dont_fragment(&mut self, enabled: bool) -> Result<(), io::Error> {
+        self.dont_fragment = enabled;
+        Ok(())
+    }
+
+    fn path_mtu(&self, _addr: SocketAddr) -> Result<usize, io::Error> {
+        self.path_mtu.ok_or_else(|| io::Error::from_raw_os_error(libc::ENOTCONN))
+    }
+
+    fn set_tos(&mut self, tos: u8) -> Result<(), io::Error> {
+        self.tos = tos;
+        Ok(())
+    }
+
+    fn send_with_tos(&mut self, data: &[u8], addr: SocketAddr, tos: u8) -> Result<usize, io::Error> {
+        let res = self.send(data, addr);
+        self.last_tos = tos;
+        res
+    }
+
+    fn set_recv_buffer_size(&mut self, size: usize) -> Result<usize, io::Error> {
+        Ok(size)
+    }
+
+    fn set_send_buffer_size(&mut self, size: usize) -> Result<usize, io::Error> {
+        Ok(size)
+    }
+}
+
+#[test]
+fn udp_socket_tos() {
+    let mut socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_tos(0xb8).unwrap();
+    assert_eq!(get_sockopt(socket.as_raw_fd(), libc::IPPROTO_IP, libc::IP_TOS).unwrap(), 0xb8);
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = receiver.local_addr().unwrap();
+    assert_eq!(socket.send_with_tos(&[1, 2, 3], addr, 0x28).unwrap(), 3);
+    let mut buffer = [0; 16];
+    assert_eq!(receiver.recv_from(&mut buffer).unwrap(), (3, socket.local_addr().unwrap()));
+    assert_eq!(&buffer[..3], &[1, 2, 3]);
+}
+
+#[test]
+fn udp_socket_buffer_sizes() {
+    let mut socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    assert_eq!(socket.set_recv_buffer_size(65536).unwrap(), 65536);
+    assert_eq!(socket.set_send_buffer_size(32768).unwrap(), 32768);
+    // Sizes above net.core.rmem_max are capped
+    assert!(socket.set_recv_buffer_size(1 << 30).unwrap() < 1 << 30);
+}
+
+#[test]
+fn udp_receive_threads() {
+    use std::{thread, time::Instant};
+    let socket = UdpSocket::listen_shared("127.0.0.1:0", AddressFamily::DualStack).unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let receivers = socket.spawn_receivers(4, &MsgBufferPool::new(16, 0)).unwrap();
+    // Sockets that do not share the port can not be opened anymore
+    assert!(UdpSocket::listen(&format!("127.0.0.1:{}", port), AddressFamily::DualStack).is_err());
+    for n in 0..16u8 {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&[n], ("127.0.0.1", port)).unwrap();
+    }
+    let mut received = vec![];
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while received.len() < 16 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+        received.extend(receivers.receive().map(|(_, data)| data.message()[0]));
+    }
+    received.sort_unstable();
+    assert_eq!(received, (0..16).collect::<Vec<u8>>());
+}
+
+#[test]
+fn udp_socket_ipv4_only() {
+    let mut socket = UdpSocket::listen_shared("127.0.0.1:0", AddressFamily::Ipv4Only).unwrap();
+    #[cfg(target_os = "linux")]
+    assert!(socket.local_addr().unwrap().is_ipv4());
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    // Peers are known by their IPv4-mapped addresses
+    let peer_addr = mapped_addr(peer.local_addr().unwrap());
+    let own_addr = socket.local_addr().unwrap();
+    let mut buffer = [0; 16];
+    assert_eq!(Socket::send(&mut socket, &[1, 2, 3], peer_addr).unwrap(), 3);
+    assert_eq!(peer.recv_from(&mut buffer).unwrap(), (3, unmapped_addr(own_addr)));
+    assert_eq!(socket.send_with_tos(&[4, 5], peer_addr, 0x28).unwrap(), 2);
+    assert_eq!(peer.recv(&mut buffer).unwrap(), 2);
+    peer.send_to(&[7, 8], own_addr).unwrap();
+    socket.set_dont_fragment(true).unwrap();
+    socket.set_dont_fragment(false).unwrap();
+    let mut msg = MsgBuffer::new(0);
+    assert_eq!(mapped_addr(socket.receive(&mut msg).unwrap()), peer_addr);
+    assert_eq!(msg.message(), &[7, 8]);
+    // More receive threads open IPv4 sockets as well
+    let receivers = socket.spawn_receivers(2, &MsgBufferPool::new(16, 0)).unwrap();
+    drop(receivers);
 }
 
 #[cfg(feature = "bench")]
@@ -0,0 +1,152 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
+use byteorder::{ByteOrder, NativeEndian};
+
+/// Length of `struct nlmsghdr`
+const NLMSG_HDRLEN: usize = 16;
+pub const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+/// Returns whether the netlink messages announce an added or removed interface address
+fn contains_address_change(mut data: &[u8]) -> bool {
+    while data.len() >= NLMSG_HDRLEN {
+        let len = NativeEndian::read_u32(&data[0..4]) as usize;
+        let type_ = NativeEndian::read_u16(&data[4..6]);
+        if type_ == RTM_NEWADDR || type_ == RTM_DELADDR {
+            return true
+        }
+        // Messages are aligned to 4 bytes
+        let len = (len + 3) & !3;
+        if len < NLMSG_HDRLEN || len > data.len() {
+            break
+        }
+        data = &data[len..];
+    }
+    false
+}
+
+/// Detects changed addresses of the network interfaces via RTNETLINK
+///
+/// The socket is non-blocking, so it can be checked in the main loop without a separate thread.
+pub struct RtnetlinkWatcher {
+    socket: File,
+    buffer: Vec<u8>,
+}
+
+impl RtnetlinkWatcher {
+    /// Subscribes to the IPv4 and IPv6 address changes of all interfaces
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn new() -> io::Result<Self> {
+        use std::{mem, os::unix::io::FromRawFd};
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        // The file closes the socket if binding fails
+        let socket = unsafe { File::from_raw_fd(fd) };
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(Self::from_socket(socket))
+    }
+
+    /// Other platforms have no RTNETLINK, the addresses are only refreshed periodically there
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn new() -> io::Result<Self> {
+        Err(io::Error::other("Address changes can only be detected on Linux"))
+    }
+
+    /// Uses a non-blocking socket that delivers netlink messages
+    pub fn from_socket(socket: File) -> Self {
+        Self { socket, buffer: vec![0; 8192] }
+    }
+
+    /// Reads all pending messages and returns whether an interface address changed since the last call
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            match self.socket.read(&mut self.buffer) {
+                Ok(0) => break,
+                Ok(len) => changed |= contains_address_change(&self.buffer[..len]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    // Messages got lost when the buffer of the socket overflowed, so addresses might have changed
+                    warn!("Failed to read address changes: {}", err);
+                    changed = true;
+                    break
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+pub fn netlink_message(type_: u16, payload_len: usize) -> Vec<u8> {
+    let len = NLMSG_HDRLEN + payload_len;
+    let mut data = vec![0; len];
+    NativeEndian::write_u32(&mut data[0..4], len as u32);
+    NativeEndian::write_u16(&mut data[4..6], type_);
+    data
+}
+
+#[cfg(test)]
+pub const RTM_NEWLINK: u16 = 16;
+
+#[test]
+fn address_changes() {
+    assert!(contains_address_change(&netlink_message(RTM_NEWADDR, 8)));
+    assert!(contains_address_change(&netlink_message(RTM_DELADDR, 8)));
+    assert!(!contains_address_change(&netlink_message(RTM_NEWLINK, 8)));
+    assert!(!contains_address_change(&[]));
+    // The address change is the second message, the first one is padded to 4 bytes
+    let mut data = netlink_message(RTM_NEWLINK, 5);
+    data.extend_from_slice(&[0; 3]);
+    data.extend_from_slice(&netlink_message(RTM_NEWADDR, 8));
+    assert!(contains_address_change(&data));
+    // Truncated messages are ignored
+    assert!(!contains_address_change(&netlink_message(RTM_NEWADDR, 8)[..NLMSG_HDRLEN - 1]));
+}
+
+#[test]
+fn watcher_reads_all_messages() {
+    use std::os::unix::{io::FromRawFd, io::IntoRawFd, net::UnixDatagram};
+    let (sender, receiver) = UnixDatagram::pair().unwrap();
+    receiver.set_nonblocking(true).unwrap();
+    let mut watcher = RtnetlinkWatcher::from_socket(unsafe { File::from_raw_fd(receiver.into_raw_fd()) });
+    assert!(!watcher.changed());
+    sender.send(&netlink_message(RTM_NEWLINK, 8)).unwrap();
+    assert!(!watcher.changed());
+    sender.send(&netlink_message(RTM_NEWADDR, 8)).unwrap();
+    sender.send(&netlink_message(RTM_NEWLINK, 8)).unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+}
@@ -0,0 +1,291 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Noise IK handshake as used by WireGuard, so that peers speaking it can connect without the VpnCloud init.
+//
+// The messages are framed like in WireGuard: a message type followed by three zero bytes. The handshake payloads
+// carry the node info of the peers and transport messages carry the nonce in the clear, followed by the ciphertext.
+
+pub const MESSAGE_TYPE_INITIATION: u8 = 1;
+pub const MESSAGE_TYPE_RESPONSE: u8 = 2;
+pub const MESSAGE_TYPE_TRANSPORT: u8 = 4;
+
+const HEADER_LEN: usize = 4;
+const EPHEMERAL_KEY_LEN: usize = 32;
+
+/// Returns whether the message is a handshake initiation, i.e. starts with the bytes `01 00 00 00`
+pub fn is_initiation(msg: &[u8]) -> bool {
+    msg.len() > HEADER_LEN && msg[..HEADER_LEN] == [MESSAGE_TYPE_INITIATION, 0, 0, 0]
+}
+
+/// The ephemeral key of the initiator that starts the handshake initiation, both sides bind their payload to it
+pub fn session_key(msg: &[u8]) -> Option<&[u8]> {
+    msg.get(HEADER_LEN..HEADER_LEN + EPHEMERAL_KEY_LEN)
+}
+
+#[cfg(feature = "noise")]
+mod internal {
+    use super::{HEADER_LEN, MESSAGE_TYPE_RESPONSE, MESSAGE_TYPE_TRANSPORT};
+    use crate::{crypto::Fingerprint, error::Error, identity, util::MsgBuffer};
+    use ring::digest;
+    use snow::{
+        params::NoiseParams,
+        resolvers::{CryptoResolver, DefaultResolver},
+        Builder, StatelessTransportState,
+    };
+    use std::convert::TryInto;
+
+    pub const SUPPORTED: bool = true;
+
+    const PATTERN: &str = "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+    const PROLOGUE: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+    const NONCE_LEN: usize = 8;
+    const TAG_LEN: usize = 16;
+    const REPLAY_WINDOW: u64 = 64;
+
+    fn params() -> NoiseParams {
+        PATTERN.parse().expect("Invalid noise pattern")
+    }
+
+    /// Static X25519 key of this node that answers the handshakes of Noise peers
+    pub struct NoiseHandshake {
+        private_key: Vec<u8>,
+        public_key: Vec<u8>,
+        psk: [u8; 32],
+    }
+
+    impl NoiseHandshake {
+        pub fn new(private_key: &[u8; 32], psk: [u8; 32]) -> Result<Self, Error> {
+            let mut dh = DefaultResolver
+                .resolve_dh(&params().dh)
+                .ok_or(Error::CryptoInitFatal("Failed to create noise keypair"))?;
+            dh.set(private_key);
+            Ok(Self { private_key: private_key.to_vec(), public_key: dh.pubkey().to_vec(), psk })
+        }
+
+        /// Loads the static key from the key file, generating it if the file does not exist
+        ///
+        /// Noise peers need to know the static key in advance, so it has to stay the same across restarts.
+        pub fn load_or_create(path: &str, psk: [u8; 32]) -> Result<Self, Error> {
+            Self::new(&identity::load_or_create_secret(path)?, psk)
+        }
+
+        pub fn public_key(&self) -> &[u8] {
+            &self.public_key
+        }
+
+        /// Processes a handshake initiation and returns the transport state, the payload of the peer and the reply
+        pub fn respond(&self, msg: &[u8], payload: &[u8]) -> Result<(NoiseTransport, Vec<u8>, Vec<u8>), Error> {
+            let mut state = Builder::new(params())
+                .local_private_key(&self.private_key)
+                .psk(2, &self.psk)
+                .prologue(PROLOGUE)
+                .build_responder()
+                .map_err(|_| Error::CryptoInitFatal("Failed to create noise handshake"))?;
+            let mut peer_payload = vec![0; msg.len()];
+            let len = state
+                .read_message(&msg[HEADER_LEN..], &mut peer_payload)
+                .map_err(|_| Error::CryptoInit("Invalid noise handshake"))?;
+            peer_payload.truncate(len);
+            let mut reply = vec![0; HEADER_LEN + payload.len() + 128];
+            reply[0] = MESSAGE_TYPE_RESPONSE;
+            let len = state
+                .write_message(payload, &mut reply[HEADER_LEN..])
+                .map_err(|_| Error::CryptoInitFatal("Failed to create noise handshake reply"))?;
+            reply.truncate(HEADER_LEN + len);
+            let fingerprint = match state.get_remote_static() {
+                Some(key) => digest::digest(&digest::SHA256, key).as_ref().try_into().unwrap(),
+                None => return Err(Error::CryptoInit("Noise peer has no static key")),
+            };
+            let state = state
+                .into_stateless_transport_mode()
+                .map_err(|_| Error::CryptoInitFatal("Noise handshake not finished"))?;
+            Ok((NoiseTransport::new(state, fingerprint), peer_payload, reply))
+        }
+    }
+
+    /// Session with a Noise peer that replaces the crypto core for its messages
+    pub struct NoiseTransport {
+        state: StatelessTransportState,
+        fingerprint: Fingerprint,
+        send_nonce: u64,
+        /// Highest received nonce and bitmap of the nonces received before it
+        max_nonce: u64,
+        seen: u64,
+    }
+
+    impl NoiseTransport {
+        fn new(state: StatelessTransportState, fingerprint: Fingerprint) -> Self {
+            Self { state, fingerprint, send_nonce: 0, max_nonce: 0, seen: 0 }
+        }
+
+        /// SHA-256 hash of the static key of the peer
+        pub fn fingerprint(&self) -> &Fingerprint {
+            &self.fingerprint
+        }
+
+        fn is_replayed(&self, nonce: u64) -> bool {
+            nonce <= self.max_nonce
+                && (self.max_nonce - nonce >= REPLAY_WINDOW || self.seen & (1 << (self.max_nonce - nonce)) != 0)
+        }
+
+        fn mark_seen(&mut self, nonce: u64) {
+            if nonce > self.max_nonce {
+                let shift = nonce - self.max_nonce;
+                self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+                self.max_nonce = nonce;
+            }
+            self.seen |= 1 << (self.max_nonce - nonce)
+        }
+
+        pub fn encrypt(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error> {
+            let nonce = self.send_nonce;
+            self.send_nonce += 1;
+            let mut data = vec![0; buffer.len() + TAG_LEN];
+            let len = self
+                .state
+                .write_message(nonce, buffer.message(), &mut data)
+                .map_err(|_| Error::Crypto("Failed to encrypt data"))?;
+            assert!(buffer.get_start() >= HEADER_LEN + NONCE_LEN);
+            buffer.set_start(buffer.get_start() - HEADER_LEN - NONCE_LEN);
+            buffer.set_length(HEADER_LEN + NONCE_LEN + len);
+            let msg = buffer.message_mut();
+            msg[..HEADER_LEN].copy_from_slice(&[MESSAGE_TYPE_TRANSPORT, 0, 0, 0]);
+            msg[HEADER_LEN..HEADER_LEN + NONCE_LEN].copy_from_slice(&nonce.to_le_bytes());
+            msg[HEADER_LEN + NONCE_LEN..].copy_from_slice(&data[..len]);
+            Ok(())
+        }
+
+        pub fn decrypt(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error> {
+            let msg = buffer.message();
+            if msg.len() < HEADER_LEN + NONCE_LEN + TAG_LEN || msg[..HEADER_LEN] != [MESSAGE_TYPE_TRANSPORT, 0, 0, 0] {
+                return Err(Error::Crypto("Invalid noise message"))
+            }
+            let nonce = u64::from_le_bytes(msg[HEADER_LEN..HEADER_LEN + NONCE_LEN].try_into().unwrap());
+            if self.is_replayed(nonce) {
+                return Err(Error::Crypto("Old nonce rejected"))
+            }
+            let mut data = vec![0; msg.len()];
+            let len = self
+                .state
+                .read_message(nonce, &msg[HEADER_LEN + NONCE_LEN..], &mut data)
+                .map_err(|_| Error::Crypto("Failed to decrypt data"))?;
+            self.mark_seen(nonce);
+            buffer.set_start(buffer.get_start() + HEADER_LEN + NONCE_LEN);
+            buffer.clone_from(&data[..len]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn noise_handshake() {
+        let responder = NoiseHandshake::new(&[5; 32], [7; 32]).unwrap();
+        // Initiator as a WireGuard peer would run it
+        let keypair = Builder::new(params()).generate_keypair().unwrap();
+        let mut initiator = Builder::new(params())
+            .local_private_key(&keypair.private)
+            .remote_public_key(responder.public_key())
+            .psk(2, &[7; 32])
+            .prologue(PROLOGUE)
+            .build_initiator()
+            .unwrap();
+        let mut msg = vec![0; 256];
+        msg[0] = super::MESSAGE_TYPE_INITIATION;
+        let len = initiator.write_message(b"hello", &mut msg[HEADER_LEN..]).unwrap();
+        msg.truncate(HEADER_LEN + len);
+        assert!(super::is_initiation(&msg));
+        let (mut transport, payload, reply) = responder.respond(&msg, b"welcome").unwrap();
+        assert_eq!(payload, b"hello");
+        assert_eq!(&transport.fingerprint()[..], digest::digest(&digest::SHA256, &keypair.public).as_ref());
+        let mut data = vec![0; 256];
+        let len = initiator.read_message(&reply[HEADER_LEN..], &mut data).unwrap();
+        assert_eq!(&data[..len], b"welcome");
+        let initiator = initiator.into_stateless_transport_mode().unwrap();
+
+        let mut buffer = MsgBuffer::new(100);
+        buffer.clone_from(b"data");
+        transport.encrypt(&mut buffer).unwrap();
+        let msg = buffer.message().to_vec();
+        let len = initiator.read_message(0, &msg[HEADER_LEN + NONCE_LEN..], &mut data).unwrap();
+        assert_eq!(&data[..len], b"data");
+
+        let mut msg = vec![MESSAGE_TYPE_TRANSPORT, 0, 0, 0];
+        msg.extend_from_slice(&5u64.to_le_bytes());
+        let mut ciphertext = vec![0; 256];
+        let len = initiator.write_message(5, b"reply", &mut ciphertext).unwrap();
+        msg.extend_from_slice(&ciphertext[..len]);
+        buffer.clear();
+        buffer.clone_from(&msg);
+        transport.decrypt(&mut buffer).unwrap();
+        assert_eq!(buffer.message(), b"reply");
+        // Replayed messages are rejected
+        buffer.clear();
+        buffer.clone_from(&msg);
+        assert!(transport.decrypt(&mut buffer).is_err());
+        // The responder can not detect a wrong PSK, only the initiator fails to read the reply
+        let mut initiator = Builder::new(params())
+            .local_private_key(&keypair.private)
+            .remote_public_key(responder.public_key())
+            .psk(2, &[8; 32])
+            .prologue(PROLOGUE)
+            .build_initiator()
+            .unwrap();
+        let mut msg = vec![0; 256];
+        msg[0] = super::MESSAGE_TYPE_INITIATION;
+        let len = initiator.write_message(b"hello", &mut msg[HEADER_LEN..]).unwrap();
+        msg.truncate(HEADER_LEN + len);
+        let (_, _, reply) = responder.respond(&msg, b"welcome").unwrap();
+        assert!(initiator.read_message(&reply[HEADER_LEN..], &mut data).is_err());
+    }
+
+    #[test]
+    fn noise_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("noise.key");
+        let path = path.to_str().unwrap();
+        let handshake = NoiseHandshake::load_or_create(path, [7; 32]).unwrap();
+        assert_eq!(NoiseHandshake::load_or_create(path, [7; 32]).unwrap().public_key(), handshake.public_key());
+    }
+}
+
+#[cfg(not(feature = "noise"))]
+mod internal {
+    use crate::{crypto::Fingerprint, error::Error, util::MsgBuffer};
+
+    pub const SUPPORTED: bool = false;
+
+    pub struct NoiseHandshake;
+
+    impl NoiseHandshake {
+        pub fn load_or_create(_path: &str, _psk: [u8; 32]) -> Result<Self, Error> {
+            Err(Error::CryptoInitFatal("Noise protocol is not supported by this build"))
+        }
+
+        pub fn public_key(&self) -> &[u8] {
+            unreachable!("Noise protocol is not supported by this build")
+        }
+
+        pub fn respond(&self, _msg: &[u8], _payload: &[u8]) -> Result<(NoiseTransport, Vec<u8>, Vec<u8>), Error> {
+            Err(Error::CryptoInitFatal("Noise protocol is not supported by this build"))
+        }
+    }
+
+    pub struct NoiseTransport;
+
+    impl NoiseTransport {
+        pub fn fingerprint(&self) -> &Fingerprint {
+            unreachable!("Noise protocol is not supported by this build")
+        }
+
+        pub fn encrypt(&mut self, _buffer: &mut MsgBuffer) -> Result<(), Error> {
+            Err(Error::Crypto("Noise protocol is not supported by this build"))
+        }
+
+        pub fn decrypt(&mut self, _buffer: &mut MsgBuffer) -> Result<(), Error> {
+            Err(Error::Crypto("Noise protocol is not supported by this build"))
+        }
+    }
+}
+
+pub use internal::*;
@@ -90,39 +90,124 @@ impl OldConfigFile {
                 load: self.beacon_load,
                 store: self.beacon_store,
                 password: self.shared_key.clone(),
+                encrypt: None,
+                allow_plaintext: None,
+                dns_domain: None,
+                dns_key_file: None,
             }),
             claims: self.subnets,
+            excluded_routes: None,
+            policy_rules: None,
+            policy_tables: None,
+            acl: None,
             crypto: CryptoConfig {
                 algorithms: vec![],
                 password: Some(self.shared_key.unwrap_or_else(|| "none".to_string())),
                 private_key: None,
                 public_key: None,
                 trusted_keys: vec![],
+                pq_kem: false,
+                ca_cert: None,
+                node_cert: None,
+                node_key: None,
+                peer_keys: vec![],
+                argon2_memory_kib: None,
+                argon2_iterations: None,
             },
             device: Some(ConfigFileDevice {
                 fix_rp_filter: None,
+                mtu: None,
+                persistent: None,
+                mac: None,
+                mac_from_node_id: None,
                 name: self.device_name,
                 path: self.device_path,
                 type_: self.device_type,
             }),
             group: self.group,
             ifdown: self.ifdown,
+            sync_routes: None,
+            local_tunnel_ip: None,
+            sync_routes_dry_run: None,
+            node_id: None,
             ifup: self.ifup,
             ip: None,
             advertise_addresses: None,
             keepalive: self.keepalive,
+            max_peers: None,
+            max_reconnect_peers: None,
+            reconnect_jitter: None,
+            dpd_probe_interval: None,
+            dpd_retries: None,
+            nat_keepalive: None,
+            gossip_fanout: None,
+            gossip_interval: None,
+            gossip_full_interval_secs: None,
+            latency_routing: None,
             listen: self.listen.or(self.port.map(|p| format!("{}", p))),
             mode: self.mode,
+            address_family: None,
             peer_timeout: self.peer_timeout,
             peers: self.peers,
+            bootstrap_dns: None,
             pid_file: self.pid_file,
+            table_persistence_path: None,
             port_forwarding: self.port_forwarding,
+            pmtu_discovery: None,
+            send_pacing_kbps: None,
+            send_queue_max_bytes: None,
+            reorder_buffer: None,
+            reorder_timeout_ms: None,
+            reorder_buffer_max_packets: None,
+            fragment_timeout_ms: None,
+            fragment_max_buffers: None,
+            init_timeout_ms: None,
+            init_retries: None,
+            hole_punch: None,
+            turn_servers: None,
             stats_file: self.stats_file,
+            audit_log: None,
+            audit_log_max_bytes: None,
+            peer_event_webhook: None,
+            webhook_secret: None,
+            tofu_store: None,
+            tofu_mode: None,
+            peer_state_file: None,
+            identity_key: None,
+            noise_protocol: None,
+            noise_key: None,
+            vxlan_vni: None,
+            require_encryption: None,
+            plaintext_peers: None,
+            cni_ipam_file: None,
+            stats_format: None,
+            traffic_stats_addresses: None,
             statsd: Some(ConfigFileStatsd { prefix: self.statsd_prefix, server: self.statsd_server }),
+            influx: None,
+            snmp: None,
+            capture: None,
+            otel_endpoint: None,
+            api: None,
             switch_timeout: self.dst_timeout,
+            igmp_timeout: None,
+            arp_proxy: None,
+            arp_proxy_ttl_secs: None,
+            flap_window_secs: None,
+            max_flaps: None,
+            max_table_entries: None,
+            buffer_pool_size: None,
+            receive_threads: None,
+            socket_recv_buffer_bytes: None,
+            socket_send_buffer_bytes: None,
+            local_shortcut: None,
+            pad_to: None,
+            pad_amount: None,
+            dscp: None,
+            dscp_inherit: None,
             user: self.user,
             hook: None,
             hooks: HashMap::new(),
+            local_tags: HashMap::new(),
         }
     }
 }
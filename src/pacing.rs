@@ -0,0 +1,170 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    cmp::{max, min},
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::cloud::Hash;
+
+/// Burst allowance for peers without a measured round trip time
+const DEFAULT_BURST: Duration = Duration::from_millis(5);
+/// Upper bound of the burst allowance, so that peers far away can not fill the uplink buffer
+const MAX_BURST: Duration = Duration::from_millis(100);
+
+/// Timeout of the run loop while pacing, deferred messages are sent with this granularity
+pub const PACING_POLL_TIMEOUT: u32 = 5;
+
+/// Paces the messages to every peer to a fixed rate
+///
+/// Every peer has a token bucket that is refilled at the configured rate. The bucket holds as many bytes as can be
+/// sent in one round trip time to the peer, so a burst never exceeds what the path holds anyway. Messages that find
+/// the bucket empty are deferred to a queue per peer that is drained by the run loop.
+pub struct Pacer {
+    /// Bytes per second to every peer
+    rate: u64,
+    /// The time at which the bucket of the peer is full again
+    next_send: HashMap<SocketAddr, Instant, Hash>,
+    queues: HashMap<SocketAddr, VecDeque<Vec<u8>>, Hash>,
+    queued_bytes: usize,
+    max_queued_bytes: usize,
+}
+
+impl Pacer {
+    pub fn new(rate_kbps: u32, max_queued_bytes: usize) -> Self {
+        Self {
+            rate: max(u64::from(rate_kbps) * 1000 / 8, 1),
+            next_send: HashMap::default(),
+            queues: HashMap::default(),
+            queued_bytes: 0,
+            max_queued_bytes,
+        }
+    }
+
+    fn burst(rtt: Option<u64>) -> Duration {
+        min(rtt.map(Duration::from_millis).unwrap_or(DEFAULT_BURST), MAX_BURST)
+    }
+
+    /// Takes the message from the bucket of the peer if it is not empty
+    fn take(&mut self, addr: SocketAddr, bytes: usize, rtt: Option<u64>, now: Instant) -> bool {
+        let send_time = Duration::from_nanos(bytes as u64 * 1_000_000_000 / self.rate);
+        let next_send = self.next_send.entry(addr).or_insert(now);
+        if *next_send > now + Self::burst(rtt) {
+            return false
+        }
+        *next_send = max(*next_send, now) + send_time;
+        true
+    }
+
+    /// Defers the message if it can not be sent to the peer now, returns whether it has been deferred
+    ///
+    /// Messages are also deferred while older messages to the peer are waiting, to keep their order. When the queued
+    /// messages exceed the maximum size, the oldest messages to the peer are dropped.
+    #[inline]
+    pub fn defer(&mut self, addr: SocketAddr, data: &[u8], rtt: Option<u64>, now: Instant) -> bool {
+        // HOT PATH
+        if !self.queues.contains_key(&addr) && self.take(addr, data.len(), rtt, now) {
+            return false
+        }
+        // COLD PATH
+        let queue = self.queues.entry(addr).or_default();
+        queue.push_back(data.to_vec());
+        self.queued_bytes += data.len();
+        // The other queues fit into the limit before, so dropping from this queue is always enough
+        while self.queued_bytes > self.max_queued_bytes {
+            match queue.pop_front() {
+                Some(dropped) => {
+                    debug!("Dropping deferred message to {}, send queue is full", addr);
+                    self.queued_bytes -= dropped.len()
+                }
+                None => break,
+            }
+        }
+        if queue.is_empty() {
+            self.queues.remove(&addr);
+        }
+        true
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Removes the deferred messages that can be sent now
+    pub fn pop_ready<F: Fn(&SocketAddr) -> Option<u64>>(&mut self, now: Instant, rtt: F) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut ready = vec![];
+        let addrs: Vec<_> = self.queues.keys().copied().collect();
+        for addr in addrs {
+            let rtt = rtt(&addr);
+            while let Some(len) = self.queues.get(&addr).and_then(|q| q.front()).map(Vec::len) {
+                if !self.take(addr, len, rtt, now) {
+                    break
+                }
+                let data = self.queues.get_mut(&addr).and_then(VecDeque::pop_front).unwrap();
+                self.queued_bytes -= data.len();
+                ready.push((addr, data));
+            }
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+        ready
+    }
+
+    /// Forgets the peers whose buckets are full again
+    pub fn housekeep(&mut self, now: Instant) {
+        let queues = &self.queues;
+        self.next_send.retain(|addr, next_send| *next_send > now || queues.contains_key(addr));
+    }
+}
+
+#[test]
+fn pacing_rate() {
+    // 1 MB/s, so 1000 bytes take 1 ms
+    let mut pacer = Pacer::new(8000, 10_000);
+    let addr = "1.2.3.4:3210".parse().unwrap();
+    let other = "5.6.7.8:3210".parse().unwrap();
+    let now = Instant::now();
+    // Bursts of one round trip time are allowed
+    for _ in 0..11 {
+        assert!(!pacer.defer(addr, &[0; 1000], Some(10), now));
+    }
+    assert!(pacer.defer(addr, &[1; 1000], Some(10), now));
+    assert!(pacer.defer(addr, &[2; 1000], Some(10), now));
+    // Other peers are paced individually
+    assert!(!pacer.defer(other, &[0; 1000], None, now));
+    assert!(pacer.pop_ready(now, |_| Some(10)).is_empty());
+    let ready = pacer.pop_ready(now + Duration::from_millis(1), |_| Some(10));
+    assert_eq!(ready, [(addr, vec![1; 1000])]);
+    assert!(!pacer.is_empty());
+    let ready = pacer.pop_ready(now + Duration::from_millis(2), |_| Some(10));
+    assert_eq!(ready, [(addr, vec![2; 1000])]);
+    assert!(pacer.is_empty());
+    pacer.housekeep(now + Duration::from_millis(100));
+    assert!(pacer.next_send.is_empty());
+}
+
+#[test]
+fn pacing_queue_limit() {
+    let mut pacer = Pacer::new(8, 2500);
+    let addr1 = "1.2.3.4:3210".parse().unwrap();
+    let addr2 = "5.6.7.8:3210".parse().unwrap();
+    let now = Instant::now();
+    assert!(!pacer.defer(addr1, &[0; 1000], None, now));
+    assert!(!pacer.defer(addr2, &[0; 1000], None, now));
+    assert!(pacer.defer(addr2, &[1; 1000], None, now));
+    for i in 1..4 {
+        assert!(pacer.defer(addr1, &[i; 1000], None, now));
+    }
+    // The oldest message to the peer is dropped
+    assert_eq!(pacer.queued_bytes, 2000);
+    assert_eq!(pacer.queues[&addr1], [vec![3; 1000]]);
+    assert_eq!(pacer.queues[&addr2], [vec![1; 1000]]);
+    // Messages larger than the limit are dropped right away
+    assert!(pacer.defer(addr2, &[0; 3000], None, now));
+    assert_eq!(pacer.queued_bytes, 1000);
+    assert!(!pacer.queues.contains_key(&addr2));
+}
@@ -7,6 +7,8 @@ use std::io::{Cursor, Read};
 
 pub trait Protocol: Sized {
     fn parse(_: &[u8]) -> Result<(Address, Address), Error>;
+    /// The DSCP bits of the traffic class of the contained IP packet, if any
+    fn dscp_tos(_: &[u8]) -> Option<u8>;
 }
 
 /// An ethernet frame dissector
@@ -50,6 +52,18 @@ impl Protocol for Frame {
             Ok((Address { data: src, len: 6 }, Address { data: dst, len: 6 }))
         }
     }
+
+    fn dscp_tos(data: &[u8]) -> Option<u8> {
+        // HOT PATH
+        let mut offset = 12;
+        if data.get(offset..offset + 2)? == [0x81, 0x00] {
+            offset += 4;
+        }
+        match data.get(offset..offset + 2)? {
+            [0x08, 0x00] | [0x86, 0xdd] => Packet::dscp_tos(&data[offset + 2..]),
+            _ => None,
+        }
+    }
 }
 
 #[test]
@@ -115,6 +129,18 @@ impl Protocol for Packet {
             _ => Err(Error::Parse("Invalid IP protocol version")),
         }
     }
+
+    fn dscp_tos(data: &[u8]) -> Option<u8> {
+        // HOT PATH
+        let first = *data.first()?;
+        let tos = match first >> 4 {
+            4 => *data.get(1)?,
+            6 => first << 4 | data.get(1)? >> 4,
+            _ => return None,
+        };
+        // The ECN bits are left to the outer packet
+        Some(tos & 0xfc)
+    }
 }
 
 #[test]
@@ -157,3 +183,16 @@ fn decode_invalid_packet() {
     ])
     .is_err());
 }
+
+#[test]
+fn dscp_of_packets() {
+    assert_eq!(Packet::dscp_tos(&[0x45, 0xb9, 0, 0]), Some(0xb8));
+    assert_eq!(Packet::dscp_tos(&[0x6b, 0x80, 0, 0]), Some(0xb8));
+    assert_eq!(Packet::dscp_tos(&[0x20, 0xb8]), None);
+    assert_eq!(Packet::dscp_tos(&[]), None);
+    let frame = [6, 5, 4, 3, 2, 1, 1, 2, 3, 4, 5, 6, 0x08, 0x00, 0x45, 0x28, 0, 0];
+    assert_eq!(Frame::dscp_tos(&frame), Some(0x28));
+    let vlan_frame = [6, 5, 4, 3, 2, 1, 1, 2, 3, 4, 5, 6, 0x81, 0, 4, 210, 0x86, 0xdd, 0x60, 0xa0, 0, 0];
+    assert_eq!(Frame::dscp_tos(&vlan_frame), Some(0x08));
+    assert_eq!(Frame::dscp_tos(&[6, 5, 4, 3, 2, 1, 1, 2, 3, 4, 5, 6, 0x08, 0x06, 0x45, 0x28]), None);
+}
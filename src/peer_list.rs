@@ -0,0 +1,90 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    net::SocketAddr,
+};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Version of the file format, incremented on incompatible changes
+const VERSION: u8 = 1;
+/// Files larger than this are rejected instead of allocating memory for them
+const MAX_SIZE: u64 = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedPeer {
+    /// The address the peer was connected on, followed by the addresses it advertised
+    pub addrs: Vec<SocketAddr>,
+}
+
+/// The peers of a node, saved on shutdown so that the node can reconnect to them right after a restart
+///
+/// The peers are only known to be reachable at these addresses, so they need a new handshake after loading.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PeerList {
+    pub peers: Vec<SavedPeer>,
+}
+
+impl PeerList {
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::options().with_limit(MAX_SIZE).serialize(&(VERSION, &self.peers)).expect("Peer list too large")
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<PeerList, Error> {
+        let (version, peers): (u8, Vec<SavedPeer>) = bincode::options()
+            .with_limit(MAX_SIZE)
+            .deserialize(data)
+            .map_err(|_| Error::Parse("Failed to parse peer list"))?;
+        if version != VERSION {
+            return Err(Error::Parse("Unsupported peer list version"))
+        }
+        Ok(PeerList { peers })
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let data =
+            fs::read(path).map_err(|e| Error::FileIo { message: "Failed to read peer state file", source: e })?;
+        Self::deserialize(&data)
+    }
+
+    /// Writes the list to a temporary file that replaces the old one, so the file is never half written
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        self.write(path).map_err(|e| Error::FileIo { message: "Failed to write peer state file", source: e })
+    }
+
+    fn write(&self, path: &str) -> Result<(), io::Error> {
+        let tmp_path = format!("{}.tmp", path);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&self.serialize())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+#[test]
+fn peer_list_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("peers.state");
+    let path = path.to_str().unwrap();
+    let list = PeerList {
+        peers: vec![
+            SavedPeer { addrs: vec!["1.2.3.4:3210".parse().unwrap(), "[2001:db8::1]:3210".parse().unwrap()] },
+            SavedPeer { addrs: vec!["5.6.7.8:3211".parse().unwrap()] },
+        ],
+    };
+    assert_eq!(PeerList::deserialize(&list.serialize()).unwrap(), list);
+    list.save(path).unwrap();
+    assert_eq!(PeerList::load(path).unwrap(), list);
+    assert!(!dir.path().join("peers.state.tmp").exists());
+    let mut data = list.serialize();
+    data[0] = VERSION + 1;
+    assert!(PeerList::deserialize(&data).is_err());
+    assert!(PeerList::deserialize(&data[1..5]).is_err());
+}
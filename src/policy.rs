@@ -0,0 +1,105 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{collections::HashMap, str::FromStr};
+
+use super::{
+    config::{PolicyRuleConfig, PolicyTableConfig},
+    error::Error,
+    types::{Address, Range},
+};
+
+/// The table holding the claims of all peers
+pub const MAIN_TABLE: u8 = 0;
+
+/// Selects the routing table for packets matching the source and destination prefixes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolicyRule {
+    pub src_prefix: Option<Range>,
+    pub dst_prefix: Option<Range>,
+    pub table_id: u8,
+}
+
+impl PolicyRule {
+    #[inline]
+    fn matches(&self, src: Address, dst: Address) -> bool {
+        self.src_prefix.map(|r| r.matches(src)).unwrap_or(true)
+            && self.dst_prefix.map(|r| r.matches(dst)).unwrap_or(true)
+    }
+}
+
+/// Ordered policy rules and the gateways of the tables they select
+///
+/// All tables besides the main table route their packets via a gateway address that is looked up
+/// in the claims of the peers. The last rule always matches all packets and selects the main table.
+pub struct PolicyTable {
+    rules: Vec<PolicyRule>,
+    gateways: HashMap<u8, Address>,
+}
+
+impl PolicyTable {
+    pub fn new(mut rules: Vec<PolicyRule>, gateways: HashMap<u8, Address>) -> Result<Self, Error> {
+        if rules.iter().any(|r| r.table_id != MAIN_TABLE && !gateways.contains_key(&r.table_id)) {
+            return Err(Error::InvalidConfig("Policy rule refers to a table without gateway"))
+        }
+        rules.push(PolicyRule { src_prefix: None, dst_prefix: None, table_id: MAIN_TABLE });
+        Ok(Self { rules, gateways })
+    }
+
+    pub fn from_config(rules: &[PolicyRuleConfig], tables: &[PolicyTableConfig]) -> Result<Self, Error> {
+        let parse_range = |s: &Option<String>| s.as_ref().map(|s| Range::from_str(s)).transpose();
+        let mut policy_rules = Vec::with_capacity(rules.len());
+        for rule in rules {
+            policy_rules.push(PolicyRule {
+                src_prefix: parse_range(&rule.from)?,
+                dst_prefix: parse_range(&rule.to)?,
+                table_id: rule.table,
+            })
+        }
+        let mut gateways = HashMap::with_capacity(tables.len());
+        for table in tables {
+            if table.id == MAIN_TABLE {
+                return Err(Error::InvalidConfig("The main table can not have a gateway"))
+            }
+            gateways.insert(table.id, Address::from_str(&table.via)?);
+        }
+        Self::new(policy_rules, gateways)
+    }
+
+    /// Returns the address to look up in the claims in order to route a packet
+    ///
+    /// This is the destination itself for the main table and the gateway of the table otherwise.
+    #[inline]
+    pub fn lookup(&self, src: Address, dst: Address) -> Address {
+        // HOT PATH
+        for rule in &self.rules {
+            if rule.matches(src, dst) {
+                return self.gateways.get(&rule.table_id).copied().unwrap_or(dst)
+            }
+        }
+        dst
+    }
+}
+
+#[test]
+fn policy_lookup() {
+    let addr = |s: &str| Address::from_str(s).unwrap();
+    let policy = PolicyTable::from_config(
+        &[
+            PolicyRuleConfig { from: Some("10.1.0.0/16".to_string()), to: Some("10.3.0.0/16".to_string()), table: 2 },
+            PolicyRuleConfig { from: Some("10.1.0.0/16".to_string()), to: None, table: 1 },
+            PolicyRuleConfig { from: None, to: Some("10.4.0.0/16".to_string()), table: 2 },
+        ],
+        &[
+            PolicyTableConfig { id: 1, via: "10.0.0.1".to_string() },
+            PolicyTableConfig { id: 2, via: "10.0.0.2".to_string() },
+        ],
+    )
+    .unwrap();
+    assert_eq!(policy.lookup(addr("10.1.2.3"), addr("10.3.0.1")), addr("10.0.0.2"));
+    assert_eq!(policy.lookup(addr("10.1.2.3"), addr("10.5.0.1")), addr("10.0.0.1"));
+    assert_eq!(policy.lookup(addr("10.2.2.3"), addr("10.4.0.1")), addr("10.0.0.2"));
+    assert_eq!(policy.lookup(addr("10.2.2.3"), addr("10.5.0.1")), addr("10.5.0.1"));
+    assert!(PolicyTable::from_config(&[PolicyRuleConfig { from: None, to: None, table: 3 }], &[]).is_err());
+}
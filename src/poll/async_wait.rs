@@ -0,0 +1,89 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{io, os::unix::io::RawFd, time::Duration};
+
+use tokio::{
+    io::{unix::AsyncFd, Interest},
+    time::{interval, Interval, MissedTickBehavior},
+};
+
+use super::WaitResult;
+
+/// Waits for the socket and the device on a tokio runtime
+///
+/// The runtime only reports new data once, but the event handlers only read one packet per event. So the readiness is
+/// only cleared when the file descriptor has nothing left to read.
+pub struct AsyncWait {
+    socket: AsyncFd<RawFd>,
+    device: AsyncFd<RawFd>,
+    timeout: Interval,
+}
+
+impl AsyncWait {
+    /// Registers the file descriptors with the runtime of the current task
+    pub fn new(socket: RawFd, device: RawFd, timeout: u32) -> io::Result<Self> {
+        let mut timeout = interval(Duration::from_millis(u64::from(timeout)));
+        timeout.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Self {
+            socket: AsyncFd::with_interest(socket, Interest::READABLE)?,
+            device: AsyncFd::with_interest(device, Interest::READABLE)?,
+            timeout,
+        })
+    }
+
+    pub async fn wait(&mut self) -> WaitResult {
+        loop {
+            tokio::select! {
+                res = self.socket.readable() => match res {
+                    Ok(mut guard) if !has_data(*guard.get_inner()) => guard.clear_ready(),
+                    Ok(_) => return WaitResult::Socket,
+                    Err(err) => return WaitResult::Error(err),
+                },
+                res = self.device.readable() => match res {
+                    Ok(mut guard) if !has_data(*guard.get_inner()) => guard.clear_ready(),
+                    Ok(_) => return WaitResult::Device,
+                    Err(err) => return WaitResult::Error(err),
+                },
+                _ = self.timeout.tick() => return WaitResult::Timeout,
+            }
+        }
+    }
+}
+
+fn has_data(fd: RawFd) -> bool {
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    unsafe { libc::poll(&mut pollfd, 1, 0) > 0 }
+}
+
+#[test]
+fn async_wait_events() {
+    use std::{net::UdpSocket, os::unix::io::AsRawFd};
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let mut waiter = AsyncWait::new(socket.as_raw_fd(), device.as_raw_fd(), 100).unwrap();
+        // The first tick of the interval is immediate
+        assert!(matches!(waiter.wait().await, WaitResult::Timeout));
+        sender.send_to(b"1", socket.local_addr().unwrap()).unwrap();
+        sender.send_to(b"2", socket.local_addr().unwrap()).unwrap();
+        sender.send_to(b"3", device.local_addr().unwrap()).unwrap();
+        // Every packet is reported although it is read only after the event
+        let mut buffer = [0; 10];
+        let mut received = vec![];
+        for _ in 0..3 {
+            let size = match waiter.wait().await {
+                WaitResult::Socket => socket.recv(&mut buffer).unwrap(),
+                WaitResult::Device => device.recv(&mut buffer).unwrap(),
+                _ => panic!("No event"),
+            };
+            received.extend_from_slice(&buffer[..size]);
+        }
+        received.sort_unstable();
+        assert_eq!(received, b"123");
+        assert!(matches!(waiter.wait().await, WaitResult::Timeout));
+    });
+}
@@ -0,0 +1,69 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{io, mem, os::unix::io::RawFd, ptr};
+
+use super::WaitResult;
+
+pub struct KqueueWait {
+    poll_fd: RawFd,
+    event: libc::kevent,
+    socket: RawFd,
+    device: RawFd,
+    timeout: libc::timespec,
+}
+
+impl KqueueWait {
+    pub fn new(socket: RawFd, device: RawFd, timeout: u32) -> io::Result<Self> {
+        let poll_fd = unsafe { libc::kqueue() };
+        if poll_fd == -1 {
+            return Err(io::Error::last_os_error())
+        }
+        // The layout of the struct differs between FreeBSD versions, so only the common fields are set
+        let mut event: libc::kevent = unsafe { mem::zeroed() };
+        for fd in &[socket, device] {
+            event.ident = *fd as libc::uintptr_t;
+            event.filter = libc::EVFILT_READ;
+            event.flags = libc::EV_ADD;
+            let res = unsafe { libc::kevent(poll_fd, &event, 1, ptr::null_mut(), 0, ptr::null()) };
+            if res == -1 {
+                let err = io::Error::last_os_error();
+                unsafe { libc::close(poll_fd) };
+                return Err(err)
+            }
+        }
+        let timeout = libc::timespec {
+            tv_sec: (timeout / 1000) as libc::time_t,
+            tv_nsec: ((timeout % 1000) * 1_000_000) as libc::c_long,
+        };
+        Ok(Self { poll_fd, event, socket, device, timeout })
+    }
+}
+
+impl Drop for KqueueWait {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.poll_fd) };
+    }
+}
+
+impl Iterator for KqueueWait {
+    type Item = WaitResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match unsafe { libc::kevent(self.poll_fd, ptr::null(), 0, &mut self.event, 1, &self.timeout) } {
+            -1 => WaitResult::Error(io::Error::last_os_error()),
+            0 => WaitResult::Timeout,
+            1 => {
+                if self.event.ident == self.socket as libc::uintptr_t {
+                    WaitResult::Socket
+                } else if self.event.ident == self.device as libc::uintptr_t {
+                    WaitResult::Device
+                } else {
+                    unreachable!()
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+}
@@ -5,9 +5,21 @@
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod epoll;
 
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+mod kqueue;
+
+#[cfg(feature = "async")]
+mod async_wait;
+
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub use self::epoll::EpollWait as WaitImpl;
 
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+pub use self::kqueue::KqueueWait as WaitImpl;
+
+#[cfg(feature = "async")]
+pub use self::async_wait::AsyncWait;
+
 use std::io;
 
 pub enum WaitResult {
@@ -0,0 +1,171 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Receiving of packets in multiple threads
+//
+// With more than one receive thread, the node opens that many sockets on its port with `SO_REUSEPORT`, so the system
+// spreads the received packets over them by their addresses. Each socket is read by its own thread, which hands the
+// packets to the node over a channel. The packets are still decrypted and handled by the node, as the state of the
+// peers is not shared between threads.
+//
+// Instead of the socket, the node waits for a wakeup stream. A thread only writes to it when the node has not been
+// woken up since it last took the packets from the channel, so the stream holds at most a few bytes.
+//
+// Nodes in the same process can pass their messages into the channel directly, see `local_bus`.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::SocketAddr,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+
+use crate::{
+    net::Socket,
+    util::{MsgBufferPool, PooledBuffer},
+};
+
+/// Number of received packets that can wait for the node, further packets are dropped
+const QUEUE_SIZE: usize = 1024;
+/// Number of packets that the node handles before it handles other events again
+const BATCH_SIZE: usize = 64;
+
+struct Wakeup {
+    stream: UnixStream,
+    notified: AtomicBool,
+    stopped: AtomicBool,
+}
+
+impl Wakeup {
+    fn notify(&self) {
+        if !self.notified.swap(true, Ordering::AcqRel) {
+            // The stream only fills up if the node does not run anymore
+            let _ = (&self.stream).write(&[0]);
+        }
+    }
+}
+
+fn receive_loop<S: Socket>(
+    mut socket: S, sender: Sender<(SocketAddr, PooledBuffer)>, buffers: MsgBufferPool, wakeup: Arc<Wakeup>,
+) {
+    while !wakeup.stopped.load(Ordering::Relaxed) {
+        // HOT PATH
+        let mut buffer = buffers.acquire();
+        match socket.receive(&mut buffer) {
+            Ok(src) => {
+                if sender.try_send((src, buffer)).is_err() {
+                    // COLD PATH
+                    debug!("Receive queue is full, dropping packet from {}", src);
+                }
+                wakeup.notify()
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(err) => fail!("Failed to read from network socket: {}", err),
+        }
+    }
+}
+
+/// Threads that receive packets from sockets on the same port
+pub struct ReceiveThreads {
+    sender: Sender<(SocketAddr, PooledBuffer)>,
+    receiver: Receiver<(SocketAddr, PooledBuffer)>,
+    wakeup: Arc<Wakeup>,
+    stream: UnixStream,
+    addr: SocketAddr,
+}
+
+impl ReceiveThreads {
+    /// Starts one thread for each of the sockets, that are bound to the given address
+    pub fn start<S: Socket + Send + 'static>(
+        addr: SocketAddr, sockets: Vec<S>, buffers: &MsgBufferPool,
+    ) -> Result<Self, io::Error> {
+        let (stream, remote) = UnixStream::pair()?;
+        stream.set_nonblocking(true)?;
+        remote.set_nonblocking(true)?;
+        let wakeup =
+            Arc::new(Wakeup { stream: remote, notified: AtomicBool::new(false), stopped: AtomicBool::new(false) });
+        let (sender, receiver) = bounded(QUEUE_SIZE);
+        let threads = sockets.len();
+        for socket in sockets {
+            let sender = sender.clone();
+            let buffers = buffers.clone();
+            let wakeup = wakeup.clone();
+            thread::spawn(move || receive_loop(socket, sender, buffers, wakeup));
+        }
+        info!("Receiving packets in {} threads", threads);
+        Ok(Self { sender, receiver, wakeup, stream, addr })
+    }
+
+    /// Takes the next batch of received packets, must be called when the wakeup stream is readable
+    pub fn receive(&self) -> impl Iterator<Item = (SocketAddr, PooledBuffer)> + '_ {
+        let mut data = [0; 16];
+        while matches!((&self.stream).read(&mut data), Ok(n) if n > 0) {}
+        // Packets that arrive from now on wake up the node again
+        self.wakeup.notified.store(false, Ordering::Release);
+        self.receiver.try_iter().take(BATCH_SIZE)
+    }
+
+    /// Wakes up the node again if it left packets in the channel
+    pub fn finish_batch(&self) {
+        if !self.receiver.is_empty() {
+            self.wakeup.notify()
+        }
+    }
+
+    /// Returns a handle to pass packets to the node as if they had been received by the threads
+    pub fn injector(&self) -> Injector {
+        Injector { sender: self.sender.clone(), wakeup: self.wakeup.clone(), addr: self.addr }
+    }
+}
+
+/// Passes packets to a node that receives in threads
+#[derive(Clone)]
+pub struct Injector {
+    sender: Sender<(SocketAddr, PooledBuffer)>,
+    wakeup: Arc<Wakeup>,
+    addr: SocketAddr,
+}
+
+impl Injector {
+    /// The address that the sockets of the node are bound to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Passes the packet to the node, returns false if the node does not receive anymore
+    pub fn inject(&self, src: SocketAddr, buffer: PooledBuffer) -> bool {
+        // HOT PATH
+        match self.sender.try_send((src, buffer)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                // COLD PATH
+                debug!("Receive queue is full, dropping packet from {}", src)
+            }
+            Err(TrySendError::Disconnected(_)) => return false,
+        }
+        self.wakeup.notify();
+        true
+    }
+}
+
+impl AsRawFd for ReceiveThreads {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl Drop for ReceiveThreads {
+    fn drop(&mut self) {
+        self.wakeup.stopped.store(true, Ordering::Relaxed)
+    }
+}
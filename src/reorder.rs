@@ -0,0 +1,178 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    time::{Duration, Instant},
+};
+
+struct Held<T> {
+    seq: u64,
+    received: Instant,
+    item: Option<T>,
+}
+
+impl<T> PartialEq for Held<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.seq == other.seq
+    }
+}
+
+impl<T> Eq for Held<T> {}
+
+impl<T> PartialOrd for Held<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Held<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.seq.cmp(&other.seq)
+    }
+}
+
+/// Holds back the messages of a peer that arrive ahead of their sequence number, to deliver them in order
+///
+/// Messages that do not need to be delivered in order are still held as empty entries, so that their sequence numbers
+/// do not appear as gaps. A held message is released when the gap before it is filled, when it has been held for the
+/// timeout or when the buffer is full, the missing messages are then considered lost.
+pub struct ReorderBuffer<T> {
+    next_seq: Option<u64>,
+    held: BinaryHeap<Reverse<Held<T>>>,
+    timeout: Duration,
+    max_packets: usize,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new(timeout: Duration, max_packets: usize) -> Self {
+        Self { next_seq: None, held: BinaryHeap::with_capacity(max_packets), timeout, max_packets }
+    }
+
+    /// Checks whether the message with the given sequence number can be delivered right away
+    ///
+    /// Otherwise it must be given to `hold`.
+    #[inline]
+    pub fn accept(&mut self, seq: u64) -> bool {
+        // HOT PATH
+        match self.next_seq {
+            Some(next) if seq == next => {
+                self.next_seq = Some(next + 1);
+                true
+            }
+            // Messages that are late have been given up on already
+            Some(next) if seq < next && seq >> 56 == next >> 56 => true,
+            Some(next) if seq > next && seq >> 56 == next >> 56 => false,
+            // The first message or a new key, the sequence starts here
+            _ => {
+                self.next_seq = Some(seq + 1);
+                true
+            }
+        }
+    }
+
+    /// Holds the message back until it can be delivered in order, returns whether the buffer overflowed
+    pub fn hold(&mut self, seq: u64, item: Option<T>, now: Instant) -> bool {
+        let overflow = self.held.len() >= self.max_packets;
+        if overflow {
+            self.skip_gap();
+        }
+        self.held.push(Reverse(Held { seq, received: now, item }));
+        overflow
+    }
+
+    /// Gives up on the missing messages before the first held message once it has been held for the timeout
+    ///
+    /// Returns whether messages have been given up on.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        match self.held.peek() {
+            Some(Reverse(first)) if first.received + self.timeout <= now => {
+                self.skip_gap();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn skip_gap(&mut self) {
+        if let (Some(Reverse(first)), Some(next)) = (self.held.peek(), self.next_seq) {
+            if first.seq > next {
+                self.next_seq = Some(first.seq)
+            }
+        }
+    }
+
+    /// Releases the next held message that is in order now
+    pub fn pop(&mut self) -> Option<T> {
+        while let (Some(Reverse(first)), Some(next)) = (self.held.peek(), self.next_seq) {
+            if first.seq > next {
+                return None
+            }
+            let Reverse(held) = self.held.pop().unwrap();
+            if held.seq == next {
+                self.next_seq = Some(next + 1)
+            }
+            if held.item.is_some() {
+                return held.item
+            }
+        }
+        None
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+}
+
+#[test]
+fn reorder_in_order() {
+    let mut buffer = ReorderBuffer::new(Duration::from_millis(50), 4);
+    let now = Instant::now();
+    assert!(buffer.accept(10));
+    assert!(!buffer.accept(12));
+    assert!(!buffer.hold(12, Some(12), now));
+    assert!(!buffer.accept(13));
+    // A message that does not need to be delivered in order
+    assert!(!buffer.hold(13, None, now));
+    assert!(!buffer.accept(14));
+    assert!(!buffer.hold(14, Some(14), now));
+    assert_eq!(buffer.pop(), None);
+    assert!(buffer.accept(11));
+    assert_eq!(buffer.pop(), Some(12));
+    assert_eq!(buffer.pop(), Some(14));
+    assert_eq!(buffer.pop(), None);
+    assert!(buffer.is_empty());
+    assert!(buffer.accept(15));
+    // Messages with a new key start a new sequence
+    assert!(buffer.accept((1 << 56) + 5));
+    assert!(buffer.accept((1 << 56) + 6));
+}
+
+#[test]
+fn reorder_timeout_and_overflow() {
+    let mut buffer = ReorderBuffer::new(Duration::from_millis(50), 2);
+    let now = Instant::now();
+    assert!(buffer.accept(1));
+    assert!(!buffer.hold(3, Some(3), now));
+    assert!(!buffer.expire(now + Duration::from_millis(49)));
+    assert_eq!(buffer.pop(), None);
+    // Message 2 is considered lost after the timeout
+    assert!(buffer.expire(now + Duration::from_millis(50)));
+    assert_eq!(buffer.pop(), Some(3));
+    assert!(!buffer.expire(now + Duration::from_millis(50)));
+    // Message 2 arriving late is delivered right away
+    assert!(buffer.accept(2));
+    assert!(!buffer.hold(6, Some(6), now));
+    assert!(!buffer.hold(5, Some(5), now));
+    // The buffer is full, so the gap before the first held message is skipped
+    assert!(buffer.hold(8, Some(8), now));
+    assert_eq!(buffer.pop(), Some(5));
+    assert_eq!(buffer.pop(), Some(6));
+    assert_eq!(buffer.pop(), None);
+    assert!(buffer.accept(7));
+    assert_eq!(buffer.pop(), Some(8));
+    assert!(buffer.is_empty());
+}
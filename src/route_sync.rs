@@ -0,0 +1,160 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    process::Command,
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{table::ClaimEvent, types::Range};
+
+/// Time in which claim changes are collected before the routes are changed
+const BATCH_WINDOW: Duration = Duration::from_millis(100);
+
+/// Mirrors the claims of the peers into the routing table of the OS
+///
+/// Routes are added when the first peer claims a range and removed when the last claim of the range is gone. Changes
+/// are batched, so claims that are removed and added again within the batch window do not touch the routing table.
+pub struct RouteSync {
+    ifname: String,
+    gateway: Option<IpAddr>,
+    dry_run: bool,
+    /// Number of claims of each range
+    claims: HashMap<Range, usize>,
+}
+
+impl RouteSync {
+    pub fn new(ifname: &str, gateway: Option<IpAddr>, dry_run: bool) -> Self {
+        Self { ifname: ifname.to_string(), gateway, dry_run, claims: HashMap::new() }
+    }
+
+    /// Processes the events in a thread that ends when the claim table is dropped
+    pub fn start(self, events: Receiver<ClaimEvent>) {
+        let res = thread::Builder::new().name("route-sync".to_string()).spawn(move || self.run(events));
+        if let Err(err) = res {
+            error!("Failed to start route sync: {}", err)
+        }
+    }
+
+    fn run(mut self, events: Receiver<ClaimEvent>) {
+        while let Ok(event) = events.recv() {
+            let mut batch = vec![event];
+            let deadline = Instant::now() + BATCH_WINDOW;
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break
+                }
+                match events.recv_timeout(deadline - now) {
+                    Ok(event) => batch.push(event),
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            for cmd in self.apply(batch) {
+                self.run_command(&cmd)
+            }
+        }
+    }
+
+    /// Updates the claim counts and returns the commands for the ranges whose routes changed
+    fn apply(&mut self, events: Vec<ClaimEvent>) -> Vec<Vec<String>> {
+        // Ranges in order of their first change with whether they had a route before
+        let mut changed: Vec<(Range, bool)> = vec![];
+        for event in events {
+            let (range, added) = match event {
+                ClaimEvent::Added(range) => (range, true),
+                ClaimEvent::Removed(range) => (range, false),
+            };
+            if range.base.len != 4 && range.base.len != 16 {
+                // Only IP ranges can be routed
+                continue
+            }
+            let count = self.claims.entry(range).or_insert(0);
+            if !changed.iter().any(|(r, _)| *r == range) {
+                changed.push((range, *count > 0));
+            }
+            if added {
+                *count += 1
+            } else {
+                *count = count.saturating_sub(1)
+            }
+        }
+        let mut cmds = vec![];
+        for (range, had_route) in changed {
+            let has_route = self.claims[&range] > 0;
+            if !has_route {
+                self.claims.remove(&range);
+            }
+            match (had_route, has_route) {
+                (false, true) => cmds.push(self.command("add", range)),
+                (true, false) => cmds.push(self.command("del", range)),
+                _ => (),
+            }
+        }
+        cmds
+    }
+
+    fn command(&self, action: &str, range: Range) -> Vec<String> {
+        let mut cmd = vec!["ip".to_string(), "route".to_string(), action.to_string(), range.to_string()];
+        if let Some(gateway) = self.gateway {
+            cmd.push("via".to_string());
+            cmd.push(gateway.to_string());
+        }
+        cmd.push("dev".to_string());
+        cmd.push(self.ifname.clone());
+        cmd
+    }
+
+    fn run_command(&self, cmd: &[String]) {
+        if self.dry_run {
+            info!("Route sync (dry run): {}", cmd.join(" "));
+            return
+        }
+        debug!("Route sync: {}", cmd.join(" "));
+        match Command::new(&cmd[0]).args(&cmd[1..]).output() {
+            Ok(output) if output.status.success() => (),
+            Ok(output) => {
+                error!("Command {} failed: {}", cmd.join(" "), String::from_utf8_lossy(&output.stderr).trim())
+            }
+            Err(err) => error!("Failed to run {}: {}", cmd.join(" "), err),
+        }
+    }
+}
+
+#[test]
+fn route_sync_batches() {
+    use crate::{table::ClaimTable, util::MockTimeSource};
+    use smallvec::smallvec;
+    use std::{net::SocketAddr, str::FromStr};
+    let peer1 = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let peer2 = SocketAddr::from_str("1.2.3.5:3210").unwrap();
+    let range1 = Range::from_str("10.1.0.0/16").unwrap();
+    let range2 = Range::from_str("10.2.0.0/16").unwrap();
+    MockTimeSource::set_time(1000);
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_claims(peer1, smallvec![range1]);
+    let events = table.subscribe();
+    table.set_claims(peer2, smallvec![range1, range2, Range::from_str("02:00:00:00:00:01/48").unwrap()]);
+    table.remove_claims(peer2);
+    let mut sync = RouteSync::new("vpncloud0", None, true);
+    // The route to range2 was added and removed within the batch
+    assert_eq!(
+        sync.apply(events.try_iter().collect()),
+        vec![vec!["ip", "route", "add", "10.1.0.0/16", "dev", "vpncloud0"]]
+    );
+    table.remove_claims(peer1);
+    assert_eq!(
+        sync.apply(events.try_iter().collect()),
+        vec![vec!["ip", "route", "del", "10.1.0.0/16", "dev", "vpncloud0"]]
+    );
+    let sync = RouteSync::new("vpncloud0", Some(IpAddr::from([10, 0, 0, 1])), true);
+    assert_eq!(
+        sync.command("add", range2),
+        vec!["ip", "route", "add", "10.2.0.0/16", "via", "10.0.0.1", "dev", "vpncloud0"]
+    );
+}
@@ -0,0 +1,569 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// SNMPv2c agent for the objects of mib/VPNCLOUD-MIB.txt
+//
+// Only GET and GETNEXT requests are answered and only the small subset of BER that SNMPv2c needs is implemented.
+
+/// Values of the scalar objects of the MIB
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnmpStats {
+    pub peers: u32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub lost_packets: u64,
+    pub active_tunnels: u32,
+}
+
+#[cfg(feature = "snmp")]
+mod internal {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        sync::{Arc, Mutex},
+        thread,
+        time::Instant,
+    };
+
+    use super::SnmpStats;
+    use crate::{
+        error::Error,
+        types::NodeId,
+        util::{addr_nice, bytes_to_hex},
+    };
+
+    /// vpncloudObjects of the VPNCLOUD-MIB
+    pub const VPNCLOUD_OBJECTS: [u32; 7] = [1, 3, 6, 1, 3, 3210, 1];
+    /// vpncloudNotifications of the VPNCLOUD-MIB
+    const VPNCLOUD_NOTIFICATIONS: [u32; 7] = [1, 3, 6, 1, 3, 3210, 2];
+    const SYS_UP_TIME: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 3, 0];
+    const SNMP_TRAP_OID: [u32; 11] = [1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
+    pub const OBJECT_PEER_COUNT: u32 = 1;
+    pub const OBJECT_BYTES_IN: u32 = 2;
+    pub const OBJECT_BYTES_OUT: u32 = 3;
+    pub const OBJECT_PACKETS_IN: u32 = 4;
+    pub const OBJECT_PACKETS_OUT: u32 = 5;
+    pub const OBJECT_LOST_PACKETS: u32 = 6;
+    pub const OBJECT_ACTIVE_TUNNELS: u32 = 7;
+    const OBJECT_PEER_ADDRESS: u32 = 8;
+    const OBJECT_PEER_NODE_ID: u32 = 9;
+    const NOTIFICATION_PEER_CONNECTED: u32 = 1;
+    const NOTIFICATION_PEER_DISCONNECTED: u32 = 2;
+
+    /// SNMPv2c
+    const VERSION: i64 = 1;
+    pub const PDU_GET: u8 = 0xa0;
+    pub const PDU_GET_NEXT: u8 = 0xa1;
+    pub const PDU_RESPONSE: u8 = 0xa2;
+    pub const PDU_TRAP: u8 = 0xa7;
+
+    const TAG_INTEGER: u8 = 0x02;
+    const TAG_OCTET_STRING: u8 = 0x04;
+    const TAG_NULL: u8 = 0x05;
+    const TAG_OID: u8 = 0x06;
+    const TAG_SEQUENCE: u8 = 0x30;
+    const TAG_GAUGE32: u8 = 0x42;
+    const TAG_TIME_TICKS: u8 = 0x43;
+    const TAG_COUNTER64: u8 = 0x46;
+    const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+    const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+    const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+    pub type Oid = Vec<u32>;
+
+    /// The instance of a scalar object
+    pub fn scalar(object: u32) -> Oid {
+        let mut oid = VPNCLOUD_OBJECTS.to_vec();
+        oid.extend_from_slice(&[object, 0]);
+        oid
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Value {
+        Integer(i64),
+        OctetString(Vec<u8>),
+        Null,
+        Oid(Oid),
+        Gauge32(u32),
+        TimeTicks(u32),
+        Counter64(u64),
+        NoSuchObject,
+        NoSuchInstance,
+        EndOfMibView,
+    }
+
+    /// An SNMPv2c message with one PDU
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Message {
+        pub community: Vec<u8>,
+        pub pdu_type: u8,
+        pub request_id: i32,
+        pub error_status: i32,
+        pub error_index: i32,
+        pub varbinds: Vec<(Oid, Value)>,
+    }
+
+    fn encode_tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+        out.push(tag);
+        let len = content.len();
+        if len < 0x80 {
+            out.push(len as u8);
+        } else {
+            let bytes = (len as u32).to_be_bytes();
+            let skip = bytes.iter().take_while(|b| **b == 0).count();
+            out.push(0x80 | (4 - skip) as u8);
+            out.extend_from_slice(&bytes[skip..]);
+        }
+        out.extend_from_slice(content);
+    }
+
+    fn encode_int(value: i64) -> Vec<u8> {
+        let bytes = value.to_be_bytes();
+        // Strip the leading bytes that only repeat the sign
+        let mut start = 0;
+        while start < 7
+            && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+                || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+        {
+            start += 1;
+        }
+        bytes[start..].to_vec()
+    }
+
+    fn encode_uint(value: u64) -> Vec<u8> {
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&value.to_be_bytes());
+        let start = bytes.windows(2).take_while(|w| w[0] == 0 && w[1] & 0x80 == 0).count();
+        bytes.split_off(start)
+    }
+
+    fn encode_oid(oid: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![(oid[0] * 40 + oid[1]) as u8];
+        for &id in &oid[2..] {
+            let mut shift = 28;
+            while shift > 0 && id >> shift == 0 {
+                shift -= 7;
+            }
+            while shift > 0 {
+                bytes.push(0x80 | (id >> shift) as u8 & 0x7f);
+                shift -= 7;
+            }
+            bytes.push(id as u8 & 0x7f);
+        }
+        bytes
+    }
+
+    impl Value {
+        fn encode(&self, out: &mut Vec<u8>) {
+            match self {
+                Value::Integer(val) => encode_tlv(out, TAG_INTEGER, &encode_int(*val)),
+                Value::OctetString(val) => encode_tlv(out, TAG_OCTET_STRING, val),
+                Value::Null => encode_tlv(out, TAG_NULL, &[]),
+                Value::Oid(val) => encode_tlv(out, TAG_OID, &encode_oid(val)),
+                Value::Gauge32(val) => encode_tlv(out, TAG_GAUGE32, &encode_uint(u64::from(*val))),
+                Value::TimeTicks(val) => encode_tlv(out, TAG_TIME_TICKS, &encode_uint(u64::from(*val))),
+                Value::Counter64(val) => encode_tlv(out, TAG_COUNTER64, &encode_uint(*val)),
+                Value::NoSuchObject => encode_tlv(out, TAG_NO_SUCH_OBJECT, &[]),
+                Value::NoSuchInstance => encode_tlv(out, TAG_NO_SUCH_INSTANCE, &[]),
+                Value::EndOfMibView => encode_tlv(out, TAG_END_OF_MIB_VIEW, &[]),
+            }
+        }
+
+        fn decode(tag: u8, data: &[u8]) -> Result<Self, Error> {
+            Ok(match tag {
+                TAG_INTEGER => Value::Integer(decode_int(data)?),
+                TAG_OCTET_STRING => Value::OctetString(data.to_vec()),
+                TAG_NULL => Value::Null,
+                TAG_OID => Value::Oid(decode_oid(data)?),
+                TAG_GAUGE32 => Value::Gauge32(decode_uint(data, 4)? as u32),
+                TAG_TIME_TICKS => Value::TimeTicks(decode_uint(data, 4)? as u32),
+                TAG_COUNTER64 => Value::Counter64(decode_uint(data, 8)?),
+                TAG_NO_SUCH_OBJECT => Value::NoSuchObject,
+                TAG_NO_SUCH_INSTANCE => Value::NoSuchInstance,
+                TAG_END_OF_MIB_VIEW => Value::EndOfMibView,
+                _ => return Err(Error::Parse("Unsupported SNMP value type")),
+            })
+        }
+    }
+
+    struct Reader<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        fn read(&mut self) -> Result<(u8, &'a [u8]), Error> {
+            let truncated = Error::Parse("Truncated SNMP message");
+            if self.data.len() < 2 {
+                return Err(truncated)
+            }
+            let (tag, first, mut rest) = (self.data[0], self.data[1], &self.data[2..]);
+            let len = if first & 0x80 == 0 {
+                first as usize
+            } else {
+                let size = (first & 0x7f) as usize;
+                if size == 0 || size > 4 || rest.len() < size {
+                    return Err(Error::Parse("Invalid length in SNMP message"))
+                }
+                let len = rest[..size].iter().fold(0, |len, b| len << 8 | *b as usize);
+                rest = &rest[size..];
+                len
+            };
+            if rest.len() < len {
+                return Err(truncated)
+            }
+            self.data = &rest[len..];
+            Ok((tag, &rest[..len]))
+        }
+
+        fn expect(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+            match self.read()? {
+                (found, data) if found == tag => Ok(data),
+                _ => Err(Error::Parse("Unexpected type in SNMP message")),
+            }
+        }
+
+        fn read_int(&mut self) -> Result<i64, Error> {
+            decode_int(self.expect(TAG_INTEGER)?)
+        }
+    }
+
+    fn decode_int(data: &[u8]) -> Result<i64, Error> {
+        if data.is_empty() || data.len() > 8 {
+            return Err(Error::Parse("Invalid integer in SNMP message"))
+        }
+        let init = if data[0] & 0x80 != 0 { -1 } else { 0 };
+        Ok(data.iter().fold(init, |val, b| val << 8 | i64::from(*b)))
+    }
+
+    fn decode_uint(data: &[u8], max_bytes: usize) -> Result<u64, Error> {
+        let data = if data.len() > 1 && data[0] == 0 { &data[1..] } else { data };
+        if data.is_empty() || data.len() > max_bytes {
+            return Err(Error::Parse("Invalid integer in SNMP message"))
+        }
+        Ok(data.iter().fold(0, |val, b| val << 8 | u64::from(*b)))
+    }
+
+    fn decode_oid(data: &[u8]) -> Result<Oid, Error> {
+        let (first, rest) = data.split_first().ok_or(Error::Parse("Empty OID in SNMP message"))?;
+        let mut oid = if *first < 80 {
+            vec![u32::from(*first / 40), u32::from(*first % 40)]
+        } else {
+            vec![2, u32::from(*first - 80)]
+        };
+        let mut id: u32 = 0;
+        for b in rest {
+            if id > u32::MAX >> 7 {
+                return Err(Error::Parse("Invalid OID in SNMP message"))
+            }
+            id = id << 7 | u32::from(b & 0x7f);
+            if b & 0x80 == 0 {
+                oid.push(id);
+                id = 0;
+            }
+        }
+        if rest.last().map(|b| b & 0x80 != 0).unwrap_or(false) {
+            return Err(Error::Parse("Invalid OID in SNMP message"))
+        }
+        Ok(oid)
+    }
+
+    impl Message {
+        pub fn encode(&self) -> Vec<u8> {
+            let mut varbinds = vec![];
+            for (oid, value) in &self.varbinds {
+                let mut varbind = vec![];
+                encode_tlv(&mut varbind, TAG_OID, &encode_oid(oid));
+                value.encode(&mut varbind);
+                encode_tlv(&mut varbinds, TAG_SEQUENCE, &varbind);
+            }
+            let mut pdu = vec![];
+            encode_tlv(&mut pdu, TAG_INTEGER, &encode_int(i64::from(self.request_id)));
+            encode_tlv(&mut pdu, TAG_INTEGER, &encode_int(i64::from(self.error_status)));
+            encode_tlv(&mut pdu, TAG_INTEGER, &encode_int(i64::from(self.error_index)));
+            encode_tlv(&mut pdu, TAG_SEQUENCE, &varbinds);
+            let mut message = vec![];
+            encode_tlv(&mut message, TAG_INTEGER, &encode_int(VERSION));
+            encode_tlv(&mut message, TAG_OCTET_STRING, &self.community);
+            encode_tlv(&mut message, self.pdu_type, &pdu);
+            let mut out = vec![];
+            encode_tlv(&mut out, TAG_SEQUENCE, &message);
+            out
+        }
+
+        pub fn decode(data: &[u8]) -> Result<Self, Error> {
+            let mut message = Reader { data: Reader { data }.expect(TAG_SEQUENCE)? };
+            if message.read_int()? != VERSION {
+                return Err(Error::Parse("Unsupported SNMP version"))
+            }
+            let community = message.expect(TAG_OCTET_STRING)?.to_vec();
+            let (pdu_type, pdu) = message.read()?;
+            let mut pdu = Reader { data: pdu };
+            let request_id = pdu.read_int()? as i32;
+            let error_status = pdu.read_int()? as i32;
+            let error_index = pdu.read_int()? as i32;
+            let mut list = Reader { data: pdu.expect(TAG_SEQUENCE)? };
+            let mut varbinds = vec![];
+            while !list.data.is_empty() {
+                let mut varbind = Reader { data: list.expect(TAG_SEQUENCE)? };
+                let oid = decode_oid(varbind.expect(TAG_OID)?)?;
+                let (tag, value) = varbind.read()?;
+                varbinds.push((oid, Value::decode(tag, value)?));
+            }
+            Ok(Self { community, pdu_type, request_id, error_status, error_index, varbinds })
+        }
+    }
+
+    /// The instances of all objects in lexicographic order
+    fn objects(stats: &SnmpStats) -> [(Oid, Value); 7] {
+        [
+            (scalar(OBJECT_PEER_COUNT), Value::Gauge32(stats.peers)),
+            (scalar(OBJECT_BYTES_IN), Value::Counter64(stats.bytes_in)),
+            (scalar(OBJECT_BYTES_OUT), Value::Counter64(stats.bytes_out)),
+            (scalar(OBJECT_PACKETS_IN), Value::Counter64(stats.packets_in)),
+            (scalar(OBJECT_PACKETS_OUT), Value::Counter64(stats.packets_out)),
+            (scalar(OBJECT_LOST_PACKETS), Value::Counter64(stats.lost_packets)),
+            (scalar(OBJECT_ACTIVE_TUNNELS), Value::Gauge32(stats.active_tunnels)),
+        ]
+    }
+
+    fn get(objects: &[(Oid, Value)], oid: &[u32]) -> Value {
+        if let Some((_, value)) = objects.iter().find(|(instance, _)| instance == oid) {
+            return value.clone()
+        }
+        // Known object but not the .0 instance
+        if objects.iter().any(|(instance, _)| oid.starts_with(&instance[..instance.len() - 1])) {
+            Value::NoSuchInstance
+        } else {
+            Value::NoSuchObject
+        }
+    }
+
+    /// Answers a GET or GETNEXT request, other requests are ignored
+    fn respond(stats: &SnmpStats, request: Message) -> Option<Message> {
+        let objects = objects(stats);
+        let varbinds = match request.pdu_type {
+            PDU_GET => request
+                .varbinds
+                .into_iter()
+                .map(|(oid, _)| {
+                    let value = get(&objects, &oid);
+                    (oid, value)
+                })
+                .collect(),
+            PDU_GET_NEXT => request
+                .varbinds
+                .into_iter()
+                .map(|(oid, _)| match objects.iter().find(|(instance, _)| *instance > oid) {
+                    Some((instance, value)) => (instance.clone(), value.clone()),
+                    None => (oid, Value::EndOfMibView),
+                })
+                .collect(),
+            _ => return None,
+        };
+        Some(Message { pdu_type: PDU_RESPONSE, error_status: 0, error_index: 0, varbinds, ..request })
+    }
+
+    /// Answers SNMP requests from a background thread and sends traps on peer events
+    ///
+    /// The main loop updates the statistics periodically, so requests never wait for it.
+    pub struct SnmpAgent {
+        stats: Arc<Mutex<SnmpStats>>,
+        community: String,
+        traps: Option<(UdpSocket, SocketAddr)>,
+        trap_id: i32,
+        started: Instant,
+    }
+
+    impl SnmpAgent {
+        pub fn start(
+            addr: Option<SocketAddr>, community: String, trap_receiver: Option<SocketAddr>,
+        ) -> Result<Self, Error> {
+            let stats = Arc::new(Mutex::new(SnmpStats::default()));
+            if let Some(addr) = addr {
+                let socket = UdpSocket::bind(addr)
+                    .map_err(|e| Error::SocketIo { message: "Failed to bind SNMP socket", source: e })?;
+                let community = community.clone();
+                let stats = stats.clone();
+                thread::Builder::new()
+                    .name("snmp".to_string())
+                    .spawn(move || Self::run(socket, community, stats))
+                    .map_err(|e| Error::SocketIo { message: "Failed to start SNMP thread", source: e })?;
+                info!("Serving SNMP on {}", addr);
+            }
+            let traps = match trap_receiver {
+                Some(receiver) => {
+                    let bind = if receiver.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                    let socket = UdpSocket::bind(bind)
+                        .map_err(|e| Error::SocketIo { message: "Failed to bind SNMP trap socket", source: e })?;
+                    Some((socket, receiver))
+                }
+                None => None,
+            };
+            Ok(Self { stats, community, traps, trap_id: 0, started: Instant::now() })
+        }
+
+        fn run(socket: UdpSocket, community: String, stats: Arc<Mutex<SnmpStats>>) {
+            let mut buffer = [0; 4096];
+            loop {
+                let (size, addr) = match socket.recv_from(&mut buffer) {
+                    Ok(res) => res,
+                    Err(err) => return error!("Failed to receive SNMP request: {}", err),
+                };
+                let request = match Message::decode(&buffer[..size]) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        debug!("Invalid SNMP request from {}: {}", addr_nice(addr), err);
+                        continue
+                    }
+                };
+                if request.community != community.as_bytes() {
+                    debug!("Ignoring SNMP request with wrong community from {}", addr_nice(addr));
+                    continue
+                }
+                let stats = stats.lock().expect("SNMP stats lock poisoned").clone();
+                if let Some(response) = respond(&stats, request) {
+                    if let Err(err) = socket.send_to(&response.encode(), addr) {
+                        warn!("Failed to send SNMP response to {}: {}", addr_nice(addr), err)
+                    }
+                }
+            }
+        }
+
+        pub fn update(&self, stats: SnmpStats) {
+            *self.stats.lock().expect("SNMP stats lock poisoned") = stats;
+        }
+
+        pub fn peer_connected(&mut self, addr: SocketAddr, node_id: &NodeId) {
+            self.send_trap(NOTIFICATION_PEER_CONNECTED, addr, node_id)
+        }
+
+        pub fn peer_disconnected(&mut self, addr: SocketAddr, node_id: &NodeId) {
+            self.send_trap(NOTIFICATION_PEER_DISCONNECTED, addr, node_id)
+        }
+
+        fn send_trap(&mut self, notification: u32, addr: SocketAddr, node_id: &NodeId) {
+            if let Some((ref socket, receiver)) = self.traps {
+                let mut trap_oid = VPNCLOUD_NOTIFICATIONS.to_vec();
+                trap_oid.push(notification);
+                self.trap_id = self.trap_id.wrapping_add(1);
+                let uptime = self.started.elapsed().as_millis() / 10;
+                let trap = Message {
+                    community: self.community.as_bytes().to_vec(),
+                    pdu_type: PDU_TRAP,
+                    request_id: self.trap_id,
+                    error_status: 0,
+                    error_index: 0,
+                    varbinds: vec![
+                        (SYS_UP_TIME.to_vec(), Value::TimeTicks(uptime as u32)),
+                        (SNMP_TRAP_OID.to_vec(), Value::Oid(trap_oid)),
+                        (scalar(OBJECT_PEER_ADDRESS), Value::OctetString(addr_nice(addr).to_string().into_bytes())),
+                        (scalar(OBJECT_PEER_NODE_ID), Value::OctetString(bytes_to_hex(node_id).into_bytes())),
+                    ],
+                };
+                if let Err(err) = socket.send_to(&trap.encode(), receiver) {
+                    warn!("Failed to send SNMP trap to {}: {}", receiver, err)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn snmp_encoding() {
+        assert_eq!(encode_int(0), [0]);
+        assert_eq!(encode_int(127), [0x7f]);
+        assert_eq!(encode_int(128), [0x00, 0x80]);
+        assert_eq!(encode_int(-129), [0xff, 0x7f]);
+        assert_eq!(encode_uint(u64::MAX), [0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        for val in [0, 1, -1, 127, 128, -128, -129, i64::from(i32::MAX), i64::from(i32::MIN)] {
+            assert_eq!(decode_int(&encode_int(val)).unwrap(), val);
+        }
+        assert_eq!(encode_oid(&[1, 3, 6, 1, 3, 3210]), [0x2b, 6, 1, 3, 0x99, 0x0a]);
+        assert_eq!(decode_oid(&encode_oid(&[1, 3, 6, 1, 4, 1, u32::MAX])).unwrap(), [1, 3, 6, 1, 4, 1, u32::MAX]);
+        assert!(decode_oid(&[0x2b, 0x99]).is_err());
+        let mut long = vec![];
+        encode_tlv(&mut long, TAG_OCTET_STRING, &[0; 300]);
+        assert_eq!(long[..4], [TAG_OCTET_STRING, 0x82, 0x01, 0x2c]);
+        let message = Message {
+            community: b"public".to_vec(),
+            pdu_type: PDU_RESPONSE,
+            request_id: -5,
+            error_status: 0,
+            error_index: 0,
+            varbinds: vec![
+                (scalar(OBJECT_BYTES_IN), Value::Counter64(u64::MAX)),
+                (scalar(OBJECT_PEER_COUNT), Value::Gauge32(3)),
+                (SNMP_TRAP_OID.to_vec(), Value::Oid(VPNCLOUD_NOTIFICATIONS.to_vec())),
+                (scalar(OBJECT_PEER_ADDRESS), Value::OctetString(vec![b'x'; 200])),
+                (scalar(42), Value::NoSuchObject),
+            ],
+        };
+        assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+        assert!(Message::decode(&message.encode()[..30]).is_err());
+    }
+
+    #[test]
+    fn snmp_responses() {
+        let stats = SnmpStats { peers: 2, bytes_in: 1000, active_tunnels: 1, ..Default::default() };
+        let request = |pdu_type, oids: Vec<Oid>| Message {
+            community: b"public".to_vec(),
+            pdu_type,
+            request_id: 17,
+            error_status: 0,
+            error_index: 0,
+            varbinds: oids.into_iter().map(|oid| (oid, Value::Null)).collect(),
+        };
+        let response =
+            respond(&stats, request(PDU_GET, vec![scalar(OBJECT_PEER_COUNT), scalar(OBJECT_BYTES_IN)])).unwrap();
+        assert_eq!(response.pdu_type, PDU_RESPONSE);
+        assert_eq!(response.request_id, 17);
+        assert_eq!(response.varbinds[0], (scalar(OBJECT_PEER_COUNT), Value::Gauge32(2)));
+        assert_eq!(response.varbinds[1], (scalar(OBJECT_BYTES_IN), Value::Counter64(1000)));
+        let mut instance = scalar(OBJECT_PEER_COUNT);
+        instance[8] = 1;
+        let response = respond(&stats, request(PDU_GET, vec![instance, vec![1, 3, 6, 1, 2, 1, 1, 1, 0]])).unwrap();
+        assert_eq!(response.varbinds[0].1, Value::NoSuchInstance);
+        assert_eq!(response.varbinds[1].1, Value::NoSuchObject);
+        // Walking the MIB visits all objects in order
+        let mut oid = vec![1, 3, 6, 1];
+        let mut walked = vec![];
+        loop {
+            let response = respond(&stats, request(PDU_GET_NEXT, vec![oid])).unwrap();
+            let (next, value) = response.varbinds.into_iter().next().unwrap();
+            if value == Value::EndOfMibView {
+                break
+            }
+            walked.push(next[7]);
+            oid = next;
+        }
+        assert_eq!(walked, [1, 2, 3, 4, 5, 6, 7]);
+        assert!(respond(&stats, request(PDU_TRAP, vec![])).is_none());
+    }
+}
+
+#[cfg(not(feature = "snmp"))]
+mod internal {
+    use super::SnmpStats;
+    use crate::{error::Error, types::NodeId};
+    use std::net::SocketAddr;
+
+    pub struct SnmpAgent;
+
+    impl SnmpAgent {
+        pub fn start(
+            _addr: Option<SocketAddr>, _community: String, _trap_receiver: Option<SocketAddr>,
+        ) -> Result<Self, Error> {
+            Err(Error::InvalidConfig("SNMP is not supported by this build"))
+        }
+
+        pub fn update(&self, _stats: SnmpStats) {}
+
+        pub fn peer_connected(&mut self, _addr: SocketAddr, _node_id: &NodeId) {}
+
+        pub fn peer_disconnected(&mut self, _addr: SocketAddr, _node_id: &NodeId) {}
+    }
+}
+
+pub use internal::*;
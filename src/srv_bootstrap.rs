@@ -0,0 +1,216 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// Bootstrapping of peers from DNS SRV records
+//
+// The SRV records `_vpncloud._udp.<domain>` name the nodes of a network, so new nodes only need to know the domain.
+// The records are looked up in a background thread at startup and again when their TTL expires. The targets are sent
+// to the node over a channel and the node adds them to its reconnect peers.
+//
+// The targets are ordered as described in RFC 2782: records with a lower priority come first and records with the
+// same priority are shuffled, the chance of a record to come first is proportional to its weight.
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use rand::{thread_rng, Rng};
+
+use crate::error::Error;
+
+/// Label below the bootstrap domain that holds the SRV records
+pub const SRV_LABEL: &str = "_vpncloud._udp";
+/// The TTL of the records is clamped to this range to get the interval between lookups
+const MIN_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_INTERVAL: Duration = Duration::from_secs(3600);
+/// Interval between lookups when a lookup failed or found no records
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+/// Orders the records as described in RFC 2782 and returns their targets as host:port
+pub fn order_targets<R: Rng>(mut records: Vec<SrvRecord>, rng: &mut R) -> Vec<String> {
+    // A target of "." means that the service is not available at this domain
+    records.retain(|record| !record.target.trim_end_matches('.').is_empty());
+    // Records without weight are placed first, so they are only picked when the random number is 0
+    records.sort_by_key(|record| (record.priority, record.weight != 0));
+    let mut targets = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].priority;
+        let count = records.iter().take_while(|record| record.priority == priority).count();
+        let mut group: Vec<_> = records.drain(..count).collect();
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|record| u32::from(record.weight)).sum();
+            let pick = rng.gen_range(0..=total);
+            let mut sum = 0;
+            let index = group
+                .iter()
+                .position(|record| {
+                    sum += u32::from(record.weight);
+                    sum >= pick
+                })
+                .unwrap_or(0);
+            let record = group.remove(index);
+            targets.push(format!("{}:{}", record.target.trim_end_matches('.'), record.port));
+        }
+    }
+    targets
+}
+
+#[cfg(feature = "dns")]
+mod internal {
+    use std::time::{Duration, Instant};
+
+    use trust_dns_proto::rr::Name;
+    use trust_dns_resolver::{error::ResolveErrorKind, Resolver};
+
+    use super::{SrvRecord, RETRY_INTERVAL, SRV_LABEL};
+    use crate::error::Error;
+
+    pub struct SrvResolver {
+        resolver: Resolver,
+        names: Vec<Name>,
+    }
+
+    impl SrvResolver {
+        pub fn new(domains: &[String]) -> Result<Self, Error> {
+            let names = domains
+                .iter()
+                .map(|domain| {
+                    let mut zone = Name::from_ascii(domain)?;
+                    zone.set_fqdn(true);
+                    Name::from_ascii(SRV_LABEL)?.append_domain(&zone)
+                })
+                .collect::<Result<_, _>>()
+                .map_err(|_| Error::InvalidConfig("Invalid DNS bootstrap domain"))?;
+            let resolver = Resolver::from_system_conf()
+                .map_err(|e| Error::BeaconIo { message: "Failed to create resolver", source: e })?;
+            Ok(Self { resolver, names })
+        }
+
+        pub fn domains(&self) -> usize {
+            self.names.len()
+        }
+
+        /// Returns the records of the domain with the given index and the time until they expire
+        pub fn lookup(&self, index: usize) -> Result<(Vec<SrvRecord>, Duration), Error> {
+            let records = match self.resolver.srv_lookup(self.names[index].clone()) {
+                Ok(records) => records,
+                Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                    return Ok((vec![], RETRY_INTERVAL))
+                }
+                Err(err) => return Err(Error::Beacon(err.to_string())),
+            };
+            let ttl = records.as_lookup().valid_until().saturating_duration_since(Instant::now());
+            let records = records
+                .iter()
+                .map(|srv| SrvRecord {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    port: srv.port(),
+                    target: srv.target().to_utf8(),
+                })
+                .collect();
+            Ok((records, ttl))
+        }
+
+        pub fn name(&self, index: usize) -> String {
+            self.names[index].to_utf8()
+        }
+    }
+}
+
+#[cfg(not(feature = "dns"))]
+mod internal {
+    use std::time::Duration;
+
+    use super::SrvRecord;
+    use crate::error::Error;
+
+    pub struct SrvResolver;
+
+    impl SrvResolver {
+        pub fn new(_domains: &[String]) -> Result<Self, Error> {
+            Err(Error::InvalidConfig("DNS bootstrapping is not supported by this build"))
+        }
+
+        pub fn domains(&self) -> usize {
+            unreachable!("DNS bootstrapping is not supported by this build")
+        }
+
+        pub fn lookup(&self, _index: usize) -> Result<(Vec<SrvRecord>, Duration), Error> {
+            unreachable!("DNS bootstrapping is not supported by this build")
+        }
+
+        pub fn name(&self, _index: usize) -> String {
+            unreachable!("DNS bootstrapping is not supported by this build")
+        }
+    }
+}
+
+use internal::SrvResolver;
+
+/// Looks up the SRV records of the domains in a background thread
+///
+/// The ordered targets of each domain are sent over the returned channel after every lookup. The thread stops when the
+/// receiver is dropped.
+pub fn start(domains: &[String]) -> Result<mpsc::Receiver<Vec<String>>, Error> {
+    let resolver = SrvResolver::new(domains)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut interval = MAX_INTERVAL;
+        for index in 0..resolver.domains() {
+            match resolver.lookup(index) {
+                Ok((records, ttl)) => {
+                    debug!("Found {} SRV records at {}", records.len(), resolver.name(index));
+                    if sender.send(order_targets(records, &mut thread_rng())).is_err() {
+                        return
+                    }
+                    interval = interval.min(ttl);
+                }
+                Err(err) => {
+                    warn!("Failed to look up SRV records at {}: {}", resolver.name(index), err);
+                    interval = interval.min(RETRY_INTERVAL)
+                }
+            }
+        }
+        thread::sleep(interval.clamp(MIN_INTERVAL, MAX_INTERVAL));
+    });
+    Ok(receiver)
+}
+
+#[cfg(test)]
+fn srv(priority: u16, weight: u16, target: &str) -> SrvRecord {
+    SrvRecord { priority, weight, port: 3210, target: target.to_string() }
+}
+
+#[test]
+fn order_by_priority() {
+    let records = vec![srv(20, 10, "backup.example.com."), srv(10, 0, "node1.example.com."), srv(5, 0, ".")];
+    for _ in 0..10 {
+        assert_eq!(
+            order_targets(records.clone(), &mut thread_rng()),
+            vec!["node1.example.com:3210".to_string(), "backup.example.com:3210".to_string()]
+        );
+    }
+}
+
+#[test]
+fn order_by_weight() {
+    let records = vec![srv(10, 90, "heavy.example.com"), srv(10, 10, "light.example.com"), srv(10, 0, "none")];
+    let mut rng = thread_rng();
+    let mut heavy_first = 0;
+    for _ in 0..1000 {
+        let targets = order_targets(records.clone(), &mut rng);
+        assert_eq!(targets.len(), 3);
+        if targets[0] == "heavy.example.com:3210" {
+            heavy_first += 1
+        }
+    }
+    assert!(heavy_first > 800 && heavy_first < 980, "{}", heavy_first);
+}
@@ -0,0 +1,266 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use schemars::JsonSchema;
+
+use crate::util::Time;
+
+/// Version of the structured stats format, must be increased on incompatible changes
+pub const STATS_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+pub enum StatsFormat {
+    #[serde(rename = "text")]
+    Text,
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "prometheus")]
+    Prometheus,
+}
+
+impl fmt::Display for StatsFormat {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            StatsFormat::Text => write!(formatter, "text"),
+            StatsFormat::Json => write!(formatter, "json"),
+            StatsFormat::Prometheus => write!(formatter, "prometheus"),
+        }
+    }
+}
+
+impl FromStr for StatsFormat {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match &text.to_lowercase() as &str {
+            "text" => Self::Text,
+            "json" => Self::Json,
+            "prometheus" => Self::Prometheus,
+            _ => return Err("Unknown stats format"),
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StatsSnapshot {
+    pub schema_version: u32,
+    pub crypto: CryptoSnapshot,
+    pub mtu: MtuSnapshot,
+    pub peers: Vec<PeerSnapshot>,
+    pub excluded_routes: Vec<String>,
+    pub table: TableSnapshot,
+    pub traffic: TrafficSnapshot,
+    pub buffer_pool_exhausted_total: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct CryptoSnapshot {
+    pub backend: String,
+    pub cpu_features: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct MtuSnapshot {
+    /// The MTU of the device
+    pub device: u16,
+    /// The MTU that is advertised to peers
+    pub effective: u16,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PeerSnapshot {
+    pub addr: String,
+    pub ttl_secs: Time,
+    pub crypto: String,
+    /// Whether the messages of the peer are encrypted, plaintext peers can disable this
+    pub encrypted: bool,
+    pub path_mtu: Option<usize>,
+    /// The MTU agreed with the peer
+    pub mtu: Option<u16>,
+    pub path: PeerPath,
+    pub tags: BTreeMap<String, String>,
+    /// Packets lost in the last stats period, estimated from gaps in the sequence numbers
+    pub lost_packets: u64,
+    pub lost_packets_total: u64,
+    pub loss_percent: f32,
+}
+
+/// A connected peer as seen by management interfaces
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub addr: String,
+    pub node_id: String,
+    pub ttl_secs: Time,
+    /// The other addresses the peer is reachable at
+    pub alt_addrs: Vec<String>,
+    /// Smoothed round trip time, only measured with latency based routing
+    pub rtt_ms: Option<u64>,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ReconnectPeerSnapshot {
+    pub address: Option<String>,
+    pub resolved: Vec<String>,
+    pub tries: u16,
+}
+
+/// How messages reach a peer
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerPath {
+    Direct,
+    HolePunched,
+    Relayed,
+}
+
+impl fmt::Display for PeerPath {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            PeerPath::Direct => write!(formatter, "direct"),
+            PeerPath::HolePunched => write!(formatter, "hole_punched"),
+            PeerPath::Relayed => write!(formatter, "relayed"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct TableSnapshot {
+    pub claims: Vec<TableEntrySnapshot>,
+    pub cache: Vec<TableEntrySnapshot>,
+    pub suppressed: usize,
+    pub longest_prefix_match_hits: u64,
+    pub bloom_true_negatives_total: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TableEntrySnapshot {
+    pub addr: String,
+    pub peer: String,
+    pub ttl_secs: Time,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TableClaimSnapshot {
+    pub address: String,
+    pub prefix_len: u8,
+    pub peer: String,
+    pub ttl_secs: Time,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct TrafficSnapshot {
+    pub peers: Vec<PeerTrafficSnapshot>,
+    pub payload: Vec<PayloadTrafficSnapshot>,
+    pub invalid_protocol: TrafficCounters,
+    pub dropped_payload: TrafficCounters,
+    pub padded_bytes: u64,
+    pub unpadded_packets: usize,
+    /// The source addresses with the most payload traffic
+    pub top_talkers: Vec<AddressTrafficSnapshot>,
+    /// Average peer traffic per second over the last hour
+    pub rate: TrafficRateSnapshot,
+    /// Peer traffic per second of the busiest period in the last hour
+    pub peak_rate: TrafficRateSnapshot,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PeerTrafficSnapshot {
+    pub peer: String,
+    #[serde(flatten)]
+    pub traffic: TrafficEntrySnapshot,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PayloadTrafficSnapshot {
+    pub remote: String,
+    pub local: String,
+    #[serde(flatten)]
+    pub traffic: TrafficEntrySnapshot,
+}
+
+/// Traffic of the last stats period and in total
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct TrafficEntrySnapshot {
+    #[serde(rename = "in")]
+    pub in_: TrafficCounters,
+    pub out: TrafficCounters,
+    pub in_total: TrafficCounters,
+    pub out_total: TrafficCounters,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AddressTrafficSnapshot {
+    pub address: String,
+    #[serde(flatten)]
+    pub traffic: TrafficEntrySnapshot,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct TrafficRateSnapshot {
+    #[serde(rename = "in")]
+    pub in_: TrafficCounters,
+    pub out: TrafficCounters,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct TrafficCounters {
+    pub bytes: u64,
+    pub packets: usize,
+}
+
+#[test]
+fn stats_json() {
+    let snapshot = StatsSnapshot {
+        schema_version: STATS_SCHEMA_VERSION,
+        crypto: CryptoSnapshot { backend: "aes-ni".to_string(), cpu_features: vec!["aes".to_string()] },
+        mtu: MtuSnapshot { device: 1400, effective: 1380 },
+        peers: vec![PeerSnapshot {
+            addr: "1.2.3.4:3210".to_string(),
+            ttl_secs: 300,
+            crypto: "AES128".to_string(),
+            encrypted: true,
+            path_mtu: None,
+            mtu: Some(1380),
+            path: PeerPath::Relayed,
+            tags: vec![("role".to_string(), "gateway".to_string())].into_iter().collect(),
+            lost_packets: 2,
+            lost_packets_total: 5,
+            loss_percent: 50.0,
+        }],
+        excluded_routes: vec!["192.168.0.0/16".to_string()],
+        table: TableSnapshot::default(),
+        traffic: TrafficSnapshot {
+            peers: vec![PeerTrafficSnapshot {
+                peer: "1.2.3.4:3210".to_string(),
+                traffic: TrafficEntrySnapshot {
+                    in_: TrafficCounters { bytes: 100, packets: 1 },
+                    ..TrafficEntrySnapshot::default()
+                },
+            }],
+            ..TrafficSnapshot::default()
+        },
+        buffer_pool_exhausted_total: 0,
+    };
+    let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+    assert_eq!(json["schema_version"], 1);
+    assert_eq!(json["crypto"]["backend"], "aes-ni");
+    assert_eq!(json["mtu"]["device"], 1400);
+    assert_eq!(json["mtu"]["effective"], 1380);
+    assert_eq!(json["peers"][0]["mtu"], 1380);
+    assert_eq!(json["peers"][0]["crypto"], "AES128");
+    assert_eq!(json["peers"][0]["encrypted"], true);
+    assert_eq!(json["peers"][0]["path"], "relayed");
+    assert_eq!(json["peers"][0]["tags"]["role"], "gateway");
+    assert_eq!(json["peers"][0]["lost_packets"], 2);
+    assert_eq!(json["peers"][0]["loss_percent"], 50.0);
+    assert_eq!(json["excluded_routes"][0], "192.168.0.0/16");
+    assert_eq!(json["traffic"]["peers"][0]["peer"], "1.2.3.4:3210");
+    assert_eq!(json["traffic"]["peers"][0]["in"]["bytes"], 100);
+    assert_eq!(json["traffic"]["dropped_payload"]["packets"], 0);
+    assert_eq!(json["traffic"]["peak_rate"]["out"]["bytes"], 0);
+}
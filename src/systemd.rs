@@ -0,0 +1,58 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+#[cfg(feature = "systemd")]
+mod internal {
+    use sd_notify::{notify, watchdog_enabled, NotifyState};
+
+    pub struct SystemdNotifier {
+        watchdog: bool,
+    }
+
+    impl SystemdNotifier {
+        pub fn from_env() -> Self {
+            let mut usec = 0;
+            let watchdog = watchdog_enabled(false, &mut usec);
+            if watchdog {
+                info!("Systemd watchdog enabled with interval of {} ms", usec / 1000);
+            }
+            Self { watchdog }
+        }
+
+        pub fn ready(&self) {
+            if let Err(err) = notify(false, &[NotifyState::Ready]) {
+                warn!("Failed to notify systemd: {}", err)
+            }
+        }
+
+        pub fn housekeep(&self, peers: usize) {
+            let status = format!("Peers: {}", peers);
+            let res = if self.watchdog {
+                notify(false, &[NotifyState::Watchdog, NotifyState::Status(&status)])
+            } else {
+                notify(false, &[NotifyState::Status(&status)])
+            };
+            if let Err(err) = res {
+                debug!("Failed to notify systemd: {}", err)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "systemd"))]
+mod internal {
+    pub struct SystemdNotifier;
+
+    impl SystemdNotifier {
+        pub fn from_env() -> Self {
+            SystemdNotifier
+        }
+
+        pub fn ready(&self) {}
+
+        pub fn housekeep(&self, _peers: usize) {}
+    }
+}
+
+pub use internal::*;
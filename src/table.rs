@@ -3,17 +3,43 @@
 // This software is licensed under GPL-3 or newer (see LICENSE.md)
 
 use fnv::FnvHasher;
+use smallvec::SmallVec;
 use std::{
-    cmp::min, collections::HashMap, hash::BuildHasherDefault, io, io::Write, marker::PhantomData, net::SocketAddr,
+    cmp::min,
+    collections::{HashMap, VecDeque},
+    hash::{self, BuildHasherDefault},
+    io,
+    io::Write,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::mpsc,
 };
 
 use crate::{
+    bloom::BloomFilter,
+    error::Error,
+    stats::{TableClaimSnapshot, TableEntrySnapshot, TableSnapshot},
     types::{Address, Range, RangeList},
     util::{addr_nice, Duration, Time, TimeSource},
 };
 
 type Hash = BuildHasherDefault<FnvHasher>;
 
+/// Maximal time in seconds that a flapping mapping is suppressed
+pub const MAX_FLAP_SUPPRESSION: Time = 300;
+
+/// Maximal number of addresses whose candidate peers are cached for latency based routing
+const MAX_MULTIPATH_ENTRIES: usize = 10_000;
+
+/// False positive rate of the Bloom filter of the claims when it holds as many claims as it has been sized for
+const BLOOM_FP_RATE: f64 = 0.01;
+
+/// Number of claims that the Bloom filter is sized for until the maximum number of claims is set
+const DEFAULT_BLOOM_CAPACITY: usize = 1000;
+
+/// Learned addresses and claims as loaded from the table store
+type StoredEntries = (Vec<(Address, SocketAddr)>, Vec<(SocketAddr, Range)>);
+
 struct CacheValue {
     peer: SocketAddr,
     timeout: Time,
@@ -25,45 +51,297 @@ struct ClaimEntry {
     timeout: Time,
 }
 
+struct FlapEntry {
+    changes: VecDeque<Time>,
+    suppressed_until: Time,
+}
+
+/// Suppresses mappings that change too often (route flap dampening)
+///
+/// When a mapping changed more than `max_flaps` times within the window, further changes are suppressed for
+/// `2^(flaps - max_flaps)` seconds but at most `MAX_FLAP_SUPPRESSION`. A `max_flaps` of 0 disables dampening.
+pub struct FlapDampener<K, TS: TimeSource> {
+    window: Duration,
+    max_flaps: usize,
+    entries: HashMap<K, FlapEntry, Hash>,
+    _dummy: PhantomData<TS>,
+}
+
+impl<K: hash::Hash + Eq, TS: TimeSource> FlapDampener<K, TS> {
+    pub fn new(window: Duration, max_flaps: usize) -> Self {
+        Self { window, max_flaps, entries: HashMap::default(), _dummy: PhantomData }
+    }
+
+    /// Records a change of the mapping and returns whether it should be applied
+    pub fn allow(&mut self, key: K) -> bool {
+        if self.max_flaps == 0 {
+            return true;
+        }
+        let now = TS::now();
+        let window = self.window as Time;
+        let entry =
+            self.entries.entry(key).or_insert_with(|| FlapEntry { changes: VecDeque::new(), suppressed_until: 0 });
+        if entry.suppressed_until > now {
+            return false;
+        }
+        while entry.changes.front().map(|t| t + window <= now).unwrap_or(false) {
+            entry.changes.pop_front();
+        }
+        entry.changes.push_back(now);
+        if entry.changes.len() > self.max_flaps {
+            // 2^9 is more than the maximal suppression anyway
+            let exp = min(entry.changes.len() - self.max_flaps, 9);
+            entry.suppressed_until = now + min(1 << exp, MAX_FLAP_SUPPRESSION);
+        }
+        true
+    }
+
+    /// Number of currently suppressed mappings
+    pub fn suppressed_len(&self) -> usize {
+        let now = TS::now();
+        self.entries.values().filter(|e| e.suppressed_until > now).count()
+    }
+
+    pub fn housekeep(&mut self) {
+        let now = TS::now();
+        let window = self.window as Time;
+        self.entries
+            .retain(|_, e| e.suppressed_until > now || e.changes.back().map(|t| t + window > now).unwrap_or(false))
+    }
+}
+
+#[derive(Default)]
+struct TrieNode {
+    /// Indices of the child nodes, 0 means no child as the root nodes are never children
+    children: [usize; 2],
+    value: Option<usize>,
+}
+
+/// Binary trie that finds the longest prefix matching an address
+///
+/// Addresses of different lengths (IPv4, IPv6, MAC) are kept in separate trees.
+#[derive(Default)]
+struct PrefixTrie {
+    roots: SmallVec<[(u8, usize); 4]>,
+    nodes: Vec<TrieNode>,
+}
+
+impl PrefixTrie {
+    #[inline]
+    fn bit(addr: &Address, pos: usize) -> usize {
+        ((addr.data[pos / 8] >> (7 - pos % 8)) & 1) as usize
+    }
+
+    fn clear(&mut self) {
+        self.roots.clear();
+        self.nodes.clear();
+    }
+
+    fn new_node(&mut self) -> usize {
+        self.nodes.push(TrieNode::default());
+        self.nodes.len() - 1
+    }
+
+    /// Inserts the value for the range, if the range already has a value, it is kept
+    fn insert(&mut self, range: &Range, value: usize) {
+        let len = range.base.len;
+        if range.prefix_len as usize > len as usize * 8 {
+            // Such ranges never match any address
+            return;
+        }
+        let mut node = match self.roots.iter().find(|(l, _)| *l == len) {
+            Some(&(_, node)) => node,
+            None => {
+                let node = self.new_node();
+                self.roots.push((len, node));
+                node
+            }
+        };
+        for pos in 0..range.prefix_len as usize {
+            let bit = Self::bit(&range.base, pos);
+            node = match self.nodes[node].children[bit] {
+                0 => {
+                    let child = self.new_node();
+                    self.nodes[node].children[bit] = child;
+                    child
+                }
+                child => child,
+            };
+        }
+        self.nodes[node].value.get_or_insert(value);
+    }
+
+    /// Returns the value of the longest range that matches the address
+    fn lookup(&self, addr: &Address) -> Option<usize> {
+        let mut node = self.roots.iter().find(|(l, _)| *l == addr.len)?.1;
+        let mut found = self.nodes[node].value;
+        for pos in 0..addr.len as usize * 8 {
+            node = self.nodes[node].children[Self::bit(addr, pos)];
+            if node == 0 {
+                break;
+            }
+            if let Some(value) = self.nodes[node].value {
+                found = Some(value)
+            }
+        }
+        found
+    }
+}
+
+/// Change of the claims in the table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClaimEvent {
+    Added(Range),
+    Removed(Range),
+}
+
+fn notify(events: &Option<mpsc::Sender<ClaimEvent>>, event: ClaimEvent) {
+    if let Some(events) = events {
+        // The receiver might be gone already, nothing to do then
+        events.send(event).ok();
+    }
+}
+
+/// Returns the range with the given prefix length that contains the address, with all bits after the prefix cleared
+fn masked(addr: &Address, prefix_len: u8) -> Range {
+    let mut base = *addr;
+    for (i, byte) in base.data[..base.len as usize].iter_mut().enumerate() {
+        let bits = i as u8 * 8;
+        if bits >= prefix_len {
+            *byte = 0
+        } else if prefix_len - bits < 8 {
+            *byte &= 0xff << (8 - (prefix_len - bits))
+        }
+    }
+    Range { base, prefix_len }
+}
+
+enum RemovedEntry {
+    Cache(Address),
+    Claim(SocketAddr, Range),
+}
+
 pub struct ClaimTable<TS: TimeSource> {
+    /// Learnt addresses and the results of claim lookups, the table is owned by one cloud and needs no locking
     cache: HashMap<Address, CacheValue, Hash>,
     cache_timeout: Duration,
     claims: Vec<ClaimEntry>,
     claim_timeout: Duration,
+    /// Index of the claims, rebuilt on lookup when the claims changed
+    trie: PrefixTrie,
+    trie_dirty: bool,
+    /// Masked claims, addresses that match none of them are not looked up in the trie
+    bloom: BloomFilter,
+    /// Address lengths and prefix lengths of the claims in the Bloom filter
+    bloom_prefixes: SmallVec<[(u8, u8); 4]>,
+    bloom_true_negatives_total: u64,
+    /// Incremented whenever the claims change
+    generation: u64,
+    longest_prefix_match_hits: u64,
+    removed: Option<Vec<RemovedEntry>>,
+    events: Option<mpsc::Sender<ClaimEvent>>,
+    cache_flaps: FlapDampener<Address, TS>,
+    claim_flaps: FlapDampener<Range, TS>,
     _dummy: PhantomData<TS>,
 }
 
 impl<TS: TimeSource> ClaimTable<TS> {
     pub fn new(cache_timeout: Duration, claim_timeout: Duration) -> Self {
-        Self { cache: HashMap::default(), cache_timeout, claims: vec![], claim_timeout, _dummy: PhantomData }
+        Self {
+            cache: HashMap::default(),
+            cache_timeout,
+            claims: vec![],
+            claim_timeout,
+            trie: PrefixTrie::default(),
+            trie_dirty: false,
+            bloom: BloomFilter::with_rate(DEFAULT_BLOOM_CAPACITY, BLOOM_FP_RATE),
+            bloom_prefixes: SmallVec::new(),
+            bloom_true_negatives_total: 0,
+            generation: 0,
+            longest_prefix_match_hits: 0,
+            removed: None,
+            events: None,
+            cache_flaps: FlapDampener::new(0, 0),
+            claim_flaps: FlapDampener::new(0, 0),
+            _dummy: PhantomData,
+        }
     }
 
-    pub fn cache(&mut self, addr: Address, peer: SocketAddr) {
+    /// Suppresses addresses and claims that changed more than `max_flaps` times within `window` seconds
+    pub fn set_flap_dampening(&mut self, window: Duration, max_flaps: usize) {
+        self.cache_flaps = FlapDampener::new(window, max_flaps);
+        self.claim_flaps = FlapDampener::new(window, max_flaps);
+    }
+
+    /// Sizes the Bloom filter of the claims for the given number of claims
+    pub fn set_bloom_capacity(&mut self, claims: usize) {
+        self.bloom = BloomFilter::with_rate(claims, BLOOM_FP_RATE);
+        self.rebuild_bloom()
+    }
+
+    /// Returns a channel that receives all changes of the claims, starting with the current claims
+    pub fn subscribe(&mut self) -> mpsc::Receiver<ClaimEvent> {
+        let (tx, rx) = mpsc::channel();
+        for entry in &self.claims {
+            tx.send(ClaimEvent::Added(entry.claim)).ok();
+        }
+        self.events = Some(tx);
+        rx
+    }
+
+    /// Caches the peer for the address and returns whether the mapping is new
+    ///
+    /// New mappings of flapping addresses are suppressed.
+    pub fn cache(&mut self, addr: Address, peer: SocketAddr) -> bool {
         // HOT PATH
-        self.cache.insert(addr, CacheValue { peer, timeout: TS::now() + self.cache_timeout as Time });
+        let timeout = TS::now() + self.cache_timeout as Time;
+        match self.cache.get_mut(&addr) {
+            Some(entry) if entry.peer == peer => {
+                entry.timeout = timeout;
+                false
+            }
+            _ => {
+                // COLD PATH
+                if !self.cache_flaps.allow(addr) {
+                    return false;
+                }
+                self.cache.insert(addr, CacheValue { peer, timeout });
+                true
+            }
+        }
     }
 
     pub fn clear_cache(&mut self) {
         self.cache.clear()
     }
 
-    pub fn set_claims(&mut self, peer: SocketAddr, mut claims: RangeList) {
+    pub fn set_claims(&mut self, peer: SocketAddr, claims: RangeList) {
+        // Duplicates would be added twice and only one of them would be removed again
+        let mut claims = claims.into_iter().fold(RangeList::new(), |mut unique, claim| {
+            if !unique.contains(&claim) {
+                unique.push(claim)
+            }
+            unique
+        });
+        // All claims of the peer have to be visited, the ones that are not listed anymore are removed
         for entry in &mut self.claims {
             if entry.peer == peer {
                 let pos = claims.iter().position(|r| r == &entry.claim);
                 if let Some(pos) = pos {
                     entry.timeout = TS::now() + self.claim_timeout as Time;
                     claims.swap_remove(pos);
-                    if claims.is_empty() {
-                        break;
-                    }
                 } else {
                     entry.timeout = 0
                 }
             }
         }
         for claim in claims {
-            self.claims.push(ClaimEntry { peer, claim, timeout: TS::now() + self.claim_timeout as Time })
+            if self.claim_flaps.allow(claim) {
+                self.claims.push(ClaimEntry { peer, claim, timeout: TS::now() + self.claim_timeout as Time });
+                self.bloom_insert(&claim);
+                self.claims_changed();
+                notify(&self.events, ClaimEvent::Added(claim));
+            }
         }
         for entry in self.cache.values_mut() {
             if entry.peer == peer {
@@ -73,6 +351,16 @@ impl<TS: TimeSource> ClaimTable<TS> {
         self.housekeep()
     }
 
+    /// Extends the timeout of all claims of the peer, the peer only sends its claims with full peer lists
+    pub fn refresh_claims(&mut self, peer: SocketAddr) {
+        let timeout = TS::now() + self.claim_timeout as Time;
+        for entry in &mut self.claims {
+            if entry.peer == peer {
+                entry.timeout = timeout
+            }
+        }
+    }
+
     pub fn remove_claims(&mut self, peer: SocketAddr) {
         for entry in &mut self.claims {
             if entry.peer == peer {
@@ -87,21 +375,89 @@ impl<TS: TimeSource> ClaimTable<TS> {
         self.housekeep()
     }
 
+    /// Replaces pairs of adjacent claims of the peer by their common supernet
+    ///
+    /// Supernets that would contain claims of other peers are not created. Returns the newly created claims.
+    pub fn aggregate_claims(&mut self, peer: SocketAddr) -> RangeList {
+        let mut created = RangeList::new();
+        loop {
+            // Supernet -> (halves claimed by the peer, earliest timeout)
+            let mut halves: HashMap<Range, (u8, Time), Hash> = HashMap::default();
+            for entry in &self.claims {
+                if entry.peer != peer {
+                    continue;
+                }
+                if let Some(supernet) = entry.claim.supernet() {
+                    let half = if entry.claim.is_upper_half() { 2 } else { 1 };
+                    let value = halves.entry(supernet).or_insert((0, entry.timeout));
+                    value.0 |= half;
+                    value.1 = min(value.1, entry.timeout);
+                }
+            }
+            let claims = &self.claims;
+            let supernets: SmallVec<[(Range, Time); 4]> = halves
+                .into_iter()
+                .filter(|(supernet, (halves, _))| {
+                    *halves == 3
+                        && !claims.iter().any(|e| {
+                            e.peer != peer
+                                && e.claim.prefix_len >= supernet.prefix_len
+                                && supernet.matches(e.claim.base)
+                        })
+                })
+                .map(|(supernet, (_, timeout))| (supernet, timeout))
+                .collect();
+            if supernets.is_empty() {
+                break;
+            }
+            for (supernet, timeout) in supernets {
+                let removed = &mut self.removed;
+                let events = &self.events;
+                self.claims.retain(|e| {
+                    let child = e.peer == peer && e.claim.supernet() == Some(supernet);
+                    if child {
+                        if let Some(removed) = removed {
+                            removed.push(RemovedEntry::Claim(e.peer, e.claim))
+                        }
+                        notify(events, ClaimEvent::Removed(e.claim));
+                    }
+                    !child
+                });
+                if !self.claims.iter().any(|e| e.peer == peer && e.claim == supernet) {
+                    self.claims.push(ClaimEntry { peer, claim: supernet, timeout });
+                    self.bloom_insert(&supernet);
+                    created.push(supernet);
+                    notify(&self.events, ClaimEvent::Added(supernet));
+                }
+            }
+        }
+        let claims = &self.claims;
+        created.retain(|c| claims.iter().any(|e| e.peer == peer && e.claim == *c));
+        self.claims_changed();
+        created
+    }
+
     pub fn lookup(&mut self, addr: Address) -> Option<SocketAddr> {
         // HOT PATH
         if let Some(entry) = self.cache.get(&addr) {
             return Some(entry.peer);
         }
         // COLD PATH
-        let mut found = None;
-        let mut prefix_len = -1;
-        for entry in &self.claims {
-            if entry.claim.prefix_len as isize > prefix_len && entry.claim.matches(addr) {
-                found = Some(entry);
-                prefix_len = entry.claim.prefix_len as isize;
-            }
+        let bloom = &self.bloom;
+        if !self
+            .bloom_prefixes
+            .iter()
+            .any(|&(len, prefix_len)| len == addr.len && bloom.contains(&masked(&addr, prefix_len)))
+        {
+            self.bloom_true_negatives_total += 1;
+            return None
+        }
+        if self.trie_dirty {
+            self.rebuild_trie()
         }
-        if let Some(entry) = found {
+        if let Some(index) = self.trie.lookup(&addr) {
+            let entry = &self.claims[index];
+            self.longest_prefix_match_hits += 1;
             self.cache.insert(
                 addr,
                 CacheValue { peer: entry.peer, timeout: min(TS::now() + self.cache_timeout as Time, entry.timeout) },
@@ -111,10 +467,93 @@ impl<TS: TimeSource> ClaimTable<TS> {
         None
     }
 
+    /// Returns all peers that claim the longest range matching the address
+    pub fn lookup_all(&mut self, addr: Address) -> SmallVec<[SocketAddr; 4]> {
+        if self.trie_dirty {
+            self.rebuild_trie()
+        }
+        match self.trie.lookup(&addr) {
+            Some(index) => {
+                let claim = self.claims[index].claim;
+                self.claims.iter().filter(|e| e.claim == claim).map(|e| e.peer).collect()
+            }
+            None => SmallVec::new(),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn claims_changed(&mut self) {
+        self.trie_dirty = true;
+        self.generation += 1;
+    }
+
+    fn rebuild_trie(&mut self) {
+        self.trie.clear();
+        for (index, entry) in self.claims.iter().enumerate() {
+            self.trie.insert(&entry.claim, index)
+        }
+        self.trie_dirty = false;
+    }
+
+    fn bloom_insert(&mut self, claim: &Range) {
+        self.bloom.insert(&masked(&claim.base, claim.prefix_len));
+        let prefix = (claim.base.len, claim.prefix_len);
+        if !self.bloom_prefixes.contains(&prefix) {
+            self.bloom_prefixes.push(prefix)
+        }
+    }
+
+    /// Fills the Bloom filter with the current claims, removed claims are only dropped from the filter this way
+    fn rebuild_bloom(&mut self) {
+        self.bloom.clear();
+        self.bloom_prefixes.clear();
+        for index in 0..self.claims.len() {
+            let claim = self.claims[index].claim;
+            self.bloom_insert(&claim)
+        }
+    }
+
     pub fn housekeep(&mut self) {
         let now = TS::now();
-        self.cache.retain(|_, v| v.timeout >= now);
-        self.claims.retain(|e| e.timeout >= now);
+        let claim_len = self.claims.len();
+        let events = &self.events;
+        if let Some(ref mut removed) = self.removed {
+            self.cache.retain(|addr, v| {
+                if v.timeout < now {
+                    removed.push(RemovedEntry::Cache(*addr))
+                }
+                v.timeout >= now
+            });
+            self.claims.retain(|e| {
+                if e.timeout < now {
+                    removed.push(RemovedEntry::Claim(e.peer, e.claim));
+                    notify(events, ClaimEvent::Removed(e.claim));
+                }
+                e.timeout >= now
+            });
+        } else {
+            self.cache.retain(|_, v| v.timeout >= now);
+            self.claims.retain(|e| {
+                if e.timeout < now {
+                    notify(events, ClaimEvent::Removed(e.claim));
+                }
+                e.timeout >= now
+            });
+        }
+        if self.claims.len() != claim_len {
+            self.claims_changed();
+        }
+        // Removed claims still match in the filter, so it is rebuilt when they make up half of it or when they push it
+        // beyond the size it has been made for
+        let stale = self.bloom.len().saturating_sub(self.claims.len());
+        if stale > 0 && (stale >= self.claims.len() || self.bloom.len() > self.bloom.capacity()) {
+            self.rebuild_bloom()
+        }
+        self.cache_flaps.housekeep();
+        self.claim_flaps.housekeep();
     }
 
     pub fn cache_len(&self) -> usize {
@@ -125,6 +564,11 @@ impl<TS: TimeSource> ClaimTable<TS> {
         self.claims.len()
     }
 
+    /// Number of addresses and claims that are currently suppressed because they flapped
+    pub fn suppressed_len(&self) -> usize {
+        self.cache_flaps.suppressed_len() + self.claim_flaps.suppressed_len()
+    }
+
     /// Write out the table
     pub fn write_out<W: Write>(&self, out: &mut W) -> Result<(), io::Error> {
         let now = TS::now();
@@ -149,8 +593,749 @@ impl<TS: TimeSource> ClaimTable<TS> {
                 entry.timeout - now
             )?;
         }
+        writeln!(out, "  suppressed: {}", self.suppressed_len())?;
+        writeln!(out, "  longest_prefix_match_hits: {}", self.longest_prefix_match_hits)?;
+        writeln!(out, "  bloom_true_negatives_total: {}", self.bloom_true_negatives_total)?;
         Ok(())
     }
+
+    pub fn snapshot(&self) -> TableSnapshot {
+        let now = TS::now();
+        TableSnapshot {
+            claims: self
+                .claims
+                .iter()
+                .map(|entry| TableEntrySnapshot {
+                    addr: entry.claim.to_string(),
+                    peer: addr_nice(entry.peer).to_string(),
+                    ttl_secs: entry.timeout - now,
+                })
+                .collect(),
+            cache: self
+                .cache
+                .iter()
+                .map(|(addr, entry)| TableEntrySnapshot {
+                    addr: addr.to_string(),
+                    peer: addr_nice(entry.peer).to_string(),
+                    ttl_secs: entry.timeout - now,
+                })
+                .collect(),
+            suppressed: self.suppressed_len(),
+            longest_prefix_match_hits: self.longest_prefix_match_hits,
+            bloom_true_negatives_total: self.bloom_true_negatives_total,
+        }
+    }
+
+    fn claim_snapshot(entry: &ClaimEntry, now: Time) -> TableClaimSnapshot {
+        TableClaimSnapshot {
+            address: entry.claim.base.to_string(),
+            prefix_len: entry.claim.prefix_len,
+            peer: addr_nice(entry.peer).to_string(),
+            ttl_secs: entry.timeout - now,
+        }
+    }
+
+    /// Returns all claims of the table
+    pub fn dump(&self) -> Vec<TableClaimSnapshot> {
+        let now = TS::now();
+        self.claims.iter().map(|entry| Self::claim_snapshot(entry, now)).collect()
+    }
+
+    /// Returns the claim with the longest prefix matching the address, without touching the cache
+    pub fn query(&mut self, addr: Address) -> Option<TableClaimSnapshot> {
+        if self.trie_dirty {
+            self.rebuild_trie()
+        }
+        let index = self.trie.lookup(&addr)?;
+        Some(Self::claim_snapshot(&self.claims[index], TS::now()))
+    }
+}
+
+#[cfg(feature = "table_persistence")]
+mod store {
+    use std::{
+        fs::DirBuilder,
+        io::{self, Cursor},
+        net::SocketAddr,
+        os::unix::fs::DirBuilderExt,
+        str::FromStr,
+    };
+
+    use super::StoredEntries;
+    use crate::{
+        error::Error,
+        types::{Address, Range},
+    };
+
+    const KEY_CACHE: u8 = 0;
+    const KEY_CLAIM: u8 = 1;
+
+    pub struct TableStore {
+        db: sled::Db,
+    }
+
+    fn cache_key(addr: Address) -> Vec<u8> {
+        let mut key = vec![KEY_CACHE];
+        addr.write_to(&mut key);
+        key
+    }
+
+    fn claim_key(peer: SocketAddr, claim: Range) -> Vec<u8> {
+        let mut key = vec![KEY_CLAIM];
+        claim.write_to(&mut key);
+        key.extend_from_slice(peer.to_string().as_bytes());
+        key
+    }
+
+    fn parse_peer(data: &[u8]) -> Result<SocketAddr, Error> {
+        std::str::from_utf8(data)
+            .ok()
+            .and_then(|s| SocketAddr::from_str(s).ok())
+            .ok_or(Error::Parse("Invalid peer address"))
+    }
+
+    impl TableStore {
+        pub fn open(path: &str) -> Result<Self, Error> {
+            // The store is a directory, only the owner can access it
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(path)
+                .map_err(|e| Error::FileIo { message: "Failed to create table store", source: e })?;
+            let db = sled::open(path)
+                .map_err(|e| Error::FileIo { message: "Failed to open table store", source: e.into() })?;
+            Ok(Self { db })
+        }
+
+        pub fn load(&self) -> Result<StoredEntries, Error> {
+            let mut cache = vec![];
+            let mut claims = vec![];
+            for entry in self.db.iter() {
+                let (key, value) =
+                    entry.map_err(|e| Error::FileIo { message: "Failed to read table store", source: e.into() })?;
+                match key.first() {
+                    Some(&KEY_CACHE) => cache.push((Address::read_from(&key[1..])?, parse_peer(&value)?)),
+                    Some(&KEY_CLAIM) => {
+                        let mut cursor = Cursor::new(&key[1..]);
+                        let claim = Range::read_from(&mut cursor)?;
+                        let peer = parse_peer(&key[1 + cursor.position() as usize..])?;
+                        claims.push((peer, claim))
+                    }
+                    _ => return Err(Error::Parse("Invalid table store entry")),
+                }
+            }
+            Ok((cache, claims))
+        }
+
+        fn result(res: sled::Result<Option<sled::IVec>>) {
+            if let Err(err) = res {
+                warn!("Failed to update table store: {}", io::Error::from(err))
+            }
+        }
+
+        pub fn put_cache(&self, addr: Address, peer: SocketAddr) {
+            Self::result(self.db.insert(cache_key(addr), peer.to_string().as_bytes()))
+        }
+
+        pub fn remove_cache(&self, addr: Address) {
+            Self::result(self.db.remove(cache_key(addr)))
+        }
+
+        pub fn put_claim(&self, peer: SocketAddr, claim: Range) {
+            Self::result(self.db.insert(claim_key(peer, claim), &[]))
+        }
+
+        pub fn remove_claim(&self, peer: SocketAddr, claim: Range) {
+            Self::result(self.db.remove(claim_key(peer, claim)))
+        }
+    }
+}
+
+#[cfg(not(feature = "table_persistence"))]
+mod store {
+    use std::net::SocketAddr;
+
+    use super::StoredEntries;
+    use crate::{
+        error::Error,
+        types::{Address, Range},
+    };
+
+    pub struct TableStore;
+
+    impl TableStore {
+        pub fn open(_path: &str) -> Result<Self, Error> {
+            Err(Error::InvalidConfig("Table persistence is not supported by this build"))
+        }
+
+        pub fn load(&self) -> Result<StoredEntries, Error> {
+            unreachable!()
+        }
+
+        pub fn put_cache(&self, _addr: Address, _peer: SocketAddr) {}
+
+        pub fn remove_cache(&self, _addr: Address) {}
+
+        pub fn put_claim(&self, _peer: SocketAddr, _claim: Range) {}
+
+        pub fn remove_claim(&self, _peer: SocketAddr, _claim: Range) {}
+    }
+}
+
+use self::store::TableStore;
+
+/// Claim table that writes learned addresses and claims through to an on-disk store
+///
+/// Entries loaded from the store are considered stale and only kept for half of the normal timeout.
+/// Chooses the peer with the lowest round trip time among all peers that claim the destination
+///
+/// The candidates of each address are cached until the claims change, so that the per packet cost is one hash lookup
+/// and a scan over the few candidates. Addresses that are not claimed by any peer use the normal lookup.
+#[derive(Default)]
+pub struct MultiPathLookup {
+    candidates: HashMap<Address, SmallVec<[SocketAddr; 4]>, Hash>,
+    generation: u64,
+}
+
+impl MultiPathLookup {
+    pub fn lookup<TS: TimeSource, F: Fn(&SocketAddr) -> Option<u64>>(
+        &mut self, table: &mut ClaimTable<TS>, addr: Address, rtt: F,
+    ) -> Option<SocketAddr> {
+        // HOT PATH
+        if self.generation != table.generation() || self.candidates.len() >= MAX_MULTIPATH_ENTRIES {
+            // COLD PATH
+            self.candidates.clear();
+            self.generation = table.generation();
+        }
+        let candidates = self.candidates.entry(addr).or_insert_with(|| table.lookup_all(addr));
+        if candidates.is_empty() {
+            return table.lookup(addr)
+        }
+        // Peers without a measured round trip time are only used if no other peer is available
+        candidates.iter().min_by_key(|peer| rtt(peer).unwrap_or(u64::MAX)).copied()
+    }
+}
+
+pub struct PersistentTable<TS: TimeSource> {
+    table: ClaimTable<TS>,
+    store: Option<TableStore>,
+}
+
+impl<TS: TimeSource> PersistentTable<TS> {
+    pub fn new(cache_timeout: Duration, claim_timeout: Duration, path: Option<&str>) -> Result<Self, Error> {
+        let mut table = ClaimTable::new(cache_timeout, claim_timeout);
+        let store = match path {
+            Some(path) => {
+                let store = TableStore::open(path)?;
+                let (cache, claims) = store.load()?;
+                info!("Loaded {} cache entries and {} claims from table store", cache.len(), claims.len());
+                let now = TS::now();
+                for (addr, peer) in cache {
+                    table.cache.insert(addr, CacheValue { peer, timeout: now + cache_timeout as Time / 2 });
+                }
+                for (peer, claim) in claims {
+                    table.claims.push(ClaimEntry { peer, claim, timeout: now + claim_timeout as Time / 2 });
+                }
+                table.claims_changed();
+                table.rebuild_bloom();
+                table.removed = Some(vec![]);
+                Some(store)
+            }
+            None => None,
+        };
+        Ok(Self { table, store })
+    }
+
+    fn sync_removed(&mut self) {
+        if let (Some(store), Some(removed)) = (&self.store, &mut self.table.removed) {
+            for entry in removed.drain(..) {
+                match entry {
+                    RemovedEntry::Cache(addr) => store.remove_cache(addr),
+                    RemovedEntry::Claim(peer, claim) => store.remove_claim(peer, claim),
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn cache(&mut self, addr: Address, peer: SocketAddr) {
+        // HOT PATH
+        if self.table.cache(addr, peer) {
+            // COLD PATH
+            if let Some(ref store) = self.store {
+                store.put_cache(addr, peer)
+            }
+        }
+    }
+
+    pub fn clear_cache(&mut self) {
+        if let Some(ref store) = self.store {
+            for addr in self.table.cache.keys() {
+                store.remove_cache(*addr)
+            }
+        }
+        self.table.clear_cache()
+    }
+
+    pub fn set_claims(&mut self, peer: SocketAddr, claims: RangeList) {
+        if let Some(ref store) = self.store {
+            for claim in &claims {
+                store.put_claim(peer, *claim)
+            }
+        }
+        self.table.set_claims(peer, claims);
+        self.sync_removed()
+    }
+
+    pub fn refresh_claims(&mut self, peer: SocketAddr) {
+        self.table.refresh_claims(peer)
+    }
+
+    pub fn remove_claims(&mut self, peer: SocketAddr) {
+        self.table.remove_claims(peer);
+        self.sync_removed()
+    }
+
+    pub fn aggregate_claims(&mut self, peer: SocketAddr) {
+        let created = self.table.aggregate_claims(peer);
+        if let Some(ref store) = self.store {
+            for claim in created {
+                store.put_claim(peer, claim)
+            }
+        }
+        self.sync_removed()
+    }
+
+    #[inline]
+    pub fn lookup(&mut self, addr: Address) -> Option<SocketAddr> {
+        // HOT PATH
+        self.table.lookup(addr)
+    }
+
+    #[inline]
+    pub fn lookup_multipath<F: Fn(&SocketAddr) -> Option<u64>>(
+        &mut self, multipath: &mut MultiPathLookup, addr: Address, rtt: F,
+    ) -> Option<SocketAddr> {
+        // HOT PATH
+        multipath.lookup(&mut self.table, addr, rtt)
+    }
+
+    pub fn housekeep(&mut self) {
+        self.table.housekeep();
+        self.sync_removed()
+    }
+
+    pub fn cache_len(&self) -> usize {
+        self.table.cache_len()
+    }
+
+    pub fn claim_len(&self) -> usize {
+        self.table.claim_len()
+    }
+
+    pub fn set_flap_dampening(&mut self, window: Duration, max_flaps: usize) {
+        self.table.set_flap_dampening(window, max_flaps)
+    }
+
+    pub fn set_bloom_capacity(&mut self, claims: usize) {
+        self.table.set_bloom_capacity(claims)
+    }
+
+    pub fn subscribe(&mut self) -> mpsc::Receiver<ClaimEvent> {
+        self.table.subscribe()
+    }
+
+    pub fn write_out<W: Write>(&self, out: &mut W) -> Result<(), io::Error> {
+        self.table.write_out(out)
+    }
+
+    pub fn snapshot(&self) -> TableSnapshot {
+        self.table.snapshot()
+    }
+
+    pub fn dump(&self) -> Vec<TableClaimSnapshot> {
+        self.table.dump()
+    }
+
+    pub fn query(&mut self, addr: Address) -> Option<TableClaimSnapshot> {
+        self.table.query(addr)
+    }
 }
 
 // TODO: test
+
+#[test]
+fn aggregate_claims() {
+    use crate::util::MockTimeSource;
+    use std::str::FromStr;
+    let peer = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_claims(peer, (0..=255).map(|i| Range::from_str(&format!("10.1.{}.0/24", i)).unwrap()).collect());
+    assert_eq!(table.claim_len(), 256);
+    assert_eq!(table.aggregate_claims(peer).as_slice(), &[Range::from_str("10.1.0.0/16").unwrap()]);
+    assert_eq!(table.claim_len(), 1);
+    assert_eq!(table.lookup(Address::from_str("10.1.200.1").unwrap()), Some(peer));
+}
+
+#[test]
+fn aggregate_claims_respects_other_peers() {
+    use crate::util::MockTimeSource;
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    let peer1 = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let peer2 = SocketAddr::from_str("1.2.3.5:3210").unwrap();
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_claims(
+        peer1,
+        smallvec![
+            Range::from_str("10.1.0.0/24").unwrap(),
+            Range::from_str("10.1.1.0/24").unwrap(),
+            Range::from_str("10.1.2.0/24").unwrap(),
+            Range::from_str("10.1.3.0/24").unwrap()
+        ],
+    );
+    table.set_claims(peer2, smallvec![Range::from_str("10.1.2.0/25").unwrap()]);
+    assert_eq!(table.aggregate_claims(peer1).as_slice(), &[Range::from_str("10.1.0.0/23").unwrap()]);
+    assert_eq!(table.claim_len(), 4);
+    assert_eq!(table.lookup(Address::from_str("10.1.2.1").unwrap()), Some(peer2));
+    assert_eq!(table.lookup(Address::from_str("10.1.3.1").unwrap()), Some(peer1));
+}
+
+#[test]
+fn longest_prefix_match() {
+    use crate::util::MockTimeSource;
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    let peer1 = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let peer2 = SocketAddr::from_str("1.2.3.5:3210").unwrap();
+    let peer3 = SocketAddr::from_str("1.2.3.6:3210").unwrap();
+    MockTimeSource::set_time(1000);
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_claims(peer1, smallvec![Range::from_str("10.0.0.0/8").unwrap(), Range::from_str("::/0").unwrap()]);
+    table.set_claims(peer3, smallvec![Range::from_str("10.1.2.0/24").unwrap()]);
+    table.set_claims(peer2, smallvec![Range::from_str("10.1.0.0/16").unwrap()]);
+    assert_eq!(table.lookup(Address::from_str("10.1.2.3").unwrap()), Some(peer3));
+    assert_eq!(table.lookup(Address::from_str("10.1.3.1").unwrap()), Some(peer2));
+    assert_eq!(table.lookup(Address::from_str("10.2.0.1").unwrap()), Some(peer1));
+    assert_eq!(table.lookup(Address::from_str("11.0.0.1").unwrap()), None);
+    assert_eq!(table.lookup(Address::from_str("fd00::1").unwrap()), Some(peer1));
+    assert_eq!(table.lookup(Address::from_str("10.1.2.4").unwrap()), Some(peer3));
+    assert_eq!(table.snapshot().longest_prefix_match_hits, 5);
+    // Cached addresses of removed claims fall back to the less specific claim
+    table.remove_claims(peer3);
+    assert_eq!(table.lookup(Address::from_str("10.1.2.3").unwrap()), Some(peer2));
+    table.set_claims(peer2, smallvec![Range::from_str("10.1.2.3/32").unwrap()]);
+    assert_eq!(table.lookup(Address::from_str("10.1.2.3").unwrap()), Some(peer2));
+    assert_eq!(table.lookup(Address::from_str("10.1.3.1").unwrap()), Some(peer1));
+}
+
+#[test]
+fn bloom_filter_skips_unclaimed() {
+    use crate::util::MockTimeSource;
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    let peer1 = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let peer2 = SocketAddr::from_str("1.2.3.5:3210").unwrap();
+    MockTimeSource::set_time(1000);
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_bloom_capacity(10);
+    table.set_claims(peer1, smallvec![Range::from_str("10.1.0.0/16").unwrap()]);
+    table.set_claims(peer2, smallvec![Range::from_str("10.2.3.4/24").unwrap()]);
+    assert_eq!(table.lookup(Address::from_str("10.1.2.3").unwrap()), Some(peer1));
+    assert_eq!(table.lookup(Address::from_str("10.2.3.1").unwrap()), Some(peer2));
+    assert_eq!(table.lookup(Address::from_str("fd00::1").unwrap()), None);
+    assert_eq!(table.snapshot().bloom_true_negatives_total, 1);
+    // Removed claims are dropped from the filter when it is rebuilt
+    table.remove_claims(peer2);
+    assert_eq!(table.bloom.len(), 1);
+    let negatives = (0..=255).filter(|n| table.lookup(Address::from_str(&format!("10.2.3.{}", n)).unwrap()).is_none());
+    assert_eq!(negatives.count(), 256);
+    assert!(table.snapshot().bloom_true_negatives_total > 200);
+}
+
+#[test]
+fn dump_and_query() {
+    use crate::util::MockTimeSource;
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    let peer1 = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let peer2 = SocketAddr::from_str("1.2.3.5:3210").unwrap();
+    MockTimeSource::set_time(1000);
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_claims(
+        peer1,
+        smallvec![
+            Range::from_str("10.0.0.0/8").unwrap(),
+            Range::from_str("10.2.0.0/16").unwrap(),
+            Range::from_str("fd00::/64").unwrap()
+        ],
+    );
+    table
+        .set_claims(peer2, smallvec![Range::from_str("10.1.0.0/16").unwrap(), Range::from_str("10.1.2.3/32").unwrap()]);
+    MockTimeSource::set_time(1100);
+    let dump = table.dump();
+    assert_eq!(dump.len(), 5);
+    assert!(dump.contains(&TableClaimSnapshot {
+        address: "10.1.0.0".to_string(),
+        prefix_len: 16,
+        peer: "1.2.3.5:3210".to_string(),
+        ttl_secs: 200
+    }));
+    let best = table.query(Address::from_str("10.1.5.6").unwrap()).unwrap();
+    assert_eq!((best.address.as_str(), best.prefix_len), ("10.1.0.0", 16));
+    let best = table.query(Address::from_str("10.3.0.1").unwrap()).unwrap();
+    assert_eq!((best.address.as_str(), best.prefix_len, best.peer.as_str()), ("10.0.0.0", 8, "1.2.3.4:3210"));
+    assert_eq!(table.query(Address::from_str("11.0.0.1").unwrap()), None);
+    // Queries do not fill the cache
+    assert_eq!(table.cache_len(), 0);
+}
+
+#[test]
+fn multipath_lookup() {
+    use crate::util::MockTimeSource;
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    let peer1 = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let peer2 = SocketAddr::from_str("1.2.3.5:3210").unwrap();
+    let peer3 = SocketAddr::from_str("1.2.3.6:3210").unwrap();
+    MockTimeSource::set_time(1000);
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_claims(peer1, smallvec![Range::from_str("0.0.0.0/0").unwrap()]);
+    table.set_claims(peer2, smallvec![Range::from_str("0.0.0.0/0").unwrap()]);
+    table.set_claims(peer3, smallvec![Range::from_str("10.1.0.0/16").unwrap()]);
+    let addr = Address::from_str("8.8.8.8").unwrap();
+    assert_eq!(table.lookup_all(addr).as_slice(), &[peer1, peer2]);
+    let mut rtts = HashMap::new();
+    rtts.insert(peer1, 40);
+    rtts.insert(peer2, 15);
+    let mut multipath = MultiPathLookup::default();
+    assert_eq!(multipath.lookup(&mut table, addr, |p| rtts.get(p).copied()), Some(peer2));
+    rtts.insert(peer1, 5);
+    assert_eq!(multipath.lookup(&mut table, addr, |p| rtts.get(p).copied()), Some(peer1));
+    // The longest prefix still wins over the round trip time
+    assert_eq!(
+        multipath.lookup(&mut table, Address::from_str("10.1.0.1").unwrap(), |p| rtts.get(p).copied()),
+        Some(peer3)
+    );
+    // Peers without round trip time are the last choice
+    rtts.remove(&peer1);
+    assert_eq!(multipath.lookup(&mut table, addr, |p| rtts.get(p).copied()), Some(peer2));
+    // Removed claims are noticed
+    table.remove_claims(peer2);
+    assert_eq!(multipath.lookup(&mut table, addr, |p| rtts.get(p).copied()), Some(peer1));
+    // Unclaimed addresses fall back to the learned addresses
+    let mac = Address::from_str("02:00:00:00:00:01").unwrap();
+    assert_eq!(multipath.lookup(&mut table, mac, |p| rtts.get(p).copied()), None);
+    table.cache(mac, peer3);
+    assert_eq!(multipath.lookup(&mut table, mac, |p| rtts.get(p).copied()), Some(peer3));
+}
+
+#[test]
+fn flap_dampening() {
+    use crate::util::MockTimeSource;
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    let peers = [SocketAddr::from_str("1.2.3.4:3210").unwrap(), SocketAddr::from_str("1.2.3.5:3210").unwrap()];
+    let addr = Address::from_str("10.0.0.1").unwrap();
+    MockTimeSource::set_time(1000);
+    let mut table = ClaimTable::<MockTimeSource>::new(60, 300);
+    table.set_flap_dampening(60, 5);
+    for i in 0..6 {
+        assert!(table.cache(addr, peers[i % 2]));
+    }
+    assert_eq!(table.suppressed_len(), 1);
+    // The 7th change is suppressed, refreshing the current mapping is not
+    assert!(!table.cache(addr, peers[0]));
+    assert!(!table.cache(addr, peers[1]));
+    assert_eq!(table.lookup(addr), Some(peers[1]));
+    MockTimeSource::set_time(1002);
+    assert_eq!(table.suppressed_len(), 0);
+    assert!(table.cache(addr, peers[0]));
+    // Now suppressed for 4 seconds
+    MockTimeSource::set_time(1005);
+    assert!(!table.cache(addr, peers[1]));
+    MockTimeSource::set_time(1006);
+    assert!(table.cache(addr, peers[1]));
+    // Claims are dampened as well
+    let claim = Range::from_str("10.1.0.0/16").unwrap();
+    for _ in 0..6 {
+        table.set_claims(peers[0], smallvec![claim]);
+        table.remove_claims(peers[0]);
+    }
+    table.set_claims(peers[0], smallvec![claim]);
+    assert_eq!(table.claim_len(), 0);
+    assert_eq!(table.suppressed_len(), 2);
+}
+
+#[cfg(feature = "table_persistence")]
+#[test]
+fn persistent_table() {
+    use crate::util::MockTimeSource;
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("table");
+    let path = path.to_str().unwrap();
+    let peer1 = SocketAddr::from_str("1.2.3.4:3210").unwrap();
+    let peer2 = SocketAddr::from_str("1.2.3.5:3210").unwrap();
+    let addr1 = Address::from_str("10.0.0.1").unwrap();
+    let addr2 = Address::from_str("10.0.0.2").unwrap();
+    MockTimeSource::set_time(1000);
+    {
+        let mut table = PersistentTable::<MockTimeSource>::new(60, 300, Some(path)).unwrap();
+        table.set_claims(peer1, smallvec![Range::from_str("10.1.0.0/16").unwrap()]);
+        table.cache(addr1, peer1);
+        table.cache(addr2, peer2);
+        table.remove_claims(peer2);
+        assert_eq!(table.lookup(addr2), None);
+    }
+    MockTimeSource::set_time(5000);
+    let mut table = PersistentTable::<MockTimeSource>::new(60, 300, Some(path)).unwrap();
+    assert_eq!(table.cache_len(), 1);
+    assert_eq!(table.claim_len(), 1);
+    assert_eq!(table.lookup(addr1), Some(peer1));
+    assert_eq!(table.lookup(addr2), None);
+    assert_eq!(table.lookup(Address::from_str("10.1.2.3").unwrap()), Some(peer1));
+    // Loaded entries only live for half of the timeout
+    MockTimeSource::set_time(5031);
+    table.housekeep();
+    assert_eq!(table.lookup(addr1), None);
+    assert_eq!(table.claim_len(), 1);
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use crate::util::MockTimeSource;
+    use proptest::{collection::vec, prelude::*};
+    use std::{collections::HashSet, str::FromStr};
+
+    const PEERS: [&str; 4] = ["1.2.3.4:3210", "1.2.3.5:3210", "[2001:db8::1]:3210", "[2001:db8::2]:3210"];
+    const RANGES: [&str; 8] = [
+        "0.0.0.0/0",
+        "10.0.0.0/8",
+        "10.1.0.0/16",
+        "10.1.2.0/24",
+        "10.1.3.0/24",
+        "10.1.2.3/32",
+        "192.168.0.0/16",
+        "fd00::/64",
+    ];
+    const ADDRS: [&str; 7] = ["10.1.2.3", "10.1.2.4", "10.1.3.1", "10.2.0.1", "192.168.1.1", "8.8.8.8", "fd00::1"];
+    const CACHE_TIMEOUT: Duration = 30;
+    const CLAIM_TIMEOUT: Duration = 60;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        SetClaims(usize, Vec<usize>),
+        RemoveClaims(usize),
+        Cache(usize, usize),
+        Lookup(usize),
+        Advance(Time),
+        Housekeep,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..PEERS.len(), vec(0..RANGES.len(), 0..4)).prop_map(|(peer, claims)| Op::SetClaims(peer, claims)),
+            (0..PEERS.len()).prop_map(Op::RemoveClaims),
+            (0..ADDRS.len(), 0..PEERS.len()).prop_map(|(addr, peer)| Op::Cache(addr, peer)),
+            (0..ADDRS.len()).prop_map(Op::Lookup),
+            (0..2 * CLAIM_TIMEOUT as Time).prop_map(Op::Advance),
+            Just(Op::Housekeep),
+        ]
+    }
+
+    /// The claims that the table should contain with their timeouts
+    type Model = HashMap<(SocketAddr, Range), Time>;
+
+    fn check_invariants(table: &mut ClaimTable<MockTimeSource>, model: &Model) {
+        let now = MockTimeSource::now();
+        let mut seen = HashSet::new();
+        for entry in &table.claims {
+            assert!(seen.insert((entry.peer, entry.claim)), "Duplicate claim {} of {}", entry.claim, entry.peer);
+            assert_eq!(model.get(&(entry.peer, entry.claim)), Some(&entry.timeout), "Unexpected claim {}", entry.claim);
+        }
+        for ((peer, claim), timeout) in model {
+            // Expired claims may stay until the next housekeeping
+            assert!(*timeout < now || seen.contains(&(*peer, *claim)), "Missing claim {} of {}", claim, peer);
+        }
+        for addr in &ADDRS {
+            let addr = Address::from_str(addr).unwrap();
+            let best = table.claims.iter().filter(|e| e.claim.matches(addr)).map(|e| e.claim.prefix_len).max();
+            let found = table.query(addr);
+            assert_eq!(found.as_ref().map(|c| c.prefix_len), best, "Not the longest prefix for {}", addr);
+            if let Some(found) = found {
+                assert!(
+                    table.claims.iter().any(|e| e.peer.to_string() == found.peer
+                        && e.claim.prefix_len == found.prefix_len
+                        && e.claim.matches(addr)),
+                    "Lookup result of {} is not in the claims",
+                    addr
+                );
+            }
+        }
+    }
+
+    fn apply(table: &mut ClaimTable<MockTimeSource>, model: &mut Model, op: Op) {
+        let now = MockTimeSource::now();
+        match op {
+            Op::SetClaims(peer, claims) => {
+                let peer = SocketAddr::from_str(PEERS[peer]).unwrap();
+                let claims: RangeList = claims.into_iter().map(|c| Range::from_str(RANGES[c]).unwrap()).collect();
+                table.set_claims(peer, claims.clone());
+                // Claims that are not listed anymore are removed immediately, all others are refreshed
+                model.retain(|(p, c), timeout| (*p != peer && *timeout >= now) || (*p == peer && claims.contains(c)));
+                for claim in claims {
+                    model.insert((peer, claim), now + CLAIM_TIMEOUT as Time);
+                }
+            }
+            Op::RemoveClaims(peer) => {
+                let peer = SocketAddr::from_str(PEERS[peer]).unwrap();
+                table.remove_claims(peer);
+                model.retain(|(p, _), timeout| *p != peer && *timeout >= now);
+                for entry in table.cache.values() {
+                    assert_ne!(entry.peer, peer, "Cache entry of removed peer");
+                }
+            }
+            Op::Cache(addr, peer) => {
+                let addr = Address::from_str(ADDRS[addr]).unwrap();
+                let peer = SocketAddr::from_str(PEERS[peer]).unwrap();
+                table.cache(addr, peer);
+                assert_eq!(table.lookup(addr), Some(peer));
+            }
+            Op::Lookup(addr) => {
+                let addr = Address::from_str(ADDRS[addr]).unwrap();
+                let cached = table.cache.get(&addr).map(|e| e.peer);
+                let found = table.lookup(addr);
+                if cached.is_some() {
+                    assert_eq!(found, cached)
+                } else if let Some(peer) = found {
+                    assert!(table.cache.get(&addr).map(|e| e.peer) == Some(peer), "Lookup result was not cached");
+                }
+            }
+            Op::Advance(secs) => MockTimeSource::advance(secs),
+            Op::Housekeep => {
+                table.housekeep();
+                model.retain(|_, timeout| *timeout >= now);
+                assert!(table.claims.iter().all(|e| e.timeout >= now), "Expired claim after housekeeping");
+                assert!(table.cache.values().all(|e| e.timeout >= now), "Expired cache entry after housekeeping");
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        #[test]
+        fn claim_table_invariants(ops in vec(op(), 1..40)) {
+            MockTimeSource::set_time(1000);
+            let mut table = ClaimTable::<MockTimeSource>::new(CACHE_TIMEOUT, CLAIM_TIMEOUT);
+            let mut model = Model::new();
+            for op in ops {
+                apply(&mut table, &mut model, op);
+                check_invariants(&mut table, &model);
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// OpenTelemetry tracing of the packet flow
+//
+// Spans are exported via OTLP/HTTP to the configured endpoint. To correlate spans across nodes, data messages to
+// peers that announced tracing support carry the trace id and span id of the sending span in front of the payload
+// (see MESSAGE_TYPE_DATA_TRACED). Peers that do not announce support never receive those messages.
+
+use crate::{error::Error, util::MsgBuffer};
+
+pub const TRACE_HEADER_LEN: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+}
+
+impl TraceContext {
+    pub fn write_to(&self, msg: &mut MsgBuffer) {
+        msg.set_start(msg.get_start() - TRACE_HEADER_LEN);
+        let header = msg.message_mut();
+        header[..16].copy_from_slice(&self.trace_id);
+        header[16..TRACE_HEADER_LEN].copy_from_slice(&self.span_id);
+    }
+
+    pub fn read_from(msg: &mut MsgBuffer) -> Result<Self, Error> {
+        if msg.len() < TRACE_HEADER_LEN {
+            return Err(Error::Message("Trace header too short"));
+        }
+        let mut ctx = TraceContext { trace_id: [0; 16], span_id: [0; 8] };
+        ctx.trace_id.copy_from_slice(&msg.message()[..16]);
+        ctx.span_id.copy_from_slice(&msg.message()[16..TRACE_HEADER_LEN]);
+        msg.set_start(msg.get_start() + TRACE_HEADER_LEN);
+        Ok(ctx)
+    }
+}
+
+#[cfg(feature = "otel")]
+mod internal {
+    use std::net::SocketAddr;
+
+    use opentelemetry::{
+        trace::{
+            Span as _, SpanContext, SpanId, Status, TraceContextExt, TraceFlags, TraceId, TraceState, Tracer as _,
+            TracerProvider as _,
+        },
+        Context, KeyValue,
+    };
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{
+        trace::{SdkTracerProvider, Span, Tracer},
+        Resource,
+    };
+
+    use super::TraceContext;
+    use crate::{error::Error, util::addr_nice};
+
+    pub struct Telemetry {
+        inner: Option<(SdkTracerProvider, Tracer)>,
+    }
+
+    impl Telemetry {
+        pub fn new(endpoint: Option<&str>) -> Result<Self, Error> {
+            let endpoint = match endpoint {
+                Some(endpoint) => endpoint,
+                None => return Ok(Self { inner: None }),
+            };
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()
+                .map_err(|_| Error::InvalidConfig("Failed to create OpenTelemetry exporter"))?;
+            let provider = SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(Resource::builder().with_service_name("vpncloud").build())
+                .build();
+            let tracer = provider.tracer("vpncloud");
+            info!("Exporting traces to {}", endpoint);
+            Ok(Self { inner: Some((provider, tracer)) })
+        }
+
+        #[inline]
+        pub fn enabled(&self) -> bool {
+            self.inner.is_some()
+        }
+
+        #[inline]
+        pub fn span(&self, name: &'static str, parent: Option<TraceContext>) -> TraceSpan {
+            // HOT PATH
+            let tracer = match self.inner {
+                Some((_, ref tracer)) => tracer,
+                None => return TraceSpan(None),
+            };
+            let cx = match parent {
+                Some(parent) => Context::new().with_remote_span_context(SpanContext::new(
+                    TraceId::from_bytes(parent.trace_id),
+                    SpanId::from_bytes(parent.span_id),
+                    TraceFlags::SAMPLED,
+                    true,
+                    TraceState::default(),
+                )),
+                None => Context::new(),
+            };
+            TraceSpan(Some(tracer.start_with_context(name, &cx)))
+        }
+    }
+
+    impl Drop for Telemetry {
+        fn drop(&mut self) {
+            if let Some((ref provider, _)) = self.inner {
+                if let Err(err) = provider.shutdown() {
+                    warn!("Failed to flush traces: {}", err)
+                }
+            }
+        }
+    }
+
+    /// A span that is ended when dropped
+    pub struct TraceSpan(Option<Span>);
+
+    impl TraceSpan {
+        #[inline]
+        pub fn set_peer(&mut self, addr: SocketAddr) {
+            if let Some(ref mut span) = self.0 {
+                span.set_attribute(KeyValue::new("peer.address", addr_nice(addr).to_string()))
+            }
+        }
+
+        #[inline]
+        pub fn set_message_type(&mut self, type_: u8) {
+            if let Some(ref mut span) = self.0 {
+                span.set_attribute(KeyValue::new("vpncloud.message_type", i64::from(type_)))
+            }
+        }
+
+        #[inline]
+        pub fn set_size(&mut self, size: usize) {
+            if let Some(ref mut span) = self.0 {
+                span.set_attribute(KeyValue::new("vpncloud.payload_size", size as i64))
+            }
+        }
+
+        #[inline]
+        pub fn set_result<T>(&mut self, result: &Result<T, Error>) {
+            if let Some(ref mut span) = self.0 {
+                match result {
+                    Ok(_) => span.set_status(Status::Ok),
+                    Err(err) => span.set_status(Status::error(err.to_string())),
+                }
+            }
+        }
+
+        #[inline]
+        pub fn context(&self) -> Option<TraceContext> {
+            self.0.as_ref().map(|span| {
+                let cx = span.span_context();
+                TraceContext { trace_id: cx.trace_id().to_bytes(), span_id: cx.span_id().to_bytes() }
+            })
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod internal {
+    use std::net::SocketAddr;
+
+    use super::TraceContext;
+    use crate::error::Error;
+
+    pub struct Telemetry;
+
+    impl Telemetry {
+        pub fn new(endpoint: Option<&str>) -> Result<Self, Error> {
+            match endpoint {
+                Some(_) => Err(Error::InvalidConfig("OpenTelemetry is not supported by this build")),
+                None => Ok(Telemetry),
+            }
+        }
+
+        #[inline]
+        pub fn enabled(&self) -> bool {
+            false
+        }
+
+        #[inline]
+        pub fn span(&self, _name: &'static str, _parent: Option<TraceContext>) -> TraceSpan {
+            TraceSpan
+        }
+    }
+
+    pub struct TraceSpan;
+
+    impl TraceSpan {
+        #[inline]
+        pub fn set_peer(&mut self, _addr: SocketAddr) {}
+
+        #[inline]
+        pub fn set_message_type(&mut self, _type: u8) {}
+
+        #[inline]
+        pub fn set_size(&mut self, _size: usize) {}
+
+        #[inline]
+        pub fn set_result<T>(&mut self, _result: &Result<T, Error>) {}
+
+        #[inline]
+        pub fn context(&self) -> Option<TraceContext> {
+            None
+        }
+    }
+}
+
+pub use internal::*;
+
+#[test]
+fn trace_header() {
+    let ctx = TraceContext { trace_id: [1; 16], span_id: [2; 8] };
+    let mut msg = MsgBuffer::new(100);
+    msg.clone_from(&[3, 4, 5]);
+    ctx.write_to(&mut msg);
+    assert_eq!(msg.len(), 3 + TRACE_HEADER_LEN);
+    assert_eq!(TraceContext::read_from(&mut msg).unwrap(), ctx);
+    assert_eq!(msg.message(), &[3, 4, 5]);
+    msg.clone_from(&[1, 2, 3]);
+    assert!(TraceContext::read_from(&mut msg).is_err());
+}
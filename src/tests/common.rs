@@ -2,6 +2,7 @@
 // Copyright (C) 2015-2021  Dennis Schwerdel
 // This software is licensed under GPL-3 or newer (see LICENSE.md)
 
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{
     collections::{HashMap, VecDeque},
     io::Write,
@@ -13,12 +14,13 @@ use std::{
 };
 
 pub use crate::{
-    cloud::GenericCloud,
+    cloud::{CloudBuilder, GenericCloud},
     config::{Config, CryptoConfig},
+    crypto::PeerKeyEntry,
     device::{MockDevice, Type},
     net::MockSocket,
     payload::{Frame, Packet, Protocol},
-    types::Range,
+    types::AddressFamily,
     util::{MockTimeSource, Time, TimeSource},
 };
 
@@ -62,10 +64,62 @@ impl log::Log for DebugLogger {
 
 type TestNode<P> = GenericCloud<MockDevice, P, MockSocket, MockTimeSource>;
 
+type Message = (SocketAddr, SocketAddr, Vec<u8>);
+
+/// Impairments of the simulated network, like those of the netem queueing discipline of Linux
+///
+/// The delays are given in seconds as the simulation runs on the mock time.
+#[derive(Debug, Clone, Default)]
+pub struct Netem {
+    /// Fraction of the messages that are lost (0.0 - 1.0)
+    pub loss_rate: f64,
+    pub delay: Time,
+    /// Maximal random delay on top of the fixed delay
+    pub delay_jitter: Time,
+    /// Fraction of the messages that overtake the message before them (0.0 - 1.0)
+    pub reorder_fraction: f64,
+}
+
+struct Network {
+    messages: VecDeque<Message>,
+    delayed: Vec<(Time, Message)>,
+    netem: Netem,
+    rng: StdRng,
+}
+
+impl Network {
+    fn send(&mut self, src: SocketAddr, dst: SocketAddr, data: Vec<u8>) {
+        if self.netem.loss_rate > 0.0 && self.rng.gen::<f64>() < self.netem.loss_rate {
+            return
+        }
+        let mut delay = self.netem.delay;
+        if self.netem.delay_jitter > 0 {
+            delay += self.rng.gen_range(0..=self.netem.delay_jitter)
+        }
+        if delay > 0 {
+            self.delayed.push((MockTimeSource::now() + delay, (src, dst, data)));
+            return
+        }
+        if self.netem.reorder_fraction > 0.0 && self.rng.gen::<f64>() < self.netem.reorder_fraction {
+            let len = self.messages.len();
+            self.messages.insert(len.saturating_sub(1), (src, dst, data));
+            return
+        }
+        self.messages.push_back((src, dst, data))
+    }
+
+    /// Moves the delayed messages that are due to the queue
+    fn release(&mut self, now: Time) {
+        let (due, delayed) = self.delayed.drain(..).partition::<Vec<_>, _>(|(time, _)| *time <= now);
+        self.delayed = delayed;
+        self.messages.extend(due.into_iter().map(|(_, msg)| msg));
+    }
+}
+
 pub struct Simulator<P: Protocol> {
     next_port: u16,
     nodes: HashMap<SocketAddr, TestNode<P>>,
-    messages: VecDeque<(SocketAddr, SocketAddr, Vec<u8>)>,
+    network: Network,
 }
 
 pub type TapSimulator = Simulator<Frame>;
@@ -76,7 +130,13 @@ impl<P: Protocol> Simulator<P> {
     pub fn new() -> Self {
         init_debug_logger();
         MockTimeSource::set_time(0);
-        Self { next_port: 1, nodes: HashMap::default(), messages: VecDeque::with_capacity(10) }
+        let network = Network {
+            messages: VecDeque::with_capacity(10),
+            delayed: vec![],
+            netem: Netem::default(),
+            rng: StdRng::seed_from_u64(0),
+        };
+        Self { next_port: 1, nodes: HashMap::default(), network }
     }
 
     pub fn add_node(&mut self, nat: bool, config: &Config) -> SocketAddr {
@@ -87,14 +147,25 @@ impl<P: Protocol> Simulator<P> {
         if config.crypto.password.is_none() && config.crypto.private_key.is_none() {
             config.crypto.password = Some("test123".to_string())
         }
+        // Keep the key derivation cheap as every node derives its keys
+        if config.crypto.argon2_memory_kib.is_none() {
+            config.crypto.argon2_memory_kib = Some(64);
+            config.crypto.argon2_iterations = Some(1);
+        }
         DebugLogger::set_node(self.next_port as usize);
         self.next_port += 1;
-        let node = TestNode::new(&config, MockSocket::new(addr), MockDevice::new(), None, None);
+        let node =
+            CloudBuilder::new().config(config).socket(MockSocket::new(addr)).device(MockDevice::new()).build().unwrap();
         DebugLogger::set_node(0);
         self.nodes.insert(addr, node);
         addr
     }
 
+    #[allow(dead_code)]
+    pub fn remove_node(&mut self, addr: SocketAddr) {
+        self.nodes.remove(&addr);
+    }
+
     #[allow(dead_code)]
     pub fn get_node(&mut self, addr: SocketAddr) -> &mut TestNode<P> {
         let node = self.nodes.get_mut(&addr).unwrap();
@@ -103,7 +174,7 @@ impl<P: Protocol> Simulator<P> {
     }
 
     pub fn simulate_next_message(&mut self) {
-        if let Some((src, dst, data)) = self.messages.pop_front() {
+        if let Some((src, dst, data)) = self.network.messages.pop_front() {
             if let Some(node) = self.nodes.get_mut(&dst) {
                 if node.socket().put_inbound(src, data) {
                     DebugLogger::set_node(node.get_num());
@@ -112,7 +183,7 @@ impl<P: Protocol> Simulator<P> {
                     let sock = node.socket();
                     let src = dst;
                     while let Some((dst, data)) = sock.pop_outbound() {
-                        self.messages.push_back((src, dst, data));
+                        self.network.send(src, dst, data);
                     }
                 }
             } else {
@@ -122,7 +193,7 @@ impl<P: Protocol> Simulator<P> {
     }
 
     pub fn simulate_all_messages(&mut self) {
-        while !self.messages.is_empty() {
+        while !self.network.messages.is_empty() {
             self.simulate_next_message()
         }
     }
@@ -134,7 +205,7 @@ impl<P: Protocol> Simulator<P> {
         DebugLogger::set_node(0);
         let sock = node.socket();
         while let Some((dst, data)) = sock.pop_outbound() {
-            self.messages.push_back((addr, dst, data));
+            self.network.send(addr, dst, data);
         }
     }
 
@@ -145,7 +216,7 @@ impl<P: Protocol> Simulator<P> {
             DebugLogger::set_node(0);
             let sock = node.socket();
             while let Some((dst, data)) = sock.pop_outbound() {
-                self.messages.push_back((*src, dst, data));
+                self.network.send(*src, dst, data);
             }
         }
     }
@@ -159,11 +230,17 @@ impl<P: Protocol> Simulator<P> {
         while t < time {
             t += 1;
             self.set_time(t);
+            self.network.release(t);
             self.trigger_housekeep();
             self.simulate_all_messages();
         }
     }
 
+    /// Impairs all messages that are sent from now on
+    pub fn set_netem(&mut self, netem: Netem) {
+        self.network.netem = netem;
+    }
+
     pub fn connect(&mut self, src: SocketAddr, dst: SocketAddr) {
         let node = self.nodes.get_mut(&src).unwrap();
         DebugLogger::set_node(node.get_num());
@@ -171,7 +248,7 @@ impl<P: Protocol> Simulator<P> {
         DebugLogger::set_node(0);
         let sock = node.socket();
         while let Some((dst, data)) = sock.pop_outbound() {
-            self.messages.push_back((src, dst, data));
+            self.network.send(src, dst, data);
         }
     }
 
@@ -186,7 +263,7 @@ impl<P: Protocol> Simulator<P> {
 
     #[allow(dead_code)]
     pub fn message_count(&self) -> usize {
-        self.messages.len()
+        self.network.messages.len()
     }
 
     pub fn put_payload(&mut self, addr: SocketAddr, data: Vec<u8>) {
@@ -197,7 +274,7 @@ impl<P: Protocol> Simulator<P> {
         DebugLogger::set_node(0);
         let sock = node.socket();
         while let Some((dst, data)) = sock.pop_outbound() {
-            self.messages.push_back((addr, dst, data));
+            self.network.send(addr, dst, data);
         }
     }
 
@@ -206,6 +283,11 @@ impl<P: Protocol> Simulator<P> {
     }
 
     pub fn drop_message(&mut self) {
-        self.messages.pop_front();
+        self.network.messages.pop_front();
+    }
+
+    /// Swaps the next two messages, as if they overtook each other on the way
+    pub fn swap_messages(&mut self) {
+        self.network.messages.swap(0, 1);
     }
 }
@@ -0,0 +1,142 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use super::{common::*, transport::*};
+use crate::util::addr_nice;
+
+type LoopbackNode = GenericCloud<SimulationDevice, Packet, LoopbackTransport, MockTimeSource>;
+
+fn create_node(config: &Config, transport: LoopbackTransport) -> LoopbackNode {
+    let mut config = config.clone();
+    config.device_type = Type::Tun;
+    config.crypto.password = Some("test123".to_string());
+    config.crypto.argon2_memory_kib = Some(64);
+    config.crypto.argon2_iterations = Some(1);
+    CloudBuilder::new().config(config).socket(transport).device(SimulationDevice::new(Type::Tun)).build().unwrap()
+}
+
+fn create_nodes(config1: &Config, config2: &Config) -> (LoopbackNode, LoopbackNode) {
+    init_debug_logger();
+    MockTimeSource::set_time(0);
+    let (transport1, transport2) = LoopbackTransport::pair();
+    (create_node(config1, transport1), create_node(config2, transport2))
+}
+
+fn run_until_idle(node1: &mut LoopbackNode, node2: &mut LoopbackNode) {
+    loop {
+        let pending1 = node1.run_step();
+        let pending2 = node2.run_step();
+        if !pending1 && !pending2 {
+            break
+        }
+    }
+}
+
+fn connected_nodes(config1: &Config, config2: &Config) -> (LoopbackNode, LoopbackNode) {
+    let (mut node1, mut node2) = create_nodes(config1, config2);
+    let addr2 = node1.socket().peer_address();
+    node1.connect(addr2).unwrap();
+    run_until_idle(&mut node1, &mut node2);
+    (node1, node2)
+}
+
+#[test]
+fn loopback_init_handshake() {
+    let config = Config::default();
+    let (mut node1, mut node2) = connected_nodes(&config, &config);
+    let (addr1, addr2) = (node2.socket().peer_address(), node1.socket().peer_address());
+    assert!(node1.is_connected(&addr2));
+    assert!(node2.is_connected(&addr1));
+    assert_eq!(node1.peer_count(), 1);
+    assert_eq!(node2.peer_count(), 1);
+}
+
+#[test]
+fn loopback_peer_list_exchange() {
+    let config1 = Config { auto_claim: false, claims: vec!["1.1.1.0/24".to_string()], ..Config::default() };
+    let mut config2 = Config { auto_claim: false, claims: vec!["2.2.2.0/24".to_string()], ..Config::default() };
+    config2.local_tags.insert("role".to_string(), "gateway".to_string());
+    let (mut node1, mut node2) = connected_nodes(&config1, &config2);
+    // The node info is sent again periodically
+    MockTimeSource::advance(60);
+    run_until_idle(&mut node1, &mut node2);
+    let stats = node1.stats_snapshot();
+    assert_eq!(stats.peers.len(), 1);
+    assert_eq!(stats.peers[0].tags.get("role").map(String::as_str), Some("gateway"));
+    assert!(stats.table.claims.iter().any(|claim| claim.addr == "2.2.2.0/24"));
+    assert!(node2.stats_snapshot().table.claims.iter().any(|claim| claim.addr == "1.1.1.0/24"));
+}
+
+#[test]
+fn loopback_data_forwarding() {
+    let config1 = Config { auto_claim: false, claims: vec!["1.1.1.1/32".to_string()], ..Config::default() };
+    let config2 = Config { auto_claim: false, claims: vec!["2.2.2.2/32".to_string()], ..Config::default() };
+    let (mut node1, mut node2) = connected_nodes(&config1, &config2);
+
+    let packet1 = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    let packet2 = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 2, 2, 1, 1, 1, 1];
+    node1.device().inject(packet1.clone());
+    node2.device().inject(packet2.clone());
+    run_until_idle(&mut node1, &mut node2);
+    assert_eq!(node2.device().written(), &[packet1]);
+    assert_eq!(node1.device().written(), &[packet2]);
+}
+
+#[test]
+fn loopback_snapshots() {
+    let config1 = Config { auto_claim: false, claims: vec!["1.1.1.1/32".to_string()], ..Config::default() };
+    let config2 = Config { auto_claim: false, claims: vec!["2.2.2.2/32".to_string()], ..Config::default() };
+    let (mut node1, mut node2) = connected_nodes(&config1, &config2);
+    let packet = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    node1.device().inject(packet.clone());
+    run_until_idle(&mut node1, &mut node2);
+
+    let addr2 = node1.socket().peer_address();
+    let peers = node1.peers_snapshot();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].addr, addr_nice(addr2).to_string());
+    assert_eq!(peers[0].node_id.len(), 32);
+    assert!(peers[0].ttl_secs > 0);
+    assert!(peers[0].tx_bytes > packet.len() as u64);
+    assert!(peers[0].rx_bytes > 0);
+    assert_eq!(peers[0].rtt_ms, None);
+    // The snapshot can be serialized and stays valid after the node changed
+    let json = serde_json::to_value(&peers).unwrap();
+    assert_eq!(json[0]["tx_bytes"], peers[0].tx_bytes);
+    node1.trigger_shutdown();
+    assert_eq!(peers.len(), 1);
+
+    let table = node1.table_snapshot();
+    assert_eq!(table.len(), 1);
+    assert_eq!(table[0].address, "2.2.2.2");
+    assert_eq!(table[0].prefix_len, 32);
+    assert_eq!(table[0].peer, addr_nice(addr2).to_string());
+}
+
+#[test]
+fn loopback_close() {
+    let config = Config::default();
+    let (mut node1, mut node2) = connected_nodes(&config, &config);
+    let addr1 = node2.socket().peer_address();
+    assert!(node2.is_connected(&addr1));
+    node1.trigger_shutdown();
+    run_until_idle(&mut node1, &mut node2);
+    assert!(!node2.is_connected(&addr1));
+    assert_eq!(node2.peer_count(), 0);
+}
+
+#[test]
+fn loopback_stop() {
+    let config = Config::default();
+    let (mut node1, mut node2) = connected_nodes(&config, &config);
+    let addr1 = node2.socket().peer_address();
+    let handle = node1.stop_handle();
+    handle.clone().stop();
+    assert!(handle.is_stopped());
+    // The node stops at the next housekeeping and closes the connection
+    MockTimeSource::advance(1);
+    assert!(!node1.run_step());
+    run_until_idle(&mut node1, &mut node2);
+    assert!(!node2.is_connected(&addr1));
+}
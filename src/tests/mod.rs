@@ -3,6 +3,8 @@
 // This software is licensed under GPL-3 or newer (see LICENSE.md)
 
 mod common;
+mod loopback;
 mod nat;
 mod payload;
-mod peers;
\ No newline at end of file
+mod peers;
+pub mod transport;
\ No newline at end of file
@@ -71,3 +71,62 @@ fn nat_keepalive() {
     assert!(sim.is_connected(node2, node3));
     assert!(sim.is_connected(node3, node2));
 }
+
+#[test]
+fn hole_punch_via_peer() {
+    let config = Config { port_forwarding: false, ..Default::default() };
+    let mut sim = TapSimulator::new();
+    let relay = sim.add_node(false, &config);
+    let node1 = sim.add_node(true, &config);
+    let node2 = sim.add_node(true, &config);
+
+    sim.connect(node1, relay);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, relay));
+
+    // Node 2 learns about node 1 from the relay but node 1 would only learn about node 2 with the next peer list
+    sim.connect(node2, relay);
+    sim.simulate_time(10);
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+}
+
+#[test]
+fn no_hole_punch() {
+    let config = Config { port_forwarding: false, hole_punch: false, ..Default::default() };
+    let mut sim = TapSimulator::new();
+    let relay = sim.add_node(false, &config);
+    let node1 = sim.add_node(true, &config);
+    let node2 = sim.add_node(true, &config);
+
+    sim.connect(node1, relay);
+    sim.simulate_all_messages();
+    sim.connect(node2, relay);
+    sim.simulate_time(10);
+    assert!(!sim.is_connected(node1, node2));
+    assert!(!sim.is_connected(node2, node1));
+}
+
+#[test]
+fn nat_keepalive_to_punched_peers() {
+    let config = Config { port_forwarding: false, ..Default::default() };
+    let mut sim = TapSimulator::new();
+    let relay = sim.add_node(false, &config);
+    let node1 = sim.add_node(true, &config);
+    let node2 = sim.add_node(true, &config);
+
+    sim.connect(node1, relay);
+    sim.simulate_all_messages();
+    sim.connect(node2, relay);
+    sim.simulate_time(10);
+    assert!(sim.is_connected(node2, node1));
+    assert_eq!(sim.get_node(node2).traffic().keepalive_packets_sent, 0);
+
+    // Keepalives are only sent after the idle period and only to the punched peer
+    sim.simulate_time(20);
+    assert_eq!(sim.get_node(node2).traffic().keepalive_packets_sent, 0);
+    sim.simulate_time(30);
+    assert_eq!(sim.get_node(node2).traffic().keepalive_packets_sent, 1);
+    assert_eq!(sim.get_node(relay).traffic().keepalive_packets_sent, 0);
+    assert!(sim.is_connected(node1, node2));
+}
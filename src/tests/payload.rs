@@ -114,6 +114,138 @@ fn switch_honours_vlans() {
     assert_eq!(Some(payload), sim.pop_payload(node3));
 }
 
+fn igmp_frame(dst_mac: [u8; 6], dst_ip: [u8; 4], type_: u8) -> Vec<u8> {
+    let mut frame = dst_mac.to_vec();
+    frame.extend_from_slice(&[2, 2, 2, 2, 2, 2, 0x08, 0x00]);
+    frame.extend_from_slice(&[0x45, 0, 0, 28, 0, 0, 0, 0, 1, 2, 0, 0, 10, 0, 0, 2]);
+    frame.extend_from_slice(&dst_ip);
+    frame.extend_from_slice(&[type_, 0, 0, 0, 239, 1, 2, 3]);
+    frame
+}
+
+#[test]
+fn switch_snoops_multicast_groups() {
+    let config = Config { device_type: Type::Tap, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+    let node3 = sim.add_node(false, &config);
+
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.connect(node2, node3);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node1, node3));
+
+    // Node 2 joins 239.1.2.3, the report is flooded
+
+    let join = igmp_frame([1, 0, 0x5e, 1, 2, 3], [239, 1, 2, 3], 0x16);
+    sim.put_payload(node2, join.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(join.clone()), sim.pop_payload(node1));
+    assert_eq!(Some(join), sim.pop_payload(node3));
+
+    // Data for the group is only sent to node 2
+
+    let payload = vec![1, 0, 0x5e, 1, 2, 3, 1, 1, 1, 1, 1, 1, 1, 2, 3, 4, 5];
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload.clone()), sim.pop_payload(node2));
+    assert_eq!(None, sim.pop_payload(node3));
+
+    // After node 2 left the group, data is flooded again
+
+    let leave = igmp_frame([1, 0, 0x5e, 0, 0, 2], [224, 0, 0, 2], 0x17);
+    sim.put_payload(node2, leave.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(leave), sim.pop_payload(node1));
+    sim.pop_payload(node3);
+
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload.clone()), sim.pop_payload(node2));
+    assert_eq!(Some(payload), sim.pop_payload(node3));
+}
+
+fn arp_frame(oper: u8, sender: ([u8; 6], [u8; 4]), target: ([u8; 6], [u8; 4])) -> Vec<u8> {
+    let mut frame = if oper == 1 { vec![0xff; 6] } else { target.0.to_vec() };
+    frame.extend_from_slice(&sender.0);
+    frame.extend_from_slice(&[0x08, 0x06, 0, 1, 0x08, 0x00, 6, 4, 0, oper]);
+    frame.extend_from_slice(&sender.0);
+    frame.extend_from_slice(&sender.1);
+    frame.extend_from_slice(&target.0);
+    frame.extend_from_slice(&target.1);
+    frame
+}
+
+#[test]
+fn switch_proxies_arp() {
+    let config = Config { device_type: Type::Tap, arp_proxy: true, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+    let node3 = sim.add_node(false, &config);
+
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.connect(node2, node3);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node1, node3));
+
+    // The address is unknown, the request is flooded
+
+    let request = arp_frame(1, ([1; 6], [10, 0, 0, 1]), ([0; 6], [10, 0, 0, 2]));
+    sim.put_payload(node1, request.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(request.clone()), sim.pop_payload(node2));
+    assert_eq!(Some(request.clone()), sim.pop_payload(node3));
+
+    let reply = arp_frame(2, ([2; 6], [10, 0, 0, 2]), ([1; 6], [10, 0, 0, 1]));
+    sim.put_payload(node2, reply.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(reply.clone()), sim.pop_payload(node1));
+
+    // Node 1 learned the address from the reply and answers the request itself
+
+    sim.put_payload(node1, request);
+    assert_eq!(0, sim.message_count());
+    assert_eq!(Some(reply), sim.pop_payload(node1));
+    assert_eq!(None, sim.pop_payload(node2));
+    assert_eq!(None, sim.pop_payload(node3));
+}
+
+#[test]
+fn switch_fragments_large_frames() {
+    let config = Config { device_type: Type::Tap, mtu: Some(100), ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+
+    // The frame exceeds the MTU plus the Ethernet header and is sent in 3 fragments
+
+    let mut payload = vec![2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2];
+    payload.extend((0..300).map(|i| i as u8));
+    sim.put_payload(node1, payload.clone());
+    assert_eq!(3, sim.message_count());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+
+    // Frames that fit are sent in one piece
+
+    let payload = vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 5, 4, 3, 2, 1];
+    sim.put_payload(node2, payload.clone());
+    assert_eq!(1, sim.message_count());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node1));
+}
+
 #[test]
 #[ignore]
 fn switch_forgets() {
@@ -152,6 +284,257 @@ fn router_delivers() {
     assert_eq!(Some(payload), sim.pop_payload(node2));
 }
 
+#[test]
+fn router_keeps_claims() {
+    let config1 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["1.1.1.1/32".to_string()],
+        peer_timeout: 150,
+        ..Config::default()
+    };
+    let config2 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["2.2.2.2/32".to_string()],
+        peer_timeout: 150,
+        ..Config::default()
+    };
+    let mut sim = TunSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    // Without membership changes, the peers only exchange keepalives between full peer lists
+    sim.simulate_time(250);
+    assert!(sim.is_connected(node1, node2));
+
+    let payload = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+}
+
+#[test]
+fn router_measures_latency() {
+    let config1 = Config { device_type: Type::Tun, auto_claim: false, latency_routing: true, ..Config::default() };
+    let config2 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["2.2.2.0/24".to_string()],
+        ..Config::default()
+    };
+    let mut sim = TunSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+    let node3 = sim.add_node(false, &config2);
+
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.simulate_all_messages();
+    assert_eq!(sim.get_node(node1).peer_rtt(&node2), None);
+    sim.simulate_time(1);
+    assert!(sim.get_node(node1).peer_rtt(&node2).is_some());
+    assert!(sim.get_node(node1).peer_rtt(&node3).is_some());
+
+    // Only one of the peers claiming the destination gets the packet
+    let payload = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    let received = [sim.pop_payload(node2), sim.pop_payload(node3)];
+    assert_eq!(received.iter().filter(|p| p.as_ref() == Some(&payload)).count(), 1);
+}
+
+#[test]
+fn router_skips_excluded_routes() {
+    let config1 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["1.1.1.1/32".to_string()],
+        excluded_routes: vec!["10.1.0.0/16".to_string()],
+        ..Config::default()
+    };
+    let config2 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["10.0.0.0/8".to_string()],
+        ..Config::default()
+    };
+    let mut sim = TunSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    let payload = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 10, 1, 2, 3];
+    sim.put_payload(node1, payload);
+    sim.simulate_all_messages();
+    assert_eq!(None, sim.pop_payload(node2));
+
+    let payload = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 10, 2, 2, 3];
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+}
+
+#[test]
+fn router_follows_policy_rules() {
+    let config1 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["10.1.0.0/16".to_string(), "10.2.0.0/16".to_string()],
+        policy_rules: vec![
+            "from 10.1.0.0/16 table 1".parse().unwrap(),
+            "from 10.2.0.0/16 table 2".parse().unwrap(),
+            "from 10.4.0.0/16 table 3".parse().unwrap(),
+        ],
+        policy_tables: vec![
+            "1 via 10.0.0.2".parse().unwrap(),
+            "2 via 10.0.0.3".parse().unwrap(),
+            "3 via 10.0.0.4".parse().unwrap(),
+        ],
+        ..Config::default()
+    };
+    let config2 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["10.0.0.2/32".to_string()],
+        ..Config::default()
+    };
+    let config3 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["10.0.0.3/32".to_string(), "10.9.0.0/16".to_string()],
+        ..Config::default()
+    };
+    let mut sim = TunSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+    let node3 = sim.add_node(false, &config3);
+
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node1, node3));
+
+    // Packets to the same destination are routed by their source
+    let payload1 = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 1, 0, 1, 10, 9, 0, 1];
+    let payload2 = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 2, 0, 1, 10, 9, 0, 1];
+
+    sim.put_payload(node1, payload1.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload1), sim.pop_payload(node2));
+    assert_eq!(None, sim.pop_payload(node3));
+
+    sim.put_payload(node1, payload2.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload2), sim.pop_payload(node3));
+    assert_eq!(None, sim.pop_payload(node2));
+
+    // Other sources use the main table
+    let payload3 = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 3, 0, 1, 10, 9, 0, 1];
+    sim.put_payload(node1, payload3.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload3), sim.pop_payload(node3));
+
+    // Packets of tables without a reachable gateway are dropped instead of using the main table
+    let payload4 = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 4, 0, 1, 10, 9, 0, 1];
+    sim.put_payload(node1, payload4);
+    sim.simulate_all_messages();
+    assert_eq!(None, sim.pop_payload(node3));
+    assert_eq!(sim.get_node(node1).stats_snapshot().traffic.dropped_payload.packets, 1);
+}
+
+#[test]
+fn router_pads_data() {
+    let config1 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["1.1.1.1/32".to_string()],
+        pad_to: Some(200),
+        pad_amount: 16,
+        ..Config::default()
+    };
+    let config2 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["2.2.2.2/32".to_string()],
+        ..Config::default()
+    };
+    let mut sim = TunSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    let payload = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+    let traffic = sim.get_node(node1).stats_snapshot().traffic;
+    assert!(traffic.padded_bytes >= 180 && traffic.padded_bytes <= 196);
+    assert_eq!(traffic.unpadded_packets, 0);
+
+    // Packets larger than the padding size are sent without padding
+    let mut payload = vec![0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    payload.resize(300, 0);
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+    assert_eq!(sim.get_node(node1).stats_snapshot().traffic.unpadded_packets, 1);
+}
+
+#[test]
+fn router_applies_acl() {
+    let config1 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["1.1.1.0/24".to_string()],
+        acl: vec!["deny proto 6".parse().unwrap()],
+        ..Config::default()
+    };
+    let config2 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["2.2.2.2/32".to_string()],
+        acl: vec!["allow from 1.1.1.1/32".parse().unwrap(), "deny from 1.1.1.0/24".parse().unwrap()],
+        ..Config::default()
+    };
+    let mut sim = TunSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    // Outbound TCP packets are dropped by node 1
+    let payload = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    sim.put_payload(node1, payload);
+    sim.simulate_all_messages();
+    assert_eq!(None, sim.pop_payload(node2));
+
+    let payload = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+
+    // Inbound packets from other sources are dropped by node 2
+    let payload = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0, 1, 1, 1, 2, 2, 2, 2, 2];
+    sim.put_payload(node1, payload);
+    sim.simulate_all_messages();
+    assert_eq!(None, sim.pop_payload(node2));
+    assert_eq!(sim.get_node(node2).stats_snapshot().traffic.dropped_payload.packets, 1);
+}
+
 #[test]
 fn router_drops_unknown_dest() {
     let config1 = Config {
@@ -182,3 +565,175 @@ fn router_drops_unknown_dest() {
 
     assert_eq!(None, sim.pop_payload(node2));
 }
+
+#[test]
+fn path_mtu_discovery() {
+    let config = Config { device_type: Type::Tap, pmtu_discovery: true, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+    sim.get_node(node1).socket().set_path_mtu(Some(1000));
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+
+    // The MTU probe sent after connecting revealed the path MTU
+    assert_eq!(sim.get_node(node1).stats_snapshot().peers[0].path_mtu, Some(1000));
+    assert_eq!(sim.get_node(node2).stats_snapshot().peers[0].path_mtu, None);
+
+    // Larger packets are fragmented instead of dropped
+    let mut payload = vec![2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2];
+    payload.resize(1200, 5);
+
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+}
+
+#[test]
+fn router_marks_dscp() {
+    let config1 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["1.1.1.1/32".to_string()],
+        dscp_inherit: true,
+        ..Config::default()
+    };
+    let config2 = Config {
+        device_type: Type::Tun,
+        auto_claim: false,
+        claims: vec!["2.2.2.2/32".to_string()],
+        dscp: Some(10),
+        ..Config::default()
+    };
+    let mut sim = TunSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert_eq!(sim.get_node(node1).socket().last_tos(), 0);
+    assert_eq!(sim.get_node(node2).socket().last_tos(), 10 << 2);
+
+    // The DSCP value of the tunneled packet is copied to the outer packet
+    let payload = vec![0x45, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2];
+    sim.put_payload(node1, payload.clone());
+    assert_eq!(sim.get_node(node1).socket().last_tos(), 0xb8);
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node2));
+
+    // The static DSCP value is used for all packets
+    let payload = vec![0x45, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 2, 2, 1, 1, 1, 1];
+    sim.put_payload(node2, payload.clone());
+    assert_eq!(sim.get_node(node2).socket().last_tos(), 10 << 2);
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload), sim.pop_payload(node1));
+}
+
+#[test]
+fn peer_keys_deliver() {
+    let ids =
+        ["11111111111111111111111111111111", "22222222222222222222222222222222", "33333333333333333333333333333333"];
+    let key = |node: usize, key: &str| PeerKeyEntry { node_id: ids[node].to_string(), key: key.to_string() };
+    let config = |node: usize, peer_keys: Vec<PeerKeyEntry>| Config {
+        device_type: Type::Tap,
+        node_id: Some(ids[node].to_string()),
+        crypto: CryptoConfig { peer_keys, ..CryptoConfig::default() },
+        ..Config::default()
+    };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config(0, vec![key(1, "key12"), key(2, "key13")]));
+    let node2 = sim.add_node(false, &config(1, vec![key(0, "key12")]));
+    let node3 = sim.add_node(false, &config(2, vec![key(0, "key13")]));
+
+    sim.connect(node1, node2);
+    sim.connect(node3, node1);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+    assert!(sim.is_connected(node1, node3));
+    assert!(sim.is_connected(node3, node1));
+
+    let payload = vec![2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2, 3, 4, 5];
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload.clone()), sim.pop_payload(node2));
+    assert_eq!(Some(payload), sim.pop_payload(node3));
+
+    // A node with the wrong peer key can not connect
+    let node4 = sim.add_node(false, &config(2, vec![key(0, "wrong")]));
+    sim.connect(node4, node1);
+    sim.simulate_all_messages();
+    assert!(!sim.is_connected(node1, node4));
+    assert!(!sim.is_connected(node4, node1));
+}
+
+#[test]
+fn reorder_buffer_delivers_in_order() {
+    let config = Config { device_type: Type::Tap, reorder_buffer: true, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    let payload = |n| vec![2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2, 3, 4, n];
+    // The first message starts the sequence
+    sim.put_payload(node1, payload(0));
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload(0)), sim.pop_payload(node2));
+    sim.put_payload(node1, payload(1));
+    sim.put_payload(node1, payload(2));
+    sim.swap_messages();
+    sim.simulate_next_message();
+    assert_eq!(None, sim.pop_payload(node2));
+    sim.simulate_next_message();
+    assert_eq!(Some(payload(1)), sim.pop_payload(node2));
+    assert_eq!(Some(payload(2)), sim.pop_payload(node2));
+
+    // A lost message is given up on after the timeout
+    sim.put_payload(node1, payload(3));
+    sim.put_payload(node1, payload(4));
+    sim.drop_message();
+    sim.simulate_next_message();
+    assert_eq!(None, sim.pop_payload(node2));
+    MockTimeSource::advance_millis(60);
+    sim.put_payload(node1, payload(5));
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload(4)), sim.pop_payload(node2));
+    assert_eq!(Some(payload(5)), sim.pop_payload(node2));
+}
+
+#[test]
+fn reorder_buffer_with_netem() {
+    let config = Config { device_type: Type::Tap, reorder_buffer: true, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    let payload = |n| vec![2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2, 3, 4, n];
+    sim.put_payload(node1, payload(0));
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload(0)), sim.pop_payload(node2));
+
+    // Every message overtakes the one before it
+    sim.set_netem(Netem { reorder_fraction: 1.0, ..Netem::default() });
+    for n in 1..=5 {
+        sim.put_payload(node1, payload(n));
+    }
+    sim.simulate_all_messages();
+    for n in 1..=5 {
+        assert_eq!(Some(payload(n)), sim.pop_payload(node2));
+    }
+    assert_eq!(None, sim.pop_payload(node2));
+}
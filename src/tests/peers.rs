@@ -33,6 +33,67 @@ fn direct_connect_unencrypted() {
     assert!(sim.is_connected(node2, node1));
 }
 
+#[test]
+fn ipv6_only() {
+    let config = Config { address_family: AddressFamily::Ipv6Only, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+
+    sim.get_node(node1).connect("1.2.3.4:3210").unwrap();
+    assert!(sim.get_node(node1).socket().pop_outbound().is_none());
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+}
+
+#[test]
+fn peer_tags() {
+    let mut config = Config::default();
+    config.local_tags.insert("role".to_string(), "gateway".to_string());
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &Config::default());
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    let peers = sim.get_node(node2).stats_snapshot().peers;
+    assert_eq!(peers[0].tags.get("role").map(String::as_str), Some("gateway"));
+    assert!(sim.get_node(node1).stats_snapshot().peers[0].tags.is_empty());
+}
+
+#[test]
+fn max_peers() {
+    // Avoid the expensive key derivation and speed tests for each node
+    let (private_key, public_key) = crate::crypto::Crypto::generate_keypair(None);
+    let crypto = CryptoConfig {
+        private_key: Some(private_key),
+        public_key: Some(public_key),
+        algorithms: vec!["AES128".to_string()],
+        ..CryptoConfig::default()
+    };
+    let config = Config { max_peers: Some(100), crypto: crypto.clone(), ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node = sim.add_node(false, &config);
+    // The other nodes only connect to the first node and not to each other
+    let other_config = Config { max_peers: Some(1), crypto, ..Config::default() };
+    let others: Vec<_> = (0..101).map(|_| sim.add_node(false, &other_config)).collect();
+
+    for other in &others[..100] {
+        sim.connect(*other, node);
+        sim.simulate_all_messages();
+    }
+    assert_eq!(sim.get_node(node).peer_count(), 100);
+
+    sim.connect(others[100], node);
+    sim.simulate_all_messages();
+    assert!(!sim.is_connected(node, others[100]));
+    assert!(!sim.is_connected(others[100], node));
+    assert_eq!(sim.get_node(node).peer_count(), 100);
+}
+
 #[test]
 fn cross_connect() {
     let config = Config::default();
@@ -95,6 +156,107 @@ fn reconnect_after_timeout() {
     assert!(sim.is_connected(node2, node1));
 }
 
+#[test]
+fn reconnect_peer_add_remove() {
+    let config = Config::default();
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+    let address = node2.to_string();
+
+    sim.get_node(node1).add_reconnect_peer(address.clone());
+    let peers = sim.get_node(node1).reconnect_peers_list();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].snapshot().address, Some(address.clone()));
+    sim.trigger_node_housekeep(node1);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    assert!(sim.get_node(node1).remove_reconnect_peer(&address));
+    assert!(!sim.get_node(node1).remove_reconnect_peer(&address));
+    assert!(sim.get_node(node1).reconnect_peers_list().is_empty());
+    // The connection stays until the peer is disconnected explicitly
+    sim.simulate_time(100);
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+
+    sim.get_node(node1).add_reconnect_peer(address);
+    assert_eq!(sim.get_node(node1).reconnect_peers_list().len(), 1);
+}
+
+#[test]
+fn dead_peer_detection() {
+    let config = Config { dpd_probe_interval: 30, dpd_retries: 3, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+    let node3 = sim.add_node(false, &config);
+
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.simulate_all_messages();
+
+    // Idle peers answer the probes
+    sim.simulate_time(100);
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node1, node3));
+    assert_eq!(sim.get_node(node1).traffic().dpd_evictions_total, 0);
+
+    // Peers that receive but never send are removed long before the peer timeout
+    sim.remove_node(node3);
+    sim.simulate_time(160);
+    assert!(sim.is_connected(node1, node2));
+    assert!(!sim.is_connected(node1, node3));
+    assert!(!sim.is_connected(node2, node3));
+    assert_eq!(sim.get_node(node1).traffic().dpd_evictions_total, 1);
+}
+
+#[test]
+fn dead_peer_detection_with_loss() {
+    let config = Config { dpd_probe_interval: 30, dpd_retries: 3, ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    // Some lost probes are retried
+    sim.set_netem(Netem { loss_rate: 0.3, ..Netem::default() });
+    sim.simulate_time(100);
+    assert!(sim.is_connected(node1, node2));
+
+    sim.set_netem(Netem { loss_rate: 1.0, ..Netem::default() });
+    sim.simulate_time(250);
+    assert!(!sim.is_connected(node1, node2));
+    assert_eq!(sim.get_node(node1).traffic().dpd_evictions_total, 1);
+}
+
+#[test]
+fn reconnect_after_loss() {
+    let config = Config::default();
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+
+    sim.get_node(node1).add_reconnect_peer(node2.to_string());
+    sim.trigger_node_housekeep(node1);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+
+    sim.set_netem(Netem { loss_rate: 1.0, ..Netem::default() });
+    sim.simulate_time(config.peer_timeout as Time + 60);
+    assert!(!sim.is_connected(node1, node2));
+    assert!(!sim.is_connected(node2, node1));
+
+    // The reconnect attempts get through once the network recovers, even with delays
+    sim.set_netem(Netem { delay: 1, delay_jitter: 2, ..Netem::default() });
+    sim.simulate_time(config.peer_timeout as Time + 300);
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+}
+
 #[test]
 fn lost_init_ping() {
     let config = Config::default();
@@ -177,3 +339,359 @@ fn automatic_peer_timeout() {
     // TODO Test
     unimplemented!()
 }
+
+#[cfg(all(feature = "rest-api", feature = "websocket"))]
+#[test]
+fn api_event_stream() {
+    use crate::util::addr_nice;
+    use std::{net::TcpStream, time::Duration};
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config = Config { api_addr: Some(format!("127.0.0.1:{}", port)), ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &Config::default());
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let (mut client, _) = tungstenite::client(format!("ws://127.0.0.1:{}/api/events", port), stream).unwrap();
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    let event: serde_json::Value = serde_json::from_str(&client.read_message().unwrap().into_text().unwrap()).unwrap();
+    assert_eq!(event["event"], "PeerConnected");
+    assert_eq!(event["peer"], addr_nice(node2).to_string());
+}
+
+#[cfg(feature = "snmp")]
+#[test]
+fn snmp_peer_count() {
+    use crate::snmp::{scalar, Message, Value, OBJECT_PEER_COUNT, PDU_GET, PDU_RESPONSE, PDU_TRAP};
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        time::Duration,
+    };
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let traps = UdpSocket::bind("127.0.0.1:0").unwrap();
+    traps.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let config = Config {
+        snmp_addr: Some(SocketAddr::from(([127, 0, 0, 1], port))),
+        snmp_trap_receiver: Some(traps.local_addr().unwrap()),
+        ..Config::default()
+    };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &Config::default());
+
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    let mut buffer = [0; 1500];
+    let size = traps.recv(&mut buffer).unwrap();
+    let trap = Message::decode(&buffer[..size]).unwrap();
+    assert_eq!(trap.pdu_type, PDU_TRAP);
+    assert_eq!(trap.varbinds[1].1, Value::Oid(vec![1, 3, 6, 1, 3, 3210, 2, 1]));
+    sim.trigger_node_housekeep(node1);
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut request = Message {
+        community: b"public".to_vec(),
+        pdu_type: PDU_GET,
+        request_id: 1,
+        error_status: 0,
+        error_index: 0,
+        varbinds: vec![(scalar(OBJECT_PEER_COUNT), Value::Null)],
+    };
+    client.send_to(&request.encode(), ("127.0.0.1", port)).unwrap();
+    let size = client.recv(&mut buffer).unwrap();
+    let response = Message::decode(&buffer[..size]).unwrap();
+    assert_eq!(response.pdu_type, PDU_RESPONSE);
+    assert_eq!(response.request_id, 1);
+    assert_eq!(response.varbinds, [(scalar(OBJECT_PEER_COUNT), Value::Gauge32(1))]);
+    // Requests with the wrong community are not answered
+    request.community = b"private".to_vec();
+    client.send_to(&request.encode(), ("127.0.0.1", port)).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    assert!(client.recv(&mut buffer).is_err());
+}
+
+#[test]
+fn tofu_rejects_changed_key() {
+    use crate::{crypto::Crypto, tofu::TofuMode};
+    let dir = tempfile::tempdir().unwrap();
+    let keys: Vec<_> = (0..3).map(|_| Crypto::generate_keypair(None)).collect();
+    let config = |node_id: &str, key: usize| Config {
+        node_id: Some(node_id.to_string()),
+        crypto: CryptoConfig {
+            private_key: Some(keys[key].0.clone()),
+            trusted_keys: keys.iter().map(|k| k.1.clone()).collect(),
+            ..CryptoConfig::default()
+        },
+        ..Config::default()
+    };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(
+        false,
+        &Config {
+            tofu_store: Some(dir.path().join("tofu.json").to_str().unwrap().to_string()),
+            tofu_mode: TofuMode::AllowFirst,
+            ..config("11111111111111111111111111111111", 0)
+        },
+    );
+    let node2 = sim.add_node(false, &config("22222222222222222222222222222222", 1));
+    // Same node id but another key
+    let node3 = sim.add_node(false, &config("22222222222222222222222222222222", 2));
+
+    sim.connect(node2, node1);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node2, node1));
+
+    sim.connect(node3, node1);
+    sim.simulate_all_messages();
+    assert!(!sim.is_connected(node1, node3));
+    assert!(!sim.is_connected(node3, node1));
+}
+
+#[test]
+fn tofu_pins_identity_keys() {
+    use crate::{
+        crypto::Crypto,
+        identity::Identity,
+        tofu::TofuMode,
+        util::{bytes_to_hex, from_base62},
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    // Keys with leading zeros do not survive the base62 encoding
+    let keys: Vec<_> = (0..)
+        .map(|_| Crypto::generate_keypair(None))
+        .filter(|(p, k)| from_base62(p).unwrap().len() == 32 && from_base62(k).unwrap().len() == 32)
+        .take(3)
+        .collect();
+    let config = |identity: &str, key: usize| Config {
+        identity_key: Some(path(identity)),
+        crypto: CryptoConfig {
+            private_key: Some(keys[key].0.clone()),
+            trusted_keys: keys.iter().map(|k| k.1.clone()).collect(),
+            ..CryptoConfig::default()
+        },
+        ..Config::default()
+    };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(
+        false,
+        &Config { tofu_store: Some(path("tofu.json")), tofu_mode: TofuMode::AllowFirst, ..config("node1.key", 0) },
+    );
+    let node2 = sim.add_node(false, &config("node2.key", 1));
+    // Random node id but the identity of node2 with another key
+    let node3 = sim.add_node(false, &config("node2.key", 2));
+    let node4 = sim.add_node(false, &config("node4.key", 2));
+
+    sim.connect(node2, node1);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    let identity = bytes_to_hex(&Identity::load_or_create(&path("node2.key")).unwrap().public_key());
+    let store: serde_json::Value = serde_json::from_slice(&std::fs::read(path("tofu.json")).unwrap()).unwrap();
+    assert!(store.get(&identity).is_some());
+
+    sim.connect(node3, node1);
+    sim.simulate_all_messages();
+    assert!(!sim.is_connected(node1, node3));
+
+    sim.connect(node4, node1);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node4));
+}
+
+#[test]
+fn mtu_negotiation() {
+    use crate::util::addr_nice;
+    use std::net::SocketAddr;
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &Config { mtu: Some(1300), ..Config::default() });
+    // Mock devices have no MTU, so the default is assumed
+    let node2 = sim.add_node(false, &Config::default());
+    let node3 = sim.add_node(false, &Config { mtu: Some(1500), ..Config::default() });
+
+    sim.connect(node1, node2);
+    sim.connect(node3, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node3, node2));
+    let stats = sim.get_node(node1).stats_snapshot();
+    assert_eq!((stats.mtu.device, stats.mtu.effective), (1400, 1300));
+    assert_eq!(stats.peers[0].mtu, Some(1300));
+    // The configured MTU is limited to the device MTU
+    assert_eq!(sim.get_node(node3).stats_snapshot().mtu.effective, 1400);
+    let stats = sim.get_node(node2).stats_snapshot();
+    let peer_mtu = |addr: SocketAddr| stats.peers.iter().find(|p| p.addr == addr_nice(addr).to_string()).unwrap().mtu;
+    assert_eq!(peer_mtu(node1), Some(1300));
+    assert_eq!(peer_mtu(node3), Some(1400));
+}
+
+#[test]
+fn plaintext_peers() {
+    use crate::util::addr_nice;
+    use std::net::SocketAddr;
+    let addr = |port: u16| SocketAddr::from(([0u16; 8], port));
+    let config = |peers: Vec<SocketAddr>| Config {
+        plaintext_peers: peers,
+        require_encryption: false,
+        device_type: Type::Tap,
+        ..Config::default()
+    };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config(vec![addr(2), addr(3)]));
+    let node2 = sim.add_node(false, &config(vec![addr(1)]));
+    // Node 3 does not disable encryption, so its messages stay encrypted
+    let node3 = sim.add_node(false, &Config { device_type: Type::Tap, ..Config::default() });
+    assert_eq!((node2, node3), (addr(2), addr(3)));
+
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node1, node3));
+    let stats = sim.get_node(node1).stats_snapshot();
+    let peer = |addr: SocketAddr| stats.peers.iter().find(|p| p.addr == addr_nice(addr).to_string()).unwrap();
+    assert_eq!((peer(node2).encrypted, peer(node2).crypto.as_str()), (false, "PLAIN"));
+    assert!(peer(node3).encrypted);
+
+    let payload = vec![2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 2, 3, 4, 5];
+    sim.put_payload(node1, payload.clone());
+    sim.simulate_all_messages();
+    assert_eq!(Some(payload.clone()), sim.pop_payload(node2));
+    assert_eq!(Some(payload), sim.pop_payload(node3));
+}
+
+#[test]
+fn gossip_convergence() {
+    let fanout = 10;
+    let config = Config { gossip_fanout: fanout, gossip_interval: Some(10), ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let nodes: Vec<_> = (0..100).map(|_| sim.add_node(false, &config)).collect();
+    // All nodes only know the first node
+    for &node in &nodes[1..] {
+        sim.connect(node, nodes[0]);
+    }
+    sim.simulate_all_messages();
+    let rounds = ((nodes.len() as f64).log2() / (fanout as f64).log2()).ceil() as Time;
+    // Peer lists are sent once the interval has fully passed
+    sim.simulate_time(rounds * 10 + 1);
+    for &node in &nodes {
+        for &other in &nodes {
+            assert!(node == other || sim.is_connected(node, other));
+        }
+    }
+}
+
+#[test]
+fn peer_list_updates() {
+    let config = Config { gossip_interval: Some(10), ..Config::default() };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    sim.simulate_time(12);
+    // New peers get a full peer list first
+    assert_eq!(sim.get_node(node1).traffic().gossip_messages_sent_total, 1);
+    assert_eq!(sim.get_node(node1).traffic().gossip_updates_sent_total, 0);
+
+    // Other peers only get the new peer
+    let node3 = sim.add_node(false, &config);
+    sim.connect(node3, node1);
+    sim.simulate_all_messages();
+    sim.simulate_time(22);
+    assert!(sim.is_connected(node2, node3));
+    assert_eq!(sim.get_node(node1).traffic().gossip_updates_sent_total, 1);
+    let full_lists = sim.get_node(node1).traffic().gossip_messages_sent_total;
+
+    // Without changes, no peer lists are sent until the full interval has passed
+    sim.simulate_time(100);
+    assert_eq!(sim.get_node(node1).traffic().gossip_messages_sent_total, full_lists);
+    assert_eq!(sim.get_node(node1).traffic().gossip_updates_sent_total, 1);
+    sim.simulate_time(700);
+    assert!(sim.get_node(node1).traffic().gossip_messages_sent_total > full_lists);
+}
+
+#[test]
+fn peer_list_updates_report_timeouts() {
+    // Without dead peer detection, peers are only removed by their timeout
+    let config = Config {
+        gossip_interval: Some(10),
+        gossip_full_interval_secs: 3600,
+        dpd_probe_interval: 0,
+        ..Config::default()
+    };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &config);
+    let node3 = sim.add_node(false, &config);
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.simulate_all_messages();
+    sim.simulate_time(100);
+    let updates = sim.get_node(node1).traffic().gossip_updates_sent_total;
+
+    // Peers that time out are sent as removed peers, not only with the next full peer list
+    sim.remove_node(node3);
+    sim.simulate_time(config.peer_timeout as Time + 120);
+    assert!(!sim.is_connected(node1, node3));
+    assert!(sim.get_node(node1).traffic().gossip_updates_sent_total > updates);
+}
+
+#[test]
+fn negotiate_features() {
+    use crate::messages::Feature;
+    let config1 = Config { hole_punch: false, ..Config::default() };
+    let config2 = Config::default();
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config1);
+    let node2 = sim.add_node(false, &config2);
+    sim.connect(node1, node2);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    // Both sides only use the extensions that both of them support
+    for (node, peer) in [(node1, node2), (node2, node1)] {
+        let features = sim.get_node(node).peer_features(&peer).unwrap();
+        assert!(!features.has(Feature::HolePunch));
+        assert!(!features.has(Feature::Tracing));
+        assert!(features.has(Feature::Ping));
+        assert!(features.has(Feature::PeerUpdates));
+    }
+}
+
+#[test]
+fn peer_state_file() {
+    use crate::util::addr_nice;
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        peer_state_file: Some(dir.path().join("peers.state").to_str().unwrap().to_string()),
+        ..Config::default()
+    };
+    let mut sim = TapSimulator::new();
+    let node1 = sim.add_node(false, &config);
+    let node2 = sim.add_node(false, &Config::default());
+    let node3 = sim.add_node(false, &Config::default());
+
+    sim.connect(node1, node2);
+    sim.connect(node1, node3);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node1, node2));
+    assert!(sim.is_connected(node1, node3));
+    sim.get_node(node1).trigger_shutdown();
+    sim.remove_node(node1);
+
+    // The restarted node reconnects to the saved peers without being told
+    let node4 = sim.add_node(false, &config);
+    let mut saved: Vec<_> =
+        sim.get_node(node4).reconnect_peers_list().iter().flat_map(|e| e.snapshot().resolved).collect();
+    saved.sort();
+    assert_eq!(saved, [addr_nice(node2).to_string(), addr_nice(node3).to_string()]);
+    sim.trigger_node_housekeep(node4);
+    sim.simulate_all_messages();
+    assert!(sim.is_connected(node4, node2));
+    assert!(sim.is_connected(node4, node3));
+}
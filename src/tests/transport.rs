@@ -0,0 +1,198 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    collections::VecDeque,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::io::{AsRawFd, RawFd},
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use crate::{
+    device::{Device, Type},
+    error::Error,
+    net::{mapped_addr, Socket},
+    port_forwarding::PortForwarding,
+    types::AddressFamily,
+    util::MsgBuffer,
+};
+
+/// One end of an in-memory connection between two nodes
+///
+/// Everything sent to the address of the other end arrives there in order, messages to other addresses are lost like
+/// they would be without a receiver.
+pub struct LoopbackTransport {
+    address: SocketAddr,
+    peer: SocketAddr,
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    next: Option<Vec<u8>>,
+}
+
+impl LoopbackTransport {
+    /// Creates two connected ends, one for each node
+    pub fn pair() -> (Self, Self) {
+        let addr1 = mapped_addr(SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 3210)));
+        let addr2 = mapped_addr(SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 3210)));
+        let (tx1, rx2) = channel();
+        let (tx2, rx1) = channel();
+        (
+            Self { address: addr1, peer: addr2, tx: tx1, rx: rx1, next: None },
+            Self { address: addr2, peer: addr1, tx: tx2, rx: rx2, next: None },
+        )
+    }
+
+    /// The address of the other end
+    pub fn peer_address(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns whether a message can be received
+    pub fn has_pending(&mut self) -> bool {
+        if self.next.is_none() {
+            self.next = self.rx.try_recv().ok();
+        }
+        self.next.is_some()
+    }
+}
+
+impl AsRawFd for LoopbackTransport {
+    fn as_raw_fd(&self) -> RawFd {
+        unimplemented!()
+    }
+}
+
+impl Socket for LoopbackTransport {
+    fn listen(_addr: &str, _family: AddressFamily) -> Result<Self, io::Error> {
+        Err(io::Error::other("loopback transports can only be created in pairs"))
+    }
+
+    fn receive(&mut self, buffer: &mut MsgBuffer) -> Result<SocketAddr, io::Error> {
+        if self.has_pending() {
+            let data = self.next.take().unwrap();
+            buffer.clear();
+            buffer.set_length(data.len());
+            buffer.message_mut().copy_from_slice(&data);
+            Ok(self.peer)
+        } else {
+            Err(io::Error::other("nothing in queue"))
+        }
+    }
+
+    fn send(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize, io::Error> {
+        if addr == self.peer {
+            // The other end may be gone already, just like a UDP receiver
+            self.tx.send(data.into()).ok();
+        }
+        Ok(data.len())
+    }
+
+    fn address(&self) -> Result<SocketAddr, io::Error> {
+        Ok(self.address)
+    }
+
+    fn create_port_forwarding(&self) -> Option<PortForwarding> {
+        None
+    }
+
+    fn set_dont_fragment(&mut self, _enabled: bool) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn path_mtu(&self, _addr: SocketAddr) -> Result<usize, io::Error> {
+        Err(io::Error::from_raw_os_error(libc::ENOTCONN))
+    }
+
+    fn set_tos(&mut self, _tos: u8) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn send_with_tos(&mut self, data: &[u8], addr: SocketAddr, _tos: u8) -> Result<usize, io::Error> {
+        self.send(data, addr)
+    }
+
+    fn set_recv_buffer_size(&mut self, size: usize) -> Result<usize, io::Error> {
+        Ok(size)
+    }
+
+    fn set_send_buffer_size(&mut self, size: usize) -> Result<usize, io::Error> {
+        Ok(size)
+    }
+}
+
+/// A device that records all written packets and reads injected ones
+pub struct SimulationDevice {
+    type_: Type,
+    reads: VecDeque<Vec<u8>>,
+    written: Vec<Vec<u8>>,
+}
+
+impl SimulationDevice {
+    pub fn new(type_: Type) -> Self {
+        Self { type_, reads: VecDeque::new(), written: vec![] }
+    }
+
+    /// Queues a packet that the node reads from the device
+    pub fn inject(&mut self, data: Vec<u8>) {
+        self.reads.push_back(data)
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.reads.is_empty()
+    }
+
+    /// All packets that have been written to the device so far
+    pub fn written(&self) -> &[Vec<u8>] {
+        &self.written
+    }
+}
+
+impl AsRawFd for SimulationDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        unimplemented!()
+    }
+}
+
+impl Device for SimulationDevice {
+    fn get_type(&self) -> Type {
+        self.type_
+    }
+
+    fn ifname(&self) -> &str {
+        "sim0"
+    }
+
+    fn read(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error> {
+        if let Some(data) = self.reads.pop_front() {
+            buffer.clear();
+            buffer.set_length(data.len());
+            buffer.message_mut().copy_from_slice(&data);
+            Ok(())
+        } else {
+            Err(Error::Device("empty"))
+        }
+    }
+
+    fn write(&mut self, buffer: &mut MsgBuffer) -> Result<(), Error> {
+        self.written.push(buffer.message().into());
+        Ok(())
+    }
+
+    fn get_ip(&self) -> Result<Ipv4Addr, Error> {
+        Err(Error::Device("Simulated devices have no IP address"))
+    }
+
+    fn get_mtu(&self) -> io::Result<u16> {
+        Err(io::Error::other("Simulated devices have no MTU"))
+    }
+
+    fn set_mac(&self, _mac: [u8; 6]) -> io::Result<()> {
+        Err(io::Error::other("Simulated devices have no MAC address"))
+    }
+
+    fn get_mac(&self) -> io::Result<[u8; 6]> {
+        Err(io::Error::other("Simulated devices have no MAC address"))
+    }
+}
@@ -0,0 +1,165 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, Write},
+    marker::PhantomData,
+    path::Path,
+    str::FromStr,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::Error,
+    util::{bytes_to_hex, Time, TimeSource},
+};
+
+/// How peers are treated that are not in the store yet
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TofuMode {
+    /// Pin the key of the first connection
+    AllowFirst,
+    /// Reject the peer until it is trusted explicitly
+    DenyUnknown,
+}
+
+impl FromStr for TofuMode {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match &text.to_lowercase().replace('-', "_") as &str {
+            "allow_first" => Ok(Self::AllowFirst),
+            "deny_unknown" => Ok(Self::DenyUnknown),
+            _ => Err("Unknown TOFU mode"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TofuEntry {
+    pub fingerprint: String,
+    pub first_seen: Time,
+}
+
+/// Key fingerprints pinned to peer identities (trust on first use)
+///
+/// Peers are identified by their identity key if they have one and by their node id otherwise.
+/// The store is saved as JSON whenever it changes. It is written to a temporary file that then replaces the
+/// store, so a crash never leaves a partially written store behind.
+pub struct TofuStore<TS: TimeSource> {
+    path: String,
+    mode: TofuMode,
+    entries: BTreeMap<String, TofuEntry>,
+    /// The fingerprints of the last rejected connection of each peer, those can be trusted explicitly
+    rejected: HashMap<String, String>,
+    _dummy: PhantomData<TS>,
+}
+
+impl<TS: TimeSource> TofuStore<TS> {
+    pub fn open(path: &str, mode: TofuMode) -> Result<Self, Error> {
+        let entries = if Path::new(path).exists() {
+            let data = fs::read(path).map_err(|e| Error::FileIo { message: "Failed to read TOFU store", source: e })?;
+            serde_json::from_slice(&data).map_err(|_| Error::Parse("Failed to parse TOFU store"))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { path: path.to_string(), mode, entries, rejected: HashMap::new(), _dummy: PhantomData })
+    }
+
+    /// Checks the fingerprint of a peer against the pinned one, pinning it if the peer is new
+    pub fn check(&mut self, peer_id: &[u8], fingerprint: &[u8]) -> Result<(), Error> {
+        let peer_id = bytes_to_hex(peer_id);
+        let fingerprint = bytes_to_hex(fingerprint);
+        match self.entries.get(&peer_id) {
+            Some(entry) if entry.fingerprint == fingerprint => Ok(()),
+            Some(entry) => {
+                warn!(
+                    "KEY MISMATCH: peer {} connected with key {} but key {} is pinned, this could be an impersonation \
+                     attempt!",
+                    peer_id, fingerprint, entry.fingerprint
+                );
+                self.rejected.insert(peer_id, fingerprint);
+                Err(Error::AuthFailed("Peer key does not match pinned key"))
+            }
+            None if self.mode == TofuMode::DenyUnknown => {
+                warn!("Rejecting unknown peer {} with key {}", peer_id, fingerprint);
+                self.rejected.insert(peer_id, fingerprint);
+                Err(Error::AuthFailed("Unknown peer"))
+            }
+            None => {
+                info!("Pinning key {} for peer {}", fingerprint, peer_id);
+                self.entries.insert(peer_id, TofuEntry { fingerprint, first_seen: TS::now() });
+                self.save()
+            }
+        }
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, TofuEntry> {
+        &self.entries
+    }
+
+    /// Removes the pinned key of a peer, returns whether there was one
+    pub fn remove(&mut self, peer_id: &str) -> Result<bool, Error> {
+        if self.entries.remove(peer_id).is_none() {
+            return Ok(false)
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Pins the key of the last rejected connection of a peer, returns whether there was one
+    pub fn trust(&mut self, peer_id: &str) -> Result<bool, Error> {
+        let fingerprint = match self.rejected.remove(peer_id) {
+            Some(fingerprint) => fingerprint,
+            None => return Ok(false),
+        };
+        info!("Pinning key {} for peer {} as requested", fingerprint, peer_id);
+        self.entries.insert(peer_id.to_string(), TofuEntry { fingerprint, first_seen: TS::now() });
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        self.write().map_err(|e| Error::FileIo { message: "Failed to write TOFU store", source: e })
+    }
+
+    fn write(&self) -> Result<(), io::Error> {
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(&self.entries)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+#[test]
+fn tofu_store() {
+    use crate::util::MockTimeSource;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tofu.json");
+    let path = path.to_str().unwrap();
+    MockTimeSource::set_time(1000);
+    let mut store = TofuStore::<MockTimeSource>::open(path, TofuMode::AllowFirst).unwrap();
+    store.check(&[1; 16], &[1; 32]).unwrap();
+    store.check(&[1; 16], &[1; 32]).unwrap();
+    assert!(store.check(&[1; 16], &[2; 32]).is_err());
+    // The store is persisted
+    let mut store = TofuStore::<MockTimeSource>::open(path, TofuMode::DenyUnknown).unwrap();
+    assert_eq!(store.entries()["01010101010101010101010101010101"].first_seen, 1000);
+    store.check(&[1; 16], &[1; 32]).unwrap();
+    assert!(store.check(&[2; 16], &[2; 32]).is_err());
+    assert!(store.trust("02020202020202020202020202020202").unwrap());
+    assert!(!store.trust("02020202020202020202020202020202").unwrap());
+    store.check(&[2; 16], &[2; 32]).unwrap();
+    assert!(store.remove("01010101010101010101010101010101").unwrap());
+    assert!(!store.remove("01010101010101010101010101010101").unwrap());
+    assert!(store.check(&[1; 16], &[1; 32]).is_err());
+    assert!(!Path::new(&format!("{}.tmp", path)).exists());
+    assert_eq!(TofuStore::<MockTimeSource>::open(path, TofuMode::DenyUnknown).unwrap().entries().len(), 1);
+}
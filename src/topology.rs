@@ -0,0 +1,147 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use serde::Serialize;
+
+use crate::{types::NodeId, util::bytes_to_hex};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopologyNode {
+    pub id: String,
+    /// Only known for the node itself and its direct peers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopologyEdge {
+    pub src: String,
+    pub dst: String,
+    pub rtt_ms: Option<u64>,
+}
+
+/// The part of the network that is known to a node
+///
+/// This consists of the node, its direct peers and the peers that those peers advertised in their last peer list.
+/// As the peer lists are limited by the gossip fan-out, building the graph takes time linear to the number of peers.
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+    pub edges: Vec<TopologyEdge>,
+    #[serde(skip)]
+    node_index: HashMap<NodeId, usize>,
+    #[serde(skip)]
+    edge_set: HashSet<(NodeId, NodeId)>,
+}
+
+impl Topology {
+    pub fn new(node_id: &NodeId, addr: Option<String>) -> Self {
+        let mut topology = Self::default();
+        topology.add_node(node_id, addr);
+        topology
+    }
+
+    fn add_node(&mut self, node_id: &NodeId, addr: Option<String>) {
+        match self.node_index.get(node_id) {
+            Some(&index) => {
+                if addr.is_some() {
+                    self.nodes[index].addr = addr
+                }
+            }
+            None => {
+                self.node_index.insert(*node_id, self.nodes.len());
+                self.nodes.push(TopologyNode { id: bytes_to_hex(node_id), addr })
+            }
+        }
+    }
+
+    fn add_edge(&mut self, src: &NodeId, dst: &NodeId, rtt_ms: Option<u64>) {
+        // Edges are undirected, so each connection is only listed once
+        let key = if src < dst { (*src, *dst) } else { (*dst, *src) };
+        if src == dst || !self.edge_set.insert(key) {
+            return
+        }
+        self.edges.push(TopologyEdge { src: bytes_to_hex(src), dst: bytes_to_hex(dst), rtt_ms })
+    }
+
+    /// Adds a direct peer of the node together with the peers that it advertised
+    pub fn add_peer(&mut self, own_id: &NodeId, node_id: &NodeId, addr: String, rtt_ms: Option<u64>, peers: &[NodeId]) {
+        self.add_node(node_id, Some(addr));
+        self.add_edge(own_id, node_id, rtt_ms);
+        for peer in peers {
+            self.add_node(peer, None);
+            self.add_edge(node_id, peer, None);
+        }
+    }
+
+    /// Formats the graph in the DOT language of Graphviz
+    ///
+    /// The nodes are labelled with their address and the round trip time of the edge from the first node.
+    pub fn to_dot(&self) -> String {
+        let own_id = match self.nodes.first() {
+            Some(node) => &node.id,
+            None => return "graph vpncloud {\n}\n".to_string(),
+        };
+        let rtts: HashMap<&str, u64> = self
+            .edges
+            .iter()
+            .filter(|edge| edge.src == *own_id)
+            .filter_map(|edge| edge.rtt_ms.map(|rtt| (edge.dst.as_str(), rtt)))
+            .collect();
+        let mut dot = "graph vpncloud {\n".to_string();
+        for node in &self.nodes {
+            let mut label = node.addr.clone().unwrap_or_else(|| node.id[..8].to_string());
+            if let Some(rtt) = rtts.get(node.id.as_str()) {
+                write!(label, "\\n{} ms", rtt).unwrap();
+            }
+            writeln!(dot, "  \"{}\" [label=\"{}\"];", node.id, label).unwrap();
+        }
+        for edge in &self.edges {
+            match edge.rtt_ms {
+                Some(rtt) => writeln!(dot, "  \"{}\" -- \"{}\" [label=\"{} ms\"];", edge.src, edge.dst, rtt),
+                None => writeln!(dot, "  \"{}\" -- \"{}\";", edge.src, edge.dst),
+            }
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[test]
+fn topology_graph() {
+    let own = [1; 16];
+    let peer1 = [2; 16];
+    let peer2 = [3; 16];
+    let remote = [4; 16];
+    let mut topology = Topology::new(&own, Some("10.0.0.1:3210".to_string()));
+    topology.add_peer(&own, &peer1, "10.0.0.2:3210".to_string(), Some(12), &[own, peer2, remote]);
+    topology.add_peer(&own, &peer2, "10.0.0.3:3210".to_string(), None, &[peer1, own]);
+    assert_eq!(topology.nodes.len(), 4);
+    // The second-hop node was first seen without an address
+    assert_eq!(topology.nodes[2], TopologyNode { id: bytes_to_hex(&peer2), addr: Some("10.0.0.3:3210".to_string()) });
+    assert_eq!(topology.nodes[3].addr, None);
+    // Own -> peer1, peer1 -> peer2, peer1 -> remote, own -> peer2
+    assert_eq!(topology.edges.len(), 4);
+    assert_eq!(
+        topology.edges[0],
+        TopologyEdge { src: bytes_to_hex(&own), dst: bytes_to_hex(&peer1), rtt_ms: Some(12) }
+    );
+    let json = serde_json::to_value(&topology).unwrap();
+    assert_eq!(json["nodes"][0]["addr"], "10.0.0.1:3210");
+    assert!(json["nodes"][3].get("addr").is_none());
+    assert!(json["edges"][1]["rtt_ms"].is_null());
+    assert!(json.get("node_index").is_none());
+    let dot = topology.to_dot();
+    assert!(dot.starts_with("graph vpncloud {\n"));
+    assert!(dot.contains(&format!("\"{}\" [label=\"10.0.0.2:3210\\n12 ms\"];", bytes_to_hex(&peer1))));
+    assert!(dot.contains(&format!("\"{}\" [label=\"04040404\"];", bytes_to_hex(&remote))));
+    assert!(dot.contains(&format!("\"{}\" -- \"{}\" [label=\"12 ms\"];", bytes_to_hex(&own), bytes_to_hex(&peer1))));
+    assert_eq!(dot.matches(" -- ").count(), 4);
+}
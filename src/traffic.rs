@@ -3,19 +3,27 @@
 // This software is licensed under GPL-3 or newer (see LICENSE.md)
 
 use std::{
-    collections::HashMap,
+    cmp::max,
+    collections::{HashMap, VecDeque},
     io::{self, Write},
     net::SocketAddr,
+    num::NonZeroUsize,
     ops::AddAssign,
 };
 
+use lru::LruCache;
+
 use super::{
     cloud::{Hash, STATS_INTERVAL},
+    stats::{
+        AddressTrafficSnapshot, PayloadTrafficSnapshot, PeerTrafficSnapshot, TrafficCounters, TrafficEntrySnapshot,
+        TrafficRateSnapshot, TrafficSnapshot,
+    },
     types::Address,
     util::{addr_nice, Bytes},
 };
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct TrafficEntry {
     pub out_bytes_total: u64,
     pub out_packets_total: usize,
@@ -54,6 +62,74 @@ impl TrafficEntry {
         self.in_bytes += bytes as u64;
     }
 
+    /// Bytes sent including the current period
+    #[inline]
+    pub fn out_bytes_sum(&self) -> u64 {
+        self.out_bytes_total + self.out_bytes
+    }
+
+    /// Packets sent including the current period
+    #[inline]
+    pub fn out_packets_sum(&self) -> usize {
+        self.out_packets_total + self.out_packets
+    }
+
+    /// Bytes received including the current period
+    #[inline]
+    pub fn in_bytes_sum(&self) -> u64 {
+        self.in_bytes_total + self.in_bytes
+    }
+
+    /// Packets received including the current period
+    #[inline]
+    pub fn in_packets_sum(&self) -> usize {
+        self.in_packets_total + self.in_packets
+    }
+
+    pub fn snapshot(&self) -> TrafficEntrySnapshot {
+        TrafficEntrySnapshot {
+            in_: TrafficCounters { bytes: self.in_bytes, packets: self.in_packets },
+            out: TrafficCounters { bytes: self.out_bytes, packets: self.out_packets },
+            in_total: TrafficCounters { bytes: self.in_bytes_sum(), packets: self.in_packets_sum() },
+            out_total: TrafficCounters { bytes: self.out_bytes_sum(), packets: self.out_packets_sum() },
+        }
+    }
+
+    /// Bytes in both directions including the current period
+    #[inline]
+    pub fn bytes_sum(&self) -> u64 {
+        self.in_bytes_sum() + self.out_bytes_sum()
+    }
+
+    /// The traffic of the current period without the totals
+    fn current(&self) -> TrafficEntry {
+        TrafficEntry {
+            out_bytes: self.out_bytes,
+            out_packets: self.out_packets,
+            in_bytes: self.in_bytes,
+            in_packets: self.in_packets,
+            ..TrafficEntry::default()
+        }
+    }
+
+    /// The traffic of the current period per second, if it spans the given number of seconds
+    fn per_second(&self, secs: u64) -> TrafficEntry {
+        TrafficEntry {
+            out_bytes: self.out_bytes / secs,
+            out_packets: self.out_packets / secs as usize,
+            in_bytes: self.in_bytes / secs,
+            in_packets: self.in_packets / secs as usize,
+            ..TrafficEntry::default()
+        }
+    }
+
+    pub fn rate_snapshot(&self) -> TrafficRateSnapshot {
+        TrafficRateSnapshot {
+            in_: TrafficCounters { bytes: self.in_bytes, packets: self.in_packets },
+            out: TrafficCounters { bytes: self.out_bytes, packets: self.out_packets },
+        }
+    }
+
     fn period(&mut self) {
         self.out_bytes_total += self.out_bytes;
         self.out_packets_total += self.out_packets;
@@ -71,14 +147,171 @@ impl TrafficEntry {
     }
 }
 
+/// Gaps larger than this are treated as a new sequence instead of lost packets
+const MAX_SEQ_GAP: u64 = 1 << 16;
+
+/// Estimation of the packets lost on the way from a peer based on gaps in their sequence numbers
+///
+/// The sequence numbers contain the key id in the most significant byte, so a key change starts a new
+/// sequence instead of counting as a gap.
 #[derive(Default)]
+pub struct PacketLoss {
+    pub last_rx_seq: u64,
+    pub received_packets: u64,
+    pub lost_packets: u64,
+    pub lost_packets_total: u64,
+    pub last_period_lost: u64,
+    pub loss_percent: f32,
+}
+
+impl PacketLoss {
+    #[inline]
+    pub fn count(&mut self, seq: u64) {
+        // HOT PATH
+        self.received_packets += 1;
+        if self.last_rx_seq == 0 || self.last_rx_seq >> 56 != seq >> 56 {
+            self.last_rx_seq = seq;
+        } else if seq > self.last_rx_seq {
+            let gap = seq - self.last_rx_seq - 1;
+            if gap <= MAX_SEQ_GAP {
+                self.lost_packets += gap;
+            }
+            self.last_rx_seq = seq;
+        } else {
+            // Reordered packet that has been counted as lost before
+            self.lost_packets = self.lost_packets.saturating_sub(1);
+        }
+    }
+
+    pub fn period(&mut self) {
+        let expected = self.received_packets + self.lost_packets;
+        self.loss_percent = if expected > 0 { self.lost_packets as f32 * 100.0 / expected as f32 } else { 0.0 };
+        self.last_period_lost = self.lost_packets;
+        self.lost_packets_total += self.lost_packets;
+        self.received_packets = 0;
+        self.lost_packets = 0;
+    }
+}
+
+/// Number of stats periods in the rolling window, one hour at the stats interval of one minute
+pub const DEFAULT_WINDOW_SIZE: usize = 60;
+
+/// The peer traffic of the last stats periods, for rates that are less noisy than those of a single period
+pub struct RollingWindow {
+    samples: VecDeque<TrafficEntry>,
+    window_size: usize,
+}
+
+impl Default for RollingWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW_SIZE)
+    }
+}
+
+impl RollingWindow {
+    pub fn new(window_size: usize) -> Self {
+        Self { samples: VecDeque::with_capacity(window_size), window_size }
+    }
+
+    /// Adds the traffic of a finished period, the oldest period is evicted when the window is full
+    pub fn push(&mut self, sample: TrafficEntry) {
+        if self.samples.len() >= self.window_size {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Average traffic per second over the window
+    pub fn rate(&self) -> TrafficEntry {
+        if self.samples.is_empty() {
+            return TrafficEntry::default()
+        }
+        let mut sum = TrafficEntry::default();
+        for sample in &self.samples {
+            sum += sample;
+        }
+        sum.per_second(self.samples.len() as u64 * STATS_INTERVAL as u64)
+    }
+
+    /// Highest traffic per second of a single period in the window, individually for every counter
+    pub fn peak_rate(&self) -> TrafficEntry {
+        let mut peak = TrafficEntry::default();
+        for sample in &self.samples {
+            peak.out_bytes = max(peak.out_bytes, sample.out_bytes);
+            peak.out_packets = max(peak.out_packets, sample.out_packets);
+            peak.in_bytes = max(peak.in_bytes, sample.in_bytes);
+            peak.in_packets = max(peak.in_packets, sample.in_packets);
+        }
+        peak.per_second(STATS_INTERVAL as u64)
+    }
+}
+
+/// Number of addresses with the most traffic in the stats output
+pub const TOP_TALKERS: usize = 10;
+
+/// Payload traffic by a single address, the least recently active addresses are evicted when the cache is full
+type AddressTraffic = LruCache<Address, TrafficEntry, Hash>;
+
 pub struct TrafficStats {
     peers: HashMap<SocketAddr, TrafficEntry, Hash>,
     payload: HashMap<(Address, Address), TrafficEntry, Hash>,
+    /// Payload traffic by source address, in and out are the directions of the traffic as in `payload`
+    payload_by_src: AddressTraffic,
+    /// Payload traffic by destination address
+    payload_by_dst: AddressTraffic,
+    window: RollingWindow,
     pub dropped: TrafficEntry,
+    pub padded_bytes: u64,
+    pub unpadded_packets: usize,
+    pub influx_write_errors_total: u64,
+    pub dpd_evictions_total: u64,
+    pub keepalive_packets_sent: u64,
+    pub gossip_messages_sent_total: u64,
+    pub gossip_updates_sent_total: u64,
+    pub reorder_buffer_flushes_total: u64,
+    pub reorder_buffer_overflows_total: u64,
+    pub table_dump_calls_total: u64,
+    pub arp_proxy_hits_total: u64,
+    pub arp_proxy_misses_total: u64,
+    /// Sizes of the socket buffers as reported by the system, if they have been configured
+    pub socket_recv_buffer_bytes: Option<usize>,
+    pub socket_send_buffer_bytes: Option<usize>,
+}
+
+impl Default for TrafficStats {
+    fn default() -> Self {
+        Self::new(1000)
+    }
 }
 
 impl TrafficStats {
+    /// Creates the stats, keeping the traffic by address for at most `max_addresses` addresses
+    pub fn new(max_addresses: usize) -> Self {
+        let max_addresses = NonZeroUsize::new(max(max_addresses, 1)).unwrap();
+        Self {
+            peers: HashMap::default(),
+            payload: HashMap::default(),
+            payload_by_src: LruCache::with_hasher(max_addresses, Hash::default()),
+            payload_by_dst: LruCache::with_hasher(max_addresses, Hash::default()),
+            window: RollingWindow::default(),
+            dropped: TrafficEntry::default(),
+            padded_bytes: 0,
+            unpadded_packets: 0,
+            influx_write_errors_total: 0,
+            dpd_evictions_total: 0,
+            keepalive_packets_sent: 0,
+            gossip_messages_sent_total: 0,
+            gossip_updates_sent_total: 0,
+            reorder_buffer_flushes_total: 0,
+            reorder_buffer_overflows_total: 0,
+            table_dump_calls_total: 0,
+            arp_proxy_hits_total: 0,
+            arp_proxy_misses_total: 0,
+            socket_recv_buffer_bytes: None,
+            socket_send_buffer_bytes: None,
+        }
+    }
+
     #[inline]
     pub fn count_out_traffic(&mut self, peer: SocketAddr, bytes: usize) {
         // HOT PATH
@@ -95,12 +328,16 @@ impl TrafficStats {
     pub fn count_out_payload(&mut self, remote: Address, local: Address, bytes: usize) {
         // HOT PATH
         self.payload.entry((remote, local)).or_insert_with(TrafficEntry::default).count_out(bytes);
+        self.payload_by_src.get_or_insert_mut(local, TrafficEntry::default).count_out(bytes);
+        self.payload_by_dst.get_or_insert_mut(remote, TrafficEntry::default).count_out(bytes);
     }
 
     #[inline]
     pub fn count_in_payload(&mut self, remote: Address, local: Address, bytes: usize) {
         // HOT PATH
         self.payload.entry((remote, local)).or_insert_with(TrafficEntry::default).count_in(bytes);
+        self.payload_by_src.get_or_insert_mut(remote, TrafficEntry::default).count_in(bytes);
+        self.payload_by_dst.get_or_insert_mut(local, TrafficEntry::default).count_in(bytes);
     }
 
     pub fn count_invalid_protocol(&mut self, bytes: usize) {
@@ -111,17 +348,40 @@ impl TrafficStats {
         self.dropped.count_out(bytes)
     }
 
+    #[inline]
+    pub fn count_padding(&mut self, bytes: usize) {
+        self.padded_bytes += bytes as u64
+    }
+
+    /// Counts a packet that is too large to be padded to the configured size
+    pub fn count_unpadded(&mut self) {
+        self.unpadded_packets += 1
+    }
+
     pub fn period(&mut self, cleanup_idle: Option<usize>) {
+        self.window.push(self.total_peer_traffic().current());
         for entry in self.peers.values_mut() {
             entry.period();
         }
         for entry in self.payload.values_mut() {
             entry.period();
         }
+        for cache in &mut [&mut self.payload_by_src, &mut self.payload_by_dst] {
+            for (_, entry) in cache.iter_mut() {
+                entry.period();
+            }
+        }
         self.dropped.period();
         if let Some(periods) = cleanup_idle {
             self.peers.retain(|_, entry| entry.idle_periods < periods);
             self.payload.retain(|_, entry| entry.idle_periods < periods);
+            for cache in &mut [&mut self.payload_by_src, &mut self.payload_by_dst] {
+                let idle: Vec<_> =
+                    cache.iter().filter(|(_, entry)| entry.idle_periods >= periods).map(|(addr, _)| *addr).collect();
+                for addr in idle {
+                    cache.pop(&addr);
+                }
+            }
         }
     }
 
@@ -129,6 +389,20 @@ impl TrafficStats {
         self.peers.iter()
     }
 
+    /// Packets received from the peer including the current period
+    pub fn peer_in_packets(&self, peer: &SocketAddr) -> usize {
+        self.peers.get(peer).map(TrafficEntry::in_packets_sum).unwrap_or(0)
+    }
+
+    pub fn peer_out_packets(&self, peer: &SocketAddr) -> usize {
+        self.peers.get(peer).map(TrafficEntry::out_packets_sum).unwrap_or(0)
+    }
+
+    /// Bytes sent to and received from the peer including the current period
+    pub fn peer_bytes(&self, peer: &SocketAddr) -> (u64, u64) {
+        self.peers.get(peer).map(|entry| (entry.out_bytes_sum(), entry.in_bytes_sum())).unwrap_or((0, 0))
+    }
+
     pub fn get_payload_traffic(&self) -> impl Iterator<Item = (&(Address, Address), &TrafficEntry)> {
         self.payload.iter()
     }
@@ -141,6 +415,26 @@ impl TrafficStats {
         total
     }
 
+    /// The `n` source addresses that sent the most payload bytes, including the current period
+    pub fn top_talkers(&self, n: usize) -> Vec<(Address, TrafficEntry)> {
+        let mut talkers: Vec<_> = self.payload_by_src.iter().map(|(addr, data)| (*addr, data.clone())).collect();
+        talkers.sort_unstable_by(|(a1, d1), (a2, d2)| {
+            d2.bytes_sum().cmp(&d1.bytes_sum()).then_with(|| a1.to_string().cmp(&a2.to_string()))
+        });
+        talkers.truncate(n);
+        talkers
+    }
+
+    /// Average peer traffic per second over the rolling window
+    pub fn rate(&self) -> TrafficEntry {
+        self.window.rate()
+    }
+
+    /// Peak peer traffic per second over the rolling window
+    pub fn peak_rate(&self) -> TrafficEntry {
+        self.window.peak_rate()
+    }
+
     pub fn total_payload_traffic(&self) -> TrafficEntry {
         let mut total = TrafficEntry::default();
         for e in self.payload.values() {
@@ -149,6 +443,38 @@ impl TrafficStats {
         total
     }
 
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        let mut peers: Vec<_> = self
+            .get_peer_traffic()
+            .map(|(addr, data)| PeerTrafficSnapshot { peer: addr_nice(*addr).to_string(), traffic: data.snapshot() })
+            .collect();
+        peers.sort_unstable_by(|a, b| a.peer.cmp(&b.peer));
+        let mut payload: Vec<_> = self
+            .get_payload_traffic()
+            .map(|((remote, local), data)| PayloadTrafficSnapshot {
+                remote: remote.to_string(),
+                local: local.to_string(),
+                traffic: data.snapshot(),
+            })
+            .collect();
+        payload.sort_unstable_by(|a, b| (&a.remote, &a.local).cmp(&(&b.remote, &b.local)));
+        TrafficSnapshot {
+            peers,
+            payload,
+            invalid_protocol: TrafficCounters { bytes: self.dropped.in_bytes, packets: self.dropped.in_packets },
+            dropped_payload: TrafficCounters { bytes: self.dropped.out_bytes, packets: self.dropped.out_packets },
+            padded_bytes: self.padded_bytes,
+            unpadded_packets: self.unpadded_packets,
+            top_talkers: self
+                .top_talkers(TOP_TALKERS)
+                .into_iter()
+                .map(|(addr, data)| AddressTrafficSnapshot { address: addr.to_string(), traffic: data.snapshot() })
+                .collect(),
+            rate: self.rate().rate_snapshot(),
+            peak_rate: self.peak_rate().rate_snapshot(),
+        }
+    }
+
     #[inline]
     pub fn write_out<W: Write>(&self, out: &mut W) -> Result<(), io::Error> {
         writeln!(out, "peer_traffic:")?;
@@ -168,6 +494,22 @@ impl TrafficStats {
             )?;
         }
         writeln!(out)?;
+        writeln!(out, "peer_traffic_total:")?;
+        peers.sort_unstable_by_key(|(_, data)| data.out_bytes_sum() + data.in_bytes_sum());
+        for (addr, data) in peers.iter().rev() {
+            writeln!(
+                out,
+                "  - peer: \"{}\"\n    in: {{ display: \"{}\", bytes: {}, packets: {} }}\n    out: {{ display: \"{}\", bytes: {}, packets: {} }}",
+                addr_nice(**addr),
+                Bytes(data.in_bytes_sum()),
+                data.in_bytes_sum(),
+                data.in_packets_sum(),
+                Bytes(data.out_bytes_sum()),
+                data.out_bytes_sum(),
+                data.out_packets_sum()
+            )?;
+        }
+        writeln!(out)?;
         writeln!(out, "payload_traffic:")?;
         let mut payload: Vec<_> = self.get_payload_traffic().collect();
         payload.sort_unstable_by_key(|(_, data)| (data.out_bytes + data.in_bytes));
@@ -200,6 +542,325 @@ impl TrafficStats {
             self.dropped.out_bytes,
             self.dropped.out_packets
         )?;
+        writeln!(out, "padded_bytes: {}", self.padded_bytes)?;
+        writeln!(out, "unpadded_packets: {}", self.unpadded_packets)?;
+        writeln!(out, "influx_write_errors_total: {}", self.influx_write_errors_total)?;
+        writeln!(out, "dpd_evictions_total: {}", self.dpd_evictions_total)?;
+        writeln!(out, "keepalive_packets_sent: {}", self.keepalive_packets_sent)?;
+        writeln!(out, "gossip_messages_sent_total: {}", self.gossip_messages_sent_total)?;
+        writeln!(out, "gossip_updates_sent_total: {}", self.gossip_updates_sent_total)?;
+        writeln!(out, "reorder_buffer_flushes_total: {}", self.reorder_buffer_flushes_total)?;
+        writeln!(out, "reorder_buffer_overflows_total: {}", self.reorder_buffer_overflows_total)?;
+        writeln!(out, "table_dump_calls_total: {}", self.table_dump_calls_total)?;
+        writeln!(out, "arp_proxy_hits_total: {}", self.arp_proxy_hits_total)?;
+        writeln!(out, "arp_proxy_misses_total: {}", self.arp_proxy_misses_total)?;
+        if let Some(size) = self.socket_recv_buffer_bytes {
+            writeln!(out, "socket_recv_buffer_bytes: {}", size)?;
+        }
+        if let Some(size) = self.socket_send_buffer_bytes {
+            writeln!(out, "socket_send_buffer_bytes: {}", size)?;
+        }
         Ok(())
     }
+
+    /// Writes the counters in the Prometheus text format
+    pub fn write_prometheus<W: Write>(&self, out: &mut W) -> Result<(), io::Error> {
+        let counters: [PrometheusCounter; 4] = [
+            ("bytes_in", "Bytes received from peers", "Bytes received from the peer", |e| e.in_bytes_sum()),
+            ("bytes_out", "Bytes sent to peers", "Bytes sent to the peer", |e| e.out_bytes_sum()),
+            ("packets_in", "Packets received from peers", "Packets received from the peer", |e| {
+                e.in_packets_sum() as u64
+            }),
+            ("packets_out", "Packets sent to peers", "Packets sent to the peer", |e| e.out_packets_sum() as u64),
+        ];
+        let total = self.total_peer_traffic();
+        for (name, help, _, value) in &counters {
+            write_prometheus_header(out, &format!("vpncloud_{}_total", name), help)?;
+            writeln!(out, "vpncloud_{}_total {}", name, value(&total))?;
+        }
+        let rate = self.rate();
+        for (name, help, _, value) in &counters {
+            writeln!(out, "# HELP vpncloud_{}_per_second {} per second, averaged over the last hour", name, help)?;
+            writeln!(out, "# TYPE vpncloud_{}_per_second gauge", name)?;
+            writeln!(out, "vpncloud_{}_per_second {}", name, value(&rate))?;
+        }
+        write_prometheus_header(out, "vpncloud_dropped_packets_total", "Packets from the device that were dropped")?;
+        writeln!(out, "vpncloud_dropped_packets_total {}", self.dropped.out_packets_sum())?;
+        write_prometheus_header(out, "vpncloud_invalid_protocol_total", "Messages with an invalid protocol")?;
+        writeln!(out, "vpncloud_invalid_protocol_total {}", self.dropped.in_packets_sum())?;
+        write_prometheus_header(out, "vpncloud_influx_write_errors_total", "Stats that could not be sent to InfluxDB")?;
+        writeln!(out, "vpncloud_influx_write_errors_total {}", self.influx_write_errors_total)?;
+        write_prometheus_header(out, "vpncloud_dpd_evictions_total", "Peers removed by the dead peer detection")?;
+        writeln!(out, "vpncloud_dpd_evictions_total {}", self.dpd_evictions_total)?;
+        write_prometheus_header(out, "vpncloud_keepalive_packets_sent_total", "NAT keepalives sent to idle peers")?;
+        writeln!(out, "vpncloud_keepalive_packets_sent_total {}", self.keepalive_packets_sent)?;
+        write_prometheus_header(out, "vpncloud_gossip_messages_sent_total", "Peer lists sent to peers")?;
+        writeln!(out, "vpncloud_gossip_messages_sent_total {}", self.gossip_messages_sent_total)?;
+        write_prometheus_header(out, "vpncloud_gossip_updates_sent_total", "Peer list updates sent to peers")?;
+        writeln!(out, "vpncloud_gossip_updates_sent_total {}", self.gossip_updates_sent_total)?;
+        write_prometheus_header(
+            out,
+            "vpncloud_reorder_buffer_flushes_total",
+            "Times that held back packets were released because of the timeout",
+        )?;
+        writeln!(out, "vpncloud_reorder_buffer_flushes_total {}", self.reorder_buffer_flushes_total)?;
+        write_prometheus_header(
+            out,
+            "vpncloud_reorder_buffer_overflows_total",
+            "Times that held back packets were released because the buffer was full",
+        )?;
+        writeln!(out, "vpncloud_reorder_buffer_overflows_total {}", self.reorder_buffer_overflows_total)?;
+        write_prometheus_header(out, "vpncloud_table_dump_calls_total", "Dumps of the claim table via the API")?;
+        writeln!(out, "vpncloud_table_dump_calls_total {}", self.table_dump_calls_total)?;
+        write_prometheus_header(out, "vpncloud_arp_proxy_hits_total", "ARP requests answered by the proxy")?;
+        writeln!(out, "vpncloud_arp_proxy_hits_total {}", self.arp_proxy_hits_total)?;
+        write_prometheus_header(out, "vpncloud_arp_proxy_misses_total", "ARP requests unknown to the proxy")?;
+        writeln!(out, "vpncloud_arp_proxy_misses_total {}", self.arp_proxy_misses_total)?;
+        let buffers =
+            [("recv", "receive", self.socket_recv_buffer_bytes), ("send", "send", self.socket_send_buffer_bytes)];
+        for (name, help, size) in &buffers {
+            if let Some(size) = size {
+                writeln!(out, "# HELP vpncloud_socket_{}_buffer_bytes Size of the socket {} buffer", name, help)?;
+                writeln!(out, "# TYPE vpncloud_socket_{}_buffer_bytes gauge", name)?;
+                writeln!(out, "vpncloud_socket_{}_buffer_bytes {}", name, size)?;
+            }
+        }
+        let talkers = self.top_talkers(TOP_TALKERS);
+        write_prometheus_header(
+            out,
+            "vpncloud_address_bytes_sent_total",
+            "Payload bytes sent by the address, for the top talkers",
+        )?;
+        for (addr, data) in &talkers {
+            writeln!(out, "vpncloud_address_bytes_sent_total{{addr=\"{}\"}} {}", addr, data.bytes_sum())?;
+        }
+        write_prometheus_header(
+            out,
+            "vpncloud_address_packets_sent_total",
+            "Payload packets sent by the address, for the top talkers",
+        )?;
+        for (addr, data) in &talkers {
+            let packets = data.in_packets_sum() + data.out_packets_sum();
+            writeln!(out, "vpncloud_address_packets_sent_total{{addr=\"{}\"}} {}", addr, packets)?;
+        }
+        let mut peers: Vec<_> = self.get_peer_traffic().map(|(addr, data)| (addr_nice(*addr), data)).collect();
+        peers.sort_unstable_by_key(|(addr, _)| *addr);
+        for (name, _, help, value) in &counters {
+            write_prometheus_header(out, &format!("vpncloud_peer_{}_total", name), help)?;
+            for (addr, data) in &peers {
+                writeln!(out, "vpncloud_peer_{}_total{{addr=\"{}\"}} {}", name, addr, value(data))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Name, help text, help text per peer and the value of a counter
+type PrometheusCounter = (&'static str, &'static str, &'static str, fn(&TrafficEntry) -> u64);
+
+fn write_prometheus_header<W: Write>(out: &mut W, name: &str, help: &str) -> Result<(), io::Error> {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} counter", name)
+}
+
+#[test]
+fn peer_traffic_totals() {
+    let mut stats = TrafficStats::default();
+    let peer1 = "1.2.3.4:3210".parse().unwrap();
+    let peer2 = "5.6.7.8:3210".parse().unwrap();
+    stats.count_out_traffic(peer1, 100);
+    stats.count_in_traffic(peer2, 1000);
+    stats.period(None);
+    stats.count_out_traffic(peer1, 2000);
+    let mut out = Vec::new();
+    stats.write_out(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let totals = &out[out.find("peer_traffic_total:").unwrap()..out.find("payload_traffic:").unwrap()];
+    assert!(totals.find("1.2.3.4").unwrap() < totals.find("5.6.7.8").unwrap());
+    assert!(totals.contains("bytes: 2100, packets: 2"));
+    assert!(totals.contains("bytes: 1000, packets: 1"));
+}
+
+#[test]
+fn rolling_window_rates() {
+    let sample =
+        |bytes: u64| TrafficEntry { in_bytes: bytes, in_packets: 120, out_bytes: 60, ..TrafficEntry::default() };
+    let mut window = RollingWindow::new(2);
+    assert_eq!(window.rate().in_bytes, 0);
+    window.push(sample(60_000));
+    window.push(sample(1200));
+    assert_eq!(window.rate().in_bytes, 510);
+    assert_eq!(window.peak_rate().in_bytes, 1000);
+    // The first period is evicted
+    window.push(sample(2400));
+    let rate = window.rate();
+    assert_eq!(rate.in_bytes, 30);
+    assert_eq!(rate.in_packets, 2);
+    assert_eq!(rate.out_bytes, 1);
+    assert_eq!(rate.out_packets, 0);
+    assert_eq!(window.peak_rate().in_bytes, 40);
+    let mut stats = TrafficStats::default();
+    stats.count_in_traffic("1.2.3.4:3210".parse().unwrap(), 6000);
+    stats.count_in_traffic("5.6.7.8:3210".parse().unwrap(), 6000);
+    stats.period(None);
+    stats.period(None);
+    assert_eq!(stats.rate().in_bytes, 100);
+    assert_eq!(stats.peak_rate().in_bytes, 200);
+}
+
+#[test]
+fn top_talkers() {
+    let mut stats = TrafficStats::new(2);
+    let addr = |s: &str| s.parse::<Address>().unwrap();
+    stats.count_in_payload(addr("10.0.0.1"), addr("10.0.0.2"), 100);
+    stats.count_out_payload(addr("10.0.0.3"), addr("10.0.0.2"), 500);
+    stats.period(None);
+    stats.count_in_payload(addr("10.0.0.1"), addr("10.0.0.2"), 200);
+    let talkers = stats.top_talkers(10);
+    assert_eq!(talkers.len(), 2);
+    assert_eq!(talkers[0].0, addr("10.0.0.2"));
+    assert_eq!(talkers[0].1.out_bytes_total, 500);
+    assert_eq!(talkers[1].0, addr("10.0.0.1"));
+    assert_eq!((talkers[1].1.in_bytes_total, talkers[1].1.in_bytes), (100, 200));
+    assert_eq!(stats.payload_by_dst.peek(&addr("10.0.0.2")).unwrap().in_bytes_sum(), 300);
+    assert_eq!(stats.top_talkers(1).len(), 1);
+    // The least recently active address is evicted
+    stats.count_in_payload(addr("10.0.0.4"), addr("10.0.0.2"), 50);
+    let talkers: Vec<_> = stats.top_talkers(10).into_iter().map(|(addr, _)| addr).collect();
+    assert_eq!(talkers, [addr("10.0.0.1"), addr("10.0.0.4")]);
+    // Idle addresses are removed
+    for _ in 0..3 {
+        stats.period(Some(2));
+    }
+    assert!(stats.top_talkers(10).is_empty());
+}
+
+#[test]
+fn packet_loss_from_sequence_gaps() {
+    let mut loss = PacketLoss::default();
+    let key = 1 << 56;
+    for seq in &[100, 101, 104, 103, 108, 109] {
+        loss.count(key + seq);
+    }
+    // 102, 105, 106 and 107 are missing
+    assert_eq!(loss.lost_packets, 4);
+    loss.period();
+    assert_eq!(loss.last_period_lost, 4);
+    assert!((loss.loss_percent - 40.0).abs() < 0.01);
+    // A new key starts a new sequence
+    loss.count((2 << 56) + 5000);
+    loss.count((2 << 56) + 5002);
+    loss.period();
+    assert_eq!(loss.last_period_lost, 1);
+    assert_eq!(loss.lost_packets_total, 5);
+    loss.period();
+    assert_eq!(loss.loss_percent, 0.0);
+}
+
+#[test]
+fn prometheus_format() {
+    let mut stats = TrafficStats::default();
+    let peer1 = "1.2.3.4:3210".parse().unwrap();
+    let peer2 = "[2001:db8::1]:3210".parse().unwrap();
+    stats.count_out_traffic(peer1, 100);
+    stats.count_in_traffic(peer2, 1000);
+    stats.period(None);
+    stats.count_out_traffic(peer1, 2000);
+    stats.count_in_traffic(peer1, 50);
+    stats.count_invalid_protocol(10);
+    stats.count_dropped_payload(20);
+    stats.count_dropped_payload(20);
+    stats.count_in_payload("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap(), 30);
+    stats.socket_recv_buffer_bytes = Some(4194304);
+    let mut out = Vec::new();
+    stats.write_prometheus(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        r#"# HELP vpncloud_bytes_in_total Bytes received from peers
+# TYPE vpncloud_bytes_in_total counter
+vpncloud_bytes_in_total 1050
+# HELP vpncloud_bytes_out_total Bytes sent to peers
+# TYPE vpncloud_bytes_out_total counter
+vpncloud_bytes_out_total 2100
+# HELP vpncloud_packets_in_total Packets received from peers
+# TYPE vpncloud_packets_in_total counter
+vpncloud_packets_in_total 2
+# HELP vpncloud_packets_out_total Packets sent to peers
+# TYPE vpncloud_packets_out_total counter
+vpncloud_packets_out_total 2
+# HELP vpncloud_bytes_in_per_second Bytes received from peers per second, averaged over the last hour
+# TYPE vpncloud_bytes_in_per_second gauge
+vpncloud_bytes_in_per_second 16
+# HELP vpncloud_bytes_out_per_second Bytes sent to peers per second, averaged over the last hour
+# TYPE vpncloud_bytes_out_per_second gauge
+vpncloud_bytes_out_per_second 1
+# HELP vpncloud_packets_in_per_second Packets received from peers per second, averaged over the last hour
+# TYPE vpncloud_packets_in_per_second gauge
+vpncloud_packets_in_per_second 0
+# HELP vpncloud_packets_out_per_second Packets sent to peers per second, averaged over the last hour
+# TYPE vpncloud_packets_out_per_second gauge
+vpncloud_packets_out_per_second 0
+# HELP vpncloud_dropped_packets_total Packets from the device that were dropped
+# TYPE vpncloud_dropped_packets_total counter
+vpncloud_dropped_packets_total 2
+# HELP vpncloud_invalid_protocol_total Messages with an invalid protocol
+# TYPE vpncloud_invalid_protocol_total counter
+vpncloud_invalid_protocol_total 1
+# HELP vpncloud_influx_write_errors_total Stats that could not be sent to InfluxDB
+# TYPE vpncloud_influx_write_errors_total counter
+vpncloud_influx_write_errors_total 0
+# HELP vpncloud_dpd_evictions_total Peers removed by the dead peer detection
+# TYPE vpncloud_dpd_evictions_total counter
+vpncloud_dpd_evictions_total 0
+# HELP vpncloud_keepalive_packets_sent_total NAT keepalives sent to idle peers
+# TYPE vpncloud_keepalive_packets_sent_total counter
+vpncloud_keepalive_packets_sent_total 0
+# HELP vpncloud_gossip_messages_sent_total Peer lists sent to peers
+# TYPE vpncloud_gossip_messages_sent_total counter
+vpncloud_gossip_messages_sent_total 0
+# HELP vpncloud_gossip_updates_sent_total Peer list updates sent to peers
+# TYPE vpncloud_gossip_updates_sent_total counter
+vpncloud_gossip_updates_sent_total 0
+# HELP vpncloud_reorder_buffer_flushes_total Times that held back packets were released because of the timeout
+# TYPE vpncloud_reorder_buffer_flushes_total counter
+vpncloud_reorder_buffer_flushes_total 0
+# HELP vpncloud_reorder_buffer_overflows_total Times that held back packets were released because the buffer was full
+# TYPE vpncloud_reorder_buffer_overflows_total counter
+vpncloud_reorder_buffer_overflows_total 0
+# HELP vpncloud_table_dump_calls_total Dumps of the claim table via the API
+# TYPE vpncloud_table_dump_calls_total counter
+vpncloud_table_dump_calls_total 0
+# HELP vpncloud_arp_proxy_hits_total ARP requests answered by the proxy
+# TYPE vpncloud_arp_proxy_hits_total counter
+vpncloud_arp_proxy_hits_total 0
+# HELP vpncloud_arp_proxy_misses_total ARP requests unknown to the proxy
+# TYPE vpncloud_arp_proxy_misses_total counter
+vpncloud_arp_proxy_misses_total 0
+# HELP vpncloud_socket_recv_buffer_bytes Size of the socket receive buffer
+# TYPE vpncloud_socket_recv_buffer_bytes gauge
+vpncloud_socket_recv_buffer_bytes 4194304
+# HELP vpncloud_address_bytes_sent_total Payload bytes sent by the address, for the top talkers
+# TYPE vpncloud_address_bytes_sent_total counter
+vpncloud_address_bytes_sent_total{addr="10.0.0.1"} 30
+# HELP vpncloud_address_packets_sent_total Payload packets sent by the address, for the top talkers
+# TYPE vpncloud_address_packets_sent_total counter
+vpncloud_address_packets_sent_total{addr="10.0.0.1"} 1
+# HELP vpncloud_peer_bytes_in_total Bytes received from the peer
+# TYPE vpncloud_peer_bytes_in_total counter
+vpncloud_peer_bytes_in_total{addr="1.2.3.4:3210"} 50
+vpncloud_peer_bytes_in_total{addr="[2001:db8::1]:3210"} 1000
+# HELP vpncloud_peer_bytes_out_total Bytes sent to the peer
+# TYPE vpncloud_peer_bytes_out_total counter
+vpncloud_peer_bytes_out_total{addr="1.2.3.4:3210"} 2100
+vpncloud_peer_bytes_out_total{addr="[2001:db8::1]:3210"} 0
+# HELP vpncloud_peer_packets_in_total Packets received from the peer
+# TYPE vpncloud_peer_packets_in_total counter
+vpncloud_peer_packets_in_total{addr="1.2.3.4:3210"} 1
+vpncloud_peer_packets_in_total{addr="[2001:db8::1]:3210"} 1
+# HELP vpncloud_peer_packets_out_total Packets sent to the peer
+# TYPE vpncloud_peer_packets_out_total counter
+vpncloud_peer_packets_out_total{addr="1.2.3.4:3210"} 2
+vpncloud_peer_packets_out_total{addr="[2001:db8::1]:3210"} 0
+"#
+    );
 }
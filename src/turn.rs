@@ -0,0 +1,578 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+// TURN client (RFC 5766) to relay traffic to peers that can not be reached directly
+//
+// The client allocates a relay address on a TURN server and binds a channel for each relayed peer. Messages to those
+// peers are sent to the TURN server wrapped in ChannelData messages, the server forwards them from the relay address.
+// The client does not do any I/O itself, the caller sends the returned messages to the server address.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use ring::hmac;
+
+use crate::{
+    cloud::Hash,
+    config::TurnServer,
+    error::Error,
+    net::mapped_addr,
+    util::{addr_nice, resolve, MsgBuffer, Time},
+};
+
+pub const DEFAULT_TURN_PORT: u16 = 3478;
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const HEADER_LEN: usize = 20;
+const CHANNEL_HEADER_LEN: usize = 4;
+
+const METHOD_ALLOCATE: u16 = 0x003;
+const METHOD_REFRESH: u16 = 0x004;
+const METHOD_CHANNEL_BIND: u16 = 0x009;
+const CLASS_SUCCESS: u16 = 0x100;
+const CLASS_ERROR: u16 = 0x110;
+
+const ATTR_USERNAME: u16 = 0x0006;
+const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_CHANNEL_NUMBER: u16 = 0x000C;
+const ATTR_LIFETIME: u16 = 0x000D;
+const ATTR_XOR_PEER_ADDRESS: u16 = 0x0012;
+const ATTR_REALM: u16 = 0x0014;
+const ATTR_NONCE: u16 = 0x0015;
+const ATTR_XOR_RELAYED_ADDRESS: u16 = 0x0016;
+const ATTR_REQUESTED_TRANSPORT: u16 = 0x0019;
+
+const ERROR_UNAUTHORIZED: u16 = 401;
+const ERROR_STALE_NONCE: u16 = 438;
+
+const TRANSPORT_UDP: u8 = 17;
+const FIRST_CHANNEL: u16 = 0x4000;
+const LAST_CHANNEL: u16 = 0x7FFF;
+
+const ALLOCATE_RETRY_INTERVAL: Time = 30;
+const CHANNEL_REFRESH_INTERVAL: Time = 240;
+const REFRESH_MARGIN: Time = 60;
+
+type TransactionId = [u8; 12];
+
+struct StunMessage<'a> {
+    type_: u16,
+    transaction: TransactionId,
+    attrs: Vec<(u16, &'a [u8])>,
+    integrity_offset: Option<usize>,
+}
+
+impl<'a> StunMessage<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_LEN {
+            return None
+        }
+        let mut r = Cursor::new(data);
+        let type_ = r.read_u16::<NetworkEndian>().ok()?;
+        let len = r.read_u16::<NetworkEndian>().ok()? as usize;
+        if type_ & 0xC000 != 0 || r.read_u32::<NetworkEndian>().ok()? != MAGIC_COOKIE || data.len() < HEADER_LEN + len {
+            return None
+        }
+        let mut transaction = [0; 12];
+        r.read_exact(&mut transaction).ok()?;
+        let mut attrs = vec![];
+        let mut integrity_offset = None;
+        let mut pos = HEADER_LEN;
+        while pos + 4 <= HEADER_LEN + len {
+            let attr_type = u16::from_be_bytes([data[pos], data[pos + 1]]);
+            let attr_len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            if pos + 4 + attr_len > HEADER_LEN + len {
+                return None
+            }
+            if attr_type == ATTR_MESSAGE_INTEGRITY {
+                integrity_offset = Some(pos);
+            }
+            attrs.push((attr_type, &data[pos + 4..pos + 4 + attr_len]));
+            pos += 4 + ((attr_len + 3) & !3);
+        }
+        Some(Self { type_, transaction, attrs, integrity_offset })
+    }
+
+    fn attr(&self, type_: u16) -> Option<&'a [u8]> {
+        self.attrs.iter().find(|(t, _)| *t == type_).map(|(_, v)| *v)
+    }
+
+    fn error_code(&self) -> Option<u16> {
+        self.attr(ATTR_ERROR_CODE).filter(|v| v.len() >= 4).map(|v| u16::from(v[2] & 0x07) * 100 + u16::from(v[3]))
+    }
+
+    fn xor_address(&self, type_: u16) -> Option<SocketAddr> {
+        decode_xor_address(self.attr(type_)?, &self.transaction)
+    }
+
+    fn verify(&self, data: &[u8], key: &[u8]) -> bool {
+        let offset = match self.integrity_offset {
+            Some(offset) => offset,
+            None => return false,
+        };
+        let mut signed = data[..offset].to_vec();
+        let len = (offset + 24 - HEADER_LEN) as u16;
+        signed[2..4].copy_from_slice(&len.to_be_bytes());
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+        hmac::verify(&key, &signed, &data[offset + 4..offset + 24]).is_ok()
+    }
+}
+
+struct StunWriter {
+    buffer: Vec<u8>,
+}
+
+impl StunWriter {
+    fn new(type_: u16, transaction: &TransactionId) -> Self {
+        let mut buffer = Vec::with_capacity(200);
+        buffer.write_u16::<NetworkEndian>(type_).unwrap();
+        buffer.write_u16::<NetworkEndian>(0).unwrap();
+        buffer.write_u32::<NetworkEndian>(MAGIC_COOKIE).unwrap();
+        buffer.extend_from_slice(transaction);
+        Self { buffer }
+    }
+
+    fn attr(&mut self, type_: u16, value: &[u8]) {
+        self.buffer.write_u16::<NetworkEndian>(type_).unwrap();
+        self.buffer.write_u16::<NetworkEndian>(value.len() as u16).unwrap();
+        self.buffer.extend_from_slice(value);
+        while self.buffer.len() & 3 != 0 {
+            self.buffer.push(0);
+        }
+    }
+
+    fn set_length(&mut self, len: usize) {
+        self.buffer[2..4].copy_from_slice(&((len - HEADER_LEN) as u16).to_be_bytes());
+    }
+
+    fn finish(mut self, key: Option<&[u8]>) -> Vec<u8> {
+        if let Some(key) = key {
+            self.set_length(self.buffer.len() + 24);
+            let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+            let tag = hmac::sign(&key, &self.buffer);
+            self.attr(ATTR_MESSAGE_INTEGRITY, tag.as_ref());
+        }
+        let len = self.buffer.len();
+        self.set_length(len);
+        self.buffer
+    }
+}
+
+fn encode_xor_address(addr: SocketAddr, transaction: &TransactionId) -> Vec<u8> {
+    let addr = addr_nice(addr);
+    let mut data = vec![0];
+    let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            data.push(0x01);
+            data.extend_from_slice(&port.to_be_bytes());
+            data.extend_from_slice(&(u32::from(ip) ^ MAGIC_COOKIE).to_be_bytes());
+        }
+        IpAddr::V6(ip) => {
+            data.push(0x02);
+            data.extend_from_slice(&port.to_be_bytes());
+            let mut mask = [0; 16];
+            mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            mask[4..].copy_from_slice(transaction);
+            data.extend(ip.octets().iter().zip(mask.iter()).map(|(a, b)| a ^ b));
+        }
+    }
+    data
+}
+
+fn decode_xor_address(data: &[u8], transaction: &TransactionId) -> Option<SocketAddr> {
+    if data.len() < 8 {
+        return None
+    }
+    let port = u16::from_be_bytes([data[2], data[3]]) ^ (MAGIC_COOKIE >> 16) as u16;
+    let ip = match data[1] {
+        0x01 => IpAddr::V4(Ipv4Addr::from(u32::from_be_bytes([data[4], data[5], data[6], data[7]]) ^ MAGIC_COOKIE)),
+        0x02 if data.len() >= 20 => {
+            let mut mask = [0; 16];
+            mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            mask[4..].copy_from_slice(transaction);
+            let mut octets = [0; 16];
+            for i in 0..16 {
+                octets[i] = data[4 + i] ^ mask[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+fn long_term_key(username: &str, realm: &str, password: &str) -> [u8; 16] {
+    md5::compute(format!("{}:{}:{}", username, realm, password)).0
+}
+
+#[derive(Clone, Copy)]
+enum Request {
+    Allocate,
+    Refresh,
+    ChannelBind(SocketAddr),
+}
+
+struct Channel {
+    number: u16,
+    bound: bool,
+    next_refresh: Time,
+}
+
+pub enum TurnResult {
+    /// A relayed message from the given peer, the channel header has been removed
+    Data(SocketAddr),
+    /// A message that has to be sent back to the server
+    Reply,
+    /// Nothing to do
+    None,
+}
+
+pub struct TurnRelay {
+    servers: Vec<TurnServer>,
+    current: usize,
+    server_addr: Option<SocketAddr>,
+    realm: String,
+    nonce: Vec<u8>,
+    key: Option<[u8; 16]>,
+    relayed_addr: Option<SocketAddr>,
+    next_refresh: Time,
+    next_allocate: Time,
+    pending: HashMap<TransactionId, Request, Hash>,
+    channels: HashMap<SocketAddr, Channel, Hash>,
+    channel_peers: HashMap<u16, SocketAddr, Hash>,
+    next_channel: u16,
+}
+
+impl TurnRelay {
+    pub fn new(servers: &[TurnServer]) -> Self {
+        Self {
+            servers: servers.to_vec(),
+            current: 0,
+            server_addr: None,
+            realm: String::new(),
+            nonce: vec![],
+            key: None,
+            relayed_addr: None,
+            next_refresh: 0,
+            next_allocate: 0,
+            pending: HashMap::default(),
+            channels: HashMap::default(),
+            channel_peers: HashMap::default(),
+            next_channel: FIRST_CHANNEL,
+        }
+    }
+
+    /// The address of the TURN server that is currently used
+    #[inline]
+    pub fn server(&self) -> Option<SocketAddr> {
+        self.server_addr
+    }
+
+    /// The address on the TURN server that relays messages to this node
+    pub fn relayed_addr(&self) -> Option<SocketAddr> {
+        self.relayed_addr
+    }
+
+    #[inline]
+    pub fn is_relayed(&self, peer: &SocketAddr) -> bool {
+        // HOT PATH
+        !self.channels.is_empty() && self.channels.contains_key(peer)
+    }
+
+    fn request(&mut self, request: Request, now: Time) -> Vec<u8> {
+        let transaction: TransactionId = rand::random();
+        self.pending.insert(transaction, request);
+        let server = self.servers[self.current].clone();
+        let mut msg = match request {
+            Request::Allocate => {
+                let mut msg = StunWriter::new(METHOD_ALLOCATE, &transaction);
+                msg.attr(ATTR_REQUESTED_TRANSPORT, &[TRANSPORT_UDP, 0, 0, 0]);
+                msg
+            }
+            Request::Refresh => StunWriter::new(METHOD_REFRESH, &transaction),
+            Request::ChannelBind(peer) => {
+                let channel = self.channels.get_mut(&peer).expect("Channel must exist");
+                channel.next_refresh = now + CHANNEL_REFRESH_INTERVAL;
+                let mut msg = StunWriter::new(METHOD_CHANNEL_BIND, &transaction);
+                msg.attr(ATTR_CHANNEL_NUMBER, &[(channel.number >> 8) as u8, channel.number as u8, 0, 0]);
+                msg.attr(ATTR_XOR_PEER_ADDRESS, &encode_xor_address(peer, &transaction));
+                msg
+            }
+        };
+        if let Some(key) = self.key {
+            msg.attr(ATTR_USERNAME, server.username.as_bytes());
+            msg.attr(ATTR_REALM, self.realm.as_bytes());
+            msg.attr(ATTR_NONCE, &self.nonce);
+            msg.finish(Some(&key))
+        } else {
+            msg.finish(None)
+        }
+    }
+
+    fn reset(&mut self, now: Time) {
+        self.server_addr = None;
+        self.key = None;
+        self.relayed_addr = None;
+        self.pending.clear();
+        self.channels.clear();
+        self.channel_peers.clear();
+        self.next_allocate = now + ALLOCATE_RETRY_INTERVAL;
+    }
+
+    /// Allocates a relay address and refreshes the allocation and the channels, returns messages for the server
+    pub fn housekeep(&mut self, now: Time) -> Vec<Vec<u8>> {
+        let mut msgs = vec![];
+        if self.servers.is_empty() {
+            return msgs
+        }
+        if self.relayed_addr.is_none() {
+            if self.next_allocate <= now {
+                if self.server_addr.is_some() {
+                    // The last allocation did not succeed, try the next server
+                    self.current = (self.current + 1) % self.servers.len();
+                }
+                self.reset(now);
+                let server = &self.servers[self.current];
+                match resolve(server.address()) {
+                    Ok(addrs) if !addrs.is_empty() => {
+                        debug!("Requesting relay address from TURN server {}", server.url);
+                        self.server_addr = Some(mapped_addr(addrs[0]));
+                        msgs.push(self.request(Request::Allocate, now));
+                    }
+                    _ => warn!("Failed to resolve TURN server {}", server.url),
+                }
+            }
+            return msgs
+        }
+        if self.next_refresh <= now {
+            msgs.push(self.request(Request::Refresh, now));
+            self.next_refresh = now + REFRESH_MARGIN;
+        }
+        let due: Vec<_> = self.channels.iter().filter(|(_, c)| c.next_refresh <= now).map(|(a, _)| *a).collect();
+        for peer in due {
+            msgs.push(self.request(Request::ChannelBind(peer), now));
+        }
+        msgs
+    }
+
+    /// Starts relaying messages to the peer, returns a message for the server
+    pub fn bind(&mut self, peer: SocketAddr, now: Time) -> Option<Vec<u8>> {
+        if self.relayed_addr.is_none() || self.channels.contains_key(&peer) {
+            return None
+        }
+        let number = self.next_channel;
+        self.next_channel = if number == LAST_CHANNEL { FIRST_CHANNEL } else { number + 1 };
+        if let Some(old) = self.channel_peers.insert(number, peer) {
+            self.channels.remove(&old);
+        }
+        self.channels.insert(peer, Channel { number, bound: false, next_refresh: now });
+        info!("Relaying messages to {} via TURN server", addr_nice(peer));
+        Some(self.request(Request::ChannelBind(peer), now))
+    }
+
+    /// Stops relaying messages to the peer, the channel expires on the server
+    pub fn unbind(&mut self, peer: &SocketAddr) {
+        if let Some(channel) = self.channels.remove(peer) {
+            self.channel_peers.remove(&channel.number);
+        }
+    }
+
+    /// Stops relaying messages to all peers that do not match the predicate
+    pub fn retain<F: FnMut(&SocketAddr) -> bool>(&mut self, mut f: F) {
+        let channel_peers = &mut self.channel_peers;
+        self.channels.retain(|addr, channel| {
+            let keep = f(addr);
+            if !keep {
+                channel_peers.remove(&channel.number);
+            }
+            keep
+        });
+    }
+
+    /// Wraps the message in a ChannelData message for the peer, returns false if the channel is not ready yet
+    pub fn wrap(&self, peer: &SocketAddr, msg: &mut MsgBuffer) -> bool {
+        let channel = match self.channels.get(peer) {
+            Some(channel) if channel.bound => channel,
+            _ => return false,
+        };
+        let len = msg.len() as u16;
+        msg.set_start(msg.get_start() - CHANNEL_HEADER_LEN);
+        let header = msg.message_mut();
+        header[..2].copy_from_slice(&channel.number.to_be_bytes());
+        header[2..CHANNEL_HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+        true
+    }
+
+    /// Handles a message from the TURN server
+    pub fn handle_message(&mut self, data: &mut MsgBuffer, now: Time) -> Result<TurnResult, Error> {
+        let msg = data.message();
+        if msg.len() >= CHANNEL_HEADER_LEN && msg[0] & 0xC0 == 0x40 {
+            let number = u16::from_be_bytes([msg[0], msg[1]]);
+            let len = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+            let peer = match self.channel_peers.get(&number) {
+                Some(peer) if msg.len() >= CHANNEL_HEADER_LEN + len => *peer,
+                _ => return Err(Error::Message("Invalid channel data from TURN server")),
+            };
+            data.set_start(data.get_start() + CHANNEL_HEADER_LEN);
+            data.set_length(len);
+            return Ok(TurnResult::Data(mapped_addr(peer)))
+        }
+        let (type_, request, reply) = {
+            let stun = match StunMessage::parse(msg) {
+                Some(stun) => stun,
+                None => return Err(Error::Message("Invalid message from TURN server")),
+            };
+            let request = match self.pending.remove(&stun.transaction) {
+                Some(request) => request,
+                None => return Ok(TurnResult::None),
+            };
+            if let Some(key) = self.key {
+                if stun.integrity_offset.is_some() && !stun.verify(msg, &key) {
+                    return Err(Error::Message("Invalid message integrity from TURN server"))
+                }
+            }
+            let reply = self.handle_response(&stun, request, now);
+            (stun.type_, request, reply)
+        };
+        match reply {
+            Some(reply) => {
+                data.clone_from(&reply);
+                Ok(TurnResult::Reply)
+            }
+            None => {
+                if type_ & CLASS_ERROR == CLASS_ERROR {
+                    if let Request::ChannelBind(peer) = request {
+                        self.unbind(&peer);
+                    }
+                }
+                Ok(TurnResult::None)
+            }
+        }
+    }
+
+    fn handle_response(&mut self, stun: &StunMessage, request: Request, now: Time) -> Option<Vec<u8>> {
+        if stun.type_ & CLASS_ERROR == CLASS_ERROR {
+            let code = stun.error_code().unwrap_or(0);
+            match code {
+                ERROR_UNAUTHORIZED | ERROR_STALE_NONCE if self.key.is_none() || code == ERROR_STALE_NONCE => {
+                    let (realm, nonce) = match (stun.attr(ATTR_REALM), stun.attr(ATTR_NONCE)) {
+                        (Some(realm), Some(nonce)) => (realm, nonce),
+                        _ => return None,
+                    };
+                    let server = &self.servers[self.current];
+                    self.realm = String::from_utf8_lossy(realm).to_string();
+                    self.nonce = nonce.to_vec();
+                    self.key = Some(long_term_key(&server.username, &self.realm, &server.password));
+                    Some(self.request(request, now))
+                }
+                _ => {
+                    warn!("TURN server {} refused request with error {}", self.servers[self.current].url, code);
+                    if let Request::Refresh = request {
+                        // The allocation is lost, allocate a new one
+                        self.relayed_addr = None;
+                    }
+                    None
+                }
+            }
+        } else if stun.type_ & CLASS_SUCCESS == CLASS_SUCCESS {
+            let lifetime =
+                stun.attr(ATTR_LIFETIME).filter(|v| v.len() == 4).map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]));
+            match request {
+                Request::Allocate => {
+                    self.relayed_addr = stun.xor_address(ATTR_XOR_RELAYED_ADDRESS);
+                    if let Some(addr) = self.relayed_addr {
+                        info!("Got relay address {} from TURN server {}", addr, self.servers[self.current].url);
+                    }
+                    self.next_refresh = now + Time::from(lifetime.unwrap_or(600)).saturating_sub(REFRESH_MARGIN);
+                }
+                Request::Refresh => {
+                    self.next_refresh = now + Time::from(lifetime.unwrap_or(600)).saturating_sub(REFRESH_MARGIN);
+                }
+                Request::ChannelBind(peer) => {
+                    if let Some(channel) = self.channels.get_mut(&peer) {
+                        channel.bound = true;
+                    }
+                }
+            }
+            None
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+fn server_response(request: &[u8], type_: u16, attrs: &[(u16, Vec<u8>)], key: Option<&[u8]>) -> MsgBuffer {
+    let stun = StunMessage::parse(request).unwrap();
+    let mut msg = StunWriter::new(type_, &stun.transaction);
+    for (t, v) in attrs {
+        msg.attr(*t, v);
+    }
+    let mut buffer = MsgBuffer::new(100);
+    buffer.clone_from(&msg.finish(key));
+    buffer
+}
+
+#[test]
+fn turn_allocate_and_relay() {
+    let server =
+        TurnServer { url: "127.0.0.1:3478".to_string(), username: "user".to_string(), password: "pass".to_string() };
+    let mut relay = TurnRelay::new(&[server]);
+    let msgs = relay.housekeep(0);
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(relay.server(), Some(mapped_addr("127.0.0.1:3478".parse().unwrap())));
+    // The server asks for authentication
+    let mut response = server_response(
+        &msgs[0],
+        METHOD_ALLOCATE | CLASS_ERROR,
+        &[(ATTR_ERROR_CODE, vec![0, 0, 4, 1]), (ATTR_REALM, b"example.com".to_vec()), (ATTR_NONCE, b"1234".to_vec())],
+        None,
+    );
+    assert!(matches!(relay.handle_message(&mut response, 0), Ok(TurnResult::Reply)));
+    let key = long_term_key("user", "example.com", "pass");
+    let request = response.message().to_vec();
+    let stun = StunMessage::parse(&request).unwrap();
+    assert_eq!(stun.attr(ATTR_USERNAME), Some(&b"user"[..]));
+    assert!(stun.verify(&request, &key));
+    // The authenticated allocation succeeds
+    let relayed: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+    let mut response = server_response(
+        &request,
+        METHOD_ALLOCATE | CLASS_SUCCESS,
+        &[
+            (ATTR_XOR_RELAYED_ADDRESS, encode_xor_address(relayed, &stun.transaction)),
+            (ATTR_LIFETIME, 600u32.to_be_bytes().to_vec()),
+        ],
+        Some(&key),
+    );
+    assert!(matches!(relay.handle_message(&mut response, 0), Ok(TurnResult::None)));
+    assert_eq!(relay.relayed_addr(), Some(relayed));
+    // Bind a channel to a peer
+    let peer = mapped_addr("5.6.7.8:3210".parse().unwrap());
+    let request = relay.bind(peer, 0).unwrap();
+    assert!(relay.is_relayed(&peer));
+    let mut msg = MsgBuffer::new(100);
+    msg.clone_from(&[1, 2, 3]);
+    assert!(!relay.wrap(&peer, &mut msg));
+    let stun = StunMessage::parse(&request).unwrap();
+    assert_eq!(stun.xor_address(ATTR_XOR_PEER_ADDRESS), Some("5.6.7.8:3210".parse().unwrap()));
+    let mut response = server_response(&request, METHOD_CHANNEL_BIND | CLASS_SUCCESS, &[], Some(&key));
+    assert!(matches!(relay.handle_message(&mut response, 0), Ok(TurnResult::None)));
+    // Messages are wrapped in channel data
+    assert!(relay.wrap(&peer, &mut msg));
+    assert_eq!(msg.message(), &[0x40, 0x00, 0, 3, 1, 2, 3]);
+    match relay.handle_message(&mut msg, 0) {
+        Ok(TurnResult::Data(addr)) => assert_eq!(addr, peer),
+        _ => panic!("Channel data not recognized"),
+    }
+    assert_eq!(msg.message(), &[1, 2, 3]);
+    relay.unbind(&peer);
+    assert!(!relay.is_relayed(&peer));
+}
@@ -7,12 +7,13 @@ use crate::{
     util::{bytes_to_hex, Encoder},
 };
 use byteorder::{ReadBytesExt, WriteBytesExt};
+use schemars::JsonSchema;
 use smallvec::SmallVec;
 use std::{
     fmt,
     hash::{Hash, Hasher},
     io::{Read, Write},
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
 };
 
@@ -54,6 +55,17 @@ impl Address {
         data[0..4].copy_from_slice(&ip.octets());
         Self { data, len: 4 }
     }
+
+    /// Whether this is a multicast MAC address (optionally prefixed with a VLAN tag), excluding broadcast
+    #[inline]
+    pub fn is_multicast(&self) -> bool {
+        let mac = match self.len {
+            6 => &self.data[..6],
+            8 => &self.data[2..8],
+            _ => return false,
+        };
+        mac[0] & 0x01 == 0x01 && mac != [0xff; 6]
+    }
 }
 
 impl PartialEq for Address {
@@ -150,6 +162,30 @@ impl Range {
         match_len >= self.prefix_len
     }
 
+    /// Returns the enclosing range with a prefix that is one bit shorter
+    pub fn supernet(&self) -> Option<Range> {
+        if self.prefix_len == 0 {
+            return None;
+        }
+        let prefix_len = self.prefix_len - 1;
+        let mut base = self.base;
+        for (i, byte) in base.data[..base.len as usize].iter_mut().enumerate() {
+            let bits = i as u8 * 8;
+            if bits >= prefix_len {
+                *byte = 0
+            } else if prefix_len - bits < 8 {
+                *byte &= 0xff << (8 - (prefix_len - bits))
+            }
+        }
+        Some(Range { base, prefix_len })
+    }
+
+    /// Returns whether the last bit of the prefix is set, i.e. whether this is the upper half of its supernet
+    pub fn is_upper_half(&self) -> bool {
+        let bit = self.prefix_len as usize - 1;
+        self.base.data[bit / 8] & (0x80 >> (bit % 8)) != 0
+    }
+
     #[inline]
     pub fn read_from<R: Read>(mut r: R) -> Result<Range, Error> {
         let base = Address::read_from(&mut r)?;
@@ -190,7 +226,7 @@ impl fmt::Debug for Range {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
 pub enum Mode {
     #[serde(rename = "normal")]
     Normal,
@@ -225,6 +261,51 @@ impl FromStr for Mode {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+pub enum AddressFamily {
+    #[serde(rename = "dual-stack")]
+    DualStack,
+    #[serde(rename = "ipv4")]
+    Ipv4Only,
+    #[serde(rename = "ipv6")]
+    Ipv6Only,
+}
+impl AddressFamily {
+    /// Whether peers can be reached at the address
+    pub fn matches(self, addr: &SocketAddr) -> bool {
+        let is_ipv4 = match addr {
+            SocketAddr::V4(_) => true,
+            SocketAddr::V6(addr) => addr.ip().segments()[..6] == [0, 0, 0, 0, 0, 0xffff],
+        };
+        match self {
+            AddressFamily::DualStack => true,
+            AddressFamily::Ipv4Only => is_ipv4,
+            AddressFamily::Ipv6Only => !is_ipv4,
+        }
+    }
+}
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            AddressFamily::DualStack => write!(formatter, "dual-stack"),
+            AddressFamily::Ipv4Only => write!(formatter, "ipv4"),
+            AddressFamily::Ipv6Only => write!(formatter, "ipv6"),
+        }
+    }
+}
+impl FromStr for AddressFamily {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match &text.to_lowercase() as &str {
+            "dual-stack" | "dual" => Self::DualStack,
+            "ipv4" => Self::Ipv4Only,
+            "ipv6" => Self::Ipv6Only,
+            _ => return Err("Unknown address family"),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -301,4 +382,15 @@ mod tests {
         buf[0] = 17;
         assert!(Range::read_from(Cursor::new(&buf)).is_err());
     }
+
+    #[test]
+    fn range_supernet() {
+        let range = Range::from_str("10.1.3.7/24").unwrap();
+        assert!(range.is_upper_half());
+        assert_eq!(range.supernet(), Some(Range::from_str("10.1.2.0/23").unwrap()));
+        assert!(!Range::from_str("10.1.2.0/24").unwrap().is_upper_half());
+        assert_eq!(Range::from_str("10.1.0.0/17").unwrap().supernet(), Some(Range::from_str("10.1.0.0/16").unwrap()));
+        assert_eq!(Range::from_str("128.0.0.0/1").unwrap().supernet(), Some(Range::from_str("0.0.0.0/0").unwrap()));
+        assert_eq!(Range::from_str("0.0.0.0/0").unwrap().supernet(), None);
+    }
 }
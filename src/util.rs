@@ -6,13 +6,16 @@ use std::process::Command;
 use std::{
     fmt,
     net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
-    sync::atomic::{AtomicIsize, Ordering},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicIsize, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use crate::error::Error;
 
-#[cfg(not(target_os = "linux"))]
-use time;
+use crossbeam_channel::{bounded, Receiver, Sender};
 
 use signal::{trap::Trap, Signal};
 use smallvec::SmallVec;
@@ -21,6 +24,11 @@ use std::time::Instant;
 pub type Duration = u32;
 pub type Time = i64;
 
+/// A buffer holding a single message
+///
+/// The message starts after some free space, so that headers can be prepended in place. Together with the crypto core
+/// encrypting in place and appending the tag into the buffer, a packet read from the device is sent out of the very same
+/// buffer without being copied. Therefore no scatter-gather I/O is needed to assemble header, payload and tag.
 #[derive(Clone)]
 pub struct MsgBuffer {
     space_before: usize,
@@ -98,6 +106,76 @@ impl MsgBuffer {
     }
 }
 
+/// A pool of message buffers that are reused instead of being allocated for every message
+#[derive(Clone)]
+pub struct MsgBufferPool {
+    space_before: usize,
+    sender: Sender<Box<MsgBuffer>>,
+    receiver: Receiver<Box<MsgBuffer>>,
+    exhausted: Arc<AtomicUsize>,
+}
+
+impl MsgBufferPool {
+    pub fn new(size: usize, space_before: usize) -> Self {
+        let (sender, receiver) = bounded(size);
+        for _ in 0..size {
+            sender.send(Box::new(MsgBuffer::new(space_before))).unwrap();
+        }
+        Self { space_before, sender, receiver, exhausted: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    /// Takes a buffer from the pool, it is returned to the pool when it is dropped
+    #[inline]
+    pub fn acquire(&self) -> PooledBuffer {
+        // HOT PATH
+        let mut buffer = match self.receiver.try_recv() {
+            Ok(buffer) => buffer,
+            Err(_) => {
+                // COLD PATH
+                self.exhausted.fetch_add(1, Ordering::Relaxed);
+                Box::new(MsgBuffer::new(self.space_before))
+            }
+        };
+        buffer.clear();
+        PooledBuffer { buffer: Some(buffer), pool: self.sender.clone() }
+    }
+
+    /// Number of times the pool was empty and a buffer had to be allocated
+    pub fn exhausted(&self) -> usize {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+}
+
+pub struct PooledBuffer {
+    buffer: Option<Box<MsgBuffer>>,
+    pool: Sender<Box<MsgBuffer>>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = MsgBuffer;
+
+    #[inline]
+    fn deref(&self) -> &MsgBuffer {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut MsgBuffer {
+        self.buffer.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            // If the pool is full, the buffer is simply dropped
+            self.pool.try_send(buffer).ok();
+        }
+    }
+}
+
 const HEX_CHARS: &[u8] = b"0123456789abcdef";
 
 pub fn bytes_to_hex(bytes: &[u8]) -> String {
@@ -109,6 +187,11 @@ pub fn bytes_to_hex(bytes: &[u8]) -> String {
     s
 }
 
+pub fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    // An odd length makes the last slice fail
+    (0..hex.len()).step_by(2).map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok())).collect()
+}
+
 pub fn addr_nice(addr: SocketAddr) -> SocketAddr {
     if let SocketAddr::V6(v6addr) = addr {
         if let Some(ip) = v6addr.ip().to_ipv4() {
@@ -263,7 +346,10 @@ impl fmt::Display for Bytes {
 }
 
 pub struct CtrlC {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
     dummy_time: Instant,
+    // Keeps the signals blocked
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
     trap: Trap,
 }
 
@@ -272,9 +358,22 @@ impl CtrlC {
         Default::default()
     }
 
+    #[cfg(target_os = "linux")]
     pub fn was_pressed(&self) -> bool {
         self.trap.wait(self.dummy_time).is_some()
     }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn was_pressed(&self) -> bool {
+        // Without sigtimedwait, the trapped signals stay pending as they are blocked
+        let mut pending = std::mem::MaybeUninit::<libc::sigset_t>::uninit();
+        unsafe {
+            libc::sigpending(pending.as_mut_ptr()) == 0
+                && [libc::SIGINT, libc::SIGTERM, libc::SIGQUIT]
+                    .iter()
+                    .any(|sig| libc::sigismember(pending.as_ptr(), *sig) == 1)
+        }
+    }
 }
 
 impl Default for CtrlC {
@@ -287,6 +386,8 @@ impl Default for CtrlC {
 
 pub trait TimeSource: Sync + Copy + Send + 'static {
     fn now() -> Time;
+    /// Monotonic clock for the timers that need more than a resolution of seconds
+    fn instant() -> Instant;
 }
 
 #[derive(Clone, Copy)]
@@ -304,12 +405,22 @@ impl TimeSource for SystemTimeSource {
 
     #[cfg(not(target_os = "linux"))]
     fn now() -> Time {
-        time::get_time().sec
+        let mut tv = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe {
+            libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut tv);
+        }
+        tv.tv_sec as Time
+    }
+
+    fn instant() -> Instant {
+        Instant::now()
     }
 }
 
 thread_local! {
+    /// The mock time in milliseconds
     static MOCK_TIME: AtomicIsize = AtomicIsize::new(0);
+    static MOCK_EPOCH: Instant = Instant::now();
 }
 
 #[derive(Clone, Copy)]
@@ -317,13 +428,34 @@ pub struct MockTimeSource;
 
 impl MockTimeSource {
     pub fn set_time(time: Time) {
-        MOCK_TIME.with(|t| t.store(time as isize, Ordering::SeqCst))
+        MOCK_TIME.with(|t| t.store(time as isize * 1000, Ordering::SeqCst))
+    }
+
+    /// Moves the mock time forward by the given number of seconds
+    pub fn advance(secs: Time) {
+        Self::advance_millis(secs * 1000)
+    }
+
+    /// Moves the mock time forward by the given number of milliseconds
+    pub fn advance_millis(millis: Time) {
+        MOCK_TIME.with(|t| t.fetch_add(millis as isize, Ordering::SeqCst));
     }
 }
 
 impl TimeSource for MockTimeSource {
     fn now() -> Time {
-        MOCK_TIME.with(|t| t.load(Ordering::SeqCst) as Time)
+        MOCK_TIME.with(|t| (t.load(Ordering::SeqCst) as Time).div_euclid(1000))
+    }
+
+    fn instant() -> Instant {
+        let millis = MOCK_TIME.with(|t| t.load(Ordering::SeqCst) as i64);
+        // The mock time 0 lies well after the start of the thread, so that negative times are still valid instants
+        let epoch = MOCK_EPOCH.with(|e| *e) + std::time::Duration::from_secs(1 << 20);
+        if millis >= 0 {
+            epoch + std::time::Duration::from_millis(millis as u64)
+        } else {
+            epoch - std::time::Duration::from_millis(millis.unsigned_abs())
+        }
     }
 }
 
@@ -439,3 +571,39 @@ fn base62() {
     assert_eq!(vec![1, 0], from_base62("48").unwrap());
     assert_eq!(b"Test".to_vec(), from_base62("1Xp7Ke").unwrap());
 }
+
+#[test]
+fn hex() {
+    assert_eq!("00ff1a", bytes_to_hex(&[0, 255, 26]));
+    assert_eq!(Some(vec![0, 255, 26]), hex_to_bytes("00ff1a"));
+    assert_eq!(Some(vec![0, 255, 26]), hex_to_bytes("00FF1A"));
+    assert_eq!(None, hex_to_bytes("00f"));
+    assert_eq!(None, hex_to_bytes("0g"));
+}
+
+#[test]
+fn msg_buffer_pool() {
+    let pool = MsgBufferPool::new(1, 10);
+    {
+        let mut buffer = pool.acquire();
+        buffer.clone_from(&[1, 2, 3]);
+        let other = pool.acquire();
+        assert!(other.is_empty());
+        assert_eq!(pool.exhausted(), 1);
+    }
+    let buffer = pool.acquire();
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.get_start(), 10);
+    assert_eq!(pool.exhausted(), 1);
+}
+
+#[test]
+fn mock_time_advance() {
+    MockTimeSource::set_time(1000);
+    MockTimeSource::advance(5);
+    assert_eq!(MockTimeSource::now(), 1005);
+    let instant = MockTimeSource::instant();
+    MockTimeSource::advance_millis(1500);
+    assert_eq!(MockTimeSource::now(), 1006);
+    assert_eq!(MockTimeSource::instant() - instant, std::time::Duration::from_millis(1500));
+}
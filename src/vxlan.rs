@@ -0,0 +1,117 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use crate::{error::Error, util::MsgBuffer};
+
+/// The UDP port assigned to VXLAN
+pub const VXLAN_PORT: u16 = 4789;
+pub const VXLAN_HEADER_LEN: usize = 8;
+/// The I flag that marks the VNI as valid
+const FLAG_VNI: u8 = 0x08;
+const MAX_VNI: u32 = 0xff_ffff;
+
+/// Encapsulation of the messages of a peer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encap {
+    None,
+    Vxlan,
+}
+
+/// Wraps messages in a VXLAN header (RFC 7348), so that they can pass SDN fabrics and hardware VTEPs
+///
+/// The messages are encrypted as usual, only the VXLAN header is added in front of them.
+pub struct VxlanTransport {
+    vni: u32,
+    local_port: u16,
+}
+
+impl VxlanTransport {
+    pub fn new(vni: u32, local_port: u16) -> Result<Self, Error> {
+        if vni > MAX_VNI {
+            return Err(Error::InvalidConfig("VXLAN network identifiers only have 24 bits"))
+        }
+        Ok(Self { vni, local_port })
+    }
+
+    /// Returns whether encapsulated messages are detected, i.e. the socket listens on the VXLAN port
+    pub fn is_listening(&self) -> bool {
+        self.local_port == VXLAN_PORT
+    }
+
+    pub fn encode(&self, buffer: &mut MsgBuffer) {
+        let len = buffer.len();
+        buffer.set_start(buffer.get_start() - VXLAN_HEADER_LEN);
+        buffer.set_length(len + VXLAN_HEADER_LEN);
+        let header = &mut buffer.message_mut()[..VXLAN_HEADER_LEN];
+        header[..4].copy_from_slice(&[FLAG_VNI, 0, 0, 0]);
+        header[4..].copy_from_slice(&(self.vni << 8).to_be_bytes());
+    }
+
+    /// Strips the VXLAN header if the message has one with the configured VNI
+    ///
+    /// Returns whether the message was encapsulated.
+    pub fn decode(&self, buffer: &mut MsgBuffer) -> bool {
+        if !self.is_listening() {
+            return false
+        }
+        let msg = buffer.message();
+        if msg.len() <= VXLAN_HEADER_LEN || msg[0] & FLAG_VNI == 0 {
+            return false
+        }
+        let vni = u32::from_be_bytes([msg[4], msg[5], msg[6], msg[7]]) >> 8;
+        if vni != self.vni {
+            return false
+        }
+        let len = msg.len();
+        buffer.set_start(buffer.get_start() + VXLAN_HEADER_LEN);
+        buffer.set_length(len - VXLAN_HEADER_LEN);
+        true
+    }
+}
+
+#[test]
+fn vxlan_vtep() {
+    use std::{net::UdpSocket, time::Duration};
+    let socket = match UdpSocket::bind(("127.0.0.1", VXLAN_PORT)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            warn!("Skipping test as the VXLAN port can not be bound: {}", err);
+            return
+        }
+    };
+    socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let vtep = UdpSocket::bind("127.0.0.1:0").unwrap();
+    vtep.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let vxlan = VxlanTransport::new(4242, socket.local_addr().unwrap().port()).unwrap();
+    assert!(VxlanTransport::new(MAX_VNI + 1, VXLAN_PORT).is_err());
+    let mut buffer = MsgBuffer::new(100);
+
+    // The VTEP sends a frame with a VXLAN header: flags, reserved, VNI 4242 (0x001092), reserved
+    vtep.send_to(&[0x08, 0, 0, 0, 0x00, 0x10, 0x92, 0, 1, 2, 3], socket.local_addr().unwrap()).unwrap();
+    let (size, src) = socket.recv_from(buffer.buffer()).unwrap();
+    buffer.set_length(size);
+    assert!(vxlan.decode(&mut buffer));
+    assert_eq!(buffer.message(), &[1, 2, 3]);
+
+    // Messages with another VNI are not stripped
+    buffer.clear();
+    buffer.clone_from(&[0x08, 0, 0, 0, 0x00, 0x10, 0x93, 0, 1, 2, 3]);
+    assert!(!vxlan.decode(&mut buffer));
+    assert_eq!(buffer.len(), 11);
+
+    // Replies are encapsulated for the VTEP
+    buffer.clear();
+    buffer.clone_from(&[4, 5, 6]);
+    vxlan.encode(&mut buffer);
+    socket.send_to(buffer.message(), src).unwrap();
+    let mut data = [0; 100];
+    let size = vtep.recv(&mut data).unwrap();
+    assert_eq!(&data[..size], &[0x08, 0, 0, 0, 0x00, 0x10, 0x92, 0, 4, 5, 6]);
+
+    // Nothing is stripped on other ports
+    let vxlan = VxlanTransport::new(4242, 3210).unwrap();
+    buffer.clear();
+    buffer.clone_from(&[0x08, 0, 0, 0, 0x00, 0x10, 0x92, 0, 1, 2, 3]);
+    assert!(!vxlan.decode(&mut buffer));
+}
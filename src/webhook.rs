@@ -0,0 +1,253 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2021  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+use std::{
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use ring::hmac;
+use serde::Serialize;
+
+use super::{
+    audit::DisconnectReason,
+    error::Error,
+    types::NodeId,
+    util::{addr_nice, bytes_to_hex, Time, TimeSource},
+};
+
+/// Header that carries the hex encoded HMAC-SHA256 of the body if a secret is configured
+pub const SIGNATURE_HEADER: &str = "X-VpnCloud-Signature";
+/// Number of events that can be queued before new events are dropped
+const QUEUE_SIZE: usize = 100;
+/// Failed requests are retried once after this delay
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum PeerEventKind {
+    Connect,
+    Disconnect,
+}
+
+#[derive(Serialize)]
+struct PeerEvent {
+    event: PeerEventKind,
+    addr: String,
+    node_id: String,
+    timestamp: Time,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<DisconnectReason>,
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    bytes_to_hex(hmac::sign(&key, body).as_ref())
+}
+
+#[cfg(feature = "webhook")]
+mod internal {
+    use std::time::Duration;
+
+    use reqwest::{
+        blocking::Client,
+        header::{CONTENT_TYPE, USER_AGENT},
+        Url,
+    };
+
+    use super::SIGNATURE_HEADER;
+    use crate::error::Error;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub struct HttpClient {
+        client: Client,
+        url: Url,
+    }
+
+    impl HttpClient {
+        pub fn new(url: &str) -> Result<Self, Error> {
+            let url = Url::parse(url).map_err(|_| Error::InvalidConfig("Invalid webhook URL"))?;
+            if url.scheme() != "http" && url.scheme() != "https" {
+                return Err(Error::InvalidConfig("Webhook URL must use http or https"))
+            }
+            let client = Client::builder()
+                .timeout(TIMEOUT)
+                .build()
+                .map_err(|_| Error::InvalidConfig("Failed to create webhook client"))?;
+            Ok(Self { client, url })
+        }
+
+        pub fn post(&self, body: &[u8], signature: Option<&str>) -> Result<(), Error> {
+            let mut request = self
+                .client
+                .post(self.url.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .header(USER_AGENT, concat!("vpncloud/", env!("CARGO_PKG_VERSION")))
+                .body(body.to_vec());
+            if let Some(signature) = signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+            request.send().and_then(|res| res.error_for_status()).map_err(|e| Error::Http(e.to_string()))?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "webhook"))]
+mod internal {
+    use crate::error::Error;
+
+    pub struct HttpClient;
+
+    impl HttpClient {
+        pub fn new(_url: &str) -> Result<Self, Error> {
+            Err(Error::InvalidConfig("Webhooks are not supported by this build"))
+        }
+
+        pub fn post(&self, _body: &[u8], _signature: Option<&str>) -> Result<(), Error> {
+            unreachable!("Webhooks are not supported by this build")
+        }
+    }
+}
+
+use internal::HttpClient;
+
+/// Posts peer connect and disconnect events as JSON to a URL
+///
+/// The requests are sent by a background thread, so that a slow receiver does not block the main loop. Events are
+/// dropped when the queue is full or when the request failed twice.
+pub struct WebhookNotifier<TS: TimeSource> {
+    events: SyncSender<Vec<u8>>,
+    _dummy: PhantomData<TS>,
+}
+
+impl<TS: TimeSource> WebhookNotifier<TS> {
+    pub fn start(url: &str, secret: Option<String>) -> Result<Self, Error> {
+        let client = HttpClient::new(url)?;
+        let (events, receiver) = sync_channel(QUEUE_SIZE);
+        thread::Builder::new()
+            .name("webhook".to_string())
+            .spawn(move || Self::run(client, secret, receiver))
+            .map_err(|_| Error::InvalidConfig("Failed to start webhook thread"))?;
+        Ok(Self { events, _dummy: PhantomData })
+    }
+
+    fn run(client: HttpClient, secret: Option<String>, events: Receiver<Vec<u8>>) {
+        while let Ok(body) = events.recv() {
+            let signature = secret.as_ref().map(|s| sign(s, &body));
+            if let Err(err) = client.post(&body, signature.as_deref()) {
+                debug!("Webhook failed, retrying: {}", err);
+                thread::sleep(RETRY_DELAY);
+                if let Err(err) = client.post(&body, signature.as_deref()) {
+                    warn!("Dropping webhook event: {}", err)
+                }
+            }
+        }
+    }
+
+    pub fn connected(&self, addr: SocketAddr, node_id: &NodeId) {
+        self.send(PeerEventKind::Connect, addr, node_id, None)
+    }
+
+    pub fn disconnected(&self, addr: SocketAddr, node_id: &NodeId, reason: DisconnectReason) {
+        self.send(PeerEventKind::Disconnect, addr, node_id, Some(reason))
+    }
+
+    fn send(&self, event: PeerEventKind, addr: SocketAddr, node_id: &NodeId, reason: Option<DisconnectReason>) {
+        let event = PeerEvent {
+            event,
+            addr: addr_nice(addr).to_string(),
+            node_id: bytes_to_hex(node_id),
+            timestamp: TS::now(),
+            reason,
+        };
+        let body = serde_json::to_vec(&event).expect("Failed to serialize webhook event");
+        match self.events.try_send(body) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => warn!("Dropping webhook event as too many events are pending"),
+            Err(TrySendError::Disconnected(_)) => error!("Webhook thread is not running"),
+        }
+    }
+}
+
+#[test]
+fn webhook_signature() {
+    // Test case 2 of RFC 4231
+    assert_eq!(
+        sign("Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    let event = PeerEvent {
+        event: PeerEventKind::Disconnect,
+        addr: "1.2.3.4:3210".to_string(),
+        node_id: bytes_to_hex(&[1; 16]),
+        timestamp: 1000,
+        reason: Some(DisconnectReason::Timeout),
+    };
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        r#"{"event":"disconnect","addr":"1.2.3.4:3210","node_id":"01010101010101010101010101010101","timestamp":1000,"reason":"timeout"}"#
+    );
+}
+
+#[cfg(feature = "webhook")]
+#[test]
+fn webhook_delivery() {
+    use crate::util::MockTimeSource;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    /// Reads one request and returns the signature header and the body
+    fn receive(listener: &TcpListener, status: &str) -> (Option<String>, String) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut signature = None;
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break
+            }
+            let mut parts = line.splitn(2, ": ");
+            match (parts.next().unwrap().to_lowercase().as_str(), parts.next()) {
+                ("content-length", Some(value)) => length = value.parse().unwrap(),
+                ("x-vpncloud-signature", Some(value)) => signature = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+        (signature, String::from_utf8(body).unwrap())
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/events", listener.local_addr().unwrap());
+    let peer = "1.2.3.4:3210".parse().unwrap();
+    MockTimeSource::set_time(1000);
+    let webhook = WebhookNotifier::<MockTimeSource>::start(&url, Some("secret".to_string())).unwrap();
+    webhook.connected(peer, &[1; 16]);
+    let (signature, body) = receive(&listener, "200 OK");
+    assert_eq!(signature.unwrap(), sign("secret", body.as_bytes()));
+    let event: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(event["event"], "connect");
+    assert_eq!(event["addr"], "1.2.3.4:3210");
+    assert_eq!(event["timestamp"], 1000);
+    assert!(event.get("reason").is_none());
+    // Failed requests are retried once
+    webhook.disconnected(peer, &[1; 16], DisconnectReason::Close);
+    let (_, first) = receive(&listener, "500 Internal Server Error");
+    let (_, second) = receive(&listener, "200 OK");
+    assert_eq!(first, second);
+    assert!(second.contains("\"reason\":\"close\""));
+    assert!(HttpClient::new("ftp://localhost/").is_err());
+}
@@ -6,6 +6,7 @@ use super::{
     net::{get_ip, mapped_addr, parse_listen, Socket},
     poll::{WaitImpl, WaitResult},
     port_forwarding::PortForwarding,
+    types::AddressFamily,
     util::MsgBuffer,
 };
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
@@ -129,7 +130,7 @@ impl AsRawFd for ProxyConnection {
 }
 
 impl Socket for ProxyConnection {
-    fn listen(url: &str) -> Result<Self, io::Error> {
+    fn listen(url: &str, _family: AddressFamily) -> Result<Self, io::Error> {
         let parsed_url = io_error!(Url::parse(url), "Invalid URL {}: {}", url)?;
         let (mut socket, _) = io_error!(connect(parsed_url), "Failed to connect to URL {}: {}", url)?;
         socket.get_mut().set_nodelay(true)?;
@@ -163,4 +164,29 @@ impl Socket for ProxyConnection {
     fn create_port_forwarding(&self) -> Option<PortForwarding> {
         None
     }
+
+    fn set_dont_fragment(&mut self, _enabled: bool) -> Result<(), io::Error> {
+        Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+
+    fn path_mtu(&self, _addr: SocketAddr) -> Result<usize, io::Error> {
+        Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+
+    fn set_tos(&mut self, _tos: u8) -> Result<(), io::Error> {
+        Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+
+    fn send_with_tos(&mut self, data: &[u8], addr: SocketAddr, _tos: u8) -> Result<usize, io::Error> {
+        // The packets are sent by the proxy
+        self.send(data, addr)
+    }
+
+    fn set_recv_buffer_size(&mut self, _size: usize) -> Result<usize, io::Error> {
+        Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+
+    fn set_send_buffer_size(&mut self, _size: usize) -> Result<usize, io::Error> {
+        Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+    }
 }